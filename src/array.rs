@@ -18,6 +18,7 @@ use crate::containers::{
 };
 use crate::error::{ErrorKind, RuntimeError};
 use crate::headers::TypeList;
+use crate::heapcheck::{HeapChecker, Verify};
 use crate::memory::MutatorView;
 use crate::printer::Print;
 use crate::rawarray::{default_array_growth, RawArray, DEFAULT_ARRAY_SIZE};
@@ -143,6 +144,11 @@ impl<T: Sized + Clone> Array<T> {
         }
     }
 
+    /// Check the backing array is intact for heap verification
+    pub fn verify_backing(&self, checker: &mut HeapChecker) -> Result<(), RuntimeError> {
+        checker.raw_array(&self.data.get(), self.length.get())
+    }
+
     /// Represent the full capacity of the array, however initialized, as a slice.
     /// This is necessarily unsafe even for the 'guard lifetime
     /// duration because while a slice is held, other code can cause array internals to change
@@ -208,11 +214,13 @@ impl<T: Sized + Clone> FillContainer<T> for Array<T> {
             let capacity = array.capacity();
 
             if size > capacity {
-                if capacity == 0 {
-                    array.resize(mem, DEFAULT_ARRAY_SIZE)?;
+                // grow by at least the default growth, but far enough to hold `size` items
+                let growth = if capacity == 0 {
+                    DEFAULT_ARRAY_SIZE
                 } else {
-                    array.resize(mem, default_array_growth(capacity)?)?;
-                }
+                    default_array_growth(capacity)?
+                };
+                array.resize(mem, growth.max(size))?;
                 // Replace the struct's copy with the resized RawArray object
                 self.data.set(array);
             }
@@ -323,6 +331,26 @@ impl<T: Sized + Clone> SliceableContainer<T> for Array<T> {
     }
 }
 
+/// Arrays of plain values only need their backing storage checked
+macro_rules! verify_array_backing {
+    ($T:ty) => {
+        impl Verify for Array<$T> {
+            fn verify_children<'guard>(
+                &self,
+                _guard: &'guard dyn MutatorScope,
+                checker: &mut HeapChecker,
+            ) -> Result<(), RuntimeError> {
+                self.verify_backing(checker)
+            }
+        }
+    };
+}
+
+verify_array_backing!(u8);
+verify_array_backing!(u16);
+verify_array_backing!(u32);
+verify_array_backing!(u64);
+
 /// Array of u8
 pub type ArrayU8 = Array<u8>;
 
@@ -379,11 +407,13 @@ impl FillAnyContainer for Array<TaggedCellPtr> {
             let capacity = array.capacity();
 
             if size > capacity {
-                if capacity == 0 {
-                    array.resize(mem, DEFAULT_ARRAY_SIZE)?;
+                // grow by at least the default growth, but far enough to hold `size` items
+                let growth = if capacity == 0 {
+                    DEFAULT_ARRAY_SIZE
                 } else {
-                    array.resize(mem, default_array_growth(capacity)?)?;
-                }
+                    default_array_growth(capacity)?
+                };
+                array.resize(mem, growth.max(size))?;
                 // Replace the struct's copy with the resized RawArray object
                 self.data.set(array);
             }
@@ -505,6 +535,22 @@ impl AnyContainerFromSlice for Array<TaggedCellPtr> {
     }
 }
 
+impl Verify for Array<TaggedCellPtr> {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        self.verify_backing(checker)?;
+
+        for item in unsafe { self.as_slice(guard) }.iter() {
+            checker.tagged(guard, item.get_ptr())?;
+        }
+
+        Ok(())
+    }
+}
+
 impl Print for Array<TaggedCellPtr> {
     fn print<'guard>(
         &self,
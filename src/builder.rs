@@ -0,0 +1,241 @@
+/// Builders for constructing lists and dicts from Rust.
+///
+/// Native functions and embedding code can build nested values by chaining calls rather than
+/// consing Pairs by hand:
+///
+///   let value = mem.list()?
+///       .push(1)?
+///       .push("a")?
+///       .push(mem.dict()?.insert(mem.lookup_sym("key"), "value")?)?
+///       .build();
+///
+/// Rust integers, strings and bools, and other builders, are converted to runtime values through
+/// the `IntoValue` trait.
+use crate::containers::HashIndexedAnyContainer;
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
+use crate::memory::MutatorView;
+use crate::pair::Pair;
+use crate::safeptr::{ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::taggedptr::{TaggedPtr, Value};
+
+/// Conversion of a Rust value into a runtime value
+pub trait IntoValue<'guard> {
+    fn into_value(self, mem: &'guard MutatorView) -> Result<TaggedScopedPtr<'guard>, RuntimeError>;
+}
+
+impl<'guard> IntoValue<'guard> for TaggedScopedPtr<'guard> {
+    fn into_value(
+        self,
+        _mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        Ok(self)
+    }
+}
+
+impl<'guard> IntoValue<'guard> for isize {
+    fn into_value(self, mem: &'guard MutatorView) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        match TaggedPtr::checked_number(self) {
+            Some(number) => Ok(TaggedScopedPtr::new(mem, number)),
+            None => Err(err_eval(&format!(
+                "Integer {} is too large to be stored inline",
+                self
+            ))),
+        }
+    }
+}
+
+impl<'guard> IntoValue<'guard> for i32 {
+    fn into_value(self, mem: &'guard MutatorView) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        (self as isize).into_value(mem)
+    }
+}
+
+/// Strings become Text
+impl<'guard> IntoValue<'guard> for &str {
+    fn into_value(self, mem: &'guard MutatorView) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        mem.text(self)
+    }
+}
+
+/// true becomes the symbol `true`, false becomes nil
+impl<'guard> IntoValue<'guard> for bool {
+    fn into_value(self, mem: &'guard MutatorView) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        Ok(mem.boolean(self))
+    }
+}
+
+/// Builds a list of Pairs, appending at the tail
+pub struct ListBuilder<'guard> {
+    mem: &'guard MutatorView<'guard>,
+    head: TaggedCellPtr,
+    tail: TaggedCellPtr,
+    length: usize,
+}
+
+impl<'guard> ListBuilder<'guard> {
+    /// Create a builder for an empty list
+    pub fn new(mem: &'guard MutatorView<'guard>) -> ListBuilder<'guard> {
+        ListBuilder {
+            mem,
+            head: TaggedCellPtr::new_nil(),
+            tail: TaggedCellPtr::new_nil(),
+            length: 0,
+        }
+    }
+
+    /// Append a value to the list
+    pub fn push<T: IntoValue<'guard>>(
+        mut self,
+        value: T,
+    ) -> Result<ListBuilder<'guard>, RuntimeError> {
+        self.mem.size_limits().check_vector(self.length + 1)?;
+        let value = value.into_value(self.mem)?;
+
+        if let Value::Pair(tail) = *self.tail.get(self.mem) {
+            self.tail.set(tail.append(self.mem, value)?);
+        } else {
+            let pair = Pair::new();
+            pair.first.set(value);
+            let pair = self.mem.alloc_tagged(pair)?;
+            self.head.set(pair);
+            self.tail.set(pair);
+        }
+
+        self.length += 1;
+        Ok(self)
+    }
+
+    /// Return the list, or nil if nothing was pushed
+    pub fn build(self) -> TaggedScopedPtr<'guard> {
+        self.head.get(self.mem)
+    }
+}
+
+impl<'guard> IntoValue<'guard> for ListBuilder<'guard> {
+    fn into_value(
+        self,
+        _mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        Ok(self.build())
+    }
+}
+
+/// Builds a Dict
+pub struct DictBuilder<'guard> {
+    mem: &'guard MutatorView<'guard>,
+    dict: ScopedPtr<'guard, Dict>,
+}
+
+impl<'guard> DictBuilder<'guard> {
+    /// Create a builder for a new empty Dict
+    pub fn new(mem: &'guard MutatorView<'guard>) -> Result<DictBuilder<'guard>, RuntimeError> {
+        Ok(DictBuilder {
+            mem,
+            dict: Dict::alloc(mem)?,
+        })
+    }
+
+    /// Associate a key with a value, replacing any existing association of the key
+    pub fn insert<K, V>(self, key: K, value: V) -> Result<DictBuilder<'guard>, RuntimeError>
+    where
+        K: IntoValue<'guard>,
+        V: IntoValue<'guard>,
+    {
+        let key = key.into_value(self.mem)?;
+        let value = value.into_value(self.mem)?;
+        self.dict.assoc(self.mem, key, value)?;
+        Ok(self)
+    }
+
+    /// Return the Dict
+    pub fn build(self) -> TaggedScopedPtr<'guard> {
+        self.dict.as_tagged(self.mem)
+    }
+}
+
+impl<'guard> IntoValue<'guard> for DictBuilder<'guard> {
+    fn into_value(
+        self,
+        _mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        Ok(self.build())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::containers::Container;
+    use crate::memory::{Memory, Mutator};
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    #[test]
+    fn builder_nested_list() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let value = mem
+                .list()?
+                .push(1)?
+                .push("a")?
+                .push(mem.list()?.push(mem.lookup_sym("b"))?.push(true)?)?
+                .push(false)?
+                .build();
+
+            assert!(format!("{}", value) == "(1 \"a\" (b true) nil)");
+
+            assert!(mem.list()?.build().is_nil());
+
+            Ok(())
+        }
+
+        test_helper(test_inner)
+    }
+
+    #[test]
+    fn builder_dict() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let key = mem.lookup_sym("key");
+            let value = mem
+                .dict()?
+                .insert(key, mem.list()?.push(2)?.push(3)?)?
+                .insert(1, "one")?
+                .insert(key, "replaced")?
+                .build();
+
+            match *value {
+                Value::Dict(dict) => {
+                    assert!(dict.length() == 2);
+                    let item = dict.lookup(mem, key)?;
+                    assert!(format!("{}", item) == "\"replaced\"");
+                }
+                _ => panic!("Expected a Dict"),
+            }
+
+            assert!(isize::max_value().into_value(mem).is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner)
+    }
+}
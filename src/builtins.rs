@@ -0,0 +1,675 @@
+/// Functions implemented in Rust that are bound to global names in every Thread
+use std::cmp::Ordering;
+use std::hash::Hasher;
+
+use fnv::FnvHasher;
+
+use crate::character;
+use crate::codec;
+use crate::compare::{compare, equal, DEFAULT_EQUAL_DEPTH_LIMIT};
+#[cfg(feature = "compiler")]
+use crate::compiler::compile_with_thread;
+use crate::containers::HashIndexedAnyContainer;
+use crate::decimal;
+use crate::deque::Deque;
+use crate::dict::Dict;
+#[cfg(feature = "digest")]
+use crate::digest;
+use crate::error::{err_eval, RuntimeError};
+use crate::function::{NativeFn, NativeFunction, ThreadNativeFn};
+use crate::generator;
+use crate::hashable::{hash_value, stable_hash};
+use crate::memory::MutatorView;
+use crate::number;
+use crate::numformat;
+use crate::pair::{cons, vec_from_pairs};
+use crate::parallel;
+use crate::parameter::Parameter;
+use crate::port;
+use crate::priorityqueue::PriorityQueue;
+use crate::safeptr::{ScopedPtr, TaggedScopedPtr};
+use crate::sortedmap::SortedMap;
+use crate::taggedptr::{TaggedPtr, Value};
+use crate::vm::Thread;
+
+/// (compare a b) -> -1, 0 or 1
+fn compare_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let result = match compare(mem, args[0].value(), args[1].value()) {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+    };
+
+    Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(result)))
+}
+
+/// (equal? a b) -> true if a and b have the same content, comparing text by its characters,
+/// lists and arrays item by item and numbers by value and exactness. Cyclic lists are compared
+/// without looping forever.
+fn equal_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let equal = equal(
+        mem,
+        args[0].value(),
+        args[1].value(),
+        DEFAULT_EQUAL_DEPTH_LIMIT,
+    )?;
+    Ok(mem.boolean(equal))
+}
+
+/// (equal-within? a b depth) -> as equal?, but an error if lists are nested more than depth deep
+fn equal_within_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let depth = match args[2].as_int() {
+        Some(depth) if depth >= 0 => depth as usize,
+        _ => {
+            return Err(err_eval(&format!(
+                "equal-within? expected a depth that is not negative, got {}",
+                args[2]
+            )))
+        }
+    };
+
+    Ok(mem.boolean(equal(mem, args[0].value(), args[1].value(), depth)?))
+}
+
+/// (sort list) -> a new list with the items in ascending order
+fn sort_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let mut items = vec_from_pairs(mem, args[0])?;
+
+    // a stable sort, so that items that compare equal keep their relative positions
+    items.sort_by(|a, b| compare(mem, a.value(), b.value()));
+
+    let mut list = mem.list()?;
+    for item in items {
+        list = list.push(item)?;
+    }
+    Ok(list.build())
+}
+
+/// (length list) -> the number of items in the list
+fn length_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let mut length = 0;
+    let mut next = args[0];
+    while let Value::Pair(pair) = *next {
+        length += 1;
+        next = pair.second.get(mem);
+    }
+
+    match *next {
+        Value::Nil => Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(length))),
+        _ => Err(err_eval(&format!(
+            "length expected a list, got {}",
+            args[0]
+        ))),
+    }
+}
+
+/// (append first second) -> a new list of the items of the first list followed by the second
+/// list, which is shared rather than copied
+fn append_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let items = vec_from_pairs(mem, args[0])?;
+
+    let mut length = items.len();
+    let mut next = args[1];
+    while let Value::Pair(pair) = *next {
+        length += 1;
+        next = pair.second.get(mem);
+    }
+    mem.size_limits().check_vector(length)?;
+
+    let mut head = args[1];
+    for item in items.iter().rev() {
+        head = cons(mem, *item, head)?;
+    }
+    Ok(head)
+}
+
+/// (reverse list) -> a new list with the items in reverse order
+fn reverse_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let mut list = mem.list()?;
+    for item in vec_from_pairs(mem, args[0])?.into_iter().rev() {
+        list = list.push(item)?;
+    }
+    Ok(list.build())
+}
+
+/// Convert a 64 bit hash to a non-negative inline integer by keeping the top 61 bits
+fn hash_to_number<'guard>(mem: &'guard MutatorView, hash: u64) -> TaggedScopedPtr<'guard> {
+    TaggedScopedPtr::new(mem, TaggedPtr::number((hash >> 3) as isize))
+}
+
+/// (hash x) -> a hash of the content of x, which may differ between versions
+fn hash_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let mut hasher = FnvHasher::default();
+    hash_value(mem, args[0].value(), &mut hasher)?;
+    Ok(hash_to_number(mem, hasher.finish()))
+}
+
+/// (stable-hash x) -> a hash of the content of x that will not change between versions, see
+/// `hashable::stable_hash()`
+fn stable_hash_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(hash_to_number(mem, stable_hash(mem, args[0].value())?))
+}
+
+/// (arity f) -> the number of arguments f needs before it is called
+fn arity_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let arity = match *args[0] {
+        Value::Function(f) => f.arity(),
+        Value::Partial(p) => p.arity(),
+        Value::NativeFunction(f) => f.arity(),
+        _ => return Err(err_eval("Expected a function")),
+    };
+
+    Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(arity as isize)))
+}
+
+/// (function-name f) -> the name f was defined with, or nil if it is anonymous
+fn function_name_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let function = match *args[0] {
+        Value::Function(f) => f,
+        Value::Partial(p) => p.function(mem),
+        Value::NativeFunction(f) => return Ok(mem.lookup_sym(f.name(mem))),
+        _ => return Err(err_eval("Expected a function")),
+    };
+
+    if function.is_anonymous(mem) {
+        Ok(mem.nil())
+    } else {
+        Ok(mem.lookup_sym(function.name(mem)))
+    }
+}
+
+/// (function-code f) -> a disassembly of the bytecode of f as text
+fn function_code_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let function = match *args[0] {
+        Value::Function(f) => f,
+        Value::Partial(p) => p.function(mem),
+        Value::NativeFunction(_) => return Err(err_eval("A native function has no bytecode")),
+        _ => return Err(err_eval("Expected a function")),
+    };
+
+    codec::text_result(mem, &function.code(mem).disassemble(mem))
+}
+
+/// (apply f args) -> the result of calling f with the items of the list args. A call of `apply`
+/// by name is compiled to an Apply instruction instead, so this is only used when apply is passed
+/// as a value.
+fn apply_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let items = vec_from_pairs(mem, args[1])?;
+    thread.call_function(mem, args[0], &items)
+}
+
+/// (eval expr) -> the value of the s-expression expr, compiled and evaluated in the global
+/// environment of the calling thread. The local variables of the caller are not visible to expr.
+/// Without the compiler there is no `eval`.
+#[cfg(feature = "compiler")]
+fn eval_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let function = compile_with_thread(mem, thread, args[0])?;
+    thread.call_function(mem, function.as_tagged(mem), &[])
+}
+
+/// (throw value) -> never returns, raising value as an exception to be caught by the innermost
+/// enclosing `try` expression
+fn throw_fn<'guard>(
+    _mem: &'guard MutatorView,
+    thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Err(thread.throw(args[0]))
+}
+
+/// Return the Symbol argument or a type error
+fn symbol_arg(arg: TaggedScopedPtr) -> Result<TaggedScopedPtr, RuntimeError> {
+    match *arg {
+        Value::Symbol(_) => Ok(arg),
+        _ => Err(err_eval(&format!("Expected a symbol, got {}", arg))),
+    }
+}
+
+/// (put-prop! sym key value) -> value, after setting the property key of the symbol sym
+fn put_prop_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    thread.put_property(mem, symbol_arg(args[0])?, args[1], args[2])?;
+    Ok(args[2])
+}
+
+/// (get-prop sym key) -> the property key of the symbol sym, or nil if it is not set
+fn get_prop_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(thread
+        .get_property(mem, symbol_arg(args[0])?, args[1])
+        .unwrap_or_else(|| mem.nil()))
+}
+
+/// (make-parameter default) -> a new Parameter with the given value until rebound
+fn make_parameter_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(Parameter::alloc(mem, args[0])?.as_tagged(mem))
+}
+
+/// Return the SortedMap argument or a type error
+fn sorted_map_arg<'guard>(
+    arg: TaggedScopedPtr<'guard>,
+) -> Result<ScopedPtr<'guard, SortedMap>, RuntimeError> {
+    match *arg {
+        Value::SortedMap(map) => Ok(map),
+        _ => Err(err_eval("Expected a sorted-map")),
+    }
+}
+
+/// Pack a list of (key . value) pairs
+fn items_to_list<'guard>(
+    mem: &'guard MutatorView,
+    items: Vec<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)>,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let mut list = mem.list()?;
+    for (key, value) in items {
+        list = list.push(cons(mem, key, value)?)?;
+    }
+    Ok(list.build())
+}
+
+/// Return a (key . value) pair or nil
+fn item_or_nil<'guard>(
+    mem: &'guard MutatorView,
+    item: Option<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)>,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match item {
+        Some((key, value)) => cons(mem, key, value),
+        None => Ok(mem.nil()),
+    }
+}
+
+/// (sorted-map) -> a new empty sorted map
+fn sorted_map_fn<'guard>(
+    mem: &'guard MutatorView,
+    _args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(SortedMap::alloc(mem)?.as_tagged(mem))
+}
+
+/// (sorted-map-set! map key value) -> map
+fn sorted_map_set_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    sorted_map_arg(args[0])?.assoc(mem, args[1], args[2])?;
+    Ok(args[0])
+}
+
+/// (sorted-map-get map key) -> the value or nil
+fn sorted_map_get_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let map = sorted_map_arg(args[0])?;
+    Ok(map.lookup(mem, args[1]).unwrap_or_else(|| mem.nil()))
+}
+
+/// (sorted-map-remove! map key) -> the removed value or nil
+fn sorted_map_remove_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let map = sorted_map_arg(args[0])?;
+    Ok(map.dissoc(mem, args[1]).unwrap_or_else(|| mem.nil()))
+}
+
+/// (sorted-map-length map) -> number of items
+fn sorted_map_length_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let length = sorted_map_arg(args[0])?.length();
+    Ok(TaggedScopedPtr::new(
+        mem,
+        TaggedPtr::number(length as isize),
+    ))
+}
+
+/// (sorted-map-first map) -> (key . value) with the smallest key, or nil
+fn sorted_map_first_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    item_or_nil(mem, sorted_map_arg(args[0])?.first(mem))
+}
+
+/// (sorted-map-last map) -> (key . value) with the largest key, or nil
+fn sorted_map_last_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    item_or_nil(mem, sorted_map_arg(args[0])?.last(mem))
+}
+
+/// (sorted-map-range map lower upper) -> list of (key . value) with lower <= key < upper.
+/// An upper bound of nil is unbounded.
+fn sorted_map_range_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let map = sorted_map_arg(args[0])?;
+    let upper = if args[2].is_nil() {
+        None
+    } else {
+        Some(args[2])
+    };
+    items_to_list(mem, map.range(mem, Some(args[1]), upper))
+}
+
+/// (sorted-map-items map) -> list of all (key . value) in key order
+fn sorted_map_items_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    items_to_list(mem, sorted_map_arg(args[0])?.items(mem))
+}
+
+/// Return the PriorityQueue argument or a type error
+fn priority_queue_arg<'guard>(
+    arg: TaggedScopedPtr<'guard>,
+) -> Result<ScopedPtr<'guard, PriorityQueue>, RuntimeError> {
+    match *arg {
+        Value::PriorityQueue(queue) => Ok(queue),
+        _ => Err(err_eval("Expected a priority-queue")),
+    }
+}
+
+/// (priority-queue) -> a new empty queue ordered by `compare`
+fn priority_queue_fn<'guard>(
+    mem: &'guard MutatorView,
+    _args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(PriorityQueue::alloc(mem, mem.nil())?.as_tagged(mem))
+}
+
+/// (priority-queue-by comparator) -> a new empty queue ordered by the comparator, which returns
+/// a negative number if its first argument should be popped first
+fn priority_queue_by_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(PriorityQueue::alloc(mem, args[0])?.as_tagged(mem))
+}
+
+/// (push! queue item) -> queue
+fn push_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    priority_queue_arg(args[0])?.push(mem, Some(thread), args[1])?;
+    Ok(args[0])
+}
+
+/// (pop-min! queue) -> the first item, removed from the queue, or nil if empty
+fn pop_min_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let item = priority_queue_arg(args[0])?.pop(mem, Some(thread))?;
+    Ok(item.unwrap_or_else(|| mem.nil()))
+}
+
+/// (peek queue) -> the first item, or nil if empty
+fn peek_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let item = priority_queue_arg(args[0])?.peek(mem);
+    Ok(item.unwrap_or_else(|| mem.nil()))
+}
+
+/// (priority-queue-length queue) -> number of items
+fn priority_queue_length_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let length = priority_queue_arg(args[0])?.length();
+    Ok(TaggedScopedPtr::new(
+        mem,
+        TaggedPtr::number(length as isize),
+    ))
+}
+
+/// Return the Deque argument or a type error
+fn queue_arg<'guard>(
+    arg: TaggedScopedPtr<'guard>,
+) -> Result<ScopedPtr<'guard, Deque>, RuntimeError> {
+    match *arg {
+        Value::Deque(queue) => Ok(queue),
+        _ => Err(err_eval("Expected a queue")),
+    }
+}
+
+/// (queue) -> a new empty double-ended queue
+fn queue_fn<'guard>(
+    mem: &'guard MutatorView,
+    _args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(Deque::alloc(mem)?.as_tagged(mem))
+}
+
+/// (queue-push-back! queue item) -> queue
+fn queue_push_back_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    queue_arg(args[0])?.push_back(mem, args[1])?;
+    Ok(args[0])
+}
+
+/// (queue-push-front! queue item) -> queue
+fn queue_push_front_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    queue_arg(args[0])?.push_front(mem, args[1])?;
+    Ok(args[0])
+}
+
+/// (queue-pop-front! queue) -> the front item, removed from the queue, or nil if empty
+fn queue_pop_front_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let item = queue_arg(args[0])?.pop_front(mem)?;
+    Ok(item.unwrap_or_else(|| mem.nil()))
+}
+
+/// (queue-pop-back! queue) -> the back item, removed from the queue, or nil if empty
+fn queue_pop_back_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let item = queue_arg(args[0])?.pop_back(mem)?;
+    Ok(item.unwrap_or_else(|| mem.nil()))
+}
+
+/// (queue-front queue) -> the front item, or nil if empty
+fn queue_front_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let item = queue_arg(args[0])?.front(mem)?;
+    Ok(item.unwrap_or_else(|| mem.nil()))
+}
+
+/// (queue-back queue) -> the back item, or nil if empty
+fn queue_back_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let item = queue_arg(args[0])?.back(mem)?;
+    Ok(item.unwrap_or_else(|| mem.nil()))
+}
+
+/// (queue-length queue) -> number of items
+fn queue_length_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let length = queue_arg(args[0])?.length();
+    Ok(TaggedScopedPtr::new(
+        mem,
+        TaggedPtr::number(length as isize),
+    ))
+}
+
+/// Bind a native function to a global name
+pub fn define<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+    name: &str,
+    arity: u8,
+    code: NativeFn,
+) -> Result<(), RuntimeError> {
+    let function = NativeFunction::alloc(mem, name, arity, code)?;
+    globals.assoc(mem, mem.lookup_sym(name), function.as_tagged(mem))
+}
+
+/// Bind a native function that needs the calling Thread to a global name
+pub fn define_with_thread<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+    name: &str,
+    arity: u8,
+    code: ThreadNativeFn,
+) -> Result<(), RuntimeError> {
+    let function = NativeFunction::alloc_with_thread(mem, name, arity, code)?;
+    globals.assoc(mem, mem.lookup_sym(name), function.as_tagged(mem))
+}
+
+/// Return the Rust function of the builtin of the given name if it has no side effects and always
+/// returns the same value for the same arguments, so that a call over constant arguments can be
+/// evaluated while compiling. Builtins that allocate their result are not, as each call must
+/// return a new object.
+pub fn pure_builtin(name: &str) -> Option<NativeFn> {
+    match name {
+        "compare" => Some(compare_fn),
+        "eq" | "equal?" => Some(equal_fn),
+        "length" => Some(length_fn),
+        _ => None,
+    }
+}
+
+/// Bind all builtin functions into the given globals Dict
+pub fn load<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+) -> Result<(), RuntimeError> {
+    define(mem, globals, "compare", 2, compare_fn)?;
+    define(mem, globals, "equal?", 2, equal_fn)?;
+    define(mem, globals, "eq", 2, equal_fn)?;
+    define(mem, globals, "equal-within?", 3, equal_within_fn)?;
+    define(mem, globals, "sort", 1, sort_fn)?;
+    define(mem, globals, "length", 1, length_fn)?;
+    define(mem, globals, "append", 2, append_fn)?;
+    define(mem, globals, "reverse", 1, reverse_fn)?;
+    define(mem, globals, "hash", 1, hash_fn)?;
+    define(mem, globals, "stable-hash", 1, stable_hash_fn)?;
+    define(mem, globals, "arity", 1, arity_fn)?;
+    define(mem, globals, "function-name", 1, function_name_fn)?;
+    define(mem, globals, "function-code", 1, function_code_fn)?;
+    define_with_thread(mem, globals, "apply", 2, apply_fn)?;
+    #[cfg(feature = "compiler")]
+    define_with_thread(mem, globals, "eval", 1, eval_fn)?;
+    define_with_thread(mem, globals, "throw", 1, throw_fn)?;
+    define_with_thread(mem, globals, "put-prop!", 3, put_prop_fn)?;
+    define_with_thread(mem, globals, "get-prop", 2, get_prop_fn)?;
+    define(mem, globals, "make-parameter", 1, make_parameter_fn)?;
+    define(mem, globals, "sorted-map", 0, sorted_map_fn)?;
+    define(mem, globals, "sorted-map-set!", 3, sorted_map_set_fn)?;
+    define(mem, globals, "sorted-map-get", 2, sorted_map_get_fn)?;
+    define(mem, globals, "sorted-map-remove!", 2, sorted_map_remove_fn)?;
+    define(mem, globals, "sorted-map-length", 1, sorted_map_length_fn)?;
+    define(mem, globals, "sorted-map-first", 1, sorted_map_first_fn)?;
+    define(mem, globals, "sorted-map-last", 1, sorted_map_last_fn)?;
+    define(mem, globals, "sorted-map-range", 3, sorted_map_range_fn)?;
+    define(mem, globals, "sorted-map-items", 1, sorted_map_items_fn)?;
+    define(mem, globals, "priority-queue", 0, priority_queue_fn)?;
+    define(mem, globals, "priority-queue-by", 1, priority_queue_by_fn)?;
+    define_with_thread(mem, globals, "push!", 2, push_fn)?;
+    define_with_thread(mem, globals, "pop-min!", 1, pop_min_fn)?;
+    define(mem, globals, "peek", 1, peek_fn)?;
+    define(
+        mem,
+        globals,
+        "priority-queue-length",
+        1,
+        priority_queue_length_fn,
+    )?;
+    define(mem, globals, "queue", 0, queue_fn)?;
+    define(mem, globals, "queue-push-back!", 2, queue_push_back_fn)?;
+    define(mem, globals, "queue-push-front!", 2, queue_push_front_fn)?;
+    define(mem, globals, "queue-pop-front!", 1, queue_pop_front_fn)?;
+    define(mem, globals, "queue-pop-back!", 1, queue_pop_back_fn)?;
+    define(mem, globals, "queue-front", 1, queue_front_fn)?;
+    define(mem, globals, "queue-back", 1, queue_back_fn)?;
+    define(mem, globals, "queue-length", 1, queue_length_fn)?;
+
+    character::load(mem, globals)?;
+    codec::load(mem, globals)?;
+    decimal::load(mem, globals)?;
+    generator::load(mem, globals)?;
+    number::load(mem, globals)?;
+    numformat::load(mem, globals)?;
+    parallel::load(mem, globals)?;
+    port::load(mem, globals)?;
+    #[cfg(feature = "digest")]
+    digest::load(mem, globals)?;
+    Ok(())
+}
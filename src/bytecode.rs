@@ -1,12 +1,17 @@
 use itertools::join;
 use std::cell::Cell;
+use std::convert::TryFrom;
 use std::fmt;
+use std::io::{self, BufWriter, Write};
 
-use crate::array::{Array, ArraySize};
+use crate::array::{Array, ArraySize, ArrayU32};
 use crate::containers::{
-    Container, IndexedContainer, SliceableContainer, StackAnyContainer, StackContainer,
+    Container, IndexedAnyContainer, IndexedContainer, SliceableContainer, StackAnyContainer,
+    StackContainer,
 };
-use crate::error::{err_eval, RuntimeError};
+use crate::dict::Dict;
+use crate::error::{err_eval, spos, ErrorKind, RuntimeError, SourcePos};
+use crate::heapcheck::{HeapChecker, Verify};
 use crate::list::List;
 use crate::memory::MutatorView;
 use crate::printer::Print;
@@ -30,9 +35,26 @@ pub type JumpOffset = i16;
 /// Jump offset when the target is still unknown.
 pub const JUMP_UNKNOWN: i16 = 0x7fff;
 
+/// The jump offset of an instruction that compares two registers and jumps if the comparison does
+/// not hold. Such an instruction is always followed by a Jump to the same target, which it skips
+/// if the comparison holds. The Jump is taken instead, by an offset of 0, when the target is too
+/// far for a short offset.
+pub type ShortJumpOffset = i8;
+
 /// Argument count for a function call or partial application
 pub type NumArgs = u8;
 
+/// Each LoadGlobal instruction of a function has an inline cache of where its global is bound,
+/// identified by a slot number given to it as it is pushed, see `ByteCode::push()`
+pub type GlobalCacheSlot = u8;
+/// Cache slot of a LoadGlobal instruction beyond the number of slots a function can have, which
+/// looks up its global every time
+pub const NO_GLOBAL_CACHE: GlobalCacheSlot = 0xff;
+/// Content of a cache slot that has not been filled
+const EMPTY_GLOBAL_CACHE: u32 = u32::MAX;
+/// Line of a position entry marking the instructions from its ip onwards as having no position
+const NO_POSITION: u32 = u32::MAX;
+
 /// Count of call frames to look back to find a nonlocal
 pub type FrameOffset = u8;
 
@@ -80,6 +102,31 @@ pub enum Opcode {
         test1: Register,
         test2: Register,
     },
+    IsLessThan {
+        dest: Register,
+        left: Register,
+        right: Register,
+    },
+    IsGreaterThan {
+        dest: Register,
+        left: Register,
+        right: Register,
+    },
+    IsLessOrEqual {
+        dest: Register,
+        left: Register,
+        right: Register,
+    },
+    IsGreaterOrEqual {
+        dest: Register,
+        left: Register,
+        right: Register,
+    },
+    IsNumericEqual {
+        dest: Register,
+        left: Register,
+        right: Register,
+    },
     Jump {
         offset: JumpOffset,
     },
@@ -91,12 +138,43 @@ pub enum Opcode {
         test: Register,
         offset: JumpOffset,
     },
+    JumpIfNotIdentical {
+        test1: Register,
+        test2: Register,
+        offset: ShortJumpOffset,
+    },
+    JumpIfNotLessThan {
+        left: Register,
+        right: Register,
+        offset: ShortJumpOffset,
+    },
+    JumpIfNotGreaterThan {
+        left: Register,
+        right: Register,
+        offset: ShortJumpOffset,
+    },
+    JumpIfNotLessOrEqual {
+        left: Register,
+        right: Register,
+        offset: ShortJumpOffset,
+    },
+    JumpIfNotGreaterOrEqual {
+        left: Register,
+        right: Register,
+        offset: ShortJumpOffset,
+    },
+    JumpIfNotNumericEqual {
+        left: Register,
+        right: Register,
+        offset: ShortJumpOffset,
+    },
     LoadNil {
         dest: Register,
     },
     LoadGlobal {
         dest: Register,
         name: Register,
+        cache: GlobalCacheSlot,
     },
     StoreGlobal {
         src: Register,
@@ -107,6 +185,21 @@ pub enum Opcode {
         dest: Register,
         arg_count: NumArgs,
     },
+    TailCall {
+        function: Register,
+        dest: Register,
+        arg_count: NumArgs,
+    },
+    Apply {
+        function: Register,
+        dest: Register,
+        arg_count: NumArgs,
+    },
+    CallWithContinuation {
+        function: Register,
+        dest: Register,
+    },
+    EndContinuation,
     MakeClosure {
         dest: Register,
         function: Register,
@@ -139,6 +232,11 @@ pub enum Opcode {
         num: Register,
         denom: Register,
     },
+    Modulo {
+        dest: Register,
+        num: Register,
+        denom: Register,
+    },
     GetUpvalue {
         dest: Register,
         src: UpvalueId,
@@ -152,6 +250,74 @@ pub enum Opcode {
         reg2: Register,
         reg3: Register,
     },
+    BindParameter {
+        param: Register,
+        value: Register,
+    },
+    UnbindParameters {
+        count: u8,
+    },
+    BeginLimit {
+        limit: Register,
+        offset: JumpOffset,
+    },
+    EndLimit,
+    AssertFail {
+        literal_id: LiteralId,
+    },
+    Yield {
+        dest: Register,
+        value: Register,
+    },
+    PushHandler {
+        dest: Register,
+        offset: JumpOffset,
+    },
+    PushCleanup {
+        thunk: Register,
+    },
+    GetField {
+        dest: Register,
+        object: Register,
+        key: Register,
+    },
+    PopHandler,
+}
+
+/// Return true if the instruction compares two registers and jumps by a `ShortJumpOffset`
+pub fn is_short_jump(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::JumpIfNotIdentical { .. }
+            | Opcode::JumpIfNotLessThan { .. }
+            | Opcode::JumpIfNotGreaterThan { .. }
+            | Opcode::JumpIfNotLessOrEqual { .. }
+            | Opcode::JumpIfNotGreaterOrEqual { .. }
+            | Opcode::JumpIfNotNumericEqual { .. }
+    )
+}
+
+/// Return the jump offset of an instruction that jumps, None for any other instruction
+pub fn jump_offset(opcode: Opcode) -> Option<JumpOffset> {
+    match opcode {
+        Opcode::Jump { offset }
+        | Opcode::JumpIfTrue { offset, .. }
+        | Opcode::JumpIfNotTrue { offset, .. }
+        | Opcode::BeginLimit { offset, .. }
+        | Opcode::PushHandler { offset, .. } => Some(offset),
+        Opcode::JumpIfNotIdentical { offset, .. }
+        | Opcode::JumpIfNotLessThan { offset, .. }
+        | Opcode::JumpIfNotGreaterThan { offset, .. }
+        | Opcode::JumpIfNotLessOrEqual { offset, .. }
+        | Opcode::JumpIfNotGreaterOrEqual { offset, .. }
+        | Opcode::JumpIfNotNumericEqual { offset, .. } => Some(offset as JumpOffset),
+        _ => None,
+    }
+}
+
+/// Return the short form of a jump offset, or 0 to take the following Jump if it does not fit
+fn short_offset(offset: JumpOffset) -> ShortJumpOffset {
+    ShortJumpOffset::try_from(offset).unwrap_or(0)
 }
 
 /// Bytecode is stored as fixed-width 32-bit values.
@@ -167,6 +333,14 @@ pub type Literals = List;
 pub struct ByteCode {
     code: ArrayOpcode,
     literals: Literals,
+    /// Source positions of the instructions, as (ip, line, column) triples in instruction order.
+    /// An entry is only added where the position changes, so the position of an instruction is
+    /// that of the nearest entry at or before it. Positions are not kept when bytecode is
+    /// serialized.
+    positions: ArrayU32,
+    /// The inline cache of each LoadGlobal instruction: the index of the entry binding the global
+    /// in the globals dict when it was last looked up, see `Dict::locate()`
+    global_cache: ArrayU32,
 }
 
 impl ByteCode {
@@ -177,14 +351,117 @@ impl ByteCode {
         mem.alloc(ByteCode {
             code: ArrayOpcode::new(),
             literals: Literals::new(),
+            positions: ArrayU32::new(),
+            global_cache: ArrayU32::new(),
         })
     }
 
-    /// Append an instuction to the back of the sequence
+    /// Append an instuction to the back of the sequence. A LoadGlobal is given the next cache
+    /// slot, whatever slot it names, so that loaded bytecode cannot share or overrun slots.
     pub fn push<'guard>(&self, mem: &'guard MutatorView, op: Opcode) -> Result<(), RuntimeError> {
+        match op {
+            Opcode::LoadGlobal { dest, name, .. } => self.push_loadglobal(mem, dest, name),
+            _ => self.code.push(mem, op),
+        }
+    }
+
+    /// Append a global lookup to the back of the sequence, with a cache slot of its own if there
+    /// are any left
+    pub fn push_loadglobal<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        dest: Register,
+        name: Register,
+    ) -> Result<(), RuntimeError> {
+        let slots = self.global_cache.length();
+        let cache = if slots < NO_GLOBAL_CACHE as ArraySize {
+            StackContainer::push(&self.global_cache, mem, EMPTY_GLOBAL_CACHE)?;
+            slots as GlobalCacheSlot
+        } else {
+            NO_GLOBAL_CACHE
+        };
+
+        self.code
+            .push(mem, Opcode::LoadGlobal { dest, name, cache })
+    }
+
+    /// Return the value of the global named `name` if the cache slot knows where it is bound in
+    /// `globals`
+    pub fn cached_global<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        cache: GlobalCacheSlot,
+        globals: &Dict,
+        name: TaggedScopedPtr<'guard>,
+    ) -> Option<TaggedScopedPtr<'guard>> {
+        if cache == NO_GLOBAL_CACHE {
+            return None;
+        }
+
+        match IndexedContainer::get(&self.global_cache, guard, cache as ArraySize) {
+            Ok(EMPTY_GLOBAL_CACHE) | Err(_) => None,
+            Ok(index) => globals.value_at(guard, index, name),
+        }
+    }
+
+    /// Remember where a global is bound in the globals dict, as returned by `Dict::locate()`
+    pub fn cache_global<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        cache: GlobalCacheSlot,
+        index: ArraySize,
+    ) -> Result<(), RuntimeError> {
+        if cache != NO_GLOBAL_CACHE {
+            IndexedContainer::set(&self.global_cache, guard, cache as ArraySize, index)?;
+        }
+        Ok(())
+    }
+
+    /// Append an instruction to the back of the sequence with its source position, or with no
+    /// position if it is not known
+    pub fn push_with_pos<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        op: Opcode,
+        pos: Option<SourcePos>,
+    ) -> Result<(), RuntimeError> {
+        let (line, column) = match pos {
+            Some(pos) => (pos.line, pos.column),
+            None => (NO_POSITION, 0),
+        };
+
+        let current = self.positions.access_slice(mem, |positions| {
+            positions
+                .chunks_exact(3)
+                .last()
+                .map(|position| (position[1], position[2]))
+        });
+
+        if current.unwrap_or((NO_POSITION, 0)) != (line, column) {
+            let ip = self.next_instruction();
+            StackContainer::push(&self.positions, mem, ip)?;
+            StackContainer::push(&self.positions, mem, line)?;
+            StackContainer::push(&self.positions, mem, column)?;
+        }
         self.code.push(mem, op)
     }
 
+    /// Return the source position of the instruction at `ip`, if it has one
+    pub fn position<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        ip: ArraySize,
+    ) -> Option<SourcePos> {
+        self.positions.access_slice(guard, |positions| {
+            positions
+                .chunks_exact(3)
+                .take_while(|position| position[0] <= ip)
+                .last()
+                .filter(|position| position[1] != NO_POSITION)
+                .map(|position| spos(position[1], position[2]))
+        })
+    }
+
     /// Set the jump offset of an existing jump instruction to a new value
     pub fn update_jump_offset<'guard>(
         &self,
@@ -197,6 +474,40 @@ impl ByteCode {
             Opcode::Jump { offset: _ } => Opcode::Jump { offset },
             Opcode::JumpIfTrue { test, offset: _ } => Opcode::JumpIfTrue { test, offset },
             Opcode::JumpIfNotTrue { test, offset: _ } => Opcode::JumpIfNotTrue { test, offset },
+            Opcode::BeginLimit { limit, offset: _ } => Opcode::BeginLimit { limit, offset },
+            Opcode::PushHandler { dest, offset: _ } => Opcode::PushHandler { dest, offset },
+            Opcode::JumpIfNotIdentical { test1, test2, .. } => Opcode::JumpIfNotIdentical {
+                test1,
+                test2,
+                offset: short_offset(offset),
+            },
+            Opcode::JumpIfNotLessThan { left, right, .. } => Opcode::JumpIfNotLessThan {
+                left,
+                right,
+                offset: short_offset(offset),
+            },
+            Opcode::JumpIfNotGreaterThan { left, right, .. } => Opcode::JumpIfNotGreaterThan {
+                left,
+                right,
+                offset: short_offset(offset),
+            },
+            Opcode::JumpIfNotLessOrEqual { left, right, .. } => Opcode::JumpIfNotLessOrEqual {
+                left,
+                right,
+                offset: short_offset(offset),
+            },
+            Opcode::JumpIfNotGreaterOrEqual { left, right, .. } => {
+                Opcode::JumpIfNotGreaterOrEqual {
+                    left,
+                    right,
+                    offset: short_offset(offset),
+                }
+            }
+            Opcode::JumpIfNotNumericEqual { left, right, .. } => Opcode::JumpIfNotNumericEqual {
+                left,
+                right,
+                offset: short_offset(offset),
+            },
             _ => {
                 return Err(err_eval(
                     "Cannot modify jump offset for non-jump instruction",
@@ -204,9 +515,51 @@ impl ByteCode {
             }
         };
         self.code.set(mem, instruction, new_code)?;
+
+        // the Jump that follows a short jump goes to the same target
+        if is_short_jump(code) {
+            self.update_jump_offset(mem, instruction + 1, offset - 1)?;
+        }
         Ok(())
     }
 
+    /// Return the last instruction, None if there are none
+    pub fn last_opcode<'guard>(&self, guard: &'guard dyn MutatorScope) -> Option<Opcode> {
+        match self.code.length() {
+            0 => None,
+            length => self.code.get(guard, length - 1).ok(),
+        }
+    }
+
+    /// Replace the last instruction
+    pub fn replace_last<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        op: Opcode,
+    ) -> Result<(), RuntimeError> {
+        self.code.set(mem, self.last_instruction(), op)
+    }
+
+    /// Return true if any instruction from `from` onwards jumps to `target`
+    pub fn is_jump_target<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        from: ArraySize,
+        target: ArraySize,
+    ) -> bool {
+        self.code.access_slice(guard, |code| {
+            code.iter()
+                .enumerate()
+                .skip(from as usize)
+                .any(|(ip, opcode)| match jump_offset(*opcode) {
+                    Some(offset) if offset != JUMP_UNKNOWN => {
+                        ip as i64 + 1 + offset as i64 == target as i64
+                    }
+                    _ => false,
+                })
+        })
+    }
+
     /// Append a literal-load operation to the back of the sequence
     pub fn push_loadlit<'guard>(
         &self,
@@ -230,6 +583,119 @@ impl ByteCode {
         Ok(lit_id)
     }
 
+    /// Return a listing of the instructions, one per line and numbered, with the value of each
+    /// literal that is loaded shown alongside the instruction that loads it
+    pub fn disassemble<'guard>(&self, guard: &'guard dyn MutatorScope) -> String {
+        let mut listing = Vec::new();
+        self.disassemble_to(guard, &mut listing)
+            .expect("Writing to a Vec cannot fail");
+        String::from_utf8_lossy(&listing).into_owned()
+    }
+
+    /// Write the listing that `disassemble()` returns to an output stream as it is produced,
+    /// through a buffer
+    pub fn disassemble_to<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let mut out = BufWriter::new(out);
+
+        self.code.access_slice(guard, |code| {
+            for (index, opcode) in code.iter().enumerate() {
+                write!(out, "{:4}  {:?}", index, opcode)?;
+
+                match opcode {
+                    Opcode::LoadLiteral { literal_id, .. } => {
+                        if let Ok(literal) = IndexedAnyContainer::get(
+                            &self.literals,
+                            guard,
+                            *literal_id as ArraySize,
+                        ) {
+                            write!(out, "  ; {}", literal)?;
+                        }
+                    }
+                    Opcode::Call { .. } => write!(out, "  ; non-tail call")?,
+                    Opcode::TailCall { .. } => write!(out, "  ; tail call, reuses frame")?,
+                    Opcode::Apply { .. } => write!(out, "  ; call spreading the last argument")?,
+                    Opcode::CallWithContinuation { .. } => {
+                        write!(out, "  ; call passing the current continuation")?
+                    }
+                    _ if is_short_jump(*opcode) => {
+                        write!(out, "  ; skips the next Jump if the comparison holds")?
+                    }
+                    _ => (),
+                }
+
+                writeln!(out)?;
+            }
+
+            Ok::<(), io::Error>(())
+        })?;
+
+        out.flush()
+    }
+
+    /// Return a copy of the instructions
+    pub fn instructions<'guard>(&self, guard: &'guard dyn MutatorScope) -> Vec<Opcode> {
+        let mut instructions = Vec::new();
+        self.code
+            .access_slice(guard, |code| instructions.extend_from_slice(code));
+        instructions
+    }
+
+    /// Return the literals, in order of their LiteralId
+    pub fn literals<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Result<Vec<TaggedScopedPtr<'guard>>, RuntimeError> {
+        (0..self.literals.length())
+            .map(|index| IndexedAnyContainer::get(&self.literals, guard, index))
+            .collect()
+    }
+
+    /// Check that every literal an instruction loads exists and that every jump lands inside the
+    /// bytecode, or exactly at its end. The compiler always produces valid bytecode; this is for
+    /// bytecode that was loaded from elsewhere.
+    pub fn validate<'guard>(&self, guard: &'guard dyn MutatorScope) -> Result<(), RuntimeError> {
+        let length = self.code.length() as i64;
+        let literals = self.literals.length();
+        let code = self.instructions(guard);
+
+        for (ip, opcode) in code.iter().enumerate() {
+            let ip = ip as ArraySize;
+
+            match *opcode {
+                Opcode::LoadLiteral { literal_id, .. } | Opcode::AssertFail { literal_id } => {
+                    if literal_id as ArraySize >= literals {
+                        return Err(RuntimeError::new(ErrorKind::BadLiteralId {
+                            ip,
+                            literal_id,
+                        }));
+                    }
+                }
+
+                _ => {
+                    if let Some(offset) = jump_offset(*opcode) {
+                        let target = ip as i64 + 1 + offset as i64;
+                        if target < 0 || target > length {
+                            return Err(RuntimeError::new(ErrorKind::BadJump { ip, offset }));
+                        }
+
+                        // a short jump must be followed by the Jump it skips
+                        if is_short_jump(*opcode)
+                            && !matches!(code.get(ip as usize + 1), Some(Opcode::Jump { .. }))
+                        {
+                            return Err(RuntimeError::new(ErrorKind::BadJump { ip, offset }));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Get the index into the bytecode array of the last instruction
     pub fn last_instruction(&self) -> ArraySize {
         self.code.length() - 1
@@ -241,6 +707,19 @@ impl ByteCode {
     }
 }
 
+impl Verify for ByteCode {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        self.code.verify_backing(checker)?;
+        self.positions.verify_backing(checker)?;
+        self.global_cache.verify_backing(checker)?;
+        self.literals.verify_children(guard, checker)
+    }
+}
+
 impl Print for ByteCode {
     fn print<'guard>(
         &self,
@@ -287,27 +766,68 @@ impl InstructionStream {
         &self,
         guard: &'guard dyn MutatorScope,
     ) -> Result<Opcode, RuntimeError> {
-        let instr = self
-            .instructions
-            .get(guard)
-            .code
-            .get(guard, self.ip.get())?;
-        self.ip.set(self.ip.get() + 1);
+        let ip = self.ip.get();
+        let code = &self.instructions.get(guard).code;
+        if ip >= code.length() {
+            return Err(RuntimeError::new(ErrorKind::BadInstructionPointer(ip)));
+        }
+
+        let instr = code.get(guard, ip)?;
+        self.ip.set(ip + 1);
         Ok(instr)
     }
 
-    /// Given an index into the literals list, return the pointer in the list at that index.
+    /// Return the next instruction without advancing the instruction pointer
+    pub fn peek_next_opcode<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Result<Opcode, RuntimeError> {
+        let ip = self.ip.get();
+        let code = &self.instructions.get(guard).code;
+        if ip >= code.length() {
+            return Err(RuntimeError::new(ErrorKind::BadInstructionPointer(ip)));
+        }
+
+        code.get(guard, ip)
+    }
+
+    /// Follow a jump by a `ShortJumpOffset`: if the comparison holds, skip the Jump that follows,
+    /// otherwise jump by the offset
+    pub fn short_jump<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        holds: bool,
+        offset: ShortJumpOffset,
+    ) -> Result<(), RuntimeError> {
+        if holds {
+            self.jump(guard, 1)
+        } else {
+            self.jump(guard, offset as JumpOffset)
+        }
+    }
+
+    /// Given an index into the literals list, return the pointer in the list at that index. This
+    /// should be called for the instruction most recently retrieved, whose ip is given in the
+    /// error if the literal does not exist.
     pub fn get_literal<'guard>(
         &self,
         guard: &'guard dyn MutatorScope,
         lit_id: LiteralId,
     ) -> Result<TaggedPtr, RuntimeError> {
-        Ok(IndexedContainer::get(
-            &self.instructions.get(guard).literals,
-            guard,
-            lit_id as ArraySize,
-        )?
-        .get_ptr())
+        let literals = &self.instructions.get(guard).literals;
+        if lit_id as ArraySize >= literals.length() {
+            return Err(RuntimeError::new(ErrorKind::BadLiteralId {
+                ip: self.ip.get().saturating_sub(1),
+                literal_id: lit_id,
+            }));
+        }
+
+        Ok(IndexedContainer::get(literals, guard, lit_id as ArraySize)?.get_ptr())
+    }
+
+    /// Return the bytecode being executed
+    pub fn get_code<'guard>(&self, guard: &'guard dyn MutatorScope) -> ScopedPtr<'guard, ByteCode> {
+        self.instructions.get(guard)
     }
 
     /// Return the next instruction pointer
@@ -315,11 +835,35 @@ impl InstructionStream {
         self.ip.get()
     }
 
-    /// Adjust the instruction pointer by the given signed offset from the current ip
-    pub fn jump(&self, offset: JumpOffset) {
-        let mut ip = self.ip.get() as i32;
-        ip += offset as i32;
+    /// Adjust the instruction pointer by the given signed offset from the current ip. The target
+    /// may be at most the end of the bytecode, as for a jump past a final instruction that is not
+    /// a Return, but not beyond it.
+    pub fn jump<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        offset: JumpOffset,
+    ) -> Result<(), RuntimeError> {
+        let ip = self.ip.get() as i64 + offset as i64;
+        let length = self.instructions.get(guard).code.length() as i64;
+        if ip < 0 || ip > length {
+            return Err(RuntimeError::new(ErrorKind::BadJump {
+                ip: self.ip.get().saturating_sub(1),
+                offset,
+            }));
+        }
+
         self.ip.set(ip as ArraySize);
+        Ok(())
+    }
+}
+
+impl Verify for InstructionStream {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.object(guard, &*self.instructions.get(guard))
     }
 }
 
@@ -335,4 +879,74 @@ mod test {
         // discriminant
         assert!(size_of::<Opcode>() == 4);
     }
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    #[test]
+    fn instruction_stream_out_of_range() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let code = ByteCode::alloc(mem)?;
+            code.push_loadlit(mem, 0, 0)?;
+            code.push_loadlit(mem, 0, 5)?;
+            code.push(mem, Opcode::Jump { offset: -3 })?;
+            code.push(mem, Opcode::Jump { offset: 1 })?;
+            code.push_lit(mem, mem.nil())?;
+
+            let instr = InstructionStream::alloc(mem, code)?;
+
+            // a literal that exists, then one that does not
+            instr.get_next_opcode(mem)?;
+            assert!(instr.get_literal(mem, 0).is_ok());
+            instr.get_next_opcode(mem)?;
+            match instr.get_literal(mem, 5) {
+                Err(error) => assert!(
+                    *error.error_kind()
+                        == ErrorKind::BadLiteralId {
+                            ip: 1,
+                            literal_id: 5
+                        }
+                ),
+                Ok(_) => panic!("Expected a literal id error"),
+            }
+
+            // a jump to before the start, then one to exactly the end
+            instr.get_next_opcode(mem)?;
+            let error = instr.jump(mem, -4).unwrap_err();
+            assert!(*error.error_kind() == ErrorKind::BadJump { ip: 2, offset: -4 });
+            assert!(instr.get_next_ip() == 3);
+
+            instr.get_next_opcode(mem)?;
+            instr.jump(mem, 0)?;
+            assert!(instr.get_next_ip() == 4);
+            assert!(instr.jump(mem, 1).is_err());
+
+            // and running off the end
+            let error = instr.get_next_opcode(mem).unwrap_err();
+            assert!(*error.error_kind() == ErrorKind::BadInstructionPointer(4));
+            assert!(format!("{}", error).contains("4"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
 }
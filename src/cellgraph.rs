@@ -0,0 +1,322 @@
+/// Incremental recomputation of named expressions.
+///
+/// A CellGraph is a set of cells, each a global variable whose value is computed by an
+/// expression. The value of a cell is kept in its global binding on the Thread, so cell
+/// expressions read other cells and ordinary globals alike, by name.
+///
+/// Which globals each expression reads is found when it is compiled, by `compile_with_info()`.
+/// Changing a global through the graph, or redefining a cell, marks every cell that depends on it,
+/// directly or through other cells, as stale. Nothing is evaluated until a value is asked for:
+/// a stale cell is then recomputed, after any stale cells it reads, and each cell is evaluated at
+/// most once per change.
+///
+/// Cell expressions may not assign globals, and cells may not depend on themselves. Changes made
+/// to globals other than through the graph are not tracked.
+use std::collections::{BTreeSet, HashMap};
+
+use crate::compiler::compile_with_info;
+use crate::error::{err_eval, RuntimeError};
+use crate::function::Function;
+use crate::memory::MutatorView;
+use crate::parser::parse;
+use crate::safeptr::{ScopedPtr, TaggedScopedPtr};
+use crate::vm::Thread;
+
+/// A named expression and the globals it reads
+struct Cell<'guard> {
+    /// The compiled expression
+    function: ScopedPtr<'guard, Function>,
+    /// Names of the globals, including other cells, the expression reads
+    reads: BTreeSet<String>,
+    /// True if the global binding does not hold the current value of the expression
+    stale: bool,
+}
+
+/// Named expressions whose values are recomputed when what they depend on changes, see module
+/// documentation
+pub struct CellGraph<'guard> {
+    cells: HashMap<String, Cell<'guard>>,
+    /// For each global name, the cells whose expressions read it
+    dependents: HashMap<String, BTreeSet<String>>,
+}
+
+impl<'guard> CellGraph<'guard> {
+    /// Create an empty graph
+    pub fn new() -> CellGraph<'guard> {
+        CellGraph {
+            cells: HashMap::new(),
+            dependents: HashMap::new(),
+        }
+    }
+
+    /// Define or redefine the named cell as the given source expression, compiled for the given
+    /// Thread. The cell and every cell that depends on it become stale.
+    pub fn define(
+        &mut self,
+        mem: &'guard MutatorView,
+        thread: &Thread,
+        name: &str,
+        source: &str,
+    ) -> Result<(), RuntimeError> {
+        let (function, access) = compile_with_info(mem, thread, parse(mem, source)?)?;
+        if !access.writes.is_empty() || access.dynamic_writes {
+            return Err(err_eval(&format!(
+                "Expression of cell {} may not assign globals",
+                name
+            )));
+        }
+
+        if let Some(old) = self.cells.remove(name) {
+            self.forget_reads(name, &old.reads);
+        }
+
+        for read in &access.reads {
+            self.dependents
+                .entry(read.clone())
+                .or_default()
+                .insert(String::from(name));
+        }
+
+        self.cells.insert(
+            String::from(name),
+            Cell {
+                function,
+                reads: access.reads,
+                stale: true,
+            },
+        );
+        self.invalidate(name);
+
+        Ok(())
+    }
+
+    /// Remove the named cell, leaving its global bound to its last value. Cells that depend on it
+    /// become stale. Returns false if there is no such cell.
+    pub fn remove(&mut self, name: &str) -> bool {
+        match self.cells.remove(name) {
+            Some(old) => {
+                self.forget_reads(name, &old.reads);
+                self.invalidate(name);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Bind the named global, which must not be a cell, to a value. Every cell that depends on it
+    /// becomes stale.
+    pub fn set(
+        &mut self,
+        mem: &'guard MutatorView,
+        thread: &Thread,
+        name: &str,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        if self.cells.contains_key(name) {
+            return Err(err_eval(&format!(
+                "{} is a cell and cannot be set directly",
+                name
+            )));
+        }
+
+        thread.define_global(mem, mem.lookup_sym(name), value)?;
+        self.invalidate(name);
+
+        Ok(())
+    }
+
+    /// Return the current value of the named cell or global, first recomputing the cell and any
+    /// stale cells it depends on if it is stale
+    pub fn value(
+        &mut self,
+        mem: &'guard MutatorView,
+        thread: &Thread,
+        name: &str,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        self.refresh(mem, thread, name, &mut Vec::new())?;
+
+        match thread.lookup_global(mem, mem.lookup_sym(name)) {
+            Some(value) => Ok(value),
+            None => Err(err_eval(&format!(
+                "Symbol {} is not bound to a value",
+                name
+            ))),
+        }
+    }
+
+    /// Return true if the named cell exists
+    pub fn contains(&self, name: &str) -> bool {
+        self.cells.contains_key(name)
+    }
+
+    /// Return true if the named cell exists and must be recomputed before its value is current
+    pub fn is_stale(&self, name: &str) -> bool {
+        self.cells.get(name).is_some_and(|cell| cell.stale)
+    }
+
+    /// Recompute the named cell if it is stale, after recomputing the stale cells it reads.
+    /// `computing` holds the cells whose recomputation is in progress, to detect cycles.
+    fn refresh(
+        &mut self,
+        mem: &'guard MutatorView,
+        thread: &Thread,
+        name: &str,
+        computing: &mut Vec<String>,
+    ) -> Result<(), RuntimeError> {
+        let reads = match self.cells.get(name) {
+            Some(cell) if cell.stale => cell.reads.clone(),
+            _ => return Ok(()),
+        };
+
+        if computing.iter().any(|c| c == name) {
+            computing.push(String::from(name));
+            return Err(err_eval(&format!(
+                "Cells depend on each other: {}",
+                computing.join(" -> ")
+            )));
+        }
+
+        computing.push(String::from(name));
+        for read in &reads {
+            self.refresh(mem, thread, read, computing)?;
+        }
+        computing.pop();
+
+        // a failed evaluation leaves the cell stale and its global unchanged
+        let function = self.cells[name].function;
+        let value = thread.quick_vm_eval(mem, function)?;
+        thread.define_global(mem, mem.lookup_sym(name), value)?;
+
+        if let Some(cell) = self.cells.get_mut(name) {
+            cell.stale = false;
+        }
+
+        Ok(())
+    }
+
+    /// Mark every cell that depends on the named global, directly or indirectly, as stale, along
+    /// with the named cell itself
+    fn invalidate(&mut self, name: &str) {
+        let mut pending = vec![String::from(name)];
+        let mut visited = BTreeSet::new();
+
+        while let Some(next) = pending.pop() {
+            if !visited.insert(next.clone()) {
+                continue;
+            }
+
+            if let Some(cell) = self.cells.get_mut(&next) {
+                cell.stale = true;
+            }
+
+            if let Some(dependents) = self.dependents.get(&next) {
+                pending.extend(dependents.iter().cloned());
+            }
+        }
+    }
+
+    /// Remove the named cell from the dependents of the globals it read
+    fn forget_reads(&mut self, name: &str, reads: &BTreeSet<String>) {
+        for read in reads {
+            if let Some(dependents) = self.dependents.get_mut(read) {
+                dependents.remove(name);
+                if dependents.is_empty() {
+                    self.dependents.remove(read);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::{Memory, Mutator};
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    #[test]
+    fn cellgraph_recompute_on_demand() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+            let mut cells = CellGraph::new();
+
+            cells.set(mem, &t, "price", parse(mem, "10")?)?;
+            cells.set(mem, &t, "qty", parse(mem, "3")?)?;
+            cells.define(mem, &t, "subtotal", "(* price qty)")?;
+            cells.define(mem, &t, "total", "(+ subtotal shipping)")?;
+            cells.define(mem, &t, "shipping", "(if (< subtotal 50) 5 0)")?;
+
+            assert!(cells.value(mem, &t, "total")?.as_int() == Some(35));
+            assert!(!cells.is_stale("total") && !cells.is_stale("shipping"));
+
+            // changing an input marks only its dependents stale
+            cells.define(mem, &t, "label", "(quote order)")?;
+            cells.value(mem, &t, "label")?;
+            cells.set(mem, &t, "qty", parse(mem, "6")?)?;
+            assert!(cells.is_stale("subtotal") && cells.is_stale("total"));
+            assert!(!cells.is_stale("label"));
+
+            assert!(cells.value(mem, &t, "total")?.as_int() == Some(60));
+            assert!(cells.value(mem, &t, "shipping")?.as_int() == Some(0));
+
+            // redefining a cell invalidates its dependents
+            cells.define(mem, &t, "shipping", "7")?;
+            assert!(cells.is_stale("total"));
+            assert!(cells.value(mem, &t, "total")?.as_int() == Some(67));
+
+            assert!(cells.remove("shipping") && !cells.contains("shipping"));
+            assert!(cells.value(mem, &t, "total")?.as_int() == Some(67));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn cellgraph_errors() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+            let mut cells = CellGraph::new();
+
+            assert!(cells.define(mem, &t, "bad", "(set! x 1)").is_err());
+            assert!(cells.define(mem, &t, "bad", "(set 'x 1)").is_err());
+
+            cells.define(mem, &t, "a", "(+ b 1)")?;
+            cells.define(mem, &t, "b", "(+ a 1)")?;
+            assert!(cells.value(mem, &t, "a").is_err());
+            assert!(cells.set(mem, &t, "a", parse(mem, "1")?).is_err());
+
+            // an unbound input leaves the cell stale until the input is set
+            cells.define(mem, &t, "c", "(+ d 1)")?;
+            assert!(cells.value(mem, &t, "c").is_err());
+            assert!(cells.is_stale("c"));
+            cells.set(mem, &t, "d", parse(mem, "1")?)?;
+            assert!(cells.value(mem, &t, "c")?.as_int() == Some(2));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+}
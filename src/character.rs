@@ -0,0 +1,98 @@
+/// Characters, which are stored inline in a TaggedPtr.
+///
+/// A character literal is written `#\` followed by the character, as in `#\a` or `#\(`, or by the
+/// name of one of the characters that would otherwise be hard to see: `#\space`, `#\newline`,
+/// `#\tab` and `#\return`. Characters print the same way.
+use std::fmt;
+
+use crate::builtins::define;
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
+use crate::memory::MutatorView;
+use crate::safeptr::{ScopedPtr, TaggedScopedPtr};
+use crate::taggedptr::{TaggedPtr, Value};
+
+/// Characters that are written by name
+const NAMED_CHARS: [(&str, char); 4] = [
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+];
+
+/// Return the character written as the given text following `#\`, either a single character or
+/// the name of one
+pub fn char_from_name(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(c);
+    }
+
+    NAMED_CHARS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, c)| *c)
+}
+
+/// Write a character as a literal
+pub fn print_char(c: char, f: &mut fmt::Formatter) -> fmt::Result {
+    match NAMED_CHARS.iter().find(|(_, named)| *named == c) {
+        Some((name, _)) => write!(f, "#\\{}", name),
+        None => write!(f, "#\\{}", c),
+    }
+}
+
+/// Return the character value of an argument
+fn char_arg(value: TaggedScopedPtr) -> Result<char, RuntimeError> {
+    match *value {
+        Value::Char(c) => Ok(c),
+        _ => Err(err_eval(&format!("Expected a character, got {}", value))),
+    }
+}
+
+/// (char->int char) -> the Unicode code point of the character
+fn char_to_int_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let c = char_arg(args[0])?;
+    Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(c as isize)))
+}
+
+/// (int->char code) -> the character with the given Unicode code point
+fn int_to_char_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let code = args[0]
+        .as_int()
+        .filter(|code| *code >= 0 && *code <= u32::MAX as isize)
+        .and_then(|code| std::char::from_u32(code as u32));
+
+    match code {
+        Some(c) => Ok(TaggedScopedPtr::new(mem, TaggedPtr::char(c))),
+        None => Err(err_eval(&format!(
+            "{} is not a Unicode code point",
+            args[0]
+        ))),
+    }
+}
+
+/// (char=? a b) -> true if both are the same character
+fn char_equal_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(mem.boolean(char_arg(args[0])? == char_arg(args[1])?))
+}
+
+/// Bind the character builtins into the given globals Dict
+pub fn load<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+) -> Result<(), RuntimeError> {
+    define(mem, globals, "char->int", 1, char_to_int_fn)?;
+    define(mem, globals, "int->char", 1, int_to_char_fn)?;
+    define(mem, globals, "char=?", 2, char_equal_fn)?;
+    Ok(())
+}
@@ -0,0 +1,247 @@
+/// Encoding and identifier builtins: UUIDs, base64 and hex.
+///
+/// Byte data is represented as ArrayU8. Functions that take bytes also accept Text, in which case
+/// the UTF-8 encoding of the text is used.
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::array::ArrayU8;
+use crate::builtins::{define, define_with_thread};
+use crate::containers::ContainerFromSlice;
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
+use crate::memory::MutatorView;
+use crate::safeptr::{MutatorScope, ScopedPtr, TaggedScopedPtr};
+use crate::taggedptr::Value;
+use crate::vm::Thread;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Encode bytes as padded base64 using the standard alphabet of RFC 4648
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+
+    for chunk in bytes.chunks(3) {
+        let b0 = chunk[0] as usize;
+        let b1 = chunk.get(1).map_or(0, |b| *b as usize);
+        let b2 = chunk.get(2).map_or(0, |b| *b as usize);
+
+        encoded.push(BASE64_ALPHABET[b0 >> 2] as char);
+        encoded.push(BASE64_ALPHABET[((b0 & 0x03) << 4) | (b1 >> 4)] as char);
+
+        if chunk.len() > 1 {
+            encoded.push(BASE64_ALPHABET[((b1 & 0x0f) << 2) | (b2 >> 6)] as char);
+        } else {
+            encoded.push('=');
+        }
+
+        if chunk.len() > 2 {
+            encoded.push(BASE64_ALPHABET[b2 & 0x3f] as char);
+        } else {
+            encoded.push('=');
+        }
+    }
+
+    encoded
+}
+
+/// Decode padded base64 in the standard alphabet of RFC 4648
+pub fn base64_decode(text: &str) -> Result<Vec<u8>, RuntimeError> {
+    let text = text.as_bytes();
+    if text.len() % 4 != 0 {
+        return Err(err_eval("Base64 input length is not a multiple of 4"));
+    }
+
+    let sextet = |c: u8| -> Result<u32, RuntimeError> {
+        match BASE64_ALPHABET.iter().position(|a| *a == c) {
+            Some(index) => Ok(index as u32),
+            None => Err(err_eval(&format!(
+                "Invalid character in base64 input: {:?}",
+                c as char
+            ))),
+        }
+    };
+
+    let mut decoded = Vec::with_capacity(text.len() / 4 * 3);
+
+    for (index, chunk) in text.chunks(4).enumerate() {
+        let last = index == text.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return Err(err_eval("Invalid padding in base64 input"));
+        }
+
+        let mut bits = 0;
+        for c in &chunk[..4 - padding] {
+            bits = (bits << 6) | sextet(*c)?;
+        }
+        bits <<= 6 * padding as u32;
+
+        decoded.push((bits >> 16) as u8);
+        if padding < 2 {
+            decoded.push((bits >> 8) as u8);
+        }
+        if padding < 1 {
+            decoded.push(bits as u8);
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Encode bytes as lowercase hexadecimal
+pub fn hex_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        encoded.push(HEX_DIGITS[(byte >> 4) as usize] as char);
+        encoded.push(HEX_DIGITS[(byte & 0x0f) as usize] as char);
+    }
+    encoded
+}
+
+/// Generate a random (version 4) UUID in the standard hyphenated form.
+///
+/// The random bits come from the standard library's per-process random hash keys, so are
+/// unpredictable enough for identifiers but not suitable for secrets.
+pub fn uuid4() -> String {
+    let random = RandomState::new();
+    let mut bytes = [0u8; 16];
+
+    for (index, half) in bytes.chunks_mut(8).enumerate() {
+        let mut hasher = random.build_hasher();
+        hasher.write_usize(index);
+        half.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+
+    // set the version and variant bits
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = hex_encode(&bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Return a copy of the bytes of an ArrayU8 or Text argument
+pub fn bytes_arg<'guard>(
+    guard: &'guard dyn MutatorScope,
+    arg: TaggedScopedPtr<'guard>,
+) -> Result<Vec<u8>, RuntimeError> {
+    match *arg {
+        Value::ArrayU8(bytes) => Ok(unsafe { bytes.as_slice(guard) }.to_vec()),
+        Value::Text(text) => Ok(text.as_str(guard).as_bytes().to_vec()),
+        _ => Err(err_eval("Expected bytes or text")),
+    }
+}
+
+/// Allocate a Text result
+pub fn text_result<'guard>(
+    mem: &'guard MutatorView,
+    text: &str,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    mem.text(text)
+}
+
+/// (uuid4) -> a random UUID as text. A recording of the evaluation keeps the UUID, so that
+/// replaying it returns the same one.
+fn uuid4_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    _args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    text_result(
+        mem,
+        &thread.nondeterministic_input("uuid4", || Ok(uuid4()))?,
+    )
+}
+
+/// (base64-encode bytes) -> base64 text
+fn base64_encode_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    text_result(mem, &base64_encode(&bytes_arg(mem, args[0])?))
+}
+
+/// (base64-decode text) -> bytes
+fn base64_decode_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let decoded = match *args[0] {
+        Value::Text(text) => base64_decode(text.as_str(mem))?,
+        Value::Symbol(symbol) => base64_decode(symbol.as_str(mem))?,
+        _ => return Err(err_eval("Expected base64 text")),
+    };
+
+    mem.size_limits().check_vector(decoded.len())?;
+    let bytes: ScopedPtr<'guard, ArrayU8> = ArrayU8::from_slice(mem, &decoded)?;
+    Ok(bytes.as_tagged(mem))
+}
+
+/// (hex-encode bytes) -> lowercase hexadecimal text
+fn hex_encode_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    text_result(mem, &hex_encode(&bytes_arg(mem, args[0])?))
+}
+
+/// Bind the codec builtins into the given globals Dict
+pub fn load<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+) -> Result<(), RuntimeError> {
+    define_with_thread(mem, globals, "uuid4", 0, uuid4_fn)?;
+    define(mem, globals, "base64-encode", 1, base64_encode_fn)?;
+    define(mem, globals, "base64-decode", 1, base64_decode_fn)?;
+    define(mem, globals, "hex-encode", 1, hex_encode_fn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn codec_base64_rfc4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+
+        for (plain, encoded) in vectors.iter() {
+            assert!(base64_encode(plain.as_bytes()) == *encoded);
+            assert!(base64_decode(encoded).unwrap() == plain.as_bytes());
+        }
+
+        assert!(base64_decode("Zm9").is_err());
+        assert!(base64_decode("Zm9v!A==").is_err());
+        assert!(base64_decode("Zg==Zm9v").is_err());
+    }
+
+    #[test]
+    fn codec_hex_and_uuid() {
+        assert!(hex_encode(&[0x00, 0x7f, 0xab, 0xff]) == "007fabff");
+
+        let uuid = uuid4();
+        assert!(uuid.len() == 36);
+        assert!(uuid.chars().nth(14) == Some('4'));
+        assert!("89ab".contains(uuid.chars().nth(19).unwrap()));
+        assert!(uuid4() != uuid);
+    }
+}
@@ -0,0 +1,356 @@
+/// A total order over all runtime values, so that heterogeneous data can be sorted and used as
+/// ordered keys.
+///
+/// Values of different types are ordered by type:
+///   nil < numbers < symbols < text < pairs < lists < byte arrays < u16 arrays < u32 arrays
+///       < dicts < functions < partials < native functions < sorted maps < priority queues
+///       < queues < parameters < ports
+///
+/// Values of the same type are ordered by content where that is meaningful: numbers numerically,
+/// with NaN last and an exact number before an equal inexact one, symbols and text lexically by
+/// their UTF-8 bytes, pairs and arrays lexicographically by their elements. Containers other than
+/// lists and arrays, and function objects, have no natural order and are ordered by identity,
+/// which is consistent within a single run.
+///
+/// `equal()` tests for the same content as the order does, but also terminates on cyclic lists.
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use num::BigInt;
+
+use crate::array::Array;
+use crate::error::{err_eval, RuntimeError};
+use crate::number::Numeric;
+use crate::safeptr::{MutatorScope, ScopedPtr, TaggedCellPtr};
+use crate::taggedptr::Value;
+
+/// Depth of nested lists beyond which `equal()` gives up, unless given another limit
+pub const DEFAULT_EQUAL_DEPTH_LIMIT: usize = 10_000;
+
+/// Position of each type in the order
+fn type_rank(value: &Value) -> u8 {
+    match value {
+        Value::Nil => 0,
+        Value::Number(_) => 1,
+        Value::NumberObject(_) => 1,
+        Value::Symbol(_) => 2,
+        Value::Text(_) => 3,
+        Value::Pair(_) => 4,
+        Value::List(_) => 5,
+        Value::ArrayU8(_) => 6,
+        Value::ArrayU16(_) => 7,
+        Value::ArrayU32(_) => 8,
+        Value::Dict(_) => 9,
+        Value::Function(_) => 10,
+        Value::Partial(_) => 11,
+        Value::NativeFunction(_) => 12,
+        Value::Upvalue(_) => 13,
+        Value::SortedMap(_) => 14,
+        Value::PriorityQueue(_) => 15,
+        Value::Deque(_) => 16,
+        Value::Parameter(_) => 17,
+        Value::Port(_) => 18,
+        Value::Continuation(_) => 19,
+        Value::Char(_) => 20,
+        Value::Generator(_) => 21,
+    }
+}
+
+/// Return the address of an object
+fn address<T>(object: ScopedPtr<'_, T>) -> usize {
+    &*object as *const T as usize
+}
+
+/// Order two objects by address
+fn identity<T>(left: ScopedPtr<'_, T>, right: ScopedPtr<'_, T>) -> Ordering {
+    address(left).cmp(&address(right))
+}
+
+/// Lexicographic order of two arrays of plain values
+fn compare_arrays<'guard, T>(
+    guard: &'guard dyn MutatorScope,
+    left: ScopedPtr<'guard, Array<T>>,
+    right: ScopedPtr<'guard, Array<T>>,
+) -> Ordering
+where
+    T: Sized + Clone + Ord,
+{
+    // Safe because the slices are only read and nothing else runs while they are held
+    unsafe { left.as_slice(guard).cmp(&right.as_slice(guard)) }
+}
+
+/// Lexicographic order of two Lists
+fn compare_lists<'guard>(
+    guard: &'guard dyn MutatorScope,
+    left: ScopedPtr<'guard, Array<TaggedCellPtr>>,
+    right: ScopedPtr<'guard, Array<TaggedCellPtr>>,
+) -> Ordering {
+    // Safe because the slices are only read and nothing else runs while they are held
+    let (left, right) = unsafe { (left.as_slice(guard), right.as_slice(guard)) };
+
+    for (l, r) in left.iter().zip(right.iter()) {
+        match compare(guard, l.get(guard).value(), r.get(guard).value()) {
+            Ordering::Equal => continue,
+            unequal => return unequal,
+        }
+    }
+
+    left.len().cmp(&right.len())
+}
+
+/// Compare two values according to the total order
+pub fn compare<'guard>(
+    guard: &'guard dyn MutatorScope,
+    left: Value<'guard>,
+    right: Value<'guard>,
+) -> Ordering {
+    match (left, right) {
+        (Value::Nil, Value::Nil) => Ordering::Equal,
+        (Value::Number(l), Value::Number(r)) => l.cmp(&r),
+        (Value::Char(l), Value::Char(r)) => l.cmp(&r),
+        (Value::Symbol(l), Value::Symbol(r)) => l.as_str(guard).cmp(r.as_str(guard)),
+        (Value::Text(l), Value::Text(r)) => l.as_str(guard).cmp(r.as_str(guard)),
+
+        // walk down both lists iteratively rather than recursing on the tail
+        (Value::Pair(mut l), Value::Pair(mut r)) => loop {
+            match compare(
+                guard,
+                l.first.get(guard).value(),
+                r.first.get(guard).value(),
+            ) {
+                Ordering::Equal => (),
+                unequal => return unequal,
+            }
+
+            match (l.second.get(guard).value(), r.second.get(guard).value()) {
+                (Value::Pair(l_next), Value::Pair(r_next)) => {
+                    l = l_next;
+                    r = r_next;
+                }
+                (l_rest, r_rest) => return compare(guard, l_rest, r_rest),
+            }
+        },
+
+        (Value::List(l), Value::List(r)) => compare_lists(guard, l, r),
+        (Value::ArrayU8(l), Value::ArrayU8(r)) => compare_arrays(guard, l, r),
+        (Value::ArrayU16(l), Value::ArrayU16(r)) => compare_arrays(guard, l, r),
+        (Value::ArrayU32(l), Value::ArrayU32(r)) => compare_arrays(guard, l, r),
+
+        (Value::Dict(l), Value::Dict(r)) => identity(l, r),
+        (Value::Function(l), Value::Function(r)) => identity(l, r),
+        (Value::Partial(l), Value::Partial(r)) => identity(l, r),
+        (Value::NativeFunction(l), Value::NativeFunction(r)) => identity(l, r),
+        (Value::Upvalue(l), Value::Upvalue(r)) => identity(l, r),
+        (Value::SortedMap(l), Value::SortedMap(r)) => identity(l, r),
+        (Value::PriorityQueue(l), Value::PriorityQueue(r)) => identity(l, r),
+        (Value::Deque(l), Value::Deque(r)) => identity(l, r),
+        (Value::Parameter(l), Value::Parameter(r)) => identity(l, r),
+        (Value::Port(l), Value::Port(r)) => identity(l, r),
+        (Value::Continuation(l), Value::Continuation(r)) => identity(l, r),
+        (Value::Generator(l), Value::Generator(r)) => identity(l, r),
+
+        (Value::NumberObject(l), Value::NumberObject(r)) => {
+            l.value(guard).total_cmp(&r.value(guard))
+        }
+        (Value::Number(l), Value::NumberObject(r)) => {
+            Numeric::Integer(BigInt::from(l)).total_cmp(&r.value(guard))
+        }
+        (Value::NumberObject(l), Value::Number(r)) => {
+            l.value(guard).total_cmp(&Numeric::Integer(BigInt::from(r)))
+        }
+
+        (l, r) => type_rank(&l).cmp(&type_rank(&r)),
+    }
+}
+
+/// Return true if two values have the same content, that is if `compare()` would order them
+/// equal, without recursing on the host stack and without looping forever on cyclic lists. Two
+/// lists that are already being compared further up are taken to be equal, so two cycles are
+/// equal if they cannot be told apart by walking them. Lists nested more than `depth_limit` deep
+/// are an error.
+pub fn equal<'guard>(
+    guard: &'guard dyn MutatorScope,
+    left: Value<'guard>,
+    right: Value<'guard>,
+    depth_limit: usize,
+) -> Result<bool, RuntimeError> {
+    // addresses of the pairs of containers compared so far
+    let mut seen: HashSet<(usize, usize)> = HashSet::new();
+    // values still to compare, with their depth of nesting
+    let mut pending = vec![(left, right, 0)];
+
+    while let Some((left, right, depth)) = pending.pop() {
+        if depth > depth_limit {
+            return Err(err_eval(&format!(
+                "equal? compared lists nested more than {} deep",
+                depth_limit
+            )));
+        }
+
+        match (left, right) {
+            // a list tail is at the same depth as the list, so long lists are not too deep
+            (Value::Pair(l), Value::Pair(r)) => {
+                if seen.insert((address(l), address(r))) {
+                    pending.push((
+                        l.second.get(guard).value(),
+                        r.second.get(guard).value(),
+                        depth,
+                    ));
+                    pending.push((
+                        l.first.get(guard).value(),
+                        r.first.get(guard).value(),
+                        depth + 1,
+                    ));
+                }
+            }
+
+            (Value::List(l), Value::List(r)) => {
+                if seen.insert((address(l), address(r))) {
+                    // Safe because the slices are only read and nothing else runs while they
+                    // are held
+                    let (l, r) = unsafe { (l.as_slice(guard), r.as_slice(guard)) };
+                    if l.len() != r.len() {
+                        return Ok(false);
+                    }
+
+                    for (l, r) in l.iter().zip(r.iter()).rev() {
+                        pending.push((l.get(guard).value(), r.get(guard).value(), depth + 1));
+                    }
+                }
+            }
+
+            (left, right) => {
+                if compare(guard, left, right) != Ordering::Equal {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(all(test, feature = "compiler"))]
+mod test {
+    use super::*;
+    use crate::error::RuntimeError;
+    use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::parser::parse;
+    use crate::safeptr::TaggedScopedPtr;
+    use crate::taggedptr::TaggedPtr;
+    use crate::text::Text;
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    #[test]
+    fn compare_across_types() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let text = mem.alloc_tagged(Text::new_from_str(mem, "a")?)?;
+
+            // nil < number < symbol < text < pair
+            let ordered = [
+                mem.nil(),
+                TaggedScopedPtr::new(mem, TaggedPtr::number(5)),
+                mem.lookup_sym("a"),
+                text,
+                parse(mem, "'a")?,
+            ];
+
+            for window in ordered.windows(2) {
+                assert!(compare(mem, window[0].value(), window[1].value()) == Ordering::Less);
+                assert!(compare(mem, window[1].value(), window[0].value()) == Ordering::Greater);
+            }
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compare_within_types() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let cmp = |a: &str, b: &str| -> Result<Ordering, RuntimeError> {
+                Ok(compare(mem, parse(mem, a)?.value(), parse(mem, b)?.value()))
+            };
+
+            assert!(cmp("apple", "banana")? == Ordering::Less);
+            assert!(cmp("(a b)", "(a b)")? == Ordering::Equal);
+            assert!(cmp("(a b)", "(a c)")? == Ordering::Less);
+            assert!(cmp("(a)", "(a b)")? == Ordering::Less);
+            assert!(cmp("(a b c)", "(a b)")? == Ordering::Greater);
+            assert!(cmp("(b)", "(a b c)")? == Ordering::Greater);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn equal_cyclic_and_nested() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // make a list loop back from its last pair to its first
+            let cycle = |code: &str| -> Result<TaggedScopedPtr, RuntimeError> {
+                let head = parse(mem, code)?;
+                let mut last = head;
+                while let Value::Pair(pair) = *last {
+                    match *pair.second.get(mem) {
+                        Value::Pair(_) => last = pair.second.get(mem),
+                        _ => {
+                            pair.second.set(head);
+                            break;
+                        }
+                    }
+                }
+                Ok(head)
+            };
+            let eq = |a: TaggedScopedPtr, b: TaggedScopedPtr| {
+                equal(mem, a.value(), b.value(), DEFAULT_EQUAL_DEPTH_LIMIT)
+            };
+
+            let ab = cycle("(a b)")?;
+            assert!(eq(ab, ab)?);
+            assert!(eq(ab, cycle("(a b)")?)?);
+            assert!(eq(ab, cycle("(a b a b)")?)?);
+            assert!(!eq(ab, cycle("(a c)")?)?);
+            assert!(!eq(ab, parse(mem, "(a b a b)")?)?);
+
+            // without cycles equal agrees with compare
+            for (a, b) in &[("(a (b c))", "(a (b c))"), ("(a (b c))", "(a (b d))")] {
+                let (a, b) = (parse(mem, a)?, parse(mem, b)?);
+                assert!(eq(a, b)? == (compare(mem, a.value(), b.value()) == Ordering::Equal));
+            }
+
+            // nesting, but not length, counts against the limit
+            let (a, b) = (parse(mem, "((((x))))")?, parse(mem, "((((x))))")?);
+            assert!(equal(mem, a.value(), b.value(), 4)?);
+            assert!(equal(mem, a.value(), b.value(), 3).is_err());
+            let (a, b) = (parse(mem, "(1 2 3 4 5 6)")?, parse(mem, "(1 2 3 4 5 6)")?);
+            assert!(equal(mem, a.value(), b.value(), 1)?);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+}
@@ -1,17 +1,29 @@
 use std::cell::{Cell, RefCell};
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
 
 use crate::array::{Array, ArraySize, ArrayU16};
-use crate::bytecode::{ByteCode, JumpOffset, Opcode, Register, UpvalueId, JUMP_UNKNOWN};
-use crate::containers::{AnyContainerFromSlice, StackContainer};
-use crate::error::{err_eval, RuntimeError};
+use crate::builtins::pure_builtin;
+use crate::bytecode::{
+    ByteCode, JumpOffset, LiteralInteger, Opcode, Register, UpvalueId, JUMP_UNKNOWN,
+};
+use crate::containers::{
+    AnyContainerFromSlice, HashIndexedAnyContainer, SliceableContainer, StackAnyContainer,
+    StackContainer,
+};
+use crate::diagnostic::{Diagnostic, Span};
+use crate::dict::Dict;
+use crate::error::{err_eval, spos, RuntimeError, SourcePos};
 use crate::function::Function;
+#[cfg(feature = "gc-stress")]
+use crate::heapcheck::HeapChecker;
 use crate::list::List;
 use crate::memory::MutatorView;
-use crate::pair::{value_from_1_pair, values_from_2_pairs, vec_from_pairs};
-use crate::safeptr::{CellPtr, ScopedPtr, TaggedScopedPtr};
-use crate::taggedptr::Value;
-use crate::vm::FIRST_ARG_REG;
+use crate::number::{self, numeric_comparison, ArithmeticOp, OverflowMode};
+use crate::pair::{cons, list_from_slice, value_from_1_pair, values_from_2_pairs, vec_from_pairs};
+use crate::safeptr::{CellPtr, MutatorScope, ScopedPtr, TaggedScopedPtr};
+use crate::taggedptr::{TaggedPtr, Value};
+use crate::vm::{Thread, FIRST_ARG_REG};
 
 /// A binding can be either local or via an upvalue depending on how a closure refers to it.
 #[derive(Copy, Clone, PartialEq)]
@@ -22,6 +34,17 @@ enum Binding {
     Upvalue(UpvalueId),
 }
 
+/// Which names a binding expression of a let form can refer to
+#[derive(Copy, Clone, PartialEq)]
+enum LetScope {
+    /// `let`: only the names of the enclosing scope
+    Parallel,
+    /// `let*`: also the names bound before it
+    Sequential,
+    /// `letrec`: also all the names being bound, which are nil until they are bound
+    Recursive,
+}
+
 /// A variable is a named register. It has compile time metadata about how it is used by closures.
 struct Variable {
     register: Register,
@@ -78,19 +101,17 @@ impl Scope {
         Ok(())
     }
 
-    /// Push a block of bindings into this scope, returning the next register available
-    /// after these bound registers. All these variables will be Unclosed by default.
+    /// Push a block of bindings into this scope, bound to consecutive registers from the given
+    /// one. All these variables will be Unclosed by default.
     fn push_bindings<'guard>(
         &mut self,
         names: &[TaggedScopedPtr<'guard>],
         start_reg: Register,
-    ) -> Result<Register, RuntimeError> {
-        let mut reg = start_reg;
-        for name in names {
-            self.push_binding(*name, reg)?;
-            reg += 1;
+    ) -> Result<(), RuntimeError> {
+        for (index, name) in names.iter().enumerate() {
+            self.push_binding(*name, start_reg + index as Register)?;
         }
-        Ok(reg)
+        Ok(())
     }
 
     /// Find a Symbol->Register binding in this scope
@@ -235,11 +256,26 @@ impl<'parent> Variables<'parent> {
         }
     }
 
+    /// Return true if any variable in this function's scopes is closed over
+    fn any_closed_over(&self) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| scope.bindings.values().any(|var| var.is_closed_over()))
+    }
+
     /// Pop the last scoped variables and create close-upvalue instructions for any closed over
     fn pop_scope<'guard>(&mut self) -> Vec<Opcode> {
+        let closings = self.closings_from(self.scopes.len().saturating_sub(1));
+        self.scopes.pop();
+        closings
+    }
+
+    /// Create close-upvalue instructions for any closed over variables in the scopes from the
+    /// given depth inwards, without popping them
+    fn closings_from(&self, depth: usize) -> Vec<Opcode> {
         let mut closings = Vec::new();
 
-        if let Some(scope) = self.scopes.pop() {
+        for scope in self.scopes.iter().skip(depth) {
             for var in scope.bindings.values() {
                 if var.is_closed_over() {
                     closings.push(Opcode::CloseUpvalues {
@@ -255,19 +291,521 @@ impl<'parent> Variables<'parent> {
     }
 }
 
+/// A built-in special form, compiling the arguments of an application of its name, which may be
+/// in tail position
+type CompileForm = for<'guard, 'parent> fn(
+    &mut Compiler<'parent>,
+    &'guard MutatorView,
+    TaggedScopedPtr<'guard>,
+    bool,
+) -> Result<Register, RuntimeError>;
+
+/// A special form defined by an embedder, rewriting the arguments of an application of its name
+/// into an expression that is compiled in place of the application
+pub type RewriteForm = for<'guard> fn(
+    &'guard MutatorView,
+    TaggedScopedPtr<'guard>,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError>;
+
+/// A built-in special form that can be evaluated while compiling, returning the value of an
+/// application of its name if its arguments are constant, see `Compiler::constant_value()`
+type FoldForm = for<'guard, 'parent> fn(
+    &Compiler<'parent>,
+    &'guard MutatorView,
+    TaggedScopedPtr<'guard>,
+) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError>;
+
+/// How an application of a special form is compiled
+#[derive(Copy, Clone)]
+enum FormHandler {
+    Compile(CompileForm, Option<FoldForm>),
+    Rewrite(RewriteForm),
+}
+
+/// The special forms a dialect is compiled with, by name. An application of any other name is
+/// compiled as a function call, so removing a form leaves its name free to be bound to a
+/// function.
+///
+/// `standard()` is the full language. An embedder hosting a restricted dialect, such as a
+/// configuration language, can start from it and remove or `restrict()` the forms, rename forms
+/// with `alias()`, and add forms of its own with `define_rewrite()`. Macros take precedence over
+/// special forms.
+#[derive(Clone)]
+pub struct SpecialFormTable {
+    forms: HashMap<String, FormHandler>,
+}
+
+impl SpecialFormTable {
+    /// A table with no special forms, in which every application is a function call
+    pub fn empty() -> SpecialFormTable {
+        SpecialFormTable {
+            forms: HashMap::new(),
+        }
+    }
+
+    /// A table of every special form of the standard language
+    pub fn standard() -> SpecialFormTable {
+        let mut table = SpecialFormTable::empty();
+
+        table.compiled("defmacro", |c, mem, args, _| {
+            c.compile_apply_defmacro(mem, args)
+        });
+        table.folded(
+            "quote",
+            |c, mem, args, _| c.push_load_literal(mem, value_from_1_pair(mem, args)?),
+            |_, mem, args| Ok(Some(value_from_1_pair(mem, args)?)),
+        );
+        table.folded(
+            "atom?",
+            |c, mem, args, _| c.push_op2(mem, args, |dest, test| Opcode::IsAtom { dest, test }),
+            |c, mem, args| {
+                c.fold_op(mem, args, 1, |mem, values| match *values[0] {
+                    Value::Pair(_) | Value::Nil => Some(mem.nil()),
+                    _ => Some(mem.boolean(true)),
+                })
+            },
+        );
+        table.folded(
+            "nil?",
+            |c, mem, args, _| c.push_op2(mem, args, |dest, test| Opcode::IsNil { dest, test }),
+            |c, mem, args| {
+                c.fold_op(mem, args, 1, |mem, values| {
+                    Some(mem.boolean(values[0].is_nil()))
+                })
+            },
+        );
+        table.folded(
+            "not",
+            |c, mem, args, _| c.push_op2(mem, args, |dest, test| Opcode::IsNil { dest, test }),
+            |c, mem, args| {
+                c.fold_op(mem, args, 1, |mem, values| {
+                    Some(mem.boolean(values[0].is_nil()))
+                })
+            },
+        );
+        table.folded(
+            "car",
+            |c, mem, args, _| c.push_op2(mem, args, |dest, reg| Opcode::FirstOfPair { dest, reg }),
+            |c, mem, args| {
+                c.fold_op(mem, args, 1, |mem, values| match *values[0] {
+                    Value::Pair(p) => Some(p.first.get(mem)),
+                    Value::Nil => Some(mem.nil()),
+                    _ => None,
+                })
+            },
+        );
+        table.folded(
+            "cdr",
+            |c, mem, args, _| c.push_op2(mem, args, |dest, reg| Opcode::SecondOfPair { dest, reg }),
+            |c, mem, args| {
+                c.fold_op(mem, args, 1, |mem, values| match *values[0] {
+                    Value::Pair(p) => Some(p.second.get(mem)),
+                    Value::Nil => Some(mem.nil()),
+                    _ => None,
+                })
+            },
+        );
+        table.compiled("cons", |c, mem, args, _| {
+            c.push_op3(mem, args, |dest, reg1, reg2| Opcode::MakePair {
+                dest,
+                reg1,
+                reg2,
+            })
+        });
+        table.compiled("list", |c, mem, args, _| c.compile_apply_list(mem, args));
+        table.compiled("assert", |c, mem, args, _| {
+            c.compile_apply_assert(mem, args)
+        });
+        table.compiled("cond", |c, mem, args, tail| {
+            c.compile_apply_cond(mem, args, tail)
+        });
+        table.compiled("if", |c, mem, args, tail| {
+            c.compile_apply_if(mem, args, tail)
+        });
+        table.compiled("and", |c, mem, args, tail| {
+            c.compile_apply_and_or(mem, args, true, tail)
+        });
+        table.compiled("or", |c, mem, args, tail| {
+            c.compile_apply_and_or(mem, args, false, tail)
+        });
+        table.compiled("begin", |c, mem, args, tail| {
+            c.compile_apply_begin(mem, args, tail)
+        });
+        table.compiled("apply", |c, mem, args, _| c.compile_apply_apply(mem, args));
+        table.compiled("call/cc", |c, mem, args, _| {
+            c.compile_apply_call_cc(mem, args)
+        });
+        table.compiled("call-with-current-continuation", |c, mem, args, _| {
+            c.compile_apply_call_cc(mem, args)
+        });
+        table.folded(
+            "is?",
+            |c, mem, args, _| {
+                c.push_op3(mem, args, |dest, test1, test2| Opcode::IsIdentical {
+                    dest,
+                    test1,
+                    test2,
+                })
+            },
+            |c, mem, args| {
+                c.fold_op(mem, args, 2, |mem, values| {
+                    let identical = values[0] == values[1]
+                        || number::is_same_big_integer(mem, values[0], values[1]);
+                    Some(mem.boolean(identical))
+                })
+            },
+        );
+        table.folded(
+            "+",
+            |c, mem, args, _| {
+                c.compile_apply_arithmetic(mem, args, 0, |dest, reg1, reg2| Opcode::Add {
+                    dest,
+                    reg1,
+                    reg2,
+                })
+            },
+            |c, mem, args| c.fold_arithmetic(mem, args, 0, ArithmeticOp::Add),
+        );
+        table.folded(
+            "-",
+            |c, mem, args, _| {
+                c.compile_apply_arithmetic(mem, args, 0, |dest, left, right| Opcode::Subtract {
+                    dest,
+                    left,
+                    right,
+                })
+            },
+            |c, mem, args| c.fold_arithmetic(mem, args, 0, ArithmeticOp::Subtract),
+        );
+        table.folded(
+            "*",
+            |c, mem, args, _| {
+                c.compile_apply_arithmetic(mem, args, 1, |dest, reg1, reg2| Opcode::Multiply {
+                    dest,
+                    reg1,
+                    reg2,
+                })
+            },
+            |c, mem, args| c.fold_arithmetic(mem, args, 1, ArithmeticOp::Multiply),
+        );
+        table.folded(
+            "/",
+            |c, mem, args, _| {
+                c.compile_apply_arithmetic(mem, args, 1, |dest, num, denom| Opcode::DivideInteger {
+                    dest,
+                    num,
+                    denom,
+                })
+            },
+            |c, mem, args| c.fold_arithmetic(mem, args, 1, ArithmeticOp::Divide),
+        );
+        table.folded(
+            "mod",
+            |c, mem, args, _| {
+                c.push_op3(mem, args, |dest, num, denom| Opcode::Modulo {
+                    dest,
+                    num,
+                    denom,
+                })
+            },
+            |c, mem, args| {
+                c.fold_op(mem, args, 2, |mem, values| {
+                    fold_integer(mem, ArithmeticOp::Modulo, values[0], values[1])
+                })
+            },
+        );
+        table.folded(
+            "<",
+            |c, mem, args, _| {
+                c.push_op3(mem, args, |dest, left, right| Opcode::IsLessThan {
+                    dest,
+                    left,
+                    right,
+                })
+            },
+            |c, mem, args| c.fold_comparison(mem, args, "<"),
+        );
+        table.folded(
+            ">",
+            |c, mem, args, _| {
+                c.push_op3(mem, args, |dest, left, right| Opcode::IsGreaterThan {
+                    dest,
+                    left,
+                    right,
+                })
+            },
+            |c, mem, args| c.fold_comparison(mem, args, ">"),
+        );
+        table.folded(
+            "<=",
+            |c, mem, args, _| {
+                c.push_op3(mem, args, |dest, left, right| Opcode::IsLessOrEqual {
+                    dest,
+                    left,
+                    right,
+                })
+            },
+            |c, mem, args| c.fold_comparison(mem, args, "<="),
+        );
+        table.folded(
+            ">=",
+            |c, mem, args, _| {
+                c.push_op3(mem, args, |dest, left, right| Opcode::IsGreaterOrEqual {
+                    dest,
+                    left,
+                    right,
+                })
+            },
+            |c, mem, args| c.fold_comparison(mem, args, ">="),
+        );
+        table.folded(
+            "=",
+            |c, mem, args, _| {
+                c.push_op3(mem, args, |dest, left, right| Opcode::IsNumericEqual {
+                    dest,
+                    left,
+                    right,
+                })
+            },
+            |c, mem, args| c.fold_comparison(mem, args, "="),
+        );
+        table.compiled("set", |c, mem, args, _| c.compile_apply_assign(mem, args));
+        table.compiled("define", |c, mem, args, _| {
+            c.compile_apply_define(mem, args)
+        });
+        table.compiled("set!", |c, mem, args, _| c.compile_apply_set(mem, args));
+        table.compiled("def", |c, mem, args, _| c.compile_named_function(mem, args));
+        table.compiled("defun", |c, mem, args, _| {
+            c.compile_named_function(mem, args)
+        });
+        table.compiled("lambda", |c, mem, args, _| {
+            c.compile_anonymous_function(mem, args)
+        });
+        table.compiled("\\", |c, mem, args, _| {
+            c.compile_anonymous_function(mem, args)
+        });
+        table.compiled("let", |c, mem, args, tail| {
+            c.compile_apply_let(mem, args, LetScope::Parallel, tail)
+        });
+        table.compiled("let*", |c, mem, args, tail| {
+            c.compile_apply_let(mem, args, LetScope::Sequential, tail)
+        });
+        table.compiled("letrec", |c, mem, args, tail| {
+            c.compile_apply_let(mem, args, LetScope::Recursive, tail)
+        });
+        table.compiled("parameterize", |c, mem, args, _| {
+            c.compile_apply_parameterize(mem, args)
+        });
+        table.compiled("with-limit", |c, mem, args, _| {
+            c.compile_apply_with_limit(mem, args)
+        });
+        table.compiled("yield", |c, mem, args, _| c.compile_apply_yield(mem, args));
+        table.compiled("try", |c, mem, args, _| c.compile_apply_try(mem, args));
+        table.compiled("unwind-protect", |c, mem, args, _| {
+            c.compile_apply_unwind_protect(mem, args)
+        });
+        table.compiled("freeze", |c, mem, args, _| {
+            c.compile_apply_freeze(mem, args)
+        });
+        table.compiled("while", |c, mem, args, _| c.compile_apply_while(mem, args));
+        table.compiled("break", |c, mem, args, _| c.compile_apply_break(mem, args));
+
+        table
+    }
+
+    /// Add a built-in special form
+    fn compiled(&mut self, name: &str, form: CompileForm) {
+        self.forms
+            .insert(String::from(name), FormHandler::Compile(form, None));
+    }
+
+    /// Add a built-in special form that can be evaluated while compiling if its arguments are
+    /// constant
+    fn folded(&mut self, name: &str, form: CompileForm, fold: FoldForm) {
+        self.forms
+            .insert(String::from(name), FormHandler::Compile(form, Some(fold)));
+    }
+
+    /// Return how the named special form is compiled, if there is one of that name
+    fn lookup(&self, name: &str) -> Option<FormHandler> {
+        self.forms.get(name).copied()
+    }
+
+    /// Return true if there is a special form of the given name
+    pub fn contains(&self, name: &str) -> bool {
+        self.forms.contains_key(name)
+    }
+
+    /// Return the names of the special forms, in no particular order
+    pub fn names(&self) -> Vec<&str> {
+        self.forms.keys().map(|name| name.as_str()).collect()
+    }
+
+    /// Define a special form that rewrites its arguments into another expression, replacing any
+    /// existing form of the same name
+    pub fn define_rewrite(&mut self, name: &str, rewrite: RewriteForm) {
+        self.forms
+            .insert(String::from(name), FormHandler::Rewrite(rewrite));
+    }
+
+    /// Make `name` another name for the existing special form `existing`
+    pub fn alias(&mut self, name: &str, existing: &str) -> Result<(), RuntimeError> {
+        match self.lookup(existing) {
+            Some(handler) => {
+                self.forms.insert(String::from(name), handler);
+                Ok(())
+            }
+            None => Err(err_eval(&format!("There is no special form {}", existing))),
+        }
+    }
+
+    /// Remove the named special form, returning true if there was one
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.forms.remove(name).is_some()
+    }
+
+    /// Remove every special form except those named
+    pub fn restrict(&mut self, names: &[&str]) {
+        self.forms.retain(|name, _| names.contains(&name.as_str()));
+    }
+}
+
+/// The global variables an expression refers to by name, found while compiling it. A global
+/// whose name is only known at runtime, as in `(set <expr> <expr>)` with a name that is not
+/// quoted, or that is referred to by code evaluated with `eval`, cannot be found.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GlobalAccess {
+    /// Globals whose values are read, including functions called by name
+    pub reads: BTreeSet<String>,
+    /// Globals that are defined or assigned
+    pub writes: BTreeSet<String>,
+    /// True if a global whose name is computed at runtime is assigned
+    pub dynamic_writes: bool,
+}
+
+/// What the compilers of a top level expression and of every function nested in it share
+struct CompileContext<'a> {
+    /// The Thread whose macros are expanded, if any
+    thread: Option<&'a Thread>,
+    /// The special forms of the dialect being compiled
+    forms: &'a SpecialFormTable,
+    /// The globals referred to so far
+    access: RefCell<GlobalAccess>,
+    /// Number of `freeze` expressions enclosing the expression being compiled, in this function
+    /// or any it is nested in
+    freeze_depth: Cell<usize>,
+}
+
+impl<'a> CompileContext<'a> {
+    fn new(thread: Option<&'a Thread>, forms: &'a SpecialFormTable) -> CompileContext<'a> {
+        CompileContext {
+            thread,
+            forms,
+            access: RefCell::new(GlobalAccess::default()),
+            freeze_depth: Cell::new(0),
+        }
+    }
+}
+
+/// An enclosing while loop that a break expression may leave
+struct Loop {
+    /// The register the loop result is left in
+    dest: Register,
+    /// Number of variable scopes open where the loop begins
+    scope_depth: usize,
+    /// Number of dynamic extents (parameterize, with-limit, try, unwind-protect) open where the
+    /// loop begins
+    extent_depth: usize,
+    /// Addresses of the break jumps to point at the end of the loop
+    break_jumps: Vec<ArraySize>,
+}
+
+/// Number of registers a function can use at once, as many as a Register can index
+const REGISTER_COUNT: usize = Register::MAX as usize + 1;
+
+/// The registers of a function's call frame, allocated as a stack that follows the nesting of
+/// expressions. A register holding a temporary value is live from when it is acquired until the
+/// enclosing expression has used the value, after which it is released and reused. Registers
+/// bound to variables stay live until their scope is closed.
+struct Registers {
+    /// The register that will be acquired next, all those before it being live
+    next: usize,
+}
+
+impl Registers {
+    fn new(first: Register) -> Registers {
+        Registers {
+            next: first as usize,
+        }
+    }
+
+    /// Return the register that will be acquired next, or an error if every register is live
+    fn next(&self) -> Result<Register, RuntimeError> {
+        self.check(1)?;
+        Ok(self.next as Register)
+    }
+
+    /// Acquire a block of consecutive registers, returning the first
+    fn acquire(&mut self, count: usize) -> Result<Register, RuntimeError> {
+        self.check(count)?;
+        let first = self.next as Register;
+        self.next += count;
+        Ok(first)
+    }
+
+    /// Release the given register and every one after it
+    fn release_from(&mut self, reg: Register) {
+        self.next = reg as usize;
+    }
+
+    /// Release every register acquired since `mark` was next, other than the one holding
+    /// `result` and any before it
+    fn release_since(&mut self, mark: usize, result: Register) {
+        self.next = mark.max(result as usize + 1).min(self.next);
+    }
+
+    fn check(&self, count: usize) -> Result<(), RuntimeError> {
+        if self.next + count > REGISTER_COUNT {
+            return Err(err_eval(&format!(
+                "Compiler ran out of registers for this function, which cannot use more than {} \
+                 at once, consider reducing complexity",
+                REGISTER_COUNT
+            )));
+        }
+        Ok(())
+    }
+}
+
 /// This is a simple, naive compiler of a nested s-expression Pair (Cons cell) data structure.
 /// It compiles for the VM in vm.rs, a sliding-window register machine.  Register allocation
 /// follows the expression nesting structure, essentially pushing and popping register locations
-/// from the evaluation tree as expressions are entered and exited. This is super simple but not
-/// the most efficient scheme possible.
+/// from the evaluation tree as expressions are entered and exited, see `Registers`. This is super
+/// simple but not the most efficient scheme possible.
+///
+/// Every heap object the compiler creates or holds on to while compiling is kept reachable, either
+/// from the ByteCode being built or from the `roots` list, so that no temporary value is left
+/// unrooted across an allocation.
 struct Compiler<'parent> {
+    /// The function bytecode being built, allocated up front
     bytecode: CellPtr<ByteCode>,
-    /// Next available register slot.
-    next_reg: Register,
+    /// Temporary values that must stay reachable until the Function is complete
+    roots: CellPtr<List>,
+    /// The registers of the function's call frame
+    registers: Registers,
     /// Optional function name
     name: Option<String>,
     /// Function-local nested scopes bindings list (including parameters at outer level)
     vars: Variables<'parent>,
+    /// The Thread, special forms and global access record shared with nested function compilers
+    context: &'parent CompileContext<'parent>,
+    /// The while loops enclosing the expression being compiled, innermost last
+    loops: Vec<Loop>,
+    /// Number of dynamic extents (parameterize, with-limit, try, unwind-protect) enclosing the
+    /// expression being compiled
+    extent_depth: usize,
+    /// The source position of the innermost application being compiled, which the instructions
+    /// it compiles to are given so that the VM can report where an instruction failed
+    form_pos: Option<SourcePos>,
 }
 
 impl<'parent> Compiler<'parent> {
@@ -275,22 +813,30 @@ impl<'parent> Compiler<'parent> {
     fn new<'guard>(
         mem: &'guard MutatorView,
         parent: Option<&'parent Variables<'parent>>,
+        context: &'parent CompileContext<'parent>,
     ) -> Result<Compiler<'parent>, RuntimeError> {
         Ok(Compiler {
             bytecode: CellPtr::new_with(ByteCode::alloc(mem)?),
+            roots: CellPtr::new_with(List::alloc(mem)?),
             // register 0 is reserved for the return value, 1 is reserved for a closure environment
-            next_reg: FIRST_ARG_REG as u8,
+            registers: Registers::new(FIRST_ARG_REG as Register),
             name: None,
             vars: Variables::new(parent),
+            context,
+            loops: Vec::new(),
+            extent_depth: 0,
+            form_pos: None,
         })
     }
 
-    /// Compile an expression that has parameters and possibly a name
+    /// Compile an expression that has parameters and possibly a name. If there is a rest
+    /// parameter, it follows the others and is bound to a list of any further arguments.
     fn compile_function<'guard>(
         mut self,
         mem: &'guard MutatorView,
         name: TaggedScopedPtr<'guard>,
         params: &[TaggedScopedPtr<'guard>],
+        rest: Option<TaggedScopedPtr<'guard>>,
         exprs: &[TaggedScopedPtr<'guard>],
     ) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
         // validate function name
@@ -305,16 +851,28 @@ impl<'parent> Compiler<'parent> {
         };
         let fn_name = name;
 
+        // the rest parameter is bound like any other, to the register after the others
+        let mut params = params.to_vec();
+        params.extend(rest);
+
         // validate arity
         if params.len() > 254 {
             return Err(err_eval("A function cannot have more than 254 parameters"));
         }
+
+        // keep the source expressions reachable for the duration of compilation
+        for expr in exprs.iter() {
+            self.root(mem, *expr)?;
+        }
+
         // put params into a list for the Function object
-        let fn_params = List::from_slice(mem, params)?;
+        let fn_params = List::from_slice(mem, &params)?;
+        self.root(mem, fn_params.as_tagged(mem))?;
 
         // also assign params to the first level function scope and give each one a register
         let mut param_scope = Scope::new();
-        self.next_reg = param_scope.push_bindings(params, self.next_reg)?;
+        let first_param = self.acquire_window(params.len())?;
+        param_scope.push_bindings(&params, first_param)?;
         self.vars.scopes.push(param_scope);
 
         // validate expression list
@@ -322,11 +880,11 @@ impl<'parent> Compiler<'parent> {
             return Err(err_eval("A function must have at least one expression"));
         }
 
-        // compile expressions
-        let mut result_reg = 0;
-        for expr in exprs.iter() {
-            result_reg = self.compile_eval(mem, *expr)?;
+        // compile expressions, the last of which is in tail position
+        for expr in &exprs[..exprs.len() - 1] {
+            self.compile_eval(mem, *expr)?;
         }
+        let result_reg = self.compile_tail(mem, exprs[exprs.len() - 1])?;
 
         // pop parameter scope
         let closing_instructions = self.vars.pop_scope();
@@ -339,11 +897,15 @@ impl<'parent> Compiler<'parent> {
         fn_bytecode.push(mem, Opcode::Return { reg: result_reg })?;
 
         let fn_nonlocals = self.vars.get_nonlocals(mem)?;
+        if let Some(nonlocals) = fn_nonlocals {
+            self.root(mem, nonlocals.as_tagged(mem))?;
+        }
 
         Ok(Function::alloc(
             mem,
             fn_name,
             fn_params,
+            rest.is_some(),
             fn_bytecode,
             fn_nonlocals,
         )?)
@@ -356,12 +918,21 @@ impl<'parent> Compiler<'parent> {
         ast_node: TaggedScopedPtr<'guard>,
     ) -> Result<Register, RuntimeError> {
         match *ast_node {
-            Value::Pair(p) => self.compile_apply(mem, p.first.get(mem), p.second.get(mem)),
+            Value::Pair(p) => self.compile_apply(
+                mem,
+                p.first.get(mem),
+                p.second.get(mem),
+                false,
+                p.first_pos.get(),
+            ),
+
+            // keywords evaluate to themselves
+            Value::Symbol(s) if s.is_keyword() => self.push_load_literal(mem, ast_node),
 
             Value::Symbol(s) => {
                 match s.as_str(mem) {
                     "nil" => {
-                        let dest = self.acquire_reg();
+                        let dest = self.acquire_reg()?;
                         self.push(mem, Opcode::LoadNil { dest })?;
                         Ok(dest)
                     }
@@ -375,7 +946,7 @@ impl<'parent> Compiler<'parent> {
 
                             Some(Binding::Upvalue(upvalue_id)) => {
                                 // Retrieve the value via Upvalue indirection
-                                let dest = self.acquire_reg();
+                                let dest = self.acquire_reg()?;
                                 self.push(
                                     mem,
                                     Opcode::GetUpvalue {
@@ -387,10 +958,23 @@ impl<'parent> Compiler<'parent> {
                             }
 
                             None => {
-                                // Otherwise do a late-binding global lookup
+                                if let Some(dest) =
+                                    self.compile_local_field_path(mem, s.as_str(mem))?
+                                {
+                                    return Ok(dest);
+                                }
+
+                                // Otherwise do a late-binding global lookup, unless the value
+                                // is to be frozen in now
+                                self.check_deprecation(mem, ast_node);
+                                self.record_global(mem, ast_node, false);
+                                if self.context.freeze_depth.get() > 0 {
+                                    return self.compile_frozen_global(mem, ast_node);
+                                }
+
                                 let name = self.push_load_literal(mem, ast_node)?;
                                 let dest = name; // reuse the register
-                                self.push(mem, Opcode::LoadGlobal { dest, name })?;
+                                self.push_load_global(mem, dest, name)?;
                                 Ok(dest)
                             }
                         }
@@ -402,42 +986,333 @@ impl<'parent> Compiler<'parent> {
         }
     }
 
-    /// Compile a function or special-form application
+    /// If the name is a dot-path such as `config.server.port` whose first name is a local variable,
+    /// compile reading each field in turn from the value of the variable. A dot-path beginning
+    /// with a global is resolved when it is read, see `Thread::resolve_global()`.
+    fn compile_local_field_path<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        name: &str,
+    ) -> Result<Option<Register>, RuntimeError> {
+        let mut names = name.split('.');
+        let head = match names.next() {
+            Some(head) if !head.is_empty() => mem.lookup_sym(head),
+            _ => return Ok(None),
+        };
+        let fields: Vec<&str> = names.collect();
+        if fields.is_empty() || self.vars.lookup_binding(head)?.is_none() {
+            return Ok(None);
+        }
+
+        let dest = self.acquire_reg()?;
+        let mut object = self.compile_eval(mem, head)?;
+        for field in fields {
+            let key = self.push_load_literal(mem, mem.lookup_sym(field))?;
+            self.push(mem, Opcode::GetField { dest, object, key })?;
+            object = dest;
+            self.reset_reg(dest + 1);
+        }
+
+        Ok(Some(dest))
+    }
+
+    /// Compile an expression in tail position, where its value will be the return value of the
+    /// function. A function call in tail position replaces the current call frame rather than
+    /// pushing a new one.
+    fn compile_tail<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        ast_node: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        match *ast_node {
+            Value::Pair(p) => self.compile_apply(
+                mem,
+                p.first.get(mem),
+                p.second.get(mem),
+                true,
+                p.first_pos.get(),
+            ),
+            _ => self.compile_eval(mem, ast_node),
+        }
+    }
+
+    /// Compile a function or special-form application, which may be in tail position. The
+    /// position is that of the function expression, if known.
     fn compile_apply<'guard>(
         &mut self,
         mem: &'guard MutatorView,
         function: TaggedScopedPtr<'guard>,
         args: TaggedScopedPtr<'guard>,
+        tail: bool,
+        pos: Option<SourcePos>,
     ) -> Result<Register, RuntimeError> {
-        match *function {
-            Value::Symbol(s) => match s.as_str(mem) {
-                "quote" => self.push_load_literal(mem, value_from_1_pair(mem, args)?),
-                "atom?" => self.push_op2(mem, args, |dest, test| Opcode::IsAtom { dest, test }),
-                "nil?" => self.push_op2(mem, args, |dest, test| Opcode::IsNil { dest, test }),
-                "car" => self.push_op2(mem, args, |dest, reg| Opcode::FirstOfPair { dest, reg }),
-                "cdr" => self.push_op2(mem, args, |dest, reg| Opcode::SecondOfPair { dest, reg }),
-                "cons" => self.push_op3(mem, args, |dest, reg1, reg2| Opcode::MakePair {
-                    dest,
-                    reg1,
-                    reg2,
-                }),
-                "cond" => self.compile_apply_cond(mem, args),
-                "is?" => self.push_op3(mem, args, |dest, test1, test2| Opcode::IsIdentical {
-                    dest,
-                    test1,
-                    test2,
-                }),
-                "set" => self.compile_apply_assign(mem, args),
-                "def" => self.compile_named_function(mem, args),
-                "lambda" => self.compile_anonymous_function(mem, args),
-                "\\" => self.compile_anonymous_function(mem, args),
-                "let" => self.compile_apply_let(mem, args),
-                _ => self.compile_apply_call(mem, function, args),
+        // macros take precedence over special forms
+        if let Some(expansion) = self.expand_macro(mem, function, args)? {
+            return if tail {
+                self.compile_tail(mem, expansion)
+            } else {
+                self.compile_eval(mem, expansion)
+            };
+        }
+
+        // a symbol in the function position may name a special form, otherwise the value in the
+        // function position is evaluated dynamically
+        let form = match *function {
+            Value::Symbol(s) => self.context.forms.lookup(s.as_str(mem)),
+            _ => None,
+        };
+
+        let outer_pos = self.form_pos;
+        self.form_pos = pos.or(outer_pos);
+
+        let mark = self.registers.next;
+        let result = match form {
+            Some(FormHandler::Compile(compile_form, fold)) => {
+                // an application over constants is evaluated now and loaded as a literal
+                let value = match fold {
+                    Some(fold) => fold(self, mem, args)?,
+                    None => None,
+                };
+                match value {
+                    Some(value) => self.push_load_literal(mem, value)?,
+                    None => compile_form(self, mem, args, tail)?,
+                }
+            }
+            Some(FormHandler::Rewrite(rewrite)) => {
+                let expansion = rewrite(mem, args)?;
+                self.root(mem, expansion)?;
+                if tail {
+                    self.compile_tail(mem, expansion)?
+                } else {
+                    self.compile_eval(mem, expansion)?
+                }
+            }
+            None => match self.fold_builtin(mem, function, args)? {
+                Some(value) => self.push_load_literal(mem, value)?,
+                None => self.compile_apply_call(mem, function, args, tail, pos)?,
             },
+        };
+        self.form_pos = outer_pos;
+
+        // once the application has a value the temporaries it used to compute it are dead
+        self.registers.release_since(mark, result);
+        Ok(result)
+    }
+
+    /// Compile an integer arithmetic application, folding any number of arguments left to right
+    /// into a chain of binary operations: (- a b c) is ((a - b) - c). With no arguments the result
+    /// is the identity value, 0 or 1. A single argument is applied to the identity value, so that
+    /// (- a) negates a.
+    fn compile_apply_arithmetic<'guard, F>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+        identity: LiteralInteger,
+        f: F,
+    ) -> Result<Register, RuntimeError>
+    where
+        F: Fn(Register, Register, Register) -> Opcode,
+    {
+        let args = vec_from_pairs(mem, args)?;
+        let result = self.acquire_reg()?;
+
+        let (first, rest) = match args.split_first() {
+            Some(split) => split,
+            None => {
+                self.push(
+                    mem,
+                    Opcode::LoadInteger {
+                        dest: result,
+                        integer: identity,
+                    },
+                )?;
+                return Ok(result);
+            }
+        };
+
+        let mut acc = self.compile_eval(mem, *first)?;
+
+        if rest.is_empty() {
+            let identity_reg = self.acquire_reg()?;
+            self.push(
+                mem,
+                Opcode::LoadInteger {
+                    dest: identity_reg,
+                    integer: identity,
+                },
+            )?;
+            self.push(mem, f(result, identity_reg, acc))?;
+        }
+
+        for arg in rest {
+            let reg = self.compile_eval(mem, *arg)?;
+            self.push(mem, f(result, acc, reg))?;
+            acc = result;
+            self.reset_reg(result + 1);
+        }
+
+        self.reset_reg(result + 1);
+        Ok(result)
+    }
+
+    /// Compile an 'if' application
+    /// (if <test-expr> <then-expr> <else-expr>)
+    /// The else-expr is optional, the result being nil if it is omitted and the test is not true.
+    /// If the if is in tail position, so are the then and else exprs.
+    fn compile_apply_if<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+        tail: bool,
+    ) -> Result<Register, RuntimeError> {
+        //
+        //   eval test
+        //   if not true then jmp -> else
+        //   eval then-expr
+        //   jmp -> end
+        //   else: eval else-expr
+        //   end:
+        //
+        let if_expr = vec_from_pairs(mem, args)?;
+        if if_expr.len() < 2 || if_expr.len() > 3 {
+            return Err(err_eval(
+                "An if expression must have a test, a then expression and an optional else expression",
+            ));
+        }
+
+        let bytecode = self.bytecode.get(mem);
+        let dest = self.next_reg()?;
+
+        let test_start = bytecode.next_instruction();
+        let test = self.compile_eval(mem, if_expr[0])?;
+        let else_jump = self.push_jump_if_not_true(mem, test, test_start)?;
+
+        self.reset_reg(dest);
+        self.compile_branch(mem, if_expr[1], dest, tail)?;
+        let offset = JUMP_UNKNOWN;
+        self.push(mem, Opcode::Jump { offset })?;
+        let end_jump = bytecode.last_instruction();
+
+        let offset = bytecode.next_instruction() - else_jump - 1;
+        bytecode.update_jump_offset(mem, else_jump, offset as JumpOffset)?;
+
+        self.reset_reg(dest);
+        match if_expr.get(2) {
+            Some(expr) => self.compile_branch(mem, *expr, dest, tail)?,
+            None => self.push(mem, Opcode::LoadNil { dest })?,
+        }
+
+        let offset = bytecode.next_instruction() - end_jump - 1;
+        bytecode.update_jump_offset(mem, end_jump, offset as JumpOffset)?;
+
+        self.reset_reg(dest + 1);
+        Ok(dest)
+    }
 
-            // Here we allow the value in the function position to be evaluated dynamically
-            _ => self.compile_apply_call(mem, function, args),
+    /// Compile an 'and' or 'or' application
+    /// (and <expr> ...) (or <expr> ...)
+    /// The exprs are evaluated in order until one is nil for 'and', or is not nil for 'or', and
+    /// the result is the value of the last expr evaluated. With no exprs the result is true for
+    /// 'and' and nil for 'or'. The last expr is in tail position if the application is.
+    fn compile_apply_and_or<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+        is_and: bool,
+        tail: bool,
+    ) -> Result<Register, RuntimeError> {
+        let exprs = vec_from_pairs(mem, args)?;
+
+        let bytecode = self.bytecode.get(mem);
+        let dest = self.next_reg()?;
+
+        if exprs.is_empty() {
+            return if is_and {
+                self.push_load_literal(mem, mem.lookup_sym("true"))
+            } else {
+                self.acquire_reg()?;
+                self.push(mem, Opcode::LoadNil { dest })?;
+                Ok(dest)
+            };
+        }
+
+        let mut end_jumps: Vec<ArraySize> = Vec::new();
+
+        for (index, expr) in exprs.iter().enumerate() {
+            let is_last = index == exprs.len() - 1;
+
+            self.reset_reg(dest);
+            self.compile_branch(mem, *expr, dest, tail && is_last)?;
+
+            if !is_last {
+                let (test, offset) = (dest, JUMP_UNKNOWN);
+                if is_and {
+                    self.push(mem, Opcode::JumpIfNotTrue { test, offset })?;
+                } else {
+                    self.push(mem, Opcode::JumpIfTrue { test, offset })?;
+                }
+                end_jumps.push(bytecode.last_instruction());
+            }
+        }
+
+        for address in end_jumps {
+            let offset = bytecode.next_instruction() - address - 1;
+            bytecode.update_jump_offset(mem, address, offset as JumpOffset)?;
+        }
+
+        self.reset_reg(dest + 1);
+        Ok(dest)
+    }
+
+    /// Compile a 'begin' application
+    /// (begin <expr> ...)
+    /// The exprs are evaluated in order, each into the same result register, and the result is the
+    /// value of the last one, or nil if there are none. The last expr is in tail position if the
+    /// application is.
+    fn compile_apply_begin<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+        tail: bool,
+    ) -> Result<Register, RuntimeError> {
+        let exprs = vec_from_pairs(mem, args)?;
+        let dest = self.next_reg()?;
+
+        if exprs.is_empty() {
+            self.acquire_reg()?;
+            self.push(mem, Opcode::LoadNil { dest })?;
+            return Ok(dest);
         }
+
+        for (index, expr) in exprs.iter().enumerate() {
+            self.reset_reg(dest);
+            self.compile_branch(mem, *expr, dest, tail && index == exprs.len() - 1)?;
+        }
+
+        self.reset_reg(dest + 1);
+        Ok(dest)
+    }
+
+    /// Compile one branch of a conditional expression, leaving the result in `dest`
+    fn compile_branch<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        expr: TaggedScopedPtr<'guard>,
+        dest: Register,
+        tail: bool,
+    ) -> Result<(), RuntimeError> {
+        let result = if tail {
+            self.compile_tail(mem, expr)?
+        } else {
+            self.compile_eval(mem, expr)?
+        };
+
+        // a variable evaluates to its own register rather than to dest
+        if result != dest {
+            self.push(mem, Opcode::CopyRegister { dest, src: result })?;
+        }
+
+        Ok(())
     }
 
     /// Compile a 'cond' application
@@ -445,11 +1320,13 @@ impl<'parent> Compiler<'parent> {
     ///   (<if-expr-is-true?>) (<then-expr>)
     ///   (<or-expr-is-true?) (<then-expr>)
     /// )
-    /// result is nil if no expression evaluates to true
+    /// result is nil if no expression evaluates to true. If the cond is in tail position, so are
+    /// the then-exprs.
     fn compile_apply_cond<'guard>(
         &mut self,
         mem: &'guard MutatorView,
         args: TaggedScopedPtr<'guard>,
+        tail: bool,
     ) -> Result<Register, RuntimeError> {
         //
         //   for each arg:
@@ -463,7 +1340,7 @@ impl<'parent> Compiler<'parent> {
         let mut end_jumps: Vec<ArraySize> = Vec::new();
         let mut last_cond_jump: Option<ArraySize> = None;
 
-        let dest = self.next_reg;
+        let dest = self.next_reg()?;
 
         let mut head = args;
         while let Value::Pair(p) = *head {
@@ -484,14 +1361,13 @@ impl<'parent> Compiler<'parent> {
                     // We have a condition to evaluate. If the resut is Not True, jump to the
                     // next condition.
                     self.reset_reg(dest); // reuse this register for condition and dest
+                    let test_start = bytecode.next_instruction();
                     let test = self.compile_eval(mem, cond)?;
-                    let offset = JUMP_UNKNOWN;
-                    self.push(mem, Opcode::JumpIfNotTrue { test, offset })?;
-                    last_cond_jump = Some(bytecode.last_instruction());
+                    last_cond_jump = Some(self.push_jump_if_not_true(mem, test, test_start)?);
 
                     // Compile the expression and jump to the end of the entire cond
                     self.reset_reg(dest); // reuse this register for condition and dest
-                    let _expr_result = self.compile_eval(mem, expr)?;
+                    self.compile_branch(mem, expr, dest, tail)?;
                     let offset = JUMP_UNKNOWN;
                     bytecode.push(mem, Opcode::Jump { offset })?;
                     end_jumps.push(bytecode.last_instruction());
@@ -503,10 +1379,10 @@ impl<'parent> Compiler<'parent> {
 
         // Close out with a default nil result if none of the conditions passed
         if let Some(address) = last_cond_jump {
-            self.reset_reg(dest);
-            self.push(mem, Opcode::LoadNil { dest })?;
             let offset = bytecode.next_instruction() - address - 1;
             bytecode.update_jump_offset(mem, address, offset as JumpOffset)?;
+            self.reset_reg(dest);
+            self.push(mem, Opcode::LoadNil { dest })?;
         }
 
         // Update all the post-expr jumps to point at the next instruction after the entire cond
@@ -527,22 +1403,158 @@ impl<'parent> Compiler<'parent> {
         params: TaggedScopedPtr<'guard>,
     ) -> Result<Register, RuntimeError> {
         let (first, second) = values_from_2_pairs(mem, params)?;
+
+        // the name is known before runtime if it is quoted
+        match quoted(mem, first) {
+            Some(name) => self.record_global(mem, name, true),
+            None => self.context.access.borrow_mut().dynamic_writes = true,
+        }
+
         let src = self.compile_eval(mem, second)?;
         let name = self.compile_eval(mem, first)?;
-        self.push(mem, Opcode::StoreGlobal { src, name })?;
+        self.push_store_global(mem, src, name, first_pos(params))?;
         Ok(src)
     }
 
-    /// (lambda (args) (exprs))
-    /// OR
-    /// (\ (args) (exprs))
-    fn compile_anonymous_function<'guard>(
+    /// Global definition, binding the value of the expression to one or more global names
+    /// (define <pattern> <expr>)
+    ///
+    /// A pattern is a symbol, `_` to discard a value, or a list of patterns with an optional
+    /// dotted rest pattern that is matched against a list value:
+    ///   (define (a (b c) . rest) <expr>)
+    /// (values <pattern> ...) is the same as a list pattern. List items missing from the value
+    /// bind to nil.
+    fn compile_apply_define<'guard>(
         &mut self,
         mem: &'guard MutatorView,
         params: TaggedScopedPtr<'guard>,
     ) -> Result<Register, RuntimeError> {
-        let items = vec_from_pairs(mem, params)?;
-
+        let (pattern, expr) = values_from_2_pairs(mem, params)?;
+        let src = self.compile_eval(mem, expr)?;
+        self.compile_destructure(mem, pattern, first_pos(params), src)?;
+        Ok(src)
+    }
+
+    /// Bind the value in the `src` register to the globals named in the pattern
+    fn compile_destructure<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        pattern: TaggedScopedPtr<'guard>,
+        pos: Option<SourcePos>,
+        src: Register,
+    ) -> Result<(), RuntimeError> {
+        match *pattern {
+            Value::Symbol(s) => {
+                if s.as_str(mem) != "_" {
+                    self.check_redefinition(mem, pattern, pos);
+                    self.record_global(mem, pattern, true);
+                    let name = self.push_load_literal(mem, pattern)?;
+                    self.push_store_global(mem, src, name, pos)?;
+                    self.reset_reg(name);
+                }
+            }
+
+            Value::Pair(p) => {
+                // (values a b) is an alias for (a b)
+                let mut head = pattern;
+                if let Value::Symbol(s) = *p.first.get(mem) {
+                    if s.as_str(mem) == "values" {
+                        head = p.second.get(mem);
+                    }
+                }
+
+                // walk down the value list alongside the pattern list. The `src` register may be
+                // a local variable so it is never overwritten.
+                let item = self.acquire_reg()?;
+                let tail = self.acquire_reg()?;
+                let mut list = src;
+                let mut rest_pos = None;
+
+                while let Value::Pair(p) = *head {
+                    self.push(
+                        mem,
+                        Opcode::FirstOfPair {
+                            dest: item,
+                            reg: list,
+                        },
+                    )?;
+                    self.compile_destructure(mem, p.first.get(mem), p.first_pos.get(), item)?;
+
+                    self.push(
+                        mem,
+                        Opcode::SecondOfPair {
+                            dest: tail,
+                            reg: list,
+                        },
+                    )?;
+                    list = tail;
+
+                    head = p.second.get(mem);
+                    rest_pos = p.second_pos.get();
+                }
+
+                // bind a dotted rest pattern to the remainder of the list
+                if let Value::Symbol(_) = *head {
+                    self.compile_destructure(mem, head, rest_pos, list)?;
+                }
+
+                self.reset_reg(item);
+            }
+
+            _ => {
+                return Err(err_eval(
+                    "A define pattern must be a symbol or a list of patterns",
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Assignment to an existing variable, which may be local, in an enclosing function or global
+    /// (set! <symbol> <expr>)
+    fn compile_apply_set<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        params: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let (name, expr) = values_from_2_pairs(mem, params)?;
+        match *name {
+            Value::Symbol(_) => (),
+            _ => return Err(err_eval("set! can only assign to a symbol")),
+        }
+
+        let src = self.compile_eval(mem, expr)?;
+
+        match self.vars.lookup_binding(name)? {
+            Some(Binding::Local(dest)) => self.push(mem, Opcode::CopyRegister { dest, src })?,
+
+            Some(Binding::Upvalue(dest)) => self.push(mem, Opcode::SetUpvalue { dest, src })?,
+
+            None => {
+                // A global must already be bound, which loading it first will check
+                self.record_global(mem, name, true);
+                let name_reg = self.push_load_literal(mem, name)?;
+                let dest = self.acquire_reg()?;
+                self.push_load_global(mem, dest, name_reg)?;
+                self.push_store_global(mem, src, name_reg, first_pos(params))?;
+                self.reset_reg(name_reg);
+            }
+        }
+
+        Ok(src)
+    }
+
+    /// (lambda (args) (exprs))
+    /// OR
+    /// (\ (args) (exprs))
+    fn compile_anonymous_function<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        params: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let items = vec_from_pairs(mem, params)?;
+
         if items.len() < 2 {
             return Err(err_eval(
                 "An anonymous function definition must have at least (lambda (params) expr)",
@@ -550,11 +1562,22 @@ impl<'parent> Compiler<'parent> {
         }
 
         // a function consists of (name (params) expr1 .. exprn)
-        let fn_params = vec_from_pairs(mem, items[0])?;
+        let (fn_params, fn_rest) = parameters(mem, items[0])?;
         let fn_exprs = &items[1..];
 
+        self.check_parameter_shadowing(mem, items[0]);
+
         // compile the function to a Function object
-        let fn_object = compile_function(mem, Some(&self.vars), mem.nil(), &fn_params, fn_exprs)?;
+        let fn_object = compile_function(
+            mem,
+            Some(&self.vars),
+            self.context,
+            mem.nil(),
+            &fn_params,
+            fn_rest,
+            fn_exprs,
+        )?;
+        self.root(mem, fn_object)?;
 
         // load the function object as a literal
         let dest = self.push_load_literal(mem, fn_object)?;
@@ -580,7 +1603,90 @@ impl<'parent> Compiler<'parent> {
         Ok(dest)
     }
 
-    /// (def name (args) (expr))
+    /// (defmacro name (args) (expr))
+    /// The macro Function is compiled and defined on the Thread immediately, so that it expands
+    /// applications of the name compiled from then on. The macro is called with the unevaluated
+    /// argument expressions and returns the expression to compile in place of the application.
+    /// A macro body is compiled on its own, so cannot refer to the local variables of an enclosing
+    /// function.
+    fn compile_apply_defmacro<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        params: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let thread = match self.context.thread {
+            Some(thread) => thread,
+            None => {
+                return Err(err_eval(
+                    "Macros can only be defined when compiling for a thread",
+                ))
+            }
+        };
+
+        let items = vec_from_pairs(mem, params)?;
+
+        if items.len() < 3 {
+            return Err(err_eval(
+                "A macro definition must have at least (defmacro name (params) expr)",
+            ));
+        }
+
+        let macro_name = items[0];
+        match *macro_name {
+            Value::Symbol(_) => (),
+            _ => return Err(err_eval("A macro name must be a symbol")),
+        }
+        let (macro_params, macro_rest) = parameters(mem, items[1])?;
+        let macro_exprs = &items[2..];
+
+        let macro_object = compile_function(
+            mem,
+            None,
+            self.context,
+            macro_name,
+            &macro_params,
+            macro_rest,
+            macro_exprs,
+        )?;
+        self.root(mem, macro_object)?;
+        thread.define_macro(mem, macro_name, macro_object)?;
+
+        // the value of the definition is the macro name
+        self.push_load_literal(mem, macro_name)
+    }
+
+    /// If `function` names a macro, call it with the unevaluated `args` and return the expansion
+    fn expand_macro<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        function: TaggedScopedPtr<'guard>,
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        let thread = match self.context.thread {
+            Some(thread) => thread,
+            None => return Ok(None),
+        };
+
+        match *function {
+            Value::Symbol(_) => (),
+            _ => return Ok(None),
+        }
+
+        match thread.lookup_macro(mem, function) {
+            Some(macro_object) => {
+                let args = vec_from_pairs(mem, args)?;
+                let expansion = thread.call_function(mem, macro_object, &args)?;
+                self.root(mem, expansion)?;
+                Ok(Some(expansion))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// (def name (args) (expr)), or (defun name (args) (expr))
+    ///
+    /// The function is bound to the global name and records the name, which the printer and
+    /// error messages show.
     fn compile_named_function<'guard>(
         &mut self,
         mem: &'guard MutatorView,
@@ -596,17 +1702,37 @@ impl<'parent> Compiler<'parent> {
 
         // a function consists of (name (params) expr1 .. exprn)
         let fn_name = items[0];
-        let fn_params = vec_from_pairs(mem, items[1])?;
+        let (fn_params, fn_rest) = parameters(mem, items[1])?;
         let fn_exprs = &items[2..];
 
+        self.check_redefinition(mem, fn_name, first_pos(params));
+        self.check_parameter_shadowing(mem, items[1]);
+
+        // text that begins a body of more than one expression documents the function
+        if let (Value::Text(_), Some(thread)) = (*fn_exprs[0], self.context.thread) {
+            if fn_exprs.len() > 1 && matches!(*fn_name, Value::Symbol(_)) {
+                thread.put_property(mem, fn_name, mem.lookup_sym("doc"), fn_exprs[0])?;
+            }
+        }
+
         // compile the function to a Function object
-        let fn_object = compile_function(mem, Some(&self.vars), fn_name, &fn_params, fn_exprs)?;
+        let fn_object = compile_function(
+            mem,
+            Some(&self.vars),
+            self.context,
+            fn_name,
+            &fn_params,
+            fn_rest,
+            fn_exprs,
+        )?;
+        self.root(mem, fn_object)?;
 
         // load the function object as a literal and associate it with a global name
         // TODO store in local scope if we're nested in an expression
+        self.record_global(mem, fn_name, true);
         let name = self.push_load_literal(mem, fn_name)?;
         let src = self.push_load_literal(mem, fn_object)?;
-        self.push(mem, Opcode::StoreGlobal { src, name })?;
+        self.push_store_global(mem, src, name, first_pos(params))?;
 
         Ok(src)
 
@@ -619,54 +1745,238 @@ impl<'parent> Compiler<'parent> {
         mem: &'guard MutatorView,
         function_expr: TaggedScopedPtr<'guard>,
         args: TaggedScopedPtr<'guard>,
+        tail: bool,
+        pos: Option<SourcePos>,
+    ) -> Result<Register, RuntimeError> {
+        let arg_list = vec_from_pairs(mem, args)?;
+        self.compile_call(mem, function_expr, &arg_list, tail, false, pos)
+    }
+
+    /// (apply <function-expr> <arg-expr-1> <arg-expr-n> <list-expr>)
+    /// Call the function with the arguments followed by the items of the list. The call is never a
+    /// tail call.
+    fn compile_apply_apply<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let arg_list = vec_from_pairs(mem, args)?;
+
+        if arg_list.len() < 2 {
+            return Err(err_eval(
+                "apply expects a function and a list of arguments: (apply f args)",
+            ));
+        }
+
+        self.compile_call(
+            mem,
+            arg_list[0],
+            &arg_list[1..],
+            false,
+            true,
+            first_pos(args),
+        )
+    }
+
+    /// Compile an 'assert' application
+    /// (assert <expr>)
+    /// The result is the value of the expr if it is true. If it is not, evaluation stops with an
+    /// AssertionFailed error at the position of the expr in the source code, which is stored with
+    /// the expr in a literal list: (<expr> <line> <column>).
+    fn compile_apply_assert<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let expr = value_from_1_pair(mem, args)?;
+
+        let mut failure = vec![expr];
+        if let Value::Pair(pair) = *args {
+            if let Some(pos) = pair.first_pos.get() {
+                failure.push(TaggedScopedPtr::new(
+                    mem,
+                    TaggedPtr::number(pos.line as isize),
+                ));
+                failure.push(TaggedScopedPtr::new(
+                    mem,
+                    TaggedPtr::number(pos.column as isize),
+                ));
+            }
+        }
+        let failure = list_from_slice(mem, &failure)?;
+
+        let test = self.compile_eval(mem, expr)?;
+        self.push(mem, Opcode::JumpIfTrue { test, offset: 1 })?;
+
+        let literal_id = self.bytecode.get(mem).push_lit(mem, failure)?;
+        self.push(mem, Opcode::AssertFail { literal_id })?;
+
+        Ok(test)
+    }
+
+    /// Compile a 'list' application
+    /// (list <expr> ...)
+    /// The expressions are evaluated in order and the list is built from the back.
+    fn compile_apply_list<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let items = vec_from_pairs(mem, args)?;
+        mem.size_limits().check_vector(items.len())?;
+
+        let dest = self.acquire_reg()?;
+        let mut regs = Vec::with_capacity(items.len());
+        for item in items {
+            regs.push(self.compile_eval(mem, item)?);
+        }
+
+        self.push(mem, Opcode::LoadNil { dest })?;
+        for reg in regs.into_iter().rev() {
+            self.push(
+                mem,
+                Opcode::MakePair {
+                    dest,
+                    reg1: reg,
+                    reg2: dest,
+                },
+            )?;
+        }
+
+        self.reset_reg(dest + 1);
+        Ok(dest)
+    }
+
+    /// Compile a 'call/cc' application
+    /// (call/cc <function-expr>)
+    /// The function is called with the current continuation, see `continuation`. The result is
+    /// that of the function, or the value the continuation is invoked with.
+    fn compile_apply_call_cc<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let function_expr = value_from_1_pair(mem, args)?;
+
+        // allocate registers for the return value, a closure environment pointer and the
+        // continuation argument
+        let dest = self.acquire_reg()?;
+        let _closure_env = self.acquire_reg()?;
+        let _continuation = self.acquire_reg()?;
+
+        let function = self.compile_eval(mem, function_expr)?;
+        self.push(mem, Opcode::CallWithContinuation { function, dest })?;
+        self.push(mem, Opcode::EndContinuation)?;
+
+        self.reset_reg(dest + 1);
+        Ok(dest)
+    }
+
+    /// Compile a 'yield' application
+    /// (yield <expr>)
+    /// Suspends the generator being run, see `generator`, making the value of the expression the
+    /// result of the `resume` that ran it. The result is the value the generator is next resumed
+    /// with.
+    fn compile_apply_yield<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let expr = value_from_1_pair(mem, args)?;
+
+        let dest = self.acquire_reg()?;
+        let value = self.compile_eval(mem, expr)?;
+        self.push(mem, Opcode::Yield { dest, value })?;
+
+        self.reset_reg(dest + 1);
+        Ok(dest)
+    }
+
+    /// Compile a call of the function expr with the given argument exprs. If `spread` is true the
+    /// last argument is a list of further arguments. The position of the call, if known, is
+    /// reported in backtraces.
+    fn compile_call<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        function_expr: TaggedScopedPtr<'guard>,
+        arg_list: &[TaggedScopedPtr<'guard>],
+        tail: bool,
+        spread: bool,
+        pos: Option<SourcePos>,
     ) -> Result<Register, RuntimeError> {
         // allocate a register for the return value
-        let dest = self.acquire_reg();
+        let dest = self.acquire_reg()?;
         // allocate a register for a closure environment pointer
-        let _closure_env = self.acquire_reg();
+        let _closure_env = self.acquire_reg()?;
 
         // evaluate arguments first
-        let arg_list = vec_from_pairs(mem, args)?;
         let arg_count = arg_list.len() as u8;
 
         for arg in arg_list {
-            let src = self.compile_eval(mem, arg)?;
-            // if a local variable register was returned, we need to copy the register to the arg
-            // list. Bound registers are necessarily lower indexes than where the function call is
-            // situated because expression scope and register acquisition progresses the register
-            // index in use.
-            if src <= dest {
-                let dest = self.acquire_reg();
+            let slot = self.next_reg()?;
+            let src = self.compile_eval(mem, *arg)?;
+            // if the value is not in the next register of the arg list, such as when a local
+            // variable register was returned, we need to copy it there
+            if src != slot {
+                self.reset_reg(slot);
+                let dest = self.acquire_reg()?;
                 self.push(mem, Opcode::CopyRegister { dest, src })?;
             }
         }
 
         // put the function pointer in the last register of the call so it'll be discarded
         let function = self.compile_eval(mem, function_expr)?;
-        self.push(
-            mem,
+
+        // A tail call overwrites this function's registers, so cannot be used if a closure may
+        // still refer to them on the stack. Any closure created before this point has already
+        // been compiled, so its variables are known to be closed over.
+        let call = if spread {
+            Opcode::Apply {
+                function,
+                dest,
+                arg_count,
+            }
+        } else if tail && !self.vars.any_closed_over() {
+            Opcode::TailCall {
+                function,
+                dest,
+                arg_count,
+            }
+        } else {
             Opcode::Call {
                 function,
                 dest,
                 arg_count,
-            },
-        )?;
+            }
+        };
+        self.push_with_pos(mem, call, pos)?;
 
         // ignore use of any registers beyond the result once the call is complete
         self.reset_reg(dest + 1);
         Ok(dest)
     }
 
-    /// Basic non-recursive let expressions
+    /// Local bindings
     /// (let
     ///   ((<name> <expr>)
     ///    (<name> <expr>))
     ///   (<expr>)
     /// )
+    ///
+    /// In `let` every binding expression is evaluated in the enclosing scope, so cannot refer to
+    /// the other names being bound. In `let*` each binding expression can refer to the names
+    /// bound before it. In `letrec` every name is in scope, initialized to nil, before any binding
+    /// expression is evaluated, so that local functions can refer to each other.
+    ///
+    /// Each binding has its own register in a window directly after the result register. Binding
+    /// and body expressions use the registers after the window for temporary values, which are
+    /// released after each expression, and the whole window is released at the end.
     fn compile_apply_let<'guard>(
         &mut self,
         mem: &'guard MutatorView,
         args: TaggedScopedPtr<'guard>,
+        let_scope: LetScope,
+        tail: bool,
     ) -> Result<Register, RuntimeError> {
         let let_expr = vec_from_pairs(mem, args)?;
         if let_expr.len() < 2 {
@@ -684,32 +1994,71 @@ impl<'parent> Compiler<'parent> {
             vec_of_tuples
         };
 
-        // acquire a let expression dest reg
-        let dest = self.acquire_reg();
+        let mut bindings = let_expr[0];
+        while let Value::Pair(binding) = *bindings {
+            if let Value::Pair(p) = *binding.first.get(mem) {
+                self.check_shadowing(mem, p.first.get(mem), p.first_pos.get());
+            }
+            bindings = binding.second.get(mem);
+        }
+
+        // acquire a let expression dest reg and a register window for the bindings
+        let dest = self.acquire_reg()?;
+        let first_binding = self.acquire_window(let_exprs.len())?;
+        let temporaries = self.next_reg()?;
+
+        // for let*, the scope is visible to the binding expressions and grows as they are bound,
+        // for letrec it is visible with every binding already in it
+        match let_scope {
+            LetScope::Parallel => (),
+            LetScope::Sequential => self.vars.scopes.push(Scope::new()),
+            LetScope::Recursive => {
+                let mut scope = Scope::new();
+                for (index, (name, _)) in let_exprs.iter().enumerate() {
+                    let binding = first_binding + index as Register;
+                    self.push(mem, Opcode::LoadNil { dest: binding })?;
+                    scope.push_binding(*name, binding)?;
+                }
+                self.vars.scopes.push(scope);
+            }
+        }
 
-        // get the names of each binding to push a scope, assigning registers post-result for
-        // each binding
-        let names: Vec<TaggedScopedPtr<'guard>> = let_exprs.iter().map(|tup| tup.0).collect();
+        // compile each binding expression directly into its binding register
+        let mut parallel_scope = Scope::new();
+        for (index, (name, expr)) in let_exprs.iter().enumerate() {
+            let binding = first_binding + index as Register;
 
-        let mut let_scope = Scope::new();
-        self.next_reg = let_scope.push_bindings(&names, self.next_reg)?;
-        self.vars.scopes.push(let_scope);
+            let src = self.compile_eval(mem, *expr)?;
+            if src != binding {
+                self.push(mem, Opcode::CopyRegister { dest: binding, src })?;
+            }
+            self.reset_reg(temporaries);
 
-        // compile each binding expression
-        for (name, expr) in let_exprs {
-            let src = self.compile_eval(mem, expr)?;
-            let dest = self.compile_eval(mem, name)?;
-            // TODO - more efficient to be able to write the result directly to the let binding reg
-            self.push(mem, Opcode::CopyRegister { dest, src })?;
+            match let_scope {
+                LetScope::Parallel => parallel_scope.push_binding(*name, binding)?,
+                LetScope::Sequential => {
+                    let scope = self.vars.scopes.last_mut().expect("let* scope is missing");
+                    scope.push_binding(*name, binding)?;
+                }
+                LetScope::Recursive => (),
+            }
         }
 
-        // compile the expressions after the bindings
+        if let_scope == LetScope::Parallel {
+            self.vars.scopes.push(parallel_scope);
+        }
+
+        // compile the expressions after the bindings, the last in tail position if the let is
         let result_exprs = &let_expr[1..];
 
-        for expr in result_exprs {
-            let src = self.compile_eval(mem, *expr)?;
-            // TODO - more efficient to be able to write the result directly to the let binding reg
+        for (index, expr) in result_exprs.iter().enumerate() {
+            let src = if tail && index == result_exprs.len() - 1 {
+                self.compile_tail(mem, *expr)?
+            } else {
+                self.compile_eval(mem, *expr)?
+            };
             self.push(mem, Opcode::CopyRegister { dest, src })?;
+            self.reset_reg(temporaries);
         }
 
         // finish up - pop the scope, de-scope all registers except the result, return the result
@@ -722,166 +2071,4021 @@ impl<'parent> Compiler<'parent> {
         Ok(dest)
     }
 
-    /// Push an instruction to the function bytecode list
-    fn push<'guard>(&mut self, mem: &'guard MutatorView, op: Opcode) -> Result<(), RuntimeError> {
-        self.bytecode.get(mem).push(mem, op)
-    }
-
-    /// Push an instruction with a result and a single argument to the function bytecode list
-    fn push_op2<'guard, F>(
+    /// Rebind Parameters for the dynamic extent of the body expressions. All parameter and value
+    /// expressions are evaluated before any Parameter is rebound.
+    /// (parameterize
+    ///   ((<parameter-expr> <expr>)
+    ///    (<parameter-expr> <expr>))
+    ///   (<expr>)
+    /// )
+    fn compile_apply_parameterize<'guard>(
         &mut self,
         mem: &'guard MutatorView,
-        params: TaggedScopedPtr<'guard>,
-        f: F,
-    ) -> Result<Register, RuntimeError>
-    where
-        F: Fn(Register, Register) -> Opcode,
-    {
-        let result = self.acquire_reg();
-        let reg1 = self.compile_eval(mem, value_from_1_pair(mem, params)?)?;
-        self.bytecode.get(mem).push(mem, f(result, reg1))?;
-        Ok(result)
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let param_expr = vec_from_pairs(mem, args)?;
+        if param_expr.len() < 2 {
+            return Err(err_eval(
+                "A parameterize expression must have at least 2 arguments",
+            ));
+        }
+
+        let bindings = vec_from_pairs(mem, param_expr[0])?;
+        if bindings.len() > 255 {
+            return Err(err_eval(
+                "A parameterize expression cannot bind more than 255 parameters",
+            ));
+        }
+
+        // acquire a parameterize expression dest reg
+        let dest = self.acquire_reg()?;
+
+        let mut binding_regs = Vec::new();
+        for binding in &bindings {
+            let (param, value) = values_from_2_pairs(mem, *binding)?;
+            let param = self.compile_eval(mem, param)?;
+            let value = self.compile_eval(mem, value)?;
+            binding_regs.push((param, value));
+        }
+
+        for (param, value) in binding_regs {
+            self.push(mem, Opcode::BindParameter { param, value })?;
+        }
+
+        self.extent_depth += 1;
+        for expr in &param_expr[1..] {
+            let src = self.compile_eval(mem, *expr)?;
+            self.push(mem, Opcode::CopyRegister { dest, src })?;
+        }
+        self.extent_depth -= 1;
+
+        let count = bindings.len() as u8;
+        self.push(mem, Opcode::UnbindParameters { count })?;
+
+        self.reset_reg(dest + 1);
+        Ok(dest)
     }
 
-    /// Push an instruction with a result and two arguments to the function bytecode list
-    fn push_op3<'guard, F>(
+    /// Compile a 'with-limit' application
+    /// (with-limit <instruction-count> <expr> ...)
+    /// The exprs are evaluated within a budget of instruction-count VM instructions, including
+    /// those of any functions called. The result is that of the last expr, or the symbol
+    /// `limit-exceeded` if the budget runs out first.
+    fn compile_apply_with_limit<'guard>(
         &mut self,
         mem: &'guard MutatorView,
-        params: TaggedScopedPtr<'guard>,
-        f: F,
-    ) -> Result<Register, RuntimeError>
-    where
-        F: Fn(Register, Register, Register) -> Opcode,
-    {
-        let result = self.acquire_reg();
-        let (first, second) = values_from_2_pairs(mem, params)?;
-        let reg1 = self.compile_eval(mem, first)?;
-        let reg2 = self.compile_eval(mem, second)?;
-        self.bytecode.get(mem).push(mem, f(result, reg1, reg2))?;
-        Ok(result)
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let limit_expr = vec_from_pairs(mem, args)?;
+        if limit_expr.len() < 2 {
+            return Err(err_eval(
+                "A with-limit expression must have at least 2 arguments",
+            ));
+        }
+
+        let bytecode = self.bytecode.get(mem);
+
+        // the limit is evaluated into the dest reg, which the VM overwrites if the limit is hit
+        let dest = self.acquire_reg()?;
+        let limit = self.compile_eval(mem, limit_expr[0])?;
+        if limit != dest {
+            self.push(mem, Opcode::CopyRegister { dest, src: limit })?;
+        }
+
+        let offset = JUMP_UNKNOWN;
+        self.push(
+            mem,
+            Opcode::BeginLimit {
+                limit: dest,
+                offset,
+            },
+        )?;
+        let begin = bytecode.last_instruction();
+
+        self.extent_depth += 1;
+        for expr in &limit_expr[1..] {
+            let src = self.compile_eval(mem, *expr)?;
+            self.push(mem, Opcode::CopyRegister { dest, src })?;
+        }
+        self.extent_depth -= 1;
+
+        self.push(mem, Opcode::EndLimit)?;
+
+        let offset = bytecode.next_instruction() - begin - 1;
+        bytecode.update_jump_offset(mem, begin, offset as JumpOffset)?;
+
+        self.reset_reg(dest + 1);
+        Ok(dest)
     }
 
-    // Push a literal onto the literals list and a load instruction onto the bytecode list
-    fn push_load_literal<'guard>(
+    /// Compile a 'try' application
+    /// (try <expr> ... (catch <name> <handler-expr> ...))
+    /// The exprs are evaluated in turn and the result is that of the last. If one of them raises
+    /// an error that can be caught, including a value passed to `throw`, evaluation of the exprs
+    /// is abandoned and the handler-exprs are evaluated with name bound to the thrown value, or to
+    /// the text of the error message. The result is then that of the last handler-expr.
+    fn compile_apply_try<'guard>(
         &mut self,
         mem: &'guard MutatorView,
-        literal: TaggedScopedPtr<'guard>,
+        args: TaggedScopedPtr<'guard>,
     ) -> Result<Register, RuntimeError> {
-        let result = self.acquire_reg();
-        let lit_id = self.bytecode.get(mem).push_lit(mem, literal)?;
-        self.bytecode.get(mem).push_loadlit(mem, result, lit_id)?;
-        Ok(result)
+        let try_expr = vec_from_pairs(mem, args)?;
+        let catch_clause = match try_expr.split_last() {
+            Some((last, body)) if !body.is_empty() => match **last {
+                Value::Pair(p) if p.first.get(mem) == mem.lookup_sym("catch") => p,
+                _ => return Err(err_eval("A try expression must end with a catch clause")),
+            },
+            _ => {
+                return Err(err_eval(
+                    "A try expression must have at least one expression and a catch clause",
+                ))
+            }
+        };
+        let body = &try_expr[..try_expr.len() - 1];
+
+        let (name, name_pos, handler) = match *catch_clause.second.get(mem) {
+            Value::Pair(p) => (
+                p.first.get(mem),
+                p.first_pos.get(),
+                vec_from_pairs(mem, p.second.get(mem))?,
+            ),
+            _ => return Err(err_eval("A catch clause must name the variable to bind")),
+        };
+        self.check_shadowing(mem, name, name_pos);
+
+        let bytecode = self.bytecode.get(mem);
+
+        // the VM puts the thrown value in the dest reg if the handler is used
+        let dest = self.acquire_reg()?;
+        self.push(
+            mem,
+            Opcode::PushHandler {
+                dest,
+                offset: JUMP_UNKNOWN,
+            },
+        )?;
+        let begin = bytecode.last_instruction();
+
+        self.extent_depth += 1;
+        for expr in body {
+            let src = self.compile_eval(mem, *expr)?;
+            self.push(mem, Opcode::CopyRegister { dest, src })?;
+        }
+        self.extent_depth -= 1;
+
+        self.push(mem, Opcode::PopHandler)?;
+        self.push(
+            mem,
+            Opcode::Jump {
+                offset: JUMP_UNKNOWN,
+            },
+        )?;
+        let end_jump = bytecode.last_instruction();
+
+        let offset = bytecode.next_instruction() - begin - 1;
+        bytecode.update_jump_offset(mem, begin, offset as JumpOffset)?;
+
+        // bind the thrown value in its own register, as a closure in the handler may capture it
+        self.reset_reg(dest + 1);
+        let binding = self.acquire_reg()?;
+        self.push(
+            mem,
+            Opcode::CopyRegister {
+                dest: binding,
+                src: dest,
+            },
+        )?;
+
+        let mut scope = Scope::new();
+        scope.push_binding(name, binding)?;
+        self.vars.scopes.push(scope);
+
+        if handler.is_empty() {
+            self.push(mem, Opcode::LoadNil { dest })?;
+        }
+        for expr in &handler {
+            let src = self.compile_eval(mem, *expr)?;
+            self.push(mem, Opcode::CopyRegister { dest, src })?;
+        }
+
+        let closing_instructions = self.vars.pop_scope();
+        for opcode in &closing_instructions {
+            self.push(mem, *opcode)?;
+        }
+
+        let offset = bytecode.next_instruction() - end_jump - 1;
+        bytecode.update_jump_offset(mem, end_jump, offset as JumpOffset)?;
+
+        self.reset_reg(dest + 1);
+        Ok(dest)
+    }
+
+    /// Compile an 'unwind-protect' application
+    /// (unwind-protect <expr> <cleanup-expr> ...)
+    /// The result is the value of expr. The cleanup-exprs are evaluated after it, whether it
+    /// completes, raises an error that can be caught or is escaped from by a continuation, in
+    /// which case the error or escape carries on once they are done. When a `with-limit` budget
+    /// runs out, or the VM limits are crossed, the cleanup-exprs are not evaluated.
+    fn compile_apply_unwind_protect<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let (body, cleanup_exprs) = match *args {
+            Value::Pair(p) if !p.second.get(mem).is_nil() => (p.first.get(mem), p.second.get(mem)),
+            _ => {
+                return Err(err_eval(
+                    "An unwind-protect expression must have an expression and at least one \
+                     cleanup expression",
+                ))
+            }
+        };
+
+        let dest = self.acquire_reg()?;
+
+        // the cleanup expressions are compiled as a function of no arguments, which the VM calls
+        // when unwinding through the expression
+        let thunk = self.compile_anonymous_function(mem, cons(mem, mem.nil(), cleanup_exprs)?)?;
+        self.push(mem, Opcode::PushCleanup { thunk })?;
+
+        self.extent_depth += 1;
+        let src = self.compile_eval(mem, body)?;
+        self.push(mem, Opcode::CopyRegister { dest, src })?;
+        self.extent_depth -= 1;
+
+        self.push(mem, Opcode::PopHandler)?;
+
+        // on completion, call the cleanup function directly, discarding its result
+        let result = self.acquire_reg()?;
+        let _closure_env = self.acquire_reg()?;
+        let function = self.acquire_reg()?;
+        self.push(
+            mem,
+            Opcode::CopyRegister {
+                dest: function,
+                src: thunk,
+            },
+        )?;
+        self.push(
+            mem,
+            Opcode::Call {
+                function,
+                dest: result,
+                arg_count: 0,
+            },
+        )?;
+
+        self.reset_reg(dest + 1);
+        Ok(dest)
+    }
+
+    /// Compile a 'freeze' application
+    /// (freeze <expr>)
+    /// The result is that of expr, except that every global variable it reads, including in
+    /// functions defined within it, is read while compiling rather than when evaluating. A copy of
+    /// the value is kept with the compiled code, so that later changes to the global, or by the
+    /// host to the data in it, do not change the result. Each global must be bound, or supplied
+    /// by the unresolved symbol handler, when the expression is compiled.
+    fn compile_apply_freeze<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let expr = value_from_1_pair(mem, args)?;
+
+        let freeze_depth = &self.context.freeze_depth;
+        freeze_depth.set(freeze_depth.get() + 1);
+        let result = self.compile_eval(mem, expr);
+        freeze_depth.set(freeze_depth.get() - 1);
+
+        result
+    }
+
+    /// Compile reading a global variable inside a `freeze` expression, which loads a copy of its
+    /// current value as a literal
+    fn compile_frozen_global<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        name: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let thread = match self.context.thread {
+            Some(thread) => thread,
+            None => {
+                return Err(err_eval(
+                    "A freeze expression can only be compiled for a thread",
+                ))
+            }
+        };
+
+        let value = match thread.resolve_global(mem, name)? {
+            Some(value) => frozen_copy(mem, value, 0)?,
+            None => {
+                return Err(err_eval(&format!(
+                    "Cannot freeze {}, which is not bound",
+                    name
+                )))
+            }
+        };
+
+        self.root(mem, value)?;
+        self.push_load_literal(mem, value)
+    }
+
+    /// Compile a 'while' application
+    /// (while <test-expr> <expr> ...)
+    /// The exprs are evaluated in turn for as long as the test evaluates to true. The result is
+    /// nil, or the value given to a (break <expr>) within the loop.
+    fn compile_apply_while<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        //
+        //   dest = nil
+        //   start:
+        //     eval test
+        //     if not true then jmp -> end
+        //     eval exprs
+        //     jmp -> start
+        //   end:
+        //
+        let loop_expr = vec_from_pairs(mem, args)?;
+        if loop_expr.is_empty() {
+            return Err(err_eval("A while expression must have a test expression"));
+        }
+
+        let bytecode = self.bytecode.get(mem);
+
+        let dest = self.acquire_reg()?;
+        self.push(mem, Opcode::LoadNil { dest })?;
+
+        self.loops.push(Loop {
+            dest,
+            scope_depth: self.vars.scopes.len(),
+            extent_depth: self.extent_depth,
+            break_jumps: Vec::new(),
+        });
+
+        let start = bytecode.next_instruction();
+        let test = self.compile_eval(mem, loop_expr[0])?;
+        let exit_jump = self.push_jump_if_not_true(mem, test, start)?;
+        self.reset_reg(dest + 1);
+
+        for expr in &loop_expr[1..] {
+            self.compile_eval(mem, *expr)?;
+            self.reset_reg(dest + 1);
+        }
+
+        let offset = start as i64 - bytecode.next_instruction() as i64 - 1;
+        if offset < JumpOffset::min_value() as i64 {
+            return Err(err_eval("The body of a while expression is too long"));
+        }
+        self.push(
+            mem,
+            Opcode::Jump {
+                offset: offset as JumpOffset,
+            },
+        )?;
+
+        // the loop was pushed above and any nested loops have been popped
+        let this_loop = self
+            .loops
+            .pop()
+            .expect("The while loop is not on the loop stack");
+
+        let offset = bytecode.next_instruction() - exit_jump - 1;
+        bytecode.update_jump_offset(mem, exit_jump, offset as JumpOffset)?;
+
+        for address in this_loop.break_jumps.iter() {
+            let offset = bytecode.next_instruction() - address - 1;
+            bytecode.update_jump_offset(mem, *address, offset as JumpOffset)?;
+        }
+
+        Ok(dest)
+    }
+
+    /// Compile a 'break' application, leaving the innermost enclosing while loop
+    /// (break [<expr>])
+    /// The loop result is the value of the expr, or nil if there is none.
+    fn compile_apply_break<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let break_expr = vec_from_pairs(mem, args)?;
+        if break_expr.len() > 1 {
+            return Err(err_eval("A break expression has at most 1 argument"));
+        }
+
+        let (dest, scope_depth, extent_depth) = match self.loops.last() {
+            Some(enclosing) => (
+                enclosing.dest,
+                enclosing.scope_depth,
+                enclosing.extent_depth,
+            ),
+            None => return Err(err_eval("A break expression must be within a while loop")),
+        };
+
+        if extent_depth != self.extent_depth {
+            return Err(err_eval(
+                "A break expression cannot leave a parameterize, with-limit, try or unwind-protect \
+                 expression",
+            ));
+        }
+
+        match break_expr.first() {
+            Some(expr) => self.compile_branch(mem, *expr, dest, false)?,
+            None => self.push(mem, Opcode::LoadNil { dest })?,
+        }
+
+        // close any variables that go out of scope when leaving the loop
+        for opcode in &self.vars.closings_from(scope_depth) {
+            self.push(mem, *opcode)?;
+        }
+
+        let offset = JUMP_UNKNOWN;
+        self.push(mem, Opcode::Jump { offset })?;
+        let address = self.bytecode.get(mem).last_instruction();
+        if let Some(enclosing) = self.loops.last_mut() {
+            enclosing.break_jumps.push(address);
+        }
+
+        Ok(dest)
+    }
+
+    /// Raise a warning on the Thread being compiled for. Without one, warnings are dropped.
+    fn warn(&self, warning: Diagnostic) {
+        if let Some(thread) = self.context.thread {
+            thread.warn(warning);
+        }
+    }
+
+    /// Warn if a definition of the named global, at the given position, replaces a global that is
+    /// already bound or that was defined earlier in code compiled for the same Thread
+    fn check_redefinition<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        name: TaggedScopedPtr<'guard>,
+        pos: Option<SourcePos>,
+    ) {
+        let (thread, s) = match (self.context.thread, *name) {
+            (Some(thread), Value::Symbol(s)) => (thread, s.as_str(mem)),
+            _ => return,
+        };
+
+        let previous = thread.record_definition(s, pos);
+        if previous.is_none() && thread.lookup_global(mem, name).is_none() {
+            return;
+        }
+
+        let mut warning =
+            Diagnostic::warning(&format!("Definition of {} replaces an existing global", s));
+        if let Some(pos) = pos {
+            warning = warning.with_label(name_span(pos, s), "redefined here");
+        }
+        warning = match previous {
+            Some(Some(previous)) => {
+                warning.with_secondary_label(name_span(previous, s), "previously defined here")
+            }
+            Some(None) => warning,
+            None => warning.with_note(&format!("{} is a builtin or was bound by the host", s)),
+        };
+        self.warn(warning);
+    }
+
+    /// Warn if a local variable, bound at the given position, has the name of a special form. In
+    /// its scope an application of the name still applies the special form, not the variable.
+    fn check_shadowing<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        name: TaggedScopedPtr<'guard>,
+        pos: Option<SourcePos>,
+    ) {
+        if let Value::Symbol(s) = *name {
+            let s = s.as_str(mem);
+            if self.context.forms.lookup(s).is_some() {
+                let mut warning = Diagnostic::warning(&format!(
+                    "Local variable {} shadows the special form of the same name",
+                    s
+                ))
+                .with_note(&format!(
+                    "({} ...) still applies the special form, not the variable",
+                    s
+                ));
+                if let Some(pos) = pos {
+                    warning = warning.with_label(name_span(pos, s), "bound here");
+                }
+                self.warn(warning);
+            }
+        }
+    }
+
+    /// Warn of any parameter in a function parameter list that shadows a special form
+    fn check_parameter_shadowing<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        params: TaggedScopedPtr<'guard>,
+    ) {
+        let mut head = params;
+        while let Value::Pair(p) = *head {
+            self.check_shadowing(mem, p.first.get(mem), p.first_pos.get());
+            head = p.second.get(mem);
+            if let Value::Symbol(_) = *head {
+                self.check_shadowing(mem, head, p.second_pos.get());
+            }
+        }
+    }
+
+    /// Warn of a reference to a global whose `deprecated` property is set. If the property is
+    /// text, it is given as a note, such as what to use instead.
+    fn check_deprecation<'guard>(&self, mem: &'guard MutatorView, name: TaggedScopedPtr<'guard>) {
+        if let Some(thread) = self.context.thread {
+            if let Some(reason) = thread.get_property(mem, name, mem.lookup_sym("deprecated")) {
+                let mut warning = Diagnostic::warning(&format!("{} is deprecated", name));
+                if let Value::Text(text) = *reason {
+                    warning = warning.with_note(text.as_str(mem));
+                }
+                self.warn(warning);
+            }
+        }
+    }
+
+    /// Record that the expression reads, or defines or assigns, the named global
+    fn record_global<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        name: TaggedScopedPtr<'guard>,
+        write: bool,
+    ) {
+        if let Value::Symbol(s) = *name {
+            let name = String::from(s.as_str(guard));
+            let mut access = self.context.access.borrow_mut();
+            if write {
+                access.writes.insert(name);
+            } else {
+                access.reads.insert(name);
+            }
+        }
+    }
+
+    /// Push an instruction to the function bytecode list
+    fn push<'guard>(&mut self, mem: &'guard MutatorView, op: Opcode) -> Result<(), RuntimeError> {
+        // Stress the heap by checking everything the compiler holds before every instruction
+        #[cfg(feature = "gc-stress")]
+        self.verify_roots(mem)?;
+
+        self.bytecode.get(mem).push_with_pos(mem, op, self.form_pos)
+    }
+
+    /// Push a lookup of the global named in the `name` register, with an inline cache slot
+    fn push_load_global<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        dest: Register,
+        name: Register,
+    ) -> Result<(), RuntimeError> {
+        #[cfg(feature = "gc-stress")]
+        self.verify_roots(mem)?;
+
+        self.bytecode.get(mem).push_loadglobal(mem, dest, name)
+    }
+
+    /// Push an instruction, recording its source position for the VM to report, if known
+    fn push_with_pos<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        op: Opcode,
+        pos: Option<SourcePos>,
+    ) -> Result<(), RuntimeError> {
+        #[cfg(feature = "gc-stress")]
+        self.verify_roots(mem)?;
+
+        self.bytecode
+            .get(mem)
+            .push_with_pos(mem, op, pos.or(self.form_pos))
     }
 
-    // this is a naive way of allocating registers - every result gets it's own register
-    fn acquire_reg(&mut self) -> Register {
-        // TODO check overflow
-        let reg = self.next_reg;
-        self.next_reg += 1;
-        reg
-    }
+    /// Push a jump that is taken if the test compiled into `test`, from the instruction at
+    /// `test_start` onwards, is not true. The offset is left to be updated once the target is
+    /// known. A test that ends in a comparison whose result is not used otherwise is combined with
+    /// the jump, so that the branch takes one instruction instead of two. Return the address of
+    /// the jump.
+    fn push_jump_if_not_true<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        test: Register,
+        test_start: ArraySize,
+    ) -> Result<ArraySize, RuntimeError> {
+        let bytecode = self.bytecode.get(mem);
+        let offset = JUMP_UNKNOWN;
+
+        // the comparison cannot be combined if a jump in the test skips it to reach the jump
+        let last = if bytecode.next_instruction() > test_start
+            && !bytecode.is_jump_target(mem, test_start, bytecode.next_instruction())
+        {
+            bytecode.last_opcode(mem)
+        } else {
+            None
+        };
+
+        let fused = match last {
+            // nil? and not are true if and only if their operand is not true
+            Some(Opcode::IsNil {
+                dest,
+                test: operand,
+            }) if dest == test => {
+                bytecode.replace_last(
+                    mem,
+                    Opcode::JumpIfTrue {
+                        test: operand,
+                        offset,
+                    },
+                )?;
+                return Ok(bytecode.last_instruction());
+            }
+
+            Some(Opcode::IsIdentical { dest, test1, test2 }) if dest == test => {
+                Opcode::JumpIfNotIdentical {
+                    test1,
+                    test2,
+                    offset: 0,
+                }
+            }
+            Some(Opcode::IsLessThan { dest, left, right }) if dest == test => {
+                Opcode::JumpIfNotLessThan {
+                    left,
+                    right,
+                    offset: 0,
+                }
+            }
+            Some(Opcode::IsGreaterThan { dest, left, right }) if dest == test => {
+                Opcode::JumpIfNotGreaterThan {
+                    left,
+                    right,
+                    offset: 0,
+                }
+            }
+            Some(Opcode::IsLessOrEqual { dest, left, right }) if dest == test => {
+                Opcode::JumpIfNotLessOrEqual {
+                    left,
+                    right,
+                    offset: 0,
+                }
+            }
+            Some(Opcode::IsGreaterOrEqual { dest, left, right }) if dest == test => {
+                Opcode::JumpIfNotGreaterOrEqual {
+                    left,
+                    right,
+                    offset: 0,
+                }
+            }
+            Some(Opcode::IsNumericEqual { dest, left, right }) if dest == test => {
+                Opcode::JumpIfNotNumericEqual {
+                    left,
+                    right,
+                    offset: 0,
+                }
+            }
+
+            _ => {
+                self.push(mem, Opcode::JumpIfNotTrue { test, offset })?;
+                return Ok(bytecode.last_instruction());
+            }
+        };
+
+        // a combined comparison is followed by a Jump, taken if the target is too far for it
+        bytecode.replace_last(mem, fused)?;
+        let address = bytecode.last_instruction();
+        self.push(mem, Opcode::Jump { offset })?;
+        Ok(address)
+    }
+
+    /// Push an instruction binding a global, recording the source position of the definition or
+    /// assignment for the global log, see `globallog`
+    fn push_store_global<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        src: Register,
+        name: Register,
+        pos: Option<SourcePos>,
+    ) -> Result<(), RuntimeError> {
+        self.push_with_pos(mem, Opcode::StoreGlobal { src, name }, pos)
+    }
+
+    /// Keep a value reachable until compilation of this function is complete
+    fn root<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        StackAnyContainer::push(&*self.roots.get(mem), mem, value)
+    }
+
+    /// Check every object reachable from this compiler
+    #[cfg(feature = "gc-stress")]
+    fn verify_roots<'guard>(&self, mem: &'guard MutatorView) -> Result<(), RuntimeError> {
+        let mut checker = HeapChecker::new();
+        checker.object(mem, &*self.bytecode.get(mem))?;
+        checker.object(mem, &*self.roots.get(mem))
+    }
+
+    /// Return the value of an expression if it is known while compiling: a literal, a keyword,
+    /// nil or true, or an application of a special form or a pure builtin that folds over
+    /// constant arguments. The
+    /// value is the same as evaluating the expression would give, and an expression whose
+    /// evaluation would raise an error has no constant value.
+    fn constant_value<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        expr: TaggedScopedPtr<'guard>,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        match *expr {
+            Value::Pair(p) => {
+                let function = p.first.get(mem);
+                let name = match *function {
+                    Value::Symbol(s) => s.as_str(mem),
+                    _ => return Ok(None),
+                };
+
+                // a macro takes precedence over the special form of the same name
+                if let Some(thread) = self.context.thread {
+                    if thread.lookup_macro(mem, function).is_some() {
+                        return Ok(None);
+                    }
+                }
+
+                match self.context.forms.lookup(name) {
+                    Some(FormHandler::Compile(_, Some(fold))) => fold(self, mem, p.second.get(mem)),
+                    Some(_) => Ok(None),
+                    None => self.fold_builtin(mem, function, p.second.get(mem)),
+                }
+            }
+
+            Value::Symbol(s) if s.is_keyword() => Ok(Some(expr)),
+            Value::Symbol(s) => match s.as_str(mem) {
+                "nil" => Ok(Some(mem.nil())),
+                "true" => Ok(Some(mem.lookup_sym("true"))),
+                _ => Ok(None),
+            },
+
+            _ => Ok(Some(expr)),
+        }
+    }
+
+    /// Return the values of the argument expressions if every one is constant
+    fn constant_args<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Option<Vec<TaggedScopedPtr<'guard>>>, RuntimeError> {
+        let mut values = Vec::new();
+        for arg in vec_from_pairs(mem, args)? {
+            match self.constant_value(mem, arg)? {
+                Some(value) => values.push(value),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(values))
+    }
+
+    /// Fold a call to a pure builtin over constant arguments, see `builtins::pure_builtin()`. The
+    /// name must not be a local variable and its global must still be bound to the builtin on
+    /// the thread being compiled for.
+    fn fold_builtin<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        function: TaggedScopedPtr<'guard>,
+        args: TaggedScopedPtr<'guard>,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        let (thread, code) = match (self.context.thread, *function) {
+            (Some(thread), Value::Symbol(s)) if !s.is_keyword() => {
+                match pure_builtin(s.as_str(mem)) {
+                    Some(code) => (thread, code),
+                    None => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        if self.vars.lookup_binding(function)?.is_some() {
+            return Ok(None);
+        }
+        let native = match thread.lookup_global(mem, function).map(|value| *value) {
+            Some(Value::NativeFunction(native)) if native.runs(code) => native,
+            _ => return Ok(None),
+        };
+
+        match self.constant_args(mem, args)? {
+            Some(values) => {
+                self.record_global(mem, function, false);
+                Ok(native.call(mem, None, &values).ok())
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Fold an application of a special form of the given arity over constant arguments
+    fn fold_op<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+        arity: usize,
+        f: fn(&'guard MutatorView, &[TaggedScopedPtr<'guard>]) -> Option<TaggedScopedPtr<'guard>>,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        match self.constant_args(mem, args)? {
+            Some(values) if values.len() == arity => Ok(f(mem, &values)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Fold an integer arithmetic application over constant arguments, left to right from the
+    /// identity value as `compile_apply_arithmetic()` compiles it
+    fn fold_arithmetic<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+        identity: LiteralInteger,
+        op: ArithmeticOp,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        let values = match self.constant_args(mem, args)? {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+
+        let identity = TaggedScopedPtr::new(mem, TaggedPtr::number(identity as isize));
+        let (first, rest) = match values.split_first() {
+            Some(split) => split,
+            None => return Ok(Some(identity)),
+        };
+
+        if rest.is_empty() {
+            return Ok(fold_integer(mem, op, identity, *first));
+        }
+
+        let mut acc = *first;
+        for value in rest {
+            acc = match fold_integer(mem, op, acc, *value) {
+                Some(result) => result,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(acc))
+    }
+
+    /// Fold a numeric comparison over constant arguments
+    fn fold_comparison<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        args: TaggedScopedPtr<'guard>,
+        op: &str,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        let values = match self.constant_args(mem, args)? {
+            Some(values) if values.len() == 2 => values,
+            _ => return Ok(None),
+        };
+
+        let ordering = match numeric_comparison(mem, op, values[0], values[1]) {
+            Ok(ordering) => ordering,
+            Err(_) => return Ok(None),
+        };
+
+        // every comparison with NaN is false
+        let holds = match ordering {
+            Some(ordering) => match op {
+                "<" => ordering == Ordering::Less,
+                ">" => ordering == Ordering::Greater,
+                "<=" => ordering != Ordering::Greater,
+                ">=" => ordering != Ordering::Less,
+                _ => ordering == Ordering::Equal,
+            },
+            None => false,
+        };
+        Ok(Some(mem.boolean(holds)))
+    }
+
+    /// Push an instruction with a result and a single argument to the function bytecode list
+    fn push_op2<'guard, F>(
+        &mut self,
+        mem: &'guard MutatorView,
+        params: TaggedScopedPtr<'guard>,
+        f: F,
+    ) -> Result<Register, RuntimeError>
+    where
+        F: Fn(Register, Register) -> Opcode,
+    {
+        let result = self.acquire_reg()?;
+        let reg1 = self.compile_eval(mem, value_from_1_pair(mem, params)?)?;
+        self.push(mem, f(result, reg1))?;
+        Ok(result)
+    }
+
+    /// Push an instruction with a result and two arguments to the function bytecode list
+    fn push_op3<'guard, F>(
+        &mut self,
+        mem: &'guard MutatorView,
+        params: TaggedScopedPtr<'guard>,
+        f: F,
+    ) -> Result<Register, RuntimeError>
+    where
+        F: Fn(Register, Register, Register) -> Opcode,
+    {
+        let result = self.acquire_reg()?;
+        let (first, second) = values_from_2_pairs(mem, params)?;
+        let reg1 = self.compile_eval(mem, first)?;
+        let reg2 = self.compile_eval(mem, second)?;
+        self.push(mem, f(result, reg1, reg2))?;
+        Ok(result)
+    }
+
+    // Push a literal onto the literals list and a load instruction onto the bytecode list
+    fn push_load_literal<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        literal: TaggedScopedPtr<'guard>,
+    ) -> Result<Register, RuntimeError> {
+        let result = self.acquire_reg()?;
+        let lit_id = self.bytecode.get(mem).push_lit(mem, literal)?;
+        self.bytecode.get(mem).push_loadlit(mem, result, lit_id)?;
+        Ok(result)
+    }
+
+    // acquire a register for a value
+    fn acquire_reg(&mut self) -> Result<Register, RuntimeError> {
+        self.registers.acquire(1)
+    }
+
+    // acquire a block of consecutive registers, returning the first
+    fn acquire_window(&mut self, count: usize) -> Result<Register, RuntimeError> {
+        self.registers.acquire(count)
+    }
+
+    // return the register that will be acquired next
+    fn next_reg(&self) -> Result<Register, RuntimeError> {
+        self.registers.next()
+    }
+
+    // reset the next register back to the given one so that it is reused
+    fn reset_reg(&mut self, reg: Register) {
+        self.registers.release_from(reg)
+    }
+}
+
+/// Compile a function - parameters and expression, returning a tagged Function object
+fn compile_function<'guard, 'scope>(
+    mem: &'guard MutatorView,
+    parent: Option<&'scope Variables<'scope>>,
+    context: &'scope CompileContext<'scope>,
+    name: TaggedScopedPtr<'guard>,
+    params: &[TaggedScopedPtr<'guard>],
+    rest: Option<TaggedScopedPtr<'guard>>,
+    exprs: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let compiler = Compiler::new(mem, parent, context)?;
+    Ok(compiler
+        .compile_function(mem, name, params, rest, exprs)?
+        .as_tagged(mem))
+}
+
+/// Return the result of integer arithmetic on two constant values, None unless both and the
+/// result are integers small enough to be inline. The result is then the same whatever the
+/// overflow mode of the Thread evaluating it would be.
+fn fold_integer<'guard>(
+    mem: &'guard MutatorView,
+    op: ArithmeticOp,
+    left: TaggedScopedPtr<'guard>,
+    right: TaggedScopedPtr<'guard>,
+) -> Option<TaggedScopedPtr<'guard>> {
+    match (*left, *right) {
+        (Value::Number(_), Value::Number(_)) => (),
+        _ => return None,
+    }
+
+    match number::arithmetic(mem, OverflowMode::Error, op, left, right) {
+        Ok(result) => match *result {
+            Value::Number(_) => Some(result),
+            _ => None,
+        },
+        Err(_) => None,
+    }
+}
+
+/// Maximum nesting of the value of a global that a `freeze` expression copies, beyond which the
+/// value is taken to contain itself
+const FREEZE_MAX_DEPTH: usize = 1000;
+
+/// Return a copy of a value that shares no mutable object with it. Pairs, lists, dicts and text
+/// are copied, as are their contents, and any other value is shared.
+fn frozen_copy<'guard>(
+    mem: &'guard MutatorView,
+    value: TaggedScopedPtr<'guard>,
+    depth: usize,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    if depth > FREEZE_MAX_DEPTH {
+        return Err(err_eval(
+            "Cannot freeze a value that is nested too deeply or that contains itself",
+        ));
+    }
+
+    match *value {
+        Value::Pair(_) => {
+            let mut items = Vec::new();
+            let mut head = value;
+            while let Value::Pair(p) = *head {
+                items.push(frozen_copy(mem, p.first.get(mem), depth + 1)?);
+                head = p.second.get(mem);
+            }
+
+            let mut copy = frozen_copy(mem, head, depth + 1)?;
+            for item in items.iter().rev() {
+                copy = cons(mem, *item, copy)?;
+            }
+            Ok(copy)
+        }
+
+        Value::List(list) => {
+            let items = list.access_slice(mem, |items| {
+                items.iter().map(|item| item.get(mem)).collect::<Vec<_>>()
+            });
+            let copies = items
+                .into_iter()
+                .map(|item| frozen_copy(mem, item, depth + 1))
+                .collect::<Result<Vec<_>, RuntimeError>>()?;
+            Ok(List::from_slice(mem, &copies)?.as_tagged(mem))
+        }
+
+        Value::Dict(dict) => {
+            let copy = Dict::alloc(mem)?;
+            for (key, value) in dict.items(mem) {
+                copy.assoc(mem, key, frozen_copy(mem, value, depth + 1)?)?;
+            }
+            Ok(copy.as_tagged(mem))
+        }
+
+        Value::Text(text) => mem.text(text.as_str(mem)),
+
+        _ => Ok(value),
+    }
+}
+
+/// Return the quoted value of a (quote <value>) expression
+fn quoted<'guard>(
+    guard: &'guard dyn MutatorScope,
+    expr: TaggedScopedPtr<'guard>,
+) -> Option<TaggedScopedPtr<'guard>> {
+    if let Value::Pair(p) = *expr {
+        if let (Value::Symbol(s), Value::Pair(rest)) = (*p.first.get(guard), *p.second.get(guard)) {
+            if s.as_str(guard) == "quote" && rest.second.get(guard).is_nil() {
+                return Some(rest.first.get(guard));
+            }
+        }
+    }
+
+    None
+}
+
+/// Return the source position of the first item of a list, if it has one
+fn first_pos(list: TaggedScopedPtr) -> Option<SourcePos> {
+    match *list {
+        Value::Pair(p) => p.first_pos.get(),
+        _ => None,
+    }
+}
+
+/// The span of a name written at the given position
+fn name_span(pos: SourcePos, name: &str) -> Span {
+    let length = name.chars().count().max(1) as u32;
+    Span::new(pos, spos(pos.line, pos.column + length - 1))
+}
+
+/// Split a parameter list into the parameter names and the rest parameter name, if the list is
+/// dotted as in (a b . rest) or is a single symbol as in (lambda args expr)
+fn parameters<'guard>(
+    guard: &'guard dyn MutatorScope,
+    params: TaggedScopedPtr<'guard>,
+) -> Result<
+    (
+        Vec<TaggedScopedPtr<'guard>>,
+        Option<TaggedScopedPtr<'guard>>,
+    ),
+    RuntimeError,
+> {
+    let mut names = Vec::new();
+    let mut tail = params;
+
+    loop {
+        match *tail {
+            Value::Pair(pair) => {
+                names.push(pair.first.get(guard));
+                tail = pair.second.get(guard);
+            }
+            Value::Nil => return Ok((names, None)),
+            Value::Symbol(s) if s.as_str(guard) == "nil" => return Ok((names, None)),
+            Value::Symbol(_) => return Ok((names, Some(tail))),
+            _ => {
+                return Err(err_eval(
+                    "Parameters must be a list of symbols, optionally ending in a dotted rest parameter",
+                ))
+            }
+        }
+    }
+}
+
+/// Compile the given AST with the standard special forms and return an anonymous Function object
+pub fn compile<'guard>(
+    mem: &'guard MutatorView,
+    ast: TaggedScopedPtr<'guard>,
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    compile_with_forms(mem, &SpecialFormTable::standard(), ast)
+}
+
+/// Compile the given AST with the given special forms and return an anonymous Function object
+pub fn compile_with_forms<'guard>(
+    mem: &'guard MutatorView,
+    forms: &SpecialFormTable,
+    ast: TaggedScopedPtr<'guard>,
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    compile_in_context(mem, &CompileContext::new(None, forms), ast)
+}
+
+/// Compile the given AST for evaluation on the given Thread and return an anonymous Function
+/// object. The special forms are those of the Thread. Macros defined on the Thread are expanded,
+/// by evaluating them on the Thread, and `defmacro` defines new ones.
+pub fn compile_with_thread<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    ast: TaggedScopedPtr<'guard>,
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    let forms = thread.special_forms();
+    compile_in_context(mem, &CompileContext::new(Some(thread), &forms), ast)
+}
+
+/// Compile the given AST for evaluation on the given Thread, as `compile_with_thread()` does, and
+/// return an anonymous Function object along with the globals the expression reads and writes.
+/// Hosts that recompute expressions when globals change can use these to find the expressions
+/// affected by a change.
+pub fn compile_with_info<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    ast: TaggedScopedPtr<'guard>,
+) -> Result<(ScopedPtr<'guard, Function>, GlobalAccess), RuntimeError> {
+    let forms = thread.special_forms();
+    let context = CompileContext::new(Some(thread), &forms);
+    let function = compile_in_context(mem, &context, ast)?;
+    Ok((function, context.access.into_inner()))
+}
+
+/// Compile a program, a list of top level forms such as `parse_program()` returns, with the
+/// standard special forms. The forms are evaluated in order and the anonymous Function object
+/// returns the value of the last, or nil if there are none.
+pub fn compile_program<'guard>(
+    mem: &'guard MutatorView,
+    forms: TaggedScopedPtr<'guard>,
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    let forms_table = SpecialFormTable::standard();
+    compile_forms_in_context(mem, &CompileContext::new(None, &forms_table), forms)
+}
+
+/// Compile a program, as `compile_program()` does, for evaluation on the given Thread. Macros
+/// defined by one form can be used by the forms that follow it.
+pub fn compile_program_with_thread<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    forms: TaggedScopedPtr<'guard>,
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    let forms_table = thread.special_forms();
+    compile_forms_in_context(mem, &CompileContext::new(Some(thread), &forms_table), forms)
+}
+
+/// Compile a list of top level forms into a single anonymous Function object
+fn compile_forms_in_context<'guard>(
+    mem: &'guard MutatorView,
+    context: &CompileContext,
+    forms: TaggedScopedPtr<'guard>,
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    let mut exprs = vec_from_pairs(mem, forms)?;
+    if exprs.is_empty() {
+        exprs.push(mem.nil());
+    }
+
+    let compiler = Compiler::new(mem, None, context)?;
+    compiler.compile_function(mem, mem.nil(), &[], None, &exprs)
+}
+
+/// Compile the given AST as a top level expression, returning an anonymous Function object
+fn compile_in_context<'guard>(
+    mem: &'guard MutatorView,
+    context: &CompileContext,
+    ast: TaggedScopedPtr<'guard>,
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    let compiler = Compiler::new(mem, None, context)?;
+    compiler.compile_function(mem, mem.nil(), &[], None, &[ast])
+}
+
+/// INTEGRATION TESTS
+/// TODO - move to a separate module
+#[cfg(test)]
+mod integration {
+    use super::*;
+    use crate::diagnostic::Diagnostic;
+    use crate::error::{spos, ErrorKind, MAX_BACKTRACE_FRAMES};
+    use crate::memory::{Memory, Mutator, SizeLimits};
+    use crate::number::OverflowMode;
+    use crate::pair::cons;
+    use crate::parser::{parse, parse_program};
+    use crate::printer::{debug_to, print_limited, print_to, PrintLimit};
+    use crate::vm::Thread;
+
+    fn eval_helper<'guard>(
+        mem: &'guard MutatorView,
+        thread: ScopedPtr<'guard, Thread>,
+        code: &str,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let compiled_code = compile_with_thread(mem, &thread, parse(mem, code)?)?;
+        println!("RUN CODE {}", code);
+        let result = thread.quick_vm_eval(mem, compiled_code)?;
+        println!("RUN RESULT {}", result);
+        Ok(result)
+    }
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    #[test]
+    fn compile_cond_first_is_true() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // testing 'cond'
+            // (nil? nil) == true, so result should be x
+            let code = "(cond (nil? nil) 'x (nil? 'a) 'y)";
+
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, code)?;
+
+            assert!(result == mem.lookup_sym("x"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_cond_second_is_true() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // testing 'cond'
+            // (nil? 'a) == nil, (nil? nil) == true, so result should be y
+            let code = "(cond (nil? 'a) 'x (nil? nil) 'y)";
+
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, code)?;
+
+            assert!(result == mem.lookup_sym("y"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_cond_none_is_true() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // testing 'cond'
+            // (nil? 'a) == nil, (nil? 'b) == nil, result should be nil
+            let code = "(cond (nil? 'a) 'x (nil? 'b) 'y)";
+
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, code)?;
+
+            assert!(result == mem.nil());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_call_functions() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // this test calls a function from another function
+            let compare_fn = "(def is_it (ask expect) (is? ask expect))";
+            let curried_fn = "(def is_it_a (ask) (is_it ask 'a))";
+            let query1 = "(is_it_a nil)";
+            let query2 = "(is_it_a 'a)";
+
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, compare_fn)?;
+            eval_helper(mem, t, curried_fn)?;
+
+            let result1 = eval_helper(mem, t, query1)?;
+            assert!(result1 == mem.nil());
+
+            let result2 = eval_helper(mem, t, query2)?;
+            assert!(result2 == mem.lookup_sym("true"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_map_function_over_list() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // this test passes a function as a parameter through recursive function calls
+            let compare_fn = "(def is_y (ask) (is? ask 'y))";
+            let map_fn =
+                "(def map (f l) (cond (nil? l) nil true (cons (f (car l)) (map f (cdr l)))))";
+
+            let query = "(map is_y '(x y z z y))";
+
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, compare_fn)?;
+            eval_helper(mem, t, map_fn)?;
+
+            let result = eval_helper(mem, t, query)?;
+
+            let result = vec_from_pairs(mem, result)?;
+            let sym_nil = mem.nil();
+            let sym_true = mem.lookup_sym("true");
+            assert!(result == &[sym_nil, sym_true, sym_nil, sym_nil, sym_true]);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_eval_nested_partials() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // this test evaluates nested Partial applications in function position
+            let a_fn = "(def isit (a b) (is? a b))";
+
+            let query1 = "((isit 'x) 'x)";
+            let query2 = "((isit 'x) 'y)";
+
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, a_fn)?;
+
+            let result = eval_helper(mem, t, query1)?;
+            assert!(result == mem.lookup_sym("true"));
+
+            let result = eval_helper(mem, t, query2)?;
+            assert!(result == mem.nil());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_pass_partial_as_param() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // this test passes a Partial as an argument of another function that will call it
+            // with it's last argument.
+            let isit_fn = "(def isit (a b) (is? a b))";
+            let map_fn = "(def map (f v) (f v))";
+
+            let query1 = "(map (isit 'x) 'x)";
+            let query2 = "(map (isit 'x) 'y)";
+
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, isit_fn)?;
+            eval_helper(mem, t, map_fn)?;
+
+            let result = eval_helper(mem, t, query1)?;
+            assert!(result == mem.lookup_sym("true"));
+
+            let result = eval_helper(mem, t, query2)?;
+            assert!(result == mem.nil());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_simple_let() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // this test compiles a basic let expression
+            let expr = "(let ((x 'y)) x)";
+
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, expr)?;
+            assert!(result == mem.lookup_sym("y"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_function_with_simple_let() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // this test compiles a let expression that deconstructs and reconstructs a pair list
+            let a_fn = "(def deconrecon (list) (let ((a (car list)) (b (cdr list))) (cons a b)))";
+            let query = "(deconrecon '(x y z z y))";
+
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, a_fn)?;
+
+            let result = eval_helper(mem, t, query)?;
+
+            let result = vec_from_pairs(mem, result)?;
+            let sym_x = mem.lookup_sym("x");
+            let sym_y = mem.lookup_sym("y");
+            let sym_z = mem.lookup_sym("z");
+            assert!(result == &[sym_x, sym_y, sym_z, sym_z, sym_y]);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_function_with_lambda_with_nonlocal_ref() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // this test compiles a function containing a lambda that references a nonlocal
+            let head_fn = "(def head (a) (let ((inner (\\ () (car a)))) (inner)))";
+            let query = "(head '(x y z z y))";
+
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, head_fn)?;
+
+            let result = eval_helper(mem, t, query)?;
+            assert!(result == mem.lookup_sym("x"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_function_returning_lambda_with_nonlocal_ref() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // this test compiles a function that returns a lambda that references a nonlocal
+            let head_fn = "(def head (a) (let ((inner (\\ () (car a)))) inner))";
+            let inner_fn = "(set 'inner (head '(x y z z y)))";
+            let query = "(inner)";
+
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, head_fn)?;
+            eval_helper(mem, t, inner_fn)?;
+
+            let result = eval_helper(mem, t, query)?;
+            assert!(result == mem.lookup_sym("x"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_let_with_lambda_with_nested_call() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // this test compiles a let containing a lambda that is referenced in a sub-let scope
+            let f = "(let ((f (\\ (a) a))) (let ((g (f 'b))) g))";
+
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, f)?;
+            assert!(result == mem.lookup_sym("b"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_call_anonymous_function() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // a lambda applied directly in call position
+            let result = eval_helper(mem, t, "((lambda (x y) (cons y x)) 'a 'b)")?;
+            assert!(format!("{}", result) == "(b . a)");
+
+            let result = eval_helper(mem, t, "((lambda () 'q))")?;
+            assert!(result == mem.lookup_sym("q"));
+
+            // anonymous functions as values passed to and called by other functions
+            eval_helper(mem, t, "(set 'twice (lambda (x) (cons x x)))")?;
+            eval_helper(mem, t, "(set 'apply-w (lambda (f) (f 'w)))")?;
+            let result = eval_helper(mem, t, "(apply-w twice)")?;
+            assert!(format!("{}", result) == "(w . w)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_builtin_compare() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, "(compare 'a 'b)")?;
+            assert!(result.value().as_int() == Some(-1));
+
+            let result = eval_helper(mem, t, "(compare '(a b) '(a b))")?;
+            assert!(result.value().as_int() == Some(0));
+
+            // symbols order after nil
+            let result = eval_helper(mem, t, "(compare 'a nil)")?;
+            assert!(result.value().as_int() == Some(1));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_builtin_sort() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, "(sort '(cherry (b) apple nil banana (a)))")?;
+            assert!(format!("{}", result) == "(nil apple banana cherry (a) (b))");
+
+            // sort is a first class function value
+            let result = eval_helper(mem, t, "(let ((f sort)) (f '(z y x)))")?;
+            assert!(format!("{}", result) == "(x y z)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_builtin_sorted_map() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(set 'm (sorted-map))")?;
+            for key in &["pear", "apple", "fig", "banana"] {
+                let source = format!("(sorted-map-set! m '{} '{}-value)", key, key);
+                eval_helper(mem, t, &source)?;
+            }
+
+            let result = eval_helper(mem, t, "(sorted-map-get m 'fig)")?;
+            assert!(result == mem.lookup_sym("fig-value"));
+
+            let result = eval_helper(mem, t, "(sorted-map-first m)")?;
+            assert!(format!("{}", result) == "(apple . apple-value)");
+
+            let result = eval_helper(mem, t, "(sorted-map-range m 'b 'g)")?;
+            assert!(format!("{}", result) == "((banana . banana-value) (fig . fig-value))");
+
+            eval_helper(mem, t, "(sorted-map-remove! m 'apple)")?;
+            let result = eval_helper(mem, t, "(sorted-map-range m 'a nil)")?;
+            assert!(
+                format!("{}", result)
+                    == "((banana . banana-value) (fig . fig-value) (pear . pear-value))"
+            );
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_builtin_priority_queue() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(set 'q (priority-queue))")?;
+            for item in &["pear", "apple", "fig"] {
+                eval_helper(mem, t, &format!("(push! q '{})", item))?;
+            }
+
+            let result = eval_helper(mem, t, "(peek q)")?;
+            assert!(result == mem.lookup_sym("apple"));
+
+            let result = eval_helper(mem, t, "(cons (pop-min! q) (cons (pop-min! q) nil))")?;
+            assert!(format!("{}", result) == "(apple fig)");
+
+            // builtin comparators are accepted
+            eval_helper(mem, t, "(set 'q (priority-queue-by compare))")?;
+            eval_helper(mem, t, "(push! q 'b)")?;
+            let result = eval_helper(mem, t, "(pop-min! q)")?;
+            assert!(result == mem.lookup_sym("b"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_builtin_queue() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(set 'q (queue))")?;
+            eval_helper(mem, t, "(queue-push-back! q 'b)")?;
+            eval_helper(mem, t, "(queue-push-back! q 'c)")?;
+            eval_helper(mem, t, "(queue-push-front! q 'a)")?;
+
+            let result = eval_helper(mem, t, "q")?;
+            assert!(format!("{}", result) == "Queue[a b c]");
+
+            let result = eval_helper(mem, t, "(queue-pop-front! q)")?;
+            assert!(result == mem.lookup_sym("a"));
+
+            let result = eval_helper(mem, t, "(queue-pop-back! q)")?;
+            assert!(result == mem.lookup_sym("c"));
+
+            let result = eval_helper(mem, t, "(queue-front q)")?;
+            assert!(result == mem.lookup_sym("b"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_builtin_hash() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, "(is? (hash '(a b)) (hash '(a b)))")?;
+            assert!(result == mem.lookup_sym("true"));
+
+            // the stable hash of nil is 0xaf63bd4c8601b7df shifted right by 3 bits
+            let result = eval_helper(mem, t, "(stable-hash nil)")?;
+            assert!(result.value().as_int() == Some((0xaf63_bd4c_8601_b7df_u64 >> 3) as isize));
+
+            assert!(eval_helper(mem, t, "(hash (sorted-map))").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_builtin_codecs() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, "(hex-encode (base64-decode 'Zm9v))")?;
+            assert!(format!("{}", result) == "\"666f6f\"");
+
+            let result = eval_helper(mem, t, "(base64-encode (base64-decode 'Zm9vYg==))")?;
+            assert!(format!("{}", result) == "\"Zm9vYg==\"");
+
+            let result = eval_helper(mem, t, "(uuid4)")?;
+            assert!(result.value().as_str().map(|s| s.len()) == Some(36));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_long_running_recursion() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(
+                mem,
+                t,
+                "(def last (l) (cond (nil? (cdr l)) (car l) true (last (cdr l))))",
+            )?;
+
+            // enough nested calls to need several batches of instructions, each of which must
+            // resume where the previous one stopped
+            let mut list = String::from("'(");
+            for _ in 0..500 {
+                list.push_str("a ");
+            }
+            list.push_str("end)");
+
+            let result = eval_helper(mem, t, &format!("(last {})", list))?;
+            assert!(result == mem.lookup_sym("end"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn compile_builtin_digests() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, "(sha256 (base64-decode 'YWJj))")?;
+            assert!(
+                result.value().as_str()
+                    == Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+            );
+
+            let result = eval_helper(mem, t, "(crc32 (base64-decode 'MTIzNDU2Nzg5))")?;
+            assert!(result.value().as_int() == Some(0xcbf4_3926));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_define_destructuring() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(define answer 'yes)")?;
+            let result = eval_helper(mem, t, "answer")?;
+            assert!(result == mem.lookup_sym("yes"));
+
+            eval_helper(mem, t, "(define (values a b) '(x y))")?;
+            let result = eval_helper(mem, t, "(cons a b)")?;
+            assert!(format!("{}", result) == "(x . y)");
+
+            eval_helper(mem, t, "(define (p (q _) . r) '(m (n o) s t))")?;
+            let result = eval_helper(mem, t, "(cons p (cons q r))")?;
+            assert!(format!("{}", result) == "(m n s t)");
+
+            // items missing from the value are nil
+            eval_helper(mem, t, "(define (c d e) '(f))")?;
+            let result = eval_helper(mem, t, "(cons c (cons d e))")?;
+            assert!(format!("{}", result) == "(f nil)");
+
+            // the value of the whole definition is the value of the expression
+            let result = eval_helper(mem, t, "(define (g h) '(i j))")?;
+            assert!(format!("{}", result) == "(i j)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_function_introspection() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, "(def pair-of (a b) (cons a b))")?;
+            assert!(format!("{}", result) == "#<fn pair-of/2>");
+
+            let result = eval_helper(mem, t, "(arity pair-of)")?;
+            assert!(result.value().as_int() == Some(2));
+
+            let result = eval_helper(mem, t, "(arity (pair-of 'x))")?;
+            assert!(result.value().as_int() == Some(1));
+
+            let result = eval_helper(mem, t, "(function-name (pair-of 'x))")?;
+            assert!(result == mem.lookup_sym("pair-of"));
+
+            let result = eval_helper(mem, t, "(function-name (\\ (x) x))")?;
+            assert!(result == mem.nil());
+
+            let result = eval_helper(mem, t, "compare")?;
+            assert!(format!("{}", result) == "#<fn compare/2>");
+
+            let result = eval_helper(mem, t, "(function-code pair-of)")?;
+            let listing = format!("{}", result);
+            assert!(listing.contains("MakePair"));
+            assert!(listing.contains("Return"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_set_existing_variables() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // globals
+            eval_helper(mem, t, "(define colour 'red)")?;
+            eval_helper(mem, t, "(set! colour 'blue)")?;
+            let result = eval_helper(mem, t, "colour")?;
+            assert!(result == mem.lookup_sym("blue"));
+
+            assert!(eval_helper(mem, t, "(set! undefined-thing 'blue)").is_err());
+
+            // locals
+            let result = eval_helper(mem, t, "(let ((a 'x)) (set! a 'y) a)")?;
+            assert!(result == mem.lookup_sym("y"));
+
+            // nonlocals, via an upvalue
+            eval_helper(
+                mem,
+                t,
+                "(def make-counter () (let ((n nil)) (\\ () (set! n (cons 'i n)))))",
+            )?;
+            eval_helper(mem, t, "(define counter (make-counter))")?;
+            eval_helper(mem, t, "(counter)")?;
+            let result = eval_helper(mem, t, "(counter)")?;
+            assert!(format!("{}", result) == "(i i)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_parameterize() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(define indent (make-parameter 'none))")?;
+            eval_helper(mem, t, "(def show () (indent))")?;
+
+            let result = eval_helper(mem, t, "(show)")?;
+            assert!(result == mem.lookup_sym("none"));
+
+            // the binding is visible in called functions and nested parameterize expressions
+            let result = eval_helper(
+                mem,
+                t,
+                "(parameterize ((indent 'two)) (cons (show) (parameterize ((indent 'four)) (show))))",
+            )?;
+            assert!(format!("{}", result) == "(two . four)");
+
+            let result = eval_helper(mem, t, "(show)")?;
+            assert!(result == mem.lookup_sym("none"));
+
+            // an error restores the previous value
+            assert!(eval_helper(mem, t, "(parameterize ((indent 'two)) (car 'x))").is_err());
+            let result = eval_helper(mem, t, "(show)")?;
+            assert!(result == mem.lookup_sym("none"));
+
+            // the binding has ended when a throw reaches a catch clause or unwind-protect cleanup
+            // outside the parameterize expression, but not one inside it
+            let result = eval_helper(
+                mem,
+                t,
+                "(try (parameterize ((indent 'two)) (throw 'x)) (catch e (show)))",
+            )?;
+            assert!(result == mem.lookup_sym("none"));
+            eval_helper(mem, t, "(define seen nil)")?;
+            let result = eval_helper(
+                mem,
+                t,
+                "(try
+                   (unwind-protect
+                     (parameterize ((indent 'two))
+                       (unwind-protect (throw 'x) (set! seen (list (show)))))
+                     (set! seen (cons (show) seen)))
+                   (catch e seen))",
+            )?;
+            assert!(format!("{}", result) == "(none two)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_let_and_let_star_scoping() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // let binding expressions see the enclosing scope
+            let result = eval_helper(mem, t, "(let ((a 'outer)) (let ((a 'inner) (b a)) b))")?;
+            assert!(result == mem.lookup_sym("outer"));
+
+            let result = eval_helper(mem, t, "(let ((a '(x y))) (let ((a (car a))) a))")?;
+            assert!(result == mem.lookup_sym("x"));
+
+            // let* binding expressions see the previous bindings
+            let result = eval_helper(mem, t, "(let ((a 'outer)) (let* ((a 'inner) (b a)) b))")?;
+            assert!(result == mem.lookup_sym("inner"));
+
+            let result = eval_helper(
+                mem,
+                t,
+                "(let* ((l '(x y z)) (r (cdr l)) (s (cdr r))) (cons (car l) (car s)))",
+            )?;
+            assert!(format!("{}", result) == "(x . z)");
+
+            // bindings shadow globals and closures capture let* bindings
+            eval_helper(mem, t, "(define g 'global)")?;
+            let result = eval_helper(mem, t, "(let* ((g 'local) (f (\\ () g))) (f))")?;
+            assert!(result == mem.lookup_sym("local"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_tail_calls() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // mutually recursive functions calling each other in tail position, in cond branches
+            // and let bodies
+            eval_helper(
+                mem,
+                t,
+                "(def even-length? (l) (cond (nil? l) 'even true (odd-length? (cdr l))))",
+            )?;
+            eval_helper(
+                mem,
+                t,
+                "(def odd-length? (l) (let ((rest (cdr l))) (cond (nil? l) 'odd true (even-length? rest))))",
+            )?;
+
+            let result = eval_helper(mem, t, "(function-code even-length?)")?;
+            assert!(format!("{}", result).contains("TailCall"));
+
+            // deep enough that a call frame and register window per call would be very costly,
+            // unless checking the whole heap before every instruction
+            let length = if cfg!(feature = "gc-stress") {
+                101
+            } else {
+                20001
+            };
+            let mut list = String::from("'(");
+            for _ in 0..length {
+                list.push_str("a ");
+            }
+            list.push_str(")");
+
+            let result = eval_helper(mem, t, &format!("(even-length? {})", list))?;
+            assert!(result == mem.lookup_sym("odd"));
+
+            // the result of a tail call to a partial application and a native function
+            eval_helper(mem, t, "(def pick (a b) b)")?;
+            eval_helper(mem, t, "(def pick-partial (a) ((pick a) 'second))")?;
+            let result = eval_helper(mem, t, "(pick-partial 'first)")?;
+            assert!(result == mem.lookup_sym("second"));
+
+            eval_helper(mem, t, "(def sorted (l) (sort l))")?;
+            let result = eval_helper(mem, t, "(sorted '(c a b))")?;
+            assert!(format!("{}", result) == "(a b c)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_ports() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // output is captured in a string port by parameterizing current-output-port
+            eval_helper(mem, t, "(define port (open-output-string))")?;
+            eval_helper(
+                mem,
+                t,
+                "(parameterize ((current-output-port port)) (display 'hello) (newline) (write '(a b)))",
+            )?;
+            let result = eval_helper(mem, t, "(get-output-string port)")?;
+            assert!(result.as_str() == Some("hello\n(a b)"));
+
+            // display writes text without quotes, write with them
+            eval_helper(mem, t, "(define copy (open-output-string))")?;
+            eval_helper(
+                mem,
+                t,
+                "(parameterize ((current-output-port copy)) (display (get-output-string port)) (write (get-output-string port)))",
+            )?;
+            let result = eval_helper(mem, t, "(get-output-string copy)")?;
+            assert!(result.as_str() == Some("hello\n(a b)\"hello\\n(a b)\""));
+
+            // read-line reads from the console input stream by default and from string ports
+            t.set_input(Box::new(std::io::Cursor::new("first\r\nsecond")));
+            let result = eval_helper(mem, t, "(read-line)")?;
+            assert!(format!("{}", result) == "\"first\"");
+            let result = eval_helper(
+                mem,
+                t,
+                "(parameterize ((current-input-port (open-input-string (get-output-string port)))) (cons (read-line) (read-line)))",
+            )?;
+            assert!(format!("{}", result) == "(\"hello\" . \"(a b)\")");
+            let result = eval_helper(mem, t, "(read-line)")?;
+            assert!(format!("{}", result) == "\"second\"");
+            let result = eval_helper(mem, t, "(read-line)")?;
+            assert!(result.is_nil());
+
+            // output builtins check that the current output port is a port
+            assert!(eval_helper(
+                mem,
+                t,
+                "(parameterize ((current-output-port 'x)) (newline))"
+            )
+            .is_err());
+            assert!(eval_helper(mem, t, "(get-output-string (current-input-port))").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_integer_arithmetic() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(define two (arity (lambda (a b) a)))")?;
+            eval_helper(mem, t, "(define three (arity (lambda (a b c) a)))")?;
+            eval_helper(mem, t, "(define seven (arity (lambda (a b c d e f g) a)))")?;
+
+            let check = |source: &str, expected: isize| -> Result<(), RuntimeError> {
+                let result = eval_helper(mem, t, source)?;
+                assert!(
+                    result.as_int() == Some(expected),
+                    "{} gave {}",
+                    source,
+                    result
+                );
+                Ok(())
+            };
+
+            // variadic application folds left to right
+            check("(+ two three seven)", 12)?;
+            check("(- seven two three)", 2)?;
+            check("(* two three seven)", 42)?;
+            check("(/ (* seven three) two three)", 3)?;
+
+            // identity values with no arguments, and a single argument applied to them
+            check("(+)", 0)?;
+            check("(*)", 1)?;
+            check("(- three)", -3)?;
+            check("(+ seven)", 7)?;
+
+            // division rounds toward zero, mod takes the sign of the divisor
+            check("(/ (- seven) two)", -3)?;
+            check("(mod seven two)", 1)?;
+            check("(mod (- seven) two)", 1)?;
+            check("(mod seven (- two))", -1)?;
+
+            // arguments are evaluated expressions, including function calls
+            eval_helper(mem, t, "(def square (n) (* n n))")?;
+            check("(+ (square three) (square (- two)))", 13)?;
+            check("(square (square (square (square seven))))", 33232930569601)?;
+
+            // overflowing results become heap allocated integers
+            let result = eval_helper(mem, t, "(square (square (square (square (square seven)))))")?;
+            assert!(format!("{}", result) == "1104427674243920646305299201");
+            assert!(eval_helper(mem, t, "(/ seven (- two two))").is_err());
+            assert!(eval_helper(mem, t, "(mod seven (- two two))").is_err());
+            assert!(eval_helper(mem, t, "(+ two 'x)").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_with_limit() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(
+                mem,
+                t,
+                "(define ten (* (arity (lambda (a b) a)) (arity (lambda (a b c d e) a))))",
+            )?;
+            eval_helper(mem, t, "(define thousand (* ten ten ten))")?;
+            eval_helper(mem, t, "(def loop () (loop))")?;
+            eval_helper(
+                mem,
+                t,
+                "(def count-down (n) (cond (is? n (-)) 'done true (count-down (- n (*)))))",
+            )?;
+
+            // a body that completes within the budget returns its value
+            let result = eval_helper(mem, t, "(with-limit thousand 'a (count-down ten))")?;
+            assert!(result == mem.lookup_sym("done"));
+
+            // a body that runs out of budget, including in nested calls, is abandoned
+            let result = eval_helper(mem, t, "(with-limit thousand (loop))")?;
+            assert!(result == mem.lookup_sym("limit-exceeded"));
+            let result = eval_helper(mem, t, "(with-limit ten (count-down thousand))")?;
+            assert!(result == mem.lookup_sym("limit-exceeded"));
+
+            // evaluation continues after the abandoned expression
+            let result = eval_helper(mem, t, "(cons (with-limit ten (loop)) (count-down ten))")?;
+            assert!(format!("{}", result) == "(limit-exceeded . done)");
+
+            // an inner limit cannot extend the outer budget
+            let result = eval_helper(
+                mem,
+                t,
+                "(with-limit thousand (cons (with-limit ten (loop)) (with-limit (* thousand thousand) (loop))))",
+            )?;
+            assert!(result == mem.lookup_sym("limit-exceeded"));
+            let result = eval_helper(
+                mem,
+                t,
+                "(with-limit thousand (cons (with-limit ten (loop)) 'after))",
+            )?;
+            assert!(format!("{}", result) == "(limit-exceeded . after)");
+
+            // parameters rebound inside an abandoned body are restored
+            eval_helper(mem, t, "(define depth (make-parameter 'outside))")?;
+            let result = eval_helper(
+                mem,
+                t,
+                "(cons (with-limit ten (parameterize ((depth 'inside)) (loop))) (depth))",
+            )?;
+            assert!(format!("{}", result) == "(limit-exceeded . outside)");
+
+            assert!(eval_helper(mem, t, "(with-limit 'x (loop))").is_err());
+
+            // no limit is left behind by an error
+            assert!(eval_helper(mem, t, "(with-limit thousand (car 'x))").is_err());
+            let result = eval_helper(mem, t, "(count-down thousand)")?;
+            assert!(result == mem.lookup_sym("done"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_numeric_comparison() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(define two (arity (lambda (a b) a)))")?;
+            eval_helper(mem, t, "(define three (arity (lambda (a b c) a)))")?;
+
+            let check = |source: &str, expected: bool| -> Result<(), RuntimeError> {
+                let result = eval_helper(mem, t, source)?;
+                let expected = if expected {
+                    mem.lookup_sym("true")
+                } else {
+                    mem.nil()
+                };
+                assert!(result == expected, "{} gave {}", source, result);
+                Ok(())
+            };
+
+            check("(< two three)", true)?;
+            check("(< three two)", false)?;
+            check("(< two two)", false)?;
+            check("(> three two)", true)?;
+            check("(> (- three) two)", false)?;
+            check("(<= two two)", true)?;
+            check("(<= three two)", false)?;
+            check("(>= two two)", true)?;
+            check("(>= (- three) (- two))", false)?;
+            check("(= (+ two two) (- (* three two) two))", true)?;
+            check("(= two three)", false)?;
+
+            // results compose with cond
+            eval_helper(
+                mem,
+                t,
+                "(def fib (n) (cond (< n two) n true (+ (fib (- n (*))) (fib (- n two)))))",
+            )?;
+            let result = eval_helper(mem, t, "(fib (* three three two))")?;
+            assert!(result.as_int() == Some(2584));
+
+            assert!(eval_helper(mem, t, "(< two 'x)").is_err());
+            assert!(eval_helper(mem, t, "(= 'x 'x)").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_if() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, "(if true 'yes 'no)")?;
+            assert!(result == mem.lookup_sym("yes"));
+
+            let result = eval_helper(mem, t, "(if nil 'yes 'no)")?;
+            assert!(result == mem.lookup_sym("no"));
+
+            // the else branch defaults to nil
+            let result = eval_helper(mem, t, "(if (nil? 'x) 'yes)")?;
+            assert!(result.is_nil());
+
+            // only the chosen branch is evaluated
+            let result = eval_helper(mem, t, "(if (atom? 'x) 'atom (car 'x))")?;
+            assert!(result == mem.lookup_sym("atom"));
+
+            // branches in tail position of a function are tail calls
+            eval_helper(
+                mem,
+                t,
+                "(def last-of (l) (if (nil? (cdr l)) (car l) (last-of (cdr l))))",
+            )?;
+            let result = eval_helper(mem, t, "(last-of '(a b c))")?;
+            assert!(result == mem.lookup_sym("c"));
+
+            // variables as branches
+            let result = eval_helper(mem, t, "((lambda (a b) (if (is? a b) a b)) 'p 'q)")?;
+            assert!(result == mem.lookup_sym("q"));
+
+            assert!(eval_helper(mem, t, "(if true)").is_err());
+            assert!(eval_helper(mem, t, "(if true 'a 'b 'c)").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_and_or() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let check = |source: &str, expected: &str| -> Result<(), RuntimeError> {
+                let result = eval_helper(mem, t, source)?;
+                assert!(
+                    format!("{}", result) == expected,
+                    "{} gave {}",
+                    source,
+                    result
+                );
+                Ok(())
+            };
+
+            check("(and)", "true")?;
+            check("(or)", "nil")?;
+            check("(and true true)", "true")?;
+            check("(and true nil)", "nil")?;
+            check("(and true 'last)", "last")?;
+            check("(or nil true)", "true")?;
+            check("(or nil 'last)", "last")?;
+            check("(or (nil? 'x) (atom? 'x))", "true")?;
+
+            // evaluation stops as soon as the result is known
+            check("(and nil (car 'x))", "nil")?;
+            check("(or true (car 'x))", "true")?;
+            check("(and 'go nil (car 'x))", "nil")?;
+            check("(or 'stop (car 'x))", "stop")?;
+
+            // variables and nested forms
+            check("((lambda (a b) (or a b)) nil 'b)", "b")?;
+            check("((lambda (a b) (and (or a b) (or b a))) true nil)", "true")?;
+
+            // the last expression is in tail position
+            eval_helper(
+                mem,
+                t,
+                "(def all-atoms? (l) (or (nil? l) (and (atom? (car l)) (all-atoms? (cdr l)))))",
+            )?;
+            check("(all-atoms? '(a b c))", "true")?;
+            check("(all-atoms? '(a (b) c))", "nil")?;
+
+            assert!(eval_helper(mem, t, "(and true (car 'x))").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_tail_call_annotations_and_warnings() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+            t.set_recursion_warning_depth(20);
+
+            eval_helper(
+                mem,
+                t,
+                "(def count (l) (if (nil? l) nil (cons 'x (count (cdr l)))))",
+            )?;
+            eval_helper(
+                mem,
+                t,
+                "(def last-of (l) (if (nil? (cdr l)) (car l) (last-of (cdr l))))",
+            )?;
+            eval_helper(
+                mem,
+                t,
+                "(def make-list (n) (if (= n (-)) nil (cons 'x (make-list (- n (*))))))",
+            )?;
+
+            // the disassembly marks each call as tail or non-tail
+            let listing = format!("{}", eval_helper(mem, t, "(function-code count)")?);
+            assert!(listing.contains("; non-tail call"));
+            assert!(!listing.contains("; tail call"));
+            let listing = format!("{}", eval_helper(mem, t, "(function-code last-of)")?);
+            assert!(listing.contains("; tail call"));
+
+            // shallow recursion raises no warning
+            eval_helper(mem, t, "(count '(a b c))")?;
+            assert!(t.take_warnings().is_empty());
+
+            // deep non-tail self recursion raises one warning
+            eval_helper(mem, t, "(define long (make-list (* (arity (lambda (a b c d e) a)) (arity (lambda (a b c d e f) a)))))")?;
+            let warnings = t.take_warnings();
+            assert!(warnings.len() == 1);
+            assert!(warnings[0].message().contains("#<fn make-list/1>"));
+            assert!(t.take_warnings().is_empty());
+
+            // deep tail recursion raises none
+            let result = eval_helper(mem, t, "(last-of long)")?;
+            assert!(result == mem.lookup_sym("x"));
+            assert!(t.take_warnings().is_empty());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_reentrant_eval() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(def descending (a b) (compare b a))")?;
+            eval_helper(
+                mem,
+                t,
+                "(def drain (q) (cond (is? (peek q) nil) nil true (cons (pop-min! q) (drain q))))",
+            )?;
+
+            // a lambda comparator is called back from the push! and pop-min! builtins
+            let result = eval_helper(
+                mem,
+                t,
+                "(drain (push! (push! (push! (priority-queue-by descending) 'b) 'c) 'a))",
+            )?;
+            assert!(format!("{}", result) == "(c b a)");
+
+            // closures and partial applications are called the same way as from bytecode
+            eval_helper(mem, t, "(def by (f) (lambda (a b) (f a b)))")?;
+            let result = eval_helper(
+                mem,
+                t,
+                "(drain (push! (push! (priority-queue-by (by descending)) 'a) 'b))",
+            )?;
+            assert!(format!("{}", result) == "(b a)");
+
+            // a callback can itself call builtins that call back, nesting evaluations
+            eval_helper(
+                mem,
+                t,
+                "(def nested (a b) (pop-min! (push! (push! (priority-queue-by descending) (compare a b)) (compare a b))))",
+            )?;
+            let result = eval_helper(
+                mem,
+                t,
+                "(drain (push! (push! (push! (priority-queue-by nested) 'b) 'a) 'c))",
+            )?;
+            assert!(format!("{}", result) == "(a b c)");
+
+            // an error in a callback propagates out through the builtin, unwinding the outer
+            // evaluation too, and leaves the thread and queue usable
+            eval_helper(mem, t, "(define p (make-parameter 'outside))")?;
+            eval_helper(
+                mem,
+                t,
+                "(define q (push! (priority-queue-by (lambda (a b) (parameterize ((p 'inside)) (car a)))) 'x))",
+            )?;
+            eval_helper(mem, t, "(def f (q) (cons (p) (push! q 'y)))")?;
+            assert!(eval_helper(mem, t, "(parameterize ((p 'outer)) (f q))").is_err());
+            assert!(eval_helper(mem, t, "(p)")? == mem.lookup_sym("outside"));
+            assert!(format!("{}", eval_helper(mem, t, "(peek q)")?) == "x");
+            let result = eval_helper(mem, t, "(cons (f (priority-queue)) 'after)")?;
+            assert!(format!("{}", result) == "((outside . PriorityQueue[y]) . after)");
+
+            // instruction limits apply across the boundary, whichever side they began on
+            eval_helper(
+                mem,
+                t,
+                "(define ten (* (arity (lambda (a b) a)) (arity (lambda (a b c d e) a))))",
+            )?;
+            eval_helper(mem, t, "(def loop () (loop))")?;
+            eval_helper(
+                mem,
+                t,
+                "(define q (push! (priority-queue-by (lambda (a b) (loop))) 'x))",
+            )?;
+            let result = eval_helper(mem, t, "(cons (with-limit ten (push! q 'y)) 'after)")?;
+            assert!(format!("{}", result) == "(limit-exceeded . after)");
+
+            eval_helper(
+                mem,
+                t,
+                "(def limited (a b) (cond (is? (with-limit ten (loop)) 'limit-exceeded) (compare a b) true nil))",
+            )?;
+            let result = eval_helper(
+                mem,
+                t,
+                "(drain (push! (push! (priority-queue-by limited) 'b) 'a))",
+            )?;
+            assert!(format!("{}", result) == "(a b)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_begin() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // every expression is evaluated, in order, and the last is the result
+            eval_helper(mem, t, "(define log (open-output-string))")?;
+            let result = eval_helper(
+                mem,
+                t,
+                "(parameterize ((current-output-port log)) (begin (display 'a) (display 'b) 'c))",
+            )?;
+            assert!(result == mem.lookup_sym("c"));
+            let output = eval_helper(mem, t, "(get-output-string log)")?;
+            assert!(format!("{}", output) == "\"ab\"");
+
+            assert!(eval_helper(mem, t, "(begin)")?.is_nil());
+            assert!(eval_helper(mem, t, "(begin 'x)")? == mem.lookup_sym("x"));
+
+            // a variable as the last expression
+            eval_helper(mem, t, "(def second (a b) (begin a b))")?;
+            let result = eval_helper(mem, t, "(cons (second 'x 'y) (second 'z 'w))")?;
+            assert!(format!("{}", result) == "(y . w)");
+
+            // the last expression is in tail position
+            eval_helper(
+                mem,
+                t,
+                "(def count-down (n) (begin n (cond (is? n (-)) 'done true (count-down (- n (*))))))",
+            )?;
+            let code = eval_helper(mem, t, "(function-code count-down)")?;
+            assert!(format!("{}", code).contains("tail call, reuses frame"));
+            let result = eval_helper(mem, t, "(count-down (arity (lambda (a b c) a)))")?;
+            assert!(result == mem.lookup_sym("done"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_integer_overflow_modes() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(define two (arity (lambda (a b) a)))")?;
+            eval_helper(
+                mem,
+                t,
+                "(define ten (* two (arity (lambda (a b c d e) a))))",
+            )?;
+            eval_helper(
+                mem,
+                t,
+                "(def pow2 (n) (cond (is? n (-)) (*) true (* two (pow2 (- n (*))))))",
+            )?;
+            eval_helper(mem, t, "(define sixty (* ten (+ two two two)))")?;
+
+            // the largest power of two that fits inline is the same in every mode
+            let largest = eval_helper(mem, t, "(pow2 sixty)")?;
+            assert!(format!("{}", largest) == "1152921504606846976");
+
+            // the default is promotion to a heap allocated integer
+            let result = eval_helper(mem, t, "(* two (pow2 sixty))")?;
+            assert!(format!("{}", result) == "2305843009213693952");
+            let result = eval_helper(mem, t, "(cons (is? (* two (pow2 sixty)) (+ (pow2 sixty) (pow2 sixty))) (is? (* two (pow2 sixty)) (pow2 sixty)))")?;
+            assert!(format!("{}", result) == "(true)");
+
+            t.set_overflow_mode(OverflowMode::Error);
+            let result = eval_helper(mem, t, "(* two (pow2 sixty))");
+            match result {
+                Err(error) => assert!(format!("{}", error).contains("Integer overflow")),
+                Ok(_) => panic!("Expected an integer overflow error"),
+            }
+
+            t.set_overflow_mode(OverflowMode::Wrap);
+            let result = eval_helper(mem, t, "(* two (pow2 sixty))")?;
+            assert!(format!("{}", result) == "-2305843009213693952");
+            let result = eval_helper(mem, t, "(* (pow2 sixty) (pow2 sixty))")?;
+            assert!(result.as_int() == Some(0));
+
+            t.set_overflow_mode(OverflowMode::Promote);
+            let result = eval_helper(mem, t, "(* (pow2 sixty) (pow2 sixty))")?;
+            assert!(format!("{}", result) == "1329227995784915872903807060280344576");
+            let result = eval_helper(
+                mem,
+                t,
+                "(cons (> (pow2 (+ sixty two)) (pow2 sixty)) (compare (- (pow2 (+ sixty two))) (-)))",
+            )?;
+            assert!(format!("{}", result) == "(true . -1)");
+
+            // results that fit inline are stored inline
+            let result = eval_helper(mem, t, "(- (pow2 (+ sixty two)) (pow2 (+ sixty two)))")?;
+            assert!(result.as_int() == Some(0));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_defmacro() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // a macro receives its arguments unevaluated and returns the code to compile
+            let result = eval_helper(
+                mem,
+                t,
+                "(defmacro unless (test expr) (cons 'if (cons test (cons nil (cons expr nil)))))",
+            )?;
+            assert!(result == mem.lookup_sym("unless"));
+
+            let result = eval_helper(mem, t, "(unless nil 'yes)")?;
+            assert!(result == mem.lookup_sym("yes"));
+            assert!(eval_helper(mem, t, "(unless true (car 'never-evaluated))")?.is_nil());
+
+            // expansions are compiled in place, including in functions and tail position
+            eval_helper(
+                mem,
+                t,
+                "(def count-down (n) (unless (is? n (-)) (count-down (- n (*)))))",
+            )?;
+            assert!(eval_helper(mem, t, "(count-down (arity (lambda (a b c) a)))")?.is_nil());
+            let code = eval_helper(mem, t, "(function-code count-down)")?;
+            assert!(format!("{}", code).contains("tail call, reuses frame"));
+
+            // macros can expand to applications of other macros, and take precedence over special
+            // forms
+            eval_helper(
+                mem,
+                t,
+                "(defmacro when (test expr) (cons 'unless (cons (cons 'nil? (cons test nil)) (cons expr nil))))",
+            )?;
+            assert!(eval_helper(mem, t, "(when true 'yes)")? == mem.lookup_sym("yes"));
+            eval_helper(mem, t, "(defmacro car (x) ''shadowed)")?;
+            assert!(eval_helper(mem, t, "(car '(a b))")? == mem.lookup_sym("shadowed"));
+
+            // an error while expanding is a compile error and the thread remains usable
+            eval_helper(mem, t, "(defmacro broken (x) (cdr x))")?;
+            assert!(eval_helper(mem, t, "(broken y)").is_err());
+            assert!(
+                eval_helper(mem, t, "(when true 'still-works)")? == mem.lookup_sym("still-works")
+            );
+
+            // without a thread there are no macros
+            assert!(compile(mem, parse(mem, "(defmacro m (x) x)")?).is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_rest_parameters() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // arguments beyond the required parameters are collected into a list
+            eval_helper(mem, t, "(def f (a . rest) (cons a rest))")?;
+            assert!(format!("{}", eval_helper(mem, t, "(f 'x)")?) == "(x)");
+            assert!(format!("{}", eval_helper(mem, t, "(f 'x 'y 'z)")?) == "(x y z)");
+            assert!(
+                format!("{}", eval_helper(mem, t, "(cons f (arity f))")?) == "(#<fn f/1+> . 1)"
+            );
+
+            // a lone symbol takes all arguments
+            eval_helper(mem, t, "(def all args args)")?;
+            assert!(eval_helper(mem, t, "(all)")?.is_nil());
+            assert!(format!("{}", eval_helper(mem, t, "((lambda args args) 'a 'b)")?) == "(a b)");
+
+            // too few arguments still make a partial application, which collects the rest when
+            // it is called
+            assert!(format!("{}", eval_helper(mem, t, "((f) 'x 'y)")?) == "(x y)");
+
+            // closures, and tail calls passing extra arguments
+            eval_helper(mem, t, "(def make (x) (lambda (a . rest) (cons x rest)))")?;
+            assert!(format!("{}", eval_helper(mem, t, "((make 'p) 'a 'b 'c)")?) == "(p b c)");
+            eval_helper(
+                mem,
+                t,
+                "(def count-down (n . ignored) (cond (is? n (-)) ignored true (count-down (- n (*)) 'extra 'args)))",
+            )?;
+            let result = eval_helper(mem, t, "(count-down (arity (lambda (a b c) a)))")?;
+            assert!(format!("{}", result) == "(extra args)");
+
+            // macros can take rest parameters too
+            eval_helper(mem, t, "(defmacro progn body (cons 'begin body))")?;
+            assert!(eval_helper(mem, t, "(progn 'a 'b 'c)")? == mem.lookup_sym("c"));
+
+            assert!(eval_helper(mem, t, "(lambda (a . \"b\") a)").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_numeric_tower() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(define one (*))")?;
+            eval_helper(mem, t, "(define two (arity (lambda (a b) a)))")?;
+            eval_helper(mem, t, "(define half (/ (exact->inexact one) two))")?;
+
+            let result = eval_helper(mem, t, "(cons (exact->inexact two) half)")?;
+            assert!(format!("{}", result) == "(2.0 . 0.5)");
+
+            // integer division truncates, exact division of a rational does not
+            let result = eval_helper(mem, t, "(cons (/ one two) (inexact->exact half))")?;
+            assert!(format!("{}", result) == "(0 . 1/2)");
+            let result = eval_helper(mem, t, "(/ (inexact->exact half) (+ one two))")?;
+            assert!(format!("{}", result) == "1/6");
+
+            // an exact result with a denominator of one is an integer
+            let result = eval_helper(mem, t, "(* (inexact->exact half) two)")?;
+            assert!(result.as_int() == Some(1));
+
+            // anything with a float gives a float
+            let result = eval_helper(mem, t, "(+ (inexact->exact half) half)")?;
+            assert!(format!("{}", result) == "1.0");
+            let result = eval_helper(mem, t, "(cons (exact? (+ one half)) (inexact? half))")?;
+            assert!(format!("{}", result) == "(nil . true)");
+
+            // comparisons coerce the same way
+            let result = eval_helper(
+                mem,
+                t,
+                "(cons (= one (exact->inexact one)) (cons (< (inexact->exact half) one) (> half one)))",
+            )?;
+            assert!(format!("{}", result) == "(true true)");
+            let result = eval_helper(mem, t, "(compare one (exact->inexact one))")?;
+            assert!(result.as_int() == Some(-1));
+
+            // float division by zero follows IEEE 754, exact division by zero is an error
+            let result = eval_helper(mem, t, "(/ one (exact->inexact (-)))")?;
+            assert!(format!("{}", result) == "+inf.0");
+            assert!(eval_helper(mem, t, "(/ (inexact->exact half) (-))").is_err());
+            assert!(eval_helper(mem, t, "(inexact->exact (/ one (exact->inexact (-))))").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_apply() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(def triple (a b c) (cons a (cons b c)))")?;
+
+            // a list of arguments spread after any leading arguments
+            let result = eval_helper(mem, t, "(apply triple '(x y z))")?;
+            assert!(format!("{}", result) == "(x y . z)");
+            let result = eval_helper(mem, t, "(apply triple 'x '(y z))")?;
+            assert!(format!("{}", result) == "(x y . z)");
+
+            // too few arguments give a partial application, rest parameters collect the excess
+            let result = eval_helper(mem, t, "((apply triple '(x y)) 'z)")?;
+            assert!(format!("{}", result) == "(x y . z)");
+            eval_helper(mem, t, "(def all (a . rest) (cons a rest))")?;
+            let result = eval_helper(mem, t, "(apply all 'a 'b '(c d))")?;
+            assert!(format!("{}", result) == "(a b c d)");
+
+            // partial applications and native functions can be applied too
+            let result = eval_helper(mem, t, "(apply (triple 'x) '(y z))")?;
+            assert!(format!("{}", result) == "(x y . z)");
+            let result = eval_helper(mem, t, "(apply arity (cons triple nil))")?;
+            assert!(result.as_int() == Some(3));
+
+            // apply itself as a value
+            eval_helper(mem, t, "(def call-with (f g args) (f g args))")?;
+            let result = eval_helper(mem, t, "(call-with apply triple '(x y z))")?;
+            assert!(format!("{}", result) == "(x y . z)");
+
+            assert!(eval_helper(mem, t, "(apply triple 'x)").is_err());
+            assert!(eval_helper(mem, t, "(apply triple)").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_decimal() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(define price 19.99m)")?;
+            eval_helper(mem, t, "(define two (arity (lambda (a b) a)))")?;
+            eval_helper(mem, t, "(define three (arity (lambda (a b c) a)))")?;
+
+            // exact addition and multiplication keep the scale
+            let result = eval_helper(mem, t, "(+ price 0.01m)")?;
+            assert!(format!("{}", result) == "20.00m");
+            let result = eval_helper(mem, t, "(* price three)")?;
+            assert!(format!("{}", result) == "59.97m");
+            let result = eval_helper(mem, t, "(- (+ 0.1m 0.2m) 0.3m)")?;
+            assert!(format!("{}", result) == "0.0m");
+            let result = eval_helper(mem, t, "(* 1.5m 0.25m)")?;
+            assert!(format!("{}", result) == "0.375m");
+
+            // division is exact and gives a rational, which can be rounded back
+            let result = eval_helper(mem, t, "(/ price three)")?;
+            assert!(format!("{}", result) == "1999/300");
+            let result = eval_helper(mem, t, "(decimal-round (/ price three) two)")?;
+            assert!(format!("{}", result) == "6.66m");
+            let result = eval_helper(
+                mem,
+                t,
+                "(cons (decimal-round 0.125m two) (decimal-round 0.135m two))",
+            )?;
+            assert!(format!("{}", result) == "(0.12m . 0.14m)");
+
+            // comparisons and conversions
+            let result = eval_helper(
+                mem,
+                t,
+                "(cons (= 1.50m 1.5m) (< 0.1m (exact->inexact 0.2m)))",
+            )?;
+            assert!(format!("{}", result) == "(true . true)");
+            let result = eval_helper(mem, t, "(exact->inexact 0.1m)")?;
+            assert!(format!("{}", result) == "0.1");
+            let result = eval_helper(mem, t, "(number->decimal (exact->inexact 0.1m))")?;
+            assert!(format!("{}", result) == "0.1m");
+            let result = eval_helper(mem, t, "(cons (decimal? price) (decimal? three))")?;
+            assert!(format!("{}", result) == "(true)");
+            assert!(eval_helper(mem, t, "(number->decimal (/ 1.0m three))").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_runtime_eval() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, "(eval '(cons 'a 'b))")?;
+            assert!(format!("{}", result) == "(a . b)");
+
+            // code built at runtime is evaluated in the global environment
+            eval_helper(mem, t, "(define item 'x)")?;
+            eval_helper(mem, t, "(def make-call (f arg) (cons f (cons arg nil)))")?;
+            let result = eval_helper(mem, t, "(eval (make-call 'atom? 'item))")?;
+            assert!(format!("{}", result) == "true");
+            eval_helper(mem, t, "(eval '(def twice (x) (cons x x)))")?;
+            let result = eval_helper(mem, t, "(twice (eval ''y))")?;
+            assert!(format!("{}", result) == "(y . y)");
+
+            // the caller's local variables are not in scope
+            eval_helper(mem, t, "(def local (item) (eval 'item))")?;
+            let result = eval_helper(mem, t, "(local 'z)")?;
+            assert!(format!("{}", result) == "x");
+
+            // macros are expanded and errors propagate to the caller
+            eval_helper(
+                mem,
+                t,
+                "(defmacro swap (a b) (cons 'cons (cons b (cons a nil))))",
+            )?;
+            let result = eval_helper(mem, t, "(eval '(swap 'p 'q))")?;
+            assert!(format!("{}", result) == "(q . p)");
+            assert!(eval_helper(mem, t, "(eval '(car 'not-a-pair))").is_err());
+            let result = eval_helper(mem, t, "(eval (eval ''(twice 'w)))")?;
+            assert!(format!("{}", result) == "(w . w)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_number_text_conversion() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(mem, t, "(define two (arity (lambda (a b) a)))")?;
+            eval_helper(
+                mem,
+                t,
+                "(def round-trip (x) (= x (string->number (number->string x))))",
+            )?;
+            let result = eval_helper(
+                mem,
+                t,
+                "(cons (round-trip (/ 1.0m (+ two (*)))) (cons (round-trip 12.50m) (round-trip (/ (exact->inexact (*)) (+ two (*))))))",
+            )?;
+            assert!(format!("{}", result) == "(true true . true)");
+
+            // the printed form of every kind of number is also read back from source code as an
+            // equal number
+            let numbers = [
+                "(- 40 82)",
+                "(* -2305843009213693952 3)",
+                "(/ -7 two)",
+                "(/ (exact->inexact 1) 3)",
+                "(* 1e300 10.0)",
+                "(exact->inexact two)",
+                "(- 1.50m 3)",
+                "(* -1e300 1e300)",
+            ];
+            for number in numbers.iter() {
+                let printed = format!("{}", eval_helper(mem, t, number)?);
+                let same = eval_helper(mem, t, &format!("(equal? {} '{})", number, printed))?;
+                assert!(
+                    same == mem.lookup_sym("true"),
+                    "{} printed as {}",
+                    number,
+                    printed
+                );
+            }
+
+            let result = eval_helper(mem, t, "(number->string (/ 1.0m (+ two (*))))")?;
+            assert!(format!("{}", result) == "\"1/3\"");
+            let result = eval_helper(
+                mem,
+                t,
+                "(cons (string->number \"-7/2\") (string->number \"seven\"))",
+            )?;
+            assert!(format!("{}", result) == "(-7/2)");
+
+            let result = eval_helper(
+                mem,
+                t,
+                "(format-number (* 1234567.891m (*)) (cons (cons 'precision two) (cons (cons 'separator \",\") nil)))",
+            )?;
+            assert!(format!("{}", result) == "\"1,234,567.89\"");
+            assert!(eval_helper(
+                mem,
+                t,
+                "(format-number two (cons (cons 'colour \"red\") nil))"
+            )
+            .is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_letrec() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // mutually recursive local functions
+            let code = "(letrec ((even-length? (lambda (l) (if (nil? l) 'true (odd-length? (cdr l)))))
+                                 (odd-length? (lambda (l) (if (nil? l) nil (even-length? (cdr l))))))
+                          (cons (even-length? '(a b c d)) (odd-length? '(a b c d))))";
+            let result = eval_helper(mem, t, code)?;
+            assert!(format!("{}", result) == "(true)");
+
+            // inside a function, closing over its parameters
+            eval_helper(
+                mem,
+                t,
+                "(def last-of (items)
+                   (letrec ((walk (lambda (l) (if (nil? (cdr l)) (car l) (walk (cdr l))))))
+                     (walk items)))",
+            )?;
+            let result = eval_helper(mem, t, "(last-of '(x y z))")?;
+            assert!(format!("{}", result) == "z");
+
+            // names are nil until bound, and the body sees the bound values
+            let result = eval_helper(mem, t, "(letrec ((a b) (b 'bee)) (cons a b))")?;
+            assert!(format!("{}", result) == "(nil . bee)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_defun() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, "(defun pair-up (a b) (cons a b))")?;
+            assert!(format!("{}", result) == "#<fn pair-up/2>");
+            let result = eval_helper(mem, t, "(cons (pair-up 'x 'y) (function-name pair-up))")?;
+            assert!(format!("{}", result) == "((x . y) . pair-up)");
+
+            // the name appears in error messages
+            match eval_helper(mem, t, "(pair-up 'x 'y 'z)") {
+                Err(error) => assert!(format!("{}", error).contains("#<fn pair-up/2>")),
+                Ok(_) => panic!("Expected an argument count error"),
+            }
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_while() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // reverse a list by walking it in a loop
+            eval_helper(mem, t, "(define items '(a b c))")?;
+            eval_helper(mem, t, "(define reversed nil)")?;
+            let result = eval_helper(
+                mem,
+                t,
+                "(while (if (nil? items) nil 'true)
+                   (set! reversed (cons (car items) reversed))
+                   (set! items (cdr items)))",
+            )?;
+            assert!(result.is_nil());
+            let result = eval_helper(mem, t, "reversed")?;
+            assert!(format!("{}", result) == "(c b a)");
+
+            // break with a value leaves the innermost loop only
+            let result = eval_helper(
+                mem,
+                t,
+                "(let ((l '(x y z)))
+                   (cons
+                     (while 'true
+                       (while 'true (break 'inner))
+                       (if (is? (car l) 'y) (break (cdr l)))
+                       (set! l (cdr l)))
+                     (while 'true (break))))",
+            )?;
+            assert!(format!("{}", result) == "((z))");
+
+            // closures made in the loop each keep their own binding
+            eval_helper(mem, t, "(define closures nil)")?;
+            eval_helper(mem, t, "(define remaining '(p q))")?;
+            eval_helper(
+                mem,
+                t,
+                "(while 'true
+                   (let ((item (car remaining)))
+                     (set! closures (cons (lambda () item) closures))
+                     (set! remaining (cdr remaining))
+                     (if (nil? remaining) (break))))",
+            )?;
+            let result = eval_helper(mem, t, "(cons ((car closures)) ((car (cdr closures))))")?;
+            assert!(format!("{}", result) == "(q . p)");
+
+            // break outside a loop, or from a nested function, is a compile error
+            assert!(eval_helper(mem, t, "(break)").is_err());
+            assert!(eval_helper(mem, t, "(while 'true ((lambda () (break))))").is_err());
+            assert!(eval_helper(mem, t, "(while 'true (with-limit (*) (break)))").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_call_cc() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // a function that returns normally gives the result
+            let result = eval_helper(mem, t, "(call/cc (lambda (k) 'normal))")?;
+            assert!(result == mem.lookup_sym("normal"));
+
+            // invoking the continuation abandons the rest of the function
+            let result = eval_helper(
+                mem,
+                t,
+                "(cons 'a (call/cc (lambda (k) (cons 'b (k 'escaped)))))",
+            )?;
+            assert!(format!("{}", result) == "(a . escaped)");
+
+            // escape from deep in a non-tail recursion
+            eval_helper(
+                mem,
+                t,
+                "(def find-first (pred l)
+                   (call/cc (lambda (return)
+                     (let ((walk nil))
+                       (set! walk (lambda (l)
+                         (if (nil? l)
+                           nil
+                           (cons (if (pred (car l)) (return (car l)) (car l)) (walk (cdr l))))))
+                       (walk l)))))",
+            )?;
+            let result = eval_helper(mem, t, "(find-first (lambda (x) (is? x 'c)) '(a b c d))")?;
+            assert!(result == mem.lookup_sym("c"));
+            let result = eval_helper(mem, t, "(find-first (lambda (x) (is? x 'z)) '(a b))")?;
+            assert!(format!("{}", result) == "(a b)");
+
+            // escape through a native function, restoring parameters bound on the way
+            eval_helper(mem, t, "(define p (make-parameter 'outer))")?;
+            let result = eval_helper(
+                mem,
+                t,
+                "(let ((call apply))
+                   (cons
+                     (call/cc (lambda (k)
+                       (parameterize ((p 'inner))
+                         (call (lambda (v) (k (cons v (p)))) (cons 'through nil)))))
+                     (p)))",
+            )?;
+            assert!(format!("{}", result) == "((through . inner) . outer)");
+
+            // with no argument the result is nil, and inner continuations end with an escape
+            let result = eval_helper(
+                mem,
+                t,
+                "(call/cc (lambda (outer) (call/cc (lambda (inner) (outer)))))",
+            )?;
+            assert!(result.is_nil());
+
+            // a continuation cannot be used once its call/cc has completed
+            eval_helper(mem, t, "(define saved (call/cc (lambda (k) k)))")?;
+            let result = eval_helper(mem, t, "saved")?;
+            assert!(format!("{}", result) == "#<continuation (ended)>");
+            assert!(eval_helper(mem, t, "(saved 'again)").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_special_form_table() {
+        // (unless <test> <expr>) is (if <test> nil <expr>)
+        fn unless_form<'guard>(
+            mem: &'guard MutatorView,
+            args: TaggedScopedPtr<'guard>,
+        ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+            let (test, expr) = values_from_2_pairs(mem, args)?;
+            let tail = cons(mem, mem.nil(), cons(mem, expr, mem.nil())?)?;
+            cons(mem, mem.lookup_sym("if"), cons(mem, test, tail)?)
+        }
+
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let mut forms = SpecialFormTable::standard();
+            forms.define_rewrite("unless", unless_form);
+            forms.alias("fn", "lambda")?;
+            assert!(forms.alias("function", "no-such-form").is_err());
+            t.set_special_forms(forms.clone());
+
+            let result = eval_helper(mem, t, "(cons (unless nil 'ran) (unless 'true 'ran))")?;
+            assert!(format!("{}", result) == "(ran)");
+            let result = eval_helper(mem, t, "((fn (x) (cons x x)) 'a)")?;
+            assert!(format!("{}", result) == "(a . a)");
+
+            // a restricted dialect compiles other names as function calls
+            forms.restrict(&["quote", "if", "cons", "fn", "unless"]);
+            assert!(!forms.contains("lambda") && forms.contains("fn"));
+            assert!(forms.remove("unless") && !forms.remove("unless"));
+            t.set_special_forms(forms);
+
+            let result = eval_helper(mem, t, "((fn (x) (if x 'yes 'no)) 'true)")?;
+            assert!(result == mem.lookup_sym("yes"));
+            assert!(eval_helper(mem, t, "(define x 'y)").is_err());
+            assert!(eval_helper(mem, t, "((lambda (x) x) 'y)").is_err());
+
+            // with no special forms even quote is a function call
+            let ast = parse(mem, "(quote a)")?;
+            let function = compile_with_forms(mem, &SpecialFormTable::empty(), ast)?;
+            assert!(t.quick_vm_eval(mem, function).is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_text_literals() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // text literals are values in any position
+            eval_helper(mem, t, "(def greet (name) (cons \"hello\" name))")?;
+            let result = eval_helper(mem, t, "(greet \"world\")")?;
+            assert!(format!("{}", result) == "(\"hello\" . \"world\")");
+
+            // equality compares content, where is? compares identity: short texts are shared,
+            // longer ones are not
+            let result = eval_helper(mem, t, "(cons (equal? \"a\" \"a\") (eq \"a\" \"b\"))")?;
+            assert!(format!("{}", result) == "(true)");
+            let result = eval_helper(mem, t, "(is? \"a\" \"a\")")?;
+            assert!(result == mem.lookup_sym("true"));
+            let long = "\"a text that is too long to be shared\"";
+            let result = eval_helper(mem, t, &format!("(is? {} {})", long, long))?;
+            assert!(result.is_nil());
+            let result = eval_helper(mem, t, "(equal? '(x \"y\") (cons 'x (cons \"y\" nil)))")?;
+            assert!(result == mem.lookup_sym("true"));
+
+            // escapes are printed so that the text reads back the same
+            let source = "\"say \\\"hi\\\"\\n\\\\\"";
+            let result = eval_helper(mem, t, source)?;
+            assert!(result.as_str() == Some("say \"hi\"\n\\"));
+            assert!(format!("{}", result) == source);
+            let result = eval_helper(mem, t, &format!("(equal? {} {})", source, result))?;
+            assert!(result == mem.lookup_sym("true"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_with_info() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+            let names = |set: &BTreeSet<String>| set.iter().cloned().collect::<Vec<String>>();
+
+            let ast = parse(
+                mem,
+                "(begin
+                   (define total (cons price qty))
+                   (def scale (x) (cons x rate))
+                   (let ((price 'local)) (set! total price))
+                   (set 'flag (car total))
+                   ((lambda (y) (cons y tax)) 'z))",
+            )?;
+            let (function, access) = super::compile_with_info(mem, &t, ast)?;
+            assert!(names(&access.writes) == ["flag", "scale", "total"]);
+            // cons and car compile to instructions rather than calls to global functions
+            assert!(names(&access.reads) == ["price", "qty", "rate", "tax", "total"]);
+            assert!(!access.dynamic_writes);
+            t.quick_vm_eval(mem, function).unwrap_err();
+
+            // parameters, locals and special forms are not globals, and a computed name is
+            // reported as a dynamic write
+            let ast = parse(mem, "(let ((n 'x)) (set n 'y))")?;
+            let (_, access) = super::compile_with_info(mem, &t, ast)?;
+            assert!(access.reads.is_empty() && access.writes.is_empty());
+            assert!(access.dynamic_writes);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_integer_literals() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, "(quote 42)")?;
+            assert!(result.as_int() == Some(42));
+
+            let result = eval_helper(mem, t, "(cons (+ 40 2) (- -3 4))")?;
+            assert!(format!("{}", result) == "(42 . -7)");
+
+            // an integer too large to store inline is read as a heap integer
+            let result = eval_helper(
+                mem,
+                t,
+                "(list 99999999999999999999999999 (+ 2305843009213693952 -1))",
+            )?;
+            assert!(format!("{}", result) == "(99999999999999999999999999 2305843009213693951)");
+
+            // floats, rationals and decimals have literals of their own
+            let result = eval_helper(mem, t, "(list (+ 2.5 1/2) (* 1/2 2/3) (- 1.50m 1))")?;
+            assert!(format!("{}", result) == "(3.0 1/3 0.50m)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_size_limits() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+            mem.set_size_limits(SizeLimits {
+                text_length: Some(4),
+                vector_length: Some(2),
+                table_entries: Some(2),
+            });
+
+            let size_error = |result: Result<TaggedScopedPtr, RuntimeError>| match result {
+                Err(e) => matches!(e.error_kind(), ErrorKind::SizeLimitExceeded(_)),
+                Ok(_) => false,
+            };
+
+            assert!(eval_helper(mem, t, "\"abcd\"").is_ok());
+            assert!(size_error(eval_helper(mem, t, "\"abcde\"")));
+            assert!(size_error(eval_helper(mem, t, "(number->string 12345)")));
+
+            eval_helper(mem, t, "(define q (queue))")?;
+            eval_helper(mem, t, "(queue-push-back! q 1)")?;
+            eval_helper(mem, t, "(queue-push-front! q 2)")?;
+            assert!(size_error(eval_helper(mem, t, "(queue-push-back! q 3)")));
+            let result = eval_helper(mem, t, "(queue-length q)")?;
+            assert!(result.as_int() == Some(2));
+
+            eval_helper(mem, t, "(define p (priority-queue))")?;
+            eval_helper(mem, t, "(push! p 1)")?;
+            eval_helper(mem, t, "(push! p 2)")?;
+            assert!(size_error(eval_helper(mem, t, "(push! p 3)")));
+
+            // replacing the value of an existing key does not add an entry
+            eval_helper(mem, t, "(define m (sorted-map))")?;
+            eval_helper(mem, t, "(sorted-map-set! m 'a 1)")?;
+            eval_helper(mem, t, "(sorted-map-set! m 'b 2)")?;
+            eval_helper(mem, t, "(sorted-map-set! m 'b 3)")?;
+            assert!(size_error(eval_helper(mem, t, "(sorted-map-set! m 'c 4)")));
+
+            assert!(eval_helper(mem, t, "(list 1 2)").is_ok());
+            assert!(size_error(eval_helper(mem, t, "(list 1 2 3)")));
+            eval_helper(mem, t, "(define l (list 1 2))")?;
+            assert!(size_error(eval_helper(mem, t, "(append l l)")));
+            assert!(size_error(eval_helper(mem, t, "(append '(1) l)")));
+            assert!(size_error(eval_helper(mem, t, "(reverse '(1 2 3))")));
+
+            // globals are the runtime's own table and are not capped, a symbol's properties are
+            eval_helper(mem, t, "(define d 1)")?;
+            eval_helper(mem, t, "(put-prop! 'd 'a 1)")?;
+            eval_helper(mem, t, "(put-prop! 'd 'b 2)")?;
+            eval_helper(mem, t, "(put-prop! 'd 'b 3)")?;
+            assert!(size_error(eval_helper(mem, t, "(put-prop! 'd 'c 4)")));
+
+            mem.set_size_limits(SizeLimits::default());
+            eval_helper(mem, t, "(append l l)")?;
+            eval_helper(mem, t, "(put-prop! 'd 'c 4)")?;
+            eval_helper(mem, t, "(sorted-map-set! m 'c 4)")?;
+            assert!(eval_helper(mem, t, "\"abcdefgh\"").is_ok());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_characters() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, "(cons #\\a (cons #\\space '(#\\newline #\\))))")?;
+            assert!(format!("{}", result) == "(#\\a #\\space #\\newline #\\))");
+            assert!(eval_helper(mem, t, "#\\a")?.as_char() == Some('a'));
+
+            let result = eval_helper(mem, t, "(cons (char->int #\\A) (int->char 955))")?;
+            assert!(format!("{}", result) == "(65 . #\\λ)");
+
+            let result = eval_helper(
+                mem,
+                t,
+                "(cons (char=? #\\a (int->char 97)) (char=? #\\a #\\b))",
+            )?;
+            assert!(format!("{}", result) == "(true)");
+            let result = eval_helper(mem, t, "(cons (is? #\\a #\\a) (compare #\\a #\\b))")?;
+            assert!(format!("{}", result) == "(true . -1)");
+
+            assert!(eval_helper(mem, t, "(int->char 55296)").is_err());
+            assert!(eval_helper(mem, t, "(int->char -1)").is_err());
+            assert!(eval_helper(mem, t, "(char->int 'a)").is_err());
+            assert!(eval_helper(mem, t, "(char=? #\\a \"a\")").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_print_to_stream() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let value = eval_helper(mem, t, "'(a (b \"c\") . #\\d)")?;
+            let mut out = Vec::new();
+            print_to(*value, &mut out)?;
+            assert!(out == format!("{}", value).as_bytes());
+
+            let mut out = Vec::new();
+            debug_to(*value, &mut out)?;
+            assert!(out == format!("{:?}", value).as_bytes());
+
+            let function = compile_with_thread(mem, &t, parse(mem, "(lambda (x) (cons x 'y))")?)?;
+            let code = function.code(mem);
+            let mut out = Vec::new();
+            code.disassemble_to(mem, &mut out)?;
+            assert!(out == code.disassemble(mem).as_bytes());
+            assert!(code.disassemble(mem).contains("Return"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_list_primitives() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let result = eval_helper(mem, t, "(list 1 (list 'a \"b\") (+ 1 2))")?;
+            assert!(format!("{}", result) == "(1 (a \"b\") 3)");
+            assert!(eval_helper(mem, t, "(list)")?.is_nil());
+            let result = eval_helper(mem, t, "((lambda (x y) (list y x)) 1 2)")?;
+            assert!(format!("{}", result) == "(2 1)");
+
+            let result = eval_helper(mem, t, "(list (length '(a b c)) (length nil))")?;
+            assert!(format!("{}", result) == "(3 0)");
+            assert!(eval_helper(mem, t, "(length '(a . b))").is_err());
+
+            eval_helper(mem, t, "(define tail '(c d))")?;
+            let result = eval_helper(mem, t, "(append '(a b) tail)")?;
+            assert!(format!("{}", result) == "(a b c d)");
+            let result = eval_helper(mem, t, "(is? (cdr (cdr (append '(a b) tail))) tail)")?;
+            assert!(result == mem.lookup_sym("true"));
+            let result = eval_helper(mem, t, "(list (append nil '(x)) (append '(x) 'y))")?;
+            assert!(format!("{}", result) == "((x) (x . y))");
+
+            let result = eval_helper(mem, t, "(reverse (list 1 2 3))")?;
+            assert!(format!("{}", result) == "(3 2 1)");
+            assert!(eval_helper(mem, t, "(reverse 'a)").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_truthiness_and_not() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // anything but nil is true
+            let result = eval_helper(
+                mem,
+                t,
+                "(list (if 'x 'yes 'no) (if 0 'yes 'no) (if \"\" 'yes 'no) (if nil 'yes 'no))",
+            )?;
+            assert!(format!("{}", result) == "(yes yes yes no)");
+            let result = eval_helper(mem, t, "(cond nil 'a '(b) 'b true 'c)")?;
+            assert!(result == mem.lookup_sym("b"));
+
+            let result = eval_helper(mem, t, "(list (not nil) (not true) (not 'x) (not (not 1)))")?;
+            assert!(format!("{}", result) == "(true nil nil true)");
+            assert!(eval_helper(mem, t, "(not nil)")? == mem.boolean(true));
+            assert!(eval_helper(mem, t, "(not)").is_err());
+
+            // predicates return the canonical booleans
+            assert!(eval_helper(mem, t, "(< 1 2)")? == mem.boolean(true));
+            assert!(eval_helper(mem, t, "(nil? 'a)")? == mem.boolean(false));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_programs() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let source = "(def square (x) (* x x))
+                          (define base 3)
+                          (set! base (+ base 1))
+                          (square base)";
+            let function = compile_program_with_thread(mem, &t, parse_program(mem, source)?)?;
+            assert!(t.quick_vm_eval(mem, function)?.as_int() == Some(16));
+            assert!(eval_helper(mem, t, "(square 5)")?.as_int() == Some(25));
+
+            // macros defined by earlier forms expand in later ones
+            let source = "(defmacro twice (e) (list 'begin e e))
+                          (define n 0)
+                          (twice (set! n (+ n 1)))
+                          n";
+            let function = compile_program_with_thread(mem, &t, parse_program(mem, source)?)?;
+            assert!(t.quick_vm_eval(mem, function)?.as_int() == Some(2));
+
+            // without a thread, and with no forms at all
+            let function = compile_program(mem, parse_program(mem, "'a 'b")?)?;
+            assert!(t.quick_vm_eval(mem, function)? == mem.lookup_sym("b"));
+            let function = compile_program(mem, parse_program(mem, "")?)?;
+            assert!(t.quick_vm_eval(mem, function)?.is_nil());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_assert() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            assert!(eval_helper(mem, t, "(assert (< 1 2))")? == mem.boolean(true));
+            let result = eval_helper(mem, t, "((lambda (x) (assert x)) 'ok)")?;
+            assert!(result == mem.lookup_sym("ok"));
+            assert!(eval_helper(mem, t, "(assert)").is_err());
+
+            let error = eval_helper(mem, t, "(assert (< 2 1))").unwrap_err();
+            assert!(*error.error_kind() == ErrorKind::AssertionFailed(String::from("(< 2 1)")));
+            assert!(error.error_pos() == Some(spos(1, 8)));
+            assert!(format!("{}", error) == "Assertion failed: (< 2 1)");
+
+            // the failure points at the expression within the enclosing code
+            let source = "(def check (n)\n  (assert (nil? n)))";
+            eval_helper(mem, t, source)?;
+            let error = eval_helper(mem, t, "(check 'x)").unwrap_err();
+            assert!(error.error_pos() == Some(spos(2, 10)));
+            let rendered = Diagnostic::from(&error).render(source, false);
+            assert!(rendered.contains("2 |   (assert (nil? n)))\n  |           ^\n"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_keywords() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // keywords evaluate to themselves, quoted or not
+            let result = eval_helper(mem, t, ":foo")?;
+            assert!(result == mem.lookup_keyword("foo"));
+            assert!(format!("{}", result) == ":foo");
+            let result = eval_helper(mem, t, "(list :a ':b '(:c 1))")?;
+            assert!(format!("{}", result) == "(:a :b (:c 1))");
+
+            // a keyword is not the symbol of the same name
+            let result = eval_helper(mem, t, "(cons (is? :foo :foo) (is? :foo 'foo))")?;
+            assert!(format!("{}", result) == "(true)");
+            let result = eval_helper(mem, t, "(is? :if 'if)")?;
+            assert!(result.is_nil());
+
+            // keywords are not variables
+            eval_helper(mem, t, "(def key (k) (cons k :key))")?;
+            let result = eval_helper(mem, t, "(key :x)")?;
+            assert!(format!("{}", result) == "(:x . :key)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_define_native() {
+        fn double<'guard>(
+            mem: &'guard MutatorView,
+            args: &[TaggedScopedPtr<'guard>],
+        ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+            match args[0].as_int() {
+                Some(n) => Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(n * 2))),
+                None => Err(err_eval("double expects an integer")),
+            }
+        }
+
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+            t.define_native(mem, "double", 1, double)?;
+
+            // a host function is called like any other, including as a value
+            let result = eval_helper(mem, t, "(double 21)")?;
+            assert!(result.as_int() == Some(42));
+            let result = eval_helper(mem, t, "((lambda (f) (list (f 1) (f 2))) double)")?;
+            assert!(format!("{}", result) == "(2 4)");
+            let result = eval_helper(mem, t, "double")?;
+            assert!(format!("{}", result) == "#<fn double/1>");
+
+            // its errors and arity are checked like a builtin's
+            assert!(eval_helper(mem, t, "(double 'x)").is_err());
+            assert!(eval_helper(mem, t, "(double 1 2)").is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_symbol_properties() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            // properties are set and read per symbol and key
+            let result = eval_helper(mem, t, "(put-prop! 'colour 'hue 120)")?;
+            assert!(result.as_int() == Some(120));
+            eval_helper(mem, t, "(put-prop! 'colour 'name \"green\")")?;
+            eval_helper(mem, t, "(put-prop! 'colour 'hue 240)")?;
+            let result = eval_helper(
+                mem,
+                t,
+                "(list (get-prop 'colour 'hue) (get-prop 'colour 'name))",
+            )?;
+            assert!(format!("{}", result) == "(240 \"green\")");
+
+            // unset properties are nil and only symbols have properties
+            let result = eval_helper(
+                mem,
+                t,
+                "(list (get-prop 'colour 'x) (get-prop 'other 'hue))",
+            )?;
+            assert!(format!("{}", result) == "(nil nil)");
+            assert!(eval_helper(mem, t, "(put-prop! 1 'hue 0)").is_err());
+
+            // a leading text in a function body of several expressions is its doc property
+            eval_helper(mem, t, "(def square (x) \"Multiply x by itself.\" (* x x))")?;
+            let result = eval_helper(mem, t, "(cons (square 3) (get-prop 'square 'doc))")?;
+            assert!(format!("{}", result) == "(9 . \"Multiply x by itself.\")");
+            eval_helper(mem, t, "(def greeting () \"hello\")")?;
+            let result = eval_helper(mem, t, "(get-prop 'greeting 'doc)")?;
+            assert!(result.is_nil());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_global_warnings() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+            let messages = |t: &Thread| -> Vec<String> {
+                t.take_warnings()
+                    .iter()
+                    .map(|w| String::from(w.message()))
+                    .collect()
+            };
+
+            // a first definition raises no warning, a second one points at both
+            eval_helper(mem, t, "(def f (x) x)")?;
+            assert!(t.take_warnings().is_empty());
+            eval_helper(mem, t, "\n(define f 1)")?;
+            let warnings = t.take_warnings();
+            assert!(warnings.len() == 1);
+            assert!(warnings[0].message() == "Definition of f replaces an existing global");
+            let json = warnings[0].to_json(None);
+            assert!(json.contains("\"line\":2,\"column\":9"));
+            assert!(json.contains(
+                "{\"message\":\"previously defined here\",\"primary\":false,\
+                 \"span\":{\"start\":{\"line\":1,\"column\":6}"
+            ));
+
+            // replacing a builtin
+            eval_helper(mem, t, "(def length (l) 0)")?;
+            let warnings = t.take_warnings();
+            assert!(warnings.len() == 1);
+            assert!(warnings[0]
+                .to_json(None)
+                .contains("length is a builtin or was bound by the host"));
+
+            // local variables named after special forms
+            eval_helper(mem, t, "(lambda (car x . list) x)")?;
+            eval_helper(mem, t, "(let ((x 1) (if 2)) x)")?;
+            eval_helper(mem, t, "(lambda (a b) a)")?;
+            assert!(
+                messages(&t)
+                    == vec![
+                        "Local variable car shadows the special form of the same name",
+                        "Local variable list shadows the special form of the same name",
+                        "Local variable if shadows the special form of the same name",
+                    ]
+            );
+
+            // references to a deprecated global
+            eval_helper(mem, t, "(put-prop! 'f 'deprecated \"use g instead\")")?;
+            eval_helper(mem, t, "(+ f 1)")?;
+            let warnings = t.take_warnings();
+            assert!(warnings.len() == 1);
+            assert!(warnings[0].message() == "f is deprecated");
+            assert!(warnings[0].to_json(None).contains("use g instead"));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn print_limited_results() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            eval_helper(
+                mem,
+                t,
+                "(def build (n acc) (if (= n 0) acc (build (- n 1) (cons n acc))))",
+            )?;
+            eval_helper(
+                mem,
+                t,
+                "(def nest (n acc) (if (= n 0) acc (nest (- n 1) (cons acc nil))))",
+            )?;
+
+            let short = eval_helper(mem, t, "(build 3 nil)")?;
+            let (printed, truncated) = print_limited(*short, PrintLimit::new(20));
+            assert!(printed == "(1 2 3)" && !truncated);
+
+            // printing stops at the limit, even inside a long or deeply nested structure
+            let long = eval_helper(mem, t, "(build 500 nil)")?;
+            let (printed, truncated) = print_limited(*long, PrintLimit::new(20));
+            assert!(printed == "(1 2 3 4 5 6 7 8 9 1" && truncated);
+
+            let deep = eval_helper(mem, t, "(nest 100 'x)")?;
+            let (printed, truncated) = print_limited(*deep, PrintLimit::new(8));
+            assert!(printed == "((((((((" && truncated);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn evaluation_cost_counters() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let instructions = t.instruction_count();
+            let bytes = mem.bytes_allocated();
+            eval_helper(mem, t, "(cons 1 (cons 2 nil))")?;
+            assert!(t.instruction_count() > instructions);
+            assert!(mem.bytes_allocated() > bytes);
+
+            // a longer computation costs more
+            eval_helper(
+                mem,
+                t,
+                "(def build (n acc) (if (= n 0) acc (build (- n 1) (cons n acc))))",
+            )?;
+            let instructions = t.instruction_count();
+            let bytes = mem.bytes_allocated();
+            eval_helper(mem, t, "(build 10 nil)")?;
+            let small = (
+                t.instruction_count() - instructions,
+                mem.bytes_allocated() - bytes,
+            );
+
+            let instructions = t.instruction_count();
+            let bytes = mem.bytes_allocated();
+            eval_helper(mem, t, "(build 100 nil)")?;
+            assert!(t.instruction_count() - instructions > small.0);
+            assert!(mem.bytes_allocated() - bytes > small.1);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_generators() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let resume = |source: &str| -> Result<String, RuntimeError> {
+                Ok(format!("{}", eval_helper(mem, t, source)?))
+            };
+
+            eval_helper(
+                mem,
+                t,
+                "(define g (make-generator (lambda () (yield 1) (yield 2) 'done)))",
+            )?;
+            assert!(resume("(resume g nil)")? == "1");
+            assert!(resume("(generator-done? g)")? == "nil");
+            assert!(resume("(resume g nil)")? == "2");
+            assert!(resume("(resume g nil)")? == "done");
+            assert!(resume("(generator-done? g)")? == "true");
+            assert!(eval_helper(mem, t, "(resume g nil)").is_err());
+
+            // the value a generator is resumed with is the result of its yield expression, and
+            // its local variables last from one resume to the next
+            eval_helper(
+                mem,
+                t,
+                "(def summer () (let ((total 0)) (while true (set! total (+ total (yield total))))))",
+            )?;
+            eval_helper(mem, t, "(define s (make-generator summer))")?;
+            assert!(resume("(resume s 100)")? == "0");
+            assert!(resume("(resume s 5)")? == "5");
+            assert!(resume("(resume s 10)")? == "15");
+
+            // yielding from inside a nested call
+            eval_helper(
+                mem,
+                t,
+                "(def count-from (n) (yield n) (count-from (+ n 1)))",
+            )?;
+            eval_helper(
+                mem,
+                t,
+                "(define c (make-generator (lambda () (count-from 10))))",
+            )?;
+            assert!(resume("(resume c nil)")? == "10");
+            assert!(resume("(resume c nil)")? == "11");
+            assert!(resume("(resume c nil)")? == "12");
+
+            // closures over generator variables see them while it is suspended, and the generator
+            // sees changes made by closures
+            eval_helper(
+                mem,
+                t,
+                "(define k (make-generator (lambda ()
+                   (let ((x 1))
+                     (yield (lambda () x))
+                     (yield (lambda (v) (set! x v)))
+                     x))))",
+            )?;
+            eval_helper(mem, t, "(define get-x (resume k nil))")?;
+            eval_helper(mem, t, "(define set-x (resume k nil))")?;
+            assert!(resume("(get-x)")? == "1");
+            eval_helper(mem, t, "(set-x 42)")?;
+            assert!(resume("(resume k nil)")? == "42");
+
+            // a generator may use closures over its caller's variables and run other generators
+            eval_helper(
+                mem,
+                t,
+                "(def outer (n)
+                   (let ((inner (make-generator (lambda () (yield (* n 2)) (yield (* n 3))))))
+                     (let ((g (make-generator (lambda ()
+                                (yield (resume inner nil))
+                                (yield (resume inner nil))))))
+                       (list (resume g nil) (resume g nil) n))))",
+            )?;
+            assert!(resume("(outer 7)")? == "(14 21 7)");
+
+            // yield is only allowed directly inside a generator
+            assert!(eval_helper(mem, t, "(yield 1)").is_err());
+            eval_helper(mem, t, "(define p (make-parameter 1))")?;
+            eval_helper(
+                mem,
+                t,
+                "(define bad (make-generator (lambda () (parameterize ((p 2)) (yield (p))))))",
+            )?;
+            assert!(eval_helper(mem, t, "(resume bad nil)").is_err());
+            assert!(resume("(generator-done? bad)")? == "true");
+            assert!(resume("(p)")? == "1");
+
+            assert!(eval_helper(mem, t, "(make-generator 1)").is_err());
+            assert!(eval_helper(mem, t, "(resume 1 nil)").is_err());
+
+            // the function is called with no arguments, so must not need any
+            assert!(eval_helper(mem, t, "(make-generator (lambda (x) (yield x)))").is_err());
+            assert!(eval_helper(mem, t, "(make-generator cons)").is_err());
+            assert!(eval_helper(mem, t, "(make-generator (lambda args args))").is_ok());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn compile_try_catch() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let eval = |source: &str| -> Result<String, RuntimeError> {
+                Ok(format!("{}", eval_helper(mem, t, source)?))
+            };
+
+            assert!(eval("(try (+ 1 2) (catch e 'caught))")? == "3");
+            assert!(eval("(try (throw 'oops) (catch e (list 'caught e)))")? == "(caught oops)");
+
+            // an error raised by a primitive is caught as the text of its message
+            assert!(eval("(try (car 1) (catch e e))")?.starts_with("\"Evaluation error: "));
+
+            // a throw unwinds through function calls and parameterize to the innermost try
+            eval_helper(mem, t, "(define p (make-parameter 1))")?;
+            eval_helper(
+                mem,
+                t,
+                "(def check (n) (parameterize ((p 2)) (if (< n 0) (throw (list 'negative n)) n)))",
+            )?;
+            assert!(eval("(try (check 5) (catch e e))")? == "5");
+            assert!(eval("(try (check -5) (catch e e))")? == "(negative -5)");
+            assert!(eval("(p)")? == "1");
+            assert!(
+                eval("(try (try (check -1) (catch e (throw (list 'again e)))) (catch e e))")?
+                    == "(again (negative -1))"
+            );
+
+            // a throw from a function called by a native function is caught outside it
+            assert!(eval("(try (apply check (list -2)) (catch e e))")? == "(negative -2)");
+
+            // the handler is removed once the try expression completes
+            eval_helper(mem, t, "(try 1 (catch e 'caught))")?;
+            match eval_helper(mem, t, "(throw 'uncaught)") {
+                Err(e) => assert!(*e.error_kind() == ErrorKind::Thrown(String::from("uncaught"))),
+                Ok(_) => panic!("uncaught throw returned a value"),
+            }
+
+            // with-limit and VM errors that are part of control flow are not caught by try
+            assert!(
+                eval("(with-limit 100 (try (while true nil) (catch e 'caught)))")?
+                    == "limit-exceeded"
+            );
+
+            assert!(eval_helper(mem, t, "(try (throw 1))").is_err());
+            assert!(eval_helper(mem, t, "(try (catch e e))").is_err());
+            assert!(eval_helper(mem, t, "(while true (try (break 1) (catch e e)))").is_err());
 
-    // this is a naive way of allocating registers - every result gets it's own register
-    fn acquire_dest_reg(&mut self, push_dest: Option<Register>) -> Result<Register, RuntimeError> {
-        if let Some(dest) = push_dest {
-            Ok(dest)
-        } else {
-            let dest = self.next_reg;
-            // check for 8 bit overflow. A function cannot allocate more than 255 registers for
-            // itself.
-            if dest == 255 {
-                return Err(err_eval(
-                    "Compiler ran out of registers for this function, consider reducing complexity",
-                ));
-            }
-            self.next_reg += 1;
-            Ok(dest)
+            Ok(())
         }
-    }
 
-    // reset the next register back to the given one so that it is reused
-    fn reset_reg(&mut self, reg: Register) {
-        self.next_reg = reg
+        test_helper(test_inner);
     }
-}
 
-/// Compile a function - parameters and expression, returning a tagged Function object
-fn compile_function<'guard, 'scope>(
-    mem: &'guard MutatorView,
-    parent: Option<&'scope Variables<'scope>>,
-    name: TaggedScopedPtr<'guard>,
-    params: &[TaggedScopedPtr<'guard>],
-    exprs: &[TaggedScopedPtr<'guard>],
-) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
-    let compiler = Compiler::new(mem, parent)?;
-    Ok(compiler
-        .compile_function(mem, name, params, exprs)?
-        .as_tagged(mem))
-}
+    #[test]
+    fn compile_infix_islands() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
 
-/// Compile the given AST and return an anonymous Function object
-pub fn compile<'guard>(
-    mem: &'guard MutatorView,
-    ast: TaggedScopedPtr<'guard>,
-) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
-    let compiler = Compiler::new(mem, None)?;
-    compiler.compile_function(mem, mem.nil(), &[], &[ast])
-}
+            let eval = |source: &str| -> Result<String, RuntimeError> {
+                Ok(format!("{}", eval_helper(mem, t, source)?))
+            };
 
-/// INTEGRATION TESTS
-/// TODO - move to a separate module
-#[cfg(test)]
-mod integration {
-    use super::*;
-    use crate::memory::{Memory, Mutator};
-    use crate::parser::parse;
-    use crate::vm::Thread;
+            eval_helper(mem, t, "(define x 5)")?;
+            assert!(eval("#[1 + 2 * x]")? == "11");
+            assert!(eval("#[(1 + 2) * x - -3]")? == "18");
+            assert!(eval("#[x % 3 == 2]")? == "true");
+            assert!(eval("#[x != 5]")? == "nil");
 
-    fn eval_helper<'guard>(
-        mem: &'guard MutatorView,
-        thread: ScopedPtr<'guard, Thread>,
-        code: &str,
-    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
-        let compiled_code = compile(mem, parse(mem, code)?)?;
-        println!("RUN CODE {}", code);
-        let result = thread.quick_vm_eval(mem, compiled_code)?;
-        println!("RUN RESULT {}", result);
-        Ok(result)
+            // islands are ordinary expressions inside s-expressions, and may call functions
+            eval_helper(mem, t, "(def square (n) (* n n))")?;
+            assert!(eval("(if #[square(x) >= 25] 'big 'small)")? == "big");
+            assert!(eval("(list #[x / 2] #[x - 1])")? == "(2 4)");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
     }
 
-    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
-        let mem = Memory::new();
+    #[test]
+    fn compile_unwind_protect() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
 
-        struct Test {}
-        impl Mutator for Test {
-            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
-            type Output = ();
+            let eval = |source: &str| -> Result<String, RuntimeError> {
+                Ok(format!("{}", eval_helper(mem, t, source)?))
+            };
+
+            eval_helper(mem, t, "(define log nil)")?;
+            eval_helper(mem, t, "(def note (x) (set! log (cons x log)))")?;
+
+            // the cleanup runs after the body completes and the result is the body's
+            assert!(eval("(unwind-protect (+ 1 2) (note 'normal))")? == "3");
+            assert!(eval("log")? == "(normal)");
+
+            // the cleanup runs when a throw leaves the body, before the catch clause
+            eval_helper(mem, t, "(set! log nil)")?;
+            assert!(
+                eval("(try (unwind-protect (throw 'oops) (note 'cleanup)) (catch e (note e) e))")?
+                    == "oops"
+            );
+            assert!(eval("log")? == "(oops cleanup)");
+
+            // nested cleanups run innermost first, including for errors from primitives in
+            // called functions
+            eval_helper(mem, t, "(set! log nil)")?;
+            eval_helper(
+                mem,
+                t,
+                "(def inner () (unwind-protect (car 1) (note 'inner)))",
+            )?;
+            assert!(eval_helper(mem, t, "(unwind-protect (inner) (note 'outer))").is_err());
+            assert!(eval("log")? == "(outer inner)");
+
+            // the cleanup runs when a continuation escapes through the body
+            eval_helper(mem, t, "(set! log nil)")?;
+            assert!(
+                eval("(call/cc (lambda (k) (unwind-protect (k 'escaped) (note 'cleanup))))")?
+                    == "escaped"
+            );
+            assert!(eval("log")? == "(cleanup)");
+
+            // an error in the cleanup replaces the error being unwound
+            assert!(
+                eval("(try (unwind-protect (throw 'first) (throw 'second)) (catch e e))")?
+                    == "second"
+            );
+
+            // the cleanup may use the local variables of the enclosing function
+            assert!(
+                eval("(let ((x 1)) (try (unwind-protect (throw 'e) (set! x 2)) (catch e x)))")?
+                    == "2"
+            );
+
+            assert!(eval_helper(mem, t, "(unwind-protect 1)").is_err());
 
-            fn run(
-                &self,
-                mem: &MutatorView,
-                test_fn: Self::Input,
-            ) -> Result<Self::Output, RuntimeError> {
-                test_fn(mem)
-            }
+            Ok(())
         }
 
-        let test = Test {};
-        mem.mutate(&test, test_fn).unwrap();
+        test_helper(test_inner);
     }
 
     #[test]
-    fn compile_cond_first_is_true() {
+    fn compile_dot_paths() {
         fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
-            // testing 'cond'
-            // (nil? nil) == true, so result should be x
-            let code = "(cond (nil? nil) 'x (nil? 'a) 'y)";
-
             let t = Thread::alloc(mem)?;
 
-            let result = eval_helper(mem, t, code)?;
+            let eval = |source: &str| -> Result<String, RuntimeError> {
+                Ok(format!("{}", eval_helper(mem, t, source)?))
+            };
 
-            assert!(result == mem.lookup_sym("x"));
+            let mut server = HashMap::new();
+            server.insert(String::from("host"), String::from("localhost"));
+            server.insert(String::from("port"), String::from("8080"));
+            let mut config = HashMap::new();
+            config.insert(String::from("server"), server);
+            t.define_global(mem, mem.lookup_sym("config"), mem.convert(&config)?)?;
+
+            // a dot-path beginning with a global reads nested dict fields
+            assert!(eval("config.server.port")? == "\"8080\"");
+            assert!(
+                eval("(list config.server.host config.server.port)")? == "(\"localhost\" \"8080\")"
+            );
+
+            // and one beginning with a local variable or a closed over variable
+            assert!(eval("(let ((c config)) c.server.host)")? == "\"localhost\"");
+            eval_helper(mem, t, "(def reader (c) (lambda () c.server.port))")?;
+            assert!(eval("((reader config))")? == "\"8080\"");
+
+            // a global whose name contains dots is still read by name
+            eval_helper(mem, t, "(define config.debug 'on)")?;
+            assert!(eval("config.debug")? == "on");
+
+            assert!(eval_helper(mem, t, "config.client").is_err());
+            assert!(eval_helper(mem, t, "config.server.port.number").is_err());
+            assert!(eval_helper(mem, t, "missing.field").is_err());
 
             Ok(())
         }
@@ -890,17 +6094,35 @@ mod integration {
     }
 
     #[test]
-    fn compile_cond_second_is_true() {
+    fn compile_stack_overflow() {
         fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
-            // testing 'cond'
-            // (nil? 'a) == nil, (nil? nil) == true, so result should be y
-            let code = "(cond (nil? 'a) 'x (nil? nil) 'y)";
-
             let t = Thread::alloc(mem)?;
+            t.set_max_stack_size(4096);
+
+            let eval = |source: &str| -> Result<String, RuntimeError> {
+                Ok(format!("{}", eval_helper(mem, t, source)?))
+            };
+
+            eval_helper(mem, t, "(def deep (n) (+ 1 (deep n)))")?;
+            eval_helper(
+                mem,
+                t,
+                "(def count (n) (if (= n 0) 0 (+ 1 (count (- n 1)))))",
+            )?;
+
+            match eval_helper(mem, t, "(deep 0)") {
+                Err(e) => assert!(*e.error_kind() == ErrorKind::StackOverflow(4096)),
+                Ok(_) => panic!("unbounded recursion returned a value"),
+            }
 
-            let result = eval_helper(mem, t, code)?;
+            // a stack overflow is caught by try and leaves the thread usable
+            assert!(eval("(try (deep 0) (catch e 'overflow))")? == "overflow");
+            assert!(eval("(try (apply deep (list 0)) (catch e 'overflow))")? == "overflow");
+            assert!(eval("(count 100)")? == "100");
 
-            assert!(result == mem.lookup_sym("y"));
+            // the stack grows past its initial window as calls nest
+            t.set_max_stack_size(1 << 16);
+            assert!(eval("(count 1000)")? == "1000");
 
             Ok(())
         }
@@ -909,17 +6131,45 @@ mod integration {
     }
 
     #[test]
-    fn compile_cond_none_is_true() {
+    fn compile_freeze() {
         fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
-            // testing 'cond'
-            // (nil? 'a) == nil, (nil? 'b) == nil, result should be nil
-            let code = "(cond (nil? 'a) 'x (nil? 'b) 'y)";
-
             let t = Thread::alloc(mem)?;
 
-            let result = eval_helper(mem, t, code)?;
-
-            assert!(result == mem.nil());
+            let eval = |source: &str| -> Result<String, RuntimeError> {
+                Ok(format!("{}", eval_helper(mem, t, source)?))
+            };
+
+            // a frozen global keeps the value it had when the expression was compiled
+            eval_helper(mem, t, "(define limit 10)")?;
+            eval_helper(mem, t, "(define frozen (freeze (lambda (n) (< n limit))))")?;
+            eval_helper(mem, t, "(define live (lambda (n) (< n limit)))")?;
+            eval_helper(mem, t, "(set! limit 1)")?;
+            assert!(eval("(frozen 5)")? == "true");
+            assert!(eval("(live 5)")? == "nil");
+
+            // host data is copied, so changing it later does not change the result
+            let server = Dict::alloc(mem)?;
+            server.assoc(mem, mem.lookup_sym("port"), mem.text("8080")?)?;
+            let config = Dict::alloc(mem)?;
+            config.assoc(mem, mem.lookup_sym("server"), server.as_tagged(mem))?;
+            t.define_global(mem, mem.lookup_sym("config"), config.as_tagged(mem))?;
+
+            eval_helper(
+                mem,
+                t,
+                "(define port (freeze (lambda () config.server.port)))",
+            )?;
+            eval_helper(mem, t, "(define snapshot (freeze config))")?;
+            server.assoc(mem, mem.lookup_sym("port"), mem.text("9090")?)?;
+            assert!(eval("(port)")? == "\"8080\"");
+            assert!(eval("snapshot.server.port")? == "\"8080\"");
+            assert!(eval("config.server.port")? == "\"9090\"");
+
+            // local variables are read as usual
+            assert!(eval("(let ((n 1)) (freeze (+ n limit)))")? == "2");
+
+            assert!(eval_helper(mem, t, "(freeze missing)").is_err());
+            assert!(eval_helper(mem, t, "(freeze)").is_err());
 
             Ok(())
         }
@@ -928,24 +6178,109 @@ mod integration {
     }
 
     #[test]
-    fn compile_call_functions() {
-        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
-            // this test calls a function from another function
-            let compare_fn = "(def is_it (ask expect) (is? ask expect))";
-            let curried_fn = "(def is_it_a (ask) (is_it ask 'a))";
-            let query1 = "(is_it_a nil)";
-            let query2 = "(is_it_a 'a)";
+    fn compile_tracer() {
+        use crate::safeptr::TaggedCellPtr;
+        use crate::tracer::Tracer;
+        use std::rc::Rc;
 
+        struct Recorder {
+            lines: Rc<RefCell<Vec<String>>>,
+        }
+
+        impl Tracer for Recorder {
+            fn instruction<'guard>(
+                &mut self,
+                guard: &'guard dyn MutatorScope,
+                function: &str,
+                ip: ArraySize,
+                opcode: Opcode,
+                registers: &[TaggedCellPtr],
+            ) {
+                let argument = registers[FIRST_ARG_REG].get(guard);
+                self.lines
+                    .borrow_mut()
+                    .push(format!("{}@{} {:?} {}", function, ip, opcode, argument));
+            }
+        }
+
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
             let t = Thread::alloc(mem)?;
+            eval_helper(mem, t, "(def square (n) (* n n))")?;
+
+            let lines = Rc::new(RefCell::new(Vec::new()));
+            t.set_tracer(Some(Box::new(Recorder {
+                lines: lines.clone(),
+            })));
+            eval_helper(mem, t, "(square 7)")?;
+            assert!(t.set_tracer(None).is_some());
+
+            // every instruction is traced in order, with the register window of its call frame
+            let traced = lines.borrow();
+            assert!(traced.len() > 3);
+            assert!(traced[0].contains("@0 "));
+            assert!(traced.iter().any(|line| line.starts_with("square@")
+                && line.contains("Multiply")
+                && line.ends_with(" 7")));
+            assert!(traced.last().unwrap().contains("Return"));
+
+            // once the tracer is removed nothing more is traced
+            let count = traced.len();
+            drop(traced);
+            eval_helper(mem, t, "(square 2)")?;
+            assert!(lines.borrow().len() == count);
 
-            eval_helper(mem, t, compare_fn)?;
-            eval_helper(mem, t, curried_fn)?;
+            Ok(())
+        }
 
-            let result1 = eval_helper(mem, t, query1)?;
-            assert!(result1 == mem.nil());
+        test_helper(test_inner);
+    }
 
-            let result2 = eval_helper(mem, t, query2)?;
-            assert!(result2 == mem.lookup_sym("true"));
+    #[test]
+    fn compile_global_log() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+            eval_helper(mem, t, "(define before 1)")?;
+
+            t.start_global_log(mem)?;
+            eval_helper(mem, t, "(define x 1)")?;
+            eval_helper(mem, t, "(set! x (+ x 1))")?;
+            eval_helper(mem, t, "(def f (n) n)")?;
+            eval_helper(mem, t, "(define (a b) (list 3 4))")?;
+            eval_helper(mem, t, "(set 'before 'changed)")?;
+
+            // globals bound by the host are not recorded
+            t.define_global(mem, mem.lookup_sym("host"), mem.nil())?;
+
+            let changes = t.global_changes(mem);
+            let summary = changes
+                .iter()
+                .map(|change| match change.old {
+                    Some(old) => format!("{} {} -> {}", change.name, old, change.new),
+                    None => format!("{} -> {}", change.name, change.new),
+                })
+                .collect::<Vec<_>>();
+            assert!(
+                summary
+                    == vec![
+                        "x -> 1",
+                        "x 1 -> 2",
+                        "f -> #<fn f/1>",
+                        "a -> 3",
+                        "b -> 4",
+                        "before 1 -> changed"
+                    ]
+            );
+
+            // each change has the position of the name defined or assigned
+            assert!(changes[0].pos == Some(spos(1, 8)));
+            assert!(changes[1].pos == Some(spos(1, 6)));
+            assert!(changes[2].pos == Some(spos(1, 5)));
+            assert!(changes[4].pos == Some(spos(1, 11)));
+            assert!(changes.windows(2).all(|pair| pair[0].time <= pair[1].time));
+
+            t.stop_global_log();
+            eval_helper(mem, t, "(define y 1)")?;
+            assert!(t.global_changes(mem).is_empty());
 
             Ok(())
         }
@@ -954,26 +6289,98 @@ mod integration {
     }
 
     #[test]
-    fn compile_map_function_over_list() {
+    fn compile_debugger() {
+        use crate::vm::EvalStatus;
+
         fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
-            // this test passes a function as a parameter through recursive function calls
-            let compare_fn = "(def is_y (ask) (is? ask 'y))";
-            let map_fn =
-                "(def map (f l) (cond (nil? l) nil true (cons (f (car l)) (map f (cdr l)))))";
+            let t = Thread::alloc(mem)?;
+            eval_helper(mem, t, "(def square (n) (* n n))")?;
+
+            // stepping executes one instruction at a time until the evaluation completes
+            let code = compile_with_thread(mem, &t, parse(mem, "(+ (square 7) 1)")?)?;
+            t.debug(mem, code)?;
+            let mut steps = Vec::new();
+            loop {
+                let step = t.step(mem)?;
+                if let EvalStatus::Return(value) = step.status {
+                    assert!(value == TaggedScopedPtr::new(mem, TaggedPtr::number(50)));
+                    break;
+                }
+                steps.push((step.function, step.ip, step.opcode));
+            }
+            assert!(steps[0].1 == 0);
+            assert!(t.step(mem).is_err());
+
+            let multiply = steps
+                .iter()
+                .find(|(function, _, opcode)| {
+                    function == "square" && matches!(opcode, Opcode::Multiply { .. })
+                })
+                .expect("square was not stepped into");
+            let ip = multiply.1;
+
+            // a breakpoint pauses the evaluation before the instruction at it
+            t.set_breakpoint("square", ip);
+            let code = compile_with_thread(mem, &t, parse(mem, "(+ (square 7) 1)")?)?;
+            t.debug(mem, code)?;
+            assert!(t.resume(mem)? == EvalStatus::Paused);
+
+            let step = t.step(mem)?;
+            assert!(step.function == "square" && step.ip == ip);
+            assert!(step.registers.len() == 3);
+            assert!(step.registers[0].1 == TaggedScopedPtr::new(mem, TaggedPtr::number(49)));
+            assert!(step.registers[1].1 == TaggedScopedPtr::new(mem, TaggedPtr::number(7)));
+
+            match t.resume(mem)? {
+                EvalStatus::Return(value) => {
+                    assert!(value == TaggedScopedPtr::new(mem, TaggedPtr::number(50)))
+                }
+                _ => panic!("Evaluation did not complete"),
+            }
 
-            let query = "(map is_y '(x y z z y))";
+            // breakpoints do not pause evaluations that are not being debugged
+            assert!(
+                eval_helper(mem, t, "(square 3)")?
+                    == TaggedScopedPtr::new(mem, TaggedPtr::number(9))
+            );
+            assert!(t.clear_breakpoint("square", ip));
+            assert!(!t.clear_breakpoint("square", ip));
 
-            let t = Thread::alloc(mem)?;
+            Ok(())
+        }
 
-            eval_helper(mem, t, compare_fn)?;
-            eval_helper(mem, t, map_fn)?;
+        test_helper(test_inner);
+    }
 
-            let result = eval_helper(mem, t, query)?;
+    #[test]
+    fn compile_undo_global_changes() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
 
-            let result = vec_from_pairs(mem, result)?;
-            let sym_nil = mem.nil();
-            let sym_true = mem.lookup_sym("true");
-            assert!(result == &[sym_nil, sym_true, sym_nil, sym_nil, sym_true]);
+            let eval = |source: &str| -> Result<String, RuntimeError> {
+                Ok(format!("{}", eval_helper(mem, t, source)?))
+            };
+
+            eval_helper(mem, t, "(define kept 'kept)")?;
+            t.start_global_log(mem)?;
+            eval_helper(mem, t, "(define x 1)")?;
+            let mark = t.global_change_count();
+            eval_helper(mem, t, "(define (x y) (list 2 3))")?;
+            eval_helper(mem, t, "(set! kept 'changed)")?;
+            assert!(t.global_change_count() == 4);
+
+            // changes are undone newest first, back to the mark
+            let restored = t.undo_global_changes(mem, mark)?;
+            let names = restored.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+            assert!(names == vec!["kept", "y", "x"]);
+            assert!(eval("(list x kept)")? == "(1 kept)");
+            assert!(eval_helper(mem, t, "y").is_err());
+            assert!(t.global_change_count() == 1);
+
+            // undoing a definition of a global that was not bound unbinds it
+            t.undo_global_changes(mem, 0)?;
+            assert!(eval_helper(mem, t, "x").is_err());
+            assert!(t.undo_global_changes(mem, 0)?.is_empty());
 
             Ok(())
         }
@@ -982,23 +6389,154 @@ mod integration {
     }
 
     #[test]
-    fn compile_eval_nested_partials() {
+    fn compile_error_backtrace() {
         fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
-            // this test evaluates nested Partial applications in function position
-            let a_fn = "(def isit (a b) (is? a b))";
+            let t = Thread::alloc(mem)?;
+            eval_helper(mem, t, "(def inner (x) (x))")?;
+            eval_helper(mem, t, "(def outer (y) (+ (inner y) 1))")?;
+
+            // the backtrace names every call frame the error left, innermost first, with the
+            // position of the failing instruction or the call the frame was waiting on
+            let error = eval_helper(mem, t, "(let ((a 5)) (+ (outer a) 1))").unwrap_err();
+            let backtrace = error.backtrace();
+            assert!(backtrace.len() == 3);
+            assert!(backtrace[0].function == "inner" && backtrace[0].pos == Some(spos(1, 16)));
+            assert!(backtrace[1].function == "outer" && backtrace[1].pos == Some(spos(1, 19)));
+            assert!(backtrace[2].function == "<lambda>" && backtrace[2].pos == Some(spos(1, 17)));
+            assert!(backtrace[1].to_string() == "in outer at line 1, column 20");
+
+            let rendered = Diagnostic::from(&error).render("(let ((a 5)) (+ (outer a) 1))", false);
+            assert!(rendered.contains("= note: in inner at line 1, column 17\n"));
+
+            // frames of an evaluation nested in a native function call are included, without
+            // the call trampoline
+            let error = eval_helper(mem, t, "(let ((ap apply)) (ap outer (list 1)))").unwrap_err();
+            let names: Vec<&str> = error
+                .backtrace()
+                .iter()
+                .map(|frame| frame.function.as_str())
+                .collect();
+            assert!(names == vec!["inner", "outer", "<lambda>"]);
+
+            // nor is the trampoline of a function called from Rust with no evaluation running
+            let outer = eval_helper(mem, t, "outer")?;
+            let error = t
+                .call_function(
+                    mem,
+                    outer,
+                    &[TaggedScopedPtr::new(mem, TaggedPtr::number(1))],
+                )
+                .unwrap_err();
+            let names: Vec<&str> = error
+                .backtrace()
+                .iter()
+                .map(|frame| frame.function.as_str())
+                .collect();
+            assert!(names == vec!["inner", "outer"]);
+
+            // an instruction that fails has the position of the application it was compiled from,
+            // reported even when a chain of tail calls leaves only its frame
+            eval_helper(mem, t, "(def first-of (x) (car x))")?;
+            eval_helper(mem, t, "(def tail-to (x) (first-of x))")?;
+            let error = eval_helper(mem, t, "(tail-to 1)").unwrap_err();
+            let backtrace = error.backtrace();
+            assert!(backtrace.len() == 1);
+            assert!(backtrace[0].function == "first-of" && backtrace[0].pos == Some(spos(1, 19)));
+            let rendered = Diagnostic::from(&error).render("(tail-to 1)", false);
+            assert!(rendered.contains("= note: in first-of at line 1, column 20\n"));
+
+            // a caught error leaves no frames
+            assert!(
+                eval_helper(mem, t, "(try (outer 1) (catch e 0))")?
+                    == TaggedScopedPtr::new(mem, TaggedPtr::number(0))
+            );
+
+            // repeated frames of deep recursion are collapsed and the backtrace is capped
+            t.set_max_stack_size(4096);
+            eval_helper(mem, t, "(def deep (n) (+ 1 (deep (- n 1))))")?;
+            let error = eval_helper(mem, t, "(deep 1)").unwrap_err();
+            let backtrace = error.backtrace();
+            assert!(backtrace[0].function == "deep" && backtrace[0].omitted > 100);
+            assert!(
+                error.backtrace_lines()[1] == format!("... {} more frames", backtrace[0].omitted)
+            );
+
+            eval_helper(mem, t, "(def ping (n) (+ 1 (pong n)))")?;
+            eval_helper(mem, t, "(def pong (n) (+ 1 (ping n)))")?;
+            let error = eval_helper(mem, t, "(ping 1)").unwrap_err();
+            assert!(error.backtrace().len() == MAX_BACKTRACE_FRAMES);
+            assert!(error.backtrace()[MAX_BACKTRACE_FRAMES - 1].omitted > 100);
 
-            let query1 = "((isit 'x) 'x)";
-            let query2 = "((isit 'x) 'y)";
+            Ok(())
+        }
 
-            let t = Thread::alloc(mem)?;
+        test_helper(test_inner);
+    }
 
-            eval_helper(mem, t, a_fn)?;
+    #[test]
+    fn compile_par_map() {
+        use crate::parallel::{Job, SpawnPool, WorkerPool};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
 
-            let result = eval_helper(mem, t, query1)?;
-            assert!(result == mem.lookup_sym("true"));
+        struct CountingPool {
+            jobs: Arc<AtomicUsize>,
+        }
 
-            let result = eval_helper(mem, t, query2)?;
-            assert!(result == mem.nil());
+        impl WorkerPool for CountingPool {
+            fn workers(&self) -> usize {
+                3
+            }
+
+            fn run(&self, jobs: Vec<Job>) -> Vec<Result<Vec<u8>, RuntimeError>> {
+                self.jobs.fetch_add(jobs.len(), Ordering::SeqCst);
+                SpawnPool::new(self.workers()).run(jobs)
+            }
+        }
+
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+            let eval = |code| -> Result<String, RuntimeError> {
+                Ok(format!("{}", eval_helper(mem, t, code)?))
+            };
+
+            // results are merged in the order of the items
+            assert!(
+                eval("(par-map (lambda (x) (* x x)) '(1 2 3 4 5 6 7))")? == "(1 4 9 16 25 36 49)"
+            );
+            assert!(eval("(par-map (lambda (x) x) nil)")? == "nil");
+
+            // the items are split into a chunk for each worker of the installed pool
+            let jobs = Arc::new(AtomicUsize::new(0));
+            t.set_worker_pool(Some(Box::new(CountingPool { jobs: jobs.clone() })));
+            assert!(
+                eval("(par-map (lambda (s) (list s 'ok)) '(\"a\" \"b\" \"c\" \"d\"))")?
+                    == "((\"a\" ok) (\"b\" ok) (\"c\" ok) (\"d\" ok))"
+            );
+            assert_eq!(jobs.load(Ordering::SeqCst), 2);
+            assert!(t.set_worker_pool(None).is_some());
+
+            // an error in a job is the error of par-map
+            assert!(eval_helper(mem, t, "(par-map (lambda (x) (car x)) '(1 2))").is_err());
+
+            // closures cannot be sent
+            assert!(
+                eval_helper(mem, t, "(let ((n 1)) (par-map (lambda (x) (+ x n)) '(1)))").is_err()
+            );
+
+            // globals of the calling thread that the function refers to are copied
+            eval_helper(mem, t, "(def offset (x) (+ x 10))")?;
+            eval_helper(mem, t, "(define base 5)")?;
+            assert!(eval("(par-map offset '(1 2))")? == "(11 12)");
+            assert!(eval("(par-map (lambda (x) (offset x)) '(1 2))")? == "(11 12)");
+            assert!(eval("(par-map (lambda (x) (+ x base)) '(1 2))")? == "(6 7)");
+            eval_helper(mem, t, "(def fact (n) (if (< n 2) 1 (* n (fact (- n 1)))))")?;
+            assert!(eval("(par-map fact '(3 4 5))")? == "(6 24 120)");
+
+            // unless they cannot be serialized
+            eval_helper(mem, t, "(define adder (let ((n 1)) (lambda (x) (+ x n))))")?;
+            let error = eval_helper(mem, t, "(par-map (lambda (x) (adder x)) '(1))").unwrap_err();
+            assert!(error.to_string().contains("global adder"));
 
             Ok(())
         }
@@ -1007,26 +6545,94 @@ mod integration {
     }
 
     #[test]
-    fn compile_pass_partial_as_param() {
+    fn compile_comparison_jumps() {
         fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
-            // this test passes a Partial as an argument of another function that will call it
-            // with it's last argument.
-            let isit_fn = "(def isit (a b) (is? a b))";
-            let map_fn = "(def map (f v) (f v))";
-
-            let query1 = "(map (isit 'x) 'x)";
-            let query2 = "(map (isit 'x) 'y)";
-
             let t = Thread::alloc(mem)?;
 
-            eval_helper(mem, t, isit_fn)?;
-            eval_helper(mem, t, map_fn)?;
-
-            let result = eval_helper(mem, t, query1)?;
-            assert!(result == mem.lookup_sym("true"));
+            eval_helper(
+                mem,
+                t,
+                "(def classify (a b)
+                   (cond (is? a b) 'same
+                         (nil? a) 'empty
+                         (not b) 'no-b
+                         (< a b) 'less
+                         (> a b) 'greater
+                         (<= a 0) 'non-positive
+                         (>= a 100) 'large
+                         (= a b) 'equal))",
+            )?;
+
+            // a comparison in a test is combined with the jump that follows it
+            let function = compile_with_thread(mem, &t, parse(mem, "classify")?)?;
+            let classify = t.quick_vm_eval(mem, function)?;
+            let listing = match *classify {
+                Value::Function(f) => f.code(mem).disassemble(mem),
+                _ => panic!("classify is not a function"),
+            };
+            assert!(!listing.contains("JumpIfNotTrue"));
+            assert!(!listing.contains("IsLessThan"));
+            assert!(listing.contains("JumpIfNotIdentical"));
+            assert!(listing.contains("JumpIfNotNumericEqual"));
+
+            let cases = [
+                ("(classify 'x 'x)", "same"),
+                ("(classify nil 1)", "empty"),
+                ("(classify 1 nil)", "no-b"),
+                ("(classify 1 2)", "less"),
+                ("(classify 3 2)", "greater"),
+                ("(classify 1.50m 1.5m)", "equal"),
+                ("(classify 'x 'y)", ""),
+            ];
+            for (code, expected) in cases.iter() {
+                match eval_helper(mem, t, code) {
+                    Ok(result) if !expected.is_empty() => {
+                        assert!(result == mem.lookup_sym(expected))
+                    }
+                    // comparing symbols numerically is still an error
+                    Err(e) => assert!(expected.is_empty() && format!("{}", e).contains("<")),
+                    Ok(result) => panic!("unexpected result {}", result),
+                }
+            }
 
-            let result = eval_helper(mem, t, query2)?;
-            assert!(result == mem.nil());
+            // a comparison ending an 'and' is not combined, as the 'and' may skip it
+            let result = eval_helper(
+                mem,
+                t,
+                "(let ((a 1) (b 1)) (if (and nil (is? a b)) 'yes 'no))",
+            )?;
+            assert!(result == mem.lookup_sym("no"));
+
+            // a branch too long for a short jump falls back to the Jump that follows
+            let conses = "(cons a b) ".repeat(200);
+            let code = format!("(lambda (a b) (if (< a b) (begin {}) 'no))", conses);
+            let function = compile_with_thread(mem, &t, parse(mem, &code)?)?;
+            let function = match *t.quick_vm_eval(mem, function)? {
+                Value::Function(f) => f,
+                _ => panic!("expected a function"),
+            };
+            assert!(function
+                .code(mem)
+                .instructions(mem)
+                .iter()
+                .any(|opcode| matches!(opcode, Opcode::JumpIfNotLessThan { offset: 0, .. })));
+
+            let two = TaggedScopedPtr::new(mem, TaggedPtr::number(2));
+            let three = TaggedScopedPtr::new(mem, TaggedPtr::number(3));
+            let f = function.as_tagged(mem);
+            assert_eq!(
+                format!("{}", t.call_function(mem, f, &[two, three])?),
+                "(2 . 3)"
+            );
+            assert!(t.call_function(mem, f, &[three, two])? == mem.lookup_sym("no"));
+
+            // a while loop test is combined too
+            let result = eval_helper(
+                mem,
+                t,
+                "(let ((i 0) (n 0)) (begin (while (< i 10) (set! n (+ n i)) (set! i (+ i 1))) n))",
+            )?;
+            assert_eq!(format!("{}", result), "45");
 
             Ok(())
         }
@@ -1035,15 +6641,31 @@ mod integration {
     }
 
     #[test]
-    fn compile_simple_let() {
+    fn compile_global_inline_cache() {
         fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
-            // this test compiles a basic let expression
-            let expr = "(let ((x 'y)) x)";
-
             let t = Thread::alloc(mem)?;
 
-            let result = eval_helper(mem, t, expr)?;
-            assert!(result == mem.lookup_sym("y"));
+            eval_helper(mem, t, "(set 'g 1)")?;
+            let read_g = eval_helper(mem, t, "(def read-g () g)")?;
+            assert!(eval_helper(mem, t, "(read-g)")?.as_int() == Some(1));
+
+            // rebinding the global is seen through the cache
+            eval_helper(mem, t, "(set 'g 2)")?;
+            assert!(eval_helper(mem, t, "(read-g)")?.as_int() == Some(2));
+
+            // as is the binding after the globals grow and their entries move
+            for index in 0..200 {
+                let name = mem.lookup_sym(&format!("filler-{}", index));
+                t.define_global(mem, name, mem.nil())?;
+            }
+            assert!(eval_helper(mem, t, "(read-g)")?.as_int() == Some(2));
+
+            // another thread running the same code sees its own globals
+            let u = Thread::alloc(mem)?;
+            assert!(u.call_function(mem, read_g, &[]).is_err());
+            u.define_global(mem, mem.lookup_sym("g"), mem.lookup_sym("other"))?;
+            assert!(u.call_function(mem, read_g, &[])? == mem.lookup_sym("other"));
+            assert!(t.call_function(mem, read_g, &[])?.as_int() == Some(2));
 
             Ok(())
         }
@@ -1052,23 +6674,34 @@ mod integration {
     }
 
     #[test]
-    fn compile_function_with_simple_let() {
+    fn compile_register_reuse() {
         fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
-            // this test compiles a let expression that deconstructs and reconstructs a pair list
-            let a_fn = "(def deconrecon (list) (let ((a (car list)) (b (cdr list))) (cons a b)))";
-            let query = "(deconrecon '(x y z z y))";
-
             let t = Thread::alloc(mem)?;
 
-            eval_helper(mem, t, a_fn)?;
+            // each argument is left in the register after the one before, whatever temporaries
+            // computing it needed
+            eval_helper(mem, t, "(def f (a b) (cons a b))")?;
+            let result = eval_helper(mem, t, "(f (cons 1 (cons 2 3)) 4)")?;
+            assert!(format!("{}", result) == "((1 2 . 3) . 4)");
 
-            let result = eval_helper(mem, t, query)?;
+            // the temporaries of each nested comparison are reused by the next
+            let mut code = String::from("nil");
+            for _ in 0..100 {
+                code = format!("(cons (< 1 2) {})", code);
+            }
+            let result = eval_helper(mem, t, &format!("(length {})", code))?;
+            assert!(result.as_int() == Some(100));
 
-            let result = vec_from_pairs(mem, result)?;
-            let sym_x = mem.lookup_sym("x");
-            let sym_y = mem.lookup_sym("y");
-            let sym_z = mem.lookup_sym("z");
-            assert!(result == &[sym_x, sym_y, sym_z, sym_z, sym_y]);
+            // more values live at once than there are registers is an error, not a wraparound
+            let args: Vec<String> = (0..300).map(|n| n.to_string()).collect();
+            let code = format!("(list {})", args.join(" "));
+            let error = compile(mem, parse(mem, &code)?).err().unwrap();
+            assert!(format!("{}", error).contains("ran out of registers"));
+
+            let params: Vec<String> = (0..254).map(|n| format!("p{}", n)).collect();
+            let code = format!("(def many ({}) (cons p0 p253))", params.join(" "));
+            let error = compile(mem, parse(mem, &code)?).err().unwrap();
+            assert!(format!("{}", error).contains("ran out of registers"));
 
             Ok(())
         }
@@ -1077,18 +6710,89 @@ mod integration {
     }
 
     #[test]
-    fn compile_function_with_lambda_with_nonlocal_ref() {
+    fn compile_constant_folding() {
         fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
-            // this test compiles a function containing a lambda that references a nonlocal
-            let head_fn = "(def head (a) (let ((inner (\\ () (car a)))) (inner)))";
-            let query = "(head '(x y z z y))";
-
             let t = Thread::alloc(mem)?;
 
-            eval_helper(mem, t, head_fn)?;
+            // expressions over constants are evaluated while compiling into a single literal
+            let cases = [
+                ("(car (quote (a b)))", "a"),
+                ("(cdr '(a b))", "(b)"),
+                ("(+ 1 2)", "3"),
+                ("(- (* 2 3) (/ 9 2) (mod 7 4))", "-1"),
+                ("(- 5)", "-5"),
+                ("(is? 'a 'a)", "true"),
+                ("(not (< 1 2))", "nil"),
+                ("(atom? :key)", "true"),
+                ("(nil? (cdr '(a)))", "true"),
+            ];
+            for (code, expected) in cases.iter() {
+                let function = compile(mem, parse(mem, code)?)?;
+                let listing = function.code(mem).disassemble(mem);
+                assert!(
+                    listing.lines().count() == 2,
+                    "{} compiled to\n{}",
+                    code,
+                    listing
+                );
+                assert!(listing.contains("LoadLiteral"));
+                assert!(format!("{}", eval_helper(mem, t, code)?) == *expected);
+            }
 
-            let result = eval_helper(mem, t, query)?;
-            assert!(result == mem.lookup_sym("x"));
+            // anything that would raise an error, or that depends on a variable, is left to be
+            // evaluated
+            let cases = [
+                "(/ 1 0)",
+                "(car 5)",
+                "(< 'a 1)",
+                "(+ 1.5m 1)",
+                "(let ((x 1)) (+ x 2))",
+            ];
+            for code in cases.iter() {
+                let function = compile(mem, parse(mem, code)?)?;
+                assert!(function.code(mem).disassemble(mem).lines().count() > 2);
+            }
+            assert!(eval_helper(mem, t, "(/ 1 0)").is_err());
+            assert!(eval_helper(mem, t, "(car 5)").is_err());
+
+            // so are calls to pure builtins, when compiled for a thread on which the name is
+            // still bound to the builtin
+            let listing = |code: &str| -> Result<String, RuntimeError> {
+                let function = compile_with_thread(mem, &t, parse(mem, code)?)?;
+                Ok(function.code(mem).disassemble(mem))
+            };
+            let cases = [
+                ("(eq 'a 'a)", "true"),
+                ("(equal? '(1 (2)) (cdr '(0 1 (2))))", "true"),
+                ("(compare 1 2)", "-1"),
+                ("(length '(a b c))", "3"),
+                ("(not (eq 'a 'b))", "true"),
+            ];
+            for (code, expected) in cases.iter() {
+                let listing = listing(code)?;
+                assert!(
+                    listing.lines().count() == 2,
+                    "{} compiled to\n{}",
+                    code,
+                    listing
+                );
+                assert!(listing.contains("LoadLiteral"));
+                assert!(format!("{}", eval_helper(mem, t, code)?) == *expected);
+            }
+            assert!(listing("(length 5)")?.lines().count() > 2);
+            assert!(listing("(let ((eq is?)) (eq 'a 'a))")?.lines().count() > 2);
+            let function = compile(mem, parse(mem, "(eq 'a 'a)")?)?;
+            assert!(function.code(mem).disassemble(mem).lines().count() > 2);
+
+            let u = Thread::alloc(mem)?;
+            eval_helper(mem, u, "(def eq (a b) 'rebound)")?;
+            assert!(eval_helper(mem, u, "(eq 'a 'a)")? == mem.lookup_sym("rebound"));
+
+            // a macro takes precedence over a special form of the same name
+            eval_helper(mem, t, "(defmacro car (x) ''shadowed)")?;
+            assert!(eval_helper(mem, t, "(car '(a b))")? == mem.lookup_sym("shadowed"));
+            let result = eval_helper(mem, t, "(is? (car '(a b)) 'shadowed)")?;
+            assert!(result == mem.lookup_sym("true"));
 
             Ok(())
         }
@@ -1097,20 +6801,20 @@ mod integration {
     }
 
     #[test]
-    fn compile_function_returning_lambda_with_nonlocal_ref() {
+    fn compile_throw_closes_upvalues() {
         fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
-            // this test compiles a function that returns a lambda that references a nonlocal
-            let head_fn = "(def head (a) (let ((inner (\\ () (car a)))) inner))";
-            let inner_fn = "(set 'inner (head '(x y z z y)))";
-            let query = "(inner)";
-
             let t = Thread::alloc(mem)?;
 
-            eval_helper(mem, t, head_fn)?;
-            eval_helper(mem, t, inner_fn)?;
-
-            let result = eval_helper(mem, t, query)?;
-            assert!(result == mem.lookup_sym("x"));
+            // a closure made in an abandoned try body keeps the value it captured, rather than
+            // reading whatever later reuses the register
+            eval_helper(mem, t, "(define f nil)")?;
+            eval_helper(
+                mem,
+                t,
+                "(try (let ((x 1)) (set! f (lambda () x)) (throw 'e)) (catch e e))",
+            )?;
+            eval_helper(mem, t, "(list 'a 'b 'c)")?;
+            assert!(eval_helper(mem, t, "(f)")?.as_int() == Some(1));
 
             Ok(())
         }
@@ -1119,15 +6823,44 @@ mod integration {
     }
 
     #[test]
-    fn compile_let_with_lambda_with_nested_call() {
+    fn compile_continuation_closes_upvalues() {
         fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
-            // this test compiles a let containing a lambda that is referenced in a sub-let scope
-            let f = "(let ((f (\\ (a) a))) (let ((g (f 'b))) g))";
+            let t = Thread::alloc(mem)?;
+
+            // a closure made in frames abandoned by escaping through a continuation keeps the
+            // value it captured
+            eval_helper(mem, t, "(define f nil)")?;
+            eval_helper(
+                mem,
+                t,
+                "(call/cc (lambda (k) (let ((x 1)) (set! f (lambda () x)) (k 'e))))",
+            )?;
+            eval_helper(mem, t, "(def g (a b c d) (list a b c d))")?;
+            eval_helper(mem, t, "(g 'a 'b 'c 'd)")?;
+            assert!(eval_helper(mem, t, "(f)")?.as_int() == Some(1));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
 
+    #[test]
+    fn compile_limit_closes_upvalues() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
             let t = Thread::alloc(mem)?;
 
-            let result = eval_helper(mem, t, f)?;
-            assert!(result == mem.lookup_sym("b"));
+            // a closure made in a with-limit body abandoned when the budget runs out keeps the
+            // value it captured
+            eval_helper(mem, t, "(define f nil)")?;
+            eval_helper(
+                mem,
+                t,
+                "(with-limit 100 (let ((x 1)) (set! f (lambda () x)) (while true nil)))",
+            )?;
+            eval_helper(mem, t, "(def g (a b c d) (list a b c d))")?;
+            eval_helper(mem, t, "(g 'a 'b 'c 'd)")?;
+            assert!(eval_helper(mem, t, "(f)")?.as_int() == Some(1));
 
             Ok(())
         }
@@ -0,0 +1,115 @@
+/// One-shot escaping continuations.
+///
+/// `(call/cc f)` calls `f` with a Continuation that represents the rest of the computation
+/// following the `call/cc` expression. Calling the continuation with a value, from anywhere
+/// within the dynamic extent of the call to `f`, abandons whatever is being evaluated and makes
+/// that value the result of the `call/cc` expression. Call frames, Parameter bindings and
+/// instruction limits made since the continuation was captured are unwound on the way.
+///
+/// A continuation is only live until its `call/cc` expression completes, by returning normally,
+/// by escaping through it or by an error unwinding past it. Continuations cannot be re-entered.
+use std::cell::Cell;
+use std::fmt;
+
+use crate::array::ArraySize;
+use crate::bytecode::Register;
+use crate::error::RuntimeError;
+use crate::heapcheck::{HeapChecker, Verify};
+use crate::memory::MutatorView;
+use crate::printer::Print;
+use crate::safeptr::{MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+
+/// The Thread state to restore when a continuation is invoked
+#[derive(Copy, Clone)]
+pub struct ResumePoint {
+    /// Number of call frames, the top one being the frame the continuation was captured in
+    pub frame_depth: ArraySize,
+    /// Stack base of the frame the continuation was captured in
+    pub stack_base: ArraySize,
+    /// Instruction to resume at, following the call made by `call/cc`
+    pub resume_ip: ArraySize,
+    /// Register to put the value passed to the continuation in
+    pub dest: Register,
+    /// Length of the Parameter binding stack when the continuation was captured
+    pub parameter_depth: ArraySize,
+    /// Number of instruction limits when the continuation was captured
+    pub limit_depth: usize,
+    /// Number of `try` handlers when the continuation was captured
+    pub handler_depth: usize,
+    /// Number of live continuations captured before this one, which is the index of this one in
+    /// the Thread's stack of live continuations
+    pub continuation_depth: ArraySize,
+}
+
+/// An escaping continuation, see module documentation
+pub struct Continuation {
+    /// Where evaluation resumes when the continuation is invoked
+    resume: ResumePoint,
+    /// False once the `call/cc` expression that captured the continuation has completed
+    live: Cell<bool>,
+    /// The value the continuation was most recently invoked with
+    value: TaggedCellPtr,
+}
+
+impl Continuation {
+    /// Allocate a new live Continuation on the heap
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        resume: ResumePoint,
+    ) -> Result<ScopedPtr<'guard, Continuation>, RuntimeError> {
+        mem.alloc(Continuation {
+            resume,
+            live: Cell::new(true),
+            value: TaggedCellPtr::new_nil(),
+        })
+    }
+
+    /// Return the Thread state to restore
+    pub fn resume_point(&self) -> ResumePoint {
+        self.resume
+    }
+
+    /// Return true if the continuation may still be invoked
+    pub fn is_live(&self) -> bool {
+        self.live.get()
+    }
+
+    /// Mark the `call/cc` expression that captured the continuation as complete
+    pub fn end(&self) {
+        self.live.set(false)
+    }
+
+    /// Return the value the continuation was invoked with
+    pub fn value<'guard>(&self, guard: &'guard dyn MutatorScope) -> TaggedScopedPtr<'guard> {
+        self.value.get(guard)
+    }
+
+    /// Set the value to resume with
+    pub fn set_value(&self, value: TaggedScopedPtr) {
+        self.value.set(value)
+    }
+}
+
+impl Verify for Continuation {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.tagged(guard, self.value.get_ptr())
+    }
+}
+
+impl Print for Continuation {
+    fn print<'guard>(
+        &self,
+        _guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        if self.is_live() {
+            write!(f, "#<continuation>")
+        } else {
+            write!(f, "#<continuation (ended)>")
+        }
+    }
+}
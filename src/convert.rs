@@ -0,0 +1,296 @@
+/// Conversion of Rust values to and from runtime values, for code embedding the runtime.
+///
+/// `ToValue` writes a Rust value into the heap and `FromValue` reads one back out, so that an
+/// embedder can pass data into globals and read results without matching on `Value` itself:
+///
+/// | Rust                   | runtime                                                |
+/// |------------------------|--------------------------------------------------------|
+/// | `i64`                  | integer                                                |
+/// | `f64`                  | float; any number converts to an `f64`                 |
+/// | `bool`                 | the symbol `true` or nil; any non-nil value is `true`  |
+/// | `String`, `str`        | text                                                   |
+/// | `Vec<T>`               | list                                                   |
+/// | `Option<T>`            | nil for None                                           |
+/// | `HashMap<String, T>`   | dict with symbol keys                                  |
+///
+/// `MutatorView::convert()` is a shorthand for `ToValue::to_value()`.
+use std::collections::HashMap;
+
+use num::bigint::BigInt;
+use num::ToPrimitive;
+
+use crate::containers::HashIndexedAnyContainer;
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
+use crate::memory::MutatorView;
+use crate::number::{integer_result, numeric_result, numeric_value, Numeric};
+use crate::pair::{list_from_slice, vec_from_pairs};
+use crate::safeptr::TaggedScopedPtr;
+use crate::taggedptr::Value;
+
+/// A Rust value that can be written into the heap
+pub trait ToValue {
+    fn to_value<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError>;
+}
+
+/// A Rust value that can be read from a runtime value. A value of another type is an error.
+pub trait FromValue: Sized {
+    fn from_value<'guard>(
+        mem: &'guard MutatorView,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<Self, RuntimeError>;
+}
+
+/// Return a type error for a value that cannot be converted
+fn expected(what: &str, value: TaggedScopedPtr) -> RuntimeError {
+    err_eval(&format!("Expected {}, got {}", what, value))
+}
+
+impl ToValue for i64 {
+    fn to_value<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        integer_result(mem, BigInt::from(*self))
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value<'guard>(
+        mem: &'guard MutatorView,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<i64, RuntimeError> {
+        match numeric_value(mem, value) {
+            Some(Numeric::Integer(i)) => i
+                .to_i64()
+                .ok_or_else(|| err_eval(&format!("{} does not fit in 64 bits", value))),
+            _ => Err(expected("an integer", value)),
+        }
+    }
+}
+
+impl ToValue for f64 {
+    fn to_value<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        numeric_result(mem, Numeric::Float(*self))
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value<'guard>(
+        mem: &'guard MutatorView,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<f64, RuntimeError> {
+        match numeric_value(mem, value).map(Numeric::to_inexact) {
+            Some(Numeric::Float(f)) => Ok(f),
+            _ => Err(expected("a number", value)),
+        }
+    }
+}
+
+impl ToValue for bool {
+    fn to_value<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        Ok(mem.boolean(*self))
+    }
+}
+
+impl FromValue for bool {
+    fn from_value<'guard>(
+        _mem: &'guard MutatorView,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<bool, RuntimeError> {
+        Ok(!value.is_nil())
+    }
+}
+
+impl ToValue for str {
+    fn to_value<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        mem.text(self)
+    }
+}
+
+impl ToValue for String {
+    fn to_value<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        mem.text(self)
+    }
+}
+
+impl FromValue for String {
+    fn from_value<'guard>(
+        mem: &'guard MutatorView,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<String, RuntimeError> {
+        match *value {
+            Value::Text(text) => Ok(String::from(text.as_str(mem))),
+            _ => Err(expected("text", value)),
+        }
+    }
+}
+
+impl<T: ToValue> ToValue for Vec<T> {
+    fn to_value<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let items = self
+            .iter()
+            .map(|item| item.to_value(mem))
+            .collect::<Result<Vec<_>, RuntimeError>>()?;
+
+        list_from_slice(mem, &items)
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value<'guard>(
+        mem: &'guard MutatorView,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<Vec<T>, RuntimeError> {
+        match *value {
+            Value::Nil | Value::Pair(_) => vec_from_pairs(mem, value)?
+                .into_iter()
+                .map(|item| T::from_value(mem, item))
+                .collect(),
+            _ => Err(expected("a list", value)),
+        }
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        match self {
+            Some(value) => value.to_value(mem),
+            None => Ok(mem.nil()),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value<'guard>(
+        mem: &'guard MutatorView,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<Option<T>, RuntimeError> {
+        if value.is_nil() {
+            Ok(None)
+        } else {
+            T::from_value(mem, value).map(Some)
+        }
+    }
+}
+
+impl<T: ToValue> ToValue for HashMap<String, T> {
+    fn to_value<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let dict = Dict::alloc(mem)?;
+        for (key, value) in self {
+            dict.assoc(mem, mem.lookup_sym(key), value.to_value(mem)?)?;
+        }
+        Ok(dict.as_tagged(mem))
+    }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value<'guard>(
+        mem: &'guard MutatorView,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<HashMap<String, T>, RuntimeError> {
+        let dict = match *value {
+            Value::Dict(dict) => dict,
+            _ => return Err(expected("a dict", value)),
+        };
+
+        let mut map = HashMap::new();
+        for (key, value) in dict.items(mem) {
+            match *key {
+                Value::Symbol(s) => {
+                    map.insert(String::from(s.as_str(mem)), T::from_value(mem, value)?);
+                }
+                _ => return Err(expected("a symbol key", key)),
+            }
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::FromValue;
+    use crate::error::RuntimeError;
+    use crate::memory::{Memory, Mutator, MutatorView};
+
+    #[test]
+    fn convert_round_trip() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                view: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let value = view.convert(&i64::MAX)?;
+                assert!(format!("{}", value) == i64::MAX.to_string());
+                assert!(i64::from_value(view, value)? == i64::MAX);
+
+                let value = view.convert(&-42i64)?;
+                assert!(value.as_int() == Some(-42));
+                assert!(f64::from_value(view, value)? == -42.0);
+
+                let value = view.convert(&1.5f64)?;
+                assert!(format!("{}", value) == "1.5");
+                assert!(f64::from_value(view, value)? == 1.5);
+                assert!(i64::from_value(view, value).is_err());
+
+                assert!(view.convert(&true)? == view.lookup_sym("true"));
+                assert!(view.convert(&false)?.is_nil());
+                assert!(bool::from_value(view, view.lookup_sym("x"))?);
+
+                let value = view.convert("text")?;
+                assert!(String::from_value(view, value)? == "text");
+                assert!(String::from_value(view, view.lookup_sym("text")).is_err());
+
+                let list = vec![Some(1i64), None, Some(3)];
+                let value = view.convert(&list)?;
+                assert!(format!("{}", value) == "(1 nil 3)");
+                assert!(Vec::<Option<i64>>::from_value(view, value)? == list);
+                assert!(Vec::<i64>::from_value(view, view.nil())?.is_empty());
+
+                let mut map = HashMap::new();
+                map.insert(String::from("a"), vec![String::from("x")]);
+                map.insert(String::from("b"), vec![]);
+                let value = view.convert(&map)?;
+                assert!(HashMap::<String, Vec<String>>::from_value(view, value)? == map);
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+}
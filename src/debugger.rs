@@ -0,0 +1,117 @@
+/// Single-step debugging of an evaluation.
+///
+/// `Thread::debug()` begins evaluating a function paused before its first instruction. From there
+/// `Thread::step()` executes one instruction at a time, describing each as a `Step`, and
+/// `Thread::resume()` runs until the evaluation completes or reaches a breakpoint set with
+/// `Thread::set_breakpoint()`. Breakpoints name a function and an instruction pointer in its
+/// bytecode, as the tracer and the disassembler identify instructions, and only pause the
+/// evaluation being debugged, not evaluations nested inside native function calls.
+use std::collections::HashSet;
+
+use crate::array::ArraySize;
+use crate::bytecode::{Opcode, Register};
+use crate::safeptr::TaggedScopedPtr;
+use crate::vm::EvalStatus;
+
+/// An instruction executed by `Thread::step()`
+pub struct Step<'guard> {
+    /// The name of the function the instruction belongs to
+    pub function: String,
+    /// The instruction pointer of the instruction in the function's bytecode
+    pub ip: ArraySize,
+    pub opcode: Opcode,
+    /// The registers the instruction names, with their values once it has executed
+    pub registers: Vec<(Register, TaggedScopedPtr<'guard>)>,
+    /// Pending, or the result of the evaluation if the instruction completed it
+    pub status: EvalStatus<'guard>,
+}
+
+/// The debugging state of a Thread
+#[derive(Default)]
+pub struct Debugger {
+    /// True while an evaluation begun by `Thread::debug()` has not completed
+    pub active: bool,
+    /// Function name and instruction pointer of each breakpoint
+    breakpoints: HashSet<(String, ArraySize)>,
+}
+
+impl Debugger {
+    /// Pause before the instruction at `ip` in the named function
+    pub fn set_breakpoint(&mut self, function: &str, ip: ArraySize) {
+        self.breakpoints.insert((String::from(function), ip));
+    }
+
+    /// Remove a breakpoint, returning true if it was set
+    pub fn clear_breakpoint(&mut self, function: &str, ip: ArraySize) -> bool {
+        self.breakpoints.remove(&(String::from(function), ip))
+    }
+
+    /// Return true if the evaluation being debugged should pause before the instruction at `ip`
+    /// in the named function
+    pub fn is_breakpoint(&self, function: &str, ip: ArraySize) -> bool {
+        self.active
+            && !self.breakpoints.is_empty()
+            && self.breakpoints.contains(&(String::from(function), ip))
+    }
+}
+
+/// Return the registers an instruction reads or writes, in operand order
+pub fn operand_registers(opcode: Opcode) -> Vec<Register> {
+    match opcode {
+        Opcode::NoOp
+        | Opcode::Jump { .. }
+        | Opcode::EndContinuation
+        | Opcode::UnbindParameters { .. }
+        | Opcode::EndLimit
+        | Opcode::AssertFail { .. }
+        | Opcode::PopHandler => vec![],
+
+        Opcode::Return { reg } => vec![reg],
+        Opcode::LoadLiteral { dest, .. }
+        | Opcode::LoadNil { dest }
+        | Opcode::LoadInteger { dest, .. }
+        | Opcode::GetUpvalue { dest, .. }
+        | Opcode::PushHandler { dest, .. } => vec![dest],
+        Opcode::JumpIfTrue { test, .. } | Opcode::JumpIfNotTrue { test, .. } => vec![test],
+        Opcode::SetUpvalue { src, .. } => vec![src],
+        Opcode::BeginLimit { limit, .. } => vec![limit],
+        Opcode::PushCleanup { thunk } => vec![thunk],
+
+        Opcode::IsNil { dest, test } | Opcode::IsAtom { dest, test } => {
+            vec![dest, test]
+        }
+        Opcode::FirstOfPair { dest, reg } | Opcode::SecondOfPair { dest, reg } => vec![dest, reg],
+        Opcode::LoadGlobal { dest, name, .. } => vec![dest, name],
+        Opcode::StoreGlobal { src, name } => vec![src, name],
+        Opcode::CopyRegister { dest, src } => vec![dest, src],
+        Opcode::MakeClosure { dest, function } => vec![dest, function],
+        Opcode::BindParameter { param, value } => vec![param, value],
+        Opcode::Yield { dest, value } => vec![dest, value],
+        Opcode::Call { function, dest, .. }
+        | Opcode::TailCall { function, dest, .. }
+        | Opcode::Apply { function, dest, .. }
+        | Opcode::CallWithContinuation { function, dest } => vec![function, dest],
+
+        Opcode::MakePair { dest, reg1, reg2 }
+        | Opcode::Add { dest, reg1, reg2 }
+        | Opcode::Multiply { dest, reg1, reg2 } => vec![dest, reg1, reg2],
+        Opcode::IsIdentical { dest, test1, test2 } => vec![dest, test1, test2],
+        Opcode::JumpIfNotIdentical { test1, test2, .. } => vec![test1, test2],
+        Opcode::JumpIfNotLessThan { left, right, .. }
+        | Opcode::JumpIfNotGreaterThan { left, right, .. }
+        | Opcode::JumpIfNotLessOrEqual { left, right, .. }
+        | Opcode::JumpIfNotGreaterOrEqual { left, right, .. }
+        | Opcode::JumpIfNotNumericEqual { left, right, .. } => vec![left, right],
+        Opcode::IsLessThan { dest, left, right }
+        | Opcode::IsGreaterThan { dest, left, right }
+        | Opcode::IsLessOrEqual { dest, left, right }
+        | Opcode::IsGreaterOrEqual { dest, left, right }
+        | Opcode::IsNumericEqual { dest, left, right }
+        | Opcode::Subtract { dest, left, right } => vec![dest, left, right],
+        Opcode::DivideInteger { dest, num, denom } | Opcode::Modulo { dest, num, denom } => {
+            vec![dest, num, denom]
+        }
+        Opcode::CloseUpvalues { reg1, reg2, reg3 } => vec![reg1, reg2, reg3],
+        Opcode::GetField { dest, object, key } => vec![dest, object, key],
+    }
+}
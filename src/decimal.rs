@@ -0,0 +1,362 @@
+/// Exact fixed point decimal numbers, for pricing and other quantities that are written in decimal
+/// and must not pick up the rounding errors of binary floats.
+///
+/// A Decimal is an arbitrarily large integer mantissa and a scale, the number of digits after the
+/// decimal point, and is written with an `m` suffix: `1.50m` has mantissa 150 and scale 2.
+/// Addition and subtraction give the larger scale of the operands and multiplication the sum of
+/// the scales, so the results are exact and keep their trailing zeros. Division is exact too but
+/// gives a rational, which `decimal-round` can round back to a Decimal.
+use std::cmp::Ordering;
+use std::fmt;
+
+use num::bigint::BigInt;
+use num::rational::BigRational;
+use num::{Integer, One, Signed, ToPrimitive, Zero};
+
+use crate::builtins::define;
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
+use crate::memory::MutatorView;
+use crate::number::{numeric_result, numeric_value, Numeric};
+use crate::safeptr::{ScopedPtr, TaggedScopedPtr};
+use crate::taggedptr::Value;
+
+/// Suffix of a Decimal literal
+const DECIMAL_SUFFIX: char = 'm';
+
+/// 10^n
+fn power_of_ten(n: u32) -> BigInt {
+    num::pow(BigInt::from(10), n as usize)
+}
+
+/// A decimal number, `mantissa / 10^scale`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decimal {
+    mantissa: BigInt,
+    scale: u32,
+}
+
+impl Decimal {
+    pub fn new(mantissa: BigInt, scale: u32) -> Decimal {
+        Decimal { mantissa, scale }
+    }
+
+    /// An integer as a Decimal with no digits after the decimal point
+    pub fn from_integer(value: BigInt) -> Decimal {
+        Decimal::new(value, 0)
+    }
+
+    /// The Decimal with the same value as a rational, if its expansion terminates
+    pub fn from_rational(value: &BigRational) -> Option<Decimal> {
+        // a fraction terminates in decimal if its denominator has no prime factors but 2 and 5
+        let mut denom = value.denom().clone();
+        let mut scale = 0;
+        for factor in &[2u32, 5] {
+            let factor = BigInt::from(*factor);
+            let mut count = 0;
+            while denom.is_multiple_of(&factor) {
+                denom /= &factor;
+                count += 1;
+            }
+            scale = scale.max(count);
+        }
+
+        if !denom.is_one() {
+            return None;
+        }
+
+        let mantissa = value.numer() * power_of_ten(scale) / value.denom();
+        Some(Decimal::new(mantissa, scale))
+    }
+
+    /// The Decimal written the same as the shortest representation of a float that reads back as
+    /// the same float, so that 0.1 converts to 0.1m and not to the exact binary value of 0.1.
+    /// Infinities and NaN have no Decimal value.
+    pub fn from_float(value: f64) -> Option<Decimal> {
+        if value.is_finite() {
+            // the Display format of a float never uses an exponent
+            Decimal::parse(&format!("{}", value))
+        } else {
+            None
+        }
+    }
+
+    /// Round a rational to the given number of digits after the decimal point, rounding a value
+    /// exactly halfway between two Decimals to the one with an even last digit
+    pub fn round_half_even(value: &BigRational, places: u32) -> Decimal {
+        let scaled = value * BigRational::from_integer(power_of_ten(places));
+        let floor = scaled.floor();
+        let fraction = &scaled - &floor;
+        let half = BigRational::new(BigInt::one(), BigInt::from(2));
+
+        let mut mantissa = floor.to_integer();
+        match fraction.cmp(&half) {
+            Ordering::Greater => mantissa += 1,
+            Ordering::Equal if mantissa.is_odd() => mantissa += 1,
+            _ => (),
+        }
+
+        Decimal::new(mantissa, places)
+    }
+
+    /// Parse a decimal written as an optional sign, digits and optionally a decimal point followed
+    /// by more digits
+    fn parse(source: &str) -> Option<Decimal> {
+        let (negative, unsigned) = match source.chars().next()? {
+            '-' => (true, &source[1..]),
+            '+' => (false, &source[1..]),
+            _ => (false, source),
+        };
+
+        let (whole, fraction) = match unsigned.find('.') {
+            Some(point) => (&unsigned[..point], &unsigned[point + 1..]),
+            None => (unsigned, ""),
+        };
+
+        let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        if whole.is_empty()
+            || !is_digits(whole)
+            || !is_digits(fraction)
+            || (unsigned.contains('.') && fraction.is_empty())
+        {
+            return None;
+        }
+
+        let mut mantissa: BigInt = format!("{}{}", whole, fraction).parse().ok()?;
+        if negative {
+            mantissa = -mantissa;
+        }
+
+        Some(Decimal::new(mantissa, fraction.len() as u32))
+    }
+
+    /// Parse a Decimal literal such as `1.50m` or `-3m`
+    pub fn parse_literal(source: &str) -> Option<Decimal> {
+        if source.ends_with(DECIMAL_SUFFIX) {
+            Decimal::parse(&source[..source.len() - 1])
+        } else {
+            None
+        }
+    }
+
+    pub fn mantissa(&self) -> &BigInt {
+        &self.mantissa
+    }
+
+    /// The number of digits after the decimal point
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.mantissa.is_negative()
+    }
+
+    /// The mantissa for the same value at a scale that is at least this Decimal's scale
+    fn mantissa_at(&self, scale: u32) -> BigInt {
+        &self.mantissa * power_of_ten(scale - self.scale)
+    }
+
+    /// The mantissas of two Decimals at the larger of their scales, and that scale
+    fn aligned(&self, other: &Decimal) -> (BigInt, BigInt, u32) {
+        let scale = self.scale.max(other.scale);
+        (self.mantissa_at(scale), other.mantissa_at(scale), scale)
+    }
+
+    pub fn add(&self, other: &Decimal) -> Decimal {
+        let (left, right, scale) = self.aligned(other);
+        Decimal::new(left + right, scale)
+    }
+
+    pub fn subtract(&self, other: &Decimal) -> Decimal {
+        let (left, right, scale) = self.aligned(other);
+        Decimal::new(left - right, scale)
+    }
+
+    pub fn multiply(&self, other: &Decimal) -> Decimal {
+        Decimal::new(&self.mantissa * &other.mantissa, self.scale + other.scale)
+    }
+
+    /// Remainder of division rounding toward negative infinity. The divisor must not be zero.
+    pub fn modulo(&self, other: &Decimal) -> Decimal {
+        let (left, right, scale) = self.aligned(other);
+        Decimal::new(left.mod_floor(&right), scale)
+    }
+
+    pub fn to_rational(&self) -> BigRational {
+        BigRational::new(self.mantissa.clone(), power_of_ten(self.scale))
+    }
+
+    /// The nearest float
+    pub fn to_float(&self) -> f64 {
+        // parsing a float from its decimal digits rounds correctly
+        format!("{}", self)
+            .parse::<f64>()
+            .expect("Decimal digits do not parse as a float")
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        let (left, right, _) = self.aligned(other);
+        Some(left.cmp(&right))
+    }
+}
+
+/// Display the digits without the literal suffix
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = self.mantissa.abs().to_string();
+        let scale = self.scale as usize;
+
+        // pad with zeros so that there is a digit before the decimal point
+        let digits = if digits.len() <= scale {
+            format!("{}{}", "0".repeat(scale + 1 - digits.len()), digits)
+        } else {
+            digits
+        };
+
+        let sign = if self.is_negative() { "-" } else { "" };
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, whole)
+        } else {
+            write!(f, "{}{}.{}", sign, whole, fraction)
+        }
+    }
+}
+
+/// Return the number of places argument or a type error
+fn places_arg<'guard>(value: TaggedScopedPtr<'guard>) -> Result<u32, RuntimeError> {
+    match value.as_int().and_then(|n| n.to_u32()) {
+        Some(places) => Ok(places),
+        None => Err(err_eval("Expected a non-negative number of decimal places")),
+    }
+}
+
+/// (number->decimal x) -> x as a Decimal. A rational must have a terminating decimal expansion and
+/// a float converts to the shortest Decimal that reads back as the same float.
+fn number_to_decimal_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let decimal = match numeric_value(mem, args[0]) {
+        Some(Numeric::Integer(i)) => Some(Decimal::from_integer(i)),
+        Some(Numeric::Decimal(d)) => Some(d),
+        Some(Numeric::Rational(r)) => Decimal::from_rational(&r),
+        Some(Numeric::Float(f)) => Decimal::from_float(f),
+        None => return Err(err_eval("Expected a number")),
+    };
+
+    match decimal {
+        Some(decimal) => numeric_result(mem, Numeric::Decimal(decimal)),
+        None => Err(err_eval(&format!(
+            "{} has no exact decimal value, use decimal-round",
+            args[0]
+        ))),
+    }
+}
+
+/// (decimal-round x places) -> x rounded half to even to a Decimal with the given number of digits
+/// after the decimal point. A float is first converted as by number->decimal.
+fn decimal_round_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let places = places_arg(args[1])?;
+
+    let value = match numeric_value(mem, args[0]) {
+        Some(Numeric::Integer(i)) => BigRational::from_integer(i),
+        Some(Numeric::Decimal(d)) => d.to_rational(),
+        Some(Numeric::Rational(r)) => r,
+        Some(Numeric::Float(f)) => match Decimal::from_float(f) {
+            Some(d) => d.to_rational(),
+            None => return Err(err_eval(&format!("{} has no decimal value", args[0]))),
+        },
+        None => return Err(err_eval("Expected a number")),
+    };
+
+    numeric_result(
+        mem,
+        Numeric::Decimal(Decimal::round_half_even(&value, places)),
+    )
+}
+
+/// (decimal? x) -> true if x is a Decimal
+fn is_decimal_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(mem.boolean(matches!(*args[0], Value::NumberObject(n) if n.is_decimal())))
+}
+
+/// Bind the Decimal builtins into the given globals Dict
+pub fn load<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+) -> Result<(), RuntimeError> {
+    define(mem, globals, "number->decimal", 1, number_to_decimal_fn)?;
+    define(mem, globals, "decimal-round", 2, decimal_round_fn)?;
+    define(mem, globals, "decimal?", 1, is_decimal_fn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decimal(source: &str) -> Decimal {
+        Decimal::parse_literal(source).unwrap()
+    }
+
+    #[test]
+    fn decimal_parse_and_display() {
+        assert!(decimal("1.50m") == Decimal::new(BigInt::from(150), 2));
+        assert!(decimal("-0.05m") == Decimal::new(BigInt::from(-5), 2));
+        assert!(format!("{}", decimal("-0.05m")) == "-0.05");
+        assert!(format!("{}", decimal("+12m")) == "12");
+
+        for bad in &["m", "1.m", ".5m", "1.5", "1..5m", "1.5.0m", "-m", "a1m"] {
+            assert!(Decimal::parse_literal(bad).is_none());
+        }
+    }
+
+    #[test]
+    fn decimal_arithmetic() {
+        assert!(format!("{}", decimal("1.50m").add(&decimal("0.5m"))) == "2.00");
+        assert!(format!("{}", decimal("1.1m").subtract(&decimal("2.25m"))) == "-1.15");
+        assert!(format!("{}", decimal("1.10m").multiply(&decimal("3.3m"))) == "3.630");
+        assert!(format!("{}", decimal("-7.5m").modulo(&decimal("2m"))) == "0.5");
+        assert!(decimal("1.50m").partial_cmp(&decimal("1.5m")) == Some(Ordering::Equal));
+        assert!(decimal("0.1m").to_float() == 0.1);
+    }
+
+    #[test]
+    fn decimal_conversions() {
+        let third = BigRational::new(BigInt::from(1), BigInt::from(3));
+        assert!(Decimal::from_rational(&third).is_none());
+        let eighth = BigRational::new(BigInt::from(-1), BigInt::from(8));
+        assert!(Decimal::from_rational(&eighth) == Some(decimal("-0.125m")));
+        assert!(Decimal::from_float(0.1) == Some(decimal("0.1m")));
+        assert!(Decimal::from_float(f64::NAN).is_none());
+
+        // halves round to the even neighbour
+        let round = |source: &str, places| {
+            format!(
+                "{}",
+                Decimal::round_half_even(&decimal(source).to_rational(), places)
+            )
+        };
+        assert!(round("2.5m", 0) == "2");
+        assert!(round("3.5m", 0) == "4");
+        assert!(round("-2.5m", 0) == "-2");
+        assert!(round("1.005m", 2) == "1.00");
+        assert!(round("1.015m", 2) == "1.02");
+        assert!(round("1.0151m", 2) == "1.02");
+        assert!(Decimal::round_half_even(&third, 3) == decimal("0.333m"));
+    }
+}
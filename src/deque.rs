@@ -0,0 +1,303 @@
+/// A double-ended queue implemented as a ring buffer over a List, giving constant time push and
+/// pop at both ends. The buffer doubles in size when full.
+use std::cell::Cell;
+use std::fmt;
+
+use crate::array::ArraySize;
+use crate::containers::{Container, FillAnyContainer, IndexedAnyContainer};
+use crate::error::RuntimeError;
+use crate::heapcheck::{HeapChecker, Verify};
+use crate::list::List;
+use crate::memory::MutatorView;
+use crate::printer::Print;
+use crate::safeptr::{CellPtr, MutatorScope, ScopedPtr, TaggedScopedPtr};
+
+/// Buffer size of a new Deque
+const INITIAL_CAPACITY: ArraySize = 8;
+
+/// A ring buffer deque, see module documentation
+pub struct Deque {
+    /// Buffer index of the front item
+    head: Cell<ArraySize>,
+    /// Number of items in the deque
+    length: Cell<ArraySize>,
+    /// The ring buffer, always filled to capacity, unused slots set to nil
+    buffer: CellPtr<List>,
+}
+
+impl Deque {
+    /// Allocate a new empty instance on the heap
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Deque>, RuntimeError> {
+        let buffer = List::alloc_with_capacity(mem, INITIAL_CAPACITY)?;
+        FillAnyContainer::fill(&*buffer, mem, INITIAL_CAPACITY, mem.nil())?;
+
+        mem.alloc(Deque {
+            head: Cell::new(0),
+            length: Cell::new(0),
+            buffer: CellPtr::new_with(buffer),
+        })
+    }
+
+    /// Number of items in the deque
+    pub fn length(&self) -> ArraySize {
+        self.length.get()
+    }
+
+    /// Buffer index of the item at the given position from the front
+    fn slot(&self, guard: &dyn MutatorScope, position: ArraySize) -> ArraySize {
+        (self.head.get() + position) % self.buffer.get(guard).length()
+    }
+
+    /// Double the buffer size if it is full, unwrapping the items to the start of the new buffer
+    fn reserve<'guard>(&self, mem: &'guard MutatorView) -> Result<(), RuntimeError> {
+        let buffer = self.buffer.get(mem);
+        let capacity = buffer.length();
+        if self.length.get() < capacity {
+            return Ok(());
+        }
+
+        let new_capacity = capacity * 2;
+        let new_buffer = List::alloc_with_capacity(mem, new_capacity)?;
+        FillAnyContainer::fill(&*new_buffer, mem, new_capacity, mem.nil())?;
+
+        for position in 0..self.length.get() {
+            let item = IndexedAnyContainer::get(&*buffer, mem, self.slot(mem, position))?;
+            IndexedAnyContainer::set(&*new_buffer, mem, position, item)?;
+        }
+
+        self.buffer.set(new_buffer);
+        self.head.set(0);
+        Ok(())
+    }
+
+    /// Add an item to the back
+    pub fn push_back<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        item: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        mem.size_limits()
+            .check_vector(self.length.get() as usize + 1)?;
+        self.reserve(mem)?;
+
+        let slot = self.slot(mem, self.length.get());
+        IndexedAnyContainer::set(&*self.buffer.get(mem), mem, slot, item)?;
+        self.length.set(self.length.get() + 1);
+        Ok(())
+    }
+
+    /// Add an item to the front
+    pub fn push_front<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        item: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        mem.size_limits()
+            .check_vector(self.length.get() as usize + 1)?;
+        self.reserve(mem)?;
+
+        let buffer = self.buffer.get(mem);
+        let head = (self.head.get() + buffer.length() - 1) % buffer.length();
+        IndexedAnyContainer::set(&*buffer, mem, head, item)?;
+        self.head.set(head);
+        self.length.set(self.length.get() + 1);
+        Ok(())
+    }
+
+    /// Remove and return the front item
+    pub fn pop_front<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        if self.length.get() == 0 {
+            return Ok(None);
+        }
+
+        let buffer = self.buffer.get(mem);
+        let head = self.head.get();
+        let item = IndexedAnyContainer::get(&*buffer, mem, head)?;
+        // don't keep a reference to the removed item
+        IndexedAnyContainer::set(&*buffer, mem, head, mem.nil())?;
+
+        self.head.set((head + 1) % buffer.length());
+        self.length.set(self.length.get() - 1);
+        Ok(Some(item))
+    }
+
+    /// Remove and return the back item
+    pub fn pop_back<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        if self.length.get() == 0 {
+            return Ok(None);
+        }
+
+        let buffer = self.buffer.get(mem);
+        let slot = self.slot(mem, self.length.get() - 1);
+        let item = IndexedAnyContainer::get(&*buffer, mem, slot)?;
+        IndexedAnyContainer::set(&*buffer, mem, slot, mem.nil())?;
+
+        self.length.set(self.length.get() - 1);
+        Ok(Some(item))
+    }
+
+    /// Return the front item without removing it
+    pub fn front<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        self.at(guard, 0)
+    }
+
+    /// Return the back item without removing it
+    pub fn back<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        match self.length.get() {
+            0 => Ok(None),
+            length => self.at(guard, length - 1),
+        }
+    }
+
+    /// Return the item at the given position from the front
+    pub fn at<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        position: ArraySize,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        if position >= self.length.get() {
+            return Ok(None);
+        }
+
+        let slot = self.slot(guard, position);
+        Ok(Some(IndexedAnyContainer::get(
+            &*self.buffer.get(guard),
+            guard,
+            slot,
+        )?))
+    }
+}
+
+impl Verify for Deque {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.object(guard, &*self.buffer.get(guard))
+    }
+}
+
+impl Print for Deque {
+    fn print<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "Queue[")?;
+        for position in 0..self.length.get() {
+            if position > 0 {
+                write!(f, " ")?;
+            }
+            match self.at(guard, position) {
+                Ok(Some(item)) => write!(f, "{}", item)?,
+                _ => return Err(fmt::Error),
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::{Memory, Mutator};
+    use crate::taggedptr::TaggedPtr;
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    fn num<'guard>(mem: &'guard MutatorView, n: isize) -> TaggedScopedPtr<'guard> {
+        TaggedScopedPtr::new(mem, TaggedPtr::number(n))
+    }
+
+    fn as_int(item: Option<TaggedScopedPtr>) -> Option<isize> {
+        item.and_then(|item| item.value().as_int())
+    }
+
+    #[test]
+    fn deque_fifo_across_growth() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let deque = Deque::alloc(mem)?;
+
+            // advance the head so that the buffer wraps before it grows
+            for i in 0..5 {
+                deque.push_back(mem, num(mem, i))?;
+                deque.pop_front(mem)?;
+            }
+
+            for i in 0..100 {
+                deque.push_back(mem, num(mem, i))?;
+            }
+            assert!(deque.length() == 100);
+
+            for i in 0..100 {
+                assert!(as_int(deque.pop_front(mem)?) == Some(i));
+            }
+            assert!(deque.pop_front(mem)?.is_none());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn deque_both_ends() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let deque = Deque::alloc(mem)?;
+
+            for i in 0..10 {
+                deque.push_front(mem, num(mem, i))?;
+                deque.push_back(mem, num(mem, i))?;
+            }
+
+            assert!(as_int(deque.front(mem)?) == Some(9));
+            assert!(as_int(deque.back(mem)?) == Some(9));
+            assert!(as_int(deque.at(mem, 10)?) == Some(0));
+
+            for i in (0..10).rev() {
+                assert!(as_int(deque.pop_back(mem)?) == Some(i));
+                assert!(as_int(deque.pop_front(mem)?) == Some(i));
+            }
+            assert!(deque.length() == 0);
+            assert!(deque.back(mem)?.is_none());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+}
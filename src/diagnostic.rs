@@ -0,0 +1,537 @@
+/// Rendering of errors and warnings against the source code they refer to.
+///
+/// A Diagnostic has a severity, a message and any number of labels, each a span of the source,
+/// from one position to another on the same or a later line, with an optional message. The
+/// primary label marks where the problem is and is underlined with `^`; secondary labels give
+/// context, such as where a function was defined, and are underlined with `-`. Notes and hints
+/// follow the source lines:
+///
+/// ```text
+/// error: Function called with the wrong number of arguments
+///  --> line 3, column 1
+///   |
+/// 1 | (def add (a b)
+///   |      --- function defined here
+/// 2 |   (+ a b))
+/// 3 | (add 1 2 3)
+///   | ^^^^^^^^^^^ called with 3 arguments here
+///   |
+///   = help: add takes 2 arguments
+/// ```
+///
+/// Rendering with color uses ANSI escape codes for terminals. Without color the output is plain
+/// text, suitable for logs.
+///
+/// For editors and other tools a Diagnostic can instead be written as a single line JSON record:
+///
+/// ```text
+/// {"kind":"error","message":"...","file":null,"line":3,"column":1,
+///  "span":{"start":{"line":3,"column":1},"end":{"line":3,"column":11}},
+///  "labels":[{"message":"...","primary":true,"span":{...}}],
+///  "notes":[{"kind":"help","message":"add takes 2 arguments"}]}
+/// ```
+///
+/// Lines and columns count from 1 in both formats. The location and `span` are those of the
+/// primary label, or of the first label if none is primary, and are `null` if there are no labels.
+use std::fmt;
+use std::io::{self, IsTerminal};
+
+use crate::error::{RuntimeError, SourcePos};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const CYAN: &str = "\x1b[1;36m";
+
+/// How serious a Diagnostic is
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+            Severity::Note => CYAN,
+        }
+    }
+}
+
+/// How diagnostics are written out
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ErrorFormat {
+    /// Source lines with underlined labels, see `Diagnostic::render()`
+    Human,
+    /// One JSON record per line, see `Diagnostic::to_json()`
+    Json,
+}
+
+impl ErrorFormat {
+    /// Parse the name of a format as given on the command line
+    pub fn from_name(name: &str) -> Option<ErrorFormat> {
+        match name {
+            "human" => Some(ErrorFormat::Human),
+            "json" => Some(ErrorFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// A range of source code from the character at `start` to the character at `end`, inclusive
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Span {
+    pub start: SourcePos,
+    pub end: SourcePos,
+}
+
+impl Span {
+    pub fn new(start: SourcePos, end: SourcePos) -> Span {
+        Span { start, end }
+    }
+
+    /// A span of the single character at the given position
+    pub fn point(pos: SourcePos) -> Span {
+        Span {
+            start: pos,
+            end: pos,
+        }
+    }
+}
+
+/// A span of source code to underline, with an optional message to show beside it
+#[derive(Clone, Debug)]
+struct Label {
+    span: Span,
+    message: String,
+    primary: bool,
+}
+
+/// An error or warning with the source spans it refers to, see module documentation
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    severity: Severity,
+    message: String,
+    labels: Vec<Label>,
+    /// Notes and hints, each with the word it is introduced by
+    footers: Vec<(&'static str, String)>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: &str) -> Diagnostic {
+        Diagnostic {
+            severity,
+            message: String::from(message),
+            labels: Vec::new(),
+            footers: Vec::new(),
+        }
+    }
+
+    pub fn error(message: &str) -> Diagnostic {
+        Diagnostic::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: &str) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, message)
+    }
+
+    /// Add a label marking where the problem is. The message may be empty.
+    pub fn with_label(mut self, span: Span, message: &str) -> Diagnostic {
+        self.labels.push(Label {
+            span,
+            message: String::from(message),
+            primary: true,
+        });
+        self
+    }
+
+    /// Add a label giving context for the problem
+    pub fn with_secondary_label(mut self, span: Span, message: &str) -> Diagnostic {
+        self.labels.push(Label {
+            span,
+            message: String::from(message),
+            primary: false,
+        });
+        self
+    }
+
+    /// Add a note explaining the problem
+    pub fn with_note(mut self, note: &str) -> Diagnostic {
+        self.footers.push(("note", String::from(note)));
+        self
+    }
+
+    /// Add a hint suggesting a fix
+    pub fn with_hint(mut self, hint: &str) -> Diagnostic {
+        self.footers.push(("help", String::from(hint)));
+        self
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Render the diagnostic against the source code its spans refer to, with or without ANSI
+    /// color. Labels on lines the source does not have are left out.
+    pub fn render(&self, source: &str, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{}{}{}", style, text, RESET)
+            } else {
+                String::from(text)
+            }
+        };
+
+        let lines: Vec<&str> = source.lines().collect();
+        let labels: Vec<&Label> = self
+            .labels
+            .iter()
+            .filter(|label| {
+                label.span.start.line >= 1 && label.span.end.line as usize <= lines.len()
+            })
+            .collect();
+
+        // the line numbers to show, in order, including single lines between labelled lines
+        let mut shown: Vec<u32> = labels
+            .iter()
+            .flat_map(|label| label.span.start.line..=label.span.end.line)
+            .collect();
+        shown.sort_unstable();
+        shown.dedup();
+        let gaps: Vec<u32> = shown
+            .windows(2)
+            .filter(|pair| pair[1] == pair[0] + 2)
+            .map(|pair| pair[0] + 1)
+            .collect();
+        shown.extend(gaps);
+        shown.sort_unstable();
+
+        let width = shown.last().map_or(1, |line| line.to_string().len());
+        let gutter = |number: &str| paint(BLUE, &format!("{:>width$} |", number, width = width));
+
+        let mut out = format!(
+            "{}{}\n",
+            paint(self.severity.color(), &self.severity.to_string()),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+
+        let location = labels.iter().find(|label| label.primary).or(labels.first());
+        if let Some(label) = location {
+            out.push_str(&format!(
+                "{}{} line {}, column {}\n",
+                " ".repeat(width),
+                paint(BLUE, "-->"),
+                label.span.start.line,
+                label.span.start.column + 1
+            ));
+            out.push_str(&format!("{}\n", gutter("")));
+        }
+
+        let mut previous = None;
+        for line in shown.iter().copied() {
+            if let Some(previous) = previous {
+                if line > previous + 1 {
+                    out.push_str(&format!("{}\n", paint(BLUE, "...")));
+                }
+            }
+            previous = Some(line);
+
+            let text = lines[line as usize - 1];
+            out.push_str(&format!("{} {}\n", gutter(&line.to_string()), text));
+
+            for label in labels
+                .iter()
+                .filter(|label| label.span.start.line <= line && line <= label.span.end.line)
+            {
+                out.push_str(&format!(
+                    "{} {}\n",
+                    gutter(""),
+                    self.underline(label, line, text, &paint)
+                ));
+            }
+        }
+
+        if !self.footers.is_empty() {
+            if location.is_some() {
+                out.push_str(&format!("{}\n", gutter("")));
+            }
+
+            for (kind, text) in &self.footers {
+                out.push_str(&format!(
+                    "{} {} {} {}\n",
+                    " ".repeat(width),
+                    paint(BLUE, "="),
+                    paint(BOLD, &format!("{}:", kind)),
+                    text
+                ));
+            }
+        }
+
+        out
+    }
+
+    /// Return the diagnostic as a single line JSON record, see module documentation. The file
+    /// name, if the source came from a file, is included as given.
+    pub fn to_json(&self, file: Option<&str>) -> String {
+        let location = self
+            .labels
+            .iter()
+            .find(|label| label.primary)
+            .or(self.labels.first());
+
+        let (line, column, span) = match location {
+            Some(label) => (
+                label.span.start.line.to_string(),
+                (label.span.start.column + 1).to_string(),
+                json_span(label.span),
+            ),
+            None => (
+                String::from("null"),
+                String::from("null"),
+                String::from("null"),
+            ),
+        };
+
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|label| {
+                format!(
+                    "{{\"message\":{},\"primary\":{},\"span\":{}}}",
+                    json_string(&label.message),
+                    label.primary,
+                    json_span(label.span)
+                )
+            })
+            .collect();
+
+        let notes: Vec<String> = self
+            .footers
+            .iter()
+            .map(|(kind, text)| {
+                format!(
+                    "{{\"kind\":{},\"message\":{}}}",
+                    json_string(kind),
+                    json_string(text)
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"kind\":{},\"message\":{},\"file\":{},\"line\":{},\"column\":{},\"span\":{},\
+             \"labels\":[{}],\"notes\":[{}]}}",
+            json_string(&self.severity.to_string()),
+            json_string(&self.message),
+            file.map_or(String::from("null"), json_string),
+            line,
+            column,
+            span,
+            labels.join(","),
+            notes.join(",")
+        )
+    }
+
+    /// Return the underline of the part of the given line that a label spans, followed by the
+    /// label message if this is the last line of the span
+    fn underline<F>(&self, label: &Label, line: u32, text: &str, paint: &F) -> String
+    where
+        F: Fn(&str, &str) -> String,
+    {
+        let length = text.chars().count() as u32;
+        let span = label.span;
+
+        // lines a span continues onto are underlined from their first non-blank character
+        let start = if line == span.start.line {
+            span.start.column
+        } else {
+            text.chars().take_while(|c| *c == ' ').count() as u32
+        };
+        let end = if line == span.end.line {
+            span.end.column
+        } else {
+            length.saturating_sub(1)
+        };
+        let end = end.max(start);
+
+        let (mark, style) = if label.primary {
+            ("^", self.severity.color())
+        } else {
+            ("-", BLUE)
+        };
+
+        let mut underline = mark.repeat((end - start + 1) as usize);
+        if line == span.end.line && !label.message.is_empty() {
+            underline.push(' ');
+            underline.push_str(&label.message);
+        }
+
+        format!("{}{}", " ".repeat(start as usize), paint(style, &underline))
+    }
+}
+
+/// Return a span as a JSON object with 1-based columns
+fn json_span(span: Span) -> String {
+    format!(
+        "{{\"start\":{{\"line\":{},\"column\":{}}},\"end\":{{\"line\":{},\"column\":{}}}}}",
+        span.start.line,
+        span.start.column + 1,
+        span.end.line,
+        span.end.column + 1
+    )
+}
+
+/// Return a string as a quoted and escaped JSON string
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(&error.to_string());
+        if let Some(pos) = error.error_pos() {
+            diagnostic = diagnostic.with_label(Span::point(pos), "");
+        }
+
+        for line in error.backtrace_lines() {
+            diagnostic = diagnostic.with_note(&line);
+        }
+
+        let mut cause = error.cause();
+        while let Some(error) = cause {
+            diagnostic = diagnostic.with_note(&format!("caused by: {}", error));
+            cause = error.cause();
+        }
+        diagnostic
+    }
+}
+
+/// Print a diagnostic to stdout, in color if stdout is a terminal
+pub fn print_diagnostic(diagnostic: &Diagnostic, source: &str) {
+    print!("{}", diagnostic.render(source, io::stdout().is_terminal()));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::{err_lexer, spos};
+
+    #[test]
+    fn diagnostic_single_position() {
+        let error = err_lexer(spos(2, 1), "tabs are not valid whitespace");
+        let rendered = Diagnostic::from(&error).render("(foo\n\t(bar))", false);
+
+        assert_eq!(
+            rendered,
+            "error: Parse error: tabs are not valid whitespace\n \
+             --> line 2, column 2\n  \
+             |\n\
+             2 | \t(bar))\n  \
+             |  ^\n"
+        );
+    }
+
+    #[test]
+    fn diagnostic_labels_spans_and_notes() {
+        let source = "(def add (a b)\n  (+ a b))\n\n\n(add 1\n     2 3)";
+        let diagnostic = Diagnostic::error("Function called with the wrong number of arguments")
+            .with_secondary_label(Span::new(spos(1, 5), spos(1, 7)), "function defined here")
+            .with_label(
+                Span::new(spos(5, 0), spos(6, 7)),
+                "called with 3 arguments here",
+            )
+            .with_note("add takes 2 arguments")
+            .with_hint("remove an argument");
+
+        assert_eq!(
+            diagnostic.render(source, false),
+            "error: Function called with the wrong number of arguments\n \
+             --> line 5, column 1\n  \
+             |\n\
+             1 | (def add (a b)\n  \
+             |      --- function defined here\n\
+             ...\n\
+             5 | (add 1\n  \
+             | ^^^^^^\n\
+             6 |      2 3)\n  \
+             |      ^^^ called with 3 arguments here\n  \
+             |\n  \
+             = note: add takes 2 arguments\n  \
+             = help: remove an argument\n"
+        );
+
+        let colored = diagnostic.render(source, true);
+        assert!(colored.contains(&format!("{}error{}", RED, RESET)));
+        assert!(colored.contains(&format!("{}--- function defined here{}", BLUE, RESET)));
+    }
+
+    #[test]
+    fn diagnostic_without_source_lines() {
+        let diagnostic = Diagnostic::warning("Unused variable")
+            .with_label(Span::point(spos(9, 0)), "here")
+            .with_note("nothing to show");
+
+        assert_eq!(
+            diagnostic.render("(x)", false),
+            "warning: Unused variable\n  = note: nothing to show\n"
+        );
+    }
+
+    #[test]
+    fn diagnostic_to_json() {
+        let diagnostic = Diagnostic::error("Unexpected \"token\"")
+            .with_secondary_label(Span::new(spos(1, 0), spos(1, 3)), "opened here")
+            .with_label(Span::point(spos(2, 4)), "line\nbreak")
+            .with_hint("close the list");
+
+        assert_eq!(
+            diagnostic.to_json(Some("src/main.evr")),
+            "{\"kind\":\"error\",\"message\":\"Unexpected \\\"token\\\"\",\
+             \"file\":\"src/main.evr\",\"line\":2,\"column\":5,\
+             \"span\":{\"start\":{\"line\":2,\"column\":5},\"end\":{\"line\":2,\"column\":5}},\
+             \"labels\":[{\"message\":\"opened here\",\"primary\":false,\
+             \"span\":{\"start\":{\"line\":1,\"column\":1},\"end\":{\"line\":1,\"column\":4}}},\
+             {\"message\":\"line\\nbreak\",\"primary\":true,\
+             \"span\":{\"start\":{\"line\":2,\"column\":5},\"end\":{\"line\":2,\"column\":5}}}],\
+             \"notes\":[{\"kind\":\"help\",\"message\":\"close the list\"}]}"
+        );
+
+        assert_eq!(
+            Diagnostic::warning("tab\tand \u{1}").to_json(None),
+            "{\"kind\":\"warning\",\"message\":\"tab\\tand \\u0001\",\"file\":null,\
+             \"line\":null,\"column\":null,\"span\":null,\"labels\":[],\"notes\":[]}"
+        );
+    }
+}
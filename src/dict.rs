@@ -5,11 +5,13 @@ use std::hash::Hasher;
 
 use fnv::FnvHasher;
 
+use crate::compare::compare;
 use crate::containers::{Container, HashIndexedAnyContainer};
 use crate::error::{ErrorKind, RuntimeError};
 use crate::hashable::Hashable;
+use crate::heapcheck::{HeapChecker, Verify};
 use crate::memory::MutatorView;
-use crate::printer::Print;
+use crate::printer::{sorted_keys, Print};
 use crate::rawarray::{default_array_growth, ArraySize, RawArray};
 use crate::safeptr::{MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
 use crate::taggedptr::Value;
@@ -49,6 +51,7 @@ fn hash_key<'guard>(
             Ok(hasher.finish())
         }
         Value::Number(n) => Ok(n as u64),
+        Value::Char(c) => Ok(c as u64),
         _ => Err(RuntimeError::new(ErrorKind::UnhashableError)),
     }
 }
@@ -60,33 +63,41 @@ fn find_entry<'guard>(
     data: &RawArray<DictItem>,
     hash: u64,
 ) -> Result<&'guard mut DictItem, RuntimeError> {
+    let index = find_index(data, hash)?;
+
+    // get raw pointer to base of array
+    let ptr = data
+        .as_ptr()
+        .ok_or(RuntimeError::new(ErrorKind::BoundsError))?;
+
+    Ok(unsafe { &mut *(ptr.offset(index as isize) as *mut DictItem) as &mut DictItem })
+}
+
+/// Return the index of the entry that `find_entry()` finds
+fn find_index(data: &RawArray<DictItem>, hash: u64) -> Result<ArraySize, RuntimeError> {
     // get raw pointer to base of array
     let ptr = data
         .as_ptr()
         .ok_or(RuntimeError::new(ErrorKind::BoundsError))?;
 
     // find the first available or matching entry slot
-    let mut tombstone: Option<&mut DictItem> = None;
+    let mut tombstone: Option<ArraySize> = None;
     let mut index = (hash % data.capacity() as u64) as ArraySize;
     loop {
-        let entry = unsafe { &mut *(ptr.offset(index as isize) as *mut DictItem) as &mut DictItem };
+        let entry = unsafe { &*ptr.offset(index as isize) };
 
         if entry.hash == TOMBSTONE && entry.key.is_nil() {
-            // this is a tombstone: save the first tombstone reference we find
+            // this is a tombstone: save the first tombstone index we find
             if tombstone.is_none() {
-                tombstone = Some(entry);
+                tombstone = Some(index);
             }
         } else if entry.hash == hash {
             // this is an exact match slot
-            return Ok(entry);
+            return Ok(index);
         } else if entry.key.is_nil() {
-            // this is a non-tombstone empty slot
-            if let Some(earlier_entry) = tombstone {
-                // if we recorded a tombstone, return _that_ slot to be reused
-                return Ok(earlier_entry);
-            } else {
-                return Ok(entry);
-            }
+            // this is a non-tombstone empty slot; if we recorded a tombstone, return _that_ slot
+            // to be reused
+            return Ok(tombstone.unwrap_or(index));
         }
 
         index = (index + 1) % data.capacity();
@@ -127,6 +138,8 @@ pub struct Dict {
     used_entries: Cell<ArraySize>,
     /// Backing array for key/value entries
     data: Cell<RawArray<DictItem>>,
+    /// Whether the number of entries is capped by `SizeLimits::table_entries`
+    capped: bool,
 }
 
 impl Dict {
@@ -137,6 +150,16 @@ impl Dict {
         mem.alloc(Dict::new())
     }
 
+    /// Allocate a new instance on the heap that is not capped by `SizeLimits`, for the runtime's
+    /// own tables such as globals, which grow with the program rather than with its data
+    pub fn alloc_uncapped<'guard>(
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Dict>, RuntimeError> {
+        let mut dict = Dict::new();
+        dict.capped = false;
+        mem.alloc(dict)
+    }
+
     /// Allocate a new instance on the heap with pre-allocated capacity
     pub fn alloc_with_capacity<'guard>(
         mem: &'guard MutatorView,
@@ -145,6 +168,80 @@ impl Dict {
         mem.alloc(Dict::with_capacity(mem, capacity)?)
     }
 
+    /// Return the key and value of every entry, in no particular order
+    pub fn items<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Vec<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)> {
+        let data = self.data.get();
+        let mut items = Vec::with_capacity(self.length.get() as usize);
+
+        if let Some(ptr) = data.as_ptr() {
+            for index in 0..data.capacity() {
+                let entry = unsafe { &*(ptr.offset(index as isize) as *const DictItem) };
+                if !entry.key.is_nil() {
+                    items.push((entry.key.get(guard), entry.value.get(guard)));
+                }
+            }
+        }
+
+        items
+    }
+
+    /// Return the key/value pairs in the order they are printed and serialized: sorted by key if
+    /// `printer::sorted_keys()` is on, otherwise in hash table order
+    pub fn ordered_items<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Vec<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)> {
+        let mut items = self.items(guard);
+        if sorted_keys() {
+            items.sort_by(|(left, _), (right, _)| compare(guard, **left, **right));
+        }
+        items
+    }
+
+    /// Return the index of the entry holding a key, None if the key is not present. For caching
+    /// where a binding is, see `value_at()`.
+    pub fn locate<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        key: TaggedScopedPtr<'guard>,
+    ) -> Result<Option<ArraySize>, RuntimeError> {
+        let hash = hash_key(guard, key)?;
+        let data = self.data.get();
+        if data.capacity() == 0 {
+            return Ok(None);
+        }
+
+        let index = find_index(&data, hash)?;
+        Ok(self.value_at(guard, index, key).map(|_| index))
+    }
+
+    /// Return the value of the entry at an index returned by `locate()`, if the entry still holds
+    /// the same key. Entries move when keys are removed or the dict grows, so this is None when
+    /// the key is no longer at the index, whether or not it is present elsewhere. Keys are
+    /// compared by identity.
+    pub fn value_at<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        index: ArraySize,
+        key: TaggedScopedPtr<'guard>,
+    ) -> Option<TaggedScopedPtr<'guard>> {
+        let data = self.data.get();
+        let ptr = data.as_ptr()?;
+        if index >= data.capacity() {
+            return None;
+        }
+
+        let entry = unsafe { &*ptr.offset(index as isize) };
+        if !entry.key.is_nil() && entry.key.get_ptr() == key.get_ptr() {
+            Some(entry.value.get(guard))
+        } else {
+            None
+        }
+    }
+
     /// Scale capacity up if needed
     fn grow_capacity<'guard>(&self, mem: &'guard MutatorView) -> Result<(), RuntimeError> {
         let data = self.data.get();
@@ -175,6 +272,7 @@ impl Container<DictItem> for Dict {
             length: Cell::new(0),
             used_entries: Cell::new(0),
             data: Cell::new(RawArray::new()),
+            capped: true,
         }
     }
 
@@ -186,6 +284,7 @@ impl Container<DictItem> for Dict {
             length: Cell::new(0),
             used_entries: Cell::new(0),
             data: Cell::new(RawArray::with_capacity(mem, capacity)?),
+            capped: true,
         };
 
         let data = dict.data.get();
@@ -241,6 +340,10 @@ impl HashIndexedAnyContainer for Dict {
         let entry = find_entry(mem, &data, hash)?;
 
         if entry.key.is_nil() {
+            if self.capped {
+                mem.size_limits()
+                    .check_table(self.length.get() as usize + 1)?;
+            }
             self.length.set(self.length.get() + 1);
             if entry.hash == 0 {
                 self.used_entries.set(self.used_entries.get() + 1);
@@ -287,14 +390,43 @@ impl HashIndexedAnyContainer for Dict {
     }
 }
 
+impl Verify for Dict {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        let data = self.data.get();
+        checker.raw_array(&data, self.used_entries.get())?;
+
+        if let Some(ptr) = data.as_ptr() {
+            for index in 0..data.capacity() {
+                let entry = unsafe { &*ptr.offset(index as isize) };
+                if !entry.key.is_nil() {
+                    checker.tagged(guard, entry.key.get_ptr())?;
+                    checker.tagged(guard, entry.value.get_ptr())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl Print for Dict {
     fn print<'guard>(
         &self,
-        _guard: &'guard dyn MutatorScope,
+        guard: &'guard dyn MutatorScope,
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
-        // TODO
-        write!(f, "Dict[...]")
+        write!(f, "Dict[")?;
+        for (index, (key, value)) in self.ordered_items(guard).iter().enumerate() {
+            if index > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "({} . {})", key, value)?;
+        }
+        write!(f, "]")
     }
 }
 
@@ -649,4 +781,42 @@ mod test {
         let test = Test {};
         mem.mutate(&test, ()).unwrap();
     }
+
+    #[test]
+    fn dict_locate_value_at() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(&self, mem: &MutatorView, _input: Self::Input) -> Result<(), RuntimeError> {
+                let dict = Dict::alloc(mem)?;
+                let key = mem.lookup_sym("key");
+                let other = mem.lookup_sym("other");
+
+                assert!(dict.locate(mem, key)?.is_none());
+
+                dict.assoc(mem, key, mem.lookup_sym("first"))?;
+                let index = dict.locate(mem, key)?.unwrap();
+                assert!(dict.value_at(mem, index, key) == Some(mem.lookup_sym("first")));
+                assert!(dict.value_at(mem, index, other).is_none());
+
+                // a new value is found at the same index
+                dict.assoc(mem, key, mem.lookup_sym("second"))?;
+                assert!(dict.value_at(mem, index, key) == Some(mem.lookup_sym("second")));
+
+                // a removed key is not
+                dict.dissoc(mem, key)?;
+                assert!(dict.value_at(mem, index, key).is_none());
+                assert!(dict.locate(mem, key)?.is_none());
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
 }
@@ -0,0 +1,176 @@
+/// Checksum and digest builtins: CRC-32 and SHA-256, over bytes or the UTF-8 encoding of text.
+///
+/// Compiled in with the `digest` feature.
+use crate::array::ArrayU8;
+use crate::builtins::define;
+use crate::codec::{bytes_arg, hex_encode, text_result};
+use crate::containers::ContainerFromSlice;
+use crate::dict::Dict;
+use crate::error::RuntimeError;
+use crate::memory::MutatorView;
+use crate::safeptr::{ScopedPtr, TaggedScopedPtr};
+use crate::taggedptr::TaggedPtr;
+
+/// CRC-32 as used by zlib, PNG and Ethernet (reflected polynomial 0xedb88320)
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+/// SHA-256 round constants
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 initial hash value
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// SHA-256 as specified in FIPS 180-4
+pub fn sha256(bytes: &[u8]) -> [u8; 32] {
+    // pad with a 1 bit, zeros, and the message length in bits to a multiple of 64 bytes
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((bytes.len() as u64) * 8).to_be_bytes());
+
+    let mut hash = H0;
+    let mut w = [0u32; 64];
+
+    for block in message.chunks(64) {
+        for (t, word) in block.chunks(4).enumerate() {
+            w[t] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for t in 16..64 {
+            let s0 = w[t - 15].rotate_right(7) ^ w[t - 15].rotate_right(18) ^ (w[t - 15] >> 3);
+            let s1 = w[t - 2].rotate_right(17) ^ w[t - 2].rotate_right(19) ^ (w[t - 2] >> 10);
+            w[t] = w[t - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[t - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = hash;
+
+        for t in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[t])
+                .wrapping_add(w[t]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (word, value) in hash.iter_mut().zip(&[a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(*value);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (out, word) in digest.chunks_mut(4).zip(hash.iter()) {
+        out.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// (crc32 bytes) -> the checksum as a number
+fn crc32_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let checksum = crc32(&bytes_arg(mem, args[0])?);
+    Ok(TaggedScopedPtr::new(
+        mem,
+        TaggedPtr::number(checksum as isize),
+    ))
+}
+
+/// (sha256 bytes) -> the digest as lowercase hexadecimal text
+fn sha256_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    text_result(mem, &hex_encode(&sha256(&bytes_arg(mem, args[0])?)))
+}
+
+/// (sha256-bytes bytes) -> the digest as 32 bytes
+fn sha256_bytes_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let digest = sha256(&bytes_arg(mem, args[0])?);
+    let bytes: ScopedPtr<'guard, ArrayU8> = ArrayU8::from_slice(mem, &digest)?;
+    Ok(bytes.as_tagged(mem))
+}
+
+/// Bind the digest builtins into the given globals Dict
+pub fn load<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+) -> Result<(), RuntimeError> {
+    define(mem, globals, "crc32", 1, crc32_fn)?;
+    define(mem, globals, "sha256", 1, sha256_fn)?;
+    define(mem, globals, "sha256-bytes", 1, sha256_bytes_fn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn digest_crc32_check_value() {
+        assert!(crc32(b"") == 0);
+        assert!(crc32(b"123456789") == 0xcbf4_3926);
+    }
+
+    #[test]
+    fn digest_sha256_vectors() {
+        assert!(
+            hex_encode(&sha256(b""))
+                == "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert!(
+            hex_encode(&sha256(b"abc"))
+                == "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // two blocks once padded
+        assert!(
+            hex_encode(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )) == "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+}
@@ -2,11 +2,16 @@ use std::error::Error;
 use std::fmt;
 use std::io;
 
+#[cfg(feature = "compiler")]
 use rustyline::error::ReadlineError;
 
 use blockalloc::BlockError;
 use stickyimmix::AllocError;
 
+use crate::array::ArraySize;
+use crate::bytecode::{JumpOffset, LiteralId};
+use crate::diagnostic::{print_diagnostic, Diagnostic};
+
 /// Source code position
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct SourcePos {
@@ -32,6 +37,69 @@ pub enum ErrorKind {
     KeyError,
     UnhashableError,
     MutableBorrowError,
+    HeapError(String),
+    /// The instruction budget of a `with-limit` expression that began outside a nested evaluation
+    /// ran out inside it
+    LimitExceeded,
+    /// A continuation captured outside a nested evaluation was invoked inside it. The live
+    /// continuation at the given depth is resumed once the nested evaluation has unwound.
+    ContinuationInvoked(ArraySize),
+    /// Creating or growing an object would take it over one of the `SizeLimits` of the heap
+    SizeLimitExceeded(String),
+    /// A top level evaluation crossed one of the `VmLimits` of its Thread
+    VmLimitExceeded(String),
+    /// A call would take the register stack over the given maximum number of registers
+    StackOverflow(ArraySize),
+    /// The result of the given integer arithmetic expression does not fit in an inline integer
+    IntegerOverflow(String),
+    /// The given expression of an `assert` was not true
+    AssertionFailed(String),
+    /// A value raised by `throw` was not caught, the value being held by the Thread. The string
+    /// is its printed form.
+    Thrown(String),
+    /// The instruction pointer is outside the bytecode being executed
+    BadInstructionPointer(ArraySize),
+    /// The instruction at `ip` refers to a literal that does not exist
+    BadLiteralId {
+        ip: ArraySize,
+        literal_id: LiteralId,
+    },
+    /// The jump instruction at `ip` targets an instruction outside the bytecode
+    BadJump {
+        ip: ArraySize,
+        offset: JumpOffset,
+    },
+}
+
+/// The most distinct call frames kept in the backtrace of an error, the rest are only counted
+pub const MAX_BACKTRACE_FRAMES: usize = 32;
+
+/// A call frame an error was raised in or unwound through
+#[derive(Clone, Debug, PartialEq)]
+pub struct BacktraceFrame {
+    /// The name of the function
+    pub function: String,
+    /// The position of the failing instruction, or of the call the frame was waiting on, in the
+    /// source the function was compiled from, if known
+    pub pos: Option<SourcePos>,
+    /// The number of frames left out after this one, either repeats of it or beyond the cap of
+    /// `MAX_BACKTRACE_FRAMES`
+    pub omitted: usize,
+}
+
+impl fmt::Display for BacktraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pos {
+            Some(pos) => write!(
+                f,
+                "in {} at line {}, column {}",
+                self.function,
+                pos.line,
+                pos.column + 1
+            ),
+            None => write!(f, "in {}", self.function),
+        }
+    }
 }
 
 /// An Eval-rs runtime error type
@@ -39,6 +107,10 @@ pub enum ErrorKind {
 pub struct RuntimeError {
     kind: ErrorKind,
     pos: Option<SourcePos>,
+    /// The call frames the error left, innermost first, once it has left an evaluation
+    backtrace: Vec<BacktraceFrame>,
+    /// The underlying failure, if this error wraps one
+    cause: Option<Box<RuntimeError>>,
 }
 
 impl RuntimeError {
@@ -46,6 +118,8 @@ impl RuntimeError {
         RuntimeError {
             kind: kind,
             pos: None,
+            backtrace: Vec::new(),
+            cause: None,
         }
     }
 
@@ -53,6 +127,8 @@ impl RuntimeError {
         RuntimeError {
             kind: kind,
             pos: Some(pos),
+            backtrace: Vec::new(),
+            cause: None,
         }
     }
 
@@ -64,24 +140,83 @@ impl RuntimeError {
         self.pos
     }
 
-    /// Given the relevant source code string, show the error in context
-    pub fn print_with_source(&self, source: &str) {
-        if let Some(ref pos) = self.pos {
-            let mut iter = source.lines().enumerate();
-
-            while let Some((count, line)) = iter.next() {
-                // count starts at 0, line numbers start at 1
-                if count + 1 == pos.line as usize {
-                    println!("error: {}", self);
-                    println!("{:5}|{}", pos.line, line);
-                    println!("{:5}|{:width$}^", " ", " ", width = pos.column as usize);
-                    println!("{:5}|", " ");
-                    return;
-                }
+    /// Return the call frames the error left, innermost first
+    pub fn backtrace(&self) -> &[BacktraceFrame] {
+        &self.backtrace
+    }
+
+    /// Add a call frame the error left, outside those already in its backtrace. A frame that
+    /// repeats the one before it, as in deep recursion, or that would take the backtrace over
+    /// `MAX_BACKTRACE_FRAMES` is only counted as omitted.
+    pub fn push_backtrace_frame(&mut self, function: &str, pos: Option<SourcePos>) {
+        let full = self.backtrace.len() >= MAX_BACKTRACE_FRAMES;
+        if let Some(last) = self.backtrace.last_mut() {
+            if full || (last.function == function && last.pos == pos) {
+                last.omitted += 1;
+                return;
+            }
+        }
+
+        self.backtrace.push(BacktraceFrame {
+            function: String::from(function),
+            pos,
+            omitted: 0,
+        });
+    }
+
+    /// Return the lines describing the backtrace, innermost first, with a line for each run of
+    /// frames that were left out. There are none if the backtrace is a single frame at the
+    /// position the error already reports.
+    pub fn backtrace_lines(&self) -> Vec<String> {
+        if let [frame] = self.backtrace.as_slice() {
+            if frame.omitted == 0 && frame.pos.is_some() && frame.pos == self.pos {
+                return Vec::new();
+            }
+        }
+
+        let mut lines = Vec::new();
+        for frame in &self.backtrace {
+            lines.push(frame.to_string());
+            if frame.omitted > 0 {
+                lines.push(format!("... {} more frames", frame.omitted));
             }
-        } else {
-            println!("error: {}", self);
         }
+        lines
+    }
+
+    /// Return this error wrapping the underlying failure that caused it
+    pub fn with_cause(mut self, cause: RuntimeError) -> RuntimeError {
+        self.cause = Some(Box::new(cause));
+        self
+    }
+
+    /// Return the underlying failure this error wraps, if any
+    pub fn cause(&self) -> Option<&RuntimeError> {
+        self.cause.as_deref()
+    }
+
+    /// Return true if a `try` expression can catch the error. Errors that are part of the control
+    /// flow of the VM, or that stop evaluation on behalf of the embedder, cannot be caught.
+    pub fn is_catchable(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::IOError(_)
+                | ErrorKind::EvalError(_)
+                | ErrorKind::BoundsError
+                | ErrorKind::KeyError
+                | ErrorKind::UnhashableError
+                | ErrorKind::MutableBorrowError
+                | ErrorKind::SizeLimitExceeded(_)
+                | ErrorKind::StackOverflow(_)
+                | ErrorKind::IntegerOverflow(_)
+                | ErrorKind::AssertionFailed(_)
+                | ErrorKind::Thrown(_)
+        )
+    }
+
+    /// Given the relevant source code string, show the error in context, see `diagnostic`
+    pub fn print_with_source(&self, source: &str) {
+        print_diagnostic(&Diagnostic::from(self), source);
     }
 }
 
@@ -98,11 +233,37 @@ impl fmt::Display for RuntimeError {
             }
             ErrorKind::BoundsError => write!(f, "Indexing bounds error"),
             ErrorKind::KeyError => write!(f, "Key does not exist in Dict"),
-            ErrorKind::UnhashableError => write!(f, "Attempt to access Dict with unhashable key"),
+            ErrorKind::UnhashableError => write!(f, "Attempt to hash an unhashable value"),
             ErrorKind::MutableBorrowError => write!(
                 f,
                 "Attempt to modify a container that is already mutably borrowed"
             ),
+            ErrorKind::HeapError(ref reason) => write!(f, "Heap verification failed: {}", reason),
+            ErrorKind::LimitExceeded => write!(f, "Instruction limit exceeded"),
+            ErrorKind::ContinuationInvoked(_) => write!(f, "Continuation invoked"),
+            ErrorKind::SizeLimitExceeded(ref reason) => {
+                write!(f, "Size limit exceeded: {}", reason)
+            }
+            ErrorKind::VmLimitExceeded(ref reason) => write!(f, "VM limit exceeded: {}", reason),
+            ErrorKind::StackOverflow(max) => {
+                write!(f, "Stack overflow: more than {} registers in use", max)
+            }
+            ErrorKind::IntegerOverflow(ref expr) => write!(f, "Integer overflow in {}", expr),
+            ErrorKind::AssertionFailed(ref expr) => write!(f, "Assertion failed: {}", expr),
+            ErrorKind::Thrown(ref value) => write!(f, "Uncaught exception: {}", value),
+            ErrorKind::BadInstructionPointer(ip) => {
+                write!(f, "Instruction pointer {} is outside the bytecode", ip)
+            }
+            ErrorKind::BadLiteralId { ip, literal_id } => write!(
+                f,
+                "Instruction {} refers to literal {}, which does not exist",
+                ip, literal_id
+            ),
+            ErrorKind::BadJump { ip, offset } => write!(
+                f,
+                "Instruction {} jumps by {} to outside the bytecode",
+                ip, offset
+            ),
         }
     }
 }
@@ -115,6 +276,7 @@ impl From<io::Error> for RuntimeError {
 }
 
 /// Convert from ReadlineError
+#[cfg(feature = "compiler")]
 impl From<ReadlineError> for RuntimeError {
     fn from(other: ReadlineError) -> RuntimeError {
         RuntimeError::new(ErrorKind::IOError(format!("{}", other)))
@@ -142,8 +304,10 @@ impl From<AllocError> for RuntimeError {
 }
 
 impl Error for RuntimeError {
-    fn cause(&self) -> Option<&dyn Error> {
-        None
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.cause
+            .as_deref()
+            .map(|cause| cause as &(dyn Error + 'static))
     }
 }
 
@@ -179,3 +343,9 @@ pub fn err_parser_wpos(pos: SourcePos, reason: &str) -> RuntimeError {
 pub fn err_eval(reason: &str) -> RuntimeError {
     RuntimeError::new(ErrorKind::EvalError(String::from(reason)))
 }
+
+/// Convenience shorthand function for building an IO error wrapping the underlying failure
+pub fn err_io(reason: &str, cause: io::Error) -> RuntimeError {
+    RuntimeError::new(ErrorKind::IOError(String::from(reason)))
+        .with_cause(RuntimeError::from(cause))
+}
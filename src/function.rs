@@ -1,15 +1,18 @@
 use itertools::join;
+use std::cell::Cell;
 use std::fmt;
 
-use crate::array::ArrayU16;
+use crate::array::{ArraySize, ArrayU16};
 use crate::bytecode::ByteCode;
 use crate::containers::{Container, ContainerFromSlice, SliceableContainer, StackContainer};
-use crate::error::RuntimeError;
+use crate::error::{err_eval, RuntimeError};
+use crate::heapcheck::{HeapChecker, Verify};
 use crate::list::List;
 use crate::memory::MutatorView;
 use crate::printer::Print;
 use crate::safeptr::{CellPtr, MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
 use crate::taggedptr::Value;
+use crate::vm::Thread;
 
 /// A function object type
 #[derive(Clone)]
@@ -18,6 +21,8 @@ pub struct Function {
     name: TaggedCellPtr,
     /// Number of arguments required to activate the function
     arity: u8,
+    /// Whether any arguments beyond `arity` are collected into a list for a final rest parameter
+    variadic: bool,
     /// Instructions comprising the function code
     code: CellPtr<ByteCode>,
     /// Param names are stored for introspection of a function signature
@@ -34,10 +39,13 @@ impl Function {
     /// The nonlocal_refs arg must contain a list of 16 bit values composed of two
     /// 8 bit values: CallFrame relative offset << 8 | Window offset
     /// These values should follow the same order as given in param_names
+    ///
+    /// If the function is variadic, the last of the param_names is the rest parameter.
     pub fn alloc<'guard>(
         mem: &'guard MutatorView,
         name: TaggedScopedPtr<'guard>,
         param_names: ScopedPtr<'guard, List>,
+        variadic: bool,
         code: ScopedPtr<'guard, ByteCode>,
         nonlocal_refs: Option<ScopedPtr<'guard, ArrayU16>>,
     ) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
@@ -50,7 +58,8 @@ impl Function {
 
         mem.alloc(Function {
             name: TaggedCellPtr::new_with(name),
-            arity: param_names.length() as u8,
+            arity: (param_names.length() - variadic as ArraySize) as u8,
+            variadic,
             code: CellPtr::new_with(code),
             param_names: CellPtr::new_with(param_names),
             nonlocal_refs: nonlocal_refs,
@@ -66,11 +75,22 @@ impl Function {
         }
     }
 
-    /// Return the number of arguments the Function can take
+    /// Return true if the Function was defined without a name
+    pub fn is_anonymous<'guard>(&self, guard: &'guard dyn MutatorScope) -> bool {
+        !matches!(*self.name.get(guard), Value::Symbol(_))
+    }
+
+    /// Return the number of arguments the Function requires
     pub fn arity(&self) -> u8 {
         self.arity
     }
 
+    /// Return true if the Function takes any number of arguments beyond its arity, as a list bound
+    /// to its rest parameter
+    pub fn is_variadic(&self) -> bool {
+        self.variadic
+    }
+
     /// Return the names of the parameters that the Function takes
     pub fn param_names<'guard>(&self, guard: &'guard dyn MutatorScope) -> ScopedPtr<'guard, List> {
         self.param_names.get(guard)
@@ -100,25 +120,29 @@ impl Function {
     }
 }
 
+impl Verify for Function {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.tagged(guard, self.name.get_ptr())?;
+        checker.object(guard, &*self.code.get(guard))?;
+        checker.object(guard, &*self.param_names.get(guard))?;
+        checker.tagged(guard, self.nonlocal_refs.get_ptr())
+    }
+}
+
 impl Print for Function {
-    /// Prints a string representation of the function
+    /// Prints the name and arity of the function: #<fn name/arity>, or #<fn name/arity+> if it
+    /// takes further arguments as a rest parameter
     fn print<'guard>(
         &self,
         guard: &'guard dyn MutatorScope,
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
-        let name = self.name.get(guard);
-        let params = self.param_names.get(guard);
-
-        let mut param_string = String::new();
-        params.access_slice(guard, |items| {
-            param_string = join(items.iter().map(|item| item.get(guard)), " ")
-        });
-
-        match *name {
-            Value::Symbol(s) => write!(f, "(Function {} ({}))", s.as_str(guard), param_string),
-            _ => write!(f, "(Function ({}))", param_string),
-        }
+        let more = if self.variadic { "+" } else { "" };
+        write!(f, "#<fn {}/{}{}>", self.name(guard), self.arity, more)
     }
 
     /// Prints the disassembled bytecode
@@ -127,9 +151,24 @@ impl Print for Function {
         guard: &'guard dyn MutatorScope,
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
+        let params = self.param_names.get(guard);
+
+        let mut param_string = String::new();
+        params.access_slice(guard, |items| {
+            param_string = join(items.iter().map(|item| item.get(guard)), " ")
+        });
+        if self.variadic {
+            // the rest parameter is written dotted, as it was declared
+            match param_string.rfind(' ') {
+                Some(space) => param_string.insert_str(space, " ."),
+                None => param_string.insert_str(0, ". "),
+            }
+        }
+
         self.print(guard, f)?;
+        write!(f, " ({})", param_string)?;
         write!(f, "\nbytecode follows:\n")?;
-        self.code(guard).debug(guard, f)
+        fmt::Debug::fmt(&self.code(guard), f)
     }
 }
 
@@ -230,6 +269,18 @@ impl Partial {
     }
 }
 
+impl Verify for Partial {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.object(guard, &*self.args.get(guard))?;
+        checker.tagged(guard, self.env.get_ptr())?;
+        checker.object(guard, &*self.func.get(guard))
+    }
+}
+
 impl Print for Partial {
     /// Prints a string representation of the Partial object
     fn print<'guard>(
@@ -261,14 +312,162 @@ impl Print for Partial {
     ) -> fmt::Result {
         self.print(guard, f)?;
         write!(f, "\nbytecode follows:\n")?;
-        self.func.get(guard).code(guard).debug(guard, f)
+        fmt::Debug::fmt(&self.func.get(guard).code(guard), f)
+    }
+}
+
+/// Signature of a function implemented in Rust. Arguments are given in order, the return value
+/// is the result of the function call. The argument slice always has exactly as many items as the
+/// declared arity, so native functions may index it directly.
+pub type NativeFn = for<'guard> fn(
+    &'guard MutatorView,
+    &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError>;
+
+/// Signature of a function implemented in Rust that also needs the state of the Thread calling
+/// it, such as the current input and output ports.
+pub type ThreadNativeFn = for<'guard> fn(
+    &'guard MutatorView,
+    &Thread,
+    &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError>;
+
+/// The two kinds of Rust function
+#[derive(Clone, Copy)]
+enum NativeCode {
+    Plain(NativeFn),
+    WithThread(ThreadNativeFn),
+}
+
+/// A function object type wrapping a Rust function
+#[derive(Clone)]
+pub struct NativeFunction {
+    /// name could be a Symbol, or nil if it is an anonymous fn
+    name: TaggedCellPtr,
+    /// Number of arguments required to activate the function
+    arity: u8,
+    /// The Rust function
+    code: NativeCode,
+    /// Whether the function was defined by the embedding application, see `mark_host()`
+    host: Cell<bool>,
+}
+
+impl NativeFunction {
+    /// Allocate a NativeFunction object on the heap
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        name: &str,
+        arity: u8,
+        code: NativeFn,
+    ) -> Result<ScopedPtr<'guard, NativeFunction>, RuntimeError> {
+        mem.alloc(NativeFunction {
+            name: TaggedCellPtr::new_with(mem.lookup_sym(name)),
+            arity,
+            code: NativeCode::Plain(code),
+            host: Cell::new(false),
+        })
+    }
+
+    /// Allocate a NativeFunction object on the heap for a Rust function that needs the calling
+    /// Thread
+    pub fn alloc_with_thread<'guard>(
+        mem: &'guard MutatorView,
+        name: &str,
+        arity: u8,
+        code: ThreadNativeFn,
+    ) -> Result<ScopedPtr<'guard, NativeFunction>, RuntimeError> {
+        mem.alloc(NativeFunction {
+            name: TaggedCellPtr::new_with(mem.lookup_sym(name)),
+            arity,
+            code: NativeCode::WithThread(code),
+            host: Cell::new(false),
+        })
+    }
+
+    /// Return a string representation of the name of the function, if any
+    pub fn name<'guard>(&self, guard: &'guard dyn MutatorScope) -> &'guard str {
+        match *self.name.get(guard) {
+            Value::Symbol(s) => s.as_str(guard),
+            _ => "<lambda>",
+        }
+    }
+
+    /// Return the number of arguments the NativeFunction requires
+    pub fn arity(&self) -> u8 {
+        self.arity
+    }
+
+    /// Mark the function as defined by the embedding application. Its results are outside the
+    /// control of the runtime, so a recording of an evaluation keeps them to replay.
+    pub fn mark_host(&self) {
+        self.host.set(true);
+    }
+
+    /// Return true if the function was defined by the embedding application
+    pub fn is_host(&self) -> bool {
+        self.host.get()
+    }
+
+    /// Return true if the NativeFunction calls the given Rust function
+    pub fn runs(&self, code: NativeFn) -> bool {
+        match self.code {
+            NativeCode::Plain(own) => own as usize == code as usize,
+            NativeCode::WithThread(_) => false,
+        }
+    }
+
+    /// Call the Rust function with the given arguments. A function that needs the calling Thread
+    /// returns an error if there is none.
+    pub fn call<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: Option<&Thread>,
+        args: &[TaggedScopedPtr<'guard>],
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        if args.len() != self.arity as usize {
+            return Err(err_eval(&format!(
+                "Function {} expected {} arguments, got {}",
+                self.name(mem),
+                self.arity,
+                args.len()
+            )));
+        }
+
+        match (self.code, thread) {
+            (NativeCode::Plain(code), _) => code(mem, args),
+            (NativeCode::WithThread(code), Some(thread)) => code(mem, thread, args),
+            (NativeCode::WithThread(_), None) => Err(err_eval(&format!(
+                "Function {} can only be called from a running thread",
+                self.name(mem)
+            ))),
+        }
+    }
+}
+
+impl Verify for NativeFunction {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.tagged(guard, self.name.get_ptr())
+    }
+}
+
+impl Print for NativeFunction {
+    fn print<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "#<fn {}/{}>", self.name(guard), self.arity)
     }
 }
 
 /// A list of arguments to apply to functions
 pub struct CurriedArguments {
     // TODO
-// not sure of the mechanics of this.
-// The ghc runtime would push all these to the stack and then consume the stack with
-// function continuations
+    // not sure of the mechanics of this.
+    // The ghc runtime would push all these to the stack and then consume the stack with
+    // function continuations
 }
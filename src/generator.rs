@@ -0,0 +1,223 @@
+/// Generators, functions that can suspend themselves part way through and be resumed later.
+///
+/// `(make-generator f)` makes a Generator that calls `f` with no arguments the first time it is
+/// resumed. Each `(resume gen v)` runs the generator until it evaluates `(yield x)`, which
+/// suspends it and makes `x` the result of `resume`. The next `resume` continues from the `yield`
+/// expression, whose result is the value passed to that `resume`; the value passed to the first
+/// `resume` is ignored. When `f` returns, its result is the result of the last `resume` and the
+/// generator is finished:
+///
+///   (define counter (make-generator (lambda () (yield 1) (yield 2) 'done)))
+///   (resume counter nil) ; 1
+///   (resume counter nil) ; 2
+///   (resume counter nil) ; done
+///
+/// A Generator owns its own call frames, register stack and upvalues, which `Thread` swaps with
+/// its own while the generator runs. A generator cannot yield inside a `parameterize`,
+/// `with-limit`, `call/cc`, `try` or `unwind-protect` expression that began since it was resumed,
+/// nor from a function called by a native function, and an error while it runs finishes it.
+use std::cell::Cell;
+use std::fmt;
+
+use crate::array::ArraySize;
+use crate::builtins::{define, define_with_thread};
+use crate::bytecode::{ByteCode, Opcode};
+use crate::containers::{FillAnyContainer, IndexedAnyContainer, StackContainer};
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
+use crate::function::Function;
+use crate::heapcheck::{HeapChecker, Verify};
+use crate::list::List;
+use crate::memory::MutatorView;
+use crate::printer::Print;
+use crate::safeptr::{CellPtr, MutatorScope, ScopedPtr, TaggedScopedPtr};
+use crate::taggedptr::Value;
+use crate::vm::{CallFrame, CallFrameList, Thread, ENV_REG};
+
+/// Where a generator is in its life
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GeneratorState {
+    /// Not yet started, or suspended at a `yield` expression
+    Suspended,
+    /// Being run by `resume`
+    Running,
+    /// Its function returned, or an error unwound it
+    Finished,
+}
+
+/// A suspendable function evaluation, see module documentation
+pub struct Generator {
+    /// Call frames of the evaluation, the bottom one being a trampoline that calls the function
+    frames: CellPtr<CallFrameList>,
+    /// The register stack of the evaluation
+    stack: CellPtr<List>,
+    /// Upvalues of the evaluation that are open, keyed by stack location
+    upvalues: CellPtr<Dict>,
+    /// Stack location the value the generator is resumed with goes in, None before it starts
+    resume_location: Cell<Option<ArraySize>>,
+    state: Cell<GeneratorState>,
+}
+
+impl Generator {
+    /// Allocate a new Generator on the heap that will call the given function with no arguments
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        function: TaggedScopedPtr<'guard>,
+    ) -> Result<ScopedPtr<'guard, Generator>, RuntimeError> {
+        let arity = match *function {
+            Value::Function(f) => f.arity(),
+            Value::Partial(p) => p.arity(),
+            Value::NativeFunction(f) => f.arity(),
+            _ => return Err(err_eval(&format!("{} is not callable", function))),
+        };
+        if arity != 0 {
+            return Err(err_eval(&format!(
+                "A generator calls its function with no arguments, {} takes {}",
+                function, arity
+            )));
+        }
+
+        // Call the function from a two instruction trampoline, as `Thread::call_function()`
+        // does. The function goes in register 2 and its register window starts at register 3.
+        let code = ByteCode::alloc(mem)?;
+        code.push(
+            mem,
+            Opcode::Call {
+                function: 2,
+                dest: 3,
+                arg_count: 0,
+            },
+        )?;
+        code.push(mem, Opcode::Return { reg: 3 })?;
+        let trampoline = Function::alloc(mem, mem.nil(), List::alloc(mem)?, false, code, None)?;
+
+        let frames = CallFrameList::alloc_with_capacity(mem, 16)?;
+        frames.push(mem, CallFrame::new_trampoline(trampoline, 0))?;
+
+        let stack = List::alloc_with_capacity(mem, 512)?;
+        stack.fill(mem, 512, mem.nil())?;
+        IndexedAnyContainer::set(&*stack, mem, 2, function)?;
+        IndexedAnyContainer::set(&*stack, mem, 3 + ENV_REG as ArraySize, mem.nil())?;
+
+        mem.alloc(Generator {
+            frames: CellPtr::new_with(frames),
+            stack: CellPtr::new_with(stack),
+            upvalues: CellPtr::new_with(Dict::alloc_uncapped(mem)?),
+            resume_location: Cell::new(None),
+            state: Cell::new(GeneratorState::Suspended),
+        })
+    }
+
+    /// Return the call frames of the evaluation
+    pub fn frames<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> ScopedPtr<'guard, CallFrameList> {
+        self.frames.get(guard)
+    }
+
+    /// Return the register stack of the evaluation
+    pub fn stack<'guard>(&self, guard: &'guard dyn MutatorScope) -> ScopedPtr<'guard, List> {
+        self.stack.get(guard)
+    }
+
+    /// Return the open upvalues of the evaluation
+    pub fn upvalues<'guard>(&self, guard: &'guard dyn MutatorScope) -> ScopedPtr<'guard, Dict> {
+        self.upvalues.get(guard)
+    }
+
+    /// Return the stack location the resume value goes in, None if the generator has not started
+    pub fn resume_location(&self) -> Option<ArraySize> {
+        self.resume_location.get()
+    }
+
+    /// Suspend the generator, to resume with the resume value in the given stack location
+    pub fn suspend(&self, location: ArraySize) {
+        self.resume_location.set(Some(location));
+        self.state.set(GeneratorState::Suspended);
+    }
+
+    /// Return where the generator is in its life
+    pub fn state(&self) -> GeneratorState {
+        self.state.get()
+    }
+
+    /// Move the generator on in its life
+    pub fn set_state(&self, state: GeneratorState) {
+        self.state.set(state)
+    }
+}
+
+impl Verify for Generator {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.object(guard, &*self.frames.get(guard))?;
+        checker.object(guard, &*self.stack.get(guard))?;
+        checker.object(guard, &*self.upvalues.get(guard))
+    }
+}
+
+impl Print for Generator {
+    fn print<'guard>(
+        &self,
+        _guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        match self.state() {
+            GeneratorState::Suspended => write!(f, "#<generator>"),
+            GeneratorState::Running => write!(f, "#<generator (running)>"),
+            GeneratorState::Finished => write!(f, "#<generator (finished)>"),
+        }
+    }
+}
+
+/// Return the Generator argument or a type error
+fn generator_arg<'guard>(
+    arg: TaggedScopedPtr<'guard>,
+) -> Result<ScopedPtr<'guard, Generator>, RuntimeError> {
+    match *arg {
+        Value::Generator(generator) => Ok(generator),
+        _ => Err(err_eval(&format!("Expected a generator, got {}", arg))),
+    }
+}
+
+/// (make-generator f) -> a new Generator that calls f with no arguments when first resumed. It is an
+/// error for f to need any.
+fn make_generator_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(Generator::alloc(mem, args[0])?.as_tagged(mem))
+}
+
+/// (resume gen v) -> the next value gen yields, or its function's result when it finishes
+fn resume_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    thread.resume_generator(mem, generator_arg(args[0])?, args[1])
+}
+
+/// (generator-done? gen) -> true if gen has finished
+fn generator_done_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let generator = generator_arg(args[0])?;
+    Ok(mem.boolean(generator.state() == GeneratorState::Finished))
+}
+
+/// Bind the generator builtins into the given globals Dict
+pub fn load<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+) -> Result<(), RuntimeError> {
+    define(mem, globals, "make-generator", 1, make_generator_fn)?;
+    define_with_thread(mem, globals, "resume", 2, resume_fn)?;
+    define(mem, globals, "generator-done?", 1, generator_done_fn)?;
+    Ok(())
+}
@@ -0,0 +1,143 @@
+/// A log of changes to global variables, for embedders that must account for every change a
+/// script makes to the state they share with it.
+///
+/// While the log of a Thread is on, see `Thread::start_global_log()`, every definition or
+/// assignment of a global variable that the Thread evaluates is recorded with the value the
+/// global had before, the value it was given, the source position of the definition or
+/// assignment, if known, and the time of the change. The values are kept reachable by the log,
+/// so an earlier value can be restored. Globals bound by the host, with `Thread::define_global()`
+/// for example, are not recorded, except by `Thread::restore_global()`.
+use std::time::SystemTime;
+
+use crate::array::ArraySize;
+use crate::containers::{IndexedAnyContainer, StackAnyContainer};
+use crate::error::{RuntimeError, SourcePos};
+use crate::heapcheck::HeapChecker;
+use crate::list::List;
+use crate::memory::MutatorView;
+use crate::safeptr::{CellPtr, MutatorScope, TaggedScopedPtr};
+
+/// A change to the binding of a global variable
+pub struct GlobalChange<'guard> {
+    /// The symbol naming the global
+    pub name: TaggedScopedPtr<'guard>,
+    /// The value before the change, None if the global was not bound
+    pub old: Option<TaggedScopedPtr<'guard>>,
+    /// The value after the change
+    pub new: TaggedScopedPtr<'guard>,
+    /// Where the definition or assignment is in the source code, if known
+    pub pos: Option<SourcePos>,
+    /// When the change was made
+    pub time: SystemTime,
+}
+
+/// What is recorded of a change besides its values
+struct Entry {
+    pos: Option<SourcePos>,
+    time: SystemTime,
+    /// True if the global was bound before the change
+    was_bound: bool,
+}
+
+/// The changes recorded so far, oldest first
+pub struct GlobalLog {
+    entries: Vec<Entry>,
+    /// The name, old value and new value of each change in turn, the old value of a global that
+    /// was not bound being nil
+    values: CellPtr<List>,
+}
+
+impl GlobalLog {
+    /// Allocate an empty log
+    pub fn alloc<'guard>(mem: &'guard MutatorView) -> Result<GlobalLog, RuntimeError> {
+        Ok(GlobalLog {
+            entries: Vec::new(),
+            values: CellPtr::new_with(List::alloc(mem)?),
+        })
+    }
+
+    /// Record a change to the named global
+    pub fn record<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        name: TaggedScopedPtr<'guard>,
+        old: Option<TaggedScopedPtr<'guard>>,
+        new: TaggedScopedPtr<'guard>,
+        pos: Option<SourcePos>,
+    ) -> Result<(), RuntimeError> {
+        let values = self.values.get(mem);
+        StackAnyContainer::push(&*values, mem, name)?;
+        StackAnyContainer::push(&*values, mem, old.unwrap_or_else(|| mem.nil()))?;
+        StackAnyContainer::push(&*values, mem, new)?;
+
+        self.entries.push(Entry {
+            pos,
+            time: SystemTime::now(),
+            was_bound: old.is_some(),
+        });
+        Ok(())
+    }
+
+    /// Return the changes recorded so far, oldest first
+    pub fn changes<'guard>(&self, guard: &'guard dyn MutatorScope) -> Vec<GlobalChange<'guard>> {
+        let values = self.values.get(guard);
+        let value = |index: usize| {
+            IndexedAnyContainer::get(&*values, guard, index as ArraySize)
+                .expect("Global log values are missing")
+        };
+
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| GlobalChange {
+                name: value(index * 3),
+                old: if entry.was_bound {
+                    Some(value(index * 3 + 1))
+                } else {
+                    None
+                },
+                new: value(index * 3 + 2),
+                pos: entry.pos,
+                time: entry.time,
+            })
+            .collect()
+    }
+
+    /// Return the number of changes recorded
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Remove the most recent change from the log and return it, None if the log is empty
+    pub fn pop<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+    ) -> Result<Option<GlobalChange<'guard>>, RuntimeError> {
+        let entry = match self.entries.pop() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        let values = self.values.get(mem);
+        let new = StackAnyContainer::pop(&*values, mem)?;
+        let old = StackAnyContainer::pop(&*values, mem)?;
+        let name = StackAnyContainer::pop(&*values, mem)?;
+
+        Ok(Some(GlobalChange {
+            name,
+            old: if entry.was_bound { Some(old) } else { None },
+            new,
+            pos: entry.pos,
+            time: entry.time,
+        }))
+    }
+
+    /// Check every value the log keeps reachable
+    pub fn verify<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.object(guard, &*self.values.get(guard))
+    }
+}
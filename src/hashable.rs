@@ -1,9 +1,317 @@
-/// Scope-guard limited Hashable trait type
-use std::hash::Hasher;
+/// Scope-guard limited Hashable trait type, and hashing of values by content.
+///
+/// Two hash functions are provided. `hash_value()` feeds a value to any std `Hasher` and is
+/// suitable for in-memory tables, but its output depends on the hasher and may change between
+/// versions. `stable_hash()` is a fixed, documented algorithm whose output will not change, for
+/// data that outlives the process such as cache keys, shard keys and content addresses.
+use std::hash::{Hash, Hasher};
 
+use crate::error::{ErrorKind, RuntimeError};
 use crate::safeptr::MutatorScope;
+use crate::taggedptr::Value;
 
 /// Similar to Hash but for use in a mutator lifetime-limited scope
 pub trait Hashable {
     fn hash<'guard, H: Hasher>(&self, _guard: &'guard dyn MutatorScope, hasher: &mut H);
 }
+
+/// Type markers that distinguish, for example, a symbol from a text with the same characters.
+/// These are also the type bytes of the stable hash encoding.
+const MARK_NIL: u8 = 0x00;
+const MARK_NUMBER: u8 = 0x01;
+const MARK_SYMBOL: u8 = 0x02;
+const MARK_TEXT: u8 = 0x03;
+const MARK_PAIR: u8 = 0x04;
+const MARK_LIST: u8 = 0x05;
+const MARK_ARRAY_U8: u8 = 0x06;
+const MARK_ARRAY_U16: u8 = 0x07;
+const MARK_ARRAY_U32: u8 = 0x08;
+const MARK_CHAR: u8 = 0x09;
+
+fn err_unhashable() -> RuntimeError {
+    RuntimeError::new(ErrorKind::UnhashableError)
+}
+
+/// Feed a value to a hasher. Nil, numbers, characters, symbols, text and pairs, lists and arrays of
+/// these are hashable, by content; other types return an error.
+pub fn hash_value<'guard, H: Hasher>(
+    guard: &'guard dyn MutatorScope,
+    value: Value<'guard>,
+    hasher: &mut H,
+) -> Result<(), RuntimeError> {
+    let mut value = value;
+
+    // loop down the tail of a pair list rather than recursing
+    loop {
+        match value {
+            Value::Nil => hasher.write_u8(MARK_NIL),
+
+            Value::Number(n) => {
+                hasher.write_u8(MARK_NUMBER);
+                hasher.write_isize(n);
+            }
+
+            Value::Char(c) => {
+                hasher.write_u8(MARK_CHAR);
+                hasher.write_u32(c as u32);
+            }
+
+            Value::Symbol(s) => {
+                hasher.write_u8(MARK_SYMBOL);
+                s.hash(guard, hasher);
+            }
+
+            Value::Text(t) => {
+                hasher.write_u8(MARK_TEXT);
+                t.hash(guard, hasher);
+            }
+
+            Value::Pair(p) => {
+                hasher.write_u8(MARK_PAIR);
+                hash_value(guard, p.first.get(guard).value(), hasher)?;
+                value = p.second.get(guard).value();
+                continue;
+            }
+
+            Value::List(l) => {
+                hasher.write_u8(MARK_LIST);
+                let items = unsafe { l.as_slice(guard) };
+                hasher.write_usize(items.len());
+                for item in items.iter() {
+                    hash_value(guard, item.get(guard).value(), hasher)?;
+                }
+            }
+
+            Value::ArrayU8(a) => {
+                hasher.write_u8(MARK_ARRAY_U8);
+                unsafe { a.as_slice(guard) }.hash(hasher);
+            }
+
+            Value::ArrayU16(a) => {
+                hasher.write_u8(MARK_ARRAY_U16);
+                unsafe { a.as_slice(guard) }.hash(hasher);
+            }
+
+            Value::ArrayU32(a) => {
+                hasher.write_u8(MARK_ARRAY_U32);
+                unsafe { a.as_slice(guard) }.hash(hasher);
+            }
+
+            _ => return Err(err_unhashable()),
+        }
+
+        return Ok(());
+    }
+}
+
+/// 64-bit FNV-1a, implemented here rather than taken from a dependency so that its output is
+/// fixed by this file
+struct StableHasher {
+    state: u64,
+}
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn new() -> StableHasher {
+        StableHasher {
+            state: StableHasher::OFFSET_BASIS,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= *byte as u64;
+            self.state = self.state.wrapping_mul(StableHasher::PRIME);
+        }
+    }
+
+    fn write_length(&mut self, length: usize) {
+        self.write(&(length as u64).to_le_bytes());
+    }
+}
+
+/// Return the version-stable hash of a value.
+///
+/// This is the 64-bit FNV-1a hash of the following encoding of the value, where lengths and
+/// numbers are 8 byte little-endian two's complement integers:
+///
+///   nil:            0x00
+///   number:         0x01 value
+///   symbol:         0x02 length utf-8-bytes
+///   text:           0x03 length utf-8-bytes
+///   pair:           0x04 encoding-of-first encoding-of-second
+///   list:           0x05 length encoding-of-each-item
+///   u8/u16/u32 arrays:  0x06/0x07/0x08 length each-item-as-1/2/4-little-endian-bytes
+///   character:      0x09 code-point-as-4-little-endian-bytes
+///
+/// Other types are not hashable.
+pub fn stable_hash<'guard>(
+    guard: &'guard dyn MutatorScope,
+    value: Value<'guard>,
+) -> Result<u64, RuntimeError> {
+    let mut hasher = StableHasher::new();
+    stable_encode(guard, value, &mut hasher)?;
+    Ok(hasher.state)
+}
+
+fn stable_encode<'guard>(
+    guard: &'guard dyn MutatorScope,
+    value: Value<'guard>,
+    hasher: &mut StableHasher,
+) -> Result<(), RuntimeError> {
+    let mut value = value;
+
+    loop {
+        match value {
+            Value::Nil => hasher.write(&[MARK_NIL]),
+
+            Value::Number(n) => {
+                hasher.write(&[MARK_NUMBER]);
+                hasher.write(&(n as i64).to_le_bytes());
+            }
+
+            Value::Char(c) => {
+                hasher.write(&[MARK_CHAR]);
+                hasher.write(&(c as u32).to_le_bytes());
+            }
+
+            Value::Symbol(s) => {
+                let s = s.as_str(guard);
+                hasher.write(&[MARK_SYMBOL]);
+                hasher.write_length(s.len());
+                hasher.write(s.as_bytes());
+            }
+
+            Value::Text(t) => {
+                let t = t.as_str(guard);
+                hasher.write(&[MARK_TEXT]);
+                hasher.write_length(t.len());
+                hasher.write(t.as_bytes());
+            }
+
+            Value::Pair(p) => {
+                hasher.write(&[MARK_PAIR]);
+                stable_encode(guard, p.first.get(guard).value(), hasher)?;
+                value = p.second.get(guard).value();
+                continue;
+            }
+
+            Value::List(l) => {
+                let items = unsafe { l.as_slice(guard) };
+                hasher.write(&[MARK_LIST]);
+                hasher.write_length(items.len());
+                for item in items.iter() {
+                    stable_encode(guard, item.get(guard).value(), hasher)?;
+                }
+            }
+
+            Value::ArrayU8(a) => {
+                let items = unsafe { a.as_slice(guard) };
+                hasher.write(&[MARK_ARRAY_U8]);
+                hasher.write_length(items.len());
+                hasher.write(items);
+            }
+
+            Value::ArrayU16(a) => {
+                let items = unsafe { a.as_slice(guard) };
+                hasher.write(&[MARK_ARRAY_U16]);
+                hasher.write_length(items.len());
+                for item in items.iter() {
+                    hasher.write(&item.to_le_bytes());
+                }
+            }
+
+            Value::ArrayU32(a) => {
+                let items = unsafe { a.as_slice(guard) };
+                hasher.write(&[MARK_ARRAY_U32]);
+                hasher.write_length(items.len());
+                for item in items.iter() {
+                    hasher.write(&item.to_le_bytes());
+                }
+            }
+
+            _ => return Err(err_unhashable()),
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(all(test, feature = "compiler"))]
+mod test {
+    use super::*;
+    use crate::dict::Dict;
+    use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::parser::parse;
+    use crate::safeptr::TaggedScopedPtr;
+    use crate::taggedptr::TaggedPtr;
+    use fnv::FnvHasher;
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    #[test]
+    fn hash_by_content() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let hash = |source: &str| -> Result<u64, RuntimeError> {
+                let mut hasher = FnvHasher::default();
+                hash_value(mem, parse(mem, source)?.value(), &mut hasher)?;
+                Ok(hasher.finish())
+            };
+
+            // separately parsed lists are distinct objects with the same content
+            assert!(hash("(a (b c) d)")? == hash("(a (b c) d)")?);
+            assert!(hash("(a (b c) d)")? != hash("(a (b d) c)")?);
+            assert!(hash("(a b)")? != hash("(a . b)")?);
+
+            let dict = Dict::alloc(mem)?;
+            let mut hasher = FnvHasher::default();
+            assert!(hash_value(mem, dict.as_tagged(mem).value(), &mut hasher).is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn stable_hash_known_values() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // these values are part of the stable hash definition and must never change
+            assert!(stable_hash(mem, mem.nil().value())? == 0xaf63_bd4c_8601_b7df);
+
+            let one = TaggedScopedPtr::new(mem, TaggedPtr::number(1));
+            assert!(stable_hash(mem, one.value())? == 0x7194_f3e5_9ae4_7dcd);
+
+            let symbol = mem.lookup_sym("abc");
+            assert!(stable_hash(mem, symbol.value())? == 0xb92d_2214_d0da_3152);
+
+            let list = parse(mem, "(a b)")?;
+            assert!(stable_hash(mem, list.value())? == 0x36ee_069e_7516_9a3c);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+}
@@ -6,13 +6,20 @@ use stickyimmix::{
 
 use crate::array::{ArrayU16, ArrayU32, ArrayU8};
 use crate::bytecode::{ArrayOpcode, ByteCode, InstructionStream};
+use crate::continuation::Continuation;
+use crate::deque::Deque;
 use crate::dict::Dict;
-use crate::function::{Function, Partial};
+use crate::function::{Function, NativeFunction, Partial};
+use crate::generator::Generator;
 use crate::list::List;
 use crate::memory::HeapStorage;
 use crate::number::NumberObject;
 use crate::pair::Pair;
+use crate::parameter::Parameter;
 use crate::pointerops::{AsNonNull, Tagged};
+use crate::port::Port;
+use crate::priorityqueue::PriorityQueue;
+use crate::sortedmap::SortedMap;
 use crate::symbol::Symbol;
 use crate::taggedptr::FatPtr;
 use crate::text::Text;
@@ -41,6 +48,14 @@ pub enum TypeList {
     CallFrameList,
     Thread,
     Upvalue,
+    NativeFunction,
+    SortedMap,
+    PriorityQueue,
+    Deque,
+    Parameter,
+    Port,
+    Continuation,
+    Generator,
 }
 
 // Mark this as a Stickyimmix type-identifier type
@@ -63,7 +78,8 @@ impl ObjectHeader {
         // Only Object* types should be derived from the header.
         // Symbol, Pair and Number should have been derived from a pointer tag.
         //
-        // NOTE any type that is a runtime dynamic type must be added to the below list
+        // NOTE any type that is a runtime dynamic type must be added to the below list and to
+        // `is_object_type()`
         match self.type_id {
             TypeList::NumberObject => {
                 FatPtr::NumberObject(RawPtr::untag(object_addr.cast::<NumberObject>()))
@@ -77,10 +93,55 @@ impl ObjectHeader {
             TypeList::Function => FatPtr::Function(RawPtr::untag(object_addr.cast::<Function>())),
             TypeList::Partial => FatPtr::Partial(RawPtr::untag(object_addr.cast::<Partial>())),
             TypeList::Upvalue => FatPtr::Upvalue(RawPtr::untag(object_addr.cast::<Upvalue>())),
+            TypeList::NativeFunction => {
+                FatPtr::NativeFunction(RawPtr::untag(object_addr.cast::<NativeFunction>()))
+            }
+            TypeList::SortedMap => {
+                FatPtr::SortedMap(RawPtr::untag(object_addr.cast::<SortedMap>()))
+            }
+            TypeList::PriorityQueue => {
+                FatPtr::PriorityQueue(RawPtr::untag(object_addr.cast::<PriorityQueue>()))
+            }
+            TypeList::Deque => FatPtr::Deque(RawPtr::untag(object_addr.cast::<Deque>())),
+            TypeList::Parameter => {
+                FatPtr::Parameter(RawPtr::untag(object_addr.cast::<Parameter>()))
+            }
+            TypeList::Port => FatPtr::Port(RawPtr::untag(object_addr.cast::<Port>())),
+            TypeList::Continuation => {
+                FatPtr::Continuation(RawPtr::untag(object_addr.cast::<Continuation>()))
+            }
+            TypeList::Generator => {
+                FatPtr::Generator(RawPtr::untag(object_addr.cast::<Generator>()))
+            }
 
             _ => panic!("Invalid ObjectHeader type tag {:?}!", self.type_id),
         }
     }
+
+    /// Return true if the header type can be converted by `get_object_fatptr()`
+    pub fn is_object_type(&self) -> bool {
+        match self.type_id {
+            TypeList::NumberObject
+            | TypeList::Text
+            | TypeList::ArrayU8
+            | TypeList::ArrayU16
+            | TypeList::ArrayU32
+            | TypeList::List
+            | TypeList::Dict
+            | TypeList::Function
+            | TypeList::Partial
+            | TypeList::Upvalue
+            | TypeList::NativeFunction
+            | TypeList::SortedMap
+            | TypeList::PriorityQueue
+            | TypeList::Deque
+            | TypeList::Parameter
+            | TypeList::Port
+            | TypeList::Continuation
+            | TypeList::Generator => true,
+            _ => false,
+        }
+    }
 }
 
 impl AsNonNull for ObjectHeader {}
@@ -157,3 +218,11 @@ declare_allocobject!(Partial, Partial);
 declare_allocobject!(CallFrameList, CallFrameList);
 declare_allocobject!(Thread, Thread);
 declare_allocobject!(Upvalue, Upvalue);
+declare_allocobject!(NativeFunction, NativeFunction);
+declare_allocobject!(SortedMap, SortedMap);
+declare_allocobject!(PriorityQueue, PriorityQueue);
+declare_allocobject!(Deque, Deque);
+declare_allocobject!(Parameter, Parameter);
+declare_allocobject!(Port, Port);
+declare_allocobject!(Continuation, Continuation);
+declare_allocobject!(Generator, Generator);
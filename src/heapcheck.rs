@@ -0,0 +1,301 @@
+/// Heap verification.
+///
+/// Walks every object reachable from a root, checking that each heap object's header carries the
+/// type id expected of the pointer that refers to it and that container backing arrays are intact.
+/// An object that was not rooted and whose memory has since been reused shows up here as a header
+/// inconsistency long before it would otherwise cause a crash.
+///
+/// With the `gc-stress` feature enabled, the VM verifies everything reachable from the Thread
+/// before executing each instruction.
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::ptr::NonNull;
+
+use stickyimmix::{AllocHeader, AllocObject, AllocRaw};
+
+use crate::error::{ErrorKind, RuntimeError};
+use crate::headers::{ObjectHeader, TypeList};
+use crate::memory::HeapStorage;
+use crate::rawarray::{ArraySize, RawArray};
+use crate::safeptr::MutatorScope;
+use crate::taggedptr::{FatPtr, TaggedPtr, Value};
+
+/// Return a heap verification error
+pub fn err_heap(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::HeapError(String::from(reason)))
+}
+
+/// Heap object types implement this to report the pointers they contain to the checker
+pub trait Verify {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError>;
+}
+
+/// Return the header of a heap allocated object
+fn header_of<T>(object: &T) -> &ObjectHeader {
+    let untyped = NonNull::from(object).cast::<()>();
+    unsafe { &*HeapStorage::get_header(untyped).as_ptr() }
+}
+
+/// Heap verification state: which objects have already been checked
+pub struct HeapChecker {
+    visited: HashSet<usize>,
+}
+
+impl HeapChecker {
+    pub fn new() -> HeapChecker {
+        HeapChecker {
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Number of distinct heap objects checked so far
+    pub fn objects(&self) -> usize {
+        self.visited.len()
+    }
+
+    /// Return true if the object was reached by the checks so far
+    pub fn reached<T>(&self, object: &T) -> bool {
+        self.visited.contains(&(object as *const T as usize))
+    }
+
+    /// Check a tagged pointer and everything reachable from it
+    pub fn tagged<'guard>(
+        &mut self,
+        guard: &'guard dyn MutatorScope,
+        ptr: TaggedPtr,
+    ) -> Result<(), RuntimeError> {
+        // the tag must agree with the header before it is safe to expand into a Value
+        ptr.verify_tag()?;
+
+        match FatPtr::from(ptr).as_value(guard) {
+            Value::Nil | Value::Number(_) | Value::Char(_) | Value::Symbol(_) => Ok(()),
+            Value::Pair(p) => self.object(guard, &*p),
+            Value::NumberObject(n) => self.object(guard, &*n),
+            Value::Text(t) => self.object(guard, &*t),
+            Value::List(l) => self.object(guard, &*l),
+            Value::ArrayU8(a) => self.object(guard, &*a),
+            Value::ArrayU16(a) => self.object(guard, &*a),
+            Value::ArrayU32(a) => self.object(guard, &*a),
+            Value::Dict(d) => self.object(guard, &*d),
+            Value::Function(f) => self.object(guard, &*f),
+            Value::Partial(p) => self.object(guard, &*p),
+            Value::Upvalue(u) => self.object(guard, &*u),
+            Value::NativeFunction(n) => self.object(guard, &*n),
+            Value::SortedMap(m) => self.object(guard, &*m),
+            Value::PriorityQueue(q) => self.object(guard, &*q),
+            Value::Deque(q) => self.object(guard, &*q),
+            Value::Parameter(p) => self.object(guard, &*p),
+            Value::Port(p) => self.object(guard, &*p),
+            Value::Continuation(k) => self.object(guard, &*k),
+            Value::Generator(g) => self.object(guard, &*g),
+        }
+    }
+
+    /// Check a heap object's header and, if it has not been seen before, everything reachable
+    /// from it
+    pub fn object<'guard, T>(
+        &mut self,
+        guard: &'guard dyn MutatorScope,
+        object: &T,
+    ) -> Result<(), RuntimeError>
+    where
+        T: AllocObject<TypeList> + Verify,
+    {
+        let address = object as *const T as usize;
+        if self.visited.contains(&address) {
+            return Ok(());
+        }
+
+        let header = header_of(object);
+
+        if header.type_id() != T::TYPE_ID {
+            return Err(err_heap(&format!(
+                "object at {:#x} expected to be {:?} but header says {:?}",
+                address,
+                T::TYPE_ID,
+                header.type_id()
+            )));
+        }
+
+        if (header.size() as usize) < size_of::<T>() {
+            return Err(err_heap(&format!(
+                "{:?} object at {:#x} has header size {}, expected at least {}",
+                T::TYPE_ID,
+                address,
+                header.size(),
+                size_of::<T>()
+            )));
+        }
+
+        self.visited.insert(address);
+        object.verify_children(guard, self)
+    }
+
+    /// Check the backing array of a container holding `length` items
+    pub fn raw_array<T>(
+        &mut self,
+        array: &RawArray<T>,
+        length: ArraySize,
+    ) -> Result<(), RuntimeError> {
+        if length > array.capacity() {
+            return Err(err_heap(&format!(
+                "container length {} exceeds capacity {}",
+                length,
+                array.capacity()
+            )));
+        }
+
+        if let Some(ptr) = array.as_ptr() {
+            let header = header_of(unsafe { &*ptr });
+
+            if header.type_id() != TypeList::Array {
+                return Err(err_heap(&format!(
+                    "array backing at {:#x} has header type {:?}",
+                    ptr as usize,
+                    header.type_id()
+                )));
+            }
+
+            let required = array.capacity() as usize * size_of::<T>();
+            if (header.size() as usize) < required {
+                return Err(err_heap(&format!(
+                    "array backing at {:#x} has header size {}, expected at least {}",
+                    ptr as usize,
+                    header.size(),
+                    required
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "compiler"))]
+mod test {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::containers::{FillAnyContainer, HashIndexedAnyContainer};
+    use crate::dict::Dict;
+    use crate::list::List;
+    use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::pair::Pair;
+    use crate::parser::parse;
+    use crate::vm::Thread;
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    #[test]
+    fn heapcheck_walks_containers() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let pair = Pair::new();
+            pair.first.set(mem.lookup_sym("a"));
+            pair.second.set(mem.alloc_tagged(Pair::new())?);
+            let pair = mem.alloc_tagged(pair)?;
+
+            let list = List::alloc(mem)?;
+            FillAnyContainer::fill(&*list, mem, 3, pair)?;
+
+            let dict = Dict::alloc(mem)?;
+            dict.assoc(mem, mem.lookup_sym("key"), list.as_tagged(mem))?;
+
+            let mut checker = HeapChecker::new();
+            checker.tagged(mem, dict.as_tagged(mem).get_ptr())?;
+
+            // dict, list and two pairs - the list refers to the same pair three times
+            assert!(checker.objects() == 4);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn heapcheck_detects_wrong_header() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            // a Dict masquerading as a List
+            let dict = Dict::alloc(mem)?;
+            let fake_list: &List = unsafe { &*(&*dict as *const Dict as *const List) };
+
+            let mut checker = HeapChecker::new();
+            let result = checker.object(mem, fake_list);
+
+            match result {
+                Err(e) => match e.error_kind() {
+                    ErrorKind::HeapError(_) => (),
+                    _ => panic!("unexpected error kind"),
+                },
+                Ok(_) => panic!("heap corruption not detected"),
+            }
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn heapcheck_thread_roots() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            for source in &["(def f (x) (cons x '(b c)))", "(set 'y (f 'a))"] {
+                t.quick_vm_eval(mem, compile(mem, parse(mem, source)?)?)?;
+            }
+
+            let objects = t.verify_heap(mem)?;
+            assert!(objects > 0);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn heapcheck_sweeps_shared_texts() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let kept = mem.text("kept")?;
+            let dropped = mem.text("dropped")?;
+
+            let dict = Dict::alloc(mem)?;
+            dict.assoc(mem, mem.lookup_sym("key"), kept)?;
+
+            let mut checker = HeapChecker::new();
+            checker.tagged(mem, dict.as_tagged(mem).get_ptr())?;
+            mem.sweep_shared_texts(&checker);
+
+            // a reachable Text is still shared, an unreachable one is no longer handed out
+            assert!(mem.text("kept")?.get_ptr() == kept.get_ptr());
+            assert!(mem.text("dropped")?.get_ptr() != dropped.get_ptr());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+}
@@ -0,0 +1,362 @@
+/// Infix expression islands, for configuration and rules written as arithmetic rather than as
+/// s-expressions.
+///
+/// The lexer reads `#[ ... ]` as an infix expression and replaces it with the tokens of the
+/// equivalent prefix expression, so `#[ 1 + 2 * x ]` is read as `(+ 1 (* 2 x))`. Operators, from
+/// lowest to highest precedence:
+///
+/// | operators                         | `a op b` is read as                     |
+/// |-----------------------------------|-----------------------------------------|
+/// | `<` `>` `<=` `>=` `=` `==` `!=`   | `(op a b)`, but `(= a b)` for `==` and  |
+/// |                                   | `(not (= a b))` for `!=`                |
+/// | `+` `-`                           | `(op a b)`                              |
+/// | `*` `/` `%`                       | `(op a b)`, but `(mod a b)` for `%`     |
+/// | unary `-`                         | `(- a)` for `-a`                        |
+///
+/// Arithmetic operators associate to the left and comparisons do not chain. Operands are
+/// integers, names, parenthesized expressions and function calls written `f(a, b)`, which are read
+/// as `(f a b)`. A name begins with a letter or `_` and continues with letters, digits, `_` and
+/// `?`, so a variable whose name contains `-` cannot be used inside an island.
+use crate::error::{err_lexer, spos, RuntimeError, SourcePos};
+use crate::lexer::{integer_literal, Token, TokenType};
+
+/// Comparison operators, which share the lowest precedence
+const COMPARISONS: [&str; 7] = ["<", ">", "<=", ">=", "=", "==", "!="];
+
+/// A lexical unit of an infix expression
+#[derive(Clone, Debug, PartialEq)]
+enum Infix {
+    /// An Integer or, if it is too large to store inline, a Number token
+    Integer(TokenType),
+    Name(String),
+    Operator(&'static str),
+    OpenParen,
+    CloseParen,
+    Comma,
+}
+
+/// Operators, longest first so that `<=` is not read as `<` followed by `=`
+const OPERATORS: [&str; 12] = [
+    "<=", ">=", "==", "!=", "<", ">", "=", "+", "-", "*", "/", "%",
+];
+
+/// Split the source of an infix expression beginning at the given position into lexical units
+fn scan(source: &str, begin: SourcePos) -> Result<Vec<(SourcePos, Infix)>, RuntimeError> {
+    let mut units = Vec::new();
+
+    let mut line = begin.line;
+    let mut column = begin.column;
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        let pos = spos(line, column);
+
+        if c == '\n' {
+            chars.next();
+            line += 1;
+            column = 0;
+            continue;
+        }
+
+        if c == ' ' || c == '\r' {
+            chars.next();
+            column += 1;
+            continue;
+        }
+
+        if c.is_ascii_digit() || c.is_alphabetic() || c == '_' {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' || c == '?' {
+                    word.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            column += word.chars().count() as u32;
+
+            let unit = match integer_literal(&word) {
+                Some(integer) => Infix::Integer(integer),
+                None if c.is_ascii_digit() => {
+                    return Err(err_lexer(pos, &format!("Invalid number {}", word)))
+                }
+                None => Infix::Name(word),
+            };
+            units.push((pos, unit));
+            continue;
+        }
+
+        let unit = match c {
+            '(' => Infix::OpenParen,
+            ')' => Infix::CloseParen,
+            ',' => Infix::Comma,
+            _ => {
+                let rest = chars.clone().collect::<String>();
+                match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                    Some(op) => Infix::Operator(op),
+                    None => {
+                        return Err(err_lexer(
+                            pos,
+                            &format!("Unexpected character {} in infix expression", c),
+                        ))
+                    }
+                }
+            }
+        };
+
+        let length = match unit {
+            Infix::Operator(op) => op.len(),
+            _ => 1,
+        };
+        for _ in 0..length {
+            chars.next();
+        }
+        column += length as u32;
+        units.push((pos, unit));
+    }
+
+    Ok(units)
+}
+
+/// A recursive descent parser of infix lexical units that writes prefix expression tokens
+struct InfixParser {
+    units: Vec<(SourcePos, Infix)>,
+    next: usize,
+    /// Position of the island, for errors at the end of its source
+    begin: SourcePos,
+}
+
+impl InfixParser {
+    /// Return the next lexical unit without consuming it
+    fn peek(&self) -> Option<&Infix> {
+        self.units.get(self.next).map(|(_, unit)| unit)
+    }
+
+    /// Return the position of the next lexical unit, or of the island if there are none left
+    fn pos(&self) -> SourcePos {
+        match self.units.get(self.next) {
+            Some((pos, _)) => *pos,
+            None => self.begin,
+        }
+    }
+
+    /// If the next lexical unit is one of the given operators, consume it and return it
+    fn operator(&mut self, operators: &[&'static str]) -> Option<(SourcePos, &'static str)> {
+        match self.units.get(self.next) {
+            Some((pos, Infix::Operator(op))) if operators.contains(op) => {
+                self.next += 1;
+                Some((*pos, *op))
+            }
+            _ => None,
+        }
+    }
+
+    /// Consume the next lexical unit, which must be the given one
+    fn expect(&mut self, unit: Infix, what: &str) -> Result<(), RuntimeError> {
+        if self.peek() == Some(&unit) {
+            self.next += 1;
+            Ok(())
+        } else {
+            Err(err_lexer(
+                self.pos(),
+                &format!("Expected {} in infix expression", what),
+            ))
+        }
+    }
+
+    /// comparison := additive [ ( < | > | <= | >= | = | == | != ) additive ]
+    fn comparison(&mut self) -> Result<Vec<Token>, RuntimeError> {
+        let left = self.additive()?;
+
+        match self.operator(&COMPARISONS) {
+            Some((pos, op)) => {
+                let right = self.additive()?;
+                if self.operator(&COMPARISONS).is_some() {
+                    return Err(err_lexer(
+                        pos,
+                        "Comparisons in infix expressions do not chain",
+                    ));
+                }
+
+                Ok(match op {
+                    "==" => apply(pos, "=", vec![left, right]),
+                    "!=" => apply(pos, "not", vec![apply(pos, "=", vec![left, right])]),
+                    _ => apply(pos, op, vec![left, right]),
+                })
+            }
+            None => Ok(left),
+        }
+    }
+
+    /// additive := multiplicative { ( + | - ) multiplicative }
+    fn additive(&mut self) -> Result<Vec<Token>, RuntimeError> {
+        let mut left = self.multiplicative()?;
+        while let Some((pos, op)) = self.operator(&["+", "-"]) {
+            let right = self.multiplicative()?;
+            left = apply(pos, op, vec![left, right]);
+        }
+        Ok(left)
+    }
+
+    /// multiplicative := unary { ( * | / | % ) unary }
+    fn multiplicative(&mut self) -> Result<Vec<Token>, RuntimeError> {
+        let mut left = self.unary()?;
+        while let Some((pos, op)) = self.operator(&["*", "/", "%"]) {
+            let right = self.unary()?;
+            left = apply(pos, if op == "%" { "mod" } else { op }, vec![left, right]);
+        }
+        Ok(left)
+    }
+
+    /// unary := - unary | primary
+    fn unary(&mut self) -> Result<Vec<Token>, RuntimeError> {
+        match self.operator(&["-"]) {
+            Some((pos, op)) => {
+                let operand = self.unary()?;
+                Ok(apply(pos, op, vec![operand]))
+            }
+            None => self.primary(),
+        }
+    }
+
+    /// primary := integer | name | name ( [ comparison { , comparison } ] ) | ( comparison )
+    fn primary(&mut self) -> Result<Vec<Token>, RuntimeError> {
+        let pos = self.pos();
+        let unit = match self.peek() {
+            Some(unit) => unit.clone(),
+            None => return Err(err_lexer(pos, "Incomplete infix expression")),
+        };
+        self.next += 1;
+
+        match unit {
+            Infix::Integer(token) => Ok(vec![Token { pos, token }]),
+
+            Infix::Name(name) if self.peek() == Some(&Infix::OpenParen) => {
+                self.next += 1;
+                let mut args = Vec::new();
+                if self.peek() != Some(&Infix::CloseParen) {
+                    args.push(self.comparison()?);
+                    while self.peek() == Some(&Infix::Comma) {
+                        self.next += 1;
+                        args.push(self.comparison()?);
+                    }
+                }
+                self.expect(Infix::CloseParen, ") after function arguments")?;
+                Ok(apply(pos, &name, args))
+            }
+
+            Infix::Name(name) => Ok(vec![Token {
+                pos,
+                token: TokenType::Symbol(name),
+            }]),
+
+            Infix::OpenParen => {
+                let inner = self.comparison()?;
+                self.expect(Infix::CloseParen, ")")?;
+                Ok(inner)
+            }
+
+            _ => Err(err_lexer(pos, "Expected an operand in infix expression")),
+        }
+    }
+}
+
+/// Return the tokens of the application of a function or operator to the given arguments
+fn apply(pos: SourcePos, function: &str, args: Vec<Vec<Token>>) -> Vec<Token> {
+    let mut tokens = vec![
+        Token {
+            pos,
+            token: TokenType::OpenParen,
+        },
+        Token {
+            pos,
+            token: TokenType::Symbol(String::from(function)),
+        },
+    ];
+    tokens.extend(args.into_iter().flatten());
+    tokens.push(Token {
+        pos,
+        token: TokenType::CloseParen,
+    });
+    tokens
+}
+
+/// Read the source of an infix expression island, which begins at the given position, returning
+/// the tokens of the equivalent prefix expression
+pub fn infix_tokens(source: &str, begin: SourcePos) -> Result<Vec<Token>, RuntimeError> {
+    let mut parser = InfixParser {
+        units: scan(source, begin)?,
+        next: 0,
+        begin,
+    };
+
+    let tokens = parser.comparison()?;
+    if parser.peek().is_some() {
+        return Err(err_lexer(
+            parser.pos(),
+            "Unexpected text after the end of an infix expression",
+        ));
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Return the prefix expression an infix expression is read as
+    fn read(source: &str) -> Result<String, RuntimeError> {
+        let tokens = infix_tokens(source, spos(1, 2))?;
+        let mut prefix = String::new();
+        for token in tokens {
+            let text = match token.token {
+                TokenType::OpenParen => String::from("("),
+                TokenType::CloseParen => String::from(")"),
+                TokenType::Symbol(s) => s,
+                TokenType::Integer(i) => i.to_string(),
+                other => panic!("unexpected token {:?}", other),
+            };
+            if !prefix.is_empty() && !prefix.ends_with('(') && text != ")" {
+                prefix.push(' ');
+            }
+            prefix.push_str(&text);
+        }
+        Ok(prefix)
+    }
+
+    #[test]
+    fn infix_precedence() {
+        assert_eq!(read("1 + 2 * x").unwrap(), "(+ 1 (* 2 x))");
+        assert_eq!(read("(1 + 2) * x").unwrap(), "(* (+ 1 2) x)");
+        assert_eq!(read("a - b - c").unwrap(), "(- (- a b) c)");
+        assert_eq!(read("a/b%3").unwrap(), "(mod (/ a b) 3)");
+        assert_eq!(read("-x * 2").unwrap(), "(* (- x) 2)");
+        assert_eq!(read("n + 1 <= limit").unwrap(), "(<= (+ n 1) limit)");
+        assert_eq!(read("a == b").unwrap(), "(= a b)");
+        assert_eq!(read("a != b").unwrap(), "(not (= a b))");
+        assert_eq!(
+            read("max(a, b + 1) > f()").unwrap(),
+            "(> (max a (+ b 1)) (f))"
+        );
+        assert_eq!(read("empty?(items)").unwrap(), "(empty? items)");
+    }
+
+    #[test]
+    fn infix_errors() {
+        assert!(read("").is_err());
+        assert!(read("1 +").is_err());
+        assert!(read("a < b < c").is_err());
+        assert!(read("(1 + 2").is_err());
+        assert!(read("f(1, 2").is_err());
+        assert!(read("1 2").is_err());
+        assert!(read("12abc").is_err());
+        assert!(read("a & b").is_err());
+
+        match read("1 +\n  * 2") {
+            Err(e) => assert_eq!(e.error_pos(), Some(spos(2, 2))),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+}
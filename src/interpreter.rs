@@ -0,0 +1,591 @@
+/// A single type for embedding the interpreter.
+///
+/// Evaluating code otherwise takes a Memory, a Mutator to get a MutatorView from it, a Thread,
+/// the parser, the compiler and `Thread::quick_vm_eval()`, and the results are only valid inside
+/// the mutator. An `Interpreter` wraps all of that: it keeps one Thread, whose globals last from one
+/// call to the next, and converts results to `OwnedValue`s, which hold no heap pointers and can be
+/// kept for as long as needed.
+///
+/// ```text
+/// let mut interpreter = Interpreter::new()?;
+/// interpreter.define_global("limit", &OwnedValue::Integer(10))?;
+/// let result = interpreter.eval_str("(list 'limit limit)")?;
+/// assert_eq!(result.to_string(), "(limit 10)");
+/// ```
+///
+/// Any Rust type with a `convert::ToValue` can be bound as a global, and `eval_to()` reads a
+/// result back as any type with a `convert::FromValue`, `OwnedValue` among them:
+///
+/// ```text
+/// interpreter.define_global("prices", &vec![1.5, 2.25])?;
+/// let total: f64 = interpreter.eval_to("(+ (car prices) (car (cdr prices)))")?;
+/// ```
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::character::print_char;
+use crate::compiler::compile_program_with_thread;
+use crate::convert::{FromValue, ToValue};
+use crate::diagnostic::Diagnostic;
+use crate::error::{err_eval, RuntimeError};
+use crate::function::NativeFn;
+use crate::memory::{Memory, Mutator, MutatorView};
+use crate::number::OverflowMode;
+use crate::pair::cons;
+use crate::parser::{parse_program_with_syntax, Syntax};
+use crate::safeptr::{CellPtr, TaggedScopedPtr};
+use crate::taggedptr::{TaggedPtr, Value};
+use crate::text::escape;
+use crate::vm::{Thread, UnresolvedSymbolHandler, VmLimits};
+
+/// A value copied out of the heap. Kinds of value that have no representation of their own here,
+/// such as functions and dicts, are kept as their printed form.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OwnedValue {
+    Nil,
+    /// A symbol or, if the name begins with a colon, a keyword
+    Symbol(String),
+    Integer(isize),
+    Char(char),
+    Text(String),
+    /// A list ending in nil
+    List(Vec<OwnedValue>),
+    /// A list ending in something other than nil, as in (a b . c)
+    DottedList(Vec<OwnedValue>, Box<OwnedValue>),
+    /// The printed form of any other value
+    Other(String),
+}
+
+impl OwnedValue {
+    /// Copy a value out of the heap
+    pub fn from_value<'guard>(value: TaggedScopedPtr<'guard>) -> OwnedValue {
+        match *value {
+            Value::Nil => OwnedValue::Nil,
+            Value::Symbol(s) => OwnedValue::Symbol(String::from(s.as_str(&value))),
+            Value::Number(n) => OwnedValue::Integer(n),
+            Value::Char(c) => OwnedValue::Char(c),
+            Value::Text(t) => OwnedValue::Text(String::from(t.as_str(&value))),
+
+            Value::Pair(_) => {
+                let mut items = Vec::new();
+                let mut head = value;
+                while let Value::Pair(p) = *head {
+                    items.push(OwnedValue::from_value(p.first.get(&value)));
+                    head = p.second.get(&value);
+                }
+
+                if head.is_nil() {
+                    OwnedValue::List(items)
+                } else {
+                    OwnedValue::DottedList(items, Box::new(OwnedValue::from_value(head)))
+                }
+            }
+
+            _ => OwnedValue::Other(format!("{}", value)),
+        }
+    }
+}
+
+/// A value kept as its printed form cannot be written back into the heap
+impl ToValue for OwnedValue {
+    fn to_value<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        match self {
+            OwnedValue::Nil => Ok(mem.nil()),
+
+            OwnedValue::Symbol(name) if name.len() > 1 && name.starts_with(':') => {
+                Ok(mem.lookup_keyword(&name[1..]))
+            }
+            OwnedValue::Symbol(name) => Ok(mem.lookup_sym(name)),
+
+            OwnedValue::Integer(n) => mem.convert(&(*n as i64)),
+            OwnedValue::Char(c) => Ok(TaggedScopedPtr::new(mem, TaggedPtr::char(*c))),
+            OwnedValue::Text(text) => mem.convert(text),
+
+            OwnedValue::List(items) => list_to_value(mem, items, mem.nil()),
+            OwnedValue::DottedList(items, tail) => {
+                let tail = tail.to_value(mem)?;
+                list_to_value(mem, items, tail)
+            }
+
+            OwnedValue::Other(printed) => Err(err_eval(&format!(
+                "{} cannot be written back into the heap",
+                printed
+            ))),
+        }
+    }
+}
+
+impl FromValue for OwnedValue {
+    fn from_value<'guard>(
+        _mem: &'guard MutatorView,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<OwnedValue, RuntimeError> {
+        Ok(OwnedValue::from_value(value))
+    }
+}
+
+/// Write a list of values ending in the given tail into the heap
+fn list_to_value<'guard>(
+    mem: &'guard MutatorView,
+    items: &[OwnedValue],
+    tail: TaggedScopedPtr<'guard>,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    mem.size_limits().check_vector(items.len())?;
+
+    let values = items
+        .iter()
+        .map(|item| item.to_value(mem))
+        .collect::<Result<Vec<_>, RuntimeError>>()?;
+
+    let mut list = tail;
+    for value in values.iter().rev() {
+        list = cons(mem, *value, list)?;
+    }
+    Ok(list)
+}
+
+/// Values print the same way they do in the heap
+impl fmt::Display for OwnedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let items = |f: &mut fmt::Formatter, items: &[OwnedValue]| -> fmt::Result {
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{}", item)?;
+            }
+            Ok(())
+        };
+
+        match self {
+            OwnedValue::Nil => write!(f, "nil"),
+            OwnedValue::Symbol(name) => write!(f, "{}", name),
+            OwnedValue::Integer(n) => write!(f, "{}", n),
+            OwnedValue::Char(c) => print_char(*c, f),
+            OwnedValue::Text(text) => write!(f, "\"{}\"", escape(text)),
+            OwnedValue::List(list) => {
+                write!(f, "(")?;
+                items(f, list)?;
+                write!(f, ")")
+            }
+            OwnedValue::DottedList(list, tail) => {
+                write!(f, "(")?;
+                items(f, list)?;
+                write!(f, " . {})", tail)
+            }
+            OwnedValue::Other(printed) => write!(f, "{}", printed),
+        }
+    }
+}
+
+/// An interpreter with its own heap and a Thread whose globals persist between evaluations
+pub struct Interpreter {
+    memory: Memory,
+    thread: CellPtr<Thread>,
+    limits: VmLimits,
+    syntax: Syntax,
+    overflow_mode: OverflowMode,
+    unresolved_symbol_handler: Option<UnresolvedSymbolHandler>,
+}
+
+/// A mutator that allocates the interpreter Thread
+struct NewThread {}
+
+impl Mutator for NewThread {
+    type Input = ();
+    type Output = CellPtr<Thread>;
+
+    fn run(&self, mem: &MutatorView, _input: ()) -> Result<CellPtr<Thread>, RuntimeError> {
+        Ok(CellPtr::new_with(Thread::alloc(mem)?))
+    }
+}
+
+/// A mutator that parses, compiles and evaluates source code on the interpreter Thread and
+/// converts the result to a T
+struct EvalSource<'a, T> {
+    thread: &'a CellPtr<Thread>,
+    limits: VmLimits,
+    syntax: Syntax,
+    overflow_mode: OverflowMode,
+    unresolved_symbol_handler: Option<UnresolvedSymbolHandler>,
+    result: PhantomData<T>,
+}
+
+impl<'a, T: FromValue> Mutator for EvalSource<'a, T> {
+    type Input = &'a str;
+    type Output = T;
+
+    fn run(&self, mem: &MutatorView, source: &'a str) -> Result<T, RuntimeError> {
+        let thread = self.thread.get(mem);
+        thread.set_vm_limits(self.limits);
+        thread.set_overflow_mode(self.overflow_mode);
+        thread.set_unresolved_symbol_handler(self.unresolved_symbol_handler);
+        let program = parse_program_with_syntax(mem, source, self.syntax)?;
+        let function = compile_program_with_thread(mem, &thread, program)?;
+        T::from_value(mem, thread.quick_vm_eval(mem, function)?)
+    }
+}
+
+/// A mutator that binds a global on the interpreter Thread
+struct DefineGlobal<'a> {
+    thread: &'a CellPtr<Thread>,
+}
+
+impl<'a> Mutator for DefineGlobal<'a> {
+    type Input = (&'a str, &'a dyn ToValue);
+    type Output = ();
+
+    fn run(&self, mem: &MutatorView, input: Self::Input) -> Result<(), RuntimeError> {
+        let (name, value) = input;
+        let value = value.to_value(mem)?;
+        self.thread
+            .get(mem)
+            .define_global(mem, mem.lookup_sym(name), value)
+    }
+}
+
+/// A mutator that binds a Rust function to a global on the interpreter Thread
+struct DefineNative<'a> {
+    thread: &'a CellPtr<Thread>,
+}
+
+impl<'a> Mutator for DefineNative<'a> {
+    type Input = (&'a str, u8, NativeFn);
+    type Output = ();
+
+    fn run(&self, mem: &MutatorView, input: Self::Input) -> Result<(), RuntimeError> {
+        let (name, arity, code) = input;
+        self.thread.get(mem).define_native(mem, name, arity, code)
+    }
+}
+
+/// A mutator that takes the warnings raised on the interpreter Thread
+struct TakeWarnings<'a> {
+    thread: &'a CellPtr<Thread>,
+}
+
+impl<'a> Mutator for TakeWarnings<'a> {
+    type Input = ();
+    type Output = Vec<Diagnostic>;
+
+    fn run(&self, mem: &MutatorView, _input: ()) -> Result<Vec<Diagnostic>, RuntimeError> {
+        Ok(self.thread.get(mem).take_warnings())
+    }
+}
+
+impl Interpreter {
+    /// Create an interpreter with a new heap and the builtin globals
+    pub fn new() -> Result<Interpreter, RuntimeError> {
+        let memory = Memory::new();
+        let thread = memory.mutate(&NewThread {}, ())?;
+        Ok(Interpreter {
+            memory,
+            thread,
+            limits: VmLimits::default(),
+            syntax: Syntax::Parenthesized,
+            overflow_mode: OverflowMode::Promote,
+            unresolved_symbol_handler: None,
+        })
+    }
+
+    /// Choose the syntax later calls to `eval_str()` expect, see `parser::Syntax`. The default is
+    /// parenthesized s-expressions.
+    pub fn set_syntax(&mut self, syntax: Syntax) {
+        self.syntax = syntax;
+    }
+
+    /// Cap the resources each later call to `eval_str()` may use, see `VmLimits`
+    pub fn set_limits(&mut self, limits: VmLimits) {
+        self.limits = limits;
+    }
+
+    /// Set what integer arithmetic does with results too large to store inline in later calls to
+    /// `eval_str()`, see `OverflowMode`. The default is `OverflowMode::Promote`.
+    pub fn set_overflow_mode(&mut self, mode: OverflowMode) {
+        self.overflow_mode = mode;
+    }
+
+    /// Set the function that supplies the values of global variables that are not bound in later
+    /// calls to `eval_str()`, see `UnresolvedSymbolHandler`
+    pub fn set_unresolved_symbol_handler(&mut self, handler: Option<UnresolvedSymbolHandler>) {
+        self.unresolved_symbol_handler = handler;
+    }
+
+    /// Evaluate every expression in the source code in turn, returning the value of the last.
+    /// Definitions are kept for later evaluations.
+    pub fn eval_str(&mut self, source: &str) -> Result<OwnedValue, RuntimeError> {
+        self.eval_to(source)
+    }
+
+    /// Evaluate the source code as `eval_str()` does and convert the value of the last
+    /// expression to a T, see `convert::FromValue`
+    pub fn eval_to<T: FromValue>(&mut self, source: &str) -> Result<T, RuntimeError> {
+        let eval = EvalSource {
+            thread: &self.thread,
+            limits: self.limits,
+            syntax: self.syntax,
+            overflow_mode: self.overflow_mode,
+            unresolved_symbol_handler: self.unresolved_symbol_handler,
+            result: PhantomData,
+        };
+        self.memory.mutate(&eval, source)
+    }
+
+    /// Bind a global variable to a Rust value, replacing any existing binding, see
+    /// `convert::ToValue`
+    pub fn define_global<T: ToValue>(&mut self, name: &str, value: &T) -> Result<(), RuntimeError> {
+        let define = DefineGlobal {
+            thread: &self.thread,
+        };
+        self.memory.mutate(&define, (name, value))
+    }
+
+    /// Bind a Rust function to a global name, see `Thread::define_native()`
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: u8,
+        code: NativeFn,
+    ) -> Result<(), RuntimeError> {
+        let define = DefineNative {
+            thread: &self.thread,
+        };
+        self.memory.mutate(&define, (name, arity, code))
+    }
+
+    /// Remove and return the warnings raised since they were last taken
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        let take = TakeWarnings {
+            thread: &self.thread,
+        };
+        self.memory.mutate(&take, ()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{Interpreter, OwnedValue};
+    use crate::error::{err_eval, ErrorKind, RuntimeError};
+    use crate::memory::MutatorView;
+    use crate::number::OverflowMode;
+    use crate::parser::Syntax;
+    use crate::safeptr::TaggedScopedPtr;
+    use crate::taggedptr::TaggedPtr;
+    use crate::vm::VmLimits;
+
+    #[test]
+    fn interpreter_eval_str() {
+        let mut interpreter = Interpreter::new().unwrap();
+
+        let result = interpreter.eval_str("(quote x)").unwrap();
+        assert_eq!(result, OwnedValue::Symbol(String::from("x")));
+
+        // definitions last from one evaluation to the next
+        interpreter.eval_str("(def square (n) (* n n))").unwrap();
+        let result = interpreter.eval_str("(square 7)").unwrap();
+        assert_eq!(result, OwnedValue::Integer(49));
+
+        // every expression is evaluated and the last is the result
+        let result = interpreter
+            .eval_str("(define a 1) (define b 2) (+ a b)")
+            .unwrap();
+        assert_eq!(result, OwnedValue::Integer(3));
+
+        let result = interpreter
+            .eval_str("(list \"text\" #\\c (cons 1 2) :key square)")
+            .unwrap();
+        assert_eq!(
+            result.to_string(),
+            "(\"text\" #\\c (1 . 2) :key #<fn square/1>)"
+        );
+
+        let error = interpreter.eval_str("(undefined)").unwrap_err();
+        assert!(matches!(error.error_kind(), ErrorKind::EvalError(_)));
+
+        interpreter.set_syntax(Syntax::Indented);
+        let result = interpreter
+            .eval_str("def cube (n)\n  * n n n\ncube 3")
+            .unwrap();
+        assert_eq!(result, OwnedValue::Integer(27));
+    }
+
+    #[test]
+    fn interpreter_define_global() {
+        fn triple<'guard>(
+            mem: &'guard MutatorView,
+            args: &[TaggedScopedPtr<'guard>],
+        ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+            let n = args[0].as_int().unwrap_or(0);
+            Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(n * 3)))
+        }
+
+        let mut interpreter = Interpreter::new().unwrap();
+
+        let value = OwnedValue::DottedList(
+            vec![
+                OwnedValue::Text(String::from("a")),
+                OwnedValue::List(vec![OwnedValue::Symbol(String::from(":b"))]),
+            ],
+            Box::new(OwnedValue::Integer(5)),
+        );
+        interpreter.define_global("value", &value).unwrap();
+        assert_eq!(interpreter.eval_str("value").unwrap(), value);
+        assert_eq!(
+            interpreter
+                .eval_str("(is? (car (car (cdr value))) :b)")
+                .unwrap(),
+            OwnedValue::Symbol(String::from("true"))
+        );
+
+        interpreter.define_native("triple", 1, triple).unwrap();
+        assert_eq!(
+            interpreter.eval_str("(triple 4)").unwrap(),
+            OwnedValue::Integer(12)
+        );
+
+        let function = interpreter.eval_str("triple").unwrap();
+        assert!(interpreter.define_global("copy", &function).is_err());
+
+        interpreter.eval_str("(def triple (n) n)").unwrap();
+        assert_eq!(interpreter.take_warnings().len(), 1);
+    }
+
+    #[test]
+    fn interpreter_unresolved_symbol_handler() {
+        fn cells<'guard>(
+            mem: &'guard MutatorView,
+            name: &str,
+        ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+            match name {
+                "A1" => Ok(Some(TaggedScopedPtr::new(mem, TaggedPtr::number(10)))),
+                "A2" => Ok(Some(TaggedScopedPtr::new(mem, TaggedPtr::number(32)))),
+                "B1" => Ok(Some(mem.text("total")?)),
+                "broken" => Err(err_eval("cell broken has an error")),
+                _ => Ok(None),
+            }
+        }
+
+        let mut interpreter = Interpreter::new().unwrap();
+        assert!(interpreter.eval_str("A1").is_err());
+
+        interpreter.set_unresolved_symbol_handler(Some(cells));
+        assert_eq!(
+            interpreter
+                .eval_str("(list B1 (+ A1 A2))")
+                .unwrap()
+                .to_string(),
+            "(\"total\" 42)"
+        );
+
+        // a bound global is not looked up by the handler
+        interpreter.eval_str("(define A1 1)").unwrap();
+        assert_eq!(
+            interpreter.eval_str("(+ A1 A2)").unwrap(),
+            OwnedValue::Integer(33)
+        );
+
+        assert!(interpreter.eval_str("C3").is_err());
+        assert!(interpreter.eval_str("broken").is_err());
+    }
+
+    #[test]
+    fn interpreter_vm_limits() {
+        let mut interpreter = Interpreter::new().unwrap();
+        interpreter
+            .eval_str("(def loop (n) (loop (+ n 1))) (def deep (n) (+ 1 (deep n)))")
+            .unwrap();
+        interpreter
+            .eval_str("(def grow (acc) (grow (cons acc acc)))")
+            .unwrap();
+
+        interpreter.set_limits(VmLimits {
+            max_instructions: Some(10_000),
+            max_call_depth: Some(100),
+            max_heap: None,
+        });
+
+        let exceeded = |result: Result<OwnedValue, RuntimeError>, reason: &str| match result {
+            Err(e) => match e.error_kind() {
+                ErrorKind::VmLimitExceeded(r) => r.contains(reason),
+                _ => false,
+            },
+            Ok(_) => false,
+        };
+
+        assert!(exceeded(interpreter.eval_str("(loop 0)"), "instructions"));
+        assert!(exceeded(interpreter.eval_str("(deep 0)"), "call depth"));
+
+        // with-limit cannot catch a VM limit
+        assert!(exceeded(
+            interpreter.eval_str("(with-limit 1000000 (loop 0))"),
+            "instructions"
+        ));
+
+        interpreter.set_limits(VmLimits {
+            max_heap: Some(10_000),
+            ..VmLimits::default()
+        });
+        assert!(exceeded(interpreter.eval_str("(grow nil)"), "bytes"));
+
+        // each evaluation has its own budget and the interpreter is usable after an error
+        for _ in 0..3 {
+            let result = interpreter.eval_str("(+ 1 2)").unwrap();
+            assert_eq!(result, OwnedValue::Integer(3));
+        }
+    }
+
+    #[test]
+    fn interpreter_overflow_mode() {
+        let mut interpreter = Interpreter::new().unwrap();
+        interpreter
+            .eval_str("(define big 1152921504606846976)")
+            .unwrap();
+
+        let result = interpreter.eval_str("(* big 2)").unwrap();
+        assert_eq!(result.to_string(), "2305843009213693952");
+
+        interpreter.set_overflow_mode(OverflowMode::Error);
+        match interpreter.eval_str("(* big 2)") {
+            Err(e) => assert!(matches!(e.error_kind(), ErrorKind::IntegerOverflow(_))),
+            Ok(_) => panic!("Expected an integer overflow error"),
+        }
+
+        interpreter.set_overflow_mode(OverflowMode::Wrap);
+        let result = interpreter.eval_str("(* big 2)").unwrap();
+        assert_eq!(result.to_string(), "-2305843009213693952");
+    }
+
+    #[test]
+    fn interpreter_convert() {
+        let mut interpreter = Interpreter::new().unwrap();
+
+        interpreter
+            .define_global("prices", &vec![1.5, 2.25])
+            .unwrap();
+        let total: f64 = interpreter
+            .eval_to("(+ (car prices) (car (cdr prices)))")
+            .unwrap();
+        assert_eq!(total, 3.75);
+
+        let mut config = HashMap::new();
+        config.insert(String::from("name"), String::from("evalrus"));
+        interpreter.define_global("config", &config).unwrap();
+        let name: String = interpreter.eval_to("config.name").unwrap();
+        assert_eq!(name, "evalrus");
+
+        let words: Vec<String> = interpreter.eval_to("(list \"a\" \"b\")").unwrap();
+        assert_eq!(words, vec![String::from("a"), String::from("b")]);
+        assert!(interpreter.eval_to::<i64>("'a").is_err());
+
+        // integers that do not fit inline are written as bignums
+        let big = OwnedValue::Integer(isize::max_value());
+        interpreter.define_global("big", &big).unwrap();
+        let big: i64 = interpreter.eval_to("big").unwrap();
+        assert_eq!(big, i64::max_value());
+    }
+}
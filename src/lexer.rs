@@ -1,8 +1,39 @@
 /// S-Expression lexer implementation.
 ///
-/// This isn't using any look-ahead yet and so always interprets
+/// This only looks ahead to recognize raw text and so always interprets
 /// (.symbol) as ( DOT SYMBOL )
+///
+/// Text is written between double quotes, in which `\"`, `\\`, `\n`, `\t` and `\r` are escapes for
+/// a double quote, a backslash, a newline, a tab and a carriage return. Raw text, for templates
+/// and patterns that contain double quotes or backslashes, is written `#r"..."#` and ends at the
+/// first `"#`. Any number of further `#` can follow the `r`, in which case the text ends at a `"`
+/// followed by one more `#` than that, so `#r#"a "# b"##` is the text `a "# b`. Raw text
+/// processes no escapes. Both forms may span lines.
+///
+/// A symbol made only of decimal digits, optionally preceded by a `-` or `+` sign, is an integer.
+/// An integer too large to fit in the bits a TaggedPtr leaves beside its tag is read as a heap
+/// integer. A symbol written as any other
+/// number, in the form the printer writes it, is that number: a float such as `2.5`, `1e-9` or
+/// `+inf.0`, a rational such as `-1/3`, or a decimal such as `1.50m`. See `numformat`.
+///
+/// A symbol beginning with a colon, such as `:name`, is a keyword. A lone colon is a symbol.
+///
+/// A character is written `#\` followed by the character or its name, see `character`. The
+/// character following the backslash is always part of the literal, so `#\(` is an open
+/// parenthesis character.
+///
+/// An infix expression is written between `#[` and `]` and is read as the equivalent prefix
+/// expression, see `infix`.
+use std::str::Chars;
+
+use num::bigint::BigInt;
+
+use crate::character::char_from_name;
 use crate::error::{err_lexer, spos, RuntimeError, SourcePos};
+use crate::infix::infix_tokens;
+use crate::number::Numeric;
+use crate::numformat::parse_number;
+use crate::taggedptr::{MAX_INLINE_INTEGER, MIN_INLINE_INTEGER};
 
 // key characters
 const OPEN_PAREN: char = '(';
@@ -13,13 +44,65 @@ const CR: char = '\r';
 const LF: char = '\n';
 const DOT: char = '.';
 const DOUBLE_QUOTE: char = '"';
+const BACKSLASH: char = '\\';
 const SINGLE_QUOTE: char = '\'';
+const HASH: char = '#';
+const OPEN_BRACKET: char = '[';
+const CLOSE_BRACKET: char = ']';
+
+/// If the characters following a `#` open raw text, an `r`, any number of `#` and a double quote,
+/// return the number of those `#`
+fn raw_text_hashes(mut following: Chars) -> Option<usize> {
+    if following.next() != Some('r') {
+        return None;
+    }
 
-#[derive(Debug, PartialEq)]
+    let mut hashes = 0;
+    loop {
+        match following.next() {
+            Some(HASH) => hashes += 1,
+            Some(DOUBLE_QUOTE) => return Some(hashes),
+            _ => return None,
+        }
+    }
+}
+
+/// If the symbol is written as an integer, return it as an Integer token, or as a Number token if
+/// it is too large to be stored inline
+pub fn integer_literal(symbol: &str) -> Option<TokenType> {
+    let digits = symbol
+        .strip_prefix(|c| c == '-' || c == '+')
+        .unwrap_or(symbol);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    match symbol.parse::<isize>() {
+        Ok(value) if (MIN_INLINE_INTEGER..=MAX_INLINE_INTEGER).contains(&value) => {
+            Some(TokenType::Integer(value))
+        }
+        _ => {
+            let magnitude = BigInt::parse_bytes(digits.as_bytes(), 10)?;
+            let value = if symbol.starts_with('-') {
+                -magnitude
+            } else {
+                magnitude
+            };
+            Some(TokenType::Number(Numeric::Integer(value)))
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum TokenType {
     OpenParen,
     CloseParen,
     Symbol(String),
+    Keyword(String),
+    Integer(isize),
+    /// A number that is not an integer that can be stored inline
+    Number(Numeric),
+    Char(char),
     Dot,
     Text(String),
     Quote,
@@ -106,28 +189,170 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, RuntimeError> {
                 current = chars.next();
             }
 
+            // text tracks the position of the character following it itself, as it may span
+            // lines
             Some(DOUBLE_QUOTE) => {
-                let text_begin = charno;
+                let text_begin = spos(lineno, charno);
+                charno += 1;
 
                 let mut text = String::from("");
 
                 loop {
                     current = chars.next();
-                    if let Some(c) = current {
-                        if c == DOUBLE_QUOTE {
+                    match current {
+                        Some(DOUBLE_QUOTE) => {
                             current = chars.next();
                             charno += 1;
                             break;
-                        } else {
+                        }
+                        Some(BACKSLASH) => {
+                            let escaped = match chars.next() {
+                                Some(DOUBLE_QUOTE) => DOUBLE_QUOTE,
+                                Some(BACKSLASH) => BACKSLASH,
+                                Some('n') => LF,
+                                Some('t') => TAB,
+                                Some('r') => CR,
+                                Some(c) => {
+                                    return Err(err_lexer(
+                                        spos(lineno, charno),
+                                        &format!("Unknown escape \\{} in text", c),
+                                    ))
+                                }
+                                None => {
+                                    return Err(err_lexer(
+                                        spos(lineno, charno),
+                                        "Unterminated string",
+                                    ))
+                                }
+                            };
+                            text.push(escaped);
+                            charno += 2;
+                        }
+                        Some(c) => {
+                            text.push(c);
+                            if c == LF {
+                                lineno += 1;
+                                charno = 0;
+                            } else {
+                                charno += 1;
+                            }
+                        }
+                        None => return Err(err_lexer(spos(lineno, charno), "Unterminated string")),
+                    }
+                }
+
+                tokens.push(Token::new(text_begin, Text(text)));
+                continue;
+            }
+
+            Some(HASH) if raw_text_hashes(chars.clone()).is_some() => {
+                let text_begin = spos(lineno, charno);
+                let hashes = raw_text_hashes(chars.clone()).unwrap_or(0);
+
+                // skip the r, the hashes and the double quote
+                for _ in 0..hashes + 2 {
+                    chars.next();
+                }
+                charno += hashes as u32 + 3;
+
+                let terminator = format!("{}{}", DOUBLE_QUOTE, "#".repeat(hashes + 1));
+                let mut text = String::from("");
+
+                loop {
+                    match chars.next() {
+                        Some(c) => {
                             text.push(c);
+                            if c == LF {
+                                lineno += 1;
+                                charno = 0;
+                            } else {
+                                charno += 1;
+                            }
+
+                            if text.ends_with(&terminator) {
+                                text.truncate(text.len() - terminator.len());
+                                break;
+                            }
+                        }
+                        None => return Err(err_lexer(text_begin, "Unterminated raw text")),
+                    }
+                }
+
+                current = chars.next();
+                tokens.push(Token::new(text_begin, Text(text)));
+                continue;
+            }
+
+            // an infix expression island is replaced by the tokens of its prefix expression
+            Some(HASH) if chars.clone().next() == Some(OPEN_BRACKET) => {
+                let island_begin = spos(lineno, charno);
+
+                // skip the bracket
+                chars.next();
+                charno += 2;
+                let source_begin = spos(lineno, charno);
+
+                let mut source = String::from("");
+                loop {
+                    match chars.next() {
+                        Some(CLOSE_BRACKET) => {
                             charno += 1;
+                            break;
+                        }
+                        Some(c) => {
+                            source.push(c);
+                            if c == LF {
+                                lineno += 1;
+                                charno = 0;
+                            } else {
+                                charno += 1;
+                            }
+                        }
+                        None => {
+                            return Err(err_lexer(
+                                island_begin,
+                                "Unterminated infix expression, expected ]",
+                            ))
                         }
-                    } else {
-                        return Err(err_lexer(spos(lineno, charno), "Unterminated string"));
                     }
                 }
 
-                tokens.push(Token::new(spos(lineno, text_begin), Text(text)))
+                tokens.extend(infix_tokens(&source, source_begin)?);
+                current = chars.next();
+                continue;
+            }
+
+            Some(HASH) if chars.clone().next() == Some(BACKSLASH) => {
+                let char_begin = spos(lineno, charno);
+
+                // skip the backslash and take the first character whatever it is
+                chars.next();
+                let mut name = String::new();
+                if let Some(c) = chars.next() {
+                    name.push(c);
+                    charno += 2;
+                }
+
+                loop {
+                    current = chars.next();
+                    match current {
+                        Some(c) if !is_terminating(c) => {
+                            name.push(c);
+                            charno += 1;
+                        }
+                        _ => break,
+                    }
+                }
+
+                match char_from_name(&name) {
+                    Some(c) => tokens.push(Token::new(char_begin, Char(c))),
+                    None => {
+                        return Err(err_lexer(
+                            char_begin,
+                            &format!("Unknown character #\\{}", name),
+                        ))
+                    }
+                }
             }
 
             Some(SINGLE_QUOTE) => {
@@ -156,8 +381,18 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, RuntimeError> {
                     }
                 }
 
-                // complete symbol
-                tokens.push(Token::new(spos(lineno, symbol_begin), Symbol(symbol)));
+                // complete symbol or number
+                let token = match integer_literal(&symbol) {
+                    Some(integer) => integer,
+                    None if symbol.len() > 1 && symbol.starts_with(':') => {
+                        Keyword(String::from(&symbol[1..]))
+                    }
+                    None => match parse_number(&symbol) {
+                        Some(number) => Number(number),
+                        None => Symbol(symbol),
+                    },
+                };
+                tokens.push(Token::new(spos(lineno, symbol_begin), token));
             }
 
             // EOL
@@ -172,7 +407,10 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, RuntimeError> {
 
 #[cfg(test)]
 mod test {
+    use num::rational::BigRational;
+
     use super::*;
+    use crate::decimal::Decimal;
 
     #[test]
     fn lexer_empty_string() {
@@ -243,6 +481,56 @@ mod test {
         }
     }
 
+    #[test]
+    fn lexer_raw_text() {
+        let tokens = tokenize("(a #r\"say \"hi\"\n\\d+\"# #r#\"x\"# \"##\"y\" b)").unwrap();
+        assert_eq!(
+            tokens[2],
+            Token::new(
+                spos(1, 3),
+                TokenType::Text(String::from("say \"hi\"\n\\d+"))
+            )
+        );
+        assert_eq!(
+            tokens[3],
+            Token::new(spos(2, 6), TokenType::Text(String::from("x\"# ")))
+        );
+        assert_eq!(
+            tokens[4],
+            Token::new(spos(2, 17), TokenType::Text(String::from("y")))
+        );
+        assert_eq!(
+            tokens[5],
+            Token::new(spos(2, 21), TokenType::Symbol(String::from("b")))
+        );
+
+        // a # that does not open raw text is part of a symbol
+        let tokens = tokenize("#rx #r").unwrap();
+        assert_eq!(
+            tokens[0],
+            Token::new(spos(1, 0), TokenType::Symbol(String::from("#rx")))
+        );
+        assert_eq!(
+            tokens[1],
+            Token::new(spos(1, 4), TokenType::Symbol(String::from("#r")))
+        );
+
+        assert!(tokenize("#r\"never closed\"").is_err());
+    }
+
+    #[test]
+    fn lexer_text_position_across_lines() {
+        let tokens = tokenize("(\"one\ntwo\" x)").unwrap();
+        assert_eq!(
+            tokens[1],
+            Token::new(spos(1, 1), TokenType::Text(String::from("one\ntwo")))
+        );
+        assert_eq!(
+            tokens[2],
+            Token::new(spos(2, 5), TokenType::Symbol(String::from("x")))
+        );
+    }
+
     #[test]
     fn lexer_text() {
         if let Ok(_tokens) = tokenize("(foo \"text\" bar)") {
@@ -251,4 +539,164 @@ mod test {
             assert!(false, "unexpected error")
         }
     }
+
+    #[test]
+    fn lexer_text_escapes() {
+        let tokens = tokenize(r#"("say \"hi\"\n\t\\" x)"#).unwrap();
+        assert_eq!(
+            tokens[1],
+            Token::new(
+                spos(1, 1),
+                TokenType::Text(String::from("say \"hi\"\n\t\\"))
+            )
+        );
+        assert_eq!(
+            tokens[2],
+            Token::new(spos(1, 20), TokenType::Symbol(String::from("x")))
+        );
+
+        assert!(tokenize(r#""\q""#).is_err());
+        assert!(tokenize(r#""ends in a backslash\"#).is_err());
+    }
+
+    #[test]
+    fn lexer_integers() {
+        let tokens = tokenize("(42 -7 +3 - 1+ 0x1)").unwrap();
+        assert_eq!(tokens[1], Token::new(spos(1, 1), TokenType::Integer(42)));
+        assert_eq!(tokens[2], Token::new(spos(1, 4), TokenType::Integer(-7)));
+        assert_eq!(tokens[3], Token::new(spos(1, 7), TokenType::Integer(3)));
+        assert_eq!(
+            tokens[4],
+            Token::new(spos(1, 10), TokenType::Symbol(String::from("-")))
+        );
+        assert_eq!(
+            tokens[5],
+            Token::new(spos(1, 12), TokenType::Symbol(String::from("1+")))
+        );
+        assert_eq!(
+            tokens[6],
+            Token::new(spos(1, 15), TokenType::Symbol(String::from("0x1")))
+        );
+
+        // the largest and smallest integers that fit beside the tag
+        let input = format!("{} {}", MAX_INLINE_INTEGER, MIN_INLINE_INTEGER);
+        let tokens = tokenize(&input).unwrap();
+        assert_eq!(tokens[0].token, TokenType::Integer(MAX_INLINE_INTEGER));
+        assert_eq!(tokens[1].token, TokenType::Integer(MIN_INLINE_INTEGER));
+
+        // and those just beyond them, from 2^61 up, are heap integers
+        let big = |n: &str| TokenType::Number(Numeric::Integer(n.parse::<BigInt>().unwrap()));
+        let input = format!("(a {} {})", MAX_INLINE_INTEGER + 1, MIN_INLINE_INTEGER - 1);
+        let tokens = tokenize(&input).unwrap();
+        assert_eq!(
+            tokens[2],
+            Token::new(spos(1, 3), big("2305843009213693952"))
+        );
+        assert_eq!(tokens[3].token, big("-2305843009213693953"));
+        let tokens = tokenize("2305843009213693953 +99999999999999999999999999").unwrap();
+        assert_eq!(tokens[0].token, big("2305843009213693953"));
+        assert_eq!(tokens[1].token, big("99999999999999999999999999"));
+    }
+
+    #[test]
+    fn lexer_chars() {
+        let tokens = tokenize("(#\\a #\\( #\\space #\\λ x)").unwrap();
+        assert_eq!(tokens[1], Token::new(spos(1, 1), TokenType::Char('a')));
+        assert_eq!(tokens[2], Token::new(spos(1, 5), TokenType::Char('(')));
+        assert_eq!(tokens[3], Token::new(spos(1, 9), TokenType::Char(' ')));
+        assert_eq!(tokens[4], Token::new(spos(1, 17), TokenType::Char('λ')));
+        assert_eq!(
+            tokens[5],
+            Token::new(spos(1, 21), TokenType::Symbol(String::from("x")))
+        );
+
+        assert!(tokenize("#\\nonsuch").is_err());
+        assert!(tokenize("#\\").is_err());
+    }
+
+    #[test]
+    fn lexer_keywords() {
+        let tokens = tokenize("(:foo : a:b)").unwrap();
+        assert_eq!(
+            tokens[1],
+            Token::new(spos(1, 1), TokenType::Keyword(String::from("foo")))
+        );
+        assert_eq!(
+            tokens[2],
+            Token::new(spos(1, 6), TokenType::Symbol(String::from(":")))
+        );
+        assert_eq!(
+            tokens[3],
+            Token::new(spos(1, 8), TokenType::Symbol(String::from("a:b")))
+        );
+    }
+
+    #[test]
+    fn lexer_infix_island() {
+        let tokens = tokenize("(f #[1 + x] y)").unwrap();
+        assert_eq!(tokens.len(), 9);
+        assert_eq!(tokens[2], Token::new(spos(1, 7), TokenType::OpenParen));
+        assert_eq!(
+            tokens[3],
+            Token::new(spos(1, 7), TokenType::Symbol(String::from("+")))
+        );
+        assert_eq!(tokens[4], Token::new(spos(1, 5), TokenType::Integer(1)));
+        assert_eq!(
+            tokens[5],
+            Token::new(spos(1, 9), TokenType::Symbol(String::from("x")))
+        );
+        assert_eq!(
+            tokens[7],
+            Token::new(spos(1, 12), TokenType::Symbol(String::from("y")))
+        );
+
+        // an island may span lines
+        let tokens = tokenize("#[a *\n  b] c").unwrap();
+        assert_eq!(
+            tokens[3],
+            Token::new(spos(2, 2), TokenType::Symbol(String::from("b")))
+        );
+        assert_eq!(
+            tokens[5],
+            Token::new(spos(2, 5), TokenType::Symbol(String::from("c")))
+        );
+
+        assert!(tokenize("#[1 + 2").is_err());
+    }
+
+    #[test]
+    fn lexer_numbers() {
+        let tokens = tokenize("(2.5 -1/3 1e-9 +inf.0 1.50m 4/2 1/0 1.2.3 e5)").unwrap();
+        assert_eq!(
+            tokens[1],
+            Token::new(spos(1, 1), TokenType::Number(Numeric::Float(2.5)))
+        );
+        assert_eq!(
+            tokens[2].token,
+            TokenType::Number(Numeric::Rational(BigRational::new(
+                BigInt::from(-1),
+                BigInt::from(3)
+            )))
+        );
+        assert_eq!(tokens[3].token, TokenType::Number(Numeric::Float(1e-9)));
+        assert_eq!(
+            tokens[4].token,
+            TokenType::Number(Numeric::Float(f64::INFINITY))
+        );
+        assert_eq!(
+            tokens[5].token,
+            TokenType::Number(Numeric::Decimal(Decimal::parse_literal("1.50m").unwrap()))
+        );
+
+        // a rational is read in lowest terms
+        assert_eq!(
+            tokens[6].token,
+            TokenType::Number(Numeric::Integer(BigInt::from(2)))
+        );
+
+        // anything that is not the printed form of a number is a symbol
+        for (index, name) in [(7, "1/0"), (8, "1.2.3"), (9, "e5")].iter() {
+            assert_eq!(tokens[*index].token, TokenType::Symbol(String::from(*name)));
+        }
+    }
 }
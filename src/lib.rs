@@ -0,0 +1,88 @@
+/// The evalrus interpreter as a library, for embedding in other programs.
+///
+/// `Interpreter` is the simplest way in: it owns the heap and a Thread and returns results as
+/// `OwnedValue`s or, through `FromValue` and `ToValue`, as plain Rust types. `RuleSet` compiles
+/// condition/action rules into one dispatch function, and `ListBuilder` and `DictBuilder` build
+/// runtime values for native functions. The modules below give access to everything else, as the
+/// `evalrus` binary uses them.
+extern crate blockalloc;
+extern crate fnv;
+extern crate itertools;
+extern crate num;
+#[macro_use]
+extern crate num_derive;
+#[cfg(feature = "compiler")]
+extern crate rustyline;
+extern crate stickyimmix;
+
+pub mod arena;
+pub mod array;
+pub mod builder;
+pub mod builtins;
+pub mod bytecode;
+#[cfg(feature = "compiler")]
+pub mod cellgraph;
+pub mod character;
+pub mod codec;
+pub mod compare;
+#[cfg(feature = "compiler")]
+pub mod compiler;
+pub mod containers;
+pub mod continuation;
+pub mod convert;
+pub mod debugger;
+pub mod decimal;
+pub mod deque;
+pub mod diagnostic;
+pub mod dict;
+#[cfg(feature = "digest")]
+pub mod digest;
+pub mod error;
+pub mod function;
+pub mod generator;
+pub mod globallog;
+pub mod hashable;
+pub mod headers;
+pub mod heapcheck;
+#[cfg(feature = "compiler")]
+pub mod infix;
+#[cfg(feature = "compiler")]
+pub mod interpreter;
+#[cfg(feature = "compiler")]
+pub mod lexer;
+pub mod list;
+pub mod memory;
+pub mod number;
+pub mod numformat;
+pub mod pair;
+pub mod parallel;
+pub mod parameter;
+#[cfg(feature = "compiler")]
+pub mod parser;
+pub mod pointerops;
+pub mod port;
+pub mod printer;
+pub mod priorityqueue;
+pub mod profiler;
+pub mod rawarray;
+#[cfg(feature = "compiler")]
+pub mod repl;
+pub mod replay;
+#[cfg(feature = "compiler")]
+pub mod rules;
+pub mod safeptr;
+pub mod serialize;
+pub mod sortedmap;
+pub mod symbol;
+pub mod symbolmap;
+pub mod taggedptr;
+pub mod text;
+pub mod tracer;
+pub mod vm;
+
+pub use crate::builder::{DictBuilder, ListBuilder};
+pub use crate::convert::{FromValue, ToValue};
+#[cfg(feature = "compiler")]
+pub use crate::interpreter::{Interpreter, OwnedValue};
+#[cfg(feature = "compiler")]
+pub use crate::rules::{RuleMatch, RuleSet};
@@ -1,56 +1,50 @@
-extern crate blockalloc;
 extern crate clap;
+#[cfg(feature = "compiler")]
 extern crate dirs;
-extern crate fnv;
-extern crate itertools;
-extern crate num;
-#[macro_use]
-extern crate num_derive;
+extern crate eval_rs;
+#[cfg(feature = "compiler")]
 extern crate rustyline;
-extern crate stickyimmix;
 
+#[cfg(feature = "compiler")]
 use std::fs::File;
+#[cfg(feature = "compiler")]
 use std::io;
+#[cfg(feature = "compiler")]
 use std::io::prelude::*;
+#[cfg(feature = "compiler")]
+use std::path::Path;
 use std::process;
 
-use clap::{App, Arg};
+#[cfg(not(feature = "compiler"))]
+use clap::AppSettings;
+#[cfg(feature = "compiler")]
+use clap::ArgMatches;
+use clap::{App, Arg, SubCommand};
 
+#[cfg(feature = "compiler")]
 use rustyline::error::ReadlineError;
+#[cfg(feature = "compiler")]
 use rustyline::Editor;
 
-mod arena;
-mod array;
-mod bytecode;
-mod compiler;
-mod containers;
-mod dict;
-mod error;
-mod function;
-mod hashable;
-mod headers;
-mod lexer;
-mod list;
-mod memory;
-mod number;
-mod pair;
-mod parser;
-mod pointerops;
-mod printer;
-mod rawarray;
-mod repl;
-mod safeptr;
-mod symbol;
-mod symbolmap;
-mod taggedptr;
-mod text;
-mod vm;
-
-use crate::error::RuntimeError;
-use crate::memory::Memory;
-use crate::repl::RepMaker;
+#[cfg(feature = "compiler")]
+use eval_rs::compiler::compile_program;
+#[cfg(feature = "compiler")]
+use eval_rs::diagnostic::print_diagnostic;
+use eval_rs::diagnostic::{Diagnostic, ErrorFormat};
+#[cfg(feature = "compiler")]
+use eval_rs::error::ErrorKind;
+use eval_rs::error::RuntimeError;
+use eval_rs::memory::{Memory, Mutator, MutatorView};
+#[cfg(feature = "compiler")]
+use eval_rs::parser::parse_program;
+use eval_rs::printer;
+#[cfg(feature = "compiler")]
+use eval_rs::repl::RepMaker;
+use eval_rs::serialize;
+use eval_rs::vm::Thread;
 
 /// Read a file into a String
+#[cfg(feature = "compiler")]
 fn load_file(filename: &str) -> Result<String, io::Error> {
     let mut contents = String::new();
 
@@ -60,6 +54,7 @@ fn load_file(filename: &str) -> Result<String, io::Error> {
 }
 
 /// Read and evaluate an entire file
+#[cfg(feature = "compiler")]
 fn read_file(filename: &str) -> Result<(), RuntimeError> {
     let _contents = load_file(&filename)?;
 
@@ -68,8 +63,90 @@ fn read_file(filename: &str) -> Result<(), RuntimeError> {
     Ok(())
 }
 
+/// A mutator that compiles source code and saves the function to a bytecode file
+#[cfg(feature = "compiler")]
+struct CompileFile {}
+
+#[cfg(feature = "compiler")]
+impl Mutator for CompileFile {
+    type Input = (String, String);
+    type Output = ();
+
+    fn run(&self, mem: &MutatorView, input: (String, String)) -> Result<(), RuntimeError> {
+        let (source, output) = input;
+        let function = compile_program(mem, parse_program(mem, &source)?)?;
+        serialize::save(mem, function, &output)
+    }
+}
+
+/// Compile a source file to a bytecode file, printing any compile error against the source
+#[cfg(feature = "compiler")]
+fn compile_file(input: &str, output: &str, error_format: ErrorFormat) -> Result<(), RuntimeError> {
+    let source = load_file(input)?;
+
+    let mem = Memory::new();
+    match mem.mutate(&CompileFile {}, (source.clone(), String::from(output))) {
+        Err(e) => match e.error_kind() {
+            ErrorKind::LexerError(_) | ErrorKind::ParseError(_) | ErrorKind::EvalError(_) => {
+                let diagnostic = Diagnostic::from(&e);
+                match error_format {
+                    ErrorFormat::Human => print_diagnostic(&diagnostic, &source),
+                    ErrorFormat::Json => println!("{}", diagnostic.to_json(Some(input))),
+                }
+                process::exit(1);
+            }
+            _ => Err(e),
+        },
+        result => result,
+    }
+}
+
+/// A mutator that loads a bytecode file and evaluates it, printing the result
+struct RunFile {}
+
+impl Mutator for RunFile {
+    type Input = String;
+    type Output = ();
+
+    fn run(&self, mem: &MutatorView, path: String) -> Result<(), RuntimeError> {
+        let function = serialize::load(mem, &path)?;
+        let thread = Thread::alloc(mem)?;
+        println!("{}", thread.quick_vm_eval(mem, function)?);
+        Ok(())
+    }
+}
+
+/// Print the error that terminated the program, with its backtrace and the failures that caused it
+fn print_terminated(err: &RuntimeError) {
+    eprintln!("Terminated: {}", err);
+    for line in err.backtrace_lines() {
+        eprintln!("  {}", line);
+    }
+
+    let mut cause = err.cause();
+    while let Some(err) = cause {
+        eprintln!("  caused by: {}", err);
+        cause = err.cause();
+    }
+}
+
+/// Evaluate a bytecode file, printing the result or the error
+fn run_file(path: &str, error_format: ErrorFormat) {
+    let mem = Memory::new();
+
+    mem.mutate(&RunFile {}, String::from(path))
+        .unwrap_or_else(|err| {
+            match error_format {
+                ErrorFormat::Human => print_terminated(&err),
+                ErrorFormat::Json => println!("{}", Diagnostic::from(&err).to_json(Some(path))),
+            }
+            process::exit(1);
+        });
+}
+
 /// Read a line at a time, printing the input back out
-fn read_print_loop() -> Result<(), RuntimeError> {
+#[cfg(feature = "compiler")]
+fn read_print_loop(error_format: ErrorFormat) -> Result<(), RuntimeError> {
     // establish a repl input history file path
     let history_file = match dirs::home_dir() {
         Some(mut path) => {
@@ -91,7 +168,7 @@ fn read_print_loop() -> Result<(), RuntimeError> {
     }
 
     let mem = Memory::new();
-    let rep_maker = RepMaker {};
+    let rep_maker = RepMaker { error_format };
     let rep = mem.mutate(&rep_maker, ())?;
 
     // repl
@@ -124,28 +201,121 @@ fn read_print_loop() -> Result<(), RuntimeError> {
     }
 }
 
-fn main() {
-    // parse command line argument, an optional filename
-    let matches = App::new("Eval-R-Us")
-        .about("Evaluate expressions")
-        .arg(
-            Arg::with_name("filename")
-                .help("Optional filename to read in")
-                .index(1),
-        )
-        .get_matches();
+/// Handle the command line arguments that need the compiler: compile a source file, read a
+/// source file or begin a repl
+#[cfg(feature = "compiler")]
+fn compiler_main(matches: &ArgMatches, error_format: ErrorFormat) {
+    if let Some(matches) = matches.subcommand_matches("compile") {
+        let input = matches.value_of("input").unwrap();
+        let output = match matches.value_of("output") {
+            Some(output) => String::from(output),
+            None => String::from(Path::new(input).with_extension("evc").to_string_lossy()),
+        };
 
-    if let Some(filename) = matches.value_of("filename") {
+        compile_file(input, &output, error_format).unwrap_or_else(|err| {
+            print_terminated(&err);
+            process::exit(1);
+        });
+    } else if let Some(filename) = matches.value_of("filename") {
         // if a filename was specified, read it into a String
         read_file(filename).unwrap_or_else(|err| {
-            eprintln!("Terminated: {}", err);
+            print_terminated(&err);
             process::exit(1);
         });
     } else {
         // otherwise begin a repl
-        read_print_loop().unwrap_or_else(|err| {
-            eprintln!("Terminated: {}", err);
+        read_print_loop(error_format).unwrap_or_else(|err| {
+            print_terminated(&err);
             process::exit(1);
         });
     }
 }
+
+/// Return the command line arguments, the source file and `compile` arguments only if the
+/// compiler is built in
+fn arguments() -> App<'static, 'static> {
+    let app = App::new("Eval-R-Us")
+        .about("Evaluate expressions")
+        .arg(
+            Arg::with_name("error-format")
+                .long("error-format")
+                .help("How errors and warnings are printed")
+                .takes_value(true)
+                .possible_values(&["human", "json"])
+                .default_value("human")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("sorted-keys")
+                .long("sorted-keys")
+                .help("Print dict items sorted by key, so that output is the same on every run")
+                .global(true),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Evaluate a bytecode file")
+                .arg(
+                    Arg::with_name("file")
+                        .help("Bytecode file to evaluate")
+                        .required(true)
+                        .index(1),
+                ),
+        );
+
+    #[cfg(feature = "compiler")]
+    let app = app
+        .arg(
+            Arg::with_name("filename")
+                .help("Optional filename to read in")
+                .index(1),
+        )
+        .subcommand(
+            SubCommand::with_name("compile")
+                .about("Compile a source file to a bytecode file")
+                .arg(
+                    Arg::with_name("input")
+                        .help("Source file to compile")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("Bytecode file to write, by default the input with extension .evc")
+                        .takes_value(true),
+                ),
+        );
+
+    // without the compiler there is nothing to do but run a bytecode file
+    #[cfg(not(feature = "compiler"))]
+    let app = app.setting(AppSettings::SubcommandRequiredElseHelp);
+
+    app
+}
+
+fn main() {
+    let matches = arguments().get_matches();
+
+    let error_format = matches
+        .value_of("error-format")
+        .and_then(ErrorFormat::from_name)
+        .unwrap_or(ErrorFormat::Human);
+
+    printer::set_sorted_keys(matches.is_present("sorted-keys"));
+
+    if let Some(matches) = matches.subcommand_matches("run") {
+        run_file(matches.value_of("file").unwrap(), error_format);
+        return;
+    }
+
+    #[cfg(feature = "compiler")]
+    compiler_main(&matches, error_format);
+
+    // clap already requires a subcommand, but never fall through to a silent success
+    #[cfg(not(feature = "compiler"))]
+    {
+        eprintln!("{}", matches.usage());
+        process::exit(1);
+    }
+}
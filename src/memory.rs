@@ -2,14 +2,73 @@
 ///
 /// Defines Stack, Heap and Memory types, and a MemoryView type that gives a mutator a safe
 /// view into the stack and heap.
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::mem::size_of;
+
 use stickyimmix::{AllocObject, AllocRaw, ArraySize, RawPtr, StickyImmixHeap};
 
-use crate::error::RuntimeError;
+use crate::builder::{DictBuilder, ListBuilder};
+use crate::convert::ToValue;
+use crate::error::{ErrorKind, RuntimeError};
 use crate::headers::{ObjectHeader, TypeList};
+use crate::heapcheck::HeapChecker;
 use crate::pointerops::ScopedRef;
 use crate::safeptr::{MutatorScope, ScopedPtr, TaggedScopedPtr};
 use crate::symbolmap::SymbolMap;
-use crate::taggedptr::{FatPtr, TaggedPtr};
+use crate::taggedptr::{FatPtr, TaggedPtr, Value};
+use crate::text::Text;
+
+/// Texts up to this many bytes are shared, see `MutatorView::text()`
+pub const SHARED_TEXT_LENGTH: usize = 32;
+
+/// Caps on the size of individual objects that scripts can grow, so that a script cannot take up
+/// most of the heap with one object. They are checked where those objects are created or grown and
+/// exceeding one is a `SizeLimitExceeded` error. None is no cap.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SizeLimits {
+    /// Maximum length of a Text, in bytes
+    pub text_length: Option<ArraySize>,
+    /// Maximum number of items in a list, byte vector, queue or priority queue
+    pub vector_length: Option<ArraySize>,
+    /// Maximum number of entries in a dict or sorted map
+    pub table_entries: Option<ArraySize>,
+}
+
+impl SizeLimits {
+    /// Check that a Text of the given length in bytes is within the cap
+    pub fn check_text(&self, length: usize) -> Result<(), RuntimeError> {
+        check_size("Text", "bytes", length, self.text_length)
+    }
+
+    /// Check that a list, byte vector or queue of the given length is within the cap
+    pub fn check_vector(&self, length: usize) -> Result<(), RuntimeError> {
+        check_size("Sequence", "items", length, self.vector_length)
+    }
+
+    /// Check that a dict or sorted map with the given number of entries is within the cap
+    pub fn check_table(&self, entries: usize) -> Result<(), RuntimeError> {
+        check_size("Table", "entries", entries, self.table_entries)
+    }
+}
+
+/// Return a `SizeLimitExceeded` error if the size is over the cap
+fn check_size(
+    object: &str,
+    unit: &str,
+    size: usize,
+    cap: Option<ArraySize>,
+) -> Result<(), RuntimeError> {
+    match cap {
+        Some(cap) if size > cap as usize => {
+            Err(RuntimeError::new(ErrorKind::SizeLimitExceeded(format!(
+                "{} of {} {} is over the limit of {}",
+                object, size, unit, cap
+            ))))
+        }
+        _ => Ok(()),
+    }
+}
 
 /// This type describes the mutator's view into memory - the heap and symbol name/ptr lookup.
 ///
@@ -29,6 +88,11 @@ impl<'memory> MutatorView<'memory> {
         TaggedScopedPtr::new(self, self.heap.lookup_sym(name))
     }
 
+    /// Get a keyword Symbol from its name, without the leading colon
+    pub fn lookup_keyword(&self, name: &str) -> TaggedScopedPtr<'_> {
+        TaggedScopedPtr::new(self, self.heap.lookup_keyword(name))
+    }
+
     /// Write an object into the heap and return a scope-limited pointer to it
     pub fn alloc<T>(&self, object: T) -> Result<ScopedPtr<'_, T>, RuntimeError>
     where
@@ -54,10 +118,88 @@ impl<'memory> MutatorView<'memory> {
         self.heap.alloc_array(capacity)
     }
 
+    /// Allocate a Text with the given content. Texts are immutable, so short ones are shared: a
+    /// Text of up to `SHARED_TEXT_LENGTH` bytes is only allocated the first time its content is
+    /// seen and every later request for the same content returns the same object, for as long as
+    /// it is reachable. Longer Texts are always allocated fresh.
+    pub fn text(&self, content: &str) -> Result<TaggedScopedPtr<'_>, RuntimeError> {
+        if content.len() > SHARED_TEXT_LENGTH {
+            return self.alloc_tagged(Text::new_from_str(self, content)?);
+        }
+
+        if let Some(ptr) = self.heap.texts.borrow().get(content) {
+            return Ok(TaggedScopedPtr::new(self, *ptr));
+        }
+
+        let text = self.alloc_tagged(Text::new_from_str(self, content)?)?;
+        self.heap
+            .texts
+            .borrow_mut()
+            .insert(String::from(content), text.get_ptr());
+        Ok(text)
+    }
+
+    /// Forget the shared Texts that a walk of the heap from every root did not reach, so that
+    /// the table never hands out an object that has been collected
+    pub fn sweep_shared_texts(&self, reached: &HeapChecker) {
+        self.heap
+            .texts
+            .borrow_mut()
+            .retain(|_, ptr| match *TaggedScopedPtr::new(self, *ptr) {
+                Value::Text(text) => reached.reached(&*text),
+                _ => false,
+            });
+    }
+
+    /// Write a Rust value into the heap, see `convert::ToValue`
+    pub fn convert<T: ToValue + ?Sized>(
+        &self,
+        value: &T,
+    ) -> Result<TaggedScopedPtr<'_>, RuntimeError> {
+        value.to_value(self)
+    }
+
     /// Return a nil-initialized runtime-tagged pointer
     pub fn nil(&self) -> TaggedScopedPtr<'_> {
         TaggedScopedPtr::new(self, TaggedPtr::nil())
     }
+
+    /// Return the canonical boolean for a value: the symbol `true`, or nil for false. The symbol
+    /// is interned when the heap is created so this does not look it up.
+    pub fn boolean(&self, value: bool) -> TaggedScopedPtr<'_> {
+        if value {
+            TaggedScopedPtr::new(self, self.heap.true_sym)
+        } else {
+            self.nil()
+        }
+    }
+
+    /// Return the number of bytes allocated on the heap so far, counting object headers. Nothing
+    /// is deducted when an object becomes unreachable, so the difference between two readings is
+    /// the amount allocated in between.
+    pub fn bytes_allocated(&self) -> usize {
+        self.heap.allocated.get()
+    }
+
+    /// Return the caps on object sizes
+    pub fn size_limits(&self) -> SizeLimits {
+        self.heap.limits.get()
+    }
+
+    /// Replace the caps on object sizes. The default is no caps.
+    pub fn set_size_limits(&self, limits: SizeLimits) {
+        self.heap.limits.set(limits)
+    }
+
+    /// Begin building a list, see `builder::ListBuilder`
+    pub fn list(&self) -> Result<ListBuilder<'_>, RuntimeError> {
+        Ok(ListBuilder::new(self))
+    }
+
+    /// Begin building a Dict, see `builder::DictBuilder`
+    pub fn dict(&self) -> Result<DictBuilder<'_>, RuntimeError> {
+        DictBuilder::new(self)
+    }
 }
 
 impl<'memory> MutatorScope for MutatorView<'memory> {}
@@ -69,13 +211,29 @@ pub type HeapStorage = StickyImmixHeap<ObjectHeader>;
 struct Heap {
     heap: HeapStorage,
     syms: SymbolMap,
+    /// The interned symbol `true`, preloaded for `MutatorView::boolean()`
+    true_sym: TaggedPtr,
+    /// Shared short Texts by content. The table is weak: it is not a root, so it must be swept
+    /// with `MutatorView::sweep_shared_texts()` before the memory of unreachable objects is
+    /// reused.
+    texts: RefCell<HashMap<String, TaggedPtr>>,
+    limits: Cell<SizeLimits>,
+    /// Running total of bytes allocated
+    allocated: Cell<usize>,
 }
 
 impl Heap {
     fn new() -> Heap {
+        let syms = SymbolMap::new();
+        let true_sym = TaggedPtr::symbol(syms.lookup("true"));
+
         Heap {
             heap: HeapStorage::new(),
-            syms: SymbolMap::new(),
+            syms,
+            true_sym,
+            texts: RefCell::new(HashMap::new()),
+            limits: Cell::new(SizeLimits::default()),
+            allocated: Cell::new(0),
         }
     }
 
@@ -84,11 +242,17 @@ impl Heap {
         TaggedPtr::symbol(self.syms.lookup(name))
     }
 
+    /// Get a keyword Symbol pointer from its name
+    fn lookup_keyword(&self, name: &str) -> TaggedPtr {
+        TaggedPtr::symbol(self.syms.lookup_keyword(name))
+    }
+
     /// Write an object to the heap and return the raw pointer to it
     fn alloc<T>(&self, object: T) -> Result<RawPtr<T>, RuntimeError>
     where
         T: AllocObject<TypeList>,
     {
+        self.count_allocation(size_of::<T>());
         Ok(self.heap.alloc(object)?)
     }
 
@@ -98,12 +262,20 @@ impl Heap {
         FatPtr: From<RawPtr<T>>,
         T: AllocObject<TypeList>,
     {
+        self.count_allocation(size_of::<T>());
         Ok(TaggedPtr::from(FatPtr::from(self.heap.alloc(object)?)))
     }
 
     fn alloc_array(&self, capacity: ArraySize) -> Result<RawPtr<u8>, RuntimeError> {
+        self.count_allocation(capacity as usize);
         Ok(self.heap.alloc_array(capacity)?)
     }
+
+    /// Add an object of the given size and its header to the allocation total
+    fn count_allocation(&self, size: usize) {
+        let total = self.allocated.get() + size_of::<ObjectHeader>() + size;
+        self.allocated.set(total);
+    }
 }
 
 /// Wraps a heap and provides scope-limited access to the heap
@@ -1,22 +1,764 @@
-/// An integer type - TODO
+/// The numeric tower: integers stored inline in a TaggedPtr, and heap allocated integers too large
+/// to be inline, exact decimals, exact rationals and inexact floats.
+///
+/// The levels of the tower, from lowest to highest, are integer, decimal, rational and float.
+/// Integers, decimals and rationals are exact and floats are inexact. Every arithmetic operation
+/// and comparison coerces its operands up to the level of the higher of the two before applying
+/// the operation:
+///
+/// - integer with integer gives an integer, with `/` rounding toward zero
+/// - integer or decimal with decimal gives a decimal, except that `/` gives an exact rational
+/// - integer, decimal or rational with rational gives an exact rational, with `/` exact
+/// - anything with a float gives a float
+///
+/// A rational result with a denominator of one is an integer, so a half multiplied by two is the
+/// integer 1.
+/// Coercion never goes down the tower implicitly: `inexact->exact` converts a float back to an
+/// exact number and `exact->inexact` converts the other way.
+///
+/// What happens when an integer result does not fit inline depends on the OverflowMode of the
+/// Thread: the result can wrap around within the inline range, raise an `IntegerOverflow` error, or,
+/// by default, be promoted to a heap allocated NumberObject. Results that fit inline are always
+/// stored inline, so a NumberObject never holds an integer that could be a Number. Large integers
+/// are allocated afresh by each operation, so `is?` compares them by value rather than identity.
+use std::cmp::Ordering;
 use std::fmt;
 
+use num::bigint::{BigInt, Sign};
+use num::rational::BigRational;
+use num::{Integer, One, Signed, ToPrimitive, Zero};
+
 use crate::array::Array;
+use crate::builtins::define;
+use crate::containers::{Container, SliceableContainer, StackContainer};
+use crate::decimal::Decimal;
+use crate::dict::Dict;
+use crate::error::{err_eval, ErrorKind, RuntimeError};
+use crate::heapcheck::{HeapChecker, Verify};
+use crate::memory::MutatorView;
 use crate::printer::Print;
-use crate::safeptr::MutatorScope;
+use crate::safeptr::{MutatorScope, ScopedPtr, TaggedScopedPtr};
+use crate::taggedptr::{TaggedPtr, Value, MAX_INLINE_INTEGER, MIN_INLINE_INTEGER};
+
+/// Number of bits of an inline integer
+const INLINE_INTEGER_BITS: u32 = 62;
+
+/// How integer arithmetic behaves when a result does not fit in an inline integer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OverflowMode {
+    /// Wrap around within the inline integer range, as fixed size machine integers do
+    Wrap,
+    /// Return an `IntegerOverflow` error
+    Error,
+    /// Allocate a NumberObject for the result
+    Promote,
+}
+
+/// An arithmetic operation
+#[derive(Copy, Clone, PartialEq)]
+pub enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+    /// Division, rounding toward zero when both operands are integers
+    Divide,
+    /// Remainder of division rounding toward negative infinity, taking the sign of the divisor
+    Modulo,
+}
+
+impl ArithmeticOp {
+    /// The name of the operation in source code
+    pub fn name(self) -> &'static str {
+        match self {
+            ArithmeticOp::Add => "+",
+            ArithmeticOp::Subtract => "-",
+            ArithmeticOp::Multiply => "*",
+            ArithmeticOp::Divide => "/",
+            ArithmeticOp::Modulo => "mod",
+        }
+    }
+
+    /// Apply the operation to machine integers, returning None on overflow
+    fn checked(self, left: isize, right: isize) -> Option<isize> {
+        match self {
+            ArithmeticOp::Add => left.checked_add(right),
+            ArithmeticOp::Subtract => left.checked_sub(right),
+            ArithmeticOp::Multiply => left.checked_mul(right),
+            ArithmeticOp::Divide => left.checked_div(right),
+            ArithmeticOp::Modulo => floor_modulo(left, right),
+        }
+    }
+
+    /// Apply the operation to arbitrarily large integers. The divisor must not be zero.
+    fn integer(self, left: &BigInt, right: &BigInt) -> BigInt {
+        match self {
+            ArithmeticOp::Add => left + right,
+            ArithmeticOp::Subtract => left - right,
+            ArithmeticOp::Multiply => left * right,
+            ArithmeticOp::Divide => left / right,
+            ArithmeticOp::Modulo => left.mod_floor(right),
+        }
+    }
+
+    /// Apply the operation to decimals. The divisor must not be zero.
+    fn decimal(self, left: &Decimal, right: &Decimal) -> Numeric {
+        match self {
+            ArithmeticOp::Add => Numeric::Decimal(left.add(right)),
+            ArithmeticOp::Subtract => Numeric::Decimal(left.subtract(right)),
+            ArithmeticOp::Multiply => Numeric::Decimal(left.multiply(right)),
+            ArithmeticOp::Divide => {
+                Numeric::Rational(self.rational(&left.to_rational(), &right.to_rational()))
+            }
+            ArithmeticOp::Modulo => Numeric::Decimal(left.modulo(right)),
+        }
+    }
+
+    /// Apply the operation to exact rationals. The divisor must not be zero.
+    fn rational(self, left: &BigRational, right: &BigRational) -> BigRational {
+        match self {
+            ArithmeticOp::Add => left + right,
+            ArithmeticOp::Subtract => left - right,
+            ArithmeticOp::Multiply => left * right,
+            ArithmeticOp::Divide => left / right,
+            ArithmeticOp::Modulo => left - right * (left / right).floor(),
+        }
+    }
+
+    /// Apply the operation to floats, following IEEE 754 for division by zero
+    fn float(self, left: f64, right: f64) -> f64 {
+        match self {
+            ArithmeticOp::Add => left + right,
+            ArithmeticOp::Subtract => left - right,
+            ArithmeticOp::Multiply => left * right,
+            ArithmeticOp::Divide => left / right,
+            ArithmeticOp::Modulo => left - right * (left / right).floor(),
+        }
+    }
+
+    fn is_division(self) -> bool {
+        self == ArithmeticOp::Divide || self == ArithmeticOp::Modulo
+    }
+}
+
+/// Integer division remainder taking the sign of the divisor
+fn floor_modulo(num: isize, denom: isize) -> Option<isize> {
+    let rem = num.checked_rem(denom)?;
+    if rem != 0 && (rem < 0) != (denom < 0) {
+        Some(rem + denom)
+    } else {
+        Some(rem)
+    }
+}
 
-/// TODO A heap-allocated number
+/// Reduce an integer into the inline integer range, modulo 2^62
+fn wrap_inline(value: &BigInt) -> isize {
+    let modulus = BigInt::one() << INLINE_INTEGER_BITS as usize;
+    let reduced = value
+        .mod_floor(&modulus)
+        .to_u64()
+        .expect("Reduced integer does not fit in 64 bits");
+
+    // sign-extend from the top inline integer bit
+    ((reduced << 2) as i64 >> 2) as isize
+}
+
+/// Convert an integer to the nearest float, or to an infinity if it is out of range
+fn integer_to_float(value: &BigInt) -> f64 {
+    match value.to_f64() {
+        Some(float) => float,
+        None if value.is_negative() => f64::NEG_INFINITY,
+        None => f64::INFINITY,
+    }
+}
+
+/// Convert a rational to the nearest float, scaling the numerator and denominator down together
+/// where they would not fit in a float separately
+fn rational_to_float(value: &BigRational) -> f64 {
+    let mut numer = value.numer().clone();
+    let mut denom = value.denom().clone();
+
+    while numer.bits() > 1000 && denom.bits() > 1000 {
+        numer >>= 64;
+        denom >>= 64;
+    }
+
+    integer_to_float(&numer) / integer_to_float(&denom)
+}
+
+/// A number at any level of the numeric tower, in a form that arithmetic can be done on
+#[derive(Clone, Debug, PartialEq)]
+pub enum Numeric {
+    Integer(BigInt),
+    Decimal(Decimal),
+    Rational(BigRational),
+    Float(f64),
+}
+
+impl Numeric {
+    /// The level of the number in the numeric tower
+    fn level(&self) -> u8 {
+        match self {
+            Numeric::Integer(_) => 0,
+            Numeric::Decimal(_) => 1,
+            Numeric::Rational(_) => 2,
+            Numeric::Float(_) => 3,
+        }
+    }
+
+    /// Coerce the number up to the given level of the numeric tower. A number is never coerced
+    /// down the tower.
+    fn coerce(self, level: u8) -> Numeric {
+        match (self, level) {
+            (Numeric::Integer(i), 1) => Numeric::Decimal(Decimal::from_integer(i)),
+            (Numeric::Integer(i), 2) => Numeric::Rational(BigRational::from_integer(i)),
+            (Numeric::Integer(i), 3) => Numeric::Float(integer_to_float(&i)),
+            (Numeric::Decimal(d), 2) => Numeric::Rational(d.to_rational()),
+            (Numeric::Decimal(d), 3) => Numeric::Float(d.to_float()),
+            (Numeric::Rational(r), 3) => Numeric::Float(rational_to_float(&r)),
+            (number, _) => number,
+        }
+    }
+
+    /// Return an exact rational as an integer if its denominator is one. A decimal keeps its
+    /// scale even if it is a whole number.
+    pub fn normalize(self) -> Numeric {
+        match self {
+            Numeric::Rational(ref r) if r.is_integer() => Numeric::Integer(r.to_integer()),
+            number => number,
+        }
+    }
+
+    /// Exact division by zero is an error, while float division by zero follows IEEE 754
+    fn is_exact_zero(&self) -> bool {
+        match self {
+            Numeric::Integer(i) => i.is_zero(),
+            Numeric::Decimal(d) => d.is_zero(),
+            Numeric::Rational(r) => r.is_zero(),
+            Numeric::Float(_) => false,
+        }
+    }
+
+    /// Integers, decimals and rationals are exact, floats are not
+    pub fn is_exact(&self) -> bool {
+        self.level() < 3
+    }
+
+    /// The nearest float to the number
+    pub fn to_inexact(self) -> Numeric {
+        self.coerce(3)
+    }
+
+    /// The exact number with the same value as the number, or None for an infinity or NaN
+    pub fn to_exact(self) -> Option<Numeric> {
+        match self {
+            Numeric::Float(f) => {
+                BigRational::from_float(f).map(|r| Numeric::Rational(r).normalize())
+            }
+            number => Some(number),
+        }
+    }
+
+    /// Compare two numbers after coercing them to the same level. Returns None if either is NaN.
+    pub fn partial_cmp(&self, other: &Numeric) -> Option<Ordering> {
+        let level = self.level().max(other.level());
+        match (self.clone().coerce(level), other.clone().coerce(level)) {
+            (Numeric::Integer(l), Numeric::Integer(r)) => Some(l.cmp(&r)),
+            (Numeric::Decimal(l), Numeric::Decimal(r)) => l.partial_cmp(&r),
+            (Numeric::Rational(l), Numeric::Rational(r)) => Some(l.cmp(&r)),
+            (Numeric::Float(l), Numeric::Float(r)) => l.partial_cmp(&r),
+            _ => unreachable!("Numbers coerced to the same level have different types"),
+        }
+    }
+
+    /// A total order over numbers: numerically, with NaN after every other number and an exact
+    /// number before an equal inexact number
+    pub fn total_cmp(&self, other: &Numeric) -> Ordering {
+        let is_nan = |n: &Numeric| match n {
+            Numeric::Float(f) => f.is_nan(),
+            _ => false,
+        };
+
+        match self.partial_cmp(other) {
+            Some(Ordering::Equal) => other.is_exact().cmp(&self.is_exact()),
+            Some(ordering) => ordering,
+            None => is_nan(self).cmp(&is_nan(other)),
+        }
+    }
+}
+
+/// Which level of the numeric tower a NumberObject holds
+#[derive(Copy, Clone, PartialEq)]
+enum NumberKind {
+    Integer,
+    Decimal,
+    Rational,
+    Float,
+}
+
+/// A number that cannot be stored inline in a TaggedPtr: an integer outside the inline integer
+/// range, a decimal, a rational or a float
 pub struct NumberObject {
+    kind: NumberKind,
+    negative: bool,
+    /// Magnitude of an integer, the mantissa of a decimal or the numerator of a rational, in 64
+    /// bit digits, least significant first, or the bits of a float
     value: Array<u64>,
+    /// Denominator of a rational in 64 bit digits, least significant first, otherwise empty
+    denominator: Array<u64>,
+    /// Number of digits after the decimal point of a decimal, otherwise zero
+    scale: u32,
+}
+
+/// Append the magnitude of an integer to an array as 64 bit digits, least significant first
+fn push_digits<'guard>(
+    mem: &'guard MutatorView,
+    array: &Array<u64>,
+    value: &BigInt,
+) -> Result<(), RuntimeError> {
+    let (_, digits) = value.to_u32_digits();
+    for pair in digits.chunks(2) {
+        let high = pair.get(1).cloned().unwrap_or(0) as u64;
+        array.push(mem, (high << 32) | pair[0] as u64)?;
+    }
+    Ok(())
+}
+
+/// Read an integer magnitude from an array of 64 bit digits
+fn read_digits<'guard>(guard: &'guard dyn MutatorScope, array: &Array<u64>, sign: Sign) -> BigInt {
+    let mut digits = Vec::new();
+    array.access_slice(guard, |magnitude| {
+        for digit in magnitude.iter() {
+            digits.push(*digit as u32);
+            digits.push((*digit >> 32) as u32);
+        }
+    });
+    BigInt::from_slice(sign, &digits)
+}
+
+impl NumberObject {
+    /// Allocate a NumberObject holding the given value. An integer should be outside the inline
+    /// integer range and a rational should not have a denominator of one. Use `numeric_result()`
+    /// to store values inline where possible.
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        value: &Numeric,
+    ) -> Result<ScopedPtr<'guard, NumberObject>, RuntimeError> {
+        let numerator = Array::<u64>::new();
+        let denominator = Array::<u64>::new();
+
+        let mut scale = 0;
+
+        let (kind, negative) = match value {
+            Numeric::Integer(i) => {
+                push_digits(mem, &numerator, i)?;
+                (NumberKind::Integer, i.is_negative())
+            }
+            Numeric::Decimal(d) => {
+                push_digits(mem, &numerator, d.mantissa())?;
+                scale = d.scale();
+                (NumberKind::Decimal, d.is_negative())
+            }
+            Numeric::Rational(r) => {
+                push_digits(mem, &numerator, r.numer())?;
+                push_digits(mem, &denominator, r.denom())?;
+                (NumberKind::Rational, r.is_negative())
+            }
+            Numeric::Float(f) => {
+                numerator.push(mem, f.to_bits())?;
+                (NumberKind::Float, false)
+            }
+        };
+
+        mem.alloc(NumberObject {
+            kind,
+            negative,
+            value: numerator,
+            denominator,
+            scale,
+        })
+    }
+
+    pub fn is_decimal(&self) -> bool {
+        self.kind == NumberKind::Decimal
+    }
+
+    /// Return the number in a form that arithmetic can be done on
+    pub fn value<'guard>(&self, guard: &'guard dyn MutatorScope) -> Numeric {
+        let sign = if self.negative {
+            Sign::Minus
+        } else {
+            Sign::Plus
+        };
+
+        match self.kind {
+            NumberKind::Integer => Numeric::Integer(read_digits(guard, &self.value, sign)),
+            NumberKind::Decimal => Numeric::Decimal(Decimal::new(
+                read_digits(guard, &self.value, sign),
+                self.scale,
+            )),
+            NumberKind::Rational => Numeric::Rational(BigRational::new_raw(
+                read_digits(guard, &self.value, sign),
+                read_digits(guard, &self.denominator, Sign::Plus),
+            )),
+            NumberKind::Float => {
+                let bits = self.value.access_slice(guard, |bits| bits[0]);
+                Numeric::Float(f64::from_bits(bits))
+            }
+        }
+    }
+}
+
+impl Verify for NumberObject {
+    fn verify_children<'guard>(
+        &self,
+        _guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        self.value.verify_backing(checker)?;
+        self.denominator.verify_backing(checker)
+    }
 }
 
 impl Print for NumberObject {
     fn print<'guard>(
         &self,
-        _guard: &'guard dyn MutatorScope,
+        guard: &'guard dyn MutatorScope,
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
-        // TODO
-        write!(f, "NumberObject(nan)")
+        write!(f, "{}", self.value(guard))
+    }
+}
+
+/// The printed form of a number, see `numformat`
+impl fmt::Display for Numeric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Numeric::Integer(i) => write!(f, "{}", i),
+            Numeric::Decimal(d) => write!(f, "{}m", d),
+            Numeric::Rational(r) => write!(f, "{}/{}", r.numer(), r.denom()),
+            Numeric::Float(x) if x.is_nan() => write!(f, "+nan.0"),
+            Numeric::Float(x) if x.is_infinite() => {
+                write!(f, "{}inf.0", if *x < 0.0 { "-" } else { "+" })
+            }
+            // the Debug format is the shortest that reads back as the same float and always
+            // includes a decimal point or exponent
+            Numeric::Float(x) => write!(f, "{:?}", x),
+        }
+    }
+}
+
+/// Return the value of any number
+pub fn numeric_value<'guard>(
+    guard: &'guard dyn MutatorScope,
+    value: TaggedScopedPtr<'guard>,
+) -> Option<Numeric> {
+    match *value {
+        Value::Number(n) => Some(Numeric::Integer(BigInt::from(n))),
+        Value::NumberObject(n) => Some(n.value(guard)),
+        _ => None,
+    }
+}
+
+/// Return true if both values are NumberObjects holding the same integer
+pub fn is_same_big_integer<'guard>(
+    guard: &'guard dyn MutatorScope,
+    left: TaggedScopedPtr<'guard>,
+    right: TaggedScopedPtr<'guard>,
+) -> bool {
+    match (*left, *right) {
+        (Value::NumberObject(l), Value::NumberObject(r)) => {
+            match (l.value(guard), r.value(guard)) {
+                (Numeric::Integer(l), Numeric::Integer(r)) => l == r,
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Store an integer inline if it fits, or in a NumberObject if not
+pub fn integer_result<'guard>(
+    mem: &'guard MutatorView,
+    value: BigInt,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match value.to_isize().and_then(TaggedPtr::checked_number) {
+        Some(number) => Ok(TaggedScopedPtr::new(mem, number)),
+        None => Ok(NumberObject::alloc(mem, &Numeric::Integer(value))?.as_tagged(mem)),
+    }
+}
+
+/// Store a number inline if it is an integer that fits, or in a NumberObject if not
+pub fn numeric_result<'guard>(
+    mem: &'guard MutatorView,
+    value: Numeric,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match value.normalize() {
+        Numeric::Integer(i) => integer_result(mem, i),
+        number => Ok(NumberObject::alloc(mem, &number)?.as_tagged(mem)),
+    }
+}
+
+/// Apply an arithmetic operation to two numbers after coercing them to the same level of the
+/// numeric tower. An integer result that does not fit inline is handled according to the
+/// overflow mode.
+pub fn arithmetic<'guard>(
+    mem: &'guard MutatorView,
+    mode: OverflowMode,
+    op: ArithmeticOp,
+    left: TaggedScopedPtr<'guard>,
+    right: TaggedScopedPtr<'guard>,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    // the common case of inline integers and an inline result
+    if let (Some(l), Some(r)) = (left.as_int(), right.as_int()) {
+        if op.is_division() && r == 0 {
+            return Err(err_eval("Division by zero"));
+        }
+
+        if let Some(result) = op.checked(l, r).and_then(TaggedPtr::checked_number) {
+            return Ok(TaggedScopedPtr::new(mem, result));
+        }
+    }
+
+    let (l, r) = match (numeric_value(mem, left), numeric_value(mem, right)) {
+        (Some(l), Some(r)) => (l, r),
+        _ => {
+            return Err(err_eval(&format!(
+                "Cannot apply {} to {} and {}, expected numbers",
+                op.name(),
+                left,
+                right
+            )))
+        }
+    };
+
+    if op.is_division() && r.is_exact_zero() {
+        return Err(err_eval("Division by zero"));
+    }
+
+    let level = l.level().max(r.level());
+    let result = match (l.coerce(level), r.coerce(level)) {
+        (Numeric::Float(l), Numeric::Float(r)) => {
+            return numeric_result(mem, Numeric::Float(op.float(l, r)))
+        }
+        (Numeric::Rational(l), Numeric::Rational(r)) => Numeric::Rational(op.rational(&l, &r)),
+        (Numeric::Decimal(l), Numeric::Decimal(r)) => op.decimal(&l, &r),
+        (Numeric::Integer(l), Numeric::Integer(r)) => Numeric::Integer(op.integer(&l, &r)),
+        _ => unreachable!("Numbers coerced to the same level have different types"),
+    };
+
+    let result = match result.normalize() {
+        Numeric::Integer(i) => i,
+        number => return numeric_result(mem, number),
+    };
+
+    match result.to_isize() {
+        Some(n) if n >= MIN_INLINE_INTEGER && n <= MAX_INLINE_INTEGER => {
+            Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(n)))
+        }
+        _ => match mode {
+            OverflowMode::Wrap => Ok(TaggedScopedPtr::new(
+                mem,
+                TaggedPtr::number(wrap_inline(&result)),
+            )),
+            OverflowMode::Error => Err(RuntimeError::new(ErrorKind::IntegerOverflow(format!(
+                "({} {} {})",
+                op.name(),
+                left,
+                right
+            )))),
+            OverflowMode::Promote => integer_result(mem, result),
+        },
+    }
+}
+
+/// Compare two numbers after coercing them to the same level of the numeric tower, returning
+/// None if either is NaN
+pub fn numeric_comparison<'guard>(
+    guard: &'guard dyn MutatorScope,
+    op: &str,
+    left: TaggedScopedPtr<'guard>,
+    right: TaggedScopedPtr<'guard>,
+) -> Result<Option<Ordering>, RuntimeError> {
+    if let (Some(l), Some(r)) = (left.as_int(), right.as_int()) {
+        return Ok(Some(l.cmp(&r)));
+    }
+
+    match (numeric_value(guard, left), numeric_value(guard, right)) {
+        (Some(l), Some(r)) => Ok(l.partial_cmp(&r)),
+        _ => Err(err_eval(&format!(
+            "Cannot apply {} to {} and {}, expected numbers",
+            op, left, right
+        ))),
+    }
+}
+
+/// Return the number argument or a type error
+fn number_arg<'guard>(
+    guard: &'guard dyn MutatorScope,
+    value: TaggedScopedPtr<'guard>,
+) -> Result<Numeric, RuntimeError> {
+    numeric_value(guard, value).ok_or_else(|| err_eval("Expected a number"))
+}
+
+/// (exact->inexact x) -> the nearest float to x
+fn exact_to_inexact_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    numeric_result(mem, number_arg(mem, args[0])?.to_inexact())
+}
+
+/// (inexact->exact x) -> the exact integer or rational with the same value as x
+fn inexact_to_exact_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match number_arg(mem, args[0])?.to_exact() {
+        Some(number) => numeric_result(mem, number),
+        None => Err(err_eval(&format!("{} has no exact value", args[0]))),
+    }
+}
+
+/// (exact? x) -> true if x is an integer or rational
+fn is_exact_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(mem.boolean(number_arg(mem, args[0])?.is_exact()))
+}
+
+/// (inexact? x) -> true if x is a float
+fn is_inexact_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(mem.boolean(!number_arg(mem, args[0])?.is_exact()))
+}
+
+/// Bind the numeric tower builtins into the given globals Dict
+pub fn load<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+) -> Result<(), RuntimeError> {
+    define(mem, globals, "exact->inexact", 1, exact_to_inexact_fn)?;
+    define(mem, globals, "inexact->exact", 1, inexact_to_exact_fn)?;
+    define(mem, globals, "exact?", 1, is_exact_fn)?;
+    define(mem, globals, "inexact?", 1, is_inexact_fn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::{Memory, Mutator};
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    fn num<'guard>(mem: &'guard MutatorView, n: isize) -> TaggedScopedPtr<'guard> {
+        TaggedScopedPtr::new(mem, TaggedPtr::number(n))
+    }
+
+    #[test]
+    fn number_overflow_modes() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let max = num(mem, MAX_INLINE_INTEGER);
+            let min = num(mem, MIN_INLINE_INTEGER);
+            let one = num(mem, 1);
+
+            let add = |mode| arithmetic(mem, mode, ArithmeticOp::Add, max, one);
+
+            // wrap around to the smallest inline integer
+            assert!(add(OverflowMode::Wrap)?.as_int() == Some(MIN_INLINE_INTEGER));
+            let result = arithmetic(mem, OverflowMode::Wrap, ArithmeticOp::Multiply, max, max)?;
+            assert!(result.as_int() == Some(1));
+
+            match add(OverflowMode::Error) {
+                Err(error) => match error.error_kind() {
+                    ErrorKind::IntegerOverflow(_) => (),
+                    _ => panic!("Expected an integer overflow error"),
+                },
+                Ok(_) => panic!("Expected an integer overflow error"),
+            }
+
+            // promote to a heap integer and back
+            let big = add(OverflowMode::Promote)?;
+            assert!(format!("{}", big) == "2305843009213693952");
+            let back = arithmetic(mem, OverflowMode::Error, ArithmeticOp::Subtract, big, one)?;
+            assert!(back.as_int() == Some(MAX_INLINE_INTEGER));
+
+            let small = arithmetic(mem, OverflowMode::Promote, ArithmeticOp::Multiply, min, max)?;
+            let squared = arithmetic(
+                mem,
+                OverflowMode::Promote,
+                ArithmeticOp::Multiply,
+                small,
+                small,
+            )?;
+            let small_value = match numeric_value(mem, small) {
+                Some(Numeric::Integer(i)) => i,
+                _ => panic!("Expected an integer"),
+            };
+            assert!(
+                numeric_value(mem, squared) == Some(Numeric::Integer(&small_value * &small_value))
+            );
+            let quotient = arithmetic(
+                mem,
+                OverflowMode::Promote,
+                ArithmeticOp::Divide,
+                squared,
+                small,
+            )?;
+            assert!(format!("{}", quotient) == format!("{}", small));
+
+            // division by zero is an error in every mode
+            let zero = num(mem, 0);
+            assert!(
+                arithmetic(mem, OverflowMode::Promote, ArithmeticOp::Modulo, big, zero).is_err()
+            );
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn number_tower_coercion() {
+        let half = Numeric::Rational(BigRational::new(BigInt::from(1), BigInt::from(2)));
+        let one = Numeric::Integer(BigInt::from(1));
+        let nan = Numeric::Float(f64::NAN);
+
+        assert!(half.clone().to_inexact() == Numeric::Float(0.5));
+        assert!(Numeric::Float(0.5).to_exact() == Some(half.clone()));
+        assert!(Numeric::Float(2.0).to_exact() == Some(Numeric::Integer(BigInt::from(2))));
+        assert!(nan.clone().to_exact() == None);
+
+        assert!(half.partial_cmp(&one) == Some(Ordering::Less));
+        assert!(one.partial_cmp(&Numeric::Float(1.0)) == Some(Ordering::Equal));
+        assert!(nan.partial_cmp(&one) == None);
+
+        // the total order puts exact before inexact and NaN last
+        assert!(one.total_cmp(&Numeric::Float(1.0)) == Ordering::Less);
+        assert!(nan.total_cmp(&Numeric::Float(f64::INFINITY)) == Ordering::Greater);
+        assert!(nan.total_cmp(&nan) == Ordering::Equal);
     }
 }
@@ -0,0 +1,334 @@
+/// Conversion of numbers to and from text.
+///
+/// Every conversion here is independent of the locale of the host: digits are ASCII, the decimal
+/// point is always `.` and digits are never grouped unless `format-number` is asked to. The printed
+/// form of a number, which is what `number->string` returns, reads back as the same number with
+/// `string->number` and as a literal in source code:
+///
+/// - integers as decimal digits, with a leading `-` if negative
+/// - decimals with the `m` literal suffix, keeping trailing zeros: `1.50m`
+/// - rationals as `numerator/denominator` in lowest terms: `-1/3`
+/// - floats in the shortest form that reads back as the same float, always with a `.` or an
+///   exponent so that they do not read back as integers: `0.1`, `1.0`, `1e300`. The values that
+///   are not finite are `+inf.0`, `-inf.0` and `+nan.0`.
+use num::bigint::BigInt;
+use num::rational::BigRational;
+use num::{ToPrimitive, Zero};
+
+use crate::builtins::define;
+use crate::codec::text_result;
+use crate::decimal::Decimal;
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
+use crate::memory::MutatorView;
+use crate::number::{numeric_result, numeric_value, Numeric};
+use crate::pair::vec_from_pairs;
+use crate::safeptr::{ScopedPtr, TaggedScopedPtr};
+use crate::taggedptr::Value;
+
+/// Parse an integer written as an optional sign followed by decimal digits
+fn parse_integer(source: &str) -> Option<BigInt> {
+    let (negative, digits) = match source.chars().next()? {
+        '-' => (true, &source[1..]),
+        '+' => (false, &source[1..]),
+        _ => (false, source),
+    };
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let magnitude = BigInt::parse_bytes(digits.as_bytes(), 10)?;
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+/// Parse a finite float written with digits, a sign, a decimal point and an exponent
+fn parse_float(source: &str) -> Option<f64> {
+    // the standard parser also accepts names such as "inf" and "NaN", which are not numbers here
+    let is_float_syntax = source.chars().any(|c| c.is_ascii_digit())
+        && source
+            .chars()
+            .all(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+
+    if is_float_syntax {
+        source.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Parse the printed form of any number, returning None if the text is not a number
+pub fn parse_number(source: &str) -> Option<Numeric> {
+    match source {
+        "+inf.0" => return Some(Numeric::Float(f64::INFINITY)),
+        "-inf.0" => return Some(Numeric::Float(f64::NEG_INFINITY)),
+        "+nan.0" | "-nan.0" => return Some(Numeric::Float(f64::NAN)),
+        _ => (),
+    }
+
+    if let Some(decimal) = Decimal::parse_literal(source) {
+        return Some(Numeric::Decimal(decimal));
+    }
+
+    if let Some(slash) = source.find('/') {
+        let numer = parse_integer(&source[..slash])?;
+        let denom_source = &source[slash + 1..];
+        if denom_source.starts_with('+') || denom_source.starts_with('-') {
+            return None;
+        }
+
+        let denom = parse_integer(denom_source)?;
+        if denom.is_zero() {
+            return None;
+        }
+
+        return Some(Numeric::Rational(BigRational::new(numer, denom)).normalize());
+    }
+
+    match parse_integer(source) {
+        Some(integer) => Some(Numeric::Integer(integer)),
+        None => parse_float(source).map(Numeric::Float),
+    }
+}
+
+/// How `format_number()` lays out a number
+pub struct NumberFormat {
+    /// Number of digits after the decimal point, rounding half to even, or None for all of them
+    pub precision: Option<u32>,
+    /// Inserted between each group of three digits before the decimal point
+    pub separator: String,
+    /// Separates the whole and fractional digits
+    pub point: String,
+}
+
+impl NumberFormat {
+    pub fn new() -> NumberFormat {
+        NumberFormat {
+            precision: None,
+            separator: String::new(),
+            point: String::from("."),
+        }
+    }
+}
+
+/// Format a number for people to read rather than for reading back. A float is first converted
+/// to the shortest decimal that reads back as the same float. Without a precision a rational
+/// that has no exact decimal value cannot be formatted.
+pub fn format_number(value: &Numeric, format: &NumberFormat) -> Result<String, RuntimeError> {
+    let exact = match value {
+        Numeric::Float(f) if !f.is_finite() => return Ok(format!("{}", value)),
+        Numeric::Float(f) => {
+            Numeric::Decimal(Decimal::from_float(*f).expect("A finite float has no decimal value"))
+        }
+        number => number.clone(),
+    };
+
+    let decimal = match (exact, format.precision) {
+        (Numeric::Integer(i), None) => Decimal::from_integer(i),
+        (Numeric::Decimal(d), None) => d,
+        (Numeric::Rational(r), None) => match Decimal::from_rational(&r) {
+            Some(d) => d,
+            None => {
+                return Err(err_eval(&format!(
+                    "{}/{} has no exact decimal value, give a precision to format it",
+                    r.numer(),
+                    r.denom()
+                )))
+            }
+        },
+        (Numeric::Integer(i), Some(places)) => {
+            Decimal::round_half_even(&BigRational::from_integer(i), places)
+        }
+        (Numeric::Decimal(d), Some(places)) => Decimal::round_half_even(&d.to_rational(), places),
+        (Numeric::Rational(r), Some(places)) => Decimal::round_half_even(&r, places),
+        (Numeric::Float(_), _) => unreachable!("Floats are converted to decimals"),
+    };
+
+    let digits = format!("{}", decimal);
+    let (sign, unsigned) = match digits.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", &digits[..]),
+    };
+
+    let (whole, fraction) = match unsigned.find('.') {
+        Some(point) => (&unsigned[..point], Some(&unsigned[point + 1..])),
+        None => (unsigned, None),
+    };
+
+    let mut result = String::from(sign);
+    for (index, digit) in whole.chars().enumerate() {
+        if index > 0 && (whole.len() - index) % 3 == 0 {
+            result.push_str(&format.separator);
+        }
+        result.push(digit);
+    }
+
+    if let Some(fraction) = fraction {
+        result.push_str(&format.point);
+        result.push_str(fraction);
+    }
+
+    Ok(result)
+}
+
+/// (number->string x) -> the printed form of x as text, which string->number reads back as x
+fn number_to_string_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match *args[0] {
+        Value::Number(_) | Value::NumberObject(_) => text_result(mem, &format!("{}", args[0])),
+        _ => Err(err_eval("Expected a number")),
+    }
+}
+
+/// (string->number text) -> the number written in text, or nil if it is not a number
+fn string_to_number_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let source = match args[0].as_str() {
+        Some(source) => source,
+        None => return Err(err_eval("Expected text")),
+    };
+
+    match parse_number(source) {
+        Some(number) => numeric_result(mem, number),
+        None => Ok(mem.nil()),
+    }
+}
+
+/// (format-number x options) -> x as text for people to read. The options are a list of pairs,
+/// each of a name and a value:
+///   (precision . n)     n digits after the decimal point, rounding half to even
+///   (separator . text)  inserted between each group of three digits before the decimal point
+///   (point . text)      the decimal point, "." by default
+fn format_number_fn<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let value = match numeric_value(mem, args[0]) {
+        Some(value) => value,
+        None => return Err(err_eval("Expected a number")),
+    };
+
+    let mut format = NumberFormat::new();
+    for option in vec_from_pairs(mem, args[1])? {
+        let (name, setting) = match *option {
+            Value::Pair(pair) => (pair.first.get(mem), pair.second.get(mem)),
+            _ => return Err(err_eval("Expected each option to be a (name . value) pair")),
+        };
+
+        let text_setting = || match setting.as_str() {
+            Some(text) => Ok(String::from(text)),
+            None => Err(err_eval(&format!("Expected text for the {} option", name))),
+        };
+
+        match name.as_str() {
+            Some("precision") => match setting.as_int().and_then(|n| n.to_u32()) {
+                Some(places) => format.precision = Some(places),
+                None => return Err(err_eval("Expected a non-negative precision")),
+            },
+            Some("separator") => format.separator = text_setting()?,
+            Some("point") => format.point = text_setting()?,
+            _ => return Err(err_eval(&format!("Unknown format-number option {}", name))),
+        }
+    }
+
+    text_result(mem, &format_number(&value, &format)?)
+}
+
+/// Bind the number conversion builtins into the given globals Dict
+pub fn load<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+) -> Result<(), RuntimeError> {
+    define(mem, globals, "number->string", 1, number_to_string_fn)?;
+    define(mem, globals, "string->number", 1, string_to_number_fn)?;
+    define(mem, globals, "format-number", 2, format_number_fn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn numformat_float_round_trip() {
+        let floats = [
+            0.1,
+            1.0 / 3.0,
+            -2.5,
+            1e300,
+            5e-324,
+            -0.0,
+            123456789.125,
+            f64::MAX,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ];
+
+        for float in floats.iter() {
+            let printed = format!("{}", Numeric::Float(*float));
+            match parse_number(&printed) {
+                Some(Numeric::Float(back)) => {
+                    assert!(
+                        back.to_bits() == float.to_bits(),
+                        "{} did not round trip",
+                        printed
+                    )
+                }
+                _ => panic!("{} did not read back as a float", printed),
+            }
+        }
+
+        match parse_number("+nan.0") {
+            Some(Numeric::Float(nan)) => assert!(nan.is_nan()),
+            _ => panic!("Expected NaN"),
+        }
+    }
+
+    #[test]
+    fn numformat_parse() {
+        assert!(parse_number("-42") == Some(Numeric::Integer(BigInt::from(-42))));
+        assert!(parse_number("6/3") == Some(Numeric::Integer(BigInt::from(2))));
+        assert!(
+            parse_number("-2/4")
+                == Some(Numeric::Rational(BigRational::new(
+                    BigInt::from(-1),
+                    BigInt::from(2)
+                )))
+        );
+        assert!(parse_number("1.50m") == Decimal::parse_literal("1.50m").map(Numeric::Decimal));
+        assert!(parse_number("2.5e-3") == Some(Numeric::Float(0.0025)));
+
+        for bad in &[
+            "", "-", "1/0", "1/-2", "x", "inf", "NaN", "1,5", "1.5.5", "--1",
+        ] {
+            assert!(parse_number(bad).is_none(), "{} parsed as a number", bad);
+        }
+    }
+
+    #[test]
+    fn numformat_format() {
+        let format = |value: Numeric, precision, separator: &str, point: &str| {
+            let format = NumberFormat {
+                precision,
+                separator: String::from(separator),
+                point: String::from(point),
+            };
+            format_number(&value, &format).unwrap()
+        };
+
+        let big = Numeric::Integer(BigInt::from(-1234567));
+        assert!(format(big.clone(), None, ",", ".") == "-1,234,567");
+        assert!(format(big, Some(2), ".", ",") == "-1.234.567,00");
+        assert!(format(Numeric::Float(1234.5), None, " ", ".") == "1 234.5");
+        assert!(format(Numeric::Float(2.675), Some(2), "", ".") == "2.68");
+        assert!(format(Numeric::Float(0.125), Some(2), "", ".") == "0.12");
+
+        let third = Numeric::Rational(BigRational::new(BigInt::from(1), BigInt::from(3)));
+        assert!(format(third.clone(), Some(4), "", ".") == "0.3333");
+        assert!(format_number(&third, &NumberFormat::new()).is_err());
+    }
+}
@@ -2,6 +2,7 @@ use std::cell::Cell;
 use std::fmt;
 
 use crate::error::{err_eval, RuntimeError, SourcePos};
+use crate::heapcheck::{HeapChecker, Verify};
 use crate::memory::MutatorView;
 use crate::printer::Print;
 use crate::safeptr::{MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
@@ -56,6 +57,17 @@ impl Pair {
     }
 }
 
+impl Verify for Pair {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.tagged(guard, self.first.get_ptr())?;
+        checker.tagged(guard, self.second.get_ptr())
+    }
+}
+
 impl Print for Pair {
     fn print<'guard>(
         &self,
@@ -108,6 +120,20 @@ pub fn cons<'guard>(
     mem.alloc_tagged(pair)
 }
 
+/// Pack a slice of values into a list of Pair instances, the reverse of `vec_from_pairs()`
+pub fn list_from_slice<'guard>(
+    mem: &'guard MutatorView,
+    items: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    mem.size_limits().check_vector(items.len())?;
+
+    let mut head = mem.nil();
+    for item in items.iter().rev() {
+        head = cons(mem, *item, head)?;
+    }
+    Ok(head)
+}
+
 /// Unpack a list of Pair instances into a Vec
 pub fn vec_from_pairs<'guard>(
     guard: &'guard dyn MutatorScope,
@@ -0,0 +1,246 @@
+/// Data-parallel evaluation across host threads.
+///
+/// `(par-map f items)` returns the list of the results of calling `f` on each of the items, which
+/// may be a list or a typed array, as `map` would, evaluating chunks of the items on several host
+/// threads at once. The heap is not `Send`, so nothing on it can be shared between host threads.
+/// Instead the function and each chunk of the items are serialized, see `serialize`, and each job
+/// deserializes them into a heap and Thread of its own, returning its results serialized in turn
+/// to be merged in order.
+///
+/// The function must be pure: it cannot close over local variables, it sees the builtin globals
+/// and copies of the caller's globals that it refers to, and any definitions, assignments or
+/// output it makes are lost with the heap of its job. The items, the results and the globals
+/// copied must be values that can be serialized.
+///
+/// Jobs run on the `WorkerPool` installed on the calling Thread with `Thread::set_worker_pool()`,
+/// or on host threads spawned for them if there is none.
+use std::collections::HashSet;
+use std::thread;
+
+use crate::array::ArraySize;
+use crate::builtins::define_with_thread;
+use crate::containers::{Container, IndexedAnyContainer, SliceableContainer};
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
+use crate::function::Function;
+use crate::memory::{Memory, Mutator, MutatorView};
+use crate::pair::{list_from_slice, vec_from_pairs};
+use crate::safeptr::{ScopedPtr, TaggedScopedPtr};
+use crate::serialize::{deserialize, deserialize_values, serialize, serialize_values};
+use crate::taggedptr::{TaggedPtr, Value};
+use crate::vm::Thread;
+
+/// A unit of work for a host thread, returning its results serialized
+pub type Job = Box<dyn FnOnce() -> Result<Vec<u8>, RuntimeError> + Send>;
+
+/// Runs jobs on host threads on behalf of an embedding application
+pub trait WorkerPool {
+    /// Return the number of jobs that can usefully run at once
+    fn workers(&self) -> usize;
+
+    /// Run every job, returning their results in the order of the jobs once all have finished
+    fn run(&self, jobs: Vec<Job>) -> Vec<Result<Vec<u8>, RuntimeError>>;
+}
+
+/// A pool that spawns a host thread for each job
+pub struct SpawnPool {
+    workers: usize,
+}
+
+impl SpawnPool {
+    pub fn new(workers: usize) -> SpawnPool {
+        SpawnPool {
+            workers: workers.max(1),
+        }
+    }
+
+    /// A pool with as many workers as the host can run threads in parallel
+    pub fn available() -> SpawnPool {
+        SpawnPool::new(thread::available_parallelism().map_or(1, |count| count.get()))
+    }
+}
+
+impl WorkerPool for SpawnPool {
+    fn workers(&self) -> usize {
+        self.workers
+    }
+
+    fn run(&self, jobs: Vec<Job>) -> Vec<Result<Vec<u8>, RuntimeError>> {
+        let handles: Vec<_> = jobs.into_iter().map(thread::spawn).collect();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(err_eval("A par-map job panicked")))
+            })
+            .collect()
+    }
+}
+
+/// A mutator that calls a serialized function on each of a chunk of serialized items in a heap
+/// of its own, with the serialized names and values of the globals it refers to bound first
+struct Worker {}
+
+impl Mutator for Worker {
+    type Input = (Vec<u8>, Vec<u8>, Vec<u8>);
+    type Output = Vec<u8>;
+
+    fn run(
+        &self,
+        mem: &MutatorView,
+        (function, globals, items): (Vec<u8>, Vec<u8>, Vec<u8>),
+    ) -> Result<Vec<u8>, RuntimeError> {
+        let function = deserialize(mem, &function)?.as_tagged(mem);
+        let thread = Thread::alloc(mem)?;
+
+        for global in deserialize_values(mem, &globals)?.chunks(2) {
+            thread.define_global(mem, global[0], global[1])?;
+        }
+
+        let mut results = Vec::new();
+        for item in deserialize_values(mem, &items)? {
+            results.push(thread.call_function(mem, function, &[item])?);
+        }
+
+        serialize_values(mem, &results)
+    }
+}
+
+/// Return the names and values, in turn, of the globals of the calling thread that the function
+/// may refer to. Every symbol among the literals of the function, and of the functions it refers
+/// to in turn, names a candidate. Native functions are left out as every Thread has the builtins.
+fn referenced_globals<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    function: ScopedPtr<'guard, Function>,
+) -> Result<Vec<TaggedScopedPtr<'guard>>, RuntimeError> {
+    let mut globals = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![function];
+
+    while let Some(function) = pending.pop() {
+        for literal in function.code(mem).literals(mem)? {
+            match *literal {
+                Value::Function(nested) => pending.push(nested),
+
+                Value::Symbol(name) if seen.insert(name.as_str(mem)) => {
+                    if let Some(value) = thread.lookup_global(mem, literal) {
+                        match *value {
+                            Value::NativeFunction(_) => continue,
+                            Value::Function(global) => pending.push(global),
+                            _ => (),
+                        }
+                        globals.push(literal);
+                        globals.push(value);
+                    }
+                }
+
+                _ => (),
+            }
+        }
+    }
+
+    Ok(globals)
+}
+
+/// Return the items of a list or typed array
+fn items_arg<'guard>(
+    mem: &'guard MutatorView,
+    items: TaggedScopedPtr<'guard>,
+) -> Result<Vec<TaggedScopedPtr<'guard>>, RuntimeError> {
+    let number = |n: isize| TaggedScopedPtr::new(mem, TaggedPtr::number(n));
+
+    match *items {
+        Value::Nil | Value::Pair(_) => vec_from_pairs(mem, items),
+        Value::List(list) => (0..list.length())
+            .map(|index| IndexedAnyContainer::get(&*list, mem, index as ArraySize))
+            .collect(),
+        Value::ArrayU8(array) => Ok(array.access_slice(mem, |items| {
+            items.iter().map(|n| number(*n as isize)).collect()
+        })),
+        Value::ArrayU16(array) => Ok(array.access_slice(mem, |items| {
+            items.iter().map(|n| number(*n as isize)).collect()
+        })),
+        Value::ArrayU32(array) => Ok(array.access_slice(mem, |items| {
+            items.iter().map(|n| number(*n as isize)).collect()
+        })),
+        _ => Err(err_eval(&format!(
+            "par-map expected a list or an array, got {}",
+            items
+        ))),
+    }
+}
+
+/// (par-map f items) -> the list of the results of calling f on each item, evaluated in chunks
+/// on several host threads
+fn par_map_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let (function, globals) = match *args[0] {
+        Value::Function(function) if !function.is_closure() => (
+            serialize(mem, function)?,
+            referenced_globals(mem, thread, function)?,
+        ),
+        Value::Function(_) | Value::Partial(_) => {
+            return Err(err_eval(
+                "par-map cannot send a closure or partial application to another thread",
+            ))
+        }
+        _ => {
+            return Err(err_eval(&format!(
+                "par-map expected a function, got {}",
+                args[0]
+            )))
+        }
+    };
+
+    let items = items_arg(mem, args[1])?;
+    if items.is_empty() {
+        return Ok(mem.nil());
+    }
+
+    let globals = serialize_values(mem, &globals).map_err(|error| {
+        let name = globals
+            .chunks(2)
+            .find(|global| serialize_values(mem, &global[1..]).is_err())
+            .map_or_else(String::new, |global| global[0].to_string());
+        err_eval(&format!(
+            "par-map cannot send the global {} the function refers to to another thread",
+            name
+        ))
+        .with_cause(error)
+    })?;
+
+    let workers = thread.worker_count();
+    let chunk_size = items.len().div_ceil(workers);
+
+    let mut jobs: Vec<Job> = Vec::new();
+    for chunk in items.chunks(chunk_size) {
+        let function = function.clone();
+        let globals = globals.clone();
+        let chunk = serialize_values(mem, chunk)?;
+        jobs.push(Box::new(move || {
+            Memory::new().mutate(&Worker {}, (function, globals, chunk))
+        }));
+    }
+
+    let mut results = Vec::new();
+    for result in thread.run_jobs(jobs) {
+        results.extend(deserialize_values(mem, &result?)?);
+    }
+
+    list_from_slice(mem, &results)
+}
+
+/// Bind the parallel evaluation builtins into the given globals Dict
+pub fn load<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+) -> Result<(), RuntimeError> {
+    define_with_thread(mem, globals, "par-map", 2, par_map_fn)?;
+    Ok(())
+}
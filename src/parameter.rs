@@ -0,0 +1,61 @@
+/// Dynamically scoped variables.
+///
+/// A Parameter holds a single value that is read by calling the parameter with no arguments,
+/// `(p)`. The value is rebound for the dynamic extent of a `parameterize` expression, including
+/// within any function called from it, and restored when the expression completes or an error
+/// unwinds it. The Thread keeps the stack of saved values used to restore them.
+use std::fmt;
+
+use crate::error::RuntimeError;
+use crate::heapcheck::{HeapChecker, Verify};
+use crate::memory::MutatorView;
+use crate::printer::Print;
+use crate::safeptr::{MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+
+/// A dynamically scoped variable, see module documentation
+pub struct Parameter {
+    /// The value of the innermost binding, or the default value if not rebound
+    value: TaggedCellPtr,
+}
+
+impl Parameter {
+    /// Allocate a new Parameter on the heap with the given default value
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        default: TaggedScopedPtr<'guard>,
+    ) -> Result<ScopedPtr<'guard, Parameter>, RuntimeError> {
+        mem.alloc(Parameter {
+            value: TaggedCellPtr::new_with(default),
+        })
+    }
+
+    /// Return the current value
+    pub fn value<'guard>(&self, guard: &'guard dyn MutatorScope) -> TaggedScopedPtr<'guard> {
+        self.value.get(guard)
+    }
+
+    /// Replace the current value
+    pub fn set(&self, value: TaggedScopedPtr) {
+        self.value.set(value)
+    }
+}
+
+impl Verify for Parameter {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.tagged(guard, self.value.get_ptr())
+    }
+}
+
+impl Print for Parameter {
+    fn print<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "#<parameter {}>", self.value(guard))
+    }
+}
@@ -4,10 +4,20 @@ use std::marker::PhantomData;
 use crate::error::{err_parser, err_parser_wpos, RuntimeError, SourcePos};
 use crate::lexer::{tokenize, Token, TokenType};
 use crate::memory::MutatorView;
+use crate::number::numeric_result;
 use crate::pair::Pair;
 use crate::safeptr::{MutatorScope, TaggedCellPtr, TaggedScopedPtr};
-use crate::taggedptr::Value;
-use crate::text;
+use crate::taggedptr::{TaggedPtr, Value};
+
+/// The surface syntax of source code
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Syntax {
+    /// Fully parenthesized s-expressions
+    Parenthesized,
+    /// Lists marked by line breaks and indentation as well as parentheses, see
+    /// `indented_tokens()`
+    Indented,
+}
 
 // A linked list, internal to the parser to simplify the code and is stored on the Rust stack
 struct PairList<'guard> {
@@ -138,6 +148,13 @@ where
                 list.push(mem, parse_sexpr(mem, tokens)?, pos)?;
             }
 
+            Some(&&Token {
+                token: Keyword(_),
+                pos,
+            }) => {
+                list.push(mem, parse_sexpr(mem, tokens)?, pos)?;
+            }
+
             Some(&&Token {
                 token: Text(_),
                 pos,
@@ -145,6 +162,27 @@ where
                 list.push(mem, parse_sexpr(mem, tokens)?, pos)?;
             }
 
+            Some(&&Token {
+                token: Integer(_),
+                pos,
+            }) => {
+                list.push(mem, parse_sexpr(mem, tokens)?, pos)?;
+            }
+
+            Some(&&Token {
+                token: Number(_),
+                pos,
+            }) => {
+                list.push(mem, parse_sexpr(mem, tokens)?, pos)?;
+            }
+
+            Some(&&Token {
+                token: Char(_),
+                pos,
+            }) => {
+                list.push(mem, parse_sexpr(mem, tokens)?, pos)?;
+            }
+
             Some(&&Token { token: Quote, pos }) => {
                 list.push(mem, parse_sexpr(mem, tokens)?, pos)?;
             }
@@ -192,7 +230,7 @@ where
 // Parse a single s-expression
 //
 // Must be a
-//  * symbol
+//  * symbol, number, character or text
 //  * or a list
 //
 fn parse_sexpr<'guard, 'i, I: 'i>(
@@ -226,13 +264,44 @@ where
             }
         }
 
+        Some(&&Token {
+            token: Keyword(ref name),
+            pos: _,
+        }) => {
+            tokens.next();
+            Ok(mem.lookup_keyword(name))
+        }
+
+        Some(&&Token {
+            token: Integer(value),
+            pos: _,
+        }) => {
+            tokens.next();
+            Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(value)))
+        }
+
+        Some(&&Token {
+            token: Number(ref number),
+            pos: _,
+        }) => {
+            tokens.next();
+            numeric_result(mem, number.clone())
+        }
+
+        Some(&&Token {
+            token: Char(c),
+            pos: _,
+        }) => {
+            tokens.next();
+            Ok(TaggedScopedPtr::new(mem, TaggedPtr::char(c)))
+        }
+
         Some(&&Token {
             token: Text(ref string),
             pos: _,
         }) => {
             tokens.next();
-            let text = mem.alloc_tagged(text::Text::new_from_str(mem, &string)?)?;
-            Ok(text)
+            mem.text(string)
         }
 
         Some(&&Token { token: Quote, pos }) => {
@@ -276,6 +345,130 @@ pub fn parse<'guard>(
     parse_tokens(mem, tokenize(input)?)
 }
 
+/// Add the parentheses implied by the layout of indentation-based source code. Each line that
+/// holds more than one expression, or that has lines indented further than it following it,
+/// becomes a list of the expressions on it followed by those on the more indented lines:
+///
+///   def square (n)
+///     * n n
+///
+/// is `(def square (n) (* n n))`. A line holding a single expression and nothing indented
+/// under it is just that expression, so a call with no arguments must be written `(f)`. Inside
+/// explicit parentheses line breaks and indentation have no meaning, so ordinary s-expressions
+/// can be mixed in freely.
+fn indented_tokens(tokens: Vec<Token>) -> Vec<Token> {
+    // Split the tokens into lines, a new line beginning at each token that is first on its line
+    // and outside any parentheses, counting the expressions on each line
+    struct Line {
+        indent: u32,
+        expressions: usize,
+        tokens: Vec<Token>,
+    }
+
+    let mut lines: Vec<Line> = Vec::new();
+    let mut depth = 0;
+    let mut last_line = 0;
+
+    for token in tokens {
+        if depth == 0 && (lines.is_empty() || token.pos.line != last_line) {
+            lines.push(Line {
+                indent: token.pos.column,
+                expressions: 0,
+                tokens: Vec::new(),
+            });
+        }
+
+        let line = lines.last_mut().expect("A line was just pushed");
+        match token.token {
+            TokenType::OpenParen => {
+                if depth == 0 {
+                    line.expressions += 1;
+                }
+                depth += 1;
+            }
+            TokenType::CloseParen => depth = if depth > 0 { depth - 1 } else { 0 },
+            TokenType::Quote => (),
+            _ if depth == 0 => line.expressions += 1,
+            _ => (),
+        }
+
+        last_line = token.pos.line;
+        line.tokens.push(token);
+    }
+
+    // Open a list for each line with more than one expression or with lines indented under it,
+    // closing it before the next line that is indented no further
+    let mut result = Vec::new();
+    let mut open: Vec<(u32, SourcePos)> = Vec::new();
+
+    let close = |result: &mut Vec<Token>, pos: SourcePos| {
+        result.push(Token {
+            pos,
+            token: TokenType::CloseParen,
+        })
+    };
+
+    for index in 0..lines.len() {
+        let indent = lines[index].indent;
+        while let Some((_, pos)) = open.last().filter(|(open, _)| *open >= indent) {
+            close(&mut result, *pos);
+            open.pop();
+        }
+
+        let has_children = match lines.get(index + 1) {
+            Some(next) => next.indent > indent,
+            None => false,
+        };
+
+        let tokens = std::mem::take(&mut lines[index].tokens);
+        if lines[index].expressions > 1 || has_children {
+            let pos = tokens[0].pos;
+            result.push(Token {
+                pos,
+                token: TokenType::OpenParen,
+            });
+            open.push((indent, pos));
+        }
+        result.extend(tokens);
+    }
+
+    while let Some((_, pos)) = open.pop() {
+        close(&mut result, pos);
+    }
+
+    result
+}
+
+/// Parse the given string, which may contain any number of expressions, into a list of their ASTs
+pub fn parse_program<'guard>(
+    mem: &'guard MutatorView,
+    input: &str,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    parse_program_with_syntax(mem, input, Syntax::Parenthesized)
+}
+
+/// Parse the given string, written in the given syntax, into a list of the ASTs of the
+/// expressions in it
+pub fn parse_program_with_syntax<'guard>(
+    mem: &'guard MutatorView,
+    input: &str,
+    syntax: Syntax,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let tokens = match syntax {
+        Syntax::Parenthesized => tokenize(input)?,
+        Syntax::Indented => indented_tokens(tokenize(input)?),
+    };
+    let mut tokenstream = tokens.iter().peekable();
+
+    let mut forms = PairList::open(mem);
+    while let Some(token) = tokenstream.peek() {
+        let pos = token.pos;
+        forms.push(mem, parse_sexpr(mem, &mut tokenstream)?, pos)?;
+    }
+
+    Ok(forms.close(mem))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -389,4 +582,85 @@ mod test {
         let expect = String::from("(a)");
         check(&input, &expect);
     }
+
+    #[test]
+    fn parse_integers() {
+        let input = String::from("(42 'x -7 . 0)");
+        let expect = String::from("(42 (quote x) -7 . 0)");
+        check(&input, &expect);
+    }
+
+    #[test]
+    fn parse_program_forms() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(&self, mem: &MutatorView, _: Self::Input) -> Result<Self::Output, RuntimeError> {
+                let forms = parse_program(mem, "(def f (x) x)\n'a\n\n(f \"b\") 7")?;
+                assert!(print(*forms) == "((def f (x) x) (quote a) (f \"b\") 7)");
+
+                assert!(parse_program(mem, "  ")?.is_nil());
+                assert!(parse_program(mem, "(a) (b").is_err());
+                assert!(parse_program(mem, "(a))").is_err());
+
+                Ok(())
+            }
+        }
+
+        mem.mutate(&Test {}, ()).unwrap();
+    }
+
+    #[test]
+    fn parse_indented_program() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(&self, mem: &MutatorView, _: Self::Input) -> Result<Self::Output, RuntimeError> {
+                let indented = |input: &str| -> Result<String, RuntimeError> {
+                    Ok(print(*parse_program_with_syntax(
+                        mem,
+                        input,
+                        Syntax::Indented,
+                    )?))
+                };
+
+                // each line with several expressions or indented lines under it is a list
+                let source = "def square (n)\n  * n n\n\nsquare 7\nx\n'y\n";
+                assert!(indented(source)? == "((def square (n) (* n n)) (square 7) x (quote y))");
+
+                // lines at the same indentation are siblings, and dedenting closes lists
+                let source = "
+define (f n)
+  if (< n 0)
+    - n
+    n
+f -3
+";
+                assert!(indented(source)? == "((define (f n) (if (< n 0) (- n) n)) (f -3))");
+
+                // a single expression with indented lines under it opens a list
+                let source = "let\n  (a 1) (b 2)\n  + a b";
+                assert!(indented(source)? == "((let ((a 1) (b 2)) (+ a b)))");
+
+                // line breaks inside parentheses are ignored
+                let source = "list (a\n  b)\n  (c\nd)\n'(e\n  f)";
+                assert!(indented(source)? == "((list (a b) (c d)) (quote (e f)))");
+
+                assert!(indented("")? == "nil");
+                assert!(parse_program_with_syntax(mem, "a (b", Syntax::Indented).is_err());
+
+                Ok(())
+            }
+        }
+
+        mem.mutate(&Test {}, ()).unwrap();
+    }
 }
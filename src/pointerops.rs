@@ -12,19 +12,32 @@ pub trait AsNonNull {
     }
 }
 
-// Pointer tag values and masks using the lowest 2 bits
+// Pointer tag values and masks using the lowest 2 bits.
+// Nil is represented by the all-zeros word, so the zero tag must be a pointer type: were it the
+// number tag, the integer 0 would be indistinguishable from nil.
 const TAG_MASK: usize = 0x3;
-pub const TAG_NUMBER: usize = 0x0;
+pub const TAG_OBJECT: usize = 0x0;
 pub const TAG_SYMBOL: usize = 0x1;
 pub const TAG_PAIR: usize = 0x2;
-pub const TAG_OBJECT: usize = 0x3;
+pub const TAG_NUMBER: usize = 0x3;
 const PTR_MASK: usize = !0x3;
 
+// Characters share the symbol tag. Symbols are word aligned, so the third lowest bit of a symbol
+// pointer is always clear; a character sets it and is stored in the bits above the lowest three.
+const CHAR_TAG_MASK: usize = 0x7;
+pub const TAG_CHAR: usize = 0x5;
+pub const CHAR_SHIFT: usize = 3;
+
 /// Return the tag from the given word
 pub fn get_tag(tagged_word: usize) -> usize {
     tagged_word & TAG_MASK
 }
 
+/// Return true if the given word, which must carry the symbol tag, is a character
+pub fn is_char(tagged_word: usize) -> bool {
+    tagged_word & CHAR_TAG_MASK == TAG_CHAR
+}
+
 /// Pointer tagging operations on RawPtr<T>
 pub trait Tagged<T> {
     fn tag(self, tag: usize) -> NonNull<T>;
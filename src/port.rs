@@ -0,0 +1,284 @@
+/// Input and output ports.
+///
+/// Output builtins write to the port that is the value of the `current-output-port` parameter
+/// and input builtins read from the `current-input-port` parameter. By default these are console
+/// ports, connected to the output and input streams the embedder gave the Thread, which are
+/// stdout and stdin unless changed. String ports read from a text or collect output in memory, so
+/// that output can be captured by parameterizing `current-output-port`:
+///
+///   (let ((port (open-output-string)))
+///     (parameterize ((current-output-port port)) (display 'hello))
+///     (get-output-string port))
+use std::cell::Cell;
+use std::fmt;
+use std::str;
+
+use crate::array::{ArraySize, ArrayU8};
+use crate::builtins::define_with_thread;
+use crate::codec::text_result;
+use crate::containers::{Container, StackContainer};
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
+use crate::heapcheck::{HeapChecker, Verify};
+use crate::memory::MutatorView;
+use crate::printer::Print;
+use crate::safeptr::{MutatorScope, ScopedPtr, TaggedScopedPtr};
+use crate::taggedptr::Value;
+use crate::vm::Thread;
+
+/// Where a port reads from or writes to
+#[derive(Copy, Clone, PartialEq)]
+enum PortKind {
+    ConsoleInput,
+    ConsoleOutput,
+    StringInput,
+    StringOutput,
+}
+
+/// An input or output port, see module documentation
+pub struct Port {
+    kind: PortKind,
+    /// The UTF-8 text being read by a string input port, or written so far to a string output port
+    bytes: ArrayU8,
+    /// Read position of a string input port
+    position: Cell<ArraySize>,
+}
+
+impl Port {
+    fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        kind: PortKind,
+        bytes: ArrayU8,
+    ) -> Result<ScopedPtr<'guard, Port>, RuntimeError> {
+        mem.alloc(Port {
+            kind,
+            bytes,
+            position: Cell::new(0),
+        })
+    }
+
+    /// Allocate a port that reads from the input stream of the Thread using it
+    pub fn alloc_console_input<'guard>(
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Port>, RuntimeError> {
+        Port::alloc(mem, PortKind::ConsoleInput, ArrayU8::new())
+    }
+
+    /// Allocate a port that writes to the output stream of the Thread using it
+    pub fn alloc_console_output<'guard>(
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Port>, RuntimeError> {
+        Port::alloc(mem, PortKind::ConsoleOutput, ArrayU8::new())
+    }
+
+    /// Allocate a port that reads from the given text
+    pub fn alloc_input_string<'guard>(
+        mem: &'guard MutatorView,
+        text: &str,
+    ) -> Result<ScopedPtr<'guard, Port>, RuntimeError> {
+        let bytes = ArrayU8::new();
+        for byte in text.as_bytes() {
+            bytes.push(mem, *byte)?;
+        }
+        Port::alloc(mem, PortKind::StringInput, bytes)
+    }
+
+    /// Allocate a port that collects everything written to it
+    pub fn alloc_output_string<'guard>(
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Port>, RuntimeError> {
+        Port::alloc(mem, PortKind::StringOutput, ArrayU8::new())
+    }
+
+    /// Write text to the port
+    pub fn write_str<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: &Thread,
+        text: &str,
+    ) -> Result<(), RuntimeError> {
+        match self.kind {
+            PortKind::ConsoleOutput => thread.write_output(text),
+            PortKind::StringOutput => {
+                for byte in text.as_bytes() {
+                    self.bytes.push(mem, *byte)?;
+                }
+                Ok(())
+            }
+            _ => Err(err_eval("Cannot write to an input port")),
+        }
+    }
+
+    /// Read the next line from the port, without the line ending, or None at the end of input
+    pub fn read_line<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: &Thread,
+    ) -> Result<Option<String>, RuntimeError> {
+        match self.kind {
+            PortKind::ConsoleInput => thread.read_input_line(),
+            PortKind::StringInput => {
+                let bytes = unsafe { self.bytes.as_slice(mem) };
+                let start = self.position.get() as usize;
+                if start >= bytes.len() {
+                    return Ok(None);
+                }
+
+                let rest = &bytes[start..];
+                let (line, consumed) = match rest.iter().position(|b| *b == b'\n') {
+                    Some(end) => (&rest[..end], end + 1),
+                    None => (rest, rest.len()),
+                };
+                self.position.set((start + consumed) as ArraySize);
+
+                let line = str::from_utf8(line).map_err(|_| err_eval("Invalid UTF-8 input"))?;
+                Ok(Some(String::from(line.trim_end_matches('\r'))))
+            }
+            _ => Err(err_eval("Cannot read from an output port")),
+        }
+    }
+
+    /// Return everything written so far to a string output port
+    pub fn output_string<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Result<String, RuntimeError> {
+        match self.kind {
+            PortKind::StringOutput => {
+                let bytes = unsafe { self.bytes.as_slice(guard) };
+                Ok(String::from_utf8_lossy(bytes).into_owned())
+            }
+            _ => Err(err_eval("Expected a string output port")),
+        }
+    }
+}
+
+impl Verify for Port {
+    fn verify_children<'guard>(
+        &self,
+        _guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        self.bytes.verify_backing(checker)
+    }
+}
+
+impl Print for Port {
+    fn print<'guard>(
+        &self,
+        _guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        let kind = match self.kind {
+            PortKind::ConsoleInput => "console-input",
+            PortKind::ConsoleOutput => "console-output",
+            PortKind::StringInput => "string-input",
+            PortKind::StringOutput => "string-output",
+        };
+        write!(f, "#<port {}>", kind)
+    }
+}
+
+/// Return the Port argument or a type error
+fn port_arg<'guard>(arg: TaggedScopedPtr<'guard>) -> Result<ScopedPtr<'guard, Port>, RuntimeError> {
+    match *arg {
+        Value::Port(port) => Ok(port),
+        _ => Err(err_eval("Expected a port")),
+    }
+}
+
+/// (display x) -> nil, writing x to the current output port, text without quotes
+fn display_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let port = thread.current_output_port(mem)?;
+    match *args[0] {
+        Value::Text(text) => port.write_str(mem, thread, text.as_str(mem))?,
+        _ => port.write_str(mem, thread, &format!("{}", args[0]))?,
+    }
+    Ok(mem.nil())
+}
+
+/// (write x) -> nil, writing x to the current output port as it would be printed by the repl
+fn write_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let port = thread.current_output_port(mem)?;
+    port.write_str(mem, thread, &format!("{}", args[0]))?;
+    Ok(mem.nil())
+}
+
+/// (newline) -> nil, writing a line ending to the current output port
+fn newline_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    _args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let port = thread.current_output_port(mem)?;
+    port.write_str(mem, thread, "\n")?;
+    Ok(mem.nil())
+}
+
+/// (read-line) -> the next line of the current input port as text, or nil at the end of input
+fn read_line_fn<'guard>(
+    mem: &'guard MutatorView,
+    thread: &Thread,
+    _args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let port = thread.current_input_port(mem)?;
+    match port.read_line(mem, thread)? {
+        Some(line) => text_result(mem, &line),
+        None => Ok(mem.nil()),
+    }
+}
+
+/// (open-output-string) -> a new string output port
+fn open_output_string_fn<'guard>(
+    mem: &'guard MutatorView,
+    _thread: &Thread,
+    _args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(Port::alloc_output_string(mem)?.as_tagged(mem))
+}
+
+/// (get-output-string port) -> everything written to a string output port so far, as text
+fn get_output_string_fn<'guard>(
+    mem: &'guard MutatorView,
+    _thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    text_result(mem, &port_arg(args[0])?.output_string(mem)?)
+}
+
+/// (open-input-string text) -> a new port reading from the text
+fn open_input_string_fn<'guard>(
+    mem: &'guard MutatorView,
+    _thread: &Thread,
+    args: &[TaggedScopedPtr<'guard>],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let text = match args[0].value().as_str() {
+        Some(text) => String::from(text),
+        None => return Err(err_eval("Expected text")),
+    };
+    Ok(Port::alloc_input_string(mem, &text)?.as_tagged(mem))
+}
+
+/// Bind the port builtins into the given globals Dict. The `current-output-port` and
+/// `current-input-port` parameters belong to each Thread so are bound by `Thread::alloc()`.
+pub fn load<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+) -> Result<(), RuntimeError> {
+    define_with_thread(mem, globals, "display", 1, display_fn)?;
+    define_with_thread(mem, globals, "write", 1, write_fn)?;
+    define_with_thread(mem, globals, "newline", 0, newline_fn)?;
+    define_with_thread(mem, globals, "read-line", 0, read_line_fn)?;
+    define_with_thread(mem, globals, "open-output-string", 0, open_output_string_fn)?;
+    define_with_thread(mem, globals, "get-output-string", 1, get_output_string_fn)?;
+    define_with_thread(mem, globals, "open-input-string", 1, open_input_string_fn)?;
+    Ok(())
+}
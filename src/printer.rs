@@ -1,9 +1,28 @@
-use std::fmt;
-//use std::io;
+use std::cell::Cell;
+use std::fmt::{self, Write as FmtWrite};
+use std::io::{self, BufWriter, Write};
 
 use crate::safeptr::MutatorScope;
 use crate::taggedptr::Value;
 
+thread_local! {
+    /// See `set_sorted_keys()`
+    static SORTED_KEYS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Print and serialize the items of dicts sorted by key, in the order of `compare::compare()`,
+/// rather than in the order of their hash tables, which can differ between runs and platforms.
+/// For output that is compared against earlier output, such as golden tests and diffs of written
+/// tables. The option is per host thread and is off by default.
+pub fn set_sorted_keys(sorted: bool) {
+    SORTED_KEYS.with(|option| option.set(sorted));
+}
+
+/// Return true if dict items are printed and serialized sorted by key, see `set_sorted_keys()`
+pub fn sorted_keys() -> bool {
+    SORTED_KEYS.with(|option| option.get())
+}
+
 /// Trait for using a `Value` lifted pointer in the `Display` trait
 pub trait Print {
     fn print<'guard>(
@@ -21,12 +40,44 @@ pub trait Print {
     }
 
     //fn repr<'guard, F: fmt::Write>(&self, _guard: &'guard dyn MutatorScope, f: &mut F) -> fmt::Result;
+}
 
-    //fn output<'guard, F: io::Write>(
-    //    &self,
-    //    _guard: &'guard dyn MutatorScope,
-    //    f: &mut F,
-    //) -> io::Result<()>;
+/// A value paired with the scope it may be safely accessed in, formatted with `Print::print()`.
+/// Obtained from `ScopedPtr::display()` or `TaggedScopedPtr::display()`.
+pub struct PrintDisplay<'guard, T: Print> {
+    guard: &'guard dyn MutatorScope,
+    value: T,
+}
+
+impl<'guard, T: Print> PrintDisplay<'guard, T> {
+    pub fn new(guard: &'guard dyn MutatorScope, value: T) -> PrintDisplay<'guard, T> {
+        PrintDisplay { guard, value }
+    }
+}
+
+impl<'guard, T: Print> fmt::Display for PrintDisplay<'guard, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.print(self.guard, f)
+    }
+}
+
+/// A value paired with the scope it may be safely accessed in, formatted with `Print::debug()`.
+/// Obtained from `ScopedPtr::debug()` or `TaggedScopedPtr::debug()`.
+pub struct PrintDebug<'guard, T: Print> {
+    guard: &'guard dyn MutatorScope,
+    value: T,
+}
+
+impl<'guard, T: Print> PrintDebug<'guard, T> {
+    pub fn new(guard: &'guard dyn MutatorScope, value: T) -> PrintDebug<'guard, T> {
+        PrintDebug { guard, value }
+    }
+}
+
+impl<'guard, T: Print> fmt::Debug for PrintDebug<'guard, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.debug(self.guard, f)
+    }
 }
 
 pub fn print(value: Value) -> String {
@@ -36,3 +87,64 @@ pub fn print(value: Value) -> String {
 pub fn debug(value: Value) -> String {
     format!("{:?}", value)
 }
+
+/// Write the printed form of a value to an output stream as it is produced, through a buffer,
+/// rather than building it in a String first
+pub fn print_to(value: Value, out: &mut dyn Write) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    write!(out, "{}", value)?;
+    out.flush()
+}
+
+/// Write the debug form of a value to an output stream, as `print_to()` does
+pub fn debug_to(value: Value, out: &mut dyn Write) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    write!(out, "{:?}", value)?;
+    out.flush()
+}
+
+/// A cap on the number of characters of printed output, for printing values that may be too large
+/// to show in full, such as results in the repl
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PrintLimit {
+    pub max_chars: usize,
+}
+
+impl PrintLimit {
+    pub fn new(max_chars: usize) -> PrintLimit {
+        PrintLimit { max_chars }
+    }
+}
+
+/// A String that refuses to grow past a number of characters. Refusing is a formatting error, so
+/// that printing a value stops as soon as the limit is reached instead of walking the rest of it.
+struct LimitedString {
+    text: String,
+    remaining: usize,
+    truncated: bool,
+}
+
+impl fmt::Write for LimitedString {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.remaining == 0 {
+                self.truncated = true;
+                return Err(fmt::Error);
+            }
+            self.text.push(c);
+            self.remaining -= 1;
+        }
+        Ok(())
+    }
+}
+
+/// Print a value up to a limit, returning the output and whether it was cut short
+pub fn print_limited(value: Value, limit: PrintLimit) -> (String, bool) {
+    let mut out = LimitedString {
+        text: String::new(),
+        remaining: limit.max_chars,
+        truncated: false,
+    };
+    let _ = write!(out, "{}", value);
+    (out.text, out.truncated)
+}
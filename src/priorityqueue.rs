@@ -0,0 +1,291 @@
+/// A priority queue implemented as a binary min-heap stored in a single typed Array.
+///
+/// Items are ordered by a comparator function that is called with two items and returns a
+/// negative number if the first should be popped before the second. With no comparator, items
+/// are ordered by the total order defined in `compare.rs`. A comparator that is not a builtin is
+/// evaluated on the Thread that pushes or pops, so needs one to be given.
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::array::{Array, ArraySize};
+use crate::compare::compare;
+use crate::containers::{Container, IndexedAnyContainer, StackAnyContainer};
+use crate::error::{err_eval, RuntimeError};
+use crate::heapcheck::{HeapChecker, Verify};
+use crate::memory::MutatorView;
+use crate::printer::Print;
+use crate::safeptr::{MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::taggedptr::Value;
+use crate::vm::Thread;
+
+/// A binary heap of values, see module documentation
+pub struct PriorityQueue {
+    /// Comparison function, or nil for the default total order
+    comparator: TaggedCellPtr,
+    /// Heap ordered items, the next item to pop at index 0
+    items: Array<TaggedCellPtr>,
+}
+
+impl PriorityQueue {
+    /// Allocate a new empty queue on the heap. The comparator may be nil.
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        comparator: TaggedScopedPtr<'guard>,
+    ) -> Result<ScopedPtr<'guard, PriorityQueue>, RuntimeError> {
+        match *comparator {
+            Value::Nil | Value::NativeFunction(_) | Value::Function(_) | Value::Partial(_) => (),
+            _ => return Err(err_eval("Priority queue comparator is not callable")),
+        }
+
+        mem.alloc(PriorityQueue {
+            comparator: TaggedCellPtr::new_with(comparator),
+            items: Array::new(),
+        })
+    }
+
+    /// Number of items in the queue
+    pub fn length(&self) -> ArraySize {
+        self.items.length()
+    }
+
+    /// Order two items according to the comparator
+    fn order<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: Option<&Thread>,
+        left: TaggedScopedPtr<'guard>,
+        right: TaggedScopedPtr<'guard>,
+    ) -> Result<Ordering, RuntimeError> {
+        let comparator = self.comparator.get(mem);
+        let result = match *comparator {
+            Value::Nil => return Ok(compare(mem, left.value(), right.value())),
+            Value::NativeFunction(function) => function.call(mem, thread, &[left, right])?,
+            _ => match thread {
+                Some(thread) => thread.call_function(mem, comparator, &[left, right])?,
+                None => {
+                    return Err(err_eval(
+                        "Priority queue comparator needs a Thread to call it",
+                    ))
+                }
+            },
+        };
+
+        match *result {
+            Value::Number(n) => Ok(n.cmp(&0)),
+            _ => Err(err_eval("Priority queue comparator must return a number")),
+        }
+    }
+
+    /// Add an item to the queue
+    pub fn push<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: Option<&Thread>,
+        item: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        mem.size_limits()
+            .check_vector(self.items.length() as usize + 1)?;
+        StackAnyContainer::push(&self.items, mem, item)?;
+
+        // sift the new item up, swapping rather than leaving a hole so that the queue is intact if
+        // the comparator fails
+        let mut index = self.items.length() - 1;
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            let parent_item = IndexedAnyContainer::get(&self.items, mem, parent)?;
+
+            if self.order(mem, thread, item, parent_item)? != Ordering::Less {
+                break;
+            }
+
+            IndexedAnyContainer::set(&self.items, mem, index, parent_item)?;
+            IndexedAnyContainer::set(&self.items, mem, parent, item)?;
+            index = parent;
+        }
+
+        Ok(())
+    }
+
+    /// Return the next item without removing it
+    pub fn peek<'guard>(&self, guard: &'guard dyn MutatorScope) -> Option<TaggedScopedPtr<'guard>> {
+        IndexedAnyContainer::get(&self.items, guard, 0).ok()
+    }
+
+    /// Remove and return the next item
+    pub fn pop<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: Option<&Thread>,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        let first = match self.peek(mem) {
+            Some(first) => first,
+            None => return Ok(None),
+        };
+
+        // move the last item to the root and sift it down
+        let item = StackAnyContainer::pop(&self.items, mem)?;
+        let length = self.items.length();
+        if length == 0 {
+            return Ok(Some(first));
+        }
+        IndexedAnyContainer::set(&self.items, mem, 0, item)?;
+
+        let mut index = 0;
+        loop {
+            let left = index * 2 + 1;
+            if left >= length {
+                break;
+            }
+
+            let mut child = left;
+            let mut child_item = IndexedAnyContainer::get(&self.items, mem, left)?;
+            if left + 1 < length {
+                let right_item = IndexedAnyContainer::get(&self.items, mem, left + 1)?;
+                if self.order(mem, thread, right_item, child_item)? == Ordering::Less {
+                    child = left + 1;
+                    child_item = right_item;
+                }
+            }
+
+            if self.order(mem, thread, child_item, item)? != Ordering::Less {
+                break;
+            }
+
+            IndexedAnyContainer::set(&self.items, mem, index, child_item)?;
+            IndexedAnyContainer::set(&self.items, mem, child, item)?;
+            index = child;
+        }
+
+        Ok(Some(first))
+    }
+}
+
+impl Verify for PriorityQueue {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.tagged(guard, self.comparator.get_ptr())?;
+        self.items.verify_children(guard, checker)
+    }
+}
+
+/// Items are printed in heap order, which begins with the next item to pop but is otherwise not
+/// the order they will be popped in, as sorting them could call the comparator.
+impl Print for PriorityQueue {
+    fn print<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "PriorityQueue[")?;
+        for index in 0..self.length() {
+            if index > 0 {
+                write!(f, " ")?;
+            }
+            match IndexedAnyContainer::get(&self.items, guard, index) {
+                Ok(item) => write!(f, "{}", item)?,
+                Err(_) => return Err(fmt::Error),
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::function::NativeFunction;
+    use crate::memory::{Memory, Mutator};
+    use crate::taggedptr::TaggedPtr;
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    fn num<'guard>(mem: &'guard MutatorView, n: isize) -> TaggedScopedPtr<'guard> {
+        TaggedScopedPtr::new(mem, TaggedPtr::number(n))
+    }
+
+    fn drain<'guard>(
+        mem: &'guard MutatorView,
+        queue: &PriorityQueue,
+    ) -> Result<Vec<isize>, RuntimeError> {
+        let mut popped = Vec::new();
+        while let Some(item) = queue.pop(mem, None)? {
+            popped.push(item.value().as_int().unwrap());
+        }
+        Ok(popped)
+    }
+
+    #[test]
+    fn priorityqueue_default_order() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let queue = PriorityQueue::alloc(mem, mem.nil())?;
+            assert!(queue.peek(mem).is_none());
+            assert!(format!("{}", queue.as_tagged(mem)) == "PriorityQueue[]");
+
+            for i in 0..50 {
+                queue.push(mem, None, num(mem, (i * 17) % 50))?;
+            }
+
+            assert!(queue.length() == 50);
+            assert!(queue.peek(mem).unwrap().value().as_int() == Some(0));
+            assert!(format!("{}", queue.as_tagged(mem)).starts_with("PriorityQueue[0 "));
+            assert!(drain(mem, &queue)? == (0..50).collect::<Vec<isize>>());
+            assert!(queue.pop(mem, None)?.is_none());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn priorityqueue_custom_comparator() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            fn reverse<'guard>(
+                mem: &'guard MutatorView,
+                args: &[TaggedScopedPtr<'guard>],
+            ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+                let result = match compare(mem, args[0].value(), args[1].value()) {
+                    Ordering::Less => 1,
+                    Ordering::Equal => 0,
+                    Ordering::Greater => -1,
+                };
+                Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(result)))
+            }
+
+            let comparator = NativeFunction::alloc(mem, "reverse", 2, reverse)?;
+            let queue = PriorityQueue::alloc(mem, comparator.as_tagged(mem))?;
+
+            for i in &[3, 1, 4, 1, 5, 9, 2, 6] {
+                queue.push(mem, None, num(mem, *i))?;
+            }
+
+            assert!(drain(mem, &queue)? == vec![9, 6, 5, 4, 3, 2, 1, 1]);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+}
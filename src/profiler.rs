@@ -0,0 +1,227 @@
+/// Per-opcode execution profiler.
+///
+/// Every instruction executed is counted by opcode. To keep overhead low, only one in every
+/// `sample_interval` instructions is timed; timings are accumulated into a power-of-two
+/// nanosecond histogram per opcode. Reports are available as CSV or as folded stacks that
+/// flamegraph tooling can consume directly.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::mem::{discriminant, Discriminant};
+use std::time::{Duration, Instant};
+
+use crate::bytecode::Opcode;
+
+/// Time one in every this many instructions by default
+pub const DEFAULT_SAMPLE_INTERVAL: u64 = 64;
+
+/// Number of histogram buckets. Bucket `n` counts samples taking less than 2^n nanoseconds,
+/// the last bucket counts everything slower.
+const HISTOGRAM_BUCKETS: usize = 24;
+
+/// Statistics for a single opcode
+struct OpcodeStats {
+    /// Opcode name without operands
+    name: String,
+    /// Number of times executed
+    count: u64,
+    /// Number of times timed
+    samples: u64,
+    /// Sum of all sampled durations
+    sampled_ns: u64,
+    /// Sampled durations in power-of-two buckets
+    histogram: [u64; HISTOGRAM_BUCKETS],
+}
+
+impl OpcodeStats {
+    fn new(opcode: Opcode) -> OpcodeStats {
+        // The Debug representation starts with the variant name
+        let debug = format!("{:?}", opcode);
+        let name = match debug.find(' ') {
+            Some(end) => String::from(&debug[..end]),
+            None => debug,
+        };
+
+        OpcodeStats {
+            name,
+            count: 0,
+            samples: 0,
+            sampled_ns: 0,
+            histogram: [0; HISTOGRAM_BUCKETS],
+        }
+    }
+
+    fn mean_ns(&self) -> u64 {
+        if self.samples == 0 {
+            0
+        } else {
+            self.sampled_ns / self.samples
+        }
+    }
+}
+
+/// Return the histogram bucket index for a duration
+fn bucket(ns: u64) -> usize {
+    let bits = (64 - ns.leading_zeros()) as usize;
+    if bits >= HISTOGRAM_BUCKETS {
+        HISTOGRAM_BUCKETS - 1
+    } else {
+        bits
+    }
+}
+
+/// Sampling opcode profiler
+pub struct Profiler {
+    sample_interval: u64,
+    /// Total instructions counted
+    executed: u64,
+    /// The opcode most recently counted, to which the next sample is attributed
+    current: Option<Discriminant<Opcode>>,
+    stats: HashMap<Discriminant<Opcode>, OpcodeStats>,
+}
+
+impl Profiler {
+    /// Create a profiler that times one in every `sample_interval` instructions
+    pub fn new(sample_interval: u64) -> Profiler {
+        Profiler {
+            sample_interval: sample_interval.max(1),
+            executed: 0,
+            current: None,
+            stats: HashMap::new(),
+        }
+    }
+
+    /// Return a start time if the next instruction to be executed should be timed
+    pub fn sample_start(&self) -> Option<Instant> {
+        if self.executed % self.sample_interval == 0 {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    /// Count an instruction about to be executed
+    pub fn count(&mut self, opcode: Opcode) {
+        let key = discriminant(&opcode);
+        self.stats
+            .entry(key)
+            .or_insert_with(|| OpcodeStats::new(opcode))
+            .count += 1;
+        self.executed += 1;
+        self.current = Some(key);
+    }
+
+    /// Attribute the time since `start` to the most recently counted instruction
+    pub fn sample_end(&mut self, start: Instant) {
+        let elapsed = start.elapsed();
+        if let Some(key) = self.current.take() {
+            if let Some(stats) = self.stats.get_mut(&key) {
+                let ns = duration_ns(elapsed);
+                stats.samples += 1;
+                stats.sampled_ns += ns;
+                stats.histogram[bucket(ns)] += 1;
+            }
+        }
+    }
+
+    /// Total number of instructions executed while profiling
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Opcode statistics, most frequently executed first
+    fn sorted_stats(&self) -> Vec<&OpcodeStats> {
+        let mut stats: Vec<&OpcodeStats> = self.stats.values().collect();
+        stats.sort_by(|a, b| b.count.cmp(&a.count).then(a.name.cmp(&b.name)));
+        stats
+    }
+
+    /// Render a CSV report, one row per opcode. Histogram columns `lt_<n>ns` count the samples
+    /// that took less than n nanoseconds but at least the previous column's bound.
+    pub fn csv_report(&self) -> String {
+        let mut report = String::from("opcode,count,samples,mean_ns");
+        for index in 0..HISTOGRAM_BUCKETS - 1 {
+            write!(report, ",lt_{}ns", 1u64 << index).unwrap();
+        }
+        report.push_str(",slower\n");
+
+        for stats in self.sorted_stats() {
+            write!(
+                report,
+                "{},{},{},{}",
+                stats.name,
+                stats.count,
+                stats.samples,
+                stats.mean_ns()
+            )
+            .unwrap();
+            for bucket_count in stats.histogram.iter() {
+                write!(report, ",{}", bucket_count).unwrap();
+            }
+            report.push('\n');
+        }
+
+        report
+    }
+
+    /// Render estimated total nanoseconds per opcode in the folded stack format used by
+    /// flamegraph tools
+    pub fn folded_report(&self) -> String {
+        let mut report = String::new();
+        for stats in self.sorted_stats() {
+            writeln!(
+                report,
+                "evalrus;{} {}",
+                stats.name,
+                stats.mean_ns() * stats.count
+            )
+            .unwrap();
+        }
+        report
+    }
+}
+
+fn duration_ns(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn profiler_histogram_buckets() {
+        assert!(bucket(0) == 0);
+        assert!(bucket(1) == 1);
+        assert!(bucket(3) == 2);
+        assert!(bucket(4) == 3);
+        assert!(bucket(u64::max_value()) == HISTOGRAM_BUCKETS - 1);
+    }
+
+    #[test]
+    fn profiler_counts_and_samples() {
+        let mut profiler = Profiler::new(2);
+
+        for _ in 0..4 {
+            let start = profiler.sample_start();
+            profiler.count(Opcode::NoOp);
+            if let Some(start) = start {
+                profiler.sample_end(start);
+            }
+        }
+        profiler.count(Opcode::Return { reg: 0 });
+
+        assert!(profiler.executed() == 5);
+
+        let csv = profiler.csv_report();
+        let mut lines = csv.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("opcode,count,samples,mean_ns,lt_1ns"));
+        assert!(lines.next().unwrap().starts_with("NoOp,4,2,"));
+        assert!(lines.next().unwrap().starts_with("Return,1,0,0"));
+
+        let folded = profiler.folded_report();
+        assert!(folded.lines().any(|line| line.starts_with("evalrus;NoOp ")));
+    }
+}
@@ -1,35 +1,299 @@
-use crate::compiler::compile;
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::io;
+use std::time::Instant;
+
+use crate::compiler::compile_with_thread;
+use crate::diagnostic::{Diagnostic, ErrorFormat};
 use crate::error::{ErrorKind, RuntimeError};
 use crate::memory::{Mutator, MutatorView};
 use crate::parser::parse;
-use crate::safeptr::{CellPtr, TaggedScopedPtr};
+use crate::printer::{print_limited, set_sorted_keys, PrintLimit};
+use crate::profiler::DEFAULT_SAMPLE_INTERVAL;
+use crate::replay::Trace;
+use crate::safeptr::{CellPtr, ScopedPtr, TaggedScopedPtr};
+use crate::serialize;
+use crate::tracer::PrintTracer;
 use crate::vm::Thread;
 
+/// Number of characters of a result printed before the rest is left out, unless the expression
+/// is evaluated with `:print-full`
+pub const RESULT_PRINT_LIMIT: usize = 10_000;
+
 /// A mutator that returns a Repl instance
-pub struct RepMaker {}
+pub struct RepMaker {
+    /// How errors and warnings are printed
+    pub error_format: ErrorFormat,
+}
 
 impl Mutator for RepMaker {
     type Input = ();
     type Output = ReadEvalPrint;
 
     fn run(&self, mem: &MutatorView, _input: ()) -> Result<ReadEvalPrint, RuntimeError> {
-        ReadEvalPrint::alloc(mem)
+        ReadEvalPrint::alloc(mem, self.error_format)
     }
 }
 
 /// Mutator that implements the VM
 pub struct ReadEvalPrint {
     main_thread: CellPtr<Thread>,
+    error_format: ErrorFormat,
+    /// Whether to report the time, instructions and allocation taken by each evaluation
+    timing: Cell<bool>,
+    /// Number of results bound to history variables so far
+    results: Cell<usize>,
+    /// Length of the global log of the main thread before each evaluation that changed globals,
+    /// the most recent last, for `:undo`
+    undo_marks: RefCell<Vec<usize>>,
 }
 
 impl ReadEvalPrint {
-    pub fn alloc(mem: &MutatorView) -> Result<ReadEvalPrint, RuntimeError> {
+    pub fn alloc(
+        mem: &MutatorView,
+        error_format: ErrorFormat,
+    ) -> Result<ReadEvalPrint, RuntimeError> {
+        let thread = Thread::alloc(mem)?;
+        thread.start_global_log(mem)?;
+
         Ok(ReadEvalPrint {
-            main_thread: CellPtr::new_with(Thread::alloc(mem)?),
+            main_thread: CellPtr::new_with(thread),
+            error_format,
+            timing: Cell::new(false),
+            results: Cell::new(0),
+            undo_marks: RefCell::new(Vec::new()),
         })
     }
 }
 
+impl ReadEvalPrint {
+    /// Bind a result to the next numbered history variable, `$1` for the first result, and to
+    /// `$it`, returning its number. The variables are globals of the main thread.
+    fn remember<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: ScopedPtr<'guard, Thread>,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<usize, RuntimeError> {
+        let number = self.results.get() + 1;
+        self.results.set(number);
+
+        thread.define_global(mem, mem.lookup_sym(&format!("${}", number)), value)?;
+        thread.define_global(mem, mem.lookup_sym("$it"), value)?;
+        Ok(number)
+    }
+
+    /// Parse, compile and evaluate a line of source code, printing debug output along the way
+    /// if requested
+    fn eval<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: ScopedPtr<'guard, Thread>,
+        line: &str,
+        debug: bool,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let value = parse(mem, line)?;
+
+        if debug {
+            println!(
+                "# Debug\n## Input:\n```\n{}\n```\n## Parsed:\n```\n{:?}\n```",
+                line, value
+            );
+        }
+
+        let function = compile_with_thread(mem, &thread, value)?;
+
+        if debug {
+            println!("## Compiled:\n```\n{:?}\n```", function);
+        }
+
+        let mark = thread.global_change_count();
+        let result = thread.quick_vm_eval(mem, function);
+        if thread.global_change_count() > mark {
+            self.undo_marks.borrow_mut().push(mark);
+        }
+
+        for warning in thread.take_warnings() {
+            match self.error_format {
+                ErrorFormat::Human => println!("warning: {}", warning.message()),
+                ErrorFormat::Json => println!("{}", warning.to_json(None)),
+            }
+        }
+
+        let value = result?;
+
+        if debug {
+            println!("## Evaluated:\n```\n{:?}\n```\n", value);
+        }
+
+        Ok(value)
+    }
+
+    /// Evaluate a line of source code, recording the instruction trace to the given file
+    fn eval_recording<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: ScopedPtr<'guard, Thread>,
+        path: &str,
+        line: &str,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        thread.start_recording(line);
+        let result = self.eval(mem, thread, line, false);
+
+        // save the trace whether evaluation succeeded or not - failures are the interesting part
+        if let Some(trace) = thread.stop_replay() {
+            match trace.save(path) {
+                Ok(()) => println!("recorded {} events to {}", trace.events.len(), path),
+                Err(e) => println!("error: could not save trace: {}", e),
+            }
+        }
+
+        result
+    }
+
+    /// Evaluate a line of source code with the profiler switched on, printing a per-opcode report
+    /// and, if a path is given, saving the report in the folded stack format to it
+    fn eval_profiling<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: ScopedPtr<'guard, Thread>,
+        folded_path: Option<&str>,
+        line: &str,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        thread.start_profiling(DEFAULT_SAMPLE_INTERVAL);
+        let result = self.eval(mem, thread, line, false);
+
+        if let Some(profiler) = thread.stop_profiling() {
+            println!("{} instructions executed", profiler.executed());
+            print!("{}", profiler.csv_report());
+
+            if let Some(path) = folded_path {
+                match fs::write(path, profiler.folded_report()) {
+                    Ok(()) => println!("saved folded stacks to {}", path),
+                    Err(e) => println!("error: could not save folded stacks: {}", e),
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Evaluate a line of source code, printing each instruction executed and its registers
+    fn eval_tracing<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: ScopedPtr<'guard, Thread>,
+        line: &str,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        thread.set_tracer(Some(Box::new(PrintTracer::new(Box::new(io::stdout())))));
+        let result = self.eval(mem, thread, line, false);
+        thread.set_tracer(None);
+
+        result
+    }
+
+    /// Revert the changes to globals made by the most recent evaluation that made any, printing
+    /// the names of the globals restored
+    fn undo<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: ScopedPtr<'guard, Thread>,
+    ) -> Result<(), RuntimeError> {
+        let mark = match self.undo_marks.borrow_mut().pop() {
+            Some(mark) => mark,
+            None => {
+                println!("nothing to undo");
+                return Ok(());
+            }
+        };
+
+        let mut names = Vec::new();
+        for name in thread.undo_global_changes(mem, mark)?.iter().rev() {
+            let name = name.to_string();
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        println!("restored {}", names.join(", "));
+        Ok(())
+    }
+
+    /// Save the globals defined or assigned in this session, with their current values, to a
+    /// session image file, see `serialize`. Globals whose value cannot be saved, such as closures
+    /// and native functions, are named.
+    fn save_session<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: ScopedPtr<'guard, Thread>,
+        path: &str,
+    ) -> Result<(), RuntimeError> {
+        let mut names: Vec<TaggedScopedPtr> = Vec::new();
+        for change in thread.global_changes(mem) {
+            if !names.contains(&change.name) {
+                names.push(change.name);
+            }
+        }
+
+        let mut bindings = Vec::new();
+        for name in names {
+            if let Some(value) = thread.lookup_global(mem, name) {
+                bindings.push((name, value));
+            }
+        }
+
+        let skipped = serialize::save_image(mem, &bindings, path)?;
+        println!(
+            "saved {} globals to {}",
+            bindings.len() - skipped.len(),
+            path
+        );
+        if !skipped.is_empty() {
+            let skipped: Vec<String> = skipped.iter().map(|name| name.to_string()).collect();
+            println!("not saved, their values cannot be: {}", skipped.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Bind the globals saved in a session image file. Restoring can be undone like an
+    /// evaluation, and the globals restored are saved again by `:save`.
+    fn restore_session<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: ScopedPtr<'guard, Thread>,
+        path: &str,
+    ) -> Result<(), RuntimeError> {
+        let bindings = serialize::load_image(mem, path)?;
+
+        let mark = thread.global_change_count();
+        for (name, value) in &bindings {
+            thread.restore_global(mem, *name, *value)?;
+        }
+        if thread.global_change_count() > mark {
+            self.undo_marks.borrow_mut().push(mark);
+        }
+
+        println!("restored {} globals from {}", bindings.len(), path);
+        Ok(())
+    }
+
+    /// Re-evaluate the source code stored in a trace file, checking execution against the trace
+    fn eval_replaying<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        thread: ScopedPtr<'guard, Thread>,
+        trace: Trace,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let line = trace.source.clone();
+        println!("replaying: {}", line);
+
+        thread.start_replay(trace);
+        let result = self.eval(mem, thread, &line, false);
+        thread.stop_replay();
+
+        result
+    }
+}
+
 impl Mutator for ReadEvalPrint {
     type Input = String;
     type Output = ();
@@ -38,51 +302,148 @@ impl Mutator for ReadEvalPrint {
         let thread = self.main_thread.get(mem);
 
         // If the first 2 chars of the line are ":d", then the user has requested a debug
-        // representation
-        let (line, debug) = if line.starts_with(":d ") {
-            (&line[3..], true)
-        } else {
-            (line.as_str(), false)
-        };
-
-        match (|mem, line| -> Result<TaggedScopedPtr, RuntimeError> {
-            let value = parse(mem, line)?;
+        // representation.
+        // ":record <file> <expr>" evaluates the expression, saving an instruction trace to the file.
+        // ":replay <file>" re-evaluates the expression recorded in the file against the trace.
+        // ":profile <expr>" evaluates the expression and prints per-opcode execution statistics.
+        // ":profile folded <file> <expr>" also saves them to the file as folded stacks for
+        // flamegraph tools.
+        // ":trace <expr>" evaluates the expression, printing each instruction as it is executed.
+        // ":verify" checks every heap object reachable from the thread.
+        // ":undo" reverts the changes to globals made by the last evaluation that made any.
+        // ":save <file>" saves the globals defined in the session to a session image file.
+        // ":restore <file>" binds the globals saved in a session image file.
+        // ":print-full <expr>" evaluates the expression and prints the result however long it is.
+        // ":set timing on|off" turns the cost report after each evaluation on or off.
+        // ":set sorted-keys on|off" turns printing dict items sorted by key on or off.
+        let start = Instant::now();
+        let start_instructions = thread.instruction_count();
+        let start_bytes = mem.bytes_allocated();
 
-            if debug {
-                println!(
-                    "# Debug\n## Input:\n```\n{}\n```\n## Parsed:\n```\n{:?}\n```",
-                    line, value
-                );
+        let mut print_full = false;
+        let result = if let Some(line) = line.strip_prefix(":d ") {
+            (line.to_string(), self.eval(mem, thread, line, true))
+        } else if let Some(rest) = line.strip_prefix(":record ") {
+            match rest.trim_start().split_once(' ') {
+                Some((path, line)) => (
+                    line.to_string(),
+                    self.eval_recording(mem, thread, path, line),
+                ),
+                None => {
+                    println!("usage: :record <file> <expr>");
+                    return Ok(());
+                }
             }
-
-            let function = compile(mem, value)?;
-
-            if debug {
-                println!("## Compiled:\n```\n{:?}\n```", function);
+        } else if let Some(rest) = line.strip_prefix(":profile folded ") {
+            match rest.trim_start().split_once(' ') {
+                Some((path, line)) => (
+                    line.to_string(),
+                    self.eval_profiling(mem, thread, Some(path), line),
+                ),
+                None => {
+                    println!("usage: :profile folded <file> <expr>");
+                    return Ok(());
+                }
             }
+        } else if let Some(line) = line.strip_prefix(":profile ") {
+            (
+                line.to_string(),
+                self.eval_profiling(mem, thread, None, line),
+            )
+        } else if let Some(line) = line.strip_prefix(":trace ") {
+            (line.to_string(), self.eval_tracing(mem, thread, line))
+        } else if let Some(path) = line.strip_prefix(":replay ") {
+            match Trace::load(path.trim()) {
+                Ok(trace) => (
+                    trace.source.clone(),
+                    self.eval_replaying(mem, thread, trace),
+                ),
+                Err(e) => {
+                    e.print_with_source("");
+                    return Ok(());
+                }
+            }
+        } else if let Some(line) = line.strip_prefix(":print-full ") {
+            print_full = true;
+            (line.to_string(), self.eval(mem, thread, line, false))
+        } else if let Some(setting) = line.strip_prefix(":set timing ") {
+            match setting.trim() {
+                "on" => self.timing.set(true),
+                "off" => self.timing.set(false),
+                _ => println!("usage: :set timing on|off"),
+            }
+            return Ok(());
+        } else if let Some(setting) = line.strip_prefix(":set sorted-keys ") {
+            match setting.trim() {
+                "on" => set_sorted_keys(true),
+                "off" => set_sorted_keys(false),
+                _ => println!("usage: :set sorted-keys on|off"),
+            }
+            return Ok(());
+        } else if line.trim() == ":undo" {
+            return self.undo(mem, thread);
+        } else if let Some(path) = line.strip_prefix(":save ") {
+            if let Err(e) = self.save_session(mem, thread, path.trim()) {
+                println!("error: could not save session: {}", e);
+            }
+            return Ok(());
+        } else if let Some(path) = line.strip_prefix(":restore ") {
+            if let Err(e) = self.restore_session(mem, thread, path.trim()) {
+                println!("error: could not restore session: {}", e);
+            }
+            return Ok(());
+        } else if line.trim() == ":verify" {
+            let objects = thread.verify_heap(mem)?;
+            println!("heap ok: {} objects reachable", objects);
+            return Ok(());
+        } else {
+            (line.clone(), self.eval(mem, thread, &line, false))
+        };
 
-            let value = thread.quick_vm_eval(mem, function)?;
-
-            if debug {
-                println!("## Evaluated:\n```\n{:?}\n```\n", value);
+        match result {
+            (_, Ok(value)) if print_full => {
+                let number = self.remember(mem, thread, value)?;
+                println!("${} = {}", number, value)
             }
 
-            Ok(value)
-        })(mem, &line)
-        {
-            Ok(value) => println!("{}", value),
+            (_, Ok(value)) => {
+                let number = self.remember(mem, thread, value)?;
+                let limit = PrintLimit::new(RESULT_PRINT_LIMIT);
+                match print_limited(*value, limit) {
+                    (printed, false) => println!("${} = {}", number, printed),
+                    (printed, true) => println!(
+                        "${} = {}...\n[result truncated at {} characters, use :print-full <expr> \
+                         to print all of it]",
+                        number, printed, limit.max_chars
+                    ),
+                }
+            }
 
-            Err(e) => {
+            (line, Err(e)) => {
                 match e.error_kind() {
                     // non-fatal repl errors
-                    ErrorKind::LexerError(_) => e.print_with_source(&line),
-                    ErrorKind::ParseError(_) => e.print_with_source(&line),
-                    ErrorKind::EvalError(_) => e.print_with_source(&line),
+                    ErrorKind::LexerError(_)
+                    | ErrorKind::ParseError(_)
+                    | ErrorKind::EvalError(_)
+                    | ErrorKind::AssertionFailed(_)
+                    | ErrorKind::StackOverflow(_)
+                    | ErrorKind::Thrown(_) => match self.error_format {
+                        ErrorFormat::Human => e.print_with_source(&line),
+                        ErrorFormat::Json => println!("{}", Diagnostic::from(&e).to_json(None)),
+                    },
                     _ => return Err(e),
                 }
             }
         }
 
+        if self.timing.get() {
+            println!(
+                "; {:.3?}, {} instructions, {} bytes allocated",
+                start.elapsed(),
+                thread.instruction_count() - start_instructions,
+                mem.bytes_allocated() - start_bytes
+            );
+        }
         Ok(())
     }
 }
@@ -0,0 +1,446 @@
+/// Instruction trace recording and deterministic replay.
+///
+/// A recording captures the source code that was evaluated, every instruction executed and
+/// every nondeterministic input consumed, in order. Replaying evaluates the same source again,
+/// feeding the recorded inputs back in and checking each executed instruction against the
+/// recording, so that a failure reported by an embedder can be reproduced offline.
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+
+use crate::array::ArraySize;
+use crate::bytecode::Opcode;
+use crate::error::{err_eval, err_io, RuntimeError};
+
+/// First line of a trace file
+const TRACE_HEADER: &str = "evalrus-trace 1";
+
+/// A single recorded event
+#[derive(Debug, PartialEq, Clone)]
+pub enum TraceEvent {
+    /// An instruction was executed at `ip` in the named function
+    Instruction {
+        function: String,
+        ip: ArraySize,
+        opcode: String,
+    },
+    /// A nondeterministic input identified by `source` produced `value`
+    Input { source: String, value: String },
+}
+
+/// A complete recording of one evaluation
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trace {
+    /// The source code that was evaluated
+    pub source: String,
+    /// Everything that happened during evaluation, in order
+    pub events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    /// Create an empty recording of the given source code
+    pub fn new(source: &str) -> Trace {
+        Trace {
+            source: String::from(source),
+            events: Vec::new(),
+        }
+    }
+
+    /// Write the trace out to a file
+    pub fn save(&self, path: &str) -> Result<(), RuntimeError> {
+        let mut file = File::create(path)
+            .map_err(|e| err_io(&format!("Could not write trace file {}", path), e))?;
+
+        writeln!(file, "{}", TRACE_HEADER)?;
+        writeln!(file, "source {}", self.source.len())?;
+        writeln!(file, "{}", self.source)?;
+
+        for event in &self.events {
+            match event {
+                TraceEvent::Instruction {
+                    function,
+                    ip,
+                    opcode,
+                } => writeln!(file, "i {} {} {}", ip, escape(function), opcode)?,
+                TraceEvent::Input { source, value } => {
+                    writeln!(file, "n {} {}", escape(source), escape(value))?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a trace from a file previously written by `save()`
+    pub fn load(path: &str) -> Result<Trace, RuntimeError> {
+        let file = File::open(path)
+            .map_err(|e| err_io(&format!("Could not read trace file {}", path), e))?;
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+
+        reader.read_line(&mut line)?;
+        if line.trim_end() != TRACE_HEADER {
+            return Err(err_eval("Not an evalrus trace file"));
+        }
+
+        // the source code is stored with a byte length prefix as it may span multiple lines
+        line.clear();
+        reader.read_line(&mut line)?;
+        let source_len = match line.trim_end().split_once(' ') {
+            Some(("source", len)) => len
+                .parse::<usize>()
+                .map_err(|_| err_eval("Invalid trace source length"))?,
+            _ => return Err(err_eval("Trace file is missing the source code")),
+        };
+
+        let mut source = vec![0; source_len + 1];
+        reader.read_exact(&mut source)?;
+        source.pop(); // trailing newline
+        let source =
+            String::from_utf8(source).map_err(|_| err_eval("Trace source is not valid UTF-8"))?;
+
+        let mut events = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let mut fields = line.splitn(4, ' ');
+
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some("i"), Some(ip), Some(function), Some(opcode)) => {
+                    events.push(TraceEvent::Instruction {
+                        function: unescape(function),
+                        ip: ip
+                            .parse::<ArraySize>()
+                            .map_err(|_| err_eval("Invalid instruction pointer in trace"))?,
+                        opcode: String::from(opcode),
+                    })
+                }
+
+                (Some("n"), Some(source), Some(value), None) => events.push(TraceEvent::Input {
+                    source: unescape(source),
+                    value: unescape(value),
+                }),
+
+                _ => return Err(err_eval(&format!("Invalid trace line: {}", line))),
+            }
+        }
+
+        Ok(Trace { source, events })
+    }
+}
+
+/// Escape whitespace and backslashes so that a string can be stored as a single trace field
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ' ' => escaped.push_str("\\s"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverse `escape()`
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('s') => unescaped.push(' '),
+                Some('n') => unescaped.push('\n'),
+                Some('r') => unescaped.push('\r'),
+                Some(other) => unescaped.push(other),
+                None => unescaped.push('\\'),
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+/// Recording or replaying state of a Thread
+pub enum ReplayMode {
+    /// Neither recording nor replaying
+    Off,
+    /// Appending every event to the trace
+    Recording(Trace),
+    /// Checking every event against the trace, `position` being the index of the next event
+    Replaying { trace: Trace, position: usize },
+}
+
+impl ReplayMode {
+    /// Return true if there is nothing to record or check
+    pub fn is_off(&self) -> bool {
+        matches!(self, ReplayMode::Off)
+    }
+
+    /// Record an executed instruction or check it against the next recorded event
+    pub fn instruction(
+        &mut self,
+        function: &str,
+        ip: ArraySize,
+        opcode: Opcode,
+    ) -> Result<(), RuntimeError> {
+        let opcode = format!("{:?}", opcode);
+
+        match self {
+            ReplayMode::Off => Ok(()),
+
+            ReplayMode::Recording(trace) => {
+                trace.events.push(TraceEvent::Instruction {
+                    function: String::from(function),
+                    ip,
+                    opcode,
+                });
+                Ok(())
+            }
+
+            ReplayMode::Replaying { trace, position } => {
+                let event = next_event(trace, position)?;
+
+                match event {
+                    TraceEvent::Instruction {
+                        function: rec_function,
+                        ip: rec_ip,
+                        opcode: rec_opcode,
+                    } if rec_function == function && *rec_ip == ip && *rec_opcode == opcode => {
+                        Ok(())
+                    }
+
+                    _ => Err(err_eval(&format!(
+                        "Replay diverged at event {}: expected {:?}, executed {} at {} in {}",
+                        *position - 1,
+                        event,
+                        opcode,
+                        ip,
+                        function
+                    ))),
+                }
+            }
+        }
+    }
+
+    /// Return the recorded value of a nondeterministic input when replaying, or None if the
+    /// input must be produced, see `record_input()`
+    pub fn replayed_input(&mut self, source: &str) -> Result<Option<String>, RuntimeError> {
+        match self {
+            ReplayMode::Off | ReplayMode::Recording(_) => Ok(None),
+
+            ReplayMode::Replaying { trace, position } => match next_event(trace, position)? {
+                TraceEvent::Input {
+                    source: rec_source,
+                    value,
+                } if rec_source == source => Ok(Some(value.clone())),
+
+                event => Err(err_eval(&format!(
+                    "Replay diverged at event {}: expected {:?}, requested input {}",
+                    *position - 1,
+                    event,
+                    source
+                ))),
+            },
+        }
+    }
+
+    /// Record the value a nondeterministic input produced, if recording
+    pub fn record_input(&mut self, source: &str, value: &str) {
+        if let ReplayMode::Recording(trace) = self {
+            trace.events.push(TraceEvent::Input {
+                source: String::from(source),
+                value: String::from(value),
+            });
+        }
+    }
+}
+
+/// Return the next event from a trace being replayed, advancing the position
+fn next_event<'a>(trace: &'a Trace, position: &mut usize) -> Result<&'a TraceEvent, RuntimeError> {
+    match trace.events.get(*position) {
+        Some(event) => {
+            *position += 1;
+            Ok(event)
+        }
+        None => Err(err_eval("Replay ran past the end of the recording")),
+    }
+}
+
+#[cfg(all(test, feature = "compiler"))]
+mod test {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::parser::parse;
+    use crate::safeptr::TaggedScopedPtr;
+    use crate::taggedptr::TaggedPtr;
+    use crate::vm::Thread;
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    #[test]
+    fn trace_escape_roundtrip() {
+        let original = "a b\\c\nd";
+        assert!(!escape(original).contains(' '));
+        assert!(unescape(&escape(original)) == original);
+    }
+
+    #[test]
+    fn trace_save_and_load() {
+        let mut trace = Trace::new("(car\n '(a b))");
+        trace.events.push(TraceEvent::Instruction {
+            function: String::from("<lambda>"),
+            ip: 3,
+            opcode: String::from("Return { reg: 2 }"),
+        });
+        trace.events.push(TraceEvent::Input {
+            source: String::from("clock"),
+            value: String::from("12 34"),
+        });
+
+        let mut path = std::env::temp_dir();
+        path.push("evalrus_trace_save_and_load.evt");
+        let path = path.to_str().unwrap();
+
+        trace.save(path).unwrap();
+        let loaded = Trace::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert!(loaded == trace);
+    }
+
+    #[test]
+    fn replay_matches_recording() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let source = "(cond (nil? 'a) 'x (nil? nil) 'y)";
+
+            let t = Thread::alloc(mem)?;
+
+            t.start_recording(source);
+            t.quick_vm_eval(mem, compile(mem, parse(mem, source)?)?)?;
+            let trace = t.stop_replay().unwrap();
+            assert!(trace.events.len() > 0);
+
+            // the same code replays cleanly
+            t.start_replay(trace.clone());
+            let result = t.quick_vm_eval(mem, compile(mem, parse(mem, source)?)?)?;
+            assert!(result == mem.lookup_sym("y"));
+            t.stop_replay();
+
+            // different code diverges from the recording
+            let other = "(cond (nil? nil) 'x (nil? nil) 'y)";
+            t.start_replay(trace);
+            let result = t.quick_vm_eval(mem, compile(mem, parse(mem, other)?)?);
+            assert!(result.is_err());
+            t.stop_replay();
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn replay_returns_recorded_uuid() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let source = "(uuid4)";
+
+            let t = Thread::alloc(mem)?;
+
+            t.start_recording(source);
+            let recorded = t.quick_vm_eval(mem, compile(mem, parse(mem, source)?)?)?;
+            let trace = t.stop_replay().unwrap();
+
+            t.start_replay(trace);
+            let replayed = t.quick_vm_eval(mem, compile(mem, parse(mem, source)?)?)?;
+            t.stop_replay();
+            assert!(format!("{}", replayed) == format!("{}", recorded));
+
+            // without a recording each call is a new UUID
+            let other = t.quick_vm_eval(mem, compile(mem, parse(mem, source)?)?)?;
+            assert!(format!("{}", other) != format!("{}", recorded));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn replay_returns_recorded_inputs() {
+        let mut mode = ReplayMode::Recording(Trace::new(""));
+        assert!(mode.replayed_input("random").unwrap().is_none());
+        mode.record_input("random", "4");
+
+        let trace = match mode {
+            ReplayMode::Recording(trace) => trace,
+            _ => unreachable!(),
+        };
+
+        let mut mode = ReplayMode::Replaying { trace, position: 0 };
+        let replayed = mode.replayed_input("random").unwrap();
+
+        assert!(replayed == Some(String::from("4")));
+        assert!(mode.replayed_input("random").is_err());
+    }
+
+    #[test]
+    fn replay_returns_recorded_host_results() {
+        use std::io::Cursor;
+        use std::sync::atomic::{AtomicIsize, Ordering};
+
+        static TICKETS: AtomicIsize = AtomicIsize::new(0);
+
+        fn next_ticket<'guard>(
+            mem: &'guard MutatorView,
+            _args: &[TaggedScopedPtr<'guard>],
+        ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+            let ticket = TICKETS.fetch_add(1, Ordering::SeqCst);
+            Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(ticket)))
+        }
+
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let source = "(list (next-ticket) (read-line) (next-ticket))";
+
+            let t = Thread::alloc(mem)?;
+            t.define_native(mem, "next-ticket", 0, next_ticket)?;
+            t.set_input(Box::new(Cursor::new("typed\n")));
+
+            t.start_recording(source);
+            let recorded = t.quick_vm_eval(mem, compile(mem, parse(mem, source)?)?)?;
+            let trace = t.stop_replay().unwrap();
+
+            // the host function is not called again and the input is not read again
+            t.start_replay(trace);
+            let replayed = t.quick_vm_eval(mem, compile(mem, parse(mem, source)?)?)?;
+            t.stop_replay();
+            assert!(format!("{}", replayed) == format!("{}", recorded));
+            assert!(TICKETS.load(Ordering::SeqCst) == 2);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+}
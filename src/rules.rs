@@ -0,0 +1,222 @@
+/// A rules engine built on the compiler and VM.
+///
+/// A RuleSet is an ordered list of rules, each a condition expression and an action expression,
+/// that refer to a fixed set of named facts. All the rules are compiled into a single dispatch
+/// function, sharing one literal pool, that takes the facts as its parameters:
+///
+/// ```text
+/// (lambda (<fact> ...)
+///   (cond <condition-0> (cons 0 <action-0>)
+///         <condition-1> (cons 1 <action-1>)
+///         ...))
+/// ```
+///
+/// Evaluating the RuleSet against a fact table calls the dispatch function once. The first rule
+/// whose condition is true fires: its action is evaluated and the result identifies the rule and
+/// gives the value of the action. Facts missing from the table are nil.
+use crate::compiler::compile_with_thread;
+use crate::containers::HashIndexedAnyContainer;
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
+use crate::memory::MutatorView;
+use crate::pair::cons;
+use crate::parser::parse;
+use crate::safeptr::{ScopedPtr, TaggedScopedPtr};
+use crate::taggedptr::{TaggedPtr, Value};
+use crate::vm::Thread;
+
+/// The rule that fired and the value of its action
+pub struct RuleMatch<'guard> {
+    /// Index of the rule in the order the rules were given
+    pub rule: usize,
+    /// Value of the rule's action expression
+    pub value: TaggedScopedPtr<'guard>,
+}
+
+/// Rules compiled into a dispatch function, see module documentation
+pub struct RuleSet<'guard> {
+    /// The function taking the facts as parameters and returning (rule . value) or nil
+    dispatch: TaggedScopedPtr<'guard>,
+    /// The fact names, as symbols, in parameter order
+    facts: Vec<TaggedScopedPtr<'guard>>,
+    /// Number of rules
+    count: usize,
+}
+
+impl<'guard> RuleSet<'guard> {
+    /// Compile the given (condition, action) source expression pairs, which may refer to the
+    /// named facts, for evaluation on the given Thread. The Thread's macros and special forms
+    /// apply.
+    pub fn compile(
+        mem: &'guard MutatorView,
+        thread: &Thread,
+        facts: &[&str],
+        rules: &[(&str, &str)],
+    ) -> Result<RuleSet<'guard>, RuntimeError> {
+        let facts: Vec<TaggedScopedPtr<'guard>> =
+            facts.iter().map(|name| mem.lookup_sym(name)).collect();
+
+        // build the cond clauses back to front
+        let mut clauses = mem.nil();
+        for (index, (condition, action)) in rules.iter().enumerate().rev() {
+            let rule = TaggedScopedPtr::new(mem, TaggedPtr::number(index as isize));
+            let action = parse(mem, action)?;
+            let result = cons(
+                mem,
+                mem.lookup_sym("cons"),
+                cons(mem, rule, cons(mem, action, mem.nil())?)?,
+            )?;
+
+            clauses = cons(mem, result, clauses)?;
+            clauses = cons(mem, parse(mem, condition)?, clauses)?;
+        }
+
+        let mut params = mem.nil();
+        for fact in facts.iter().rev() {
+            params = cons(mem, *fact, params)?;
+        }
+
+        let body = cons(mem, mem.lookup_sym("cond"), clauses)?;
+        let lambda = cons(
+            mem,
+            mem.lookup_sym("lambda"),
+            cons(mem, params, cons(mem, body, mem.nil())?)?,
+        )?;
+
+        // evaluating the lambda expression gives the dispatch function
+        let function = compile_with_thread(mem, thread, lambda)?;
+        let dispatch = thread.quick_vm_eval(mem, function)?;
+
+        Ok(RuleSet {
+            dispatch,
+            facts,
+            count: rules.len(),
+        })
+    }
+
+    /// Return the number of rules
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Return true if there are no rules
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Evaluate the rules against a fact table keyed by fact name symbols, returning the first
+    /// rule whose condition is true and the value of its action, or None if no rule fired
+    pub fn evaluate(
+        &self,
+        mem: &'guard MutatorView,
+        thread: &Thread,
+        facts: ScopedPtr<'guard, Dict>,
+    ) -> Result<Option<RuleMatch<'guard>>, RuntimeError> {
+        let args: Vec<TaggedScopedPtr<'guard>> = self
+            .facts
+            .iter()
+            .map(|name| facts.lookup(mem, *name).unwrap_or_else(|_| mem.nil()))
+            .collect();
+
+        let result = thread.call_function(mem, self.dispatch, &args)?;
+
+        match *result {
+            Value::Nil => Ok(None),
+            Value::Pair(pair) => match pair.first.get(mem).as_int() {
+                Some(rule) => Ok(Some(RuleMatch {
+                    rule: rule as usize,
+                    value: pair.second.get(mem),
+                })),
+                None => Err(err_eval("Rule dispatch returned an unexpected value")),
+            },
+            _ => Err(err_eval("Rule dispatch returned an unexpected value")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::{Memory, Mutator};
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    #[test]
+    fn rules_first_match_fires() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            let rules = RuleSet::compile(
+                mem,
+                &t,
+                &["status", "items"],
+                &[
+                    ("(is? status 'blocked)", "'reject"),
+                    ("(nil? items)", "'empty"),
+                    ("(is? status 'ok)", "(cons 'accept (car items))"),
+                ],
+            )?;
+            assert!(rules.len() == 3);
+
+            let facts = Dict::alloc(mem)?;
+            facts.assoc(mem, mem.lookup_sym("status"), mem.lookup_sym("ok"))?;
+            facts.assoc(mem, mem.lookup_sym("items"), parse(mem, "(x y)")?)?;
+
+            let fired = rules.evaluate(mem, &t, facts)?.unwrap();
+            assert!(fired.rule == 2);
+            assert!(format!("{}", fired.value) == "(accept . x)");
+
+            // earlier rules take precedence, and a missing fact is nil
+            facts.assoc(mem, mem.lookup_sym("status"), mem.lookup_sym("blocked"))?;
+            let fired = rules.evaluate(mem, &t, facts)?.unwrap();
+            assert!(fired.rule == 0 && fired.value == mem.lookup_sym("reject"));
+
+            let fired = rules.evaluate(mem, &t, Dict::alloc(mem)?)?.unwrap();
+            assert!(fired.rule == 1);
+
+            // no rule fires
+            facts.assoc(mem, mem.lookup_sym("status"), mem.lookup_sym("pending"))?;
+            assert!(rules.evaluate(mem, &t, facts)?.is_none());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn rules_compile_errors() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+
+            assert!(RuleSet::compile(mem, &t, &["a"], &[("(is? a", "'x")]).is_err());
+
+            let rules = RuleSet::compile(mem, &t, &["a"], &[])?;
+            assert!(rules.is_empty());
+            assert!(rules.evaluate(mem, &t, Dict::alloc(mem)?)?.is_none());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+}
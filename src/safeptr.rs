@@ -6,7 +6,7 @@ use stickyimmix::{AllocObject, RawPtr};
 
 use crate::headers::TypeList;
 use crate::pointerops::ScopedRef;
-use crate::printer::Print;
+use crate::printer::{Print, PrintDebug, PrintDisplay};
 use crate::taggedptr::{FatPtr, TaggedPtr, Value};
 
 /// Type that provides a generic anchor for mutator timeslice lifetimes
@@ -71,6 +71,37 @@ impl<'guard, T: Sized> Deref for ScopedPtr<'guard, T> {
     }
 }
 
+impl<'guard, T: Sized + Print> ScopedPtr<'guard, T> {
+    /// Return a wrapper that implements `Display` using the given scope
+    pub fn display(&self, guard: &'guard dyn MutatorScope) -> PrintDisplay<'guard, Self> {
+        PrintDisplay::new(guard, *self)
+    }
+
+    /// Return a wrapper that implements `Debug` using the given scope
+    pub fn debug(&self, guard: &'guard dyn MutatorScope) -> PrintDebug<'guard, Self> {
+        PrintDebug::new(guard, *self)
+    }
+}
+
+/// A typed pointer prints as the object it points to
+impl<'guard, T: Sized + Print> Print for ScopedPtr<'guard, T> {
+    fn print<'scope>(
+        &self,
+        guard: &'scope dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        self.value.print(guard, f)
+    }
+
+    fn debug<'scope>(
+        &self,
+        guard: &'scope dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        self.value.debug(guard, f)
+    }
+}
+
 impl<'guard, T: Sized + Print> fmt::Display for ScopedPtr<'guard, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.value.print(self, f)
@@ -79,7 +110,7 @@ impl<'guard, T: Sized + Print> fmt::Display for ScopedPtr<'guard, T> {
 
 impl<'guard, T: Sized + Print> fmt::Debug for ScopedPtr<'guard, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.value.print(self, f)
+        self.value.debug(self, f)
     }
 }
 
@@ -145,6 +176,16 @@ impl<'guard> TaggedScopedPtr<'guard> {
     pub fn get_ptr(&self) -> TaggedPtr {
         self.ptr
     }
+
+    /// Return a wrapper that implements `Display` using the given scope
+    pub fn display(&self, guard: &'guard dyn MutatorScope) -> PrintDisplay<'guard, Value<'guard>> {
+        PrintDisplay::new(guard, self.value)
+    }
+
+    /// Return a wrapper that implements `Debug` using the given scope
+    pub fn debug(&self, guard: &'guard dyn MutatorScope) -> PrintDebug<'guard, Value<'guard>> {
+        PrintDebug::new(guard, self.value)
+    }
 }
 
 /// Anything that _has_ a scope lifetime can pass as a scope representation. `Value` also implements
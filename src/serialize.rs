@@ -0,0 +1,962 @@
+/// Serialized bytecode files.
+///
+/// A compiled Function can be written out as bytes and loaded again later, in another process,
+/// without its source code and without compiling anything. The bytes hold the function and
+/// everything it refers to: its parameters, its bytecode, its literals and the functions nested
+/// in it.
+///
+/// The bytes begin with a magic number and a format version, followed by the function. Every
+/// value is a type byte followed by its content. Numbers are little endian, lengths are 32 bit
+/// and strings are UTF-8. Only the kinds of value that the compiler stores as literals can be
+/// serialized: nil, symbols, numbers, characters, text, lists and functions, and dicts of them.
+/// A dict is written as its items, sorted by key if `printer::sorted_keys()` is on so that the
+/// same dict is always written as the same bytes. Source code positions are not kept.
+///
+/// Each function's bytecode is validated as it is loaded, so a damaged or hand made file is
+/// reported as an error instead of being run.
+///
+/// A session image holds global variable bindings in the same way, so that the globals of an
+/// interactive session can be saved and restored in a later one. It begins with its own magic
+/// number and the format version, followed by the number of bindings and the name and value of
+/// each in turn.
+use std::fs;
+
+use num::bigint::BigInt;
+use num::rational::BigRational;
+use num::Zero;
+
+use crate::array::ArrayU16;
+use crate::bytecode::{ByteCode, Opcode};
+use crate::containers::{
+    AnyContainerFromSlice, Container, ContainerFromSlice, HashIndexedAnyContainer,
+    IndexedAnyContainer, IndexedContainer,
+};
+use crate::decimal::Decimal;
+use crate::dict::Dict;
+use crate::error::{err_eval, err_io, RuntimeError};
+use crate::function::Function;
+use crate::list::List;
+use crate::memory::MutatorView;
+use crate::number::{NumberObject, Numeric};
+use crate::pair::cons;
+use crate::safeptr::{MutatorScope, ScopedPtr, TaggedScopedPtr};
+use crate::taggedptr::{TaggedPtr, Value};
+
+/// First bytes of a serialized function
+const MAGIC: &[u8; 4] = b"EVC\0";
+/// First bytes of a session image
+const IMAGE_MAGIC: &[u8; 4] = b"EVI\0";
+/// Format version, changed whenever the format or the instruction set changes
+const VERSION: u8 = 3;
+
+// Value type bytes
+const TAG_NIL: u8 = 0;
+const TAG_SYMBOL: u8 = 1;
+const TAG_INTEGER: u8 = 2;
+const TAG_CHAR: u8 = 3;
+const TAG_TEXT: u8 = 4;
+const TAG_LIST: u8 = 5;
+const TAG_BIG_INTEGER: u8 = 6;
+const TAG_DECIMAL: u8 = 7;
+const TAG_RATIONAL: u8 = 8;
+const TAG_FLOAT: u8 = 9;
+const TAG_FUNCTION: u8 = 10;
+const TAG_KEYWORD: u8 = 11;
+const TAG_DICT: u8 = 12;
+
+/// Return an error describing why bytes could not be loaded
+fn err_format(reason: &str) -> RuntimeError {
+    err_eval(&format!("Invalid bytecode file: {}", reason))
+}
+
+/// An instruction operand, written as its own width
+trait Operand: Sized {
+    fn write(self, out: &mut Vec<u8>);
+    fn read(input: &mut Reader) -> Result<Self, RuntimeError>;
+}
+
+impl Operand for u8 {
+    fn write(self, out: &mut Vec<u8>) {
+        out.push(self);
+    }
+
+    fn read(input: &mut Reader) -> Result<u8, RuntimeError> {
+        input.byte()
+    }
+}
+
+impl Operand for i8 {
+    fn write(self, out: &mut Vec<u8>) {
+        out.push(self as u8);
+    }
+
+    fn read(input: &mut Reader) -> Result<i8, RuntimeError> {
+        Ok(input.byte()? as i8)
+    }
+}
+
+impl Operand for u16 {
+    fn write(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read(input: &mut Reader) -> Result<u16, RuntimeError> {
+        Ok(u16::from_le_bytes([input.byte()?, input.byte()?]))
+    }
+}
+
+impl Operand for i16 {
+    fn write(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read(input: &mut Reader) -> Result<i16, RuntimeError> {
+        Ok(i16::from_le_bytes([input.byte()?, input.byte()?]))
+    }
+}
+
+/// Define the opcode byte for each instruction and the functions that write and read them. The
+/// operands follow the opcode byte in the order given.
+macro_rules! opcode_codes {
+    ($($code:literal => $variant:ident { $($field:ident),* },)*) => {
+        fn write_opcode(opcode: Opcode, out: &mut Vec<u8>) {
+            match opcode {
+                $(Opcode::$variant { $($field),* } => {
+                    out.push($code);
+                    $($field.write(out);)*
+                })*
+            }
+        }
+
+        fn read_opcode(input: &mut Reader) -> Result<Opcode, RuntimeError> {
+            match input.byte()? {
+                $($code => Ok(Opcode::$variant { $($field: Operand::read(input)?),* }),)*
+                code => Err(err_format(&format!("unknown opcode {}", code))),
+            }
+        }
+    };
+}
+
+opcode_codes! {
+    0 => NoOp {},
+    1 => Return { reg },
+    2 => LoadLiteral { dest, literal_id },
+    3 => IsNil { dest, test },
+    5 => IsAtom { dest, test },
+    6 => FirstOfPair { dest, reg },
+    7 => SecondOfPair { dest, reg },
+    8 => MakePair { dest, reg1, reg2 },
+    9 => IsIdentical { dest, test1, test2 },
+    10 => IsLessThan { dest, left, right },
+    11 => IsGreaterThan { dest, left, right },
+    12 => IsLessOrEqual { dest, left, right },
+    13 => IsGreaterOrEqual { dest, left, right },
+    14 => IsNumericEqual { dest, left, right },
+    15 => Jump { offset },
+    16 => JumpIfTrue { test, offset },
+    17 => JumpIfNotTrue { test, offset },
+    18 => LoadNil { dest },
+    19 => LoadGlobal { dest, name, cache },
+    20 => StoreGlobal { src, name },
+    21 => Call { function, dest, arg_count },
+    22 => TailCall { function, dest, arg_count },
+    23 => Apply { function, dest, arg_count },
+    24 => CallWithContinuation { function, dest },
+    25 => EndContinuation {},
+    26 => MakeClosure { dest, function },
+    27 => LoadInteger { dest, integer },
+    28 => CopyRegister { dest, src },
+    29 => Add { dest, reg1, reg2 },
+    30 => Subtract { dest, left, right },
+    31 => Multiply { dest, reg1, reg2 },
+    32 => DivideInteger { dest, num, denom },
+    33 => Modulo { dest, num, denom },
+    34 => GetUpvalue { dest, src },
+    35 => SetUpvalue { dest, src },
+    36 => CloseUpvalues { reg1, reg2, reg3 },
+    37 => BindParameter { param, value },
+    38 => UnbindParameters { count },
+    39 => BeginLimit { limit, offset },
+    40 => EndLimit {},
+    41 => AssertFail { literal_id },
+    42 => Yield { dest, value },
+    43 => PushHandler { dest, offset },
+    44 => PopHandler {},
+    45 => PushCleanup { thunk },
+    46 => GetField { dest, object, key },
+    47 => JumpIfNotIdentical { test1, test2, offset },
+    48 => JumpIfNotLessThan { left, right, offset },
+    49 => JumpIfNotGreaterThan { left, right, offset },
+    50 => JumpIfNotLessOrEqual { left, right, offset },
+    51 => JumpIfNotGreaterOrEqual { left, right, offset },
+    52 => JumpIfNotNumericEqual { left, right, offset },
+}
+
+/// Writes values to a byte vector
+struct Writer<'guard> {
+    guard: &'guard dyn MutatorScope,
+    out: Vec<u8>,
+}
+
+impl<'guard> Writer<'guard> {
+    fn length(&mut self, length: usize) {
+        self.out.extend_from_slice(&(length as u32).to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.length(bytes.len());
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn integer(&mut self, value: &BigInt) {
+        self.bytes(&value.to_signed_bytes_le());
+    }
+
+    fn value(&mut self, value: TaggedScopedPtr<'guard>) -> Result<(), RuntimeError> {
+        match *value {
+            Value::Nil => self.out.push(TAG_NIL),
+
+            Value::Symbol(symbol) if symbol.is_keyword() => {
+                self.out.push(TAG_KEYWORD);
+                self.bytes(symbol.as_str(self.guard)[1..].as_bytes());
+            }
+
+            Value::Symbol(symbol) => {
+                self.out.push(TAG_SYMBOL);
+                self.bytes(symbol.as_str(self.guard).as_bytes());
+            }
+
+            Value::Number(number) => {
+                self.out.push(TAG_INTEGER);
+                self.out.extend_from_slice(&(number as i64).to_le_bytes());
+            }
+
+            Value::Char(c) => {
+                self.out.push(TAG_CHAR);
+                self.out.extend_from_slice(&(c as u32).to_le_bytes());
+            }
+
+            Value::Text(text) => {
+                self.out.push(TAG_TEXT);
+                self.bytes(text.as_str(self.guard).as_bytes());
+            }
+
+            // a list is written as its items and its tail, which is nil unless the list is
+            // improper, so that long lists are not written recursively
+            Value::Pair(_) => {
+                let mut items = Vec::new();
+                let mut next = value;
+                while let Value::Pair(pair) = *next {
+                    items.push(pair.first.get(self.guard));
+                    next = pair.second.get(self.guard);
+                }
+
+                self.out.push(TAG_LIST);
+                self.length(items.len());
+                for item in items {
+                    self.value(item)?;
+                }
+                self.value(next)?;
+            }
+
+            Value::NumberObject(number) => match number.value(self.guard) {
+                Numeric::Integer(integer) => {
+                    self.out.push(TAG_BIG_INTEGER);
+                    self.integer(&integer);
+                }
+                Numeric::Decimal(decimal) => {
+                    self.out.push(TAG_DECIMAL);
+                    self.integer(decimal.mantissa());
+                    self.out.extend_from_slice(&decimal.scale().to_le_bytes());
+                }
+                Numeric::Rational(rational) => {
+                    self.out.push(TAG_RATIONAL);
+                    self.integer(rational.numer());
+                    self.integer(rational.denom());
+                }
+                Numeric::Float(float) => {
+                    self.out.push(TAG_FLOAT);
+                    self.out.extend_from_slice(&float.to_bits().to_le_bytes());
+                }
+            },
+
+            Value::Function(function) => self.function(function)?,
+
+            Value::Dict(dict) => {
+                let items = dict.ordered_items(self.guard);
+
+                self.out.push(TAG_DICT);
+                self.length(items.len());
+                for (key, value) in items {
+                    self.value(key)?;
+                    self.value(value)?;
+                }
+            }
+
+            _ => return Err(err_eval(&format!("Cannot serialize the value {}", value))),
+        }
+
+        Ok(())
+    }
+
+    fn function(&mut self, function: ScopedPtr<'guard, Function>) -> Result<(), RuntimeError> {
+        let guard = self.guard;
+
+        self.out.push(TAG_FUNCTION);
+
+        if function.is_anonymous(guard) {
+            self.out.push(TAG_NIL);
+        } else {
+            self.out.push(TAG_SYMBOL);
+            self.bytes(function.name(guard).as_bytes());
+        }
+
+        self.out.push(function.is_variadic() as u8);
+
+        let params = function.param_names(guard);
+        self.length(params.length() as usize);
+        for index in 0..params.length() {
+            self.value(IndexedAnyContainer::get(&*params, guard, index)?)?;
+        }
+
+        if function.is_closure() {
+            let nonlocals = function.nonlocals(guard);
+            self.length(nonlocals.length() as usize);
+            for index in 0..nonlocals.length() {
+                IndexedContainer::get(&*nonlocals, guard, index)?.write(&mut self.out);
+            }
+        } else {
+            self.length(0);
+        }
+
+        let code = function.code(guard);
+        let instructions = code.instructions(guard);
+        self.length(instructions.len());
+        for opcode in instructions {
+            write_opcode(opcode, &mut self.out);
+        }
+
+        let literals = code.literals(guard)?;
+        self.length(literals.len());
+        for literal in literals {
+            self.value(literal)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads values from a byte slice
+struct Reader<'bytes> {
+    bytes: &'bytes [u8],
+    position: usize,
+}
+
+impl<'bytes> Reader<'bytes> {
+    fn take(&mut self, count: usize) -> Result<&'bytes [u8], RuntimeError> {
+        if self.bytes.len() - self.position < count {
+            return Err(err_format("unexpected end of file"));
+        }
+
+        let taken = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, RuntimeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, RuntimeError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, RuntimeError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn length(&mut self) -> Result<usize, RuntimeError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn string(&mut self) -> Result<&'bytes str, RuntimeError> {
+        let length = self.length()?;
+        std::str::from_utf8(self.take(length)?).map_err(|_| err_format("invalid UTF-8"))
+    }
+
+    fn integer(&mut self) -> Result<BigInt, RuntimeError> {
+        let length = self.length()?;
+        Ok(BigInt::from_signed_bytes_le(self.take(length)?))
+    }
+
+    fn value<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        match self.byte()? {
+            TAG_NIL => Ok(mem.nil()),
+
+            TAG_SYMBOL => Ok(mem.lookup_sym(self.string()?)),
+
+            TAG_KEYWORD => Ok(mem.lookup_keyword(self.string()?)),
+
+            TAG_INTEGER => match TaggedPtr::checked_number(self.u64()? as i64 as isize) {
+                Some(number) => Ok(TaggedScopedPtr::new(mem, number)),
+                None => Err(err_format("inline integer out of range")),
+            },
+
+            TAG_CHAR => match std::char::from_u32(self.u32()?) {
+                Some(c) => Ok(TaggedScopedPtr::new(mem, TaggedPtr::char(c))),
+                None => Err(err_format("invalid character")),
+            },
+
+            TAG_TEXT => mem.text(self.string()?),
+
+            TAG_LIST => {
+                let length = self.length()?;
+                let mut items = Vec::new();
+                for _ in 0..length {
+                    items.push(self.value(mem)?);
+                }
+
+                let mut head = self.value(mem)?;
+                for item in items.into_iter().rev() {
+                    head = cons(mem, item, head)?;
+                }
+                Ok(head)
+            }
+
+            TAG_BIG_INTEGER => {
+                let integer = self.integer()?;
+                Ok(NumberObject::alloc(mem, &Numeric::Integer(integer))?.as_tagged(mem))
+            }
+
+            TAG_DECIMAL => {
+                let decimal = Decimal::new(self.integer()?, self.u32()?);
+                Ok(NumberObject::alloc(mem, &Numeric::Decimal(decimal))?.as_tagged(mem))
+            }
+
+            TAG_RATIONAL => {
+                let (numer, denom) = (self.integer()?, self.integer()?);
+                if denom.is_zero() {
+                    return Err(err_format("rational with a zero denominator"));
+                }
+                let rational = BigRational::new_raw(numer, denom);
+                Ok(NumberObject::alloc(mem, &Numeric::Rational(rational))?.as_tagged(mem))
+            }
+
+            TAG_FLOAT => {
+                let float = f64::from_bits(self.u64()?);
+                Ok(NumberObject::alloc(mem, &Numeric::Float(float))?.as_tagged(mem))
+            }
+
+            TAG_FUNCTION => Ok(self.function(mem)?.as_tagged(mem)),
+
+            TAG_DICT => {
+                let length = self.length()?;
+                let dict = Dict::alloc(mem)?;
+                for _ in 0..length {
+                    let key = self.value(mem)?;
+                    let value = self.value(mem)?;
+                    dict.assoc(mem, key, value)?;
+                }
+                Ok(dict.as_tagged(mem))
+            }
+
+            tag => Err(err_format(&format!("unknown value type {}", tag))),
+        }
+    }
+
+    fn function<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+        let name = self.value(mem)?;
+        if !matches!(*name, Value::Nil | Value::Symbol(_)) {
+            return Err(err_format("function name is not a symbol"));
+        }
+
+        let variadic = match self.byte()? {
+            0 => false,
+            1 => true,
+            _ => return Err(err_format("invalid function flags")),
+        };
+
+        let param_count = self.length()?;
+        let mut params = Vec::new();
+        for _ in 0..param_count {
+            let param = self.value(mem)?;
+            if !matches!(*param, Value::Symbol(_)) {
+                return Err(err_format("parameter name is not a symbol"));
+            }
+            params.push(param);
+        }
+        if params.len() > u8::MAX as usize || (variadic && params.is_empty()) {
+            return Err(err_format("invalid parameter list"));
+        }
+        let params = <List as AnyContainerFromSlice>::from_slice(mem, &params)?;
+
+        let nonlocal_count = self.length()?;
+        let nonlocals = if nonlocal_count > 0 {
+            let mut refs = Vec::new();
+            for _ in 0..nonlocal_count {
+                refs.push(u16::read(self)?);
+            }
+            Some(ArrayU16::from_slice(mem, &refs)?)
+        } else {
+            None
+        };
+
+        let code = ByteCode::alloc(mem)?;
+        let instruction_count = self.length()?;
+        for _ in 0..instruction_count {
+            code.push(mem, read_opcode(self)?)?;
+        }
+
+        let literal_count = self.length()?;
+        if literal_count > u16::MAX as usize + 1 {
+            return Err(err_format("too many literals"));
+        }
+        for _ in 0..literal_count {
+            let literal = self.value(mem)?;
+            code.push_lit(mem, literal)?;
+        }
+
+        code.validate(mem)?;
+
+        Function::alloc(mem, name, params, variadic, code, nonlocals)
+    }
+}
+
+/// Return the given function and everything it refers to as bytes, see module documentation
+pub fn serialize<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: ScopedPtr<'guard, Function>,
+) -> Result<Vec<u8>, RuntimeError> {
+    let mut writer = Writer {
+        guard,
+        out: Vec::new(),
+    };
+
+    writer.out.extend_from_slice(MAGIC);
+    writer.out.push(VERSION);
+    writer.function(function)?;
+
+    Ok(writer.out)
+}
+
+/// Load a function from bytes returned by `serialize()`, validating its bytecode
+pub fn deserialize<'guard>(
+    mem: &'guard MutatorView,
+    bytes: &[u8],
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(err_format("not a bytecode file"));
+    }
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(err_format(&format!(
+            "format version {} is not supported, expected {}",
+            version, VERSION
+        )));
+    }
+
+    if reader.byte()? != TAG_FUNCTION {
+        return Err(err_format("expected a function"));
+    }
+    let function = reader.function(mem)?;
+
+    if reader.position != bytes.len() {
+        return Err(err_format("unexpected data after the function"));
+    }
+
+    Ok(function)
+}
+
+/// Return global variable bindings as the bytes of a session image, see module documentation.
+/// Bindings whose value cannot be serialized are left out, and their names returned.
+pub fn serialize_image<'guard>(
+    guard: &'guard dyn MutatorScope,
+    bindings: &[(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)],
+) -> Result<(Vec<u8>, Vec<TaggedScopedPtr<'guard>>), RuntimeError> {
+    let mut writer = Writer {
+        guard,
+        out: Vec::new(),
+    };
+    let mut skipped = Vec::new();
+
+    // each binding is written on its own first, so that one that fails leaves nothing behind
+    let mut count = 0;
+    let mut written = Vec::new();
+    for (name, value) in bindings {
+        let mut binding = Writer {
+            guard,
+            out: Vec::new(),
+        };
+        match binding.value(*name).and_then(|_| binding.value(*value)) {
+            Ok(()) => {
+                written.extend_from_slice(&binding.out);
+                count += 1;
+            }
+            Err(_) => skipped.push(*name),
+        }
+    }
+
+    writer.out.extend_from_slice(IMAGE_MAGIC);
+    writer.out.push(VERSION);
+    writer.length(count);
+    writer.out.extend_from_slice(&written);
+
+    Ok((writer.out, skipped))
+}
+
+/// Load the global variable bindings of a session image returned by `serialize_image()`
+pub fn deserialize_image<'guard>(
+    mem: &'guard MutatorView,
+    bytes: &[u8],
+) -> Result<Vec<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)>, RuntimeError> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if reader.take(IMAGE_MAGIC.len()).ok() != Some(&IMAGE_MAGIC[..]) {
+        return Err(err_format("not a session image"));
+    }
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(err_format(&format!(
+            "format version {} is not supported, expected {}",
+            version, VERSION
+        )));
+    }
+
+    let count = reader.length()?;
+    let mut bindings = Vec::new();
+    for _ in 0..count {
+        let name = reader.value(mem)?;
+        if !matches!(*name, Value::Symbol(_)) {
+            return Err(err_format("expected the name of a global"));
+        }
+        bindings.push((name, reader.value(mem)?));
+    }
+
+    if reader.position != bytes.len() {
+        return Err(err_format("unexpected data after the bindings"));
+    }
+
+    Ok(bindings)
+}
+
+/// Return values as bytes, for passing them to another heap in the same process: the number of
+/// values followed by each in turn, without a magic number or version
+pub fn serialize_values<'guard>(
+    guard: &'guard dyn MutatorScope,
+    values: &[TaggedScopedPtr<'guard>],
+) -> Result<Vec<u8>, RuntimeError> {
+    let mut writer = Writer {
+        guard,
+        out: Vec::new(),
+    };
+
+    writer.length(values.len());
+    for value in values {
+        writer.value(*value)?;
+    }
+
+    Ok(writer.out)
+}
+
+/// Load values from bytes returned by `serialize_values()`
+pub fn deserialize_values<'guard>(
+    mem: &'guard MutatorView,
+    bytes: &[u8],
+) -> Result<Vec<TaggedScopedPtr<'guard>>, RuntimeError> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    let count = reader.length()?;
+    let mut values = Vec::new();
+    for _ in 0..count {
+        values.push(reader.value(mem)?);
+    }
+
+    if reader.position != bytes.len() {
+        return Err(err_format("unexpected data after the values"));
+    }
+
+    Ok(values)
+}
+
+/// Serialize a function to the named file
+pub fn save<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: ScopedPtr<'guard, Function>,
+    path: &str,
+) -> Result<(), RuntimeError> {
+    fs::write(path, serialize(guard, function)?)
+        .map_err(|e| err_io(&format!("Could not write bytecode file {}", path), e))
+}
+
+/// Load a function from the named file
+pub fn load<'guard>(
+    mem: &'guard MutatorView,
+    path: &str,
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    let bytes =
+        fs::read(path).map_err(|e| err_io(&format!("Could not read bytecode file {}", path), e))?;
+    deserialize(mem, &bytes)
+        .map_err(|e| err_eval(&format!("Could not load bytecode file {}", path)).with_cause(e))
+}
+
+/// Write global variable bindings to the named file as a session image, returning the names
+/// of those left out
+pub fn save_image<'guard>(
+    guard: &'guard dyn MutatorScope,
+    bindings: &[(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)],
+    path: &str,
+) -> Result<Vec<TaggedScopedPtr<'guard>>, RuntimeError> {
+    let (bytes, skipped) = serialize_image(guard, bindings)?;
+    fs::write(path, bytes)
+        .map_err(|e| err_io(&format!("Could not write session image {}", path), e))?;
+    Ok(skipped)
+}
+
+/// Load the global variable bindings of the session image in the named file
+pub fn load_image<'guard>(
+    mem: &'guard MutatorView,
+    path: &str,
+) -> Result<Vec<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)>, RuntimeError> {
+    let bytes =
+        fs::read(path).map_err(|e| err_io(&format!("Could not read session image {}", path), e))?;
+    deserialize_image(mem, &bytes)
+        .map_err(|e| err_eval(&format!("Could not load session image {}", path)).with_cause(e))
+}
+
+#[cfg(all(test, feature = "compiler"))]
+mod test {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::error::ErrorKind;
+    use crate::memory::{Memory, Mutator};
+    use crate::parser::parse;
+    use crate::printer;
+    use crate::vm::Thread;
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let source = "((lambda (n . rest)
+                             (let ((add (lambda (x) (+ x n))))
+                               (list (add 1) rest '(a (b . c) \"text\" #\\x :k) 1.50m
+                                     (* 1152921504606846976 16) (if (< n 0) 'neg 'pos))))
+                           41 'r 's)";
+
+            let function = compile(mem, parse(mem, source)?)?;
+            let bytes = serialize(mem, function)?;
+            let loaded = deserialize(mem, &bytes)?;
+
+            let t = Thread::alloc(mem)?;
+            let expected = format!("{}", t.quick_vm_eval(mem, function)?);
+            let result = format!("{}", t.quick_vm_eval(mem, loaded)?);
+            assert_eq!(result, expected);
+            assert_eq!(
+                result,
+                "(42 (r s) (a (b . c) \"text\" #\\x :k) 1.50m 18446744073709551616 pos)"
+            );
+
+            // serializing the loaded function gives the same bytes
+            assert_eq!(serialize(mem, loaded)?, bytes);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn serialize_rejects_invalid_bytes() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let function = compile(mem, parse(mem, "(cons 'a 'b)")?)?;
+            let bytes = serialize(mem, function)?;
+
+            assert!(deserialize(mem, b"not bytecode").is_err());
+            assert!(deserialize(mem, &bytes[..bytes.len() - 1]).is_err());
+
+            let mut trailing = bytes.clone();
+            trailing.push(0);
+            assert!(deserialize(mem, &trailing).is_err());
+
+            let mut version = bytes.clone();
+            version[MAGIC.len()] = VERSION + 1;
+            assert!(deserialize(mem, &version).is_err());
+
+            // bytecode that loads a literal it does not have fails validation
+            let code = ByteCode::alloc(mem)?;
+            code.push_loadlit(mem, 0, 3)?;
+            code.push(mem, Opcode::Return { reg: 0 })?;
+            let params = List::alloc(mem)?;
+            let bad = Function::alloc(mem, mem.nil(), params, false, code, None)?;
+
+            let error = deserialize(mem, &serialize(mem, bad)?).unwrap_err();
+            assert!(
+                *error.error_kind()
+                    == ErrorKind::BadLiteralId {
+                        ip: 0,
+                        literal_id: 3
+                    }
+            );
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn serialize_image_round_trip() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let t = Thread::alloc(mem)?;
+            let function = compile(mem, parse(mem, "(def twice (x) (* x 2))")?)?;
+            let twice = t.quick_vm_eval(mem, function)?;
+            let apply = t.lookup_global(mem, mem.lookup_sym("apply")).unwrap();
+
+            let bindings = vec![
+                (mem.lookup_sym("twice"), twice),
+                (mem.lookup_sym("apply"), apply),
+                (mem.lookup_sym("items"), parse(mem, "'(1 \"two\" :three)")?),
+            ];
+            let (bytes, skipped) = serialize_image(mem, &bindings)?;
+
+            // a native function cannot be serialized, so it is left out
+            assert!(skipped == vec![mem.lookup_sym("apply")]);
+
+            let loaded = deserialize_image(mem, &bytes)?;
+            assert_eq!(loaded.len(), 2);
+            assert!(loaded[0].0 == mem.lookup_sym("twice"));
+            assert!(loaded[1].0 == mem.lookup_sym("items"));
+            assert_eq!(format!("{}", loaded[1].1), "(quote (1 \"two\" :three))");
+
+            let u = Thread::alloc(mem)?;
+            u.define_global(mem, loaded[0].0, loaded[0].1)?;
+            let result = u.quick_vm_eval(mem, compile(mem, parse(mem, "(twice 21)")?)?)?;
+            assert_eq!(format!("{}", result), "42");
+
+            // a bytecode file is not a session image
+            assert!(deserialize_image(mem, &serialize(mem, function)?).is_err());
+            assert!(deserialize_image(mem, &bytes[..bytes.len() - 1]).is_err());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn serialize_dict_sorted_keys() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let dict_of = |keys: &[&str]| -> Result<_, RuntimeError> {
+                let dict = Dict::alloc(mem)?;
+                for (index, key) in keys.iter().enumerate() {
+                    let value = TaggedScopedPtr::new(mem, TaggedPtr::number(index as isize));
+                    dict.assoc(mem, mem.lookup_sym(key), value)?;
+                }
+                Ok(dict)
+            };
+
+            let first = dict_of(&["c", "a", "b", "e", "d"])?;
+            let second = dict_of(&["a", "e", "d", "c", "b"])?;
+
+            printer::set_sorted_keys(true);
+            assert_eq!(
+                format!("{}", first.as_tagged(mem)),
+                "Dict[(a . 1) (b . 2) (c . 0) (d . 4) (e . 3)]"
+            );
+
+            // the same keys are written in the same order whatever order they were added in
+            let first_bytes = serialize_values(mem, &[first.as_tagged(mem)])?;
+            let keys = |bytes: &[u8]| -> Result<Vec<String>, RuntimeError> {
+                let values = deserialize_values(mem, bytes)?;
+                match *values[0] {
+                    Value::Dict(dict) => Ok(dict
+                        .ordered_items(mem)
+                        .iter()
+                        .map(|(key, _)| format!("{}", key))
+                        .collect()),
+                    _ => panic!("expected a dict"),
+                }
+            };
+            assert_eq!(keys(&first_bytes)?, vec!["a", "b", "c", "d", "e"]);
+            let second_bytes = serialize_values(mem, &[second.as_tagged(mem)])?;
+            assert_eq!(keys(&second_bytes)?, keys(&first_bytes)?);
+
+            let loaded = deserialize_values(mem, &first_bytes)?;
+            assert_eq!(
+                format!("{}", loaded[0]),
+                "Dict[(a . 1) (b . 2) (c . 0) (d . 4) (e . 3)]"
+            );
+
+            printer::set_sorted_keys(false);
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn load_errors_keep_their_cause() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            use std::error::Error;
+
+            let missing = std::env::temp_dir().join("evalrus-missing-bytecode.evc");
+            let missing = missing.to_str().unwrap();
+            let error = load(mem, missing).err().unwrap();
+            assert_eq!(
+                *error.error_kind(),
+                ErrorKind::IOError(format!("Could not read bytecode file {}", missing))
+            );
+            let cause = error.cause().unwrap();
+            assert!(matches!(cause.error_kind(), ErrorKind::IOError(_)));
+            assert_eq!(error.source().unwrap().to_string(), cause.to_string());
+
+            let garbage = std::env::temp_dir().join("evalrus-garbage-bytecode.evc");
+            let garbage = garbage.to_str().unwrap();
+            fs::write(garbage, b"not bytecode").unwrap();
+            let error = load(mem, garbage).err().unwrap();
+            fs::remove_file(garbage).unwrap();
+            assert_eq!(
+                *error.error_kind(),
+                ErrorKind::EvalError(format!("Could not load bytecode file {}", garbage))
+            );
+            assert!(error.cause().is_some());
+            assert!(error.cause().unwrap().cause().is_none());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+}
@@ -0,0 +1,642 @@
+/// An ordered associative container, keyed by any value according to the total order defined in
+/// `compare.rs`.
+///
+/// Implemented as an AA tree - a red-black tree variant with simpler rebalancing - whose nodes are
+/// stored in a single typed Array and refer to each other by index rather than by pointer, so
+/// that inserting an item doesn't allocate a heap object per node. Index 0 is a sentinel node
+/// standing in for an empty subtree. Nodes of removed items are kept on a free list for reuse.
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::array::{Array, ArraySize};
+use crate::compare::compare;
+use crate::containers::{Container, SliceableContainer, StackContainer};
+use crate::error::RuntimeError;
+use crate::heapcheck::{HeapChecker, Verify};
+use crate::memory::MutatorView;
+use crate::printer::Print;
+use crate::safeptr::{MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+
+/// Index of the sentinel node
+const EMPTY: ArraySize = 0;
+
+/// A tree node
+#[derive(Clone)]
+pub struct TreeNode {
+    key: TaggedCellPtr,
+    value: TaggedCellPtr,
+    left: ArraySize,
+    right: ArraySize,
+    /// AA tree level, 0 only for the sentinel
+    level: ArraySize,
+}
+
+impl TreeNode {
+    fn new() -> TreeNode {
+        TreeNode {
+            key: TaggedCellPtr::new_nil(),
+            value: TaggedCellPtr::new_nil(),
+            left: EMPTY,
+            right: EMPTY,
+            level: 0,
+        }
+    }
+}
+
+/// Tree operations over the node slice
+struct Tree<'nodes, 'guard> {
+    guard: &'guard dyn MutatorScope,
+    nodes: &'nodes mut [TreeNode],
+    root: ArraySize,
+    /// Head of the free node list, linked through `right`
+    free: ArraySize,
+}
+
+impl<'nodes, 'guard> Tree<'nodes, 'guard> {
+    fn order(&self, key: TaggedScopedPtr<'guard>, node: ArraySize) -> Ordering {
+        let node_key = self.nodes[node as usize].key.get(self.guard);
+        compare(self.guard, key.value(), node_key.value())
+    }
+
+    fn left(&self, node: ArraySize) -> ArraySize {
+        self.nodes[node as usize].left
+    }
+
+    fn right(&self, node: ArraySize) -> ArraySize {
+        self.nodes[node as usize].right
+    }
+
+    fn level(&self, node: ArraySize) -> ArraySize {
+        self.nodes[node as usize].level
+    }
+
+    /// Find the node for the given key
+    fn find(&self, key: TaggedScopedPtr<'guard>) -> Option<ArraySize> {
+        let mut node = self.root;
+        while node != EMPTY {
+            node = match self.order(key, node) {
+                Ordering::Less => self.left(node),
+                Ordering::Greater => self.right(node),
+                Ordering::Equal => return Some(node),
+            };
+        }
+        None
+    }
+
+    /// Rotate right to remove a horizontal left link
+    fn skew(&mut self, node: ArraySize) -> ArraySize {
+        let left = self.left(node);
+        if node != EMPTY && left != EMPTY && self.level(left) == self.level(node) {
+            self.nodes[node as usize].left = self.right(left);
+            self.nodes[left as usize].right = node;
+            left
+        } else {
+            node
+        }
+    }
+
+    /// Rotate left and promote to remove two consecutive horizontal right links
+    fn split(&mut self, node: ArraySize) -> ArraySize {
+        let right = self.right(node);
+        if node != EMPTY
+            && right != EMPTY
+            && self.right(right) != EMPTY
+            && self.level(self.right(right)) == self.level(node)
+        {
+            self.nodes[node as usize].right = self.left(right);
+            self.nodes[right as usize].left = node;
+            self.nodes[right as usize].level += 1;
+            right
+        } else {
+            node
+        }
+    }
+
+    /// Insert the new node, whose key must not already be present, into the subtree
+    fn insert(&mut self, subtree: ArraySize, new: ArraySize) -> ArraySize {
+        if subtree == EMPTY {
+            return new;
+        }
+
+        let key = self.nodes[new as usize].key.get(self.guard);
+        if self.order(key, subtree) == Ordering::Less {
+            let left = self.insert(self.left(subtree), new);
+            self.nodes[subtree as usize].left = left;
+        } else {
+            let right = self.insert(self.right(subtree), new);
+            self.nodes[subtree as usize].right = right;
+        }
+
+        let subtree = self.skew(subtree);
+        self.split(subtree)
+    }
+
+    /// Remove the node with the given key from the subtree
+    fn remove(&mut self, subtree: ArraySize, key: TaggedScopedPtr<'guard>) -> ArraySize {
+        if subtree == EMPTY {
+            return EMPTY;
+        }
+
+        match self.order(key, subtree) {
+            Ordering::Less => {
+                let left = self.remove(self.left(subtree), key);
+                self.nodes[subtree as usize].left = left;
+            }
+
+            Ordering::Greater => {
+                let right = self.remove(self.right(subtree), key);
+                self.nodes[subtree as usize].right = right;
+            }
+
+            Ordering::Equal => {
+                if self.left(subtree) == EMPTY && self.right(subtree) == EMPTY {
+                    self.release(subtree);
+                    return EMPTY;
+                }
+
+                // replace this node's item with its in-order neighbour's, then remove the
+                // neighbour, which is nearer to a leaf
+                if self.left(subtree) == EMPTY {
+                    let mut next = self.right(subtree);
+                    while self.left(next) != EMPTY {
+                        next = self.left(next);
+                    }
+                    let (next_key, next_value) = self.item(next);
+                    let right = self.remove(self.right(subtree), next_key);
+                    self.set_item(subtree, next_key, next_value);
+                    self.nodes[subtree as usize].right = right;
+                } else {
+                    let mut prev = self.left(subtree);
+                    while self.right(prev) != EMPTY {
+                        prev = self.right(prev);
+                    }
+                    let (prev_key, prev_value) = self.item(prev);
+                    let left = self.remove(self.left(subtree), prev_key);
+                    self.set_item(subtree, prev_key, prev_value);
+                    self.nodes[subtree as usize].left = left;
+                }
+            }
+        }
+
+        // rebalance on the way back up
+        let should_be = self
+            .level(self.left(subtree))
+            .min(self.level(self.right(subtree)))
+            + 1;
+        if should_be < self.level(subtree) {
+            self.nodes[subtree as usize].level = should_be;
+            let right = self.right(subtree);
+            if right != EMPTY && should_be < self.level(right) {
+                self.nodes[right as usize].level = should_be;
+            }
+        }
+
+        let subtree = self.skew(subtree);
+        let right = self.skew(self.right(subtree));
+        self.nodes[subtree as usize].right = right;
+        if right != EMPTY {
+            let right_right = self.skew(self.right(right));
+            self.nodes[right as usize].right = right_right;
+        }
+        let subtree = self.split(subtree);
+        let right = self.split(self.right(subtree));
+        self.nodes[subtree as usize].right = right;
+
+        subtree
+    }
+
+    fn item(&self, node: ArraySize) -> (TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>) {
+        let node = &self.nodes[node as usize];
+        (node.key.get(self.guard), node.value.get(self.guard))
+    }
+
+    fn set_item(
+        &mut self,
+        node: ArraySize,
+        key: TaggedScopedPtr<'guard>,
+        value: TaggedScopedPtr<'guard>,
+    ) {
+        let node = &self.nodes[node as usize];
+        node.key.set(key);
+        node.value.set(value);
+    }
+
+    /// Put a node on the free list, clearing it so that it doesn't keep its item reachable
+    fn release(&mut self, node: ArraySize) {
+        let free = self.free;
+        let released = &mut self.nodes[node as usize];
+        released.key.set_to_nil();
+        released.value.set_to_nil();
+        released.left = EMPTY;
+        released.right = free;
+        released.level = 0;
+        self.free = node;
+    }
+
+    /// Visit every item in order, pruning subtrees wholly outside [lower, upper)
+    fn visit<F>(
+        &self,
+        subtree: ArraySize,
+        lower: Option<TaggedScopedPtr<'guard>>,
+        upper: Option<TaggedScopedPtr<'guard>>,
+        f: &mut F,
+    ) where
+        F: FnMut(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>),
+    {
+        if subtree == EMPTY {
+            return;
+        }
+
+        let above_lower = match lower {
+            Some(lower) => self.order(lower, subtree) != Ordering::Greater,
+            None => true,
+        };
+        let below_upper = match upper {
+            Some(upper) => self.order(upper, subtree) == Ordering::Greater,
+            None => true,
+        };
+
+        if above_lower {
+            self.visit(self.left(subtree), lower, upper, f);
+        }
+
+        if above_lower && below_upper {
+            let (key, value) = self.item(subtree);
+            f(key, value);
+        }
+
+        if below_upper {
+            self.visit(self.right(subtree), lower, upper, f);
+        }
+    }
+}
+
+/// A sorted map, see module documentation
+pub struct SortedMap {
+    /// Index of the root node
+    root: Cell<ArraySize>,
+    /// Number of items stored
+    length: Cell<ArraySize>,
+    /// Head of the free node list
+    free: Cell<ArraySize>,
+    /// All tree nodes, the sentinel first
+    nodes: Array<TreeNode>,
+}
+
+impl SortedMap {
+    /// Allocate a new empty instance on the heap
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, SortedMap>, RuntimeError> {
+        mem.alloc(SortedMap {
+            root: Cell::new(EMPTY),
+            length: Cell::new(0),
+            free: Cell::new(EMPTY),
+            nodes: Array::new(),
+        })
+    }
+
+    /// Number of items in the map
+    pub fn length(&self) -> ArraySize {
+        self.length.get()
+    }
+
+    /// Run a tree operation over the nodes
+    fn with_tree<'guard, F, R>(&self, guard: &'guard dyn MutatorScope, f: F) -> R
+    where
+        F: FnOnce(&mut Tree<'_, 'guard>) -> R,
+    {
+        self.nodes.access_slice(guard, |nodes| {
+            let mut tree = Tree {
+                guard,
+                nodes,
+                root: self.root.get(),
+                free: self.free.get(),
+            };
+            let result = f(&mut tree);
+            self.root.set(tree.root);
+            self.free.set(tree.free);
+            result
+        })
+    }
+
+    /// Return the value associated with the key, if present
+    pub fn lookup<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        key: TaggedScopedPtr<'guard>,
+    ) -> Option<TaggedScopedPtr<'guard>> {
+        if self.length.get() == 0 {
+            return None;
+        }
+
+        self.with_tree(guard, |tree| tree.find(key).map(|node| tree.item(node).1))
+    }
+
+    /// Associate the value with the key, replacing any existing value
+    pub fn assoc<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        key: TaggedScopedPtr<'guard>,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        // the sentinel node
+        if self.nodes.length() == 0 {
+            self.nodes.push(mem, TreeNode::new())?;
+        }
+
+        let existing = self.with_tree(mem, |tree| tree.find(key));
+        if let Some(node) = existing {
+            return self.with_tree(mem, |tree| {
+                tree.nodes[node as usize].value.set(value);
+                Ok(())
+            });
+        }
+
+        mem.size_limits()
+            .check_table(self.length.get() as usize + 1)?;
+
+        // get a node, reusing a released one if possible. Pushing may reallocate the array so
+        // must happen outside of any slice access.
+        let free = self.free.get();
+        let new = if free != EMPTY {
+            self.with_tree(mem, |tree| {
+                tree.free = tree.right(free);
+                tree.nodes[free as usize].right = EMPTY;
+            });
+            free
+        } else {
+            self.nodes.push(mem, TreeNode::new())?;
+            self.nodes.length() - 1
+        };
+
+        self.with_tree(mem, |tree| {
+            let node = &mut tree.nodes[new as usize];
+            node.key.set(key);
+            node.value.set(value);
+            node.level = 1;
+
+            tree.root = tree.insert(tree.root, new);
+        });
+
+        self.length.set(self.length.get() + 1);
+        Ok(())
+    }
+
+    /// Remove the key, returning the value that was associated with it
+    pub fn dissoc<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        key: TaggedScopedPtr<'guard>,
+    ) -> Option<TaggedScopedPtr<'guard>> {
+        let value = self.lookup(guard, key)?;
+
+        self.with_tree(guard, |tree| {
+            tree.root = tree.remove(tree.root, key);
+        });
+
+        self.length.set(self.length.get() - 1);
+        Some(value)
+    }
+
+    /// Return all (key, value) items with keys in the range [lower, upper), in order. A bound of
+    /// None is unbounded.
+    pub fn range<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        lower: Option<TaggedScopedPtr<'guard>>,
+        upper: Option<TaggedScopedPtr<'guard>>,
+    ) -> Vec<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)> {
+        let mut items = Vec::new();
+
+        if self.length.get() > 0 {
+            self.with_tree(guard, |tree| {
+                tree.visit(tree.root, lower, upper, &mut |key, value| {
+                    items.push((key, value))
+                })
+            });
+        }
+
+        items
+    }
+
+    /// Return all (key, value) items in order
+    pub fn items<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Vec<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)> {
+        self.range(guard, None, None)
+    }
+
+    /// Return the item with the smallest key
+    pub fn first<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Option<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)> {
+        self.extreme(guard, |tree, node| tree.left(node))
+    }
+
+    /// Return the item with the largest key
+    pub fn last<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Option<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)> {
+        self.extreme(guard, |tree, node| tree.right(node))
+    }
+
+    /// Follow links in one direction from the root as far as possible
+    fn extreme<'guard, F>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        next: F,
+    ) -> Option<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)>
+    where
+        F: Fn(&Tree, ArraySize) -> ArraySize,
+    {
+        if self.length.get() == 0 {
+            return None;
+        }
+
+        self.with_tree(guard, |tree| {
+            let mut node = tree.root;
+            while next(tree, node) != EMPTY {
+                node = next(tree, node);
+            }
+
+            Some(tree.item(node))
+        })
+    }
+}
+
+impl Verify for SortedMap {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        self.nodes.verify_backing(checker)?;
+
+        for node in unsafe { self.nodes.as_slice(guard) }.iter() {
+            checker.tagged(guard, node.key.get_ptr())?;
+            checker.tagged(guard, node.value.get_ptr())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Print for SortedMap {
+    fn print<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "SortedMap[")?;
+        for (index, (key, value)) in self.items(guard).iter().enumerate() {
+            if index > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "({} . {})", key, value)?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::{Memory, Mutator};
+    use crate::taggedptr::TaggedPtr;
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    fn num<'guard>(mem: &'guard MutatorView, n: isize) -> TaggedScopedPtr<'guard> {
+        TaggedScopedPtr::new(mem, TaggedPtr::number(n))
+    }
+
+    fn keys<'guard>(mem: &'guard MutatorView, map: &SortedMap) -> Vec<isize> {
+        map.items(mem)
+            .iter()
+            .map(|(key, _)| key.value().as_int().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn sortedmap_assoc_lookup_in_order() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let map = SortedMap::alloc(mem)?;
+
+            // insert in a scrambled order
+            for i in 0..100 {
+                let key = (i * 37) % 100;
+                map.assoc(mem, num(mem, key), num(mem, key * 2))?;
+            }
+
+            assert!(map.length() == 100);
+            assert!(keys(mem, &map) == (0..100).collect::<Vec<isize>>());
+
+            for i in 0..100 {
+                let value = map.lookup(mem, num(mem, i)).unwrap();
+                assert!(value.value().as_int() == Some(i * 2));
+            }
+            assert!(map.lookup(mem, num(mem, 100)).is_none());
+
+            // replace a value
+            map.assoc(mem, num(mem, 5), mem.lookup_sym("five"))?;
+            assert!(map.length() == 100);
+            assert!(map.lookup(mem, num(mem, 5)) == Some(mem.lookup_sym("five")));
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn sortedmap_dissoc_and_reuse() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let map = SortedMap::alloc(mem)?;
+
+            for i in 0..64 {
+                map.assoc(mem, num(mem, i), num(mem, i))?;
+            }
+
+            // remove the even keys
+            for i in (0..64).step_by(2) {
+                assert!(map.dissoc(mem, num(mem, i)).is_some());
+            }
+            assert!(map.dissoc(mem, num(mem, 0)).is_none());
+
+            assert!(map.length() == 32);
+            assert!(keys(mem, &map) == (1..64).step_by(2).collect::<Vec<isize>>());
+
+            // released nodes are reused rather than growing the node array
+            let nodes = map.nodes.length();
+            for i in (0..64).step_by(2) {
+                map.assoc(mem, num(mem, i), num(mem, i))?;
+            }
+            assert!(map.nodes.length() == nodes);
+            assert!(keys(mem, &map) == (0..64).collect::<Vec<isize>>());
+
+            // remove everything
+            for i in 0..64 {
+                assert!(map.dissoc(mem, num(mem, 63 - i)).is_some());
+            }
+            assert!(map.length() == 0);
+            assert!(map.items(mem).is_empty());
+            assert!(map.first(mem).is_none());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn sortedmap_first_last_range() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let map = SortedMap::alloc(mem)?;
+
+            for i in &[50, 10, 40, 20, 30] {
+                map.assoc(mem, num(mem, *i), mem.nil())?;
+            }
+
+            assert!(map.first(mem).unwrap().0.value().as_int() == Some(10));
+            assert!(map.last(mem).unwrap().0.value().as_int() == Some(50));
+
+            let range: Vec<isize> = map
+                .range(mem, Some(num(mem, 20)), Some(num(mem, 40)))
+                .iter()
+                .map(|(key, _)| key.value().as_int().unwrap())
+                .collect();
+            assert!(range == vec![20, 30]);
+
+            let range = map.range(mem, Some(num(mem, 35)), None);
+            assert!(range.len() == 2);
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+}
@@ -12,10 +12,15 @@ use crate::safeptr::MutatorScope;
 /// underlying str data must have a lifetime of at least that of the Symbol instance to
 /// prevent use-after-free.
 /// See `SymbolMap`
+///
+/// A keyword, written `:name`, is a Symbol that evaluates to itself. Keywords are interned
+/// separately from other symbols and their name includes the leading colon, so `:foo` is never
+/// the same Symbol as `foo`.
 #[derive(Copy, Clone)]
 pub struct Symbol {
     name_ptr: *const u8,
     name_len: usize,
+    keyword: bool,
 }
 
 impl Symbol {
@@ -24,9 +29,24 @@ impl Symbol {
         Symbol {
             name_ptr: name.as_ptr(),
             name_len: name.len(),
+            keyword: false,
         }
     }
 
+    /// The originating &str must be owned by a SymbolMap hash table and include the colon
+    pub fn new_keyword(name: &str) -> Symbol {
+        Symbol {
+            name_ptr: name.as_ptr(),
+            name_len: name.len(),
+            keyword: true,
+        }
+    }
+
+    /// Return true if this is a self-evaluating keyword
+    pub fn is_keyword(&self) -> bool {
+        self.keyword
+    }
+
     /// Unsafe because Symbol does not own the &str nor can it know anything about the actual lifetime
     pub unsafe fn unguarded_as_str<'desired_lifetime>(&self) -> &'desired_lifetime str {
         let slice = slice::from_raw_parts(self.name_ptr, self.name_len);
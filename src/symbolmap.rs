@@ -13,9 +13,12 @@ use crate::symbol::Symbol;
 /// managed memory. This is arranged here by maintaining Symbol memory alongside the
 /// mapping HashMap.
 ///
+/// Keywords are kept in a mapping of their own, keyed by name without the leading colon.
+///
 /// No Symbol is ever deleted. Symbol name strings must be immutable.
 pub struct SymbolMap {
     map: RefCell<HashMap<String, RawPtr<Symbol>>>,
+    keywords: RefCell<HashMap<String, (String, RawPtr<Symbol>)>>,
     arena: Arena,
 }
 
@@ -23,6 +26,7 @@ impl SymbolMap {
     pub fn new() -> SymbolMap {
         SymbolMap {
             map: RefCell::new(HashMap::new()),
+            keywords: RefCell::new(HashMap::new()),
             arena: Arena::new(),
         }
     }
@@ -43,4 +47,20 @@ impl SymbolMap {
         self.map.borrow_mut().insert(name, ptr);
         ptr
     }
+
+    /// Look up the keyword with the given name, which does not include the leading colon
+    pub fn lookup_keyword(&self, name: &str) -> RawPtr<Symbol> {
+        {
+            if let Some((_, ptr)) = self.keywords.borrow().get(name) {
+                return *ptr;
+            }
+        }
+
+        let text = format!(":{}", name);
+        let ptr = self.arena.alloc(Symbol::new_keyword(&text)).unwrap();
+        self.keywords
+            .borrow_mut()
+            .insert(String::from(name), (text, ptr));
+        ptr
+    }
 }
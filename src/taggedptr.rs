@@ -14,41 +14,156 @@
 use std::fmt;
 use std::ptr::NonNull;
 
-use stickyimmix::{AllocRaw, RawPtr};
+use stickyimmix::{AllocHeader, AllocRaw, RawPtr};
 
 use crate::array::{ArrayU16, ArrayU32, ArrayU8};
+use crate::character::print_char;
+use crate::continuation::Continuation;
+use crate::deque::Deque;
 use crate::dict::Dict;
-use crate::function::{Function, Partial};
+use crate::error::RuntimeError;
+use crate::function::{Function, NativeFunction, Partial};
+use crate::generator::Generator;
+use crate::headers::TypeList;
+use crate::heapcheck::err_heap;
 use crate::list::List;
 use crate::memory::HeapStorage;
 use crate::number::NumberObject;
 use crate::pair::Pair;
-use crate::pointerops::{get_tag, ScopedRef, Tagged, TAG_NUMBER, TAG_OBJECT, TAG_PAIR, TAG_SYMBOL};
+use crate::parameter::Parameter;
+use crate::pointerops::{
+    get_tag, is_char, ScopedRef, Tagged, CHAR_SHIFT, TAG_CHAR, TAG_NUMBER, TAG_OBJECT, TAG_PAIR,
+    TAG_SYMBOL,
+};
+use crate::port::Port;
 use crate::printer::Print;
+use crate::priorityqueue::PriorityQueue;
 use crate::safeptr::{MutatorScope, ScopedPtr};
+use crate::sortedmap::SortedMap;
 use crate::symbol::Symbol;
 use crate::text::Text;
 use crate::vm::Upvalue;
 
+/// Largest integer that can be stored inline in a TaggedPtr
+pub const MAX_INLINE_INTEGER: isize = isize::MAX >> 2;
+/// Smallest integer that can be stored inline in a TaggedPtr
+pub const MIN_INLINE_INTEGER: isize = isize::MIN >> 2;
+
 /// A safe interface to GC-heap managed objects. The `'guard` lifetime must be a safe lifetime for
 /// the GC not to move or collect the referenced object.
 /// This should represent every type native to the runtime.
+///
+/// This is the type through which embedding code inspects evaluation results. New variants will be
+/// added as the runtime grows, so matches on it must include a wildcard arm. Prefer the `as_*`
+/// accessors, which will keep working when the underlying pointer representation changes.
+#[non_exhaustive]
 #[derive(Copy, Clone)]
 pub enum Value<'guard> {
+    /// The empty list, also used as false
     Nil,
+    /// A cons cell
     Pair(ScopedPtr<'guard, Pair>),
+    /// An interned symbol
     Symbol(ScopedPtr<'guard, Symbol>),
+    /// An integer small enough to be stored inline in a pointer
     Number(isize),
+    /// A Unicode character, stored inline in a pointer
+    Char(char),
+    /// A heap-allocated number
     NumberObject(ScopedPtr<'guard, NumberObject>),
+    /// A string
     Text(ScopedPtr<'guard, Text>),
+    /// A vector of any values
     List(ScopedPtr<'guard, List>),
+    /// A vector of bytes
     ArrayU8(ScopedPtr<'guard, ArrayU8>),
+    /// A vector of 16 bit unsigned integers
     ArrayU16(ScopedPtr<'guard, ArrayU16>),
+    /// A vector of 32 bit unsigned integers
     ArrayU32(ScopedPtr<'guard, ArrayU32>),
+    /// A hash map keyed by symbols or numbers
     Dict(ScopedPtr<'guard, Dict>),
+    /// A compiled function
     Function(ScopedPtr<'guard, Function>),
+    /// A partially applied function or closure
     Partial(ScopedPtr<'guard, Partial>),
+    /// A closed-over variable. Not normally visible outside of the VM.
     Upvalue(ScopedPtr<'guard, Upvalue>),
+    /// A function implemented in Rust
+    NativeFunction(ScopedPtr<'guard, NativeFunction>),
+    /// A map ordered by key
+    SortedMap(ScopedPtr<'guard, SortedMap>),
+    /// A binary heap ordered by a comparator
+    PriorityQueue(ScopedPtr<'guard, PriorityQueue>),
+    /// A double-ended queue
+    Deque(ScopedPtr<'guard, Deque>),
+    /// A dynamically scoped variable
+    Parameter(ScopedPtr<'guard, Parameter>),
+    /// An input or output port
+    Port(ScopedPtr<'guard, Port>),
+    /// An escaping continuation captured by call/cc
+    Continuation(ScopedPtr<'guard, Continuation>),
+    /// A suspendable function evaluation
+    Generator(ScopedPtr<'guard, Generator>),
+}
+
+impl<'guard> Value<'guard> {
+    /// Return true if the value is nil
+    pub fn is_nil(&self) -> bool {
+        match self {
+            Value::Nil => true,
+            _ => false,
+        }
+    }
+
+    /// Return the integer value, if this is an integer
+    pub fn as_int(&self) -> Option<isize> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Return the character, if this is a character
+    pub fn as_char(&self) -> Option<char> {
+        match self {
+            Value::Char(c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    /// Return the Pair, if this is a cons cell
+    pub fn as_pair(&self) -> Option<ScopedPtr<'guard, Pair>> {
+        match self {
+            Value::Pair(p) => Some(*p),
+            _ => None,
+        }
+    }
+
+    /// Return the string content of a Symbol or Text value
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Symbol(s) => Some(s.as_str(self)),
+            Value::Text(t) => Some(t.as_str(self)),
+            _ => None,
+        }
+    }
+
+    /// Return the List, if this is a List
+    pub fn as_list(&self) -> Option<ScopedPtr<'guard, List>> {
+        match self {
+            Value::List(l) => Some(*l),
+            _ => None,
+        }
+    }
+
+    /// Return the Dict, if this is a Dict
+    pub fn as_dict(&self) -> Option<ScopedPtr<'guard, Dict>> {
+        match self {
+            Value::Dict(d) => Some(*d),
+            _ => None,
+        }
+    }
 }
 
 /// `Value` can have a safe `Display` implementation
@@ -59,6 +174,8 @@ impl<'guard> fmt::Display for Value<'guard> {
             Value::Pair(p) => p.print(self, f),
             Value::Symbol(s) => s.print(self, f),
             Value::Number(n) => write!(f, "{}", *n),
+            Value::Char(c) => print_char(*c, f),
+            Value::NumberObject(n) => n.print(self, f),
             Value::Text(t) => t.print(self, f),
             Value::List(a) => a.print(self, f),
             Value::ArrayU8(a) => a.print(self, f),
@@ -68,6 +185,14 @@ impl<'guard> fmt::Display for Value<'guard> {
             Value::Function(n) => n.print(self, f),
             Value::Partial(p) => p.print(self, f),
             Value::Upvalue(_) => write!(f, "Upvalue"),
+            Value::NativeFunction(n) => n.print(self, f),
+            Value::SortedMap(m) => m.print(self, f),
+            Value::PriorityQueue(q) => q.print(self, f),
+            Value::Deque(q) => q.print(self, f),
+            Value::Parameter(p) => p.print(self, f),
+            Value::Port(p) => p.print(self, f),
+            Value::Continuation(k) => k.print(self, f),
+            Value::Generator(g) => g.print(self, f),
             _ => write!(f, "<unidentified-object-type>"),
         }
     }
@@ -77,23 +202,52 @@ impl<'guard> fmt::Debug for Value<'guard> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Value::Nil => write!(f, "nil"),
-            Value::Pair(p) => p.debug(self, f),
-            Value::Symbol(s) => s.debug(self, f),
+            Value::Pair(p) => fmt::Debug::fmt(p, f),
+            Value::Symbol(s) => fmt::Debug::fmt(s, f),
             Value::Number(n) => write!(f, "{}", *n),
-            Value::Text(t) => t.debug(self, f),
-            Value::List(a) => a.debug(self, f),
-            Value::ArrayU8(a) => a.debug(self, f),
-            Value::ArrayU16(a) => a.debug(self, f),
-            Value::ArrayU32(a) => a.debug(self, f),
-            Value::Dict(d) => d.debug(self, f),
-            Value::Function(n) => n.debug(self, f),
-            Value::Partial(p) => p.debug(self, f),
+            Value::Char(c) => print_char(*c, f),
+            Value::NumberObject(n) => fmt::Debug::fmt(n, f),
+            Value::Text(t) => fmt::Debug::fmt(t, f),
+            Value::List(a) => fmt::Debug::fmt(a, f),
+            Value::ArrayU8(a) => fmt::Debug::fmt(a, f),
+            Value::ArrayU16(a) => fmt::Debug::fmt(a, f),
+            Value::ArrayU32(a) => fmt::Debug::fmt(a, f),
+            Value::Dict(d) => fmt::Debug::fmt(d, f),
+            Value::Function(n) => fmt::Debug::fmt(n, f),
+            Value::Partial(p) => fmt::Debug::fmt(p, f),
             Value::Upvalue(_) => write!(f, "Upvalue"),
+            Value::NativeFunction(n) => fmt::Debug::fmt(n, f),
+            Value::SortedMap(m) => fmt::Debug::fmt(m, f),
+            Value::PriorityQueue(q) => fmt::Debug::fmt(q, f),
+            Value::Deque(q) => fmt::Debug::fmt(q, f),
+            Value::Parameter(p) => fmt::Debug::fmt(p, f),
+            Value::Port(p) => fmt::Debug::fmt(p, f),
+            Value::Continuation(k) => fmt::Debug::fmt(k, f),
+            Value::Generator(g) => fmt::Debug::fmt(g, f),
             _ => write!(f, "<unidentified-object-type>"),
         }
     }
 }
 
+/// `Value` already carries its scope, so printing it ignores the one given
+impl<'guard> Print for Value<'guard> {
+    fn print<'scope>(
+        &self,
+        _guard: &'scope dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+
+    fn debug<'scope>(
+        &self,
+        _guard: &'scope dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
 impl<'guard> MutatorScope for Value<'guard> {}
 
 /// An unpacked tagged Fat Pointer that carries the type information in the enum structure.
@@ -104,6 +258,7 @@ pub enum FatPtr {
     Pair(RawPtr<Pair>),
     Symbol(RawPtr<Symbol>),
     Number(isize),
+    Char(char),
     NumberObject(RawPtr<NumberObject>),
     Text(RawPtr<Text>),
     List(RawPtr<List>),
@@ -114,6 +269,14 @@ pub enum FatPtr {
     Function(RawPtr<Function>),
     Partial(RawPtr<Partial>),
     Upvalue(RawPtr<Upvalue>),
+    NativeFunction(RawPtr<NativeFunction>),
+    SortedMap(RawPtr<SortedMap>),
+    PriorityQueue(RawPtr<PriorityQueue>),
+    Deque(RawPtr<Deque>),
+    Parameter(RawPtr<Parameter>),
+    Port(RawPtr<Port>),
+    Continuation(RawPtr<Continuation>),
+    Generator(RawPtr<Generator>),
 }
 
 impl FatPtr {
@@ -127,6 +290,7 @@ impl FatPtr {
                 Value::Symbol(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
             FatPtr::Number(num) => Value::Number(*num),
+            FatPtr::Char(c) => Value::Char(*c),
             FatPtr::NumberObject(raw_ptr) => {
                 Value::NumberObject(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
@@ -151,6 +315,28 @@ impl FatPtr {
             FatPtr::Upvalue(raw_ptr) => {
                 Value::Upvalue(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
+            FatPtr::NativeFunction(raw_ptr) => {
+                Value::NativeFunction(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
+            FatPtr::SortedMap(raw_ptr) => {
+                Value::SortedMap(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
+            FatPtr::PriorityQueue(raw_ptr) => {
+                Value::PriorityQueue(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
+            FatPtr::Deque(raw_ptr) => {
+                Value::Deque(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
+            FatPtr::Parameter(raw_ptr) => {
+                Value::Parameter(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
+            FatPtr::Port(raw_ptr) => Value::Port(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard))),
+            FatPtr::Continuation(raw_ptr) => {
+                Value::Continuation(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
+            FatPtr::Generator(raw_ptr) => {
+                Value::Generator(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
         }
     }
 }
@@ -178,6 +364,14 @@ fatptr_from_rawptr!(Dict, Dict);
 fatptr_from_rawptr!(Function, Function);
 fatptr_from_rawptr!(Partial, Partial);
 fatptr_from_rawptr!(Upvalue, Upvalue);
+fatptr_from_rawptr!(NativeFunction, NativeFunction);
+fatptr_from_rawptr!(SortedMap, SortedMap);
+fatptr_from_rawptr!(PriorityQueue, PriorityQueue);
+fatptr_from_rawptr!(Deque, Deque);
+fatptr_from_rawptr!(Parameter, Parameter);
+fatptr_from_rawptr!(Port, Port);
+fatptr_from_rawptr!(Continuation, Continuation);
+fatptr_from_rawptr!(Generator, Generator);
 
 /// Conversion from an integer type
 impl From<isize> for FatPtr {
@@ -204,6 +398,7 @@ impl PartialEq for FatPtr {
             (Pair(p), Pair(q)) => p == q,
             (Symbol(p), Symbol(q)) => p == q,
             (Number(i), Number(j)) => i == j,
+            (Char(c), Char(d)) => c == d,
             (NumberObject(p), NumberObject(q)) => p == q,
             _ => false,
         }
@@ -260,6 +455,23 @@ impl TaggedPtr {
         }
     }
 
+    /// Construct an inline integer TaggedPtr, or None if the value does not fit in the bits left
+    /// after the tag
+    pub fn checked_number(value: isize) -> Option<TaggedPtr> {
+        if value >= MIN_INLINE_INTEGER && value <= MAX_INLINE_INTEGER {
+            Some(TaggedPtr::number(value))
+        } else {
+            None
+        }
+    }
+
+    /// Construct an inline character TaggedPtr
+    pub fn char(value: char) -> TaggedPtr {
+        TaggedPtr {
+            tag: ((value as usize) << CHAR_SHIFT) | TAG_CHAR,
+        }
+    }
+
     /// Construct an inline integer from a literal signed 16bit number
     pub fn literal_integer(value: i16) -> TaggedPtr {
         TaggedPtr {
@@ -274,6 +486,10 @@ impl TaggedPtr {
             } else {
                 match get_tag(self.tag) {
                     TAG_NUMBER => FatPtr::Number(self.number >> 2),
+                    // only ever constructed from a valid char
+                    TAG_SYMBOL if is_char(self.tag) => FatPtr::Char(std::char::from_u32_unchecked(
+                        (self.tag >> CHAR_SHIFT) as u32,
+                    )),
                     TAG_SYMBOL => FatPtr::Symbol(RawPtr::untag(self.symbol)),
                     TAG_PAIR => FatPtr::Pair(RawPtr::untag(self.pair)),
 
@@ -289,6 +505,50 @@ impl TaggedPtr {
             }
         }
     }
+
+    /// Check that the pointer tag agrees with the object header, for those types that have one.
+    /// Unlike conversion to a `FatPtr`, this returns an error rather than panicking.
+    pub fn verify_tag(&self) -> Result<(), RuntimeError> {
+        unsafe {
+            if self.tag == 0 {
+                return Ok(());
+            }
+
+            match get_tag(self.tag) {
+                // numbers and characters are inline and symbols are not allocated in the heap
+                TAG_NUMBER | TAG_SYMBOL => Ok(()),
+
+                TAG_PAIR => {
+                    let untyped_object_ptr = RawPtr::untag(self.pair).as_untyped();
+                    let header = HeapStorage::get_header(untyped_object_ptr);
+
+                    match header.as_ref().type_id() {
+                        TypeList::Pair => Ok(()),
+                        other => Err(err_heap(&format!(
+                            "Pair-tagged pointer refers to a {:?} header",
+                            other
+                        ))),
+                    }
+                }
+
+                TAG_OBJECT => {
+                    let untyped_object_ptr = RawPtr::untag(self.object).as_untyped();
+                    let header = HeapStorage::get_header(untyped_object_ptr);
+
+                    if header.as_ref().is_object_type() {
+                        Ok(())
+                    } else {
+                        Err(err_heap(&format!(
+                            "Object-tagged pointer refers to a {:?} header",
+                            header.as_ref().type_id()
+                        )))
+                    }
+                }
+
+                _ => Err(err_heap("Invalid TaggedPtr type tag")),
+            }
+        }
+    }
 }
 
 impl From<FatPtr> for TaggedPtr {
@@ -296,6 +556,7 @@ impl From<FatPtr> for TaggedPtr {
         match ptr {
             FatPtr::Nil => TaggedPtr::nil(),
             FatPtr::Number(value) => TaggedPtr::number(value),
+            FatPtr::Char(value) => TaggedPtr::char(value),
             FatPtr::Symbol(raw) => TaggedPtr::symbol(raw),
             FatPtr::Pair(raw) => TaggedPtr::pair(raw),
             FatPtr::NumberObject(raw) => TaggedPtr::object(raw),
@@ -308,6 +569,14 @@ impl From<FatPtr> for TaggedPtr {
             FatPtr::Function(raw) => TaggedPtr::object(raw),
             FatPtr::Partial(raw) => TaggedPtr::object(raw),
             FatPtr::Upvalue(raw) => TaggedPtr::object(raw),
+            FatPtr::NativeFunction(raw) => TaggedPtr::object(raw),
+            FatPtr::SortedMap(raw) => TaggedPtr::object(raw),
+            FatPtr::PriorityQueue(raw) => TaggedPtr::object(raw),
+            FatPtr::Deque(raw) => TaggedPtr::object(raw),
+            FatPtr::Parameter(raw) => TaggedPtr::object(raw),
+            FatPtr::Port(raw) => TaggedPtr::object(raw),
+            FatPtr::Continuation(raw) => TaggedPtr::object(raw),
+            FatPtr::Generator(raw) => TaggedPtr::object(raw),
         }
     }
 }
@@ -318,3 +587,82 @@ impl PartialEq for TaggedPtr {
         unsafe { self.tag == other.tag }
     }
 }
+
+#[cfg(all(test, feature = "compiler"))]
+mod test {
+    use super::*;
+    use crate::error::RuntimeError;
+    use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::parser::parse;
+    use crate::safeptr::TaggedScopedPtr;
+
+    fn test_helper(test_fn: fn(&MutatorView) -> Result<(), RuntimeError>) {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = fn(&MutatorView) -> Result<(), RuntimeError>;
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                test_fn: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                test_fn(mem)
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, test_fn).unwrap();
+    }
+
+    #[test]
+    fn value_accessors() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let sym = mem.lookup_sym("foo");
+            assert!(sym.value().as_str() == Some("foo"));
+            assert!(sym.value().as_int().is_none());
+
+            let text = Text::new_from_str(mem, "bar")?;
+            let text = mem.alloc_tagged(text)?;
+            assert!(text.value().as_str() == Some("bar"));
+
+            let num = TaggedScopedPtr::new(mem, TaggedPtr::number(42));
+            assert!(num.value().as_int() == Some(42));
+
+            // zero must not be mistaken for nil
+            let zero = TaggedScopedPtr::new(mem, TaggedPtr::number(0));
+            assert!(zero.value().as_int() == Some(0));
+            assert!(!zero.value().is_nil());
+
+            let pair = parse(mem, "(a b)")?;
+            let pair = pair.value().as_pair().unwrap();
+            assert!(pair.first.get(mem).value().as_str() == Some("a"));
+
+            assert!(mem.nil().value().is_nil());
+            assert!(mem.nil().value().as_pair().is_none());
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+
+    #[test]
+    fn value_display_and_debug() {
+        fn test_inner(mem: &MutatorView) -> Result<(), RuntimeError> {
+            let list = parse(mem, "(a b)")?;
+            assert!(format!("{}", list.display(mem)) == "(a b)");
+            assert!(format!("{:?}", list.debug(mem)) == "(a . (b . nil))");
+
+            let pair = list.value().as_pair().unwrap();
+            assert!(format!("{}", pair.display(mem)) == "(a b)");
+            assert!(format!("{:?}", pair.debug(mem)) == "(a . (b . nil))");
+
+            Ok(())
+        }
+
+        test_helper(test_inner);
+    }
+}
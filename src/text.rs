@@ -6,12 +6,14 @@ use std::str;
 
 use crate::error::{ErrorKind, RuntimeError};
 use crate::hashable::Hashable;
+use crate::heapcheck::{HeapChecker, Verify};
 use crate::memory::MutatorView;
 use crate::printer::Print;
 use crate::rawarray::{ArraySize, RawArray};
 use crate::safeptr::MutatorScope;
 
-/// While Text is somewhat similar to Symbol, it is instead garbage-collected heap allocated and not interned.
+/// While Text is somewhat similar to Symbol, it is instead garbage-collected heap allocated and not
+/// interned. Short Texts allocated through `MutatorView::text()` are shared by content though.
 #[derive(Copy, Clone)]
 pub struct Text {
     content: RawArray<u8>,
@@ -33,6 +35,8 @@ impl Text {
         let len = from_str.len();
         let from_ptr = from_str.as_ptr();
 
+        mem.size_limits().check_text(len)?;
+
         if len > (ArraySize::max_value() as usize) {
             return Err(RuntimeError::new(ErrorKind::BadAllocationRequest));
         }
@@ -62,15 +66,42 @@ impl Text {
     }
 }
 
+impl Verify for Text {
+    fn verify_children<'guard>(
+        &self,
+        _guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.raw_array(&self.content, self.content.capacity())
+    }
+}
+
 impl Print for Text {
     fn print<'guard>(
         &self,
         guard: &'guard dyn MutatorScope,
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
-        // TODO this will need to be printed with certain string escape codes embedded
-        write!(f, "\"{}\"", self.as_str(guard))
+        write!(f, "\"{}\"", escape(self.as_str(guard)))
+    }
+}
+
+/// Escape text so that, written between double quotes, it reads back as the same text
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
     }
+
+    escaped
 }
 
 impl Hashable for Text {
@@ -83,7 +114,7 @@ impl Hashable for Text {
 mod test {
     use super::Text;
     use crate::error::RuntimeError;
-    use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::memory::{Memory, Mutator, MutatorView, SHARED_TEXT_LENGTH};
 
     #[test]
     fn text_empty_string() {
@@ -170,4 +201,37 @@ mod test {
         let test = Test {};
         mem.mutate(&test, ()).unwrap();
     }
+
+    #[test]
+    fn short_text_is_shared() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                view: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let a = view.text("hello")?;
+                let b = view.text("hello")?;
+                assert!(a.get_ptr() == b.get_ptr());
+                assert!(view.text("world")?.get_ptr() != a.get_ptr());
+
+                let long = "x".repeat(SHARED_TEXT_LENGTH + 1);
+                let c = view.text(&long)?;
+                let d = view.text(&long)?;
+                assert!(c.get_ptr() != d.get_ptr());
+                assert!(c.as_str() == d.as_str());
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
 }
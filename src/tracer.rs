@@ -0,0 +1,67 @@
+/// Instruction-level execution tracing.
+///
+/// A `Tracer` installed on a Thread with `Thread::set_tracer()` is called before every instruction
+/// is executed, with the name of the function executing, the instruction pointer, the opcode and
+/// the register window of the call frame. While no tracer is installed the VM only checks for
+/// one. `PrintTracer` writes a line per instruction, as the REPL's `:trace` command does.
+use std::fmt::Write as FmtWrite;
+use std::io::Write;
+
+use crate::array::ArraySize;
+use crate::bytecode::Opcode;
+use crate::printer::{print_limited, PrintLimit};
+use crate::safeptr::{MutatorScope, TaggedCellPtr};
+
+/// Number of characters of a register value printed by `PrintTracer` before the rest is left out
+const REGISTER_PRINT_LIMIT: usize = 40;
+
+/// Receiver of the instructions a Thread executes
+pub trait Tracer {
+    /// Called before the instruction at `ip` of the named function is executed. The registers are
+    /// the whole window of the call frame, including registers left over from earlier calls.
+    fn instruction<'guard>(
+        &mut self,
+        guard: &'guard dyn MutatorScope,
+        function: &str,
+        ip: ArraySize,
+        opcode: Opcode,
+        registers: &[TaggedCellPtr],
+    );
+}
+
+/// A tracer that writes each instruction, followed by the registers that are not nil, as a line
+/// of text
+pub struct PrintTracer {
+    output: Box<dyn Write>,
+}
+
+impl PrintTracer {
+    pub fn new(output: Box<dyn Write>) -> PrintTracer {
+        PrintTracer { output }
+    }
+}
+
+impl Tracer for PrintTracer {
+    fn instruction<'guard>(
+        &mut self,
+        guard: &'guard dyn MutatorScope,
+        function: &str,
+        ip: ArraySize,
+        opcode: Opcode,
+        registers: &[TaggedCellPtr],
+    ) {
+        let mut line = format!("{}@{} {:?}", function, ip, opcode);
+        for (index, register) in registers.iter().enumerate() {
+            let value = register.get(guard);
+            if !value.is_nil() {
+                let (printed, truncated) =
+                    print_limited(*value, PrintLimit::new(REGISTER_PRINT_LIMIT));
+                let ellipsis = if truncated { "..." } else { "" };
+                let _ = write!(line, " r{}={}{}", index, printed, ellipsis);
+            }
+        }
+
+        // a trace that cannot be written is not an evaluation error
+        let _ = writeln!(self.output, "{}", line);
+    }
+}
@@ -1,24 +1,56 @@
-use std::cell::Cell;
+#[cfg(feature = "compiler")]
+use std::cell::Ref;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
 
 use crate::array::{Array, ArraySize};
-use crate::bytecode::{ByteCode, InstructionStream, Opcode};
+use crate::builtins;
+use crate::bytecode::{ByteCode, InstructionStream, NumArgs, Opcode, Register};
+use crate::codec::{base64_decode, base64_encode};
+#[cfg(feature = "compiler")]
+use crate::compiler::SpecialFormTable;
 use crate::containers::{
     Container, FillAnyContainer, HashIndexedAnyContainer, IndexedAnyContainer, IndexedContainer,
     SliceableContainer, StackAnyContainer, StackContainer,
 };
+use crate::continuation::{Continuation, ResumePoint};
+use crate::debugger::{operand_registers, Debugger, Step};
+use crate::diagnostic::Diagnostic;
 use crate::dict::Dict;
-use crate::error::{err_eval, RuntimeError};
-use crate::function::{Function, Partial};
+use crate::error::{err_eval, spos, ErrorKind, RuntimeError, SourcePos};
+use crate::function::{Function, NativeFn, NativeFunction, Partial, ThreadNativeFn};
+use crate::generator::{Generator, GeneratorState};
+use crate::globallog::{GlobalChange, GlobalLog};
+use crate::heapcheck::{HeapChecker, Verify};
 use crate::list::List;
 use crate::memory::MutatorView;
-use crate::pair::Pair;
+use crate::number::{self, numeric_comparison, ArithmeticOp, OverflowMode};
+use crate::pair::{cons, vec_from_pairs, Pair};
+use crate::parallel::{Job, SpawnPool, WorkerPool};
+use crate::parameter::Parameter;
+use crate::port::Port;
+use crate::profiler::Profiler;
+use crate::replay::{ReplayMode, Trace};
 use crate::safeptr::{CellPtr, MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::serialize::{deserialize_values, serialize_values};
 use crate::taggedptr::{TaggedPtr, Value};
+use crate::tracer::Tracer;
 
 pub const RETURN_REG: usize = 0;
 pub const ENV_REG: usize = 1;
 pub const FIRST_ARG_REG: usize = 2;
 
+/// Default call depth at which a function calling itself in non-tail position raises a warning
+pub const DEFAULT_RECURSION_WARNING_DEPTH: ArraySize = 10000;
+
+/// Number of registers in the window of each call frame, one for every value of a `Register`
+pub const WINDOW_SIZE: ArraySize = 256;
+
+/// Default maximum number of registers on the stack, at which a call raises a stack overflow
+pub const DEFAULT_MAX_STACK_SIZE: ArraySize = 1 << 20;
+
 /// Evaluation control flow flags
 #[derive(PartialEq)]
 pub enum EvalStatus<'guard> {
@@ -26,6 +58,12 @@ pub enum EvalStatus<'guard> {
     Pending,
     /// Eval is complete, here is the resulting value
     Return(TaggedScopedPtr<'guard>),
+    /// The generator being run yielded the value. It resumes with the value it is resumed with
+    /// in the given stack location.
+    Yielded(TaggedScopedPtr<'guard>, ArraySize),
+    /// The evaluation being debugged reached a breakpoint and is paused before the instruction
+    /// at it
+    Paused,
 }
 
 /// A call frame, separate from the register stack
@@ -37,6 +75,9 @@ pub struct CallFrame {
     ip: Cell<ArraySize>,
     /// Stack base - index into the register stack where register window for this function begins
     base: ArraySize,
+    /// Whether the function is a trampoline that calls a function from Rust, which is left out
+    /// of backtraces
+    trampoline: bool,
 }
 
 impl CallFrame {
@@ -46,6 +87,21 @@ impl CallFrame {
             function: CellPtr::new_with(main_fn),
             ip: Cell::new(0),
             base: 0,
+            trampoline: false,
+        }
+    }
+
+    /// Instantiate a frame for a trampoline function that calls a function from Rust, with a
+    /// register window at `base`
+    pub fn new_trampoline<'guard>(
+        function: ScopedPtr<'guard, Function>,
+        base: ArraySize,
+    ) -> CallFrame {
+        CallFrame {
+            function: CellPtr::new_with(function),
+            ip: Cell::new(0),
+            base,
+            trampoline: true,
         }
     }
 
@@ -60,13 +116,267 @@ impl CallFrame {
             function: CellPtr::new_with(function),
             ip: Cell::new(ip),
             base,
+            trampoline: false,
         }
     }
+}
 
-    /// Return a string representation of this stack frame
-    fn as_string<'guard>(&self, guard: &'guard dyn MutatorScope) -> String {
-        let function = self.function.get(guard);
-        format!("in {}", function)
+/// Return the field of a dict named by a symbol, as read by a dot-path such as `config.port`. The
+/// field is the value of the symbol key, or failing that of the keyword of the same name.
+fn get_field<'guard>(
+    mem: &'guard MutatorView,
+    object: TaggedScopedPtr<'guard>,
+    field: TaggedScopedPtr<'guard>,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let name = match *field {
+        Value::Symbol(s) => s.as_str(mem),
+        _ => return Err(err_eval("A field name must be a symbol")),
+    };
+
+    match *object {
+        Value::Dict(dict) => dict
+            .lookup(mem, field)
+            .or_else(|_| dict.lookup(mem, mem.lookup_keyword(name)))
+            .map_err(|_| err_eval(&format!("Dict has no field {}", name))),
+        _ => Err(err_eval(&format!(
+            "Cannot read field {} of {}, which is not a dict",
+            name, object
+        ))),
+    }
+}
+
+/// Return true if two registers hold identical values: the same pointer, or the same integer too
+/// large to be inline
+fn is_identical<'guard>(
+    mem: &'guard MutatorView,
+    test1: &TaggedCellPtr,
+    test2: &TaggedCellPtr,
+) -> bool {
+    test1.get_ptr() == test2.get_ptr()
+        || number::is_same_big_integer(mem, test1.get(mem), test2.get(mem))
+}
+
+/// Return true if the numeric comparison made by a comparison opcode holds. The operands are
+/// coerced as for arithmetic and every comparison with NaN is false.
+fn comparison_holds<'guard>(
+    mem: &'guard MutatorView,
+    opcode: Opcode,
+    left: TaggedScopedPtr<'guard>,
+    right: TaggedScopedPtr<'guard>,
+) -> Result<bool, RuntimeError> {
+    let (op, f): (&str, fn(&Ordering, &Ordering) -> bool) = match opcode {
+        Opcode::IsLessThan { .. } | Opcode::JumpIfNotLessThan { .. } => ("<", Ordering::lt),
+        Opcode::IsGreaterThan { .. } | Opcode::JumpIfNotGreaterThan { .. } => (">", Ordering::gt),
+        Opcode::IsLessOrEqual { .. } | Opcode::JumpIfNotLessOrEqual { .. } => ("<=", Ordering::le),
+        Opcode::IsGreaterOrEqual { .. } | Opcode::JumpIfNotGreaterOrEqual { .. } => {
+            (">=", Ordering::ge)
+        }
+        _ => ("=", Ordering::eq),
+    };
+
+    Ok(match numeric_comparison(mem, op, left, right)? {
+        Some(ordering) => f(&ordering, &Ordering::Equal),
+        None => false,
+    })
+}
+
+/// Move the closure environment and arguments of a tail call down to the base of the current
+/// register window, overwriting the registers of the calling function
+fn shift_tail_call_args(window: &mut [TaggedCellPtr], dest: Register, arg_count: usize) {
+    for index in ENV_REG..FIRST_ARG_REG + arg_count {
+        window[index] = window[dest as usize + index].clone();
+    }
+}
+
+/// Collect the arguments of a call to a variadic function beyond its required parameters into a
+/// list in the register of its rest parameter, returning the new argument count, which includes
+/// the rest parameter
+fn pack_rest_args<'guard>(
+    mem: &'guard MutatorView,
+    window: &mut [TaggedCellPtr],
+    dest: Register,
+    function: ScopedPtr<'guard, Function>,
+    arg_count: usize,
+) -> Result<usize, RuntimeError> {
+    let rest_reg = dest as usize + FIRST_ARG_REG + function.arity() as usize;
+    let args_end = dest as usize + FIRST_ARG_REG + arg_count;
+
+    let mut rest = mem.nil();
+    for reg in (rest_reg..args_end).rev() {
+        rest = cons(mem, window[reg].get(mem), rest)?;
+    }
+    window[rest_reg].set(rest);
+
+    Ok(function.arity() as usize + 1)
+}
+
+/// Spread the list in the last argument register of an `Apply` over the argument registers from
+/// that register onward, returning the resulting argument count
+fn spread_apply_args<'guard>(
+    mem: &'guard MutatorView,
+    window: &mut [TaggedCellPtr],
+    dest: Register,
+    arg_count: NumArgs,
+) -> Result<NumArgs, RuntimeError> {
+    if arg_count == 0 {
+        return Err(err_eval("apply expected a list of arguments"));
+    }
+
+    let list_reg = dest as usize + FIRST_ARG_REG + arg_count as usize - 1;
+    let items = vec_from_pairs(mem, window[list_reg].get(mem))?;
+
+    let count = arg_count as usize - 1 + items.len();
+    if list_reg + items.len() > window.len() || count > NumArgs::max_value() as usize {
+        return Err(err_eval(&format!(
+            "Too many arguments to apply, got {}",
+            count
+        )));
+    }
+
+    for (index, item) in items.into_iter().enumerate() {
+        window[list_reg + index].set(item);
+    }
+
+    Ok(count as NumArgs)
+}
+
+/// Collect the arguments of a call to a Rust function. By convention the caller places the
+/// arguments in consecutive registers starting at `dest + FIRST_ARG_REG` and the result is written
+/// back to `dest`, so native functions only ever see a slice of argument values and never the
+/// register window itself.
+fn native_call_args<'guard>(
+    guard: &'guard dyn MutatorScope,
+    window: &[TaggedCellPtr],
+    dest: Register,
+    arg_count: NumArgs,
+) -> Result<Vec<TaggedScopedPtr<'guard>>, RuntimeError> {
+    let args_start = dest as usize + FIRST_ARG_REG;
+    let args_end = args_start + arg_count as usize;
+
+    match window.get(args_start..args_end) {
+        Some(args) => Ok(args.iter().map(|arg| arg.get(guard)).collect()),
+        None => Err(err_eval(&format!(
+            "Call arguments in registers {} to {} are outside the register window",
+            args_start, args_end
+        ))),
+    }
+}
+
+/// Caps on the resources a top level evaluation may use, so that untrusted code can be run
+/// safely. Crossing one abandons the whole evaluation with a `VmLimitExceeded` error, which
+/// `with-limit` does not catch. None is no cap.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct VmLimits {
+    /// Maximum number of instructions executed
+    pub max_instructions: Option<u64>,
+    /// Maximum number of call frames on the stack at once
+    pub max_call_depth: Option<ArraySize>,
+    /// Maximum number of bytes allocated on the heap, see `MutatorView::bytes_allocated()`
+    pub max_heap: Option<usize>,
+}
+
+/// Signature of a function with which an embedding application supplies the values of global
+/// variables that are not bound, such as the cells of a spreadsheet. It is given the name of the
+/// variable and returns its value, or None to leave it unbound. The value is not stored in the
+/// globals, so the function is asked again each time the variable is read.
+pub type UnresolvedSymbolHandler =
+    for<'guard> fn(
+        &'guard MutatorView,
+        &str,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError>;
+
+/// The parts of a Thread that every instruction refers to. `vm_eval_stream()` fetches them once
+/// for each run of instructions rather than once per instruction. None of them is replaced while
+/// instructions run: a generator swaps in its own call frames and register stack only for an
+/// evaluation nested inside a native function call, and swaps them back before the call returns.
+#[derive(Copy, Clone)]
+struct Dispatch<'guard> {
+    frames: ScopedPtr<'guard, CallFrameList>,
+    stack: ScopedPtr<'guard, List>,
+    globals: ScopedPtr<'guard, Dict>,
+    instr: ScopedPtr<'guard, InstructionStream>,
+}
+
+/// An instruction budget set by a `with-limit` expression, with the state needed to abandon
+/// evaluation of the expression body when the budget runs out
+struct InstructionLimit {
+    /// Value of the Thread instruction counter at which the budget is exhausted
+    deadline: u64,
+    /// Number of call frames when the limit began
+    frame_depth: ArraySize,
+    /// Stack base of the frame the limit began in
+    stack_base: ArraySize,
+    /// Instruction to resume at, following the end of the expression body
+    resume_ip: ArraySize,
+    /// Register to put the `limit-exceeded` result in
+    dest: Register,
+    /// Length of the Parameter binding stack when the limit began
+    parameter_depth: ArraySize,
+    /// Number of live continuations when the limit began
+    continuation_depth: ArraySize,
+    /// Number of `try` handlers when the limit began
+    handler_depth: usize,
+}
+
+/// What a handler does with an error that unwinds through it
+#[derive(Copy, Clone)]
+enum HandlerAction {
+    /// Resume at the catch clause of a `try` expression, which begins at the instruction, with the
+    /// thrown value in the register
+    Catch {
+        resume_ip: ArraySize,
+        dest: Register,
+    },
+    /// Call the cleanup function of an `unwind-protect` expression, which is at the stack location,
+    /// and carry on unwinding
+    Cleanup { thunk: ArraySize },
+}
+
+/// A `try` or `unwind-protect` expression being evaluated, with the state needed to resume at its
+/// catch clause
+struct Handler {
+    action: HandlerAction,
+    /// Number of call frames when the expression began
+    frame_depth: ArraySize,
+    /// Stack base of the frame the expression began in
+    stack_base: ArraySize,
+    /// Length of the Parameter binding stack when the expression began
+    parameter_depth: ArraySize,
+    /// Number of instruction limits when the expression began
+    limit_depth: usize,
+    /// Number of live continuations when the expression began
+    continuation_depth: ArraySize,
+}
+
+/// The extent of the thread state belonging to the evaluation in progress. Evaluation nested by
+/// `Thread::call_function()` returns when its first call frame returns, and on error unwinds only
+/// what lies above its entry.
+#[derive(Copy, Clone)]
+struct EvalEntry {
+    /// Number of call frames below the evaluation
+    frame_depth: ArraySize,
+    /// Length of the Parameter binding stack when the evaluation began
+    parameter_depth: ArraySize,
+    /// Number of instruction limits when the evaluation began
+    limit_depth: usize,
+    /// Number of live continuations when the evaluation began
+    continuation_depth: ArraySize,
+    /// Number of `try` handlers when the evaluation began
+    handler_depth: usize,
+    /// True if the evaluation is a generator being resumed, which may yield
+    generator: bool,
+}
+
+impl EvalEntry {
+    fn new() -> EvalEntry {
+        EvalEntry {
+            frame_depth: 0,
+            parameter_depth: 0,
+            limit_depth: 0,
+            continuation_depth: 0,
+            handler_depth: 0,
+            generator: false,
+        }
     }
 }
 
@@ -144,6 +454,32 @@ impl Upvalue {
         self.closed.set(true);
         Ok(())
     }
+
+    /// Reopen a closed upvalue, copying its value back into the stack variable
+    fn reopen<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        stack: ScopedPtr<'guard, List>,
+    ) -> Result<(), RuntimeError> {
+        let ptr = self.value.get_ptr();
+        IndexedContainer::set(&*stack, guard, self.location, TaggedCellPtr::new_ptr(ptr))?;
+        self.closed.set(false);
+        Ok(())
+    }
+}
+
+impl Verify for Upvalue {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.tagged(guard, self.value.get_ptr())?;
+        match self.next {
+            Some(ref next) => checker.object(guard, &*next.get(guard)),
+            None => Ok(()),
+        }
+    }
 }
 
 /// Get the Upvalue for the index into the given closure environment.
@@ -166,6 +502,22 @@ fn env_upvalue_lookup<'guard>(
     }
 }
 
+impl Verify for CallFrameList {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        self.verify_backing(checker)?;
+
+        for frame in unsafe { self.as_slice(guard) }.iter() {
+            checker.object(guard, &*frame.function.get(guard))?;
+        }
+
+        Ok(())
+    }
+}
+
 /// An execution Thread object.
 /// It is composed of all the data structures required for execution of a bytecode stream -
 /// register stack, call frames, closure upvalues, thread-local global associations and the current
@@ -180,10 +532,96 @@ pub struct Thread {
     upvalues: CellPtr<Dict>,
     /// A dict that should only contain Symbol keys but any type as values
     globals: CellPtr<Dict>,
+    /// Macros defined by `defmacro`, a dict of Symbol keys and Function values that the compiler
+    /// calls to expand applications of the name
+    macros: CellPtr<Dict>,
+    /// Symbol property lists, a dict of Symbol keys and Dict values holding each symbol's
+    /// properties. A named function's docstring is its `doc` property and code that refers to a
+    /// global with a `deprecated` property is compiled with a warning.
+    properties: CellPtr<Dict>,
+    /// The special forms code compiled for this thread is compiled with
+    #[cfg(feature = "compiler")]
+    special_forms: RefCell<SpecialFormTable>,
+    /// Saved values of Parameters rebound by parameterize, pushed as Parameter then value pairs
+    /// so that they can be restored in reverse order
+    parameter_bindings: CellPtr<List>,
+    /// Continuations captured by call/cc that are still live, innermost last
+    continuations: CellPtr<List>,
+    /// The `current-output-port` Parameter, by default a console port writing to `output`
+    output_port: CellPtr<Parameter>,
+    /// The `current-input-port` Parameter, by default a console port reading from `input`
+    input_port: CellPtr<Parameter>,
+    /// The stream written to by console output ports
+    output: RefCell<Box<dyn Write>>,
+    /// The stream read from by console input ports
+    input: RefCell<Box<dyn BufRead>>,
+    /// Instruction budgets of the `with-limit` expressions being evaluated, innermost last
+    limits: RefCell<Vec<InstructionLimit>>,
+    /// Handlers of the `try` and `unwind-protect` expressions being evaluated, innermost last
+    handlers: RefCell<Vec<Handler>>,
+    /// The value most recently raised by `throw`
+    thrown: TaggedCellPtr,
+    /// Count of instructions executed, against which limit deadlines are compared
+    instruction_count: Cell<u64>,
+    /// Caps on the resources used by each top level evaluation
+    vm_limits: Cell<VmLimits>,
+    /// Instruction count and heap bytes allocated when the top level evaluation began
+    eval_start: Cell<(u64, usize)>,
+    /// Call depth at which non-tail self-recursion raises a warning
+    recursion_warning_depth: Cell<ArraySize>,
+    /// Maximum number of registers on the stack
+    max_stack_size: Cell<ArraySize>,
+    /// Warnings raised during compilation and evaluation, waiting to be taken by the embedder
+    warnings: RefCell<Vec<Diagnostic>>,
+    /// The source position of the latest definition of each global defined by code compiled for
+    /// this thread, if the source had positions
+    definitions: RefCell<HashMap<String, Option<SourcePos>>>,
     /// The current instruction location
     instr: CellPtr<InstructionStream>,
     /// The current stack base pointer
     stack_base: Cell<ArraySize>,
+    /// Where the innermost evaluation began, if it is nested inside a native function call
+    entry: Cell<EvalEntry>,
+    /// What integer arithmetic does with results too large to store inline
+    overflow_mode: Cell<OverflowMode>,
+    /// Supplier of the values of global variables that are not bound
+    unresolved_symbol_handler: Cell<Option<UnresolvedSymbolHandler>>,
+    /// Instruction trace recording or replay state
+    replay: RefCell<ReplayMode>,
+    /// Per-opcode profiler, if profiling is switched on
+    profiler: RefCell<Option<Profiler>>,
+    /// Receiver of every instruction executed, if tracing is switched on
+    tracer: RefCell<Option<Box<dyn Tracer>>>,
+    /// Changes to global variables, if the global log is on
+    global_log: RefCell<Option<GlobalLog>>,
+    /// Breakpoints, and whether an evaluation is being debugged
+    debugger: RefCell<Debugger>,
+    /// Runner of `par-map` jobs supplied by the embedder, if any
+    worker_pool: RefCell<Option<Box<dyn WorkerPool>>>,
+}
+
+impl Verify for Thread {
+    fn verify_children<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        checker: &mut HeapChecker,
+    ) -> Result<(), RuntimeError> {
+        checker.object(guard, &*self.frames.get(guard))?;
+        checker.object(guard, &*self.stack.get(guard))?;
+        checker.object(guard, &*self.upvalues.get(guard))?;
+        checker.object(guard, &*self.globals.get(guard))?;
+        checker.object(guard, &*self.macros.get(guard))?;
+        checker.object(guard, &*self.properties.get(guard))?;
+        checker.object(guard, &*self.parameter_bindings.get(guard))?;
+        checker.object(guard, &*self.continuations.get(guard))?;
+        checker.object(guard, &*self.output_port.get(guard))?;
+        checker.object(guard, &*self.input_port.get(guard))?;
+        checker.tagged(guard, self.thrown.get_ptr())?;
+        if let Some(log) = self.global_log.borrow().as_ref() {
+            log.verify(guard, checker)?;
+        }
+        checker.object(guard, &*self.instr.get(guard))
+    }
 }
 
 impl Thread {
@@ -196,14 +634,32 @@ impl Thread {
         let frames = CallFrameList::alloc_with_capacity(mem, 16)?;
 
         // create a minimal value stack
-        let stack = List::alloc_with_capacity(mem, 256)?;
-        stack.fill(mem, 256, mem.nil())?;
+        let stack = List::alloc_with_capacity(mem, WINDOW_SIZE)?;
+        stack.fill(mem, WINDOW_SIZE, mem.nil())?;
 
         // create an empty upvalue stack->heap mapping
-        let upvalues = Dict::alloc(mem)?;
-
-        // create an empty globals dict
-        let globals = Dict::alloc(mem)?;
+        let upvalues = Dict::alloc_uncapped(mem)?;
+
+        // create a globals dict containing the builtin functions
+        let globals = Dict::alloc_uncapped(mem)?;
+        builtins::load(mem, globals)?;
+
+        // create an empty parameter binding stack
+        let parameter_bindings = List::alloc(mem)?;
+
+        // create the console port parameters
+        let output_port = Parameter::alloc(mem, Port::alloc_console_output(mem)?.as_tagged(mem))?;
+        globals.assoc(
+            mem,
+            mem.lookup_sym("current-output-port"),
+            output_port.as_tagged(mem),
+        )?;
+        let input_port = Parameter::alloc(mem, Port::alloc_console_input(mem)?.as_tagged(mem))?;
+        globals.assoc(
+            mem,
+            mem.lookup_sym("current-input-port"),
+            input_port.as_tagged(mem),
+        )?;
 
         // create an empty instruction stream
         let blank_code = ByteCode::alloc(mem)?;
@@ -214,11 +670,1023 @@ impl Thread {
             stack: CellPtr::new_with(stack),
             upvalues: CellPtr::new_with(upvalues),
             globals: CellPtr::new_with(globals),
+            macros: CellPtr::new_with(Dict::alloc_uncapped(mem)?),
+            properties: CellPtr::new_with(Dict::alloc_uncapped(mem)?),
+            #[cfg(feature = "compiler")]
+            special_forms: RefCell::new(SpecialFormTable::standard()),
+            parameter_bindings: CellPtr::new_with(parameter_bindings),
+            continuations: CellPtr::new_with(List::alloc(mem)?),
+            output_port: CellPtr::new_with(output_port),
+            input_port: CellPtr::new_with(input_port),
+            output: RefCell::new(Box::new(io::stdout())),
+            input: RefCell::new(Box::new(io::BufReader::new(io::stdin()))),
+            limits: RefCell::new(Vec::new()),
+            handlers: RefCell::new(Vec::new()),
+            thrown: TaggedCellPtr::new_nil(),
+            instruction_count: Cell::new(0),
+            vm_limits: Cell::new(VmLimits::default()),
+            eval_start: Cell::new((0, 0)),
+            recursion_warning_depth: Cell::new(DEFAULT_RECURSION_WARNING_DEPTH),
+            max_stack_size: Cell::new(DEFAULT_MAX_STACK_SIZE),
+            warnings: RefCell::new(Vec::new()),
+            definitions: RefCell::new(HashMap::new()),
             instr: CellPtr::new_with(instr),
             stack_base: Cell::new(0),
+            entry: Cell::new(EvalEntry::new()),
+            overflow_mode: Cell::new(OverflowMode::Promote),
+            unresolved_symbol_handler: Cell::new(None),
+            replay: RefCell::new(ReplayMode::Off),
+            profiler: RefCell::new(None),
+            tracer: RefCell::new(None),
+            global_log: RefCell::new(None),
+            debugger: RefCell::new(Debugger::default()),
+            worker_pool: RefCell::new(None),
         })
     }
 
+    /// Begin profiling instructions executed on this thread, timing one in every
+    /// `sample_interval` instructions
+    pub fn start_profiling(&self, sample_interval: u64) {
+        *self.profiler.borrow_mut() = Some(Profiler::new(sample_interval));
+    }
+
+    /// Stop profiling, returning the collected profile
+    pub fn stop_profiling(&self) -> Option<Profiler> {
+        self.profiler.replace(None)
+    }
+
+    /// Begin recording every change to a global variable made by code this thread evaluates, see
+    /// `globallog`, discarding any changes already recorded
+    pub fn start_global_log<'guard>(&self, mem: &'guard MutatorView) -> Result<(), RuntimeError> {
+        *self.global_log.borrow_mut() = Some(GlobalLog::alloc(mem)?);
+        Ok(())
+    }
+
+    /// Stop recording changes to global variables, discarding those recorded
+    pub fn stop_global_log(&self) {
+        *self.global_log.borrow_mut() = None;
+    }
+
+    /// Return the changes to global variables recorded so far, oldest first, or nothing if the
+    /// global log is off
+    pub fn global_changes<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Vec<GlobalChange<'guard>> {
+        match self.global_log.borrow().as_ref() {
+            Some(log) => log.changes(guard),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return the number of changes to global variables recorded so far, 0 if the global log is
+    /// off
+    pub fn global_change_count(&self) -> usize {
+        match self.global_log.borrow().as_ref() {
+            Some(log) => log.len(),
+            None => 0,
+        }
+    }
+
+    /// Undo the changes to global variables recorded after the first `count`, newest first,
+    /// restoring each global to its old value or unbinding it if it was not bound, and remove
+    /// them from the log. Returns the names of the globals restored, in the order they were.
+    pub fn undo_global_changes<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        count: usize,
+    ) -> Result<Vec<TaggedScopedPtr<'guard>>, RuntimeError> {
+        let globals = self.globals.get(mem);
+        let mut restored = Vec::new();
+
+        if let Some(log) = self.global_log.borrow_mut().as_mut() {
+            while log.len() > count {
+                let change = match log.pop(mem)? {
+                    Some(change) => change,
+                    None => break,
+                };
+
+                match change.old {
+                    Some(old) => globals.assoc(mem, change.name, old)?,
+                    None => {
+                        globals.dissoc(mem, change.name)?;
+                    }
+                }
+                restored.push(change.name);
+            }
+        }
+
+        Ok(restored)
+    }
+
+    /// Install a tracer to be called before every instruction this thread executes, see
+    /// `tracer`, or remove the installed one with None. Returns the tracer that was installed.
+    pub fn set_tracer(&self, tracer: Option<Box<dyn Tracer>>) -> Option<Box<dyn Tracer>> {
+        self.tracer.replace(tracer)
+    }
+
+    /// Install a pool of host threads to run `par-map` jobs on, see `parallel`, or remove the
+    /// installed one with None. Returns the pool that was installed.
+    pub fn set_worker_pool(
+        &self,
+        pool: Option<Box<dyn WorkerPool>>,
+    ) -> Option<Box<dyn WorkerPool>> {
+        self.worker_pool.replace(pool)
+    }
+
+    /// Return the number of `par-map` jobs that can usefully run at once
+    pub fn worker_count(&self) -> usize {
+        match self.worker_pool.borrow().as_ref() {
+            Some(pool) => pool.workers().max(1),
+            None => SpawnPool::available().workers(),
+        }
+    }
+
+    /// Run `par-map` jobs on the installed worker pool, or on host threads spawned for them,
+    /// returning their results in order
+    pub fn run_jobs(&self, jobs: Vec<Job>) -> Vec<Result<Vec<u8>, RuntimeError>> {
+        match self.worker_pool.borrow().as_ref() {
+            Some(pool) => pool.run(jobs),
+            None => SpawnPool::available().run(jobs),
+        }
+    }
+
+    /// Pause the evaluation being debugged before the instruction at `ip` in the named function,
+    /// see `debugger`
+    pub fn set_breakpoint(&self, function: &str, ip: ArraySize) {
+        self.debugger.borrow_mut().set_breakpoint(function, ip);
+    }
+
+    /// Remove a breakpoint, returning true if it was set
+    pub fn clear_breakpoint(&self, function: &str, ip: ArraySize) -> bool {
+        self.debugger.borrow_mut().clear_breakpoint(function, ip)
+    }
+
+    /// Begin recording every instruction executed and nondeterministic input consumed by this
+    /// thread, discarding any recording or replay in progress
+    pub fn start_recording(&self, source: &str) {
+        *self.replay.borrow_mut() = ReplayMode::Recording(Trace::new(source));
+    }
+
+    /// Begin checking every instruction executed against the given recording, and supplying
+    /// nondeterministic inputs from it
+    pub fn start_replay(&self, trace: Trace) {
+        *self.replay.borrow_mut() = ReplayMode::Replaying { trace, position: 0 };
+    }
+
+    /// Stop recording or replaying, returning the trace if one was being recorded
+    pub fn stop_replay(&self) -> Option<Trace> {
+        match self.replay.replace(ReplayMode::Off) {
+            ReplayMode::Recording(trace) => Some(trace),
+            _ => None,
+        }
+    }
+
+    /// Obtain a value from a nondeterministic source such as a clock or random number generator.
+    /// The value is recorded if recording is on and taken from the recording if replaying.
+    pub fn nondeterministic_input<F>(
+        &self,
+        source: &str,
+        produce: F,
+    ) -> Result<String, RuntimeError>
+    where
+        F: FnOnce() -> Result<String, RuntimeError>,
+    {
+        if let Some(value) = self.replay.borrow_mut().replayed_input(source)? {
+            return Ok(value);
+        }
+
+        // Whatever the source does in turn, such as a host function calling back into the VM, is
+        // part of producing the value, so it is neither recorded nor replayed on its own
+        let mode = self.replay.replace(ReplayMode::Off);
+        let value = produce();
+        self.replay.replace(mode);
+
+        let value = value?;
+        self.replay.borrow_mut().record_input(source, &value);
+        Ok(value)
+    }
+
+    /// Obtain a value from the embedding application, such as the result of a host function,
+    /// through `nondeterministic_input()`. The value is recorded serialized, so it must be
+    /// serializable while recording, and replaying returns a copy of it.
+    fn nondeterministic_value<'guard, F>(
+        &self,
+        mem: &'guard MutatorView,
+        source: &str,
+        produce: F,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError>
+    where
+        F: FnOnce() -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError>,
+    {
+        if self.replay.borrow().is_off() {
+            return produce();
+        }
+
+        // no value is recorded as an empty string, which no serialized value encodes to
+        let mut produced = None;
+        let recorded = self.nondeterministic_input(source, || {
+            let value = produce()?;
+            produced = Some(value);
+            match value {
+                Some(value) => Ok(base64_encode(&serialize_values(mem, &[value])?)),
+                None => Ok(String::new()),
+            }
+        })?;
+
+        match produced {
+            Some(value) => Ok(value),
+            None if recorded.is_empty() => Ok(None),
+            None => Ok(deserialize_values(mem, &base64_decode(&recorded)?)?
+                .first()
+                .copied()),
+        }
+    }
+
+    /// Call a Rust function. The result of a host function is a nondeterministic input, so that
+    /// replaying a recording returns the recorded result rather than calling the function again.
+    fn call_native<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        native: ScopedPtr<'guard, NativeFunction>,
+        args: &[TaggedScopedPtr<'guard>],
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        if !native.is_host() {
+            return native.call(mem, Some(self), args);
+        }
+
+        let source = format!("host {}", native.name(mem));
+        let result = self.nondeterministic_value(mem, &source, || {
+            native.call(mem, Some(self), args).map(Some)
+        })?;
+        Ok(result.unwrap_or_else(|| mem.nil()))
+    }
+
+    /// Replace the stream that console output ports write to
+    pub fn set_output(&self, output: Box<dyn Write>) {
+        *self.output.borrow_mut() = output;
+    }
+
+    /// Replace the stream that console input ports read from
+    pub fn set_input(&self, input: Box<dyn BufRead>) {
+        *self.input.borrow_mut() = input;
+    }
+
+    /// Write text to the console output stream
+    pub fn write_output(&self, text: &str) -> Result<(), RuntimeError> {
+        let mut output = self.output.borrow_mut();
+        output
+            .write_all(text.as_bytes())
+            .and_then(|_| output.flush())
+            .map_err(|e| err_eval("Output failed").with_cause(RuntimeError::from(e)))
+    }
+
+    /// Read a line from the console input stream, without the line ending, or None at the end of
+    /// input
+    pub fn read_input_line(&self) -> Result<Option<String>, RuntimeError> {
+        // a line is recorded after a '>' so that it can be told apart from the end of input
+        let recorded = self.nondeterministic_input("read-line", || {
+            let mut line = String::new();
+            match self.input.borrow_mut().read_line(&mut line) {
+                Ok(0) => Ok(String::new()),
+                Ok(_) => Ok(format!(">{}", line.trim_end_matches(&['\n', '\r'][..]))),
+                Err(e) => Err(err_eval("Input failed").with_cause(RuntimeError::from(e))),
+            }
+        })?;
+        Ok(recorded.strip_prefix('>').map(String::from))
+    }
+
+    /// Return the value of the `current-output-port` Parameter
+    pub fn current_output_port<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Result<ScopedPtr<'guard, Port>, RuntimeError> {
+        match *self.output_port.get(guard).value(guard) {
+            Value::Port(port) => Ok(port),
+            value => Err(err_eval(&format!(
+                "current-output-port is not a port: {}",
+                value
+            ))),
+        }
+    }
+
+    /// Return the value of the `current-input-port` Parameter
+    pub fn current_input_port<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Result<ScopedPtr<'guard, Port>, RuntimeError> {
+        match *self.input_port.get(guard).value(guard) {
+            Value::Port(port) => Ok(port),
+            value => Err(err_eval(&format!(
+                "current-input-port is not a port: {}",
+                value
+            ))),
+        }
+    }
+
+    /// Rebind a Parameter to a new value, saving the current value to be restored by
+    /// `unbind_parameters()`
+    fn bind_parameter<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        param: TaggedScopedPtr<'guard>,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        match *param {
+            Value::Parameter(p) => {
+                let bindings = self.parameter_bindings.get(mem);
+                StackAnyContainer::push(&*bindings, mem, param)?;
+                StackAnyContainer::push(&*bindings, mem, p.value(mem))?;
+                p.set(value);
+                Ok(())
+            }
+            _ => Err(err_eval(&format!("Cannot parameterize {}", param))),
+        }
+    }
+
+    /// Restore the saved values of the most recent `count` Parameter bindings
+    fn unbind_parameters<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        count: ArraySize,
+    ) -> Result<(), RuntimeError> {
+        let bindings = self.parameter_bindings.get(mem);
+
+        for _ in 0..count {
+            let value = StackAnyContainer::pop(&*bindings, mem)?;
+            if let Value::Parameter(p) = *StackAnyContainer::pop(&*bindings, mem)? {
+                p.set(value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the caps on the resources used by each top level evaluation
+    pub fn vm_limits(&self) -> VmLimits {
+        self.vm_limits.get()
+    }
+
+    /// Replace the caps on the resources used by each top level evaluation. The default is no
+    /// caps.
+    pub fn set_vm_limits(&self, limits: VmLimits) {
+        self.vm_limits.set(limits);
+    }
+
+    /// Set the call depth at which a function calling itself in non-tail position raises a
+    /// warning
+    pub fn set_recursion_warning_depth(&self, depth: ArraySize) {
+        self.recursion_warning_depth.set(depth);
+    }
+
+    /// Set the maximum number of registers on the stack. A call that would need a register window
+    /// reaching past it raises a `StackOverflow` error, which `try` can catch.
+    pub fn set_max_stack_size(&self, size: ArraySize) {
+        self.max_stack_size.set(size);
+    }
+
+    /// Return a `StackOverflow` error if a register window at the given stack base would reach
+    /// past the maximum stack size
+    fn check_stack_window(&self, base: ArraySize) -> Result<(), RuntimeError> {
+        let max = self.max_stack_size.get();
+        if base.saturating_add(WINDOW_SIZE) > max {
+            Err(RuntimeError::new(ErrorKind::StackOverflow(max)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Grow the given stack, filling it with nil, until a whole register window fits at the
+    /// given stack base
+    fn reserve_stack_window<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        stack: &List,
+        base: ArraySize,
+    ) -> Result<(), RuntimeError> {
+        self.check_stack_window(base)?;
+        stack.fill(mem, base + WINDOW_SIZE, mem.nil())
+    }
+
+    /// Define a macro, replacing any existing macro of the same name
+    pub fn define_macro<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        name: TaggedScopedPtr<'guard>,
+        function: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        self.macros.get(mem).assoc(mem, name, function)
+    }
+
+    /// Return the macro Function of the given name, if there is one
+    pub fn lookup_macro<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        name: TaggedScopedPtr<'guard>,
+    ) -> Option<TaggedScopedPtr<'guard>> {
+        self.macros.get(guard).lookup(guard, name).ok()
+    }
+
+    /// Bind a global variable, replacing any existing binding
+    pub fn define_global<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        name: TaggedScopedPtr<'guard>,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        self.globals.get(mem).assoc(mem, name, value)
+    }
+
+    /// Bind a global variable as a definition evaluated by this thread does, recording the change
+    /// in the global log if it is on, without a source position. This is how globals saved from
+    /// an earlier session are restored.
+    pub fn restore_global<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        name: TaggedScopedPtr<'guard>,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        let globals = self.globals.get(mem);
+        if let Some(log) = self.global_log.borrow_mut().as_mut() {
+            let old = globals.lookup(mem, name).ok();
+            log.record(mem, name, old, value, None)?;
+        }
+
+        globals.assoc(mem, name, value)
+    }
+
+    /// Set a property of a symbol, replacing any existing value of the same key
+    pub fn put_property<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        symbol: TaggedScopedPtr<'guard>,
+        key: TaggedScopedPtr<'guard>,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        let properties = self.properties.get(mem);
+
+        let list = match properties.lookup(mem, symbol) {
+            Ok(list) => list,
+            Err(_) => {
+                let list = Dict::alloc(mem)?.as_tagged(mem);
+                properties.assoc(mem, symbol, list)?;
+                list
+            }
+        };
+
+        match *list {
+            Value::Dict(dict) => dict.assoc(mem, key, value),
+            _ => Err(err_eval("Symbol property list is not a dict")),
+        }
+    }
+
+    /// Return the value of the global variable of the given name. That is its binding if it has one,
+    /// or else the value supplied by the unresolved symbol handler. Failing that, a dot-path such
+    /// as `config.server.port` is the field `port` of the field `server` of the global `config`.
+    /// None if there is no value.
+    pub fn resolve_global<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        name: TaggedScopedPtr<'guard>,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        if let Ok(value) = self.globals.get(mem).lookup(mem, name) {
+            return Ok(Some(value));
+        }
+
+        let name = match *name {
+            Value::Symbol(s) => s.as_str(mem),
+            _ => return Err(err_eval("Cannot lookup global for non-symbol type")),
+        };
+
+        if let Some(handler) = self.unresolved_symbol_handler.get() {
+            let source = format!("unresolved {}", name);
+            if let Some(value) = self.nondeterministic_value(mem, &source, || handler(mem, name))? {
+                return Ok(Some(value));
+            }
+        }
+
+        match name.split_once('.') {
+            Some((head, path)) if !head.is_empty() => {
+                let mut value = match self.resolve_global(mem, mem.lookup_sym(head))? {
+                    Some(value) => value,
+                    None => return Ok(None),
+                };
+                for field in path.split('.') {
+                    value = get_field(mem, value, mem.lookup_sym(field))?;
+                }
+                Ok(Some(value))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Return a property of a symbol, if it is set
+    pub fn get_property<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        symbol: TaggedScopedPtr<'guard>,
+        key: TaggedScopedPtr<'guard>,
+    ) -> Option<TaggedScopedPtr<'guard>> {
+        match *self.properties.get(guard).lookup(guard, symbol).ok()? {
+            Value::Dict(dict) => dict.lookup(guard, key).ok(),
+            _ => None,
+        }
+    }
+
+    /// Bind a Rust function to a global name, replacing any existing binding, so that scripts can
+    /// call it like any other function. This is how an embedding application exposes its own
+    /// functions to scripts. A recording of an evaluation keeps the results of its calls.
+    pub fn define_native<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        name: &str,
+        arity: u8,
+        code: NativeFn,
+    ) -> Result<(), RuntimeError> {
+        let function = NativeFunction::alloc(mem, name, arity, code)?;
+        function.mark_host();
+        self.globals
+            .get(mem)
+            .assoc(mem, mem.lookup_sym(name), function.as_tagged(mem))
+    }
+
+    /// Bind a Rust function that needs the calling Thread to a global name, see `define_native()`
+    pub fn define_native_with_thread<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        name: &str,
+        arity: u8,
+        code: ThreadNativeFn,
+    ) -> Result<(), RuntimeError> {
+        let function = NativeFunction::alloc_with_thread(mem, name, arity, code)?;
+        function.mark_host();
+        self.globals
+            .get(mem)
+            .assoc(mem, mem.lookup_sym(name), function.as_tagged(mem))
+    }
+
+    /// Return the value of the global variable of the given name, if it is bound
+    pub fn lookup_global<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        name: TaggedScopedPtr<'guard>,
+    ) -> Option<TaggedScopedPtr<'guard>> {
+        self.globals.get(guard).lookup(guard, name).ok()
+    }
+
+    /// Replace the special forms that code compiled for this thread is compiled with. The default
+    /// is `SpecialFormTable::standard()`.
+    #[cfg(feature = "compiler")]
+    pub fn set_special_forms(&self, forms: SpecialFormTable) {
+        *self.special_forms.borrow_mut() = forms;
+    }
+
+    /// Return the special forms that code compiled for this thread is compiled with
+    #[cfg(feature = "compiler")]
+    pub fn special_forms(&self) -> Ref<SpecialFormTable> {
+        self.special_forms.borrow()
+    }
+
+    /// Set what integer arithmetic does with results too large to store inline. The default is
+    /// `OverflowMode::Promote`.
+    pub fn set_overflow_mode(&self, mode: OverflowMode) {
+        self.overflow_mode.set(mode);
+    }
+
+    /// Set the function that supplies the values of global variables that are not bound, see
+    /// `UnresolvedSymbolHandler`. The default is None, for which reading an unbound global is an
+    /// error.
+    pub fn set_unresolved_symbol_handler(&self, handler: Option<UnresolvedSymbolHandler>) {
+        self.unresolved_symbol_handler.set(handler);
+    }
+
+    /// Apply an arithmetic operation under this thread's overflow mode
+    fn arithmetic<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        op: ArithmeticOp,
+        left: TaggedScopedPtr<'guard>,
+        right: TaggedScopedPtr<'guard>,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        number::arithmetic(mem, self.overflow_mode.get(), op, left, right)
+    }
+
+    /// Return the number of instructions executed by this thread so far
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count.get()
+    }
+
+    /// Remove and return the warnings raised since they were last taken
+    pub fn take_warnings(&self) -> Vec<Diagnostic> {
+        self.warnings.replace(Vec::new())
+    }
+
+    /// Raise a warning, to be taken by the embedder
+    pub fn warn(&self, warning: Diagnostic) {
+        self.warnings.borrow_mut().push(warning);
+    }
+
+    /// Record the source position of a definition of a global, returning the position of the
+    /// previous definition compiled for this thread if there was one
+    pub fn record_definition(
+        &self,
+        name: &str,
+        pos: Option<SourcePos>,
+    ) -> Option<Option<SourcePos>> {
+        self.definitions
+            .borrow_mut()
+            .insert(String::from(name), pos)
+    }
+
+    /// Warn if a non-tail call from a function to itself reaches the recursion warning depth.
+    /// Deep recursion in non-tail position grows the call frame stack, where a tail call would
+    /// not.
+    fn check_self_recursion<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        function: ScopedPtr<'guard, Function>,
+    ) -> Result<(), RuntimeError> {
+        let frames = self.frames.get(guard);
+        if frames.length() != self.recursion_warning_depth.get() {
+            return Ok(());
+        }
+
+        let caller = frames.top(guard)?.function.get(guard);
+        if std::ptr::eq(&*caller, &*function) {
+            self.warn(Diagnostic::warning(&format!(
+                "{} has recursed {} calls deep in non-tail position, consider making the \
+                 recursive call a tail call",
+                function,
+                frames.length()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Begin an instruction budget of `limit` instructions. The enclosing budget, if any, still
+    /// applies.
+    fn begin_limit<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        limit: TaggedScopedPtr<'guard>,
+        dest: Register,
+        resume_ip: ArraySize,
+    ) -> Result<(), RuntimeError> {
+        let count = match limit.as_int() {
+            Some(count) if count >= 0 => count as u64,
+            _ => {
+                return Err(err_eval(&format!(
+                    "Expected a non-negative instruction limit, got {}",
+                    limit
+                )))
+            }
+        };
+
+        let mut limits = self.limits.borrow_mut();
+        let mut deadline = self.instruction_count.get().saturating_add(count);
+        if let Some(outer) = limits.last() {
+            deadline = deadline.min(outer.deadline);
+        }
+
+        limits.push(InstructionLimit {
+            deadline,
+            frame_depth: self.frames.get(mem).length(),
+            stack_base: self.stack_base.get(),
+            resume_ip,
+            dest,
+            parameter_depth: self.parameter_bindings.get(mem).length(),
+            continuation_depth: self.continuations.get(mem).length(),
+            handler_depth: self.handlers.borrow().len(),
+        });
+
+        Ok(())
+    }
+
+    /// Count an instruction against the innermost budget, returning true if it has run out
+    fn limit_exceeded(&self) -> bool {
+        let count = self.instruction_count.get() + 1;
+        self.instruction_count.set(count);
+
+        match self.limits.borrow().last() {
+            Some(limit) => count > limit.deadline,
+            None => false,
+        }
+    }
+
+    /// Return a `VmLimitExceeded` error if the top level evaluation in progress has crossed one of
+    /// the VM limits
+    fn check_vm_limits<'guard>(&self, mem: &'guard MutatorView) -> Result<(), RuntimeError> {
+        let limits = self.vm_limits.get();
+        let (start_count, start_bytes) = self.eval_start.get();
+
+        let exceeded = |reason: String| Err(RuntimeError::new(ErrorKind::VmLimitExceeded(reason)));
+
+        if let Some(max) = limits.max_instructions {
+            if self.instruction_count.get() - start_count > max {
+                return exceeded(format!("more than {} instructions executed", max));
+            }
+        }
+
+        if let Some(max) = limits.max_call_depth {
+            if self.frames.get(mem).length() > max {
+                return exceeded(format!("call depth of more than {}", max));
+            }
+        }
+
+        if let Some(max) = limits.max_heap {
+            if mem.bytes_allocated() - start_bytes > max {
+                return exceeded(format!("more than {} bytes allocated", max));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Abandon the body of the innermost `with-limit` expression, unwinding call frames and
+    /// Parameter bindings made since it began, and resume after it with the result
+    /// `limit-exceeded`
+    fn unwind_limit<'guard>(&self, mem: &'guard MutatorView) -> Result<(), RuntimeError> {
+        let limit = match self.limits.borrow_mut().pop() {
+            Some(limit) => limit,
+            None => return Err(err_eval("No instruction limit to unwind")),
+        };
+
+        let frames = self.frames.get(mem);
+        while frames.length() > limit.frame_depth {
+            frames.pop(mem)?;
+        }
+
+        let bound = (self.parameter_bindings.get(mem).length() - limit.parameter_depth) / 2;
+        self.unbind_parameters(mem, bound)?;
+        self.end_continuations(mem, limit.continuation_depth)?;
+        self.handlers.borrow_mut().truncate(limit.handler_depth);
+
+        // closures made in the abandoned body keep the values of the variables they captured
+        self.close_upvalues_from(mem, limit.stack_base + limit.dest as ArraySize)?;
+
+        let frame = frames.top(mem)?;
+        self.stack_base.set(limit.stack_base);
+        self.instr
+            .get(mem)
+            .switch_frame(frame.function.get(mem).code(mem), limit.resume_ip);
+
+        IndexedAnyContainer::set(
+            &*self.stack.get(mem),
+            mem,
+            limit.stack_base + limit.dest as ArraySize,
+            mem.lookup_sym("limit-exceeded"),
+        )
+    }
+
+    /// Raise a value as an exception, returning the error that unwinds evaluation to the innermost
+    /// `try` expression
+    pub fn throw(&self, value: TaggedScopedPtr) -> RuntimeError {
+        self.thrown.set(value);
+        RuntimeError::new(ErrorKind::Thrown(format!("{}", value)))
+    }
+
+    /// Abandon the body of the innermost `try` expression, unwinding call frames, Parameter
+    /// bindings, instruction limits and continuations made since it began, and resume at its
+    /// catch clause with the thrown value, or the error message for any other error
+    fn unwind_handler<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        error: &RuntimeError,
+    ) -> Result<(), RuntimeError> {
+        let handler = self.handlers.borrow_mut().pop();
+        let (handler, resume_ip, dest) = match handler {
+            Some(handler) => match handler.action {
+                HandlerAction::Catch { resume_ip, dest } => (handler, resume_ip, dest),
+                HandlerAction::Cleanup { .. } => {
+                    return Err(err_eval("Expected a try handler, found an unwind-protect"))
+                }
+            },
+            None => return Err(err_eval("No try handler to unwind")),
+        };
+
+        let frames = self.frames.get(mem);
+        while frames.length() > handler.frame_depth {
+            frames.pop(mem)?;
+        }
+
+        let bound = (self.parameter_bindings.get(mem).length() - handler.parameter_depth) / 2;
+        self.unbind_parameters(mem, bound)?;
+        self.limits.borrow_mut().truncate(handler.limit_depth);
+        self.end_continuations(mem, handler.continuation_depth)?;
+
+        // closures made in the abandoned body keep the values of the variables they captured
+        self.close_upvalues_from(mem, handler.stack_base + dest as ArraySize)?;
+
+        let frame = frames.top(mem)?;
+        self.stack_base.set(handler.stack_base);
+        self.instr
+            .get(mem)
+            .switch_frame(frame.function.get(mem).code(mem), resume_ip);
+
+        let value = match error.error_kind() {
+            ErrorKind::Thrown(_) => self.thrown.get(mem),
+            _ => mem.text(&format!("{}", error))?,
+        };
+        self.thrown.set_to_nil();
+
+        IndexedAnyContainer::set(
+            &*self.stack.get(mem),
+            mem,
+            handler.stack_base + dest as ArraySize,
+            value,
+        )
+    }
+
+    /// Return the number of handlers that are left once an error has unwound as far as it goes in
+    /// the evaluation that began at `entry`. Continuations and errors that can be caught run the
+    /// cleanup functions of the handlers above that. Any other error, such as a limit being
+    /// exceeded, runs no more code before evaluation ends.
+    fn unwind_depth<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        error: &RuntimeError,
+        entry: EvalEntry,
+    ) -> Result<usize, RuntimeError> {
+        let handlers = self.handlers.borrow();
+
+        match *error.error_kind() {
+            ErrorKind::ContinuationInvoked(depth) if depth >= entry.continuation_depth => {
+                match *IndexedAnyContainer::get(&*self.continuations.get(mem), mem, depth)? {
+                    Value::Continuation(k) => Ok(k.resume_point().handler_depth),
+                    _ => Err(err_eval("No live continuation to resume")),
+                }
+            }
+
+            ErrorKind::ContinuationInvoked(_) => Ok(entry.handler_depth),
+
+            _ if error.is_catchable() => Ok(handlers
+                .iter()
+                .enumerate()
+                .skip(entry.handler_depth)
+                .rev()
+                .find(|(_, handler)| matches!(handler.action, HandlerAction::Catch { .. }))
+                .map_or(entry.handler_depth, |(index, _)| index + 1)),
+
+            _ => Ok(handlers.len()),
+        }
+    }
+
+    /// Remove the handlers above the given depth as far as the innermost unwind-protect, returning
+    /// its cleanup function. The Parameter bindings, instruction limits and continuations made
+    /// since the unwind-protect began are unwound first, so that the cleanup function sees the
+    /// dynamic state its expression began in.
+    fn pop_cleanup<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        depth: usize,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        loop {
+            if self.handlers.borrow().len() <= depth {
+                return Ok(None);
+            }
+
+            let handler = match self.handlers.borrow_mut().pop() {
+                Some(handler) => handler,
+                None => return Ok(None),
+            };
+
+            if let HandlerAction::Cleanup { thunk } = handler.action {
+                let bound =
+                    (self.parameter_bindings.get(mem).length() - handler.parameter_depth) / 2;
+                self.unbind_parameters(mem, bound)?;
+                self.limits.borrow_mut().truncate(handler.limit_depth);
+                self.end_continuations(mem, handler.continuation_depth)?;
+
+                return Ok(Some(IndexedAnyContainer::get(
+                    &*self.stack.get(mem),
+                    mem,
+                    thunk,
+                )?));
+            }
+        }
+    }
+
+    /// Capture a continuation that resumes at `resume_ip` in the current call frame with the value
+    /// it is invoked with in the `dest` register
+    fn capture_continuation<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        dest: Register,
+        resume_ip: ArraySize,
+    ) -> Result<ScopedPtr<'guard, Continuation>, RuntimeError> {
+        let continuations = self.continuations.get(mem);
+
+        let continuation = Continuation::alloc(
+            mem,
+            ResumePoint {
+                frame_depth: self.frames.get(mem).length(),
+                stack_base: self.stack_base.get(),
+                resume_ip,
+                dest,
+                parameter_depth: self.parameter_bindings.get(mem).length(),
+                limit_depth: self.limits.borrow().len(),
+                handler_depth: self.handlers.borrow().len(),
+                continuation_depth: continuations.length(),
+            },
+        )?;
+
+        StackAnyContainer::push(&*continuations, mem, continuation.as_tagged(mem))?;
+        Ok(continuation)
+    }
+
+    /// End the extent of the live continuations captured since there were `depth` of them
+    fn end_continuations<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        depth: ArraySize,
+    ) -> Result<(), RuntimeError> {
+        let continuations = self.continuations.get(mem);
+
+        while continuations.length() > depth {
+            if let Value::Continuation(k) = *StackAnyContainer::pop(&*continuations, mem)? {
+                k.end();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Invoke a continuation with a value, returning the error that unwinds evaluation to it
+    fn invoke_continuation<'guard>(
+        &self,
+        continuation: ScopedPtr<'guard, Continuation>,
+        value: TaggedScopedPtr<'guard>,
+    ) -> RuntimeError {
+        if !continuation.is_live() {
+            return err_eval(
+                "Continuation invoked after its call/cc expression completed, a continuation \
+                 can only escape from within the extent of its call/cc",
+            );
+        }
+
+        continuation.set_value(value);
+        RuntimeError::new(ErrorKind::ContinuationInvoked(
+            continuation.resume_point().continuation_depth,
+        ))
+    }
+
+    /// Abandon everything evaluated since the live continuation at `depth` was captured,
+    /// unwinding call frames, Parameter bindings, instruction limits and continuations made since,
+    /// and resume after its call/cc expression with the value it was invoked with
+    fn resume_continuation<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        depth: ArraySize,
+    ) -> Result<(), RuntimeError> {
+        let continuation =
+            match *IndexedAnyContainer::get(&*self.continuations.get(mem), mem, depth)? {
+                Value::Continuation(k) => k,
+                _ => return Err(err_eval("No live continuation to resume")),
+            };
+        let resume = continuation.resume_point();
+
+        let frames = self.frames.get(mem);
+        while frames.length() > resume.frame_depth {
+            frames.pop(mem)?;
+        }
+
+        let bound = (self.parameter_bindings.get(mem).length() - resume.parameter_depth) / 2;
+        self.unbind_parameters(mem, bound)?;
+        self.limits.borrow_mut().truncate(resume.limit_depth);
+        self.handlers.borrow_mut().truncate(resume.handler_depth);
+
+        // the continuation itself stays live until its call/cc expression completes, which is
+        // the instruction it resumes at
+        self.end_continuations(mem, depth + 1)?;
+
+        // closures made in the abandoned frames keep the values of the variables they captured
+        self.close_upvalues_from(mem, resume.stack_base + resume.dest as ArraySize)?;
+
+        let frame = frames.top(mem)?;
+        self.stack_base.set(resume.stack_base);
+        self.instr
+            .get(mem)
+            .switch_frame(frame.function.get(mem).code(mem), resume.resume_ip);
+
+        IndexedAnyContainer::set(
+            &*self.stack.get(mem),
+            mem,
+            resume.stack_base + resume.dest as ArraySize,
+            continuation.value(mem),
+        )
+    }
+
+    /// Check every object reachable from this thread, returning the number of objects checked
+    pub fn verify_heap<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Result<usize, RuntimeError> {
+        let mut checker = HeapChecker::new();
+        checker.object(guard, self)?;
+        Ok(checker.objects())
+    }
+
     /// Retrieve an Upvalue for the given absolute stack offset.
     fn upvalue_lookup<'guard>(
         &self,
@@ -265,33 +1733,82 @@ impl Thread {
         }
     }
 
+    /// Close every open upvalue at or above the given absolute stack offset and unanchor it from
+    /// the Thread, as `CloseUpvalues` does, when the registers there are abandoned by unwinding
+    fn close_upvalues_from<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        location: ArraySize,
+    ) -> Result<(), RuntimeError> {
+        let stack = self.stack.get(mem);
+        let upvalues = self.upvalues.get(mem);
+
+        for (location_ptr, upvalue) in upvalues.items(mem) {
+            let above = match location_ptr.as_int() {
+                Some(open_at) => open_at >= location as isize,
+                None => false,
+            };
+            if let (true, Value::Upvalue(upvalue)) = (above, *upvalue) {
+                upvalue.close(mem, stack)?;
+                upvalues.dissoc(mem, location_ptr)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Execute the next instruction in the current instruction stream
     fn eval_next_instr<'guard>(
         &self,
         mem: &'guard MutatorView,
+        dispatch: Dispatch<'guard>,
     ) -> Result<EvalStatus<'guard>, RuntimeError> {
-        // TODO not all these locals are required in every opcode - optimize and get them only
-        // where needed
-        let frames = self.frames.get(mem);
-        let stack = self.stack.get(mem);
-        let globals = self.globals.get(mem);
-        let instr = self.instr.get(mem);
-
-        // Establish a 256-register window into the stack from the stack base
+        // Stress the heap by checking every root before every instruction, to catch temporary
+        // values that are referenced but not reachable
+        #[cfg(feature = "gc-stress")]
+        self.verify_heap(mem)?;
+
+        let Dispatch {
+            frames,
+            stack,
+            globals,
+            instr,
+        } = dispatch;
+
+        // Establish a register window into the stack from the stack base, growing the stack first
+        // if this is the first instruction of a call frame reaching past its end
+        self.reserve_stack_window(mem, &stack, self.stack_base.get())?;
         stack.access_slice(mem, |full_stack| {
             let stack_base = self.stack_base.get() as usize;
-            let window = &mut full_stack[stack_base..stack_base + 256];
+            let window = &mut full_stack[stack_base..stack_base + WINDOW_SIZE as usize];
 
             // Fetch the next instruction and identify it
+            let ip = instr.get_next_ip();
             let opcode = instr.get_next_opcode(mem)?;
 
+            // Record or check the instruction against a recording
+            if !self.replay.borrow().is_off() {
+                let function = frames.top(mem)?.function.get(mem);
+                self.replay
+                    .borrow_mut()
+                    .instruction(function.name(mem), ip, opcode)?;
+            }
+
+            if let Some(profiler) = self.profiler.borrow_mut().as_mut() {
+                profiler.count(opcode);
+            }
+
+            if let Some(tracer) = self.tracer.borrow_mut().as_mut() {
+                let function = frames.top(mem)?.function.get(mem);
+                tracer.instruction(mem, function.name(mem), ip, opcode, window);
+            }
+
             match opcode {
                 // Do nothing.
                 Opcode::NoOp => return Ok(EvalStatus::Pending),
 
                 // Set the return register to the given register's value and pop the top call
                 // frame, updating the instruction stream to the previous call frame's saved state.
-                // If the call frame stack is empty, the program completed.
+                // If the call frame stack is back to where the evaluation began, it completed.
                 Opcode::Return { reg } => {
                     // write the return value to register 0
                     let result = window[reg as usize].get_ptr();
@@ -300,8 +1817,9 @@ impl Thread {
                     // remove this function's stack frame
                     frames.pop(mem)?;
 
-                    // if we just returned from the last stack frame, program evaluation is complete
-                    if frames.length() == 0 {
+                    // if we just returned from the first stack frame of this evaluation, it is
+                    // complete
+                    if frames.length() == self.entry.get().frame_depth {
                         return Ok(EvalStatus::Return(window[RETURN_REG].get(mem)));
                     } else {
                         // otherwise restore the previous stack frame settings
@@ -311,6 +1829,25 @@ impl Thread {
                     }
                 }
 
+                // Stop with an AssertionFailed error. The literal is a list of the expression that
+                // was not true and, if known, the line and column of the expression.
+                Opcode::AssertFail { literal_id } => {
+                    let failure = TaggedScopedPtr::new(mem, instr.get_literal(mem, literal_id)?);
+                    let failure = vec_from_pairs(mem, failure)?;
+
+                    let kind = ErrorKind::AssertionFailed(format!("{}", failure[0]));
+                    return match (failure.get(1), failure.get(2)) {
+                        (Some(line), Some(column)) => Err(RuntimeError::with_pos(
+                            kind,
+                            spos(
+                                line.as_int().unwrap_or(0) as u32,
+                                column.as_int().unwrap_or(0) as u32,
+                            ),
+                        )),
+                        _ => Err(RuntimeError::new(kind)),
+                    };
+                }
+
                 // Load a literal into a register from the function literals array
                 Opcode::LoadLiteral { dest, literal_id } => {
                     let literal_ptr = instr.get_literal(mem, literal_id)?;
@@ -318,14 +1855,11 @@ impl Thread {
                 }
 
                 // Evaluate whether the `test` register contains `nil` - if so, set the `dest`
-                // register to the symbol "true", otherwise set it to `nil`
+                // register to the symbol "true", otherwise set it to `nil`. As `nil` is the only
+                // false value this is also logical negation, which `not` compiles to.
                 Opcode::IsNil { dest, test } => {
                     let test_val = window[test as usize].get(mem);
-
-                    match *test_val {
-                        Value::Nil => window[dest as usize].set(mem.lookup_sym("true")),
-                        _ => window[dest as usize].set_to_nil(),
-                    }
+                    window[dest as usize].set(mem.boolean(test_val.is_nil()));
                 }
 
                 // Evaluate whether the `test` register contains an atomic value - i.e. a
@@ -337,7 +1871,7 @@ impl Thread {
                         Value::Pair(_) => window[dest as usize].set_to_nil(),
                         Value::Nil => window[dest as usize].set_to_nil(),
                         // TODO what other types?
-                        _ => window[dest as usize].set(mem.lookup_sym("true")),
+                        _ => window[dest as usize].set(mem.boolean(true)),
                     }
                 }
 
@@ -375,45 +1909,89 @@ impl Thread {
                     window[dest as usize].set(mem.alloc_tagged(new_pair)?);
                 }
 
-                // Identity comparison - if `test1` and `test2` are identical pointers, set `dest`
-                // to the symbol "true"
+                // Identity comparison - if `test1` and `test2` are identical, set `dest` to the
+                // symbol "true"
                 Opcode::IsIdentical { dest, test1, test2 } => {
-                    // compare raw pointers - identity comparison
-                    let test1_val = window[test1 as usize].get_ptr();
-                    let test2_val = window[test2 as usize].get_ptr();
+                    let identical =
+                        is_identical(mem, &window[test1 as usize], &window[test2 as usize]);
+                    window[dest as usize].set(mem.boolean(identical));
+                }
 
-                    if test1_val == test2_val {
-                        window[dest as usize].set(mem.lookup_sym("true"));
-                    } else {
-                        window[dest as usize].set(mem.nil());
-                    }
+                // Numeric comparisons - set `dest` to the symbol "true" if the comparison holds,
+                // otherwise to `nil`. The operands are coerced as for arithmetic.
+                Opcode::IsLessThan { dest, left, right }
+                | Opcode::IsGreaterThan { dest, left, right }
+                | Opcode::IsLessOrEqual { dest, left, right }
+                | Opcode::IsGreaterOrEqual { dest, left, right }
+                | Opcode::IsNumericEqual { dest, left, right } => {
+                    let left = window[left as usize].get(mem);
+                    let right = window[right as usize].get(mem);
+                    let holds = comparison_holds(mem, opcode, left, right)?;
+                    window[dest as usize].set(mem.boolean(holds));
                 }
 
                 // Unconditional jump - advance the instruction pointer by `offset`
                 Opcode::Jump { offset } => {
-                    instr.jump(offset);
+                    instr.jump(mem, offset)?;
                 }
 
-                // Jump if the `test` register contains the symbol "true"
+                // Jump if the `test` register is true, which is anything but `nil`
                 Opcode::JumpIfTrue { test, offset } => {
-                    let test_val = window[test as usize].get(mem);
-
-                    let true_sym = mem.lookup_sym("true"); // TODO preload keyword syms
-
-                    if test_val == true_sym {
-                        instr.jump(offset)
+                    if !window[test as usize].is_nil() {
+                        instr.jump(mem, offset)?;
                     }
                 }
 
-                // Jump if the `test` register does not contain the symbol "true"
+                // Jump if the `test` register is false, which is to say `nil`
                 Opcode::JumpIfNotTrue { test, offset } => {
-                    let test_val = window[test as usize].get(mem);
+                    if window[test as usize].is_nil() {
+                        instr.jump(mem, offset)?;
+                    }
+                }
 
-                    let true_sym = mem.lookup_sym("true");
+                // Jump by the short `offset` if `test1` and `test2` are not identical, otherwise
+                // skip the Jump that follows
+                Opcode::JumpIfNotIdentical {
+                    test1,
+                    test2,
+                    offset,
+                } => {
+                    let identical =
+                        is_identical(mem, &window[test1 as usize], &window[test2 as usize]);
+                    instr.short_jump(mem, identical, offset)?;
+                }
 
-                    if test_val != true_sym {
-                        instr.jump(offset)
-                    }
+                // Jump by the short `offset` if the numeric comparison does not hold, otherwise
+                // skip the Jump that follows
+                Opcode::JumpIfNotLessThan {
+                    left,
+                    right,
+                    offset,
+                }
+                | Opcode::JumpIfNotGreaterThan {
+                    left,
+                    right,
+                    offset,
+                }
+                | Opcode::JumpIfNotLessOrEqual {
+                    left,
+                    right,
+                    offset,
+                }
+                | Opcode::JumpIfNotGreaterOrEqual {
+                    left,
+                    right,
+                    offset,
+                }
+                | Opcode::JumpIfNotNumericEqual {
+                    left,
+                    right,
+                    offset,
+                } => {
+                    let left = window[left as usize].get(mem);
+                    let right = window[right as usize].get(mem);
+                    let holds = comparison_holds(mem, opcode, left, right)?;
+                    instr.short_jump(mem, holds, offset)?;
                 }
 
                 // Set the register `dest` to `nil`
@@ -428,15 +2006,22 @@ impl Thread {
                 }
 
                 // Lookup a global binding and put it in the register `dest`
-                Opcode::LoadGlobal { dest, name } => {
+                Opcode::LoadGlobal { dest, name, cache } => {
                     let name_val = window[name as usize].get(mem);
 
-                    if let Value::Symbol(_) = *name_val {
-                        let lookup_result = globals.lookup(mem, name_val);
+                    // the inline cache of the instruction may know where the global is bound
+                    let code = instr.get_code(mem);
+                    if let Some(value) = code.cached_global(mem, cache, &globals, name_val) {
+                        window[dest as usize].set(value);
+                    } else if let Value::Symbol(_) = *name_val {
+                        // cache the binding only if it is in the globals, not if it is resolved
+                        if let Some(index) = globals.locate(mem, name_val)? {
+                            code.cache_global(mem, cache, index)?;
+                        }
 
-                        match lookup_result {
-                            Ok(binding) => window[dest as usize].set(binding),
-                            Err(_) => {
+                        match self.resolve_global(mem, name_val)? {
+                            Some(value) => window[dest as usize].set(value),
+                            None => {
                                 return Err(err_eval(&format!(
                                     "Symbol {} is not bound to a value",
                                     name_val
@@ -448,11 +2033,26 @@ impl Thread {
                     }
                 }
 
+                // Read the field named by the symbol in the `key` register from the dict in the
+                // `object` register
+                Opcode::GetField { dest, object, key } => {
+                    let object = window[object as usize].get(mem);
+                    let key = window[key as usize].get(mem);
+                    window[dest as usize].set(get_field(mem, object, key)?);
+                }
+
                 // Bind a symbol to the `src` register in the globals dict
                 Opcode::StoreGlobal { src, name } => {
                     let name_val = window[name as usize].get(mem);
                     if let Value::Symbol(_) = *name_val {
                         let src_val = window[src as usize].get(mem);
+
+                        if let Some(log) = self.global_log.borrow_mut().as_mut() {
+                            let old = globals.lookup(mem, name_val).ok();
+                            let code = frames.top(mem)?.function.get(mem).code(mem);
+                            log.record(mem, name_val, old, src_val, code.position(mem, ip))?;
+                        }
+
                         globals.assoc(mem, name_val, src_val)?;
                     } else {
                         return Err(err_eval("Cannot bind global to non-symbol type"));
@@ -469,13 +2069,44 @@ impl Thread {
                 //
                 // If the arg_count is equal to the Function or Partial arity, enter the Function
                 // object code.
-                Opcode::Call {
-                    function,
-                    dest,
-                    arg_count,
-                } => {
+                //
+                // A TailCall enters the Function object code in place of the current function,
+                // reusing its call frame and register window, so that the callee returns directly
+                // to the caller of the current function.
+                //
+                // An Apply is a non-tail call whose last argument register holds a list of further
+                // arguments, which are spread into the argument registers before the call. The
+                // function register may be overwritten by the spread arguments.
+                //
+                // A CallWithContinuation is a non-tail call with a single argument, a Continuation
+                // that resumes at the following instruction with its value in the `dest` register.
+                Opcode::Call { function, dest, .. }
+                | Opcode::TailCall { function, dest, .. }
+                | Opcode::Apply { function, dest, .. }
+                | Opcode::CallWithContinuation { function, dest } => {
+                    let tail = match opcode {
+                        Opcode::TailCall { .. } => true,
+                        _ => false,
+                    };
+
                     let binding = window[function as usize].get(mem);
 
+                    let arg_count = match opcode {
+                        Opcode::Apply { arg_count, .. } => {
+                            spread_apply_args(mem, window, dest, arg_count)?
+                        }
+                        Opcode::CallWithContinuation { .. } => {
+                            let continuation =
+                                self.capture_continuation(mem, dest, instr.get_next_ip())?;
+                            window[dest as usize + FIRST_ARG_REG].set(continuation.as_tagged(mem));
+                            1
+                        }
+                        Opcode::Call { arg_count, .. } | Opcode::TailCall { arg_count, .. } => {
+                            arg_count
+                        }
+                        _ => unreachable!(),
+                    };
+
                     // To avoid duplicating code in function and partial application cases,
                     // this is declared as a closure so it can access local variables
                     let new_call_frame = |function| -> Result<(), RuntimeError> {
@@ -488,8 +2119,11 @@ impl Thread {
                                 .set(current_frame_ip)
                         });
 
-                        // Create a new call frame, pushing it to the frame stack
+                        // Create a new call frame, pushing it to the frame stack. The stack grows
+                        // to fit its register window before its first instruction, as it cannot
+                        // be resized from within access_slice().
                         let new_stack_base = self.stack_base.get() + dest as ArraySize;
+                        self.check_stack_window(new_stack_base)?;
                         let frame = CallFrame::new(function, 0, new_stack_base);
                         frames.push(mem, frame)?;
 
@@ -498,15 +2132,24 @@ impl Thread {
                         self.stack_base.set(new_stack_base);
                         instr.switch_frame(code, 0);
 
-                        // Ensure the stack has 256 registers allocated
                         // TODO reset to nil to avoid accidental leakage of previous call values
-                        // TODO Ruh-roh we shouldn't be able to modify the stack size from
-                        // within an access_slice() call :grimace:
-                        stack.fill(mem, new_stack_base + 256, mem.nil())?;
 
                         Ok(())
                     };
 
+                    // Replace the function in the current call frame, which must already have its
+                    // arguments shifted down to the base of the register window
+                    let replace_call_frame = |function: ScopedPtr<'guard, Function>| {
+                        frames.access_slice(mem, |f| {
+                            f.last()
+                                .expect("No CallFrames in slice!")
+                                .function
+                                .set(function)
+                        });
+
+                        instr.switch_frame(function.code(mem), 0);
+                    };
+
                     // Handle the two similar-but-different cases: this might be a Function object
                     // or a Partial application object
                     match *binding {
@@ -528,7 +2171,7 @@ impl Thread {
                                 window[dest as usize].set(partial.as_tagged(mem));
 
                                 return Ok(EvalStatus::Pending);
-                            } else if arg_count > arity {
+                            } else if arg_count > arity && !function.is_variadic() {
                                 // Too many args, we haven't got a continuations stack (yet)
                                 return Err(err_eval(&format!(
                                     "Function {} expected {} arguments, got {}",
@@ -538,7 +2181,18 @@ impl Thread {
                                 )));
                             }
 
-                            new_call_frame(function)?;
+                            let mut count = arg_count as usize;
+                            if function.is_variadic() {
+                                count = pack_rest_args(mem, window, dest, function, count)?;
+                            }
+
+                            if tail {
+                                shift_tail_call_args(window, dest, count);
+                                replace_call_frame(function);
+                            } else {
+                                self.check_self_recursion(mem, function)?;
+                                new_call_frame(function)?;
+                            }
                         }
 
                         Value::Partial(partial) => {
@@ -546,8 +2200,7 @@ impl Thread {
 
                             if arg_count == 0 && arity > 0 {
                                 // Partial is unchanged, no args added, copy directly to dest
-                                window[dest as usize]
-                                    .set_to_ptr(window[function as usize].get_ptr());
+                                window[dest as usize].set(binding);
                                 return Ok(EvalStatus::Pending);
                             } else if arg_count < arity {
                                 // Too few args, bake a new Partial from the existing one, adding the new
@@ -564,7 +2217,7 @@ impl Thread {
                                 window[dest as usize].set(new_partial.as_tagged(mem));
 
                                 return Ok(EvalStatus::Pending);
-                            } else if arg_count > arity {
+                            } else if arg_count > arity && !partial.function(mem).is_variadic() {
                                 // Too many args, we haven't got a continuations stack
                                 return Err(err_eval(&format!(
                                     "Partial {} expected {} arguments, got {}",
@@ -595,7 +2248,59 @@ impl Thread {
                                 }
                             });
 
-                            new_call_frame(partial.function(mem))?;
+                            let function = partial.function(mem);
+                            let mut count = (partial.used() + arg_count) as usize;
+                            if function.is_variadic() {
+                                count = pack_rest_args(mem, window, dest, function, count)?;
+                            }
+
+                            if tail {
+                                shift_tail_call_args(window, dest, count);
+                                replace_call_frame(function);
+                            } else {
+                                new_call_frame(function)?;
+                            }
+                        }
+
+                        // A Rust function is called directly, without a new call frame. It may evaluate
+                        // functions itself through call_function(), which can grow the stack, so the
+                        // register window must not be used after the call.
+                        Value::NativeFunction(native) => {
+                            let args = native_call_args(mem, window, dest, arg_count)?;
+                            let result = self.call_native(mem, native, &args)?;
+                            let location = self.stack_base.get() + dest as ArraySize;
+                            IndexedAnyContainer::set(&*stack, mem, location, result)?;
+                            return Ok(EvalStatus::Pending);
+                        }
+
+                        // Calling a Parameter with no arguments returns its current value
+                        Value::Parameter(param) => {
+                            if arg_count != 0 {
+                                return Err(err_eval(&format!(
+                                    "Parameter expected 0 arguments, got {}",
+                                    arg_count
+                                )));
+                            }
+
+                            window[dest as usize].set(param.value(mem));
+                        }
+
+                        // Calling a Continuation with one argument, or none for nil, abandons
+                        // evaluation up to its call/cc expression, which the error handler in
+                        // `vm_eval_stream()` resumes after with the argument as its result
+                        Value::Continuation(continuation) => {
+                            let value = match arg_count {
+                                0 => mem.nil(),
+                                1 => window[dest as usize + FIRST_ARG_REG].get(mem),
+                                _ => {
+                                    return Err(err_eval(&format!(
+                                        "Continuation expected 0 or 1 arguments, got {}",
+                                        arg_count
+                                    )))
+                                }
+                            };
+
+                            return Err(self.invoke_continuation(continuation, value));
                         }
 
                         _ => return Err(err_eval("Type is not callable")),
@@ -649,17 +2354,48 @@ impl Thread {
                     window[dest as usize] = window[src as usize].clone();
                 }
 
-                // TODO
-                Opcode::Add { dest, reg1, reg2 } => unimplemented!(),
+                // Add the numbers in registers `reg1` and `reg2`, putting the result in `dest`. Every
+                // arithmetic opcode coerces its operands up the numeric tower, see `number`.
+                Opcode::Add { dest, reg1, reg2 } => {
+                    let left = window[reg1 as usize].get(mem);
+                    let right = window[reg2 as usize].get(mem);
+                    let result = self.arithmetic(mem, ArithmeticOp::Add, left, right)?;
+                    window[dest as usize].set(result);
+                }
+
+                // Subtract the number in `right` from the number in `left`
+                Opcode::Subtract { dest, left, right } => {
+                    let left = window[left as usize].get(mem);
+                    let right = window[right as usize].get(mem);
+                    let result = self.arithmetic(mem, ArithmeticOp::Subtract, left, right)?;
+                    window[dest as usize].set(result);
+                }
 
-                // TODO
-                Opcode::Subtract { dest, left, right } => unimplemented!(),
+                // Multiply the numbers in registers `reg1` and `reg2`
+                Opcode::Multiply { dest, reg1, reg2 } => {
+                    let left = window[reg1 as usize].get(mem);
+                    let right = window[reg2 as usize].get(mem);
+                    let result = self.arithmetic(mem, ArithmeticOp::Multiply, left, right)?;
+                    window[dest as usize].set(result);
+                }
 
-                // TODO
-                Opcode::Multiply { dest, reg1, reg2 } => unimplemented!(),
+                // Divide the number in `num` by the number in `denom`, rounding toward zero if both are
+                // integers
+                Opcode::DivideInteger { dest, num, denom } => {
+                    let left = window[num as usize].get(mem);
+                    let right = window[denom as usize].get(mem);
+                    let result = self.arithmetic(mem, ArithmeticOp::Divide, left, right)?;
+                    window[dest as usize].set(result);
+                }
 
-                // TODO
-                Opcode::DivideInteger { dest, num, denom } => unimplemented!(),
+                // Remainder of dividing the number in `num` by the number in `denom`, taking the sign
+                // of `denom`
+                Opcode::Modulo { dest, num, denom } => {
+                    let left = window[num as usize].get(mem);
+                    let right = window[denom as usize].get(mem);
+                    let result = self.arithmetic(mem, ArithmeticOp::Modulo, left, right)?;
+                    window[dest as usize].set(result);
+                }
 
                 // Follow the indirection of an Upvalue to retrieve the value, copy the value to a
                 // local register
@@ -691,51 +2427,258 @@ impl Thread {
                         }
                     }
                 }
+
+                // Rebind a Parameter for the extent of a parameterize expression
+                Opcode::BindParameter { param, value } => {
+                    let param = window[param as usize].get(mem);
+                    let value = window[value as usize].get(mem);
+                    self.bind_parameter(mem, param, value)?;
+                }
+
+                // Restore the previous values of the most recently bound Parameters
+                Opcode::UnbindParameters { count } => {
+                    self.unbind_parameters(mem, count as ArraySize)?;
+                }
+
+                // Begin an instruction budget for the following expression body. If the budget
+                // runs out, evaluation resumes at the given offset with `limit-exceeded` in the
+                // `limit` register
+                Opcode::BeginLimit { limit, offset } => {
+                    let count = window[limit as usize].get(mem);
+                    let resume_ip = (instr.get_next_ip() as i32 + offset as i32) as ArraySize;
+                    self.begin_limit(mem, count, limit, resume_ip)?;
+                }
+
+                // The expression body completed within its instruction budget
+                Opcode::EndLimit => {
+                    self.limits.borrow_mut().pop();
+                }
+
+                // The call made by a call/cc expression is complete, so the innermost continuation
+                // is no longer live
+                Opcode::EndContinuation => {
+                    let depth = self.continuations.get(mem).length();
+                    self.end_continuations(mem, depth.saturating_sub(1))?;
+                }
+
+                // Begin a try expression, whose catch clause is at the given offset with the
+                // thrown value in the `dest` register
+                Opcode::PushHandler { dest, offset } => {
+                    let resume_ip = (instr.get_next_ip() as i32 + offset as i32) as ArraySize;
+                    self.handlers.borrow_mut().push(Handler {
+                        action: HandlerAction::Catch { resume_ip, dest },
+                        frame_depth: frames.length(),
+                        stack_base: self.stack_base.get(),
+                        parameter_depth: self.parameter_bindings.get(mem).length(),
+                        limit_depth: self.limits.borrow().len(),
+                        continuation_depth: self.continuations.get(mem).length(),
+                    });
+                }
+
+                // Begin an unwind-protect expression, whose cleanup function is in the `thunk`
+                // register
+                Opcode::PushCleanup { thunk } => {
+                    self.handlers.borrow_mut().push(Handler {
+                        action: HandlerAction::Cleanup {
+                            thunk: self.stack_base.get() + thunk as ArraySize,
+                        },
+                        frame_depth: frames.length(),
+                        stack_base: self.stack_base.get(),
+                        parameter_depth: self.parameter_bindings.get(mem).length(),
+                        limit_depth: self.limits.borrow().len(),
+                        continuation_depth: self.continuations.get(mem).length(),
+                    });
+                }
+
+                // The body of the innermost try or unwind-protect expression completed without
+                // error
+                Opcode::PopHandler => {
+                    self.handlers.borrow_mut().pop();
+                }
+
+                // Suspend the generator being run, saving the instruction to resume at in the
+                // current call frame. The value it is resumed with goes in the `dest` register.
+                Opcode::Yield { dest, value } => {
+                    let entry = self.entry.get();
+                    if !entry.generator {
+                        return Err(err_eval(
+                            "yield outside of a generator, or in a function called from Rust",
+                        ));
+                    }
+
+                    if self.parameter_bindings.get(mem).length() > entry.parameter_depth
+                        || self.limits.borrow().len() > entry.limit_depth
+                        || self.continuations.get(mem).length() > entry.continuation_depth
+                        || self.handlers.borrow().len() > entry.handler_depth
+                    {
+                        return Err(err_eval(
+                            "Cannot yield inside a parameterize, with-limit, call/cc, try or \
+                             unwind-protect expression",
+                        ));
+                    }
+
+                    let resume_ip = instr.get_next_ip();
+                    frames.access_slice(mem, |f| {
+                        f.last().expect("No CallFrames in slice!").ip.set(resume_ip)
+                    });
+
+                    return Ok(EvalStatus::Yielded(
+                        window[value as usize].get(mem),
+                        self.stack_base.get() + dest as ArraySize,
+                    ));
+                }
             }
 
             Ok(EvalStatus::Pending)
         })
     }
 
-    /// Given ByteCode, execute up to max_instr more instructions
+    /// Add the call frames of the current evaluation to the backtrace of an error leaving it,
+    /// innermost first. Trampoline frames, such as the one a nested evaluation begins with, are
+    /// left out.
+    fn record_backtrace<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        rt_error: &mut RuntimeError,
+        entry: EvalEntry,
+    ) {
+        // The innermost frame is at the failing instruction, the others at the call they were
+        // waiting on, just before the instruction they return to
+        let next_ip = self.instr.get(mem).get_next_ip();
+        self.frames.get(mem).access_slice(mem, |window| {
+            let innermost = window.len().saturating_sub(1);
+            let frames = window.iter().enumerate().skip(entry.frame_depth as usize);
+            for (index, frame) in frames.rev().filter(|(_, frame)| !frame.trampoline) {
+                let ip = if index == innermost {
+                    next_ip
+                } else {
+                    frame.ip.get()
+                };
+
+                let function = frame.function.get(mem);
+                rt_error.push_backtrace_frame(
+                    function.name(mem),
+                    function.code(mem).position(mem, ip.saturating_sub(1)),
+                );
+            }
+        });
+    }
+
+    /// Execute up to max_instr more instructions, continuing from wherever the instruction stream
+    /// left off
     fn vm_eval_stream<'guard>(
         &self,
         mem: &'guard MutatorView,
-        code: ScopedPtr<'guard, ByteCode>,
         max_instr: ArraySize,
     ) -> Result<EvalStatus<'guard>, RuntimeError> {
-        let instr = self.instr.get(mem);
-        // TODO this is broken logic, this function shouldn't switch back to this code object every
-        // time it is called
-        instr.switch_frame(code, 0);
+        let dispatch = Dispatch {
+            frames: self.frames.get(mem),
+            stack: self.stack.get(mem),
+            globals: self.globals.get(mem),
+            instr: self.instr.get(mem),
+        };
 
         for _ in 0..max_instr {
-            match self.eval_next_instr(mem) {
+            let result = if self.limit_exceeded() {
+                Err(RuntimeError::new(ErrorKind::LimitExceeded))
+            } else if let Err(rt_error) = self.check_vm_limits(mem) {
+                Err(rt_error)
+            } else {
+                let sample_start = match self.profiler.borrow().as_ref() {
+                    Some(profiler) => profiler.sample_start(),
+                    None => None,
+                };
+
+                let result = self.eval_next_instr(mem, dispatch);
+
+                if let Some(start) = sample_start {
+                    if let Some(profiler) = self.profiler.borrow_mut().as_mut() {
+                        profiler.sample_end(start);
+                    }
+                }
+
+                result
+            };
+
+            match result {
                 // Evaluation paused or completed without error
                 Ok(exit_cond) => match exit_cond {
-                    EvalStatus::Return(value) => return Ok(EvalStatus::Return(value)),
-                    _ => (),
+                    EvalStatus::Pending => {
+                        if self.at_breakpoint(mem)? {
+                            return Ok(EvalStatus::Paused);
+                        }
+                    }
+                    _ => return Ok(exit_cond),
                 },
 
                 // Evaluation hit an error
-                Err(rt_error) => {
-                    // unwind the stack, printing a trace
-                    let frames = self.frames.get(mem);
+                Err(mut rt_error) => {
+                    let entry = self.entry.get();
+
+                    // An instruction budget that began in this evaluation ran out, possibly in an
+                    // evaluation nested inside it, so abandon the `with-limit` body
+                    if *rt_error.error_kind() == ErrorKind::LimitExceeded
+                        && self.limits.borrow().len() > entry.limit_depth
+                    {
+                        self.unwind_limit(mem)?;
+                        continue;
+                    }
 
-                    // Print a stack trace if the error is multiple call frames deep
-                    frames.access_slice(mem, |window| {
-                        if window.len() > 1 {
-                            println!("Error traceback:");
+                    // Call the cleanup function of each unwind-protect expression the error leaves,
+                    // innermost first. An error raised by a cleanup function replaces the error
+                    // being unwound.
+                    while let Some(thunk) =
+                        self.pop_cleanup(mem, self.unwind_depth(mem, &rt_error, entry)?)?
+                    {
+                        if let Err(cleanup_error) = self.call_function(mem, thunk, &[]) {
+                            rt_error = cleanup_error;
                         }
+                    }
+
+                    // An error was raised inside a try expression that began in this evaluation,
+                    // possibly in an evaluation nested inside it, so resume at its catch clause
+                    if rt_error.is_catchable() && self.handlers.borrow().len() > entry.handler_depth
+                    {
+                        self.unwind_handler(mem, &rt_error)?;
+                        continue;
+                    }
 
-                        for frame in &window[1..] {
-                            println!("  {}", frame.as_string(mem));
+                    // A continuation captured in this evaluation was invoked, possibly in an
+                    // evaluation nested inside it, so resume after its call/cc expression
+                    let escaping = match *rt_error.error_kind() {
+                        ErrorKind::ContinuationInvoked(depth) => {
+                            if depth >= entry.continuation_depth {
+                                self.resume_continuation(mem, depth)?;
+                                continue;
+                            }
+                            true
                         }
-                    });
+                        _ => false,
+                    };
+
+                    // Record the call frames the error leaves in its backtrace, unless a
+                    // continuation is escaping through this evaluation
+                    if !escaping {
+                        self.record_backtrace(mem, &mut rt_error, entry);
+                    }
+
+                    let frames = self.frames.get(mem);
+                    // Unwind by removing the frames of this evaluation and restoring the
+                    // parameters it bound. A nested evaluation leaves the state of the evaluation
+                    // that called into it for the caller to restore.
+                    while frames.length() > entry.frame_depth {
+                        frames.pop(mem)?;
+                    }
+                    if entry.frame_depth == 0 {
+                        self.stack_base.set(0);
+                    }
 
-                    // Unwind by clearing all frames from the stack
-                    frames.clear(mem)?;
-                    self.stack_base.set(0);
+                    let bound =
+                        (self.parameter_bindings.get(mem).length() - entry.parameter_depth) / 2;
+                    self.unbind_parameters(mem, bound)?;
+                    self.limits.borrow_mut().truncate(entry.limit_depth);
+                    self.handlers.borrow_mut().truncate(entry.handler_depth);
+                    self.end_continuations(mem, entry.continuation_depth)?;
 
                     return Err(rt_error);
                 }
@@ -755,12 +2698,21 @@ impl Thread {
         let mut status = EvalStatus::Pending;
 
         let frames = self.frames.get(mem);
+
+        // VM limits apply to the whole top level evaluation, not to evaluations nested in it
+        if frames.length() == 0 {
+            self.eval_start
+                .set((self.instruction_count.get(), mem.bytes_allocated()));
+        }
+
         frames.push(mem, CallFrame::new_main(function))?;
 
-        let code = function.code(mem);
+        // begin at the start of the function. Calls and returns switch the instruction stream
+        // between functions from here on.
+        self.instr.get(mem).switch_frame(function.code(mem), 0);
 
         while status == EvalStatus::Pending {
-            status = self.vm_eval_stream(mem, code, 1024)?;
+            status = self.vm_eval_stream(mem, 1024)?;
             match status {
                 EvalStatus::Return(value) => return Ok(value),
                 _ => (),
@@ -769,4 +2721,320 @@ impl Thread {
 
         Err(err_eval("Unexpected end of evaluation"))
     }
+
+    /// Return true if the evaluation being debugged is about to execute an instruction with a
+    /// breakpoint. Evaluations nested inside native function calls and generators do not pause.
+    fn at_breakpoint<'guard>(&self, mem: &'guard MutatorView) -> Result<bool, RuntimeError> {
+        let debugger = self.debugger.borrow();
+        if !debugger.active {
+            return Ok(false);
+        }
+
+        let entry = self.entry.get();
+        if entry.generator || entry.frame_depth > 0 {
+            return Ok(false);
+        }
+
+        let frames = self.frames.get(mem);
+        if frames.length() == 0 {
+            return Ok(false);
+        }
+
+        let function = frames.top(mem)?.function.get(mem);
+        Ok(debugger.is_breakpoint(function.name(mem), self.instr.get(mem).get_next_ip()))
+    }
+
+    /// Begin evaluating a Function under the debugger, paused before its first instruction. The
+    /// Function should expect no arguments, as for `quick_vm_eval()`. Continue the evaluation with
+    /// `step()` or `resume()`.
+    pub fn debug<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        function: ScopedPtr<'guard, Function>,
+    ) -> Result<(), RuntimeError> {
+        let frames = self.frames.get(mem);
+        if frames.length() != 0 {
+            return Err(err_eval("Cannot debug while an evaluation is in progress"));
+        }
+
+        self.eval_start
+            .set((self.instruction_count.get(), mem.bytes_allocated()));
+        frames.push(mem, CallFrame::new_main(function))?;
+        self.instr.get(mem).switch_frame(function.code(mem), 0);
+        self.debugger.borrow_mut().active = true;
+
+        Ok(())
+    }
+
+    /// Execute the next instruction of the evaluation being debugged, describing it. The
+    /// evaluation stops being debugged once it completes or raises an error.
+    pub fn step<'guard>(&self, mem: &'guard MutatorView) -> Result<Step<'guard>, RuntimeError> {
+        if !self.debugger.borrow().active {
+            return Err(err_eval("No evaluation is being debugged"));
+        }
+
+        let function = String::from(self.frames.get(mem).top(mem)?.function.get(mem).name(mem));
+        let instr = self.instr.get(mem);
+        let ip = instr.get_next_ip();
+        let opcode = instr.peek_next_opcode(mem)?;
+        let base = self.stack_base.get();
+
+        let status = match self.vm_eval_stream(mem, 1) {
+            Ok(EvalStatus::Paused) => EvalStatus::Pending,
+            Ok(status) => status,
+            Err(rt_error) => {
+                self.debugger.borrow_mut().active = false;
+                return Err(rt_error);
+            }
+        };
+        if let EvalStatus::Return(_) = status {
+            self.debugger.borrow_mut().active = false;
+        }
+
+        // The registers are read from the window the instruction executed in, whichever call
+        // frame is current now
+        let stack = self.stack.get(mem);
+        let mut registers = Vec::new();
+        for reg in operand_registers(opcode) {
+            let value = IndexedAnyContainer::get(&*stack, mem, base + reg as ArraySize)?;
+            registers.push((reg, value));
+        }
+
+        Ok(Step {
+            function,
+            ip,
+            opcode,
+            registers,
+            status,
+        })
+    }
+
+    /// Run the evaluation being debugged until it completes, returning its result, or reaches a
+    /// breakpoint, returning Paused. The instruction the evaluation is paused at is executed
+    /// even if it has a breakpoint.
+    pub fn resume<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<EvalStatus<'guard>, RuntimeError> {
+        let mut status = self.step(mem)?.status;
+        if status == EvalStatus::Pending && self.at_breakpoint(mem)? {
+            return Ok(EvalStatus::Paused);
+        }
+
+        while status == EvalStatus::Pending {
+            status = match self.vm_eval_stream(mem, 1024) {
+                Ok(status) => status,
+                Err(rt_error) => {
+                    self.debugger.borrow_mut().active = false;
+                    return Err(rt_error);
+                }
+            };
+        }
+
+        if let EvalStatus::Return(_) = status {
+            self.debugger.borrow_mut().active = false;
+        }
+        Ok(status)
+    }
+
+    /// Call a function with the given arguments, evaluating it completely and returning the
+    /// result. A native function may call this to evaluate a function it was passed; the
+    /// evaluation nests above the caller's register window and call frames, which are restored
+    /// afterwards. An error unwinds only the nested evaluation before being returned to the
+    /// caller.
+    pub fn call_function<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        function: TaggedScopedPtr<'guard>,
+        args: &[TaggedScopedPtr<'guard>],
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        match *function {
+            Value::NativeFunction(native) => return self.call_native(mem, native, args),
+            Value::Function(_) | Value::Partial(_) | Value::Continuation(_) => (),
+            _ => return Err(err_eval(&format!("{} is not callable", function))),
+        }
+
+        if args.len() > 250 {
+            return Err(err_eval("Too many arguments to call a function from Rust"));
+        }
+
+        // Call the function from a two instruction trampoline so that argument checking, partial
+        // application and closures are handled exactly as for a call from bytecode. The function
+        // goes in register 2 and its register window starts at register 3.
+        let code = ByteCode::alloc(mem)?;
+        code.push(
+            mem,
+            Opcode::Call {
+                function: 2,
+                dest: 3,
+                arg_count: args.len() as NumArgs,
+            },
+        )?;
+        code.push(mem, Opcode::Return { reg: 3 })?;
+        let trampoline = Function::alloc(mem, mem.nil(), List::alloc(mem)?, false, code, None)?;
+
+        let frames = self.frames.get(mem);
+        let stack = self.stack.get(mem);
+        let instr = self.instr.get(mem);
+
+        // Place the trampoline register window above the caller's
+        let outer_base = self.stack_base.get();
+        let base = outer_base + WINDOW_SIZE;
+        self.reserve_stack_window(mem, &stack, base)?;
+        IndexedAnyContainer::set(&*stack, mem, base + 2, function)?;
+        IndexedAnyContainer::set(&*stack, mem, base + 3 + ENV_REG as ArraySize, mem.nil())?;
+        for (index, arg) in args.iter().enumerate() {
+            let location = base + 3 + (FIRST_ARG_REG + index) as ArraySize;
+            IndexedAnyContainer::set(&*stack, mem, location, *arg)?;
+        }
+
+        let outer_ip = instr.get_next_ip();
+        let outer_entry = self.entry.replace(EvalEntry {
+            frame_depth: frames.length(),
+            parameter_depth: self.parameter_bindings.get(mem).length(),
+            limit_depth: self.limits.borrow().len(),
+            continuation_depth: self.continuations.get(mem).length(),
+            handler_depth: self.handlers.borrow().len(),
+            generator: false,
+        });
+
+        frames.push(mem, CallFrame::new_trampoline(trampoline, base))?;
+        self.stack_base.set(base);
+        instr.switch_frame(code, 0);
+
+        let result = loop {
+            match self.vm_eval_stream(mem, 1024) {
+                Ok(EvalStatus::Return(value)) => break Ok(value),
+                Ok(EvalStatus::Pending) => (),
+                Ok(EvalStatus::Yielded(..)) | Ok(EvalStatus::Paused) => unreachable!(),
+                Err(rt_error) => break Err(rt_error),
+            }
+        };
+
+        // Restore the caller's evaluation state
+        self.entry.set(outer_entry);
+        self.stack_base.set(outer_base);
+        if let Ok(frame) = frames.top(mem) {
+            instr.switch_frame(frame.function.get(mem).code(mem), outer_ip);
+        }
+
+        result
+    }
+
+    /// Close every open upvalue, so that closures that refer to the register stack being swapped
+    /// out keep their values while it is not the Thread's stack
+    fn park_upvalues<'guard>(&self, mem: &'guard MutatorView) -> Result<(), RuntimeError> {
+        let stack = self.stack.get(mem);
+        for (_, upvalue) in self.upvalues.get(mem).items(mem) {
+            if let Value::Upvalue(upvalue) = *upvalue {
+                upvalue.close(mem, stack)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reopen the upvalues closed by `park_upvalues()` once their stack is swapped back in
+    fn unpark_upvalues<'guard>(&self, mem: &'guard MutatorView) -> Result<(), RuntimeError> {
+        let stack = self.stack.get(mem);
+        for (_, upvalue) in self.upvalues.get(mem).items(mem) {
+            if let Value::Upvalue(upvalue) = *upvalue {
+                upvalue.reopen(mem, stack)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the call frames, register stack and open upvalues of the Thread with the given
+    /// ones, parking the open upvalues of the ones replaced
+    fn switch_evaluation<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        frames: ScopedPtr<'guard, CallFrameList>,
+        stack: ScopedPtr<'guard, List>,
+        upvalues: ScopedPtr<'guard, Dict>,
+    ) -> Result<(), RuntimeError> {
+        self.park_upvalues(mem)?;
+        self.frames.set(frames);
+        self.stack.set(stack);
+        self.upvalues.set(upvalues);
+        self.unpark_upvalues(mem)
+    }
+
+    /// Run a generator until it yields or its function returns, see `generator`. The value is
+    /// the result of the `yield` expression the generator is suspended at. The result is the
+    /// value yielded, or the result of the function once it returns. The generator runs nested
+    /// above the caller, as for `call_function()`, and is finished by an error.
+    pub fn resume_generator<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        generator: ScopedPtr<'guard, Generator>,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        match generator.state() {
+            GeneratorState::Suspended => (),
+            GeneratorState::Running => return Err(err_eval("Generator is already running")),
+            GeneratorState::Finished => return Err(err_eval("Generator has finished")),
+        }
+
+        let instr = self.instr.get(mem);
+        let outer_base = self.stack_base.get();
+        let outer_ip = instr.get_next_ip();
+
+        let outer_frames = self.frames.get(mem);
+        let outer_stack = self.stack.get(mem);
+        let outer_upvalues = self.upvalues.get(mem);
+        self.switch_evaluation(
+            mem,
+            generator.frames(mem),
+            generator.stack(mem),
+            generator.upvalues(mem),
+        )?;
+
+        if let Some(location) = generator.resume_location() {
+            IndexedAnyContainer::set(&*generator.stack(mem), mem, location, value)?;
+        }
+
+        let outer_entry = self.entry.replace(EvalEntry {
+            frame_depth: 0,
+            parameter_depth: self.parameter_bindings.get(mem).length(),
+            limit_depth: self.limits.borrow().len(),
+            continuation_depth: self.continuations.get(mem).length(),
+            handler_depth: self.handlers.borrow().len(),
+            generator: true,
+        });
+
+        let frame = generator.frames(mem).top(mem)?;
+        self.stack_base.set(frame.base);
+        instr.switch_frame(frame.function.get(mem).code(mem), frame.ip.get());
+        generator.set_state(GeneratorState::Running);
+
+        let result = loop {
+            match self.vm_eval_stream(mem, 1024) {
+                Ok(EvalStatus::Pending) => (),
+                Ok(EvalStatus::Paused) => unreachable!(),
+                Ok(EvalStatus::Yielded(value, location)) => {
+                    generator.suspend(location);
+                    break Ok(value);
+                }
+                Ok(EvalStatus::Return(value)) => {
+                    generator.set_state(GeneratorState::Finished);
+                    break Ok(value);
+                }
+                Err(rt_error) => {
+                    generator.set_state(GeneratorState::Finished);
+                    break Err(rt_error);
+                }
+            }
+        };
+
+        // Restore the caller's evaluation state
+        self.switch_evaluation(mem, outer_frames, outer_stack, outer_upvalues)?;
+        self.entry.set(outer_entry);
+        self.stack_base.set(outer_base);
+        if let Ok(frame) = outer_frames.top(mem) {
+            instr.switch_frame(frame.function.get(mem).code(mem), outer_ip);
+        }
+
+        result
+    }
 }